@@ -0,0 +1,227 @@
+//! Deterministic in-process provider used by unit tests
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::ai::{AiProvider, CompletionOptions, CompletionStream};
+use crate::models::Message;
+use crate::rag::count_tokens;
+
+/// Dimension of the bag-of-words embeddings produced by the mock
+pub const MOCK_EMBEDDING_DIM: usize = 64;
+
+/// Mock provider returning canned completions and word-hash embeddings
+pub struct MockProvider {
+    /// Text returned by every chat completion
+    pub response: Mutex<String>,
+
+    /// Every prompt sent to `chat_completion`
+    pub chat_calls: Mutex<Vec<Vec<Message>>>,
+
+    /// Options of every chat completion
+    pub completion_options: Mutex<Vec<CompletionOptions>>,
+
+    /// Every text sent for embedding
+    pub embedded_texts: Mutex<Vec<String>>,
+
+    /// Make chat completions fail
+    pub fail_chat: AtomicBool,
+
+    /// Reject embedding inputs longer than this many tokens, like the API does
+    pub embedding_token_limit: Option<usize>,
+
+    /// Fail any embedding batch containing a text with this substring
+    pub fail_embeddings_containing: Mutex<Option<String>>,
+
+    /// Number of `generate_embeddings_batch` calls
+    pub embedding_batch_calls: AtomicUsize,
+
+    /// Keep completion streams open after the last word, like a slow upstream
+    pub stream_stalls: AtomicBool,
+
+    /// Completion streams created and not yet dropped
+    pub open_streams: Arc<AtomicUsize>,
+
+    /// Wait this long before answering a chat completion, like a slow upstream
+    pub completion_delay: Option<Duration>,
+}
+
+/// Counts a completion stream as open until it is dropped
+struct OpenStream(Arc<AtomicUsize>);
+
+impl Drop for OpenStream {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl MockProvider {
+    pub fn new(response: impl Into<String>) -> Self {
+        Self {
+            response: Mutex::new(response.into()),
+            chat_calls: Mutex::new(Vec::new()),
+            completion_options: Mutex::new(Vec::new()),
+            embedded_texts: Mutex::new(Vec::new()),
+            fail_chat: AtomicBool::new(false),
+            embedding_token_limit: None,
+            fail_embeddings_containing: Mutex::new(None),
+            embedding_batch_calls: AtomicUsize::new(0),
+            stream_stalls: AtomicBool::new(false),
+            open_streams: Arc::new(AtomicUsize::new(0)),
+            completion_delay: None,
+        }
+    }
+
+    pub fn with_embedding_token_limit(mut self, limit: usize) -> Self {
+        self.embedding_token_limit = Some(limit);
+        self
+    }
+
+    pub fn with_completion_delay(mut self, delay: Duration) -> Self {
+        self.completion_delay = Some(delay);
+        self
+    }
+
+    /// Fail embedding batches that contain `marker`
+    pub fn with_failing_embeddings(self, marker: impl Into<String>) -> Self {
+        *self.fail_embeddings_containing.lock().unwrap() = Some(marker.into());
+        self
+    }
+
+    pub fn failing() -> Self {
+        let provider = Self::new("");
+        provider.fail_chat.store(true, Ordering::Relaxed);
+        provider
+    }
+
+    /// Stream completions that never finish after their last word
+    pub fn stalling() -> Self {
+        let provider = Self::new("Loosen the axle nut");
+        provider.stream_stalls.store(true, Ordering::Relaxed);
+        provider
+    }
+
+    /// Number of chat completions requested so far
+    pub fn chat_call_count(&self) -> usize {
+        self.chat_calls.lock().unwrap().len()
+    }
+
+    /// Options of the most recent chat completion
+    pub fn last_options(&self) -> Option<CompletionOptions> {
+        self.completion_options.lock().unwrap().last().cloned()
+    }
+
+    /// Prompt of the most recent chat completion
+    pub fn last_prompt(&self) -> Option<Vec<Message>> {
+        self.chat_calls.lock().unwrap().last().cloned()
+    }
+
+    /// Hash each lowercase word into a bucket so texts sharing words score higher
+    pub fn embed(text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0f32; MOCK_EMBEDDING_DIM];
+        for word in text
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+        {
+            let hash = word
+                .bytes()
+                .fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+            vector[(hash % MOCK_EMBEDDING_DIM as u64) as usize] += 1.0;
+        }
+
+        let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|v| *v /= norm);
+        }
+        vector
+    }
+}
+
+#[async_trait]
+impl AiProvider for MockProvider {
+    async fn complete(&self, messages: Vec<Message>, options: CompletionOptions) -> Result<String> {
+        self.chat_calls.lock().unwrap().push(messages);
+        self.completion_options.lock().unwrap().push(options);
+        if let Some(delay) = self.completion_delay {
+            tokio::time::sleep(delay).await;
+        }
+        if self.fail_chat.load(Ordering::Relaxed) {
+            anyhow::bail!("mock completion failure");
+        }
+        Ok(self.response.lock().unwrap().clone())
+    }
+
+    /// The canned response once per requested choice, numbered after the first
+    async fn complete_choices(
+        &self,
+        messages: Vec<Message>,
+        options: CompletionOptions,
+    ) -> Result<Vec<String>> {
+        let count = options.choices.unwrap_or(1).max(1);
+        let text = self.complete(messages, options).await?;
+        Ok((1..=count)
+            .map(|i| match i {
+                1 => text.clone(),
+                i => format!("{} ({})", text, i),
+            })
+            .collect())
+    }
+
+    /// Streams the canned response word by word
+    async fn complete_stream(
+        &self,
+        messages: Vec<Message>,
+        options: CompletionOptions,
+    ) -> Result<CompletionStream> {
+        let text = self.complete(messages, options).await?;
+        let words: Vec<Result<String>> = text
+            .split_inclusive(' ')
+            .map(|word| Ok(word.to_string()))
+            .collect();
+        let tail = if self.stream_stalls.load(Ordering::Relaxed) {
+            stream::pending().boxed()
+        } else {
+            stream::empty().boxed()
+        };
+
+        self.open_streams.fetch_add(1, Ordering::SeqCst);
+        let open = OpenStream(self.open_streams.clone());
+        Ok(stream::iter(words)
+            .chain(tail)
+            .map(move |word| {
+                let _open = &open;
+                word
+            })
+            .boxed())
+    }
+
+    async fn generate_embedding(&self, text: &str) -> Result<Vec<f32>> {
+        if let Some(limit) = self.embedding_token_limit {
+            let tokens = count_tokens("text-embedding-3-small", text);
+            if tokens > limit {
+                anyhow::bail!("input has {} tokens, maximum is {}", tokens, limit);
+            }
+        }
+        self.embedded_texts.lock().unwrap().push(text.to_string());
+        Ok(Self::embed(text))
+    }
+
+    async fn generate_embeddings_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.embedding_batch_calls.fetch_add(1, Ordering::Relaxed);
+        if let Some(marker) = self.fail_embeddings_containing.lock().unwrap().as_deref() {
+            if texts.iter().any(|t| t.contains(marker)) {
+                anyhow::bail!("mock embedding batch failure");
+            }
+        }
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for text in &texts {
+            embeddings.push(self.generate_embedding(text).await?);
+        }
+        Ok(embeddings)
+    }
+}
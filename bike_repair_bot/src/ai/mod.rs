@@ -1,5 +1,10 @@
 pub mod openai_client;
 pub mod prompts;
+pub mod provider;
+
+#[cfg(test)]
+pub mod mock;
 
 pub use openai_client::*;
 pub use prompts::*;
+pub use provider::*;
@@ -1,26 +1,74 @@
 use anyhow::Result;
+use futures::StreamExt;
 use async_openai::{
-    config::OpenAIConfig,
+    config::{Config as ApiConfig, OpenAIConfig},
     types::{
         ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs,
         ChatCompletionRequestUserMessageArgs, ChatCompletionRequestAssistantMessageArgs,
-        CreateChatCompletionRequestArgs, CreateEmbeddingRequestArgs, EmbeddingInput,
+        ChatCompletionResponseFormat, ChatCompletionResponseFormatType,
+        CreateChatCompletionRequest, CreateChatCompletionRequestArgs,
+        CreateChatCompletionResponse, CreateEmbeddingRequestArgs, EmbeddingInput,
     },
     Client,
 };
 
-use crate::models::Message;
+use crate::ai::{CompletionOptions, CompletionStream, MAX_COMPLETION_CHOICES};
+use crate::models::{Message, Role};
+
+/// Header selecting the project billed for a request
+pub const OPENAI_PROJECT_HEADER: &str = "OpenAI-Project";
+
+/// `OpenAIConfig` plus the `OpenAI-Project` header, which async-openai 0.20 lacks
+#[derive(Debug, Clone)]
+pub struct ProjectConfig {
+    inner: OpenAIConfig,
+    project_id: Option<String>,
+}
+
+impl ApiConfig for ProjectConfig {
+    fn headers(&self) -> http::HeaderMap {
+        let mut headers = self.inner.headers();
+        if let Some(project_id) = &self.project_id {
+            match http::HeaderValue::from_str(project_id) {
+                Ok(value) => {
+                    headers.insert(OPENAI_PROJECT_HEADER, value);
+                }
+                Err(_) => log::warn!("Ignoring invalid OpenAI project id"),
+            }
+        }
+        headers
+    }
+
+    fn url(&self, path: &str) -> String {
+        self.inner.url(path)
+    }
+
+    fn query(&self) -> Vec<(&str, &str)> {
+        self.inner.query()
+    }
+
+    fn api_base(&self) -> &str {
+        self.inner.api_base()
+    }
+
+    fn api_key(&self) -> &secrecy::Secret<String> {
+        self.inner.api_key()
+    }
+}
 
 /// OpenAI API client wrapper
 pub struct OpenAIClient {
-    client: Client<OpenAIConfig>,
+    client: Client<ProjectConfig>,
     chat_model: String,
     embedding_model: String,
 }
 
 impl OpenAIClient {
     pub fn new(api_key: impl Into<String>, chat_model: String, embedding_model: String) -> Self {
-        let config = OpenAIConfig::new().with_api_key(api_key);
+        let config = ProjectConfig {
+            inner: OpenAIConfig::new().with_api_key(api_key),
+            project_id: None,
+        };
         let client = Client::with_config(config);
 
         Self {
@@ -30,32 +78,121 @@ impl OpenAIClient {
         }
     }
 
+    /// Send `OpenAI-Organization` / `OpenAI-Project` headers for cost attribution.
+    /// `None` leaves the respective header out.
+    pub fn with_organization(
+        mut self,
+        org_id: Option<String>,
+        project_id: Option<String>,
+    ) -> Self {
+        let mut config = self.client.config().clone();
+        if let Some(org_id) = org_id {
+            config.inner = config.inner.with_org_id(org_id);
+        }
+        config.project_id = project_id;
+        self.client = Client::with_config(config);
+        self
+    }
+
     /// Generate a chat completion
     pub async fn chat_completion(
         &self,
         messages: Vec<Message>,
         max_tokens: Option<u16>,
     ) -> Result<String> {
+        let options = CompletionOptions {
+            max_tokens,
+            ..Default::default()
+        };
+        self.complete(messages, options).await
+    }
+
+    /// Generate a chat completion with explicit options (a single choice)
+    pub async fn complete(&self, messages: Vec<Message>, options: CompletionOptions) -> Result<String> {
+        let options = CompletionOptions {
+            choices: None,
+            ..options
+        };
+        let mut choices = self.complete_choices(messages, options).await?;
+        Ok(choices.swap_remove(0))
+    }
+
+    /// Generate `options.choices` alternative completions in one request
+    #[tracing::instrument(
+        skip_all,
+        fields(model = %self.chat_model, messages = messages.len(), choices = options.choices)
+    )]
+    pub async fn complete_choices(
+        &self,
+        messages: Vec<Message>,
+        options: CompletionOptions,
+    ) -> Result<Vec<String>> {
+        let request = self.build_chat_request(messages, &options)?;
+
+        // Call API
+        let response = self.client.chat().create(request).await?;
+
+        log::debug!(
+            "Chat completion: {} choices, {} tokens used",
+            response.choices.len(),
+            response.usage.as_ref().map(|u| u.total_tokens).unwrap_or(0)
+        );
+
+        choice_texts(response)
+    }
+
+    /// Stream a chat completion as content deltas (empty deltas are skipped)
+    #[tracing::instrument(skip_all, fields(model = %self.chat_model, messages = messages.len()))]
+    pub async fn complete_stream(
+        &self,
+        messages: Vec<Message>,
+        options: CompletionOptions,
+    ) -> Result<CompletionStream> {
+        // Deltas of several choices would interleave; stream only one
+        let options = CompletionOptions {
+            choices: None,
+            ..options
+        };
+        let request = self.build_chat_request(messages, &options)?;
+        let stream = self.client.chat().create_stream(request).await?;
+
+        Ok(stream
+            .filter_map(|chunk| async move {
+                match chunk {
+                    Ok(response) => response
+                        .choices
+                        .into_iter()
+                        .next()
+                        .and_then(|choice| choice.delta.content)
+                        .filter(|text| !text.is_empty())
+                        .map(Ok),
+                    Err(e) => Some(Err(e.into())),
+                }
+            })
+            .boxed())
+    }
+
+    /// Build the API request for a chat completion
+    pub fn build_chat_request(
+        &self,
+        messages: Vec<Message>,
+        options: &CompletionOptions,
+    ) -> Result<CreateChatCompletionRequest> {
         // Convert our Message type to OpenAI's message type
         let api_messages: Vec<ChatCompletionRequestMessage> = messages
             .into_iter()
-            .map(|msg| match msg.role.as_str() {
-                "system" => ChatCompletionRequestSystemMessageArgs::default()
+            .map(|msg| match msg.role {
+                Role::System => ChatCompletionRequestSystemMessageArgs::default()
                     .content(msg.content)
                     .build()
                     .unwrap()
                     .into(),
-                "user" => ChatCompletionRequestUserMessageArgs::default()
+                Role::User => ChatCompletionRequestUserMessageArgs::default()
                     .content(msg.content)
                     .build()
                     .unwrap()
                     .into(),
-                "assistant" => ChatCompletionRequestAssistantMessageArgs::default()
-                    .content(msg.content)
-                    .build()
-                    .unwrap()
-                    .into(),
-                _ => ChatCompletionRequestUserMessageArgs::default()
+                Role::Assistant => ChatCompletionRequestAssistantMessageArgs::default()
                     .content(msg.content)
                     .build()
                     .unwrap()
@@ -67,34 +204,42 @@ impl OpenAIClient {
         let mut request = CreateChatCompletionRequestArgs::default();
         request.model(&self.chat_model).messages(api_messages);
 
-        if let Some(tokens) = max_tokens {
+        if let Some(tokens) = options.max_tokens {
             request.max_tokens(tokens);
         }
 
-        let request = request.build()?;
+        if let Some(temperature) = options.temperature {
+            request.temperature(temperature);
+        }
+
+        if let Some(top_p) = options.top_p {
+            request.top_p(top_p);
+        }
 
-        // Call API
-        let response = self.client.chat().create(request).await?;
+        if let Some(choices) = options.choices.filter(|&n| n > 1) {
+            request.n(choices.min(MAX_COMPLETION_CHOICES));
+        }
 
-        // Extract response text
-        let response_text = response
-            .choices
-            .first()
-            .ok_or_else(|| anyhow::anyhow!("No response from OpenAI"))?
-            .message
-            .content
-            .clone()
-            .ok_or_else(|| anyhow::anyhow!("Empty response from OpenAI"))?;
+        if options.json_mode {
+            request.response_format(ChatCompletionResponseFormat {
+                r#type: ChatCompletionResponseFormatType::JsonObject,
+            });
+        }
 
-        log::debug!(
-            "Chat completion: {} tokens used",
-            response.usage.map(|u| u.total_tokens).unwrap_or(0)
-        );
+        Ok(request.build()?)
+    }
 
-        Ok(response_text)
+    /// Check the API key is accepted and both configured models are available,
+    /// without generating anything
+    pub async fn check_access(&self) -> Result<()> {
+        for model in [&self.chat_model, &self.embedding_model] {
+            self.client.models().retrieve(model).await?;
+        }
+        Ok(())
     }
 
     /// Generate embeddings for text
+    #[tracing::instrument(skip_all, fields(model = %self.embedding_model, chars = text.len()))]
     pub async fn generate_embedding(&self, text: &str) -> Result<Vec<f32>> {
         let request = CreateEmbeddingRequestArgs::default()
             .model(&self.embedding_model)
@@ -114,6 +259,7 @@ impl OpenAIClient {
     }
 
     /// Generate embeddings for multiple texts in batch
+    #[tracing::instrument(skip_all, fields(model = %self.embedding_model, texts = texts.len()))]
     pub async fn generate_embeddings_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
         if texts.is_empty() {
             return Ok(Vec::new());
@@ -147,6 +293,24 @@ impl OpenAIClient {
     }
 }
 
+/// Text of every choice of a completion, ordered by index. Choices without
+/// text (a tool call, say) are skipped; a completion without any fails.
+fn choice_texts(response: CreateChatCompletionResponse) -> Result<Vec<String>> {
+    if response.choices.is_empty() {
+        anyhow::bail!("No response from OpenAI");
+    }
+    let mut choices = response.choices;
+    choices.sort_by_key(|choice| choice.index);
+    let texts: Vec<String> = choices
+        .into_iter()
+        .filter_map(|choice| choice.message.content)
+        .collect();
+    if texts.is_empty() {
+        anyhow::bail!("Empty response from OpenAI");
+    }
+    Ok(texts)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -171,6 +335,132 @@ mod tests {
         println!("Response: {}", response);
     }
 
+    fn test_client() -> OpenAIClient {
+        OpenAIClient::new(
+            "sk-test",
+            "gpt-4o-mini".to_string(),
+            "text-embedding-3-small".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_json_mode_sets_response_format() {
+        let client = test_client();
+        let options = CompletionOptions {
+            max_tokens: Some(200),
+            json_mode: true,
+            ..Default::default()
+        };
+
+        let request = client
+            .build_chat_request(vec![Message::user("Return JSON")], &options)
+            .unwrap();
+
+        assert_eq!(request.max_tokens, Some(200));
+        assert_eq!(
+            request.response_format,
+            Some(ChatCompletionResponseFormat {
+                r#type: ChatCompletionResponseFormatType::JsonObject,
+            })
+        );
+    }
+
+    #[test]
+    fn test_sampling_options_passed_to_request() {
+        let options = CompletionOptions {
+            temperature: Some(0.2),
+            top_p: Some(0.9),
+            ..Default::default()
+        };
+
+        let request = test_client()
+            .build_chat_request(vec![Message::user("Hi")], &options)
+            .unwrap();
+
+        assert_eq!(request.temperature, Some(0.2));
+        assert_eq!(request.top_p, Some(0.9));
+        let plain = test_client()
+            .build_chat_request(vec![Message::user("Hi")], &CompletionOptions::default())
+            .unwrap();
+        assert_eq!((plain.temperature, plain.top_p), (None, None));
+    }
+
+    #[test]
+    fn test_organization_and_project_headers() {
+        let headers = test_client().client.config().headers();
+        assert!(!headers.contains_key("OpenAI-Organization"));
+        assert!(!headers.contains_key(OPENAI_PROJECT_HEADER));
+
+        let client = test_client()
+            .with_organization(Some("org-123".to_string()), Some("proj_456".to_string()));
+        let headers = client.client.config().headers();
+
+        assert_eq!(headers["OpenAI-Organization"], "org-123");
+        assert_eq!(headers[OPENAI_PROJECT_HEADER], "proj_456");
+        assert_eq!(headers["Authorization"], "Bearer sk-test");
+    }
+
+    #[test]
+    fn test_choices_bounded_in_request() {
+        let request = |choices| {
+            let options = CompletionOptions {
+                choices,
+                ..Default::default()
+            };
+            test_client()
+                .build_chat_request(vec![Message::user("Hi")], &options)
+                .unwrap()
+                .n
+        };
+
+        assert_eq!(request(None), None);
+        assert_eq!(request(Some(1)), None);
+        assert_eq!(request(Some(3)), Some(3));
+        assert_eq!(request(Some(50)), Some(MAX_COMPLETION_CHOICES));
+    }
+
+    #[test]
+    fn test_every_choice_of_a_response_returned() {
+        let choice = |index: u32, content: Option<&str>| {
+            serde_json::json!({
+                "index": index,
+                "message": { "role": "assistant", "content": content },
+                "finish_reason": "stop",
+            })
+        };
+        let response = |choices: Vec<serde_json::Value>| -> CreateChatCompletionResponse {
+            serde_json::from_value(serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 0,
+                "model": "gpt-4o-mini",
+                "choices": choices,
+            }))
+            .unwrap()
+        };
+
+        let texts = choice_texts(response(vec![
+            choice(2, Some("Third")),
+            choice(0, Some("First")),
+            choice(1, None),
+            choice(3, Some("Fourth")),
+        ]))
+        .unwrap();
+        assert_eq!(texts, vec!["First", "Third", "Fourth"]);
+
+        assert!(choice_texts(response(vec![choice(0, None)])).is_err());
+        assert!(choice_texts(response(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn test_plain_mode_has_no_response_format() {
+        let request = test_client()
+            .build_chat_request(vec![Message::user("Hi")], &CompletionOptions::default())
+            .unwrap();
+
+        assert!(request.response_format.is_none());
+    }
+
     #[tokio::test]
     #[ignore]
     async fn test_generate_embedding() {
@@ -186,6 +476,9 @@ mod tests {
             .await
             .unwrap();
 
-        assert_eq!(embedding.len(), 1536); // text-embedding-3-small dimension
+        assert_eq!(
+            Some(embedding.len()),
+            crate::rag::embedding_dimension("text-embedding-3-small")
+        );
     }
 }
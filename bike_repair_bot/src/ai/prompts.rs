@@ -1,4 +1,6 @@
-use crate::models::Message;
+use serde::Deserialize;
+
+use crate::models::{Message, Role, Source, StructuredAnswer, Verbosity};
 
 /// System prompt for the motorcycle repair assistant
 pub const SYSTEM_PROMPT: &str = r#"You are an expert motorcycle mechanic and repair assistant with decades of experience. Your role is to help users diagnose and fix motorcycle issues.
@@ -25,11 +27,87 @@ pub const SYSTEM_PROMPT: &str = r#"You are an expert motorcycle mechanic and rep
 When citing manual information, always mention the source (e.g., "According to the manual...").
 "#;
 
+/// Output format instructions for structured answers (JSON mode requires the word "JSON")
+pub const STRUCTURED_OUTPUT_PROMPT: &str = r#"**Response Format:**
+Respond with a single JSON object and nothing else, using exactly these fields:
+{
+  "answer": "short summary answer",
+  "steps": [{"text": "step 1", "sources": [1]}, {"text": "step 2", "sources": [1, 2]}],
+  "safety_warnings": ["warning"],
+  "tools_needed": ["tool"]
+}
+In each step's "sources", list the numbers N of the [Source N] manual excerpts the step is based on, or an empty array if it is not based on them. Put every safety warning in "safety_warnings" rather than in the answer text. Use empty arrays when a field does not apply."#;
+
+/// Follow-up question instructions for text answers, parsed by `split_suggestions`
+pub const SUGGESTIONS_PROMPT: &str = "**Follow-up Questions:** After your answer, add a block with three short follow-up questions the user might ask next, one per line, exactly like this:\n<suggestions>\nHow do I check chain tension?\nWhat chain lube do you recommend?\nHow often should I clean the chain?\n</suggestions>";
+
+/// Follow-up question instructions for structured answers
+pub const STRUCTURED_SUGGESTIONS_PROMPT: &str = "**Follow-up Questions:** Also add a \"suggestions\" field to the JSON object: an array of three short follow-up questions the user might ask next.";
+
+/// Most follow-up questions returned with an answer
+pub const MAX_SUGGESTIONS: usize = 3;
+
+/// Longest follow-up question kept; longer ones are the model rambling
+const MAX_SUGGESTION_CHARS: usize = 150;
+
+/// Safety warning added to structured answers with little support in the manuals
+pub const VERIFY_WITH_MECHANIC_WARNING: &str = "This answer is only loosely supported by the \
+    manuals; have a qualified mechanic verify the work before riding.";
+
+/// Length guidance for concise answers
+pub const CONCISE_PROMPT: &str = "**Answer Length:** Be brief. Answer in at most 5 short bullet points, without introductions or background. Keep any safety warning.";
+
+/// Length guidance for detailed answers
+pub const DETAILED_PROMPT: &str = "**Answer Length:** Be thorough. Give a complete numbered step-by-step procedure, including torque specs, checks between steps and the reasoning behind them.";
+
+/// Guidance when the manual context comes mostly from a parts catalog
+pub const PARTS_MANUAL_PROMPT: &str = "**Manual Type:** The manual context is from a parts catalog. Give the exact part numbers, names and quantities as listed, and say which assembly or diagram each part belongs to.";
+
+/// Guidance when the manual context comes mostly from an owner's manual
+pub const OWNER_MANUAL_PROMPT: &str = "**Manual Type:** The manual context is from an owner's manual. Emphasize maintenance intervals (distance or time), recommended fluids and capacities, and the checks a rider can do at home.";
+
+/// Guidance when the manual context comes mostly from a service manual
+pub const SERVICE_MANUAL_PROMPT: &str = "**Manual Type:** The manual context is from a service manual. Follow its procedure in order, with torque specs, clearances and the special tools it calls for.";
+
+/// Instructions for compressing older conversation turns into a summary
+pub const HISTORY_SUMMARY_PROMPT: &str = "Summarize this motorcycle repair conversation in at most 5 sentences. Keep the bike model, the symptoms, what was already checked or tried, and any specs or measurements mentioned. Reply with the summary only.";
+
+/// Start of the message that stands in for summarized turns
+pub const HISTORY_SUMMARY_PREFIX: &str = "Summary of the earlier conversation:";
+
+/// Longest `ASSISTANT_NAME` or `SHOP_NAME` accepted
+pub const MAX_PERSONA_NAME_CHARS: usize = 80;
+
+/// Load the system prompt from a file, or the built-in prompt when no path is set
+pub fn load_system_prompt(path: Option<&str>) -> anyhow::Result<String> {
+    match path {
+        Some(path) => {
+            let prompt = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read system prompt {}: {}", path, e))?;
+            if prompt.trim().is_empty() {
+                anyhow::bail!("System prompt file {} is empty", path);
+            }
+            Ok(prompt)
+        }
+        None => Ok(SYSTEM_PROMPT.to_string()),
+    }
+}
+
 /// Build the complete prompt for a chat request
 pub fn build_chat_prompt(
     user_query: &str,
     retrieved_context: Option<&str>,
     chat_history: &[Message],
+) -> Vec<Message> {
+    build_chat_prompt_with_system(SYSTEM_PROMPT, user_query, retrieved_context, chat_history)
+}
+
+/// Build the complete prompt for a chat request using a custom system prompt
+pub fn build_chat_prompt_with_system(
+    system_prompt: &str,
+    user_query: &str,
+    retrieved_context: Option<&str>,
+    chat_history: &[Message],
 ) -> Vec<Message> {
     let mut messages = Vec::new();
 
@@ -37,22 +115,22 @@ pub fn build_chat_prompt(
     let system_content = if let Some(context) = retrieved_context {
         format!(
             "{}\n\n**Manual Context:**\n{}\n\nAlways cite the manual when using this context.",
-            SYSTEM_PROMPT, context
+            system_prompt, context
         )
     } else {
-        SYSTEM_PROMPT.to_string()
+        system_prompt.to_string()
     };
 
     messages.push(Message::system(system_content));
 
-    // Add recent chat history (limit to last 6 messages to avoid token limits)
-    let recent_history = if chat_history.len() > 6 {
-        &chat_history[chat_history.len() - 6..]
-    } else {
-        chat_history
-    };
+    // Add the summary of older turns, if any, and recent chat history (limit to
+    // last 6 messages to avoid token limits)
+    let (summaries, turns): (Vec<_>, Vec<_>) =
+        chat_history.iter().partition(|m| m.role == Role::System);
+    let recent_history = &turns[turns.len().saturating_sub(6)..];
 
-    messages.extend(recent_history.iter().cloned());
+    messages.extend(summaries.into_iter().cloned());
+    messages.extend(recent_history.iter().copied().cloned());
 
     // Add current user query
     messages.push(Message::user(user_query));
@@ -60,6 +138,234 @@ pub fn build_chat_prompt(
     messages
 }
 
+/// Prompt asking the model to summarize conversation turns, including an
+/// earlier summary among them
+pub fn build_history_summary_prompt(turns: &[Message]) -> Vec<Message> {
+    let transcript = turns
+        .iter()
+        .map(|m| format!("{}: {}", m.role, m.content))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    vec![Message::system(HISTORY_SUMMARY_PROMPT), Message::user(transcript)]
+}
+
+/// Append the structured output instructions to the system message
+pub fn add_structured_output_instructions(messages: &mut [Message]) {
+    if let Some(system) = messages.iter_mut().find(|m| m.role == Role::System) {
+        system.content = format!("{}\n\n{}", system.content, STRUCTURED_OUTPUT_PROMPT);
+    }
+}
+
+/// Append the follow-up question instructions to the system message
+pub fn add_suggestion_instructions(messages: &mut [Message], structured: bool) {
+    let guidance = match structured {
+        true => STRUCTURED_SUGGESTIONS_PROMPT,
+        false => SUGGESTIONS_PROMPT,
+    };
+    if let Some(system) = messages.iter_mut().find(|m| m.role == Role::System) {
+        system.content = format!("{}\n\n{}", system.content, guidance);
+    }
+}
+
+/// Append answer-length guidance to the system message (normal adds nothing)
+pub fn add_verbosity_instructions(messages: &mut [Message], verbosity: Verbosity) {
+    let guidance = match verbosity {
+        Verbosity::Concise => CONCISE_PROMPT,
+        Verbosity::Normal => return,
+        Verbosity::Detailed => DETAILED_PROMPT,
+    };
+    if let Some(system) = messages.iter_mut().find(|m| m.role == Role::System) {
+        system.content = format!("{}\n\n{}", system.content, guidance);
+    }
+}
+
+/// Append answer-style guidance for the kind of manual the context came from
+/// (unrecognized manual types add nothing)
+pub fn add_manual_type_instructions(messages: &mut [Message], manual_type: &str) {
+    let manual_type = manual_type.to_lowercase();
+    let guidance = if manual_type.contains("part") {
+        PARTS_MANUAL_PROMPT
+    } else if manual_type.contains("owner") || manual_type.contains("user") {
+        OWNER_MANUAL_PROMPT
+    } else if ["service", "repair", "workshop"].iter().any(|t| manual_type.contains(t)) {
+        SERVICE_MANUAL_PROMPT
+    } else {
+        return;
+    };
+    if let Some(system) = messages.iter_mut().find(|m| m.role == Role::System) {
+        system.content = format!("{}\n\n{}", system.content, guidance);
+    }
+}
+
+/// Persona instructions for a branded assistant; `None` when neither name is
+/// configured, leaving the generic assistant
+pub fn persona_prompt(assistant_name: Option<&str>, shop_name: Option<&str>) -> Option<String> {
+    let identity = match (assistant_name, shop_name) {
+        (Some(name), Some(shop)) => format!(
+            "Your name is {name}, the master mechanic of {shop}. Introduce yourself as {name} \
+             when greeting or asked who you are, and refer to the shop as {shop}, e.g. when \
+             suggesting to bring the motorcycle in for professional help."
+        ),
+        (Some(name), None) => format!(
+            "Your name is {name}. Introduce yourself as {name} when greeting or asked who you are."
+        ),
+        (None, Some(shop)) => format!(
+            "You are the repair assistant of {shop}. Refer to the shop as {shop}, e.g. when \
+             suggesting to bring the motorcycle in for professional help."
+        ),
+        (None, None) => return None,
+    };
+    Some(format!("**Persona:** {}", identity))
+}
+
+/// Append the persona instructions to the system message (no names add nothing)
+pub fn add_persona_instructions(
+    messages: &mut [Message],
+    assistant_name: Option<&str>,
+    shop_name: Option<&str>,
+) {
+    let Some(persona) = persona_prompt(assistant_name, shop_name) else {
+        return;
+    };
+    if let Some(system) = messages.iter_mut().find(|m| m.role == Role::System) {
+        system.content = format!("{}\n\n{}", system.content, persona);
+    }
+}
+
+/// Tell the model which motorcycle the user has, in a system message right
+/// after the system prompt
+pub fn add_bike_note(messages: &mut Vec<Message>, bike: &str) {
+    let note = Message::system(format!("The user's motorcycle is a {}.", bike));
+    messages.insert(messages.len().min(1), note);
+}
+
+/// A structured answer as the model writes it
+#[derive(Deserialize)]
+struct ModelAnswer {
+    answer: String,
+    #[serde(default)]
+    steps: Vec<ModelStep>,
+    #[serde(default)]
+    safety_warnings: Vec<String>,
+    #[serde(default)]
+    tools_needed: Vec<String>,
+}
+
+/// A step with the sources it cites, or bare text
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ModelStep {
+    Text(String),
+    Cited {
+        text: String,
+        #[serde(default)]
+        sources: Vec<usize>,
+    },
+}
+
+impl From<ModelAnswer> for StructuredAnswer {
+    fn from(answer: ModelAnswer) -> Self {
+        let (steps, mut step_sources): (Vec<_>, Vec<_>) = answer
+            .steps
+            .into_iter()
+            .map(|step| match step {
+                ModelStep::Text(text) => (text, Vec::new()),
+                ModelStep::Cited { text, sources } => (text, sources),
+            })
+            .unzip();
+        if step_sources.iter().all(Vec::is_empty) {
+            step_sources.clear();
+        }
+        StructuredAnswer {
+            answer: answer.answer,
+            steps,
+            step_sources,
+            safety_warnings: answer.safety_warnings,
+            tools_needed: answer.tools_needed,
+        }
+    }
+}
+
+/// Mean relevance of the sources an answer's steps cite, each counted once;
+/// citations of numbers outside `sources` are ignored. 0 when none is cited.
+pub fn answer_confidence(answer: &StructuredAnswer, sources: &[Source]) -> f32 {
+    let mut cited: Vec<usize> = answer
+        .step_sources
+        .iter()
+        .flatten()
+        .copied()
+        .filter(|n| (1..=sources.len()).contains(n))
+        .collect();
+    cited.sort_unstable();
+    cited.dedup();
+    if cited.is_empty() {
+        return 0.0;
+    }
+    cited.iter().map(|n| sources[n - 1].relevance_score).sum::<f32>() / cited.len() as f32
+}
+
+/// The JSON of a response, tolerating a markdown code fence around it
+fn strip_code_fence(response: &str) -> &str {
+    let trimmed = response.trim();
+    trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+        .and_then(|rest| rest.strip_suffix("```"))
+        .unwrap_or(trimmed)
+        .trim()
+}
+
+/// Parse a structured answer, returning `None` if the model ignored the format
+pub fn parse_structured_answer(response: &str) -> Option<StructuredAnswer> {
+    match serde_json::from_str::<ModelAnswer>(strip_code_fence(response)) {
+        Ok(answer) if !answer.answer.trim().is_empty() => Some(answer.into()),
+        Ok(_) => None,
+        Err(e) => {
+            log::warn!("Structured answer could not be parsed: {}", e);
+            None
+        }
+    }
+}
+
+/// Take the follow-up questions out of a response: the trailing
+/// `<suggestions>` block of a text answer, removed from the text, or the
+/// `suggestions` field of a structured one. No questions when the model
+/// ignored the format.
+pub fn split_suggestions(response: &str) -> (String, Vec<String>) {
+    if let Some(start) = response.rfind("<suggestions>") {
+        let block = &response[start + "<suggestions>".len()..];
+        let block = block.split("</suggestions>").next().unwrap_or_default();
+        let answer = response[..start].trim_end().to_string();
+        return (answer, clean_suggestions(block.lines()));
+    }
+
+    #[derive(Deserialize)]
+    struct WithSuggestions {
+        suggestions: Vec<String>,
+    }
+    let suggestions = serde_json::from_str::<WithSuggestions>(strip_code_fence(response))
+        .map(|parsed| clean_suggestions(parsed.suggestions.iter().map(String::as_str)))
+        .unwrap_or_default();
+    (response.to_string(), suggestions)
+}
+
+/// Questions without list markers, blank and overlong lines dropped
+fn clean_suggestions<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<String> {
+    lines
+        .map(|line| {
+            let line = line.trim().trim_start_matches(['-', '*', '\u{2022}']);
+            let unnumbered = line.trim_start_matches(|c: char| c.is_ascii_digit());
+            let line = match unnumbered.strip_prefix(['.', ')']) {
+                Some(rest) if unnumbered.len() < line.len() => rest,
+                _ => line,
+            };
+            line.trim().to_string()
+        })
+        .filter(|line| !line.is_empty() && line.chars().count() <= MAX_SUGGESTION_CHARS)
+        .take(MAX_SUGGESTIONS)
+        .collect()
+}
+
 /// Validate that a response is appropriate
 pub fn validate_response(response: &str) -> bool {
     // Make sure response isn't empty
@@ -90,11 +396,147 @@ mod tests {
         );
 
         assert_eq!(messages.len(), 2); // system + user
-        assert_eq!(messages[0].role, "system");
-        assert_eq!(messages[1].role, "user");
+        assert_eq!(messages[0].role, Role::System);
+        assert_eq!(messages[1].role, Role::User);
         assert!(messages[0].content.contains("Manual Context"));
     }
 
+    #[test]
+    fn test_history_summary_kept_before_recent_turns() {
+        let mut history = vec![Message::system(format!("{} Chain slack.", HISTORY_SUMMARY_PREFIX))];
+        for i in 0..4 {
+            history.push(Message::user(format!("q{}", i)));
+            history.push(Message::assistant(format!("a{}", i)));
+        }
+
+        let messages = build_chat_prompt("How do I change oil?", None, &history);
+
+        let contents: Vec<_> = messages[1..].iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                "Summary of the earlier conversation: Chain slack.",
+                "q1",
+                "a1",
+                "q2",
+                "a2",
+                "q3",
+                "a3",
+                "How do I change oil?"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_structured_output_instructions_added_to_system() {
+        let mut messages = build_chat_prompt("How do I bleed brakes?", None, &[]);
+        add_structured_output_instructions(&mut messages);
+
+        assert!(messages[0].content.contains("JSON"));
+        assert!(messages[0].content.contains("safety_warnings"));
+        assert!(!messages[1].content.contains("JSON"));
+    }
+
+    #[test]
+    fn test_verbosity_instructions_differ() {
+        let prompt_for = |verbosity| {
+            let mut messages = build_chat_prompt("How do I bleed brakes?", None, &[]);
+            add_verbosity_instructions(&mut messages, verbosity);
+            messages[0].content.clone()
+        };
+
+        assert_eq!(prompt_for(Verbosity::Normal), SYSTEM_PROMPT);
+        assert!(prompt_for(Verbosity::Concise).ends_with(CONCISE_PROMPT));
+        assert!(prompt_for(Verbosity::Detailed).ends_with(DETAILED_PROMPT));
+    }
+
+    #[test]
+    fn test_persona_names_added_to_system() {
+        let prompt_for = |name, shop| {
+            let mut messages = build_chat_prompt("How do I bleed brakes?", None, &[]);
+            add_persona_instructions(&mut messages, name, shop);
+            messages
+        };
+
+        let messages = prompt_for(Some("Mike"), Some("Joe's Garage"));
+        assert!(messages[0].content.contains("Your name is Mike"));
+        assert!(messages[0].content.contains("Joe's Garage"));
+        assert!(!messages[1].content.contains("Mike"));
+        assert!(prompt_for(None, Some("Joe's Garage"))[0].content.contains("Joe's Garage"));
+        assert_eq!(prompt_for(None, None)[0].content, SYSTEM_PROMPT);
+    }
+
+    #[test]
+    fn test_parse_structured_answer() {
+        let parsed = parse_structured_answer(
+            r#"```json
+{"answer": "Bleed the brakes", "steps": ["Open bleeder"], "safety_warnings": ["Fluid damages paint"]}
+```"#,
+        )
+        .unwrap();
+
+        assert_eq!(parsed.answer, "Bleed the brakes");
+        assert_eq!(parsed.steps, vec!["Open bleeder"]);
+        assert_eq!(parsed.safety_warnings, vec!["Fluid damages paint"]);
+        assert!(parsed.tools_needed.is_empty());
+    }
+
+    #[test]
+    fn test_confidence_from_cited_sources() {
+        let parsed = parse_structured_answer(
+            r#"{"answer": "Adjust the chain", "steps": [
+                {"text": "Loosen axle nut", "sources": [1]},
+                {"text": "Turn adjusters", "sources": [1, 3, 9]},
+                "Ride carefully"]}"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.steps[2], "Ride carefully");
+        assert_eq!(parsed.step_sources, vec![vec![1], vec![1, 3, 9], vec![]]);
+
+        let source = |relevance_score| Source {
+            chunk_id: "chunk-1".to_string(),
+            document_id: "doc-1".to_string(),
+            bike_model: "Yamaha R1".to_string(),
+            page_number: None,
+            volume: None,
+            section: None,
+            relevance_score,
+            excerpt: None,
+        };
+        let sources = [source(0.9), source(0.2), source(0.5)];
+        // Sources 1 and 3; 9 does not exist
+        assert!((answer_confidence(&parsed, &sources) - 0.7).abs() < 1e-6);
+
+        let uncited = parse_structured_answer(r#"{"answer": "a", "steps": ["b"]}"#).unwrap();
+        assert!(uncited.step_sources.is_empty());
+        assert_eq!(answer_confidence(&uncited, &sources), 0.0);
+    }
+
+    #[test]
+    fn test_parse_structured_answer_rejects_plain_text() {
+        assert!(parse_structured_answer("Just open the bleeder valve.").is_none());
+        assert!(parse_structured_answer(r#"{"steps": []}"#).is_none());
+    }
+
+    #[test]
+    fn test_split_suggestions() {
+        let response = "Adjust the chain slack.\n\n<suggestions>\n1. How do I check chain \
+            tension?\n- What chain lube do you recommend?\n\nHow often?\nA fourth?\n</suggestions>";
+        let (answer, suggestions) = split_suggestions(response);
+        assert_eq!(answer, "Adjust the chain slack.");
+        assert_eq!(
+            suggestions,
+            vec!["How do I check chain tension?", "What chain lube do you recommend?", "How often?"]
+        );
+
+        let structured = r#"{"answer": "Adjust it", "suggestions": ["* Why?"]}"#;
+        assert_eq!(split_suggestions(structured), (structured.to_string(), vec!["Why?".into()]));
+
+        // The model ignored the format
+        let plain = "Adjust the chain slack.";
+        assert_eq!(split_suggestions(plain), (plain.to_string(), Vec::new()));
+    }
+
     #[test]
     fn test_validate_response() {
         assert!(validate_response("This is a valid response"));
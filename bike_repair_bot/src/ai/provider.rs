@@ -0,0 +1,251 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt};
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use crate::ai::OpenAIClient;
+use crate::models::Message;
+
+/// Temperatures the chat API accepts
+pub const TEMPERATURE_RANGE: RangeInclusive<f32> = 0.0..=2.0;
+
+/// `top_p` values the chat API accepts
+pub const TOP_P_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+
+/// Most alternative answers one completion may generate; each is billed
+pub const MAX_COMPLETION_CHOICES: u8 = 4;
+
+/// Per-call options for a chat completion
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompletionOptions {
+    /// Maximum tokens to generate
+    pub max_tokens: Option<u16>,
+
+    /// Ask the model for a JSON object (`response_format: json_object`)
+    pub json_mode: bool,
+
+    /// Sampling temperature, 0.0–2.0 (`None` uses the API default)
+    pub temperature: Option<f32>,
+
+    /// Nucleus sampling probability mass, 0.0–1.0 (`None` uses the API default)
+    pub top_p: Option<f32>,
+
+    /// Alternative answers to generate (the API's `n`), at most
+    /// `MAX_COMPLETION_CHOICES`; `None` generates one. Only `complete_choices`
+    /// returns more than the first.
+    pub choices: Option<u8>,
+}
+
+/// Completion text as it arrives from the model. Dropping the stream cancels
+/// the upstream request.
+pub type CompletionStream = BoxStream<'static, Result<String>>;
+
+/// Extra attempts for a failed embedding batch before giving up on it
+pub const EMBEDDING_BATCH_RETRIES: u32 = 2;
+
+/// Delay before the first retry; doubled for each further attempt
+const EMBEDDING_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Embeddings for a list of inputs where some batches may have failed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchEmbeddings {
+    /// Embedding per input index; `None` where the input's batch failed
+    pub embeddings: Vec<Option<Vec<f32>>>,
+
+    /// Error of each failed batch
+    pub errors: Vec<String>,
+}
+
+impl BatchEmbeddings {
+    /// Input indices that were embedded
+    pub fn succeeded(&self) -> Vec<usize> {
+        (0..self.embeddings.len())
+            .filter(|&i| self.embeddings[i].is_some())
+            .collect()
+    }
+
+    /// Input indices whose batch failed after retries
+    pub fn failed(&self) -> Vec<usize> {
+        (0..self.embeddings.len())
+            .filter(|&i| self.embeddings[i].is_none())
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.embeddings.iter().all(Option::is_some)
+    }
+}
+
+/// Abstraction over the model backend used for completions and embeddings
+#[async_trait]
+pub trait AiProvider: Send + Sync {
+    /// Generate a chat completion with explicit options
+    async fn complete(&self, messages: Vec<Message>, options: CompletionOptions) -> Result<String>;
+
+    /// Generate `options.choices` alternative completions, in the model's
+    /// order; by default backends produce a single one
+    async fn complete_choices(
+        &self,
+        messages: Vec<Message>,
+        options: CompletionOptions,
+    ) -> Result<Vec<String>> {
+        Ok(vec![self.complete(messages, options).await?])
+    }
+
+    /// Stream a chat completion; by default the whole completion arrives as one piece
+    async fn complete_stream(
+        &self,
+        messages: Vec<Message>,
+        options: CompletionOptions,
+    ) -> Result<CompletionStream> {
+        let text = self.complete(messages, options).await?;
+        Ok(futures::stream::once(async move { Ok(text) }).boxed())
+    }
+
+    /// Generate a chat completion
+    async fn chat_completion(
+        &self,
+        messages: Vec<Message>,
+        max_tokens: Option<u16>,
+    ) -> Result<String> {
+        let options = CompletionOptions {
+            max_tokens,
+            ..Default::default()
+        };
+        self.complete(messages, options).await
+    }
+
+    /// Check the backend accepts our credentials; by default by embedding a word
+    async fn check_access(&self) -> Result<()> {
+        self.generate_embedding("ping").await.map(|_| ())
+    }
+
+    /// Generate embeddings for text
+    async fn generate_embedding(&self, text: &str) -> Result<Vec<f32>>;
+
+    /// Generate embeddings for multiple texts in batch
+    async fn generate_embeddings_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>>;
+
+    /// Embed texts in batches of `batch_size`, retrying failed batches. A batch
+    /// that still fails is reported instead of failing the whole call.
+    async fn generate_embeddings_partial(
+        &self,
+        texts: Vec<String>,
+        batch_size: usize,
+    ) -> BatchEmbeddings {
+        let batch_size = batch_size.max(1);
+        let mut result = BatchEmbeddings {
+            embeddings: vec![None; texts.len()],
+            errors: Vec::new(),
+        };
+
+        for (batch_index, batch) in texts.chunks(batch_size).enumerate() {
+            let offset = batch_index * batch_size;
+            let mut attempt = 0;
+            loop {
+                let error = match self.generate_embeddings_batch(batch.to_vec()).await {
+                    Ok(embeddings) if embeddings.len() == batch.len() => {
+                        for (i, embedding) in embeddings.into_iter().enumerate() {
+                            result.embeddings[offset + i] = Some(embedding);
+                        }
+                        break;
+                    }
+                    Ok(embeddings) => format!(
+                        "provider returned {} embeddings for {} inputs",
+                        embeddings.len(),
+                        batch.len()
+                    ),
+                    Err(e) => e.to_string(),
+                };
+
+                if attempt >= EMBEDDING_BATCH_RETRIES {
+                    log::warn!(
+                        "Embedding batch {}..{} failed after {} attempts: {}",
+                        offset,
+                        offset + batch.len(),
+                        attempt + 1,
+                        error
+                    );
+                    result.errors.push(format!(
+                        "inputs {}..{}: {}",
+                        offset,
+                        offset + batch.len(),
+                        error
+                    ));
+                    break;
+                }
+                tokio::time::sleep(EMBEDDING_RETRY_BACKOFF * 2u32.pow(attempt)).await;
+                attempt += 1;
+            }
+        }
+
+        result
+    }
+}
+
+/// Prime the provider's connections before serving traffic with a one-word
+/// embedding and a one-token completion. Returns how long it took.
+pub async fn warm_up(provider: &dyn AiProvider) -> Result<Duration> {
+    let started = std::time::Instant::now();
+    provider.generate_embedding("warm-up").await?;
+    let options = CompletionOptions {
+        max_tokens: Some(1),
+        ..Default::default()
+    };
+    provider.complete(vec![Message::user("ping")], options).await?;
+    Ok(started.elapsed())
+}
+
+#[async_trait]
+impl AiProvider for OpenAIClient {
+    async fn complete(&self, messages: Vec<Message>, options: CompletionOptions) -> Result<String> {
+        OpenAIClient::complete(self, messages, options).await
+    }
+
+    async fn complete_choices(
+        &self,
+        messages: Vec<Message>,
+        options: CompletionOptions,
+    ) -> Result<Vec<String>> {
+        OpenAIClient::complete_choices(self, messages, options).await
+    }
+
+    async fn complete_stream(
+        &self,
+        messages: Vec<Message>,
+        options: CompletionOptions,
+    ) -> Result<CompletionStream> {
+        OpenAIClient::complete_stream(self, messages, options).await
+    }
+
+    async fn check_access(&self) -> Result<()> {
+        OpenAIClient::check_access(self).await
+    }
+
+    async fn generate_embedding(&self, text: &str) -> Result<Vec<f32>> {
+        OpenAIClient::generate_embedding(self, text).await
+    }
+
+    async fn generate_embeddings_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        OpenAIClient::generate_embeddings_batch(self, texts).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::MockProvider;
+
+    #[tokio::test]
+    async fn test_warm_up_makes_one_embedding_and_one_completion() {
+        let provider = MockProvider::new("pong");
+        warm_up(&provider).await.unwrap();
+
+        assert_eq!(provider.embedded_texts.lock().unwrap().len(), 1);
+        assert_eq!(provider.chat_call_count(), 1);
+        assert_eq!(provider.last_options().unwrap().max_tokens, Some(1));
+
+        assert!(warm_up(&MockProvider::failing()).await.is_err());
+    }
+}
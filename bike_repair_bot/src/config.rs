@@ -1,107 +1,774 @@
-use anyhow::Result;
-use dotenv::dotenv;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::maintenance::DEFAULT_MAINTENANCE_MESSAGE;
+use crate::ai::{MAX_PERSONA_NAME_CHARS, TEMPERATURE_RANGE, TOP_P_RANGE};
+use crate::metrics::DEFAULT_LATENCY_BUCKETS;
+use crate::models::{same_model, ChunkIdMode, DEFAULT_TENANT};
+use crate::pdf::{
+    DEFAULT_BOILERPLATE_PAGE_RATIO, DEFAULT_CHUNK_MIN_ALPHA_RATIO, DEFAULT_CHUNK_MIN_TOKENS,
+    DEFAULT_IMAGE_ONLY_PAGE_RATIO, KNOWN_MODELS,
+};
+use crate::security::{ChatApiKeys, TenantApiKeys, DEFAULT_MAX_SPECIAL_CHAR_RATIO};
+use crate::sessions::SessionBackend;
+use crate::rag::{
+    Distance, DEFAULT_COLLECTION, DEFAULT_EMBEDDING_MAX_INPUT_TOKENS, EMBEDDING_BATCH_SIZE,
+};
 
 /// Application configuration loaded from environment variables
 #[derive(Debug, Clone)]
 pub struct Config {
     // OpenAI Configuration
     pub openai_api_key: String,
+    pub openai_org_id: Option<String>,
+    pub openai_project_id: Option<String>,
     pub openai_chat_model: String,
     pub openai_embedding_model: String,
+    pub embedding_max_input_tokens: usize,
+    pub embedding_workers: usize,
+    pub embedding_queue_depth: usize,
+    pub embedding_batch_size: usize,
+    pub system_prompt_path: Option<String>,
+    pub max_response_tokens: u16,
+    /// Sampling temperature for chat answers; lower gives more consistent repair steps
+    pub openai_temperature: f32,
+    pub openai_top_p: f32,
+    pub response_footer: Option<String>,
+    /// Name the assistant introduces itself with, e.g. "Mike"
+    pub assistant_name: Option<String>,
+    /// Shop the assistant answers for, e.g. "Joe's Garage"
+    pub shop_name: Option<String>,
+    /// Ask the model for follow-up questions after each `/api/chat` answer,
+    /// unless the request sets `include_suggestions: false`
+    pub suggestions_enabled: bool,
+    /// Make an embedding and a completion call at startup so the first
+    /// request does not pay for connection setup
+    pub enable_warmup: bool,
 
     // Server Configuration
     pub server_host: String,
     pub server_port: u16,
+    pub admin_api_key: Option<String>,
+    /// API key → tenant id; a chat or upload is scoped to the tenant of its key
+    pub tenant_api_keys: TenantApiKeys,
+    /// Tenants a request without an API key may select with `X-Tenant-Id`;
+    /// a tenant with API keys is only reachable with one of them
+    pub tenant_ids: Vec<String>,
+    /// Keys accepted in `X-Api-Key` by the chat endpoints; empty leaves them open
+    pub chat_api_keys: ChatApiKeys,
+    pub sse_keep_alive_seconds: u64,
+    /// A chat WebSocket receiving no message for this long is closed
+    pub ws_idle_timeout_seconds: u64,
+    /// Chat requests one WebSocket connection may send before it is closed
+    pub ws_max_messages_per_connection: usize,
+    /// Time in-flight requests get to finish once shutdown begins
+    pub shutdown_grace_seconds: u64,
+    /// Largest chat or feedback request body accepted, checked against `Content-Length`
+    pub max_chat_body_bytes: u64,
+    /// Smallest `/api` response body compressed for clients sending `Accept-Encoding`
+    pub compression_min_bytes: u64,
+    /// Deadline for a whole `/api/chat` request, retrieval and completion included
+    pub request_timeout_seconds: u64,
+    /// Directory of the web frontend served at `/`; unset serves only the API
+    pub static_dir: Option<String>,
+    /// Serve Swagger UI for `/api/openapi.json` at `/api/docs`
+    pub enable_api_docs: bool,
+    /// Start with chat switched off for maintenance; `POST /api/admin/maintenance`
+    /// toggles it at runtime
+    pub maintenance_mode: bool,
+    /// Message chat callers get while maintenance mode is on
+    pub maintenance_message: String,
+    /// PEM certificate chain and private key; with both set the server speaks HTTPS
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    /// Port of a plain-HTTP listener redirecting to HTTPS; unset runs none
+    pub redirect_http_port: Option<u16>,
+    /// `/api/health/ready` embeds a probe text at most this often; 0 skips it
+    pub readiness_embedding_interval_seconds: u64,
+    /// Upper bounds (seconds) of the latency histograms on `/metrics`
+    pub metrics_latency_buckets: Vec<f64>,
 
     // Vector Database Configuration
-    pub qdrant_path: String,
+    /// Directory of the embedded vector store; `QDRANT_PATH` is read as a
+    /// deprecated alias
+    pub vector_store_path: String,
+    /// Collection (and alias) name; `QDRANT_COLLECTION` is read as a deprecated alias
+    pub vector_store_collection: String,
+    pub vector_distance: Distance,
+    /// Tries at opening the vector store on startup before giving up
+    pub vector_store_init_attempts: u32,
+    /// Delay before the first retry; doubled for each further attempt
+    pub vector_store_init_backoff_ms: u64,
+    /// Start even when the collection's vectors do not match the embedding
+    /// model's dimension (searches then skip them until `reembed`)
+    pub allow_dimension_mismatch: bool,
+    /// Directory receiving collection snapshots
+    pub snapshot_dir: String,
+    /// Hours between scheduled snapshots; 0 only snapshots on request
+    pub snapshot_interval_hours: u64,
+    /// Snapshots kept per collection, the oldest deleted first; 0 keeps all
+    pub snapshot_keep: usize,
 
     // Rate Limiting Configuration
+    pub blocklist_path: Option<String>,
     pub max_requests_per_minute: u32,
     pub max_requests_per_hour: u32,
+    pub max_uploads_per_minute: u32,
+    pub max_uploads_per_hour: u32,
+    pub max_feedback_per_minute: u32,
+    pub max_feedback_per_hour: u32,
+    /// Invalid admin keys an IP may send before it is locked out of the
+    /// management endpoints
+    pub admin_max_failures_per_minute: u32,
+    pub admin_max_failures_per_hour: u32,
+    /// Identical queries one IP may send within the repeat window (0 disables)
+    pub repeat_query_limit: u32,
+    pub repeat_query_window_seconds: u64,
+    /// Queries with fewer words or characters are refused with 422
+    /// `QUERY_TOO_SHORT` (0 disables)
+    pub min_query_words: usize,
+    pub min_query_chars: usize,
+    /// Share of special characters above which a query is refused
+    pub max_special_char_ratio: f32,
+
+    // Session Configuration
+    pub session_store: SessionBackend,
+    /// SQLite database used when `session_store` is `sqlite`
+    pub session_db_path: String,
+    /// Messages of conversation history kept per session (0 disables history)
+    pub session_max_messages: usize,
+    /// Sessions idle for longer are forgotten
+    pub session_ttl_seconds: u64,
+    /// Tokens one session may spend before its requests are refused (0 disables)
+    pub session_token_budget: u64,
+    /// Tokens paid back to a session's budget per hour (0 never refills)
+    pub session_token_refill_per_hour: u64,
+    /// SQLite database holding ratings of answers
+    pub feedback_db_path: String,
+    /// SQLite database of per-request usage records for `GET /api/admin/usage`
+    pub usage_db_path: String,
+    /// Secret keying the client IP hashes in usage records; unset uses one
+    /// generated next to the usage database (`<USAGE_DB_PATH>.key`)
+    pub usage_ip_hash_secret: Option<String>,
+    /// Usage records older than this are pruned; 0 keeps them forever
+    pub usage_retention_days: u64,
+    /// Prices in USD per million prompt and completion tokens, for the usage
+    /// report's cost estimate
+    pub usage_input_price_per_million: f64,
+    pub usage_output_price_per_million: f64,
+    /// Summarize older turns once a session's history has more messages than
+    /// the threshold, keeping the most recent ones verbatim
+    pub enable_history_summary: bool,
+    pub history_summary_threshold: usize,
+    pub history_summary_keep_recent: usize,
 
     // Circuit Breaker Configuration
     pub circuit_breaker_threshold: u32,
     pub circuit_breaker_timeout_seconds: u64,
+    /// Every circuit breaker state change is POSTed here as JSON
+    pub alert_webhook_url: Option<String>,
 
     // PDF Processing Configuration
     pub max_pdf_size_mb: u64,
+    /// Most `file` parts (volumes) one upload or preview may carry
+    pub max_upload_volumes: usize,
+    /// Limit on the size of all files of one upload or preview together
+    pub max_upload_total_mb: u64,
     pub chunk_size_tokens: usize,
     pub chunk_overlap_tokens: usize,
+    /// Chunks adding fewer new tokens are merged into the previous chunk
+    /// (at most half the chunk size; 0 disables). Unlike `chunk_min_tokens`,
+    /// nothing is dropped. `MIN_CHUNK_TOKENS` is read as a deprecated alias.
+    pub chunk_merge_below_tokens: usize,
+    pub chunk_id_mode: ChunkIdMode,
+    pub model_detection_min_confidence: f32,
+    /// Remove running headers and footers from pages before chunking
+    pub strip_boilerplate: bool,
+    pub boilerplate_page_ratio: f32,
+    /// PDFs with at least this share of pages without text are rejected as
+    /// scanned, advising OCR
+    pub image_only_page_ratio: f32,
+    pub chunk_min_tokens: usize,
+    pub chunk_min_alpha_ratio: f32,
+    pub chunk_drop_toc_and_captions: bool,
+    pub url_ingest_allowed_hosts: Vec<String>,
+
+    // Retrieval Configuration
+    pub retrieval_top_k: usize,
+    pub min_confidence: f32,
+    /// Structured answers whose cited sources average a lower relevance get
+    /// a warning to have the work checked by a mechanic
+    pub low_confidence_threshold: f32,
+    /// Quote the start of each cited chunk in chat `sources`
+    pub source_excerpts_enabled: bool,
+    /// Characters of chunk text quoted per source, cut back to a word boundary
+    pub source_excerpt_chars: usize,
+    pub default_bike_model: Option<String>,
+    /// Bike models a chat may ask about besides the built-in ones
+    pub known_bike_models: Vec<String>,
+    pub synonyms_path: Option<String>,
 }
 
 impl Config {
-    /// Load configuration from environment variables
-    pub fn from_env() -> Result<Self> {
-        // Load .env file if it exists
-        dotenv().ok();
+    /// Load configuration from environment variables, reporting every missing
+    /// or malformed variable at once. The .env file is not read here; `main`
+    /// loads it before calling this.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_env_with(HashMap::new())
+    }
 
-        Ok(Config {
+    /// `from_env`, with `overrides` taking precedence over the process
+    /// environment (how a reload applies the .env file without touching the
+    /// environment)
+    pub fn from_env_with(overrides: HashMap<String, String>) -> Result<Self, ConfigError> {
+        let mut vars = EnvReader::new(overrides);
+        let config = Config {
             // OpenAI Configuration
-            openai_api_key: env::var("OPENAI_API_KEY")
-                .expect("OPENAI_API_KEY must be set in .env file"),
-            openai_chat_model: env::var("OPENAI_CHAT_MODEL")
-                .unwrap_or_else(|_| "gpt-4o-mini".to_string()),
-            openai_embedding_model: env::var("OPENAI_EMBEDDING_MODEL")
-                .unwrap_or_else(|_| "text-embedding-3-small".to_string()),
+            openai_api_key: vars.required("OPENAI_API_KEY", "your OpenAI API key"),
+            openai_org_id: vars.var("OPENAI_ORG_ID")
+                .filter(|id| !id.trim().is_empty()),
+            openai_project_id: vars.var("OPENAI_PROJECT_ID")
+                .filter(|id| !id.trim().is_empty()),
+            openai_chat_model: vars.var("OPENAI_CHAT_MODEL")
+                .unwrap_or_else(|| "gpt-4o-mini".to_string()),
+            openai_embedding_model: vars.var("OPENAI_EMBEDDING_MODEL")
+                .unwrap_or_else(|| "text-embedding-3-small".to_string()),
+            embedding_max_input_tokens: vars
+                .number("EMBEDDING_MAX_INPUT_TOKENS", DEFAULT_EMBEDDING_MAX_INPUT_TOKENS),
+            embedding_workers: vars.number("EMBEDDING_WORKERS", 4),
+            embedding_queue_depth: vars.number("EMBEDDING_QUEUE_DEPTH", 8),
+            embedding_batch_size: vars.number("EMBEDDING_BATCH_SIZE", EMBEDDING_BATCH_SIZE),
+            system_prompt_path: vars.var("SYSTEM_PROMPT_PATH"),
+            max_response_tokens: vars.number("MAX_RESPONSE_TOKENS", 1000),
+            openai_temperature: vars.number("OPENAI_TEMPERATURE", 1.0),
+            openai_top_p: vars.number("OPENAI_TOP_P", 1.0),
+            response_footer: vars.var("RESPONSE_FOOTER")
+                .map(|footer| footer.trim().to_string())
+                .filter(|footer| !footer.is_empty()),
+            assistant_name: vars.var("ASSISTANT_NAME")
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty()),
+            shop_name: vars.var("SHOP_NAME")
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty()),
+            enable_warmup: vars.flag("ENABLE_WARMUP", false),
 
             // Server Configuration
-            server_host: env::var("SERVER_HOST")
-                .unwrap_or_else(|_| "0.0.0.0".to_string()),
-            server_port: env::var("SERVER_PORT")
-                .unwrap_or_else(|_| "8080".to_string())
-                .parse()
-                .expect("SERVER_PORT must be a valid port number"),
+            server_host: vars.var("SERVER_HOST")
+                .unwrap_or_else(|| "0.0.0.0".to_string()),
+            server_port: vars.parse("SERVER_PORT", 8080, "a valid port number"),
+            admin_api_key: vars.var("ADMIN_API_KEY")
+                .filter(|key| !key.trim().is_empty()),
+            tenant_api_keys: vars
+                .read("TENANT_API_KEYS", "comma-separated tenant:key pairs", |list| {
+                    TenantApiKeys::parse(list).ok()
+                })
+                .unwrap_or_default(),
+            tenant_ids: vars.var("TENANT_IDS")
+                .map(|list| {
+                    list.split(',')
+                        .map(|t| t.trim().to_string())
+                        .filter(|t| !t.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            chat_api_keys: vars
+                .read(
+                    "CHAT_API_KEYS",
+                    "comma-separated keys or sha256:<hex digest> entries",
+                    |list| ChatApiKeys::parse(list).ok(),
+                )
+                .unwrap_or_default(),
+            sse_keep_alive_seconds: vars.number("SSE_KEEP_ALIVE_SECONDS", 15),
+            ws_idle_timeout_seconds: vars.number("WS_IDLE_TIMEOUT_SECONDS", 300),
+            ws_max_messages_per_connection: vars.number("WS_MAX_MESSAGES_PER_CONNECTION", 100),
+            shutdown_grace_seconds: vars.number("SHUTDOWN_GRACE_SECONDS", 30),
+            max_chat_body_bytes: vars.number("MAX_CHAT_BODY_BYTES", 65536),
+            compression_min_bytes: vars.number("COMPRESSION_MIN_BYTES", 1024),
+            request_timeout_seconds: vars.number("REQUEST_TIMEOUT_SECONDS", 60),
+            static_dir: vars.var("STATIC_DIR").filter(|dir| !dir.trim().is_empty()),
+            suggestions_enabled: vars.flag("SUGGESTIONS_ENABLED", false),
+            enable_api_docs: vars.flag("ENABLE_API_DOCS", false),
+            maintenance_mode: vars.flag("MAINTENANCE_MODE", false),
+            maintenance_message: vars.var("MAINTENANCE_MESSAGE")
+                .filter(|message| !message.trim().is_empty())
+                .unwrap_or_else(|| DEFAULT_MAINTENANCE_MESSAGE.to_string()),
+            tls_cert_path: vars.var("TLS_CERT_PATH").filter(|path| !path.trim().is_empty()),
+            tls_key_path: vars.var("TLS_KEY_PATH").filter(|path| !path.trim().is_empty()),
+            redirect_http_port: vars
+                .read("REDIRECT_HTTP_PORT", "a valid port number", |port| port.parse().ok()),
+            readiness_embedding_interval_seconds: vars
+                .number("READINESS_EMBEDDING_INTERVAL_SECONDS", 30),
+            metrics_latency_buckets: vars
+                .read("METRICS_LATENCY_BUCKETS", "comma-separated numbers", |list| {
+                    list.split(',')
+                        .map(str::trim)
+                        .filter(|bound| !bound.is_empty())
+                        .map(|bound| bound.parse().ok())
+                        .collect()
+                })
+                .unwrap_or_else(|| DEFAULT_LATENCY_BUCKETS.to_vec()),
 
             // Vector Database Configuration
-            qdrant_path: env::var("QDRANT_PATH")
-                .unwrap_or_else(|_| "./qdrant_storage".to_string()),
+            vector_store_path: vars
+                .var(vars.renamed("VECTOR_STORE_PATH", "QDRANT_PATH"))
+                .unwrap_or_else(|| "./qdrant_storage".to_string()),
+            vector_store_collection: vars
+                .var(vars.renamed("VECTOR_STORE_COLLECTION", "QDRANT_COLLECTION"))
+                .unwrap_or_else(|| DEFAULT_COLLECTION.to_string()),
+            vector_distance: vars.parse(
+                "VECTOR_DISTANCE",
+                Distance::Cosine,
+                "one of cosine, dot, euclid",
+            ),
+            vector_store_init_attempts: vars.number("VECTOR_STORE_INIT_ATTEMPTS", 5),
+            vector_store_init_backoff_ms: vars.number("VECTOR_STORE_INIT_BACKOFF_MS", 500),
+            allow_dimension_mismatch: vars.flag("ALLOW_DIMENSION_MISMATCH", false),
+            snapshot_dir: vars.var("SNAPSHOT_DIR")
+                .unwrap_or_else(|| "./snapshots".to_string()),
+            snapshot_interval_hours: vars.number("SNAPSHOT_INTERVAL_HOURS", 0),
+            snapshot_keep: vars.number("SNAPSHOT_KEEP", 7),
 
             // Rate Limiting Configuration
-            max_requests_per_minute: env::var("MAX_REQUESTS_PER_MINUTE")
-                .unwrap_or_else(|_| "20".to_string())
-                .parse()
-                .expect("MAX_REQUESTS_PER_MINUTE must be a number"),
-            max_requests_per_hour: env::var("MAX_REQUESTS_PER_HOUR")
-                .unwrap_or_else(|_| "100".to_string())
-                .parse()
-                .expect("MAX_REQUESTS_PER_HOUR must be a number"),
+            blocklist_path: vars.var("BLOCKLIST_PATH"),
+            max_requests_per_minute: vars.number("MAX_REQUESTS_PER_MINUTE", 20),
+            max_requests_per_hour: vars.number("MAX_REQUESTS_PER_HOUR", 100),
+            max_uploads_per_minute: vars.number("MAX_UPLOADS_PER_MINUTE", 2),
+            max_uploads_per_hour: vars.number("MAX_UPLOADS_PER_HOUR", 10),
+            max_feedback_per_minute: vars.number("MAX_FEEDBACK_PER_MINUTE", 10),
+            max_feedback_per_hour: vars.number("MAX_FEEDBACK_PER_HOUR", 60),
+            admin_max_failures_per_minute: vars.number("ADMIN_MAX_FAILURES_PER_MINUTE", 5),
+            admin_max_failures_per_hour: vars.number("ADMIN_MAX_FAILURES_PER_HOUR", 20),
+            repeat_query_limit: vars.number("REPEAT_QUERY_LIMIT", 0),
+            repeat_query_window_seconds: vars.number("REPEAT_QUERY_WINDOW_SECONDS", 60),
+            min_query_words: vars.number("MIN_QUERY_WORDS", 0),
+            min_query_chars: vars.number("MIN_QUERY_CHARS", 0),
+            max_special_char_ratio: vars
+                .number("MAX_SPECIAL_CHAR_RATIO", DEFAULT_MAX_SPECIAL_CHAR_RATIO),
+
+            // Session Configuration
+            session_store: vars.parse(
+                "SESSION_STORE",
+                SessionBackend::Memory,
+                "memory or sqlite",
+            ),
+            session_db_path: vars.var("SESSION_DB_PATH")
+                .unwrap_or_else(|| "./sessions.db".to_string()),
+            session_max_messages: vars.number("SESSION_MAX_MESSAGES", 20),
+            feedback_db_path: vars.var("FEEDBACK_DB_PATH")
+                .unwrap_or_else(|| "./feedback.db".to_string()),
+            usage_db_path: vars.var("USAGE_DB_PATH")
+                .unwrap_or_else(|| "./usage.db".to_string()),
+            usage_ip_hash_secret: vars
+                .var("USAGE_IP_HASH_SECRET")
+                .filter(|secret| !secret.trim().is_empty()),
+            usage_retention_days: vars.number("USAGE_RETENTION_DAYS", 90),
+            usage_input_price_per_million: vars.number("USAGE_INPUT_PRICE_PER_MILLION", 0.15),
+            usage_output_price_per_million: vars.number("USAGE_OUTPUT_PRICE_PER_MILLION", 0.60),
+            session_ttl_seconds: vars.number("SESSION_TTL_SECONDS", 3600),
+            session_token_budget: vars.number("SESSION_TOKEN_BUDGET", 0),
+            session_token_refill_per_hour: vars.number("SESSION_TOKEN_REFILL_PER_HOUR", 0),
+            enable_history_summary: vars.flag("ENABLE_HISTORY_SUMMARY", false),
+            history_summary_threshold: vars.number("HISTORY_SUMMARY_THRESHOLD", 12),
+            history_summary_keep_recent: vars.number("HISTORY_SUMMARY_KEEP_RECENT", 4),
 
             // Circuit Breaker Configuration
-            circuit_breaker_threshold: env::var("CIRCUIT_BREAKER_THRESHOLD")
-                .unwrap_or_else(|_| "5".to_string())
-                .parse()
-                .expect("CIRCUIT_BREAKER_THRESHOLD must be a number"),
-            circuit_breaker_timeout_seconds: env::var("CIRCUIT_BREAKER_TIMEOUT_SECONDS")
-                .unwrap_or_else(|_| "60".to_string())
-                .parse()
-                .expect("CIRCUIT_BREAKER_TIMEOUT_SECONDS must be a number"),
+            circuit_breaker_threshold: vars.number("CIRCUIT_BREAKER_THRESHOLD", 5),
+            circuit_breaker_timeout_seconds: vars.number("CIRCUIT_BREAKER_TIMEOUT_SECONDS", 60),
+            alert_webhook_url: vars.var("ALERT_WEBHOOK_URL")
+                .map(|url| url.trim().to_string())
+                .filter(|url| !url.is_empty()),
 
             // PDF Processing Configuration
-            max_pdf_size_mb: env::var("MAX_PDF_SIZE_MB")
-                .unwrap_or_else(|_| "50".to_string())
-                .parse()
-                .expect("MAX_PDF_SIZE_MB must be a number"),
-            chunk_size_tokens: env::var("CHUNK_SIZE_TOKENS")
-                .unwrap_or_else(|_| "512".to_string())
-                .parse()
-                .expect("CHUNK_SIZE_TOKENS must be a number"),
-            chunk_overlap_tokens: env::var("CHUNK_OVERLAP_TOKENS")
-                .unwrap_or_else(|_| "50".to_string())
-                .parse()
-                .expect("CHUNK_OVERLAP_TOKENS must be a number"),
-        })
+            max_pdf_size_mb: vars.number("MAX_PDF_SIZE_MB", 50),
+            max_upload_volumes: vars.number("MAX_UPLOAD_VOLUMES", 10),
+            max_upload_total_mb: vars.number("MAX_UPLOAD_TOTAL_MB", 200),
+            chunk_size_tokens: vars.number("CHUNK_SIZE_TOKENS", 512),
+            chunk_overlap_tokens: vars.number("CHUNK_OVERLAP_TOKENS", 50),
+            chunk_merge_below_tokens: vars
+                .number(vars.renamed("CHUNK_MERGE_BELOW_TOKENS", "MIN_CHUNK_TOKENS"), 0),
+            chunk_id_mode: vars.parse(
+                "CHUNK_ID_MODE",
+                ChunkIdMode::Random,
+                "random or deterministic",
+            ),
+            model_detection_min_confidence: vars.number("MODEL_DETECTION_MIN_CONFIDENCE", 0.6),
+            strip_boilerplate: vars.flag("STRIP_BOILERPLATE", true),
+            boilerplate_page_ratio: vars
+                .number("BOILERPLATE_PAGE_RATIO", DEFAULT_BOILERPLATE_PAGE_RATIO),
+            image_only_page_ratio: vars
+                .number("IMAGE_ONLY_PAGE_RATIO", DEFAULT_IMAGE_ONLY_PAGE_RATIO),
+            chunk_min_tokens: vars.number("CHUNK_MIN_TOKENS", DEFAULT_CHUNK_MIN_TOKENS),
+            chunk_min_alpha_ratio: vars
+                .number("CHUNK_MIN_ALPHA_RATIO", DEFAULT_CHUNK_MIN_ALPHA_RATIO),
+            chunk_drop_toc_and_captions: vars.flag("CHUNK_DROP_TOC_AND_CAPTIONS", true),
+            url_ingest_allowed_hosts: vars.var("URL_INGEST_ALLOWED_HOSTS")
+                .map(|list| {
+                    list.split(',')
+                        .map(|h| h.trim().to_lowercase())
+                        .filter(|h| !h.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+
+            // Retrieval Configuration
+            retrieval_top_k: vars.number("RETRIEVAL_TOP_K", 5),
+            min_confidence: vars.number("MIN_CONFIDENCE", 0.3),
+            low_confidence_threshold: vars.number("LOW_CONFIDENCE_THRESHOLD", 0.5),
+            source_excerpts_enabled: vars.flag("SOURCE_EXCERPTS_ENABLED", true),
+            source_excerpt_chars: vars.number("SOURCE_EXCERPT_CHARS", 300),
+            default_bike_model: vars.var("DEFAULT_BIKE_MODEL")
+                .filter(|model| !model.trim().is_empty()),
+            known_bike_models: vars.var("KNOWN_BIKE_MODELS")
+                .map(|list| {
+                    list.split(',')
+                        .map(|m| m.trim().to_string())
+                        .filter(|m| !m.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            synonyms_path: vars.var("SYNONYMS_PATH"),
+        };
+        vars.finish(config)
+    }
+
+    /// Whether a chat may filter on `model`: a built-in model, one listed in
+    /// `KNOWN_BIKE_MODELS` or the default model, in any spelling
+    pub fn is_known_bike_model(&self, model: &str) -> bool {
+        KNOWN_MODELS
+            .iter()
+            .map(|(name, _, _)| *name)
+            .chain(self.known_bike_models.iter().map(String::as_str))
+            .chain(self.default_bike_model.as_deref())
+            .any(|known| same_model(known, model))
+    }
+
+    /// Tenant a request acts for: its API key's tenant (`None` for a key
+    /// that is not configured); without a key, the tenant named in
+    /// `X-Tenant-Id` when it is listed in `TENANT_IDS`, else the default namespace
+    pub fn tenant_for(&self, api_key: Option<&str>, tenant_id: Option<&str>) -> Option<String> {
+        match api_key.map(str::trim) {
+            Some(key) => self.tenant_api_keys.tenant_for(key).map(str::to_string),
+            None => match tenant_id.map(str::trim) {
+                Some(tenant) if self.tenant_ids.iter().any(|id| id == tenant) => {
+                    Some(tenant.to_string())
+                }
+                Some(tenant) => {
+                    log::debug!("Tenant '{}' is not in TENANT_IDS, using default namespace", tenant);
+                    Some(DEFAULT_TENANT.to_string())
+                }
+                None => Some(DEFAULT_TENANT.to_string()),
+            },
+        }
+    }
+
+    /// Resolve a tenant id given by name (the `ingest --tenant` option).
+    /// Tenants neither with an API key nor in `TENANT_IDS`, and a missing name,
+    /// fall back to the default namespace.
+    pub fn resolve_tenant(&self, name: Option<&str>) -> String {
+        match name.map(str::trim) {
+            Some(tenant)
+                if self.tenant_api_keys.has_tenant(tenant)
+                    || self.tenant_ids.iter().any(|id| id == tenant) =>
+            {
+                tenant.to_string()
+            }
+            Some(tenant) => {
+                log::debug!("Unknown tenant '{}', using default namespace", tenant);
+                DEFAULT_TENANT.to_string()
+            }
+            None => DEFAULT_TENANT.to_string(),
+        }
+    }
+
+    /// Variables set in the env file at `path`, for `from_env_with`; empty
+    /// when there is no such file
+    pub fn read_env_file(
+        path: impl AsRef<Path>,
+    ) -> Result<HashMap<String, String>, dotenvy::Error> {
+        match dotenvy::from_path_iter(path.as_ref()) {
+            Ok(vars) => vars.collect(),
+            Err(e) if e.not_found() => Ok(HashMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Name the service reports in `/api/health`: the assistant and shop names
+    /// when configured
+    pub fn service_name(&self) -> String {
+        match (&self.assistant_name, &self.shop_name) {
+            (Some(name), Some(shop)) => format!("{} ({})", name, shop),
+            (Some(name), None) => name.clone(),
+            (None, Some(shop)) => format!("Bike Repair ChatBot ({})", shop),
+            (None, None) => "Bike Repair ChatBot".to_string(),
+        }
+    }
+
+    /// Settings that cannot change without a restart whose value in `new`
+    /// differs from this configuration's
+    pub fn changed_restart_settings(&self, new: &Config) -> Vec<&'static str> {
+        let settings = |config: &Config| {
+            [
+                ("SERVER_HOST", config.server_host.clone()),
+                ("SERVER_PORT", config.server_port.to_string()),
+                ("VECTOR_STORE_PATH", config.vector_store_path.clone()),
+                ("VECTOR_STORE_COLLECTION", config.vector_store_collection.clone()),
+                ("VECTOR_DISTANCE", config.vector_distance.as_str().to_string()),
+                ("OPENAI_API_KEY", config.openai_api_key.clone()),
+                ("OPENAI_ORG_ID", config.openai_org_id.clone().unwrap_or_default()),
+                ("OPENAI_PROJECT_ID", config.openai_project_id.clone().unwrap_or_default()),
+                ("OPENAI_CHAT_MODEL", config.openai_chat_model.clone()),
+                ("OPENAI_EMBEDDING_MODEL", config.openai_embedding_model.clone()),
+            ]
+        };
+
+        settings(self)
+            .into_iter()
+            .zip(settings(new))
+            .filter(|((_, current), (_, new))| current != new)
+            .map(|((key, _), _)| key)
+            .collect()
     }
 
-    /// Validate that all required configuration is present
-    pub fn validate(&self) -> Result<()> {
-        if self.openai_api_key.is_empty() || self.openai_api_key == "sk-your-api-key-here" {
-            anyhow::bail!("OPENAI_API_KEY must be set to a valid API key");
+    /// Validate the configuration's values and how they fit together,
+    /// reporting every problem at once
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if self.openai_api_key.is_empty() {
+            problems.push(ConfigProblem::unset("OPENAI_API_KEY", "your OpenAI API key"));
+        } else if self.openai_api_key == "sk-your-api-key-here" {
+            problems.push(ConfigProblem::new(
+                "OPENAI_API_KEY",
+                &self.openai_api_key,
+                "your OpenAI API key rather than the example value",
+            ));
         }
 
         if self.server_port == 0 {
-            anyhow::bail!("SERVER_PORT must be a valid port number");
+            problems.push(ConfigProblem::new("SERVER_PORT", 0, "a valid port number"));
+        }
+
+        let ranges = [
+            ("OPENAI_TEMPERATURE", self.openai_temperature, TEMPERATURE_RANGE),
+            ("OPENAI_TOP_P", self.openai_top_p, TOP_P_RANGE),
+            ("BOILERPLATE_PAGE_RATIO", self.boilerplate_page_ratio, 0.0..=1.0),
+            ("CHUNK_MIN_ALPHA_RATIO", self.chunk_min_alpha_ratio, 0.0..=1.0),
+            ("MAX_SPECIAL_CHAR_RATIO", self.max_special_char_ratio, 0.0..=1.0),
+            ("LOW_CONFIDENCE_THRESHOLD", self.low_confidence_threshold, 0.0..=1.0),
+            ("MIN_CONFIDENCE", self.min_confidence, 0.0..=1.0),
+        ];
+        for (key, value, range) in ranges {
+            if !range.contains(&value) {
+                let expected = format!("between {} and {}", range.start(), range.end());
+                problems.push(ConfigProblem::new(key, value, expected));
+            }
+        }
+
+        if !(self.image_only_page_ratio > 0.0 && self.image_only_page_ratio <= 1.0) {
+            problems.push(ConfigProblem::new(
+                "IMAGE_ONLY_PAGE_RATIO",
+                self.image_only_page_ratio,
+                "above 0 and at most 1",
+            ));
+        }
+
+        if self.chunk_overlap_tokens >= self.chunk_size_tokens {
+            problems.push(ConfigProblem::new(
+                "CHUNK_OVERLAP_TOKENS",
+                self.chunk_overlap_tokens,
+                format!("below CHUNK_SIZE_TOKENS ({})", self.chunk_size_tokens),
+            ));
+        }
+
+        for (minute_key, per_minute, hour_key, per_hour) in [
+            (
+                "MAX_REQUESTS_PER_MINUTE",
+                self.max_requests_per_minute,
+                "MAX_REQUESTS_PER_HOUR",
+                self.max_requests_per_hour,
+            ),
+            (
+                "MAX_UPLOADS_PER_MINUTE",
+                self.max_uploads_per_minute,
+                "MAX_UPLOADS_PER_HOUR",
+                self.max_uploads_per_hour,
+            ),
+            (
+                "MAX_FEEDBACK_PER_MINUTE",
+                self.max_feedback_per_minute,
+                "MAX_FEEDBACK_PER_HOUR",
+                self.max_feedback_per_hour,
+            ),
+            (
+                "ADMIN_MAX_FAILURES_PER_MINUTE",
+                self.admin_max_failures_per_minute,
+                "ADMIN_MAX_FAILURES_PER_HOUR",
+                self.admin_max_failures_per_hour,
+            ),
+        ] {
+            if per_minute > per_hour {
+                let expected = format!("at most {} ({})", hour_key, per_hour);
+                problems.push(ConfigProblem::new(minute_key, per_minute, expected));
+            }
+        }
+
+        if self.max_upload_total_mb < self.max_pdf_size_mb {
+            problems.push(ConfigProblem::new(
+                "MAX_UPLOAD_TOTAL_MB",
+                self.max_upload_total_mb,
+                format!("at least MAX_PDF_SIZE_MB ({})", self.max_pdf_size_mb),
+            ));
+        }
+
+        if self.source_excerpts_enabled && self.source_excerpt_chars == 0 {
+            problems.push(ConfigProblem::new(
+                "SOURCE_EXCERPT_CHARS",
+                0,
+                "at least 1 while excerpts are enabled",
+            ));
+        }
+
+        for (key, value) in [
+            ("EMBEDDING_WORKERS", self.embedding_workers),
+            ("EMBEDDING_QUEUE_DEPTH", self.embedding_queue_depth),
+            ("EMBEDDING_BATCH_SIZE", self.embedding_batch_size),
+            ("VECTOR_STORE_INIT_ATTEMPTS", self.vector_store_init_attempts as usize),
+            ("ADMIN_MAX_FAILURES_PER_MINUTE", self.admin_max_failures_per_minute as usize),
+            ("ADMIN_MAX_FAILURES_PER_HOUR", self.admin_max_failures_per_hour as usize),
+            ("MAX_CHAT_BODY_BYTES", self.max_chat_body_bytes as usize),
+            ("REQUEST_TIMEOUT_SECONDS", self.request_timeout_seconds as usize),
+            ("SSE_KEEP_ALIVE_SECONDS", self.sse_keep_alive_seconds as usize),
+            ("WS_IDLE_TIMEOUT_SECONDS", self.ws_idle_timeout_seconds as usize),
+            ("WS_MAX_MESSAGES_PER_CONNECTION", self.ws_max_messages_per_connection),
+            ("SESSION_TTL_SECONDS", self.session_ttl_seconds as usize),
+            ("MAX_UPLOAD_VOLUMES", self.max_upload_volumes),
+            ("RETRIEVAL_TOP_K", self.retrieval_top_k),
+        ] {
+            if value == 0 {
+                problems.push(ConfigProblem::new(key, 0, "at least 1"));
+            }
+        }
+
+        for (key, price) in [
+            ("USAGE_INPUT_PRICE_PER_MILLION", self.usage_input_price_per_million),
+            ("USAGE_OUTPUT_PRICE_PER_MILLION", self.usage_output_price_per_million),
+        ] {
+            if price.is_nan() || price < 0.0 {
+                problems.push(ConfigProblem::new(key, price, "a price of 0 or more"));
+            }
+        }
+
+        let persona = [("ASSISTANT_NAME", &self.assistant_name), ("SHOP_NAME", &self.shop_name)];
+        for (key, name) in persona {
+            if let Some(name) = name {
+                let too_long = name.chars().count() > MAX_PERSONA_NAME_CHARS;
+                if too_long || name.contains(char::is_control) {
+                    let expected = format!(
+                        "a single line of at most {} characters",
+                        MAX_PERSONA_NAME_CHARS
+                    );
+                    problems.push(ConfigProblem::new(key, name, expected));
+                }
+            }
+        }
+
+        let buckets = &self.metrics_latency_buckets;
+        let increasing = buckets.windows(2).all(|pair| pair[0] < pair[1]);
+        if buckets.is_empty() || !increasing || !buckets.iter().all(|b| b.is_finite() && *b > 0.0) {
+            let value = buckets.iter().map(f64::to_string).collect::<Vec<_>>().join(",");
+            problems.push(ConfigProblem::new(
+                "METRICS_LATENCY_BUCKETS",
+                value,
+                "increasing positive numbers of seconds",
+            ));
+        }
+
+        if let Some(dir) = &self.static_dir {
+            if !std::path::Path::new(dir).join("index.html").is_file() {
+                problems.push(ConfigProblem::new(
+                    "STATIC_DIR",
+                    dir,
+                    "a directory containing index.html",
+                ));
+            }
+        }
+
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(_), None) => problems.push(ConfigProblem::unset(
+                "TLS_KEY_PATH",
+                "a key file, as TLS_CERT_PATH is set",
+            )),
+            (None, Some(_)) => problems.push(ConfigProblem::unset(
+                "TLS_CERT_PATH",
+                "a certificate file, as TLS_KEY_PATH is set",
+            )),
+            _ => {}
+        }
+
+        if let Some(port) = self.redirect_http_port {
+            if self.tls_cert_path.is_none() {
+                problems.push(ConfigProblem::unset(
+                    "TLS_CERT_PATH",
+                    "a certificate file, as REDIRECT_HTTP_PORT is set",
+                ));
+            }
+            if port == 0 || port == self.server_port {
+                problems.push(ConfigProblem::new(
+                    "REDIRECT_HTTP_PORT",
+                    port,
+                    "a valid port other than SERVER_PORT",
+                ));
+            }
+        }
+
+        if self.enable_history_summary
+            && self.history_summary_keep_recent >= self.history_summary_threshold
+        {
+            problems.push(ConfigProblem::new(
+                "HISTORY_SUMMARY_KEEP_RECENT",
+                self.history_summary_keep_recent,
+                format!("below HISTORY_SUMMARY_THRESHOLD ({})", self.history_summary_threshold),
+            ));
+        }
+
+        if let Some(url) = &self.alert_webhook_url {
+            let web = reqwest::Url::parse(url)
+                .is_ok_and(|url| url.scheme() == "http" || url.scheme() == "https");
+            if !web {
+                problems.push(ConfigProblem::new("ALERT_WEBHOOK_URL", url, "an http or https URL"));
+            }
+        }
+
+        // The name becomes part of file names under VECTOR_STORE_PATH
+        let collection_ok = !self.vector_store_collection.is_empty()
+            && self
+                .vector_store_collection
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !collection_ok {
+            problems.push(ConfigProblem::new(
+                "VECTOR_STORE_COLLECTION",
+                &self.vector_store_collection,
+                "only letters, digits, '_' and '-'",
+            ));
+        }
+
+        for (key, id) in [
+            ("OPENAI_ORG_ID", &self.openai_org_id),
+            ("OPENAI_PROJECT_ID", &self.openai_project_id),
+        ] {
+            if let Some(id) = id.as_deref().filter(|id| http::HeaderValue::from_str(id).is_err()) {
+                problems.push(ConfigProblem::new(key, id, "characters allowed in an HTTP header"));
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(ConfigError { problems });
         }
 
         log::info!("Configuration loaded successfully");
@@ -112,7 +779,440 @@ impl Config {
             "  Rate Limits: {}/min, {}/hour",
             self.max_requests_per_minute, self.max_requests_per_hour
         );
+        if let Some(model) = &self.default_bike_model {
+            log::info!("  Default Bike Model: {}", model);
+        }
+
+        Ok(())
+    }
+}
+
+/// Subset of configuration that can be reloaded at runtime
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReloadableSettings {
+    pub max_requests_per_minute: u32,
+    pub max_requests_per_hour: u32,
+    pub circuit_breaker_threshold: u32,
+    pub circuit_breaker_timeout_seconds: u64,
+    pub retrieval_top_k: usize,
+    pub min_confidence: f32,
+    pub system_prompt_path: Option<String>,
+}
+
+impl ReloadableSettings {
+    /// The reloadable settings of a configuration; reloads take them from a
+    /// freshly loaded and validated `Config`, so they accept what startup does
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_requests_per_minute: config.max_requests_per_minute,
+            max_requests_per_hour: config.max_requests_per_hour,
+            circuit_breaker_threshold: config.circuit_breaker_threshold,
+            circuit_breaker_timeout_seconds: config.circuit_breaker_timeout_seconds,
+            retrieval_top_k: config.retrieval_top_k,
+            min_confidence: config.min_confidence,
+            system_prompt_path: config.system_prompt_path.clone(),
+        }
+    }
+}
 
+/// An environment variable `Config::from_env` could not use, or a value
+/// `Config::validate` refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    pub key: String,
+    /// The value found; `None` when the variable is required but unset
+    pub value: Option<String>,
+    /// What the variable should hold, e.g. "a number"
+    pub expected: String,
+}
+
+impl ConfigProblem {
+    /// `key` holds `value`, which is not what it should be
+    fn new(key: &str, value: impl fmt::Display, expected: impl Into<String>) -> Self {
+        Self {
+            key: key.to_string(),
+            value: Some(value.to_string()),
+            expected: expected.into(),
+        }
+    }
+
+    /// `key` is missing
+    fn unset(key: &str, expected: impl Into<String>) -> Self {
+        Self {
+            key: key.to_string(),
+            value: None,
+            expected: expected.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{} is '{}', expected {}", self.key, value, self.expected),
+            None => write!(f, "{} is not set, expected {}", self.key, self.expected),
+        }
+    }
+}
+
+/// Every missing, malformed or out-of-range variable found while loading or
+/// validating the configuration
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub struct ConfigError {
+    pub problems: Vec<ConfigProblem>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration ({} problems):", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
         Ok(())
     }
 }
+
+/// Reads environment variables for `Config::from_env`, noting each one that is
+/// missing or does not parse and carrying on with its default, so a single run
+/// reports all of them
+struct EnvReader {
+    overrides: HashMap<String, String>,
+    problems: Vec<ConfigProblem>,
+}
+
+impl EnvReader {
+    fn new(overrides: HashMap<String, String>) -> Self {
+        Self {
+            overrides,
+            problems: Vec::new(),
+        }
+    }
+
+    /// The variable's value, from the overrides or else the environment
+    fn var(&self, key: &str) -> Option<String> {
+        self.overrides.get(key).cloned().or_else(|| env::var(key).ok())
+    }
+
+    /// A variable that must be set (to something other than blanks)
+    fn required(&mut self, key: &str, expected: &str) -> String {
+        match self.var(key) {
+            Some(value) if !value.trim().is_empty() => value,
+            _ => {
+                self.problems.push(ConfigProblem::unset(key, expected));
+                String::new()
+            }
+        }
+    }
+
+    /// `parse` applied to the trimmed variable; `None` when it is unset or
+    /// blank, or when `parse` rejects it (which is recorded)
+    fn read<T>(
+        &mut self,
+        key: &str,
+        expected: &str,
+        parse: impl FnOnce(&str) -> Option<T>,
+    ) -> Option<T> {
+        let value = self.var(key).filter(|value| !value.trim().is_empty())?;
+        let parsed = parse(value.trim());
+        if parsed.is_none() {
+            self.problems.push(ConfigProblem::new(key, value, expected));
+        }
+        parsed
+    }
+
+    /// The name to read a renamed variable under: its old name, with a
+    /// deprecation warning, when only that one is set
+    fn renamed<'a>(&self, key: &'a str, deprecated: &'a str) -> &'a str {
+        if self.var(key).is_none() && self.var(deprecated).is_some() {
+            log::warn!("{} is deprecated, set {} instead", deprecated, key);
+            return deprecated;
+        }
+        key
+    }
+
+    fn parse<T: FromStr>(&mut self, key: &str, default: T, expected: &str) -> T {
+        self.read(key, expected, |value| value.parse().ok()).unwrap_or(default)
+    }
+
+    fn number<T: FromStr>(&mut self, key: &str, default: T) -> T {
+        self.parse(key, default, "a number")
+    }
+
+    fn flag(&mut self, key: &str, default: bool) -> bool {
+        self.parse(key, default, "true or false")
+    }
+
+    fn finish<T>(self, value: T) -> Result<T, ConfigError> {
+        if self.problems.is_empty() {
+            Ok(value)
+        } else {
+            Err(ConfigError { problems: self.problems })
+        }
+    }
+}
+
+/// Serializes tests that modify process environment variables
+#[cfg(test)]
+pub(crate) static ENV_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+impl Default for Config {
+    /// Defaults mirror the fallbacks used by `from_env` (without an API key)
+    fn default() -> Self {
+        Self {
+            openai_api_key: String::new(),
+            openai_org_id: None,
+            openai_project_id: None,
+            openai_chat_model: "gpt-4o-mini".to_string(),
+            openai_embedding_model: "text-embedding-3-small".to_string(),
+            embedding_max_input_tokens: DEFAULT_EMBEDDING_MAX_INPUT_TOKENS,
+            embedding_workers: 4,
+            embedding_queue_depth: 8,
+            embedding_batch_size: EMBEDDING_BATCH_SIZE,
+            system_prompt_path: None,
+            max_response_tokens: 1000,
+            openai_temperature: 1.0,
+            openai_top_p: 1.0,
+            response_footer: None,
+            assistant_name: None,
+            shop_name: None,
+            suggestions_enabled: false,
+            enable_warmup: false,
+            server_host: "0.0.0.0".to_string(),
+            server_port: 8080,
+            admin_api_key: None,
+            tenant_api_keys: TenantApiKeys::default(),
+            tenant_ids: Vec::new(),
+            chat_api_keys: ChatApiKeys::default(),
+            sse_keep_alive_seconds: 15,
+            ws_idle_timeout_seconds: 300,
+            ws_max_messages_per_connection: 100,
+            shutdown_grace_seconds: 30,
+            max_chat_body_bytes: 64 * 1024,
+            compression_min_bytes: 1024,
+            request_timeout_seconds: 60,
+            static_dir: None,
+            enable_api_docs: false,
+            maintenance_mode: false,
+            maintenance_message: DEFAULT_MAINTENANCE_MESSAGE.to_string(),
+            tls_cert_path: None,
+            tls_key_path: None,
+            redirect_http_port: None,
+            readiness_embedding_interval_seconds: 30,
+            metrics_latency_buckets: DEFAULT_LATENCY_BUCKETS.to_vec(),
+            vector_store_path: "./qdrant_storage".to_string(),
+            vector_store_collection: DEFAULT_COLLECTION.to_string(),
+            vector_distance: Distance::Cosine,
+            vector_store_init_attempts: 5,
+            vector_store_init_backoff_ms: 500,
+            allow_dimension_mismatch: false,
+            snapshot_dir: "./snapshots".to_string(),
+            snapshot_interval_hours: 0,
+            snapshot_keep: 7,
+            blocklist_path: None,
+            max_requests_per_minute: 20,
+            max_requests_per_hour: 100,
+            max_uploads_per_minute: 2,
+            max_uploads_per_hour: 10,
+            max_feedback_per_minute: 10,
+            max_feedback_per_hour: 60,
+            admin_max_failures_per_minute: 5,
+            admin_max_failures_per_hour: 20,
+            repeat_query_limit: 0,
+            repeat_query_window_seconds: 60,
+            min_query_words: 0,
+            min_query_chars: 0,
+            max_special_char_ratio: DEFAULT_MAX_SPECIAL_CHAR_RATIO,
+            session_store: SessionBackend::Memory,
+            session_db_path: "./sessions.db".to_string(),
+            feedback_db_path: "./feedback.db".to_string(),
+            usage_db_path: "./usage.db".to_string(),
+            usage_ip_hash_secret: None,
+            usage_retention_days: 90,
+            usage_input_price_per_million: 0.15,
+            usage_output_price_per_million: 0.60,
+            session_max_messages: 20,
+            session_ttl_seconds: 3600,
+            session_token_budget: 0,
+            session_token_refill_per_hour: 0,
+            enable_history_summary: false,
+            history_summary_threshold: 12,
+            history_summary_keep_recent: 4,
+            circuit_breaker_threshold: 5,
+            circuit_breaker_timeout_seconds: 60,
+            alert_webhook_url: None,
+            max_pdf_size_mb: 50,
+            max_upload_volumes: 10,
+            max_upload_total_mb: 200,
+            chunk_size_tokens: 512,
+            chunk_overlap_tokens: 50,
+            chunk_merge_below_tokens: 0,
+            chunk_id_mode: ChunkIdMode::Random,
+            model_detection_min_confidence: 0.6,
+            strip_boilerplate: true,
+            boilerplate_page_ratio: DEFAULT_BOILERPLATE_PAGE_RATIO,
+            image_only_page_ratio: DEFAULT_IMAGE_ONLY_PAGE_RATIO,
+            chunk_min_tokens: DEFAULT_CHUNK_MIN_TOKENS,
+            chunk_min_alpha_ratio: DEFAULT_CHUNK_MIN_ALPHA_RATIO,
+            chunk_drop_toc_and_captions: true,
+            url_ingest_allowed_hosts: Vec::new(),
+            retrieval_top_k: 5,
+            min_confidence: 0.3,
+            low_confidence_threshold: 0.5,
+            source_excerpts_enabled: true,
+            source_excerpt_chars: 300,
+            default_bike_model: None,
+            known_bike_models: Vec::new(),
+            synonyms_path: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `f` with `vars` set (`None` removes one), restoring them afterwards
+    async fn with_env<T>(vars: &[(&str, Option<&str>)], f: impl FnOnce() -> T) -> T {
+        let _env = ENV_LOCK.lock().await;
+        let saved: Vec<_> = vars.iter().map(|(key, _)| (*key, env::var(key).ok())).collect();
+        let apply = |vars: &[(&str, Option<String>)]| {
+            for (key, value) in vars {
+                match value {
+                    Some(value) => env::set_var(key, value),
+                    None => env::remove_var(key),
+                }
+            }
+        };
+        let wanted: Vec<_> = vars.iter().map(|(k, v)| (*k, v.map(String::from))).collect();
+        apply(&wanted);
+        let result = f();
+        apply(&saved);
+        result
+    }
+
+    #[tokio::test]
+    async fn test_from_env_reports_every_bad_variable() {
+        let vars = [
+            ("OPENAI_API_KEY", None),
+            ("MAX_REQUESTS_PER_MINUTE", Some("lots")),
+            ("VECTOR_DISTANCE", Some("manhattan")),
+            ("TENANT_API_KEYS", Some("acme")),
+        ];
+        let error = with_env(&vars, Config::from_env).await.unwrap_err();
+
+        let keys: Vec<&str> = error.problems.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            ["OPENAI_API_KEY", "TENANT_API_KEYS", "VECTOR_DISTANCE", "MAX_REQUESTS_PER_MINUTE"]
+        );
+        let message = error.to_string();
+        assert!(message.starts_with("Invalid configuration (4 problems):"), "{}", message);
+        assert!(message.contains("\n  - OPENAI_API_KEY is not set, expected your OpenAI API key"));
+        assert!(message.contains("\n  - MAX_REQUESTS_PER_MINUTE is 'lots', expected a number"));
+    }
+
+    #[tokio::test]
+    async fn test_from_env_reads_trimmed_values() {
+        let vars = [
+            ("OPENAI_API_KEY", Some("sk-test")),
+            ("MAX_REQUESTS_PER_MINUTE", Some(" 30 ")),
+            ("REDIRECT_HTTP_PORT", Some("")),
+        ];
+        let config = with_env(&vars, Config::from_env).await.unwrap();
+
+        assert_eq!(config.max_requests_per_minute, 30);
+        assert_eq!(config.redirect_http_port, None);
+    }
+
+    #[tokio::test]
+    async fn test_vector_store_reads_deprecated_qdrant_names() {
+        let vars = [
+            ("OPENAI_API_KEY", Some("sk-test")),
+            ("VECTOR_STORE_PATH", None),
+            ("QDRANT_PATH", Some("./old_storage")),
+            ("VECTOR_STORE_COLLECTION", Some("manuals")),
+            ("QDRANT_COLLECTION", Some("ignored")),
+        ];
+        let config = with_env(&vars, Config::from_env).await.unwrap();
+
+        assert_eq!(config.vector_store_path, "./old_storage");
+        assert_eq!(config.vector_store_collection, "manuals");
+    }
+
+    #[tokio::test]
+    async fn test_chunk_merging_is_off_unless_configured() {
+        let mut vars = vec![
+            ("OPENAI_API_KEY", Some("sk-test")),
+            ("CHUNK_MERGE_BELOW_TOKENS", None),
+            ("MIN_CHUNK_TOKENS", None),
+        ];
+        let config = with_env(&vars, Config::from_env).await.unwrap();
+        assert_eq!(config.chunk_merge_below_tokens, 0);
+
+        vars[2].1 = Some("24");
+        let config = with_env(&vars, Config::from_env).await.unwrap();
+        assert_eq!(config.chunk_merge_below_tokens, 24);
+
+        vars[2].1 = Some("many");
+        let error = with_env(&vars, Config::from_env).await.unwrap_err();
+        assert_eq!(error.problems[0].key, "MIN_CHUNK_TOKENS");
+    }
+
+    #[tokio::test]
+    async fn test_env_file_overrides_environment_without_changing_it() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(".env");
+        std::fs::write(
+            &path,
+            "# comment\nexport MAX_REQUESTS_PER_MINUTE=30 # per client\n\
+             SHOP_NAME=\"Spoke & Chain\"\n",
+        )
+        .unwrap();
+        let overrides = Config::read_env_file(&path).unwrap();
+        assert!(Config::read_env_file(dir.path().join("missing")).unwrap().is_empty());
+
+        let vars = [
+            ("OPENAI_API_KEY", Some("sk-test")),
+            ("MAX_REQUESTS_PER_MINUTE", Some("10")),
+            ("SHOP_NAME", None),
+        ];
+        let (config, after) = with_env(&vars, || {
+            (Config::from_env_with(overrides), env::var("MAX_REQUESTS_PER_MINUTE"))
+        })
+        .await;
+        let config = config.unwrap();
+
+        assert_eq!(config.max_requests_per_minute, 30);
+        assert_eq!(config.shop_name.as_deref(), Some("Spoke & Chain"));
+        assert_eq!(after.as_deref(), Ok("10"));
+    }
+
+    #[tokio::test]
+    async fn test_validate_checks_limits_against_each_other() {
+        async fn validate(vars: &[(&str, Option<&str>)]) -> Result<(), ConfigError> {
+            let mut all = vec![("OPENAI_API_KEY", Some("sk-test"))];
+            all.extend_from_slice(vars);
+            with_env(&all, || Config::from_env().unwrap().validate()).await
+        }
+
+        assert!(validate(&[]).await.is_ok());
+        let vars = [
+            ("CHUNK_SIZE_TOKENS", Some("64")),
+            ("CHUNK_OVERLAP_TOKENS", Some("64")),
+            ("MAX_REQUESTS_PER_MINUTE", Some("200")),
+            ("OPENAI_TEMPERATURE", Some("3")),
+        ];
+        let error = validate(&vars).await.unwrap_err();
+
+        let problems: Vec<String> = error.problems.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            problems,
+            [
+                "OPENAI_TEMPERATURE is '3', expected between 0 and 2",
+                "CHUNK_OVERLAP_TOKENS is '64', expected below CHUNK_SIZE_TOKENS (64)",
+                "MAX_REQUESTS_PER_MINUTE is '200', expected at most MAX_REQUESTS_PER_HOUR (100)",
+            ]
+        );
+        assert!(error.to_string().starts_with("Invalid configuration (3 problems):"));
+    }
+}
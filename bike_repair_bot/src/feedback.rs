@@ -0,0 +1,120 @@
+//! Ratings of chat answers in a SQLite database, for evaluating prompt and
+//! retrieval changes
+
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use rusqlite::params;
+use std::path::Path;
+
+use crate::models::{Feedback, FeedbackFilter, DEFAULT_FEEDBACK_LIMIT};
+use crate::sqlite::Database;
+
+/// Schema of the feedback database, see [`Database::open`]
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE feedback (
+        id          INTEGER PRIMARY KEY,
+        tenant_id   TEXT NOT NULL,
+        session_id  TEXT NOT NULL,
+        response_id TEXT,
+        rating      TEXT NOT NULL,
+        comment     TEXT,
+        query       TEXT,
+        response    TEXT NOT NULL,
+        source_ids  TEXT NOT NULL,
+        created_at  INTEGER NOT NULL,
+        UNIQUE (tenant_id, response_id)
+    );
+    CREATE INDEX feedback_created_at ON feedback (created_at);
+"#];
+
+/// Feedback in a SQLite database file. A response rated again keeps only the
+/// latest rating.
+pub struct FeedbackStore {
+    db: Database,
+}
+
+impl FeedbackStore {
+    /// Open (or create) the database at `path` and bring its schema up to date
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            db: Database::open(path, "feedback", MIGRATIONS).await?,
+        })
+    }
+
+    /// Store a rating, replacing an earlier one of the same response
+    pub async fn record(&self, feedback: Feedback) -> Result<()> {
+        self.db.transaction(move |tx| {
+            tx.execute(
+                "INSERT INTO feedback (tenant_id, session_id, response_id, rating, comment,
+                                       query, response, source_ids, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                 ON CONFLICT (tenant_id, response_id) DO UPDATE SET
+                     rating = excluded.rating,
+                     comment = excluded.comment,
+                     created_at = excluded.created_at",
+                params![
+                    feedback.tenant_id,
+                    feedback.session_id,
+                    feedback.response_id,
+                    feedback.rating.as_str(),
+                    feedback.comment,
+                    feedback.query,
+                    feedback.response,
+                    serde_json::to_string(&feedback.source_ids)?,
+                    feedback.created_at.timestamp_millis(),
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Feedback matching the filter, newest first
+    pub async fn list(&self, filter: FeedbackFilter) -> Result<Vec<Feedback>> {
+        self.db.transaction(move |tx| {
+            let mut statement = tx.prepare(
+                "SELECT tenant_id, session_id, response_id, rating, comment, query, response,
+                        source_ids, created_at
+                 FROM feedback
+                 WHERE (?1 IS NULL OR rating = ?1)
+                   AND (?2 IS NULL OR created_at >= ?2)
+                   AND (?3 IS NULL OR created_at < ?3)
+                 ORDER BY created_at DESC, id DESC
+                 LIMIT ?4",
+            )?;
+            let mut rows = statement.query(params![
+                filter.rating.map(|r| r.as_str()),
+                filter.from.map(|t| t.timestamp_millis()),
+                filter.to.map(|t| t.timestamp_millis()),
+                filter.limit.unwrap_or(DEFAULT_FEEDBACK_LIMIT),
+            ])?;
+
+            let mut feedback = Vec::new();
+            while let Some(row) = rows.next()? {
+                let rating: String = row.get(3)?;
+                let source_ids: String = row.get(7)?;
+                feedback.push(Feedback {
+                    tenant_id: row.get(0)?,
+                    session_id: row.get(1)?,
+                    response_id: row.get(2)?,
+                    rating: rating.parse()?,
+                    comment: row.get(4)?,
+                    query: row.get(5)?,
+                    response: row.get(6)?,
+                    source_ids: serde_json::from_str(&source_ids)?,
+                    created_at: Utc
+                        .timestamp_millis_opt(row.get(8)?)
+                        .single()
+                        .unwrap_or_default(),
+                });
+            }
+            Ok(feedback)
+        })
+        .await
+    }
+
+    /// Fold the write-ahead log into the database file before the process exits
+    pub async fn flush(&self) -> Result<()> {
+        self.db.flush().await
+    }
+}
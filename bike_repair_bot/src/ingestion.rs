@@ -0,0 +1,1158 @@
+pub mod bulk;
+pub mod pages;
+pub mod progress;
+pub mod remote;
+
+use dashmap::DashMap;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::{AbortHandle, JoinHandle};
+
+use crate::ai::{AiProvider, BatchEmbeddings};
+use crate::config::Config;
+use crate::models::{
+    normalize_model, ChunkMetadata, Document, DocumentChunk, DocumentStatus, SourceFormat,
+};
+use crate::pdf::{
+    extract_html, extract_markdown, extract_plain_text, strip_boilerplate, ChunkQualityFilter,
+    Chunker, ExtractError, ExtractedDocument, ModelDetection, ModelDetector, PageRanges,
+    PageText, PdfExtractor, DETECTION_PAGES,
+};
+use crate::rag::{EmbeddingGenerator, VectorStore};
+use pages::{PageStore, StoredPages};
+use progress::{IngestionProgress, ProgressStore};
+
+/// Why a document could not be ingested
+#[derive(Debug, Error)]
+pub enum IngestError {
+    #[error(transparent)]
+    Extraction(#[from] ExtractError),
+    #[error("failed to index document: {0:#}")]
+    Indexing(anyhow::Error),
+    #[error("embedding failed for {failed} of {total} chunks; the rest were indexed")]
+    PartialEmbedding { failed: usize, total: usize },
+    #[error("failed to record ingestion progress: {0:#}")]
+    Progress(anyhow::Error),
+    #[error("no extracted page text is stored for this document")]
+    NoStoredPages,
+    #[error("failed to load stored page text: {0:#}")]
+    StoredPages(anyhow::Error),
+}
+
+/// A failed ingestion whose upload and progress were kept, ready to restart
+#[derive(Debug, Clone)]
+pub struct ResumableIngestion {
+    /// Document record as of the failed run
+    pub document: Document,
+    pub volumes: Vec<Vec<u8>>,
+    pub chunks_total: usize,
+    /// Chunks not (or no longer) in the vector store
+    pub chunks_remaining: usize,
+}
+
+/// In-memory record of uploaded documents and their ingestion progress
+#[derive(Default)]
+pub struct DocumentRegistry {
+    documents: DashMap<String, Document>,
+}
+
+impl DocumentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert or replace a document record
+    pub fn update(&self, document: &Document) {
+        self.documents.insert(document.id.clone(), document.clone());
+    }
+
+    pub fn get(&self, id: &str) -> Option<Document> {
+        self.documents.get(id).map(|d| d.clone())
+    }
+
+    /// A live (not failed) document of the tenant with the given file hash
+    pub fn find_by_hash(&self, tenant_id: &str, content_hash: &str) -> Option<Document> {
+        self.documents
+            .iter()
+            .find(|d| {
+                d.tenant_id == tenant_id
+                    && d.content_hash.as_deref() == Some(content_hash)
+                    && d.status != DocumentStatus::Failed
+            })
+            .map(|d| d.clone())
+    }
+
+    /// Mark a document as failed with the given reason
+    pub fn fail(&self, id: &str, error: impl Into<String>) {
+        if let Some(mut document) = self.documents.get_mut(id) {
+            document.status = DocumentStatus::Failed;
+            document.error = Some(error.into());
+        }
+    }
+}
+
+/// Runs extraction → chunking → embedding → vector upsert for uploaded manuals
+pub struct Ingestor {
+    extractor: PdfExtractor,
+    chunker: Chunker,
+    detector: ModelDetector,
+    /// Share of pages a line must repeat on to be stripped as a header/footer;
+    /// `None` when stripping is off
+    boilerplate_page_ratio: Option<f32>,
+    /// Share of textless pages from which a PDF is rejected as scanned
+    image_only_page_ratio: f32,
+    /// Drops junk chunks before they are embedded
+    quality: ChunkQualityFilter,
+    embedder: EmbeddingGenerator,
+    /// Embedding calls in flight at once during an ingestion
+    embedding_workers: usize,
+    /// Batches waiting for a worker, and embedded batches waiting to be stored
+    embedding_queue_depth: usize,
+    /// Chunks per embedding call
+    embedding_batch_size: usize,
+    vector_store: Arc<VectorStore>,
+    /// Uploads and chunk progress of documents not yet fully indexed
+    progress: ProgressStore,
+    /// Extracted page text of every ingested document, for reprocessing
+    pages: PageStore,
+}
+
+impl Ingestor {
+    pub fn new(
+        config: &Config,
+        ai_provider: Arc<dyn AiProvider>,
+        vector_store: Arc<VectorStore>,
+    ) -> Self {
+        Self {
+            extractor: PdfExtractor::new(),
+            chunker: Chunker::from_config(config),
+            detector: ModelDetector::new(config.model_detection_min_confidence),
+            boilerplate_page_ratio: config
+                .strip_boilerplate
+                .then_some(config.boilerplate_page_ratio),
+            image_only_page_ratio: config.image_only_page_ratio,
+            quality: ChunkQualityFilter::from_config(config),
+            embedder: EmbeddingGenerator::new(
+                ai_provider,
+                config.openai_embedding_model.clone(),
+                config.embedding_max_input_tokens,
+            ),
+            embedding_workers: config.embedding_workers,
+            embedding_queue_depth: config.embedding_queue_depth,
+            embedding_batch_size: config.embedding_batch_size,
+            progress: ProgressStore::new(
+                vector_store
+                    .storage_dir()
+                    .join(format!("{}.ingestion", vector_store.collection())),
+            ),
+            pages: PageStore::new(
+                vector_store
+                    .storage_dir()
+                    .join(format!("{}.pages", vector_store.collection())),
+            ),
+            vector_store,
+        }
+    }
+
+    /// Pull page text out of an upload; text formats yield a single page
+    pub fn extract(
+        &self,
+        format: SourceFormat,
+        bytes: &[u8],
+        max_pages: usize,
+    ) -> Result<ExtractedDocument, ExtractError> {
+        let text = || String::from_utf8_lossy(bytes);
+        match format {
+            SourceFormat::Pdf => self.extractor.extract_pages(bytes, max_pages),
+            SourceFormat::Text => Ok(extract_plain_text(&text())),
+            SourceFormat::Markdown => Ok(extract_markdown(&text())),
+            SourceFormat::Html => Ok(extract_html(&text())),
+        }
+    }
+
+    /// Extract only the pages in `ranges` (all when `None`), keeping their
+    /// page numbers
+    fn extract_selected(
+        &self,
+        format: SourceFormat,
+        bytes: &[u8],
+        ranges: Option<&PageRanges>,
+    ) -> Result<ExtractedDocument, ExtractError> {
+        match (format, ranges) {
+            (_, None) => self.extract(format, bytes, usize::MAX),
+            (SourceFormat::Pdf, Some(ranges)) => self.extractor.extract_ranges(bytes, ranges),
+            (_, Some(ranges)) => {
+                let mut extracted = self.extract(format, bytes, usize::MAX)?;
+                extracted.pages.retain(|p| ranges.contains(p.page_number));
+                Ok(extracted)
+            }
+        }
+    }
+
+    /// Check that an upload can be opened before queueing it and return its
+    /// page count; only PDFs can fail (encrypted, malformed, unsupported
+    /// version), text formats are a single page
+    pub fn check(&self, format: SourceFormat, bytes: &[u8]) -> Result<u32, ExtractError> {
+        match format {
+            SourceFormat::Pdf => self.extractor.check(bytes),
+            SourceFormat::Text | SourceFormat::Markdown | SourceFormat::Html => Ok(1),
+        }
+    }
+
+    /// Guess bike model and year from the document title and first pages.
+    /// Unreadable files yield an empty detection; ingestion reports the error.
+    pub fn detect(&self, format: SourceFormat, bytes: &[u8]) -> ModelDetection {
+        match self.extract(format, bytes, DETECTION_PAGES) {
+            Ok(front_matter) => self.detector.detect(&front_matter),
+            Err(e) => {
+                log::debug!("Skipping model detection: {}", e);
+                ModelDetection {
+                    needs_review: true,
+                    ..ModelDetection::default()
+                }
+            }
+        }
+    }
+
+    /// Id of a document already indexed from files with this hash, if any
+    pub async fn find_ingested(&self, tenant_id: &str, content_hash: &str) -> Option<String> {
+        self.vector_store.find_document_by_hash(tenant_id, content_hash).await
+    }
+
+    /// The kept upload and progress of a document whose ingestion stopped part way,
+    /// if any
+    pub async fn resumable(&self, document_id: &str) -> anyhow::Result<Option<ResumableIngestion>> {
+        let Some(progress) = self.progress.load(document_id).await? else {
+            return Ok(None);
+        };
+        let volumes = self.progress.load_upload(document_id).await?;
+        if volumes.is_empty() {
+            return Ok(None);
+        }
+
+        let stored_ids = self.vector_store.ids().await;
+        Ok(Some(ResumableIngestion {
+            chunks_total: progress.chunk_ids.len(),
+            chunks_remaining: progress.remaining(&stored_ids),
+            document: progress.document,
+            volumes,
+        }))
+    }
+
+    /// Record of a document whose page text is stored, as of its last ingestion
+    pub async fn stored_document(&self, document_id: &str) -> anyhow::Result<Option<Document>> {
+        Ok(self.pages.load(document_id).await?.map(|p| p.document))
+    }
+
+    /// Chunk size and overlap a reprocess with these overrides would use
+    pub fn chunk_settings(
+        &self,
+        chunk_size: Option<usize>,
+        overlap: Option<usize>,
+    ) -> (usize, usize) {
+        let chunker = self.resized_chunker(chunk_size, overlap);
+        (chunker.chunk_size(), chunker.overlap())
+    }
+
+    /// Token count of a chunk's text under the embedding model
+    pub fn chunk_tokens(&self, text: &str) -> usize {
+        self.chunker.tokens(text)
+    }
+
+    fn resized_chunker(&self, chunk_size: Option<usize>, overlap: Option<usize>) -> Chunker {
+        self.chunker.resized(
+            chunk_size.unwrap_or(self.chunker.chunk_size()),
+            overlap.unwrap_or(self.chunker.overlap()),
+        )
+    }
+
+    /// Copy a document's bike model, year and manual type onto its stored chunks
+    pub async fn update_metadata(&self, document: &Document) -> anyhow::Result<usize> {
+        self.vector_store
+            .update_metadata(&document.id, |metadata| {
+                metadata.bike_model = normalize_model(&document.bike_model);
+                metadata.year = document.year;
+                metadata.manual_type = document.manual_type.clone();
+            })
+            .await
+    }
+
+    /// Index a file in the document's `source_format` under `document`, updating
+    /// its counts, progress and status. Only pages in the document's
+    /// `page_ranges` are extracted, and its chunk size and overlap override the
+    /// configured ones.
+    /// `on_progress` is called after extraction and after each embedded batch.
+    /// Returns the number of chunks stored.
+    pub async fn ingest(
+        &self,
+        document: &mut Document,
+        bytes: &[u8],
+        on_progress: &(dyn Fn(&Document) + Send + Sync),
+    ) -> Result<usize, IngestError> {
+        self.ingest_volumes(document, &[bytes], on_progress).await
+    }
+
+    /// Index a manual set split across several files as one document; pages are
+    /// counted across all volumes and chunks record their volume.
+    /// Once chunked, the upload and the indices of stored chunks are kept on disk
+    /// until the document completes, and a rerun over the same content (see
+    /// [`Ingestor::resumable`]) skips chunks that are still in the vector store.
+    pub async fn ingest_volumes(
+        &self,
+        document: &mut Document,
+        volumes: &[&[u8]],
+        on_progress: &(dyn Fn(&Document) + Send + Sync),
+    ) -> Result<usize, IngestError> {
+        let result = self.run(document, volumes, on_progress).await;
+        self.finish(document, &result).await;
+        result
+    }
+
+    /// Chunk a document's stored page text again and replace its chunks, with the
+    /// current header/footer settings and the given chunk size and overlap (the
+    /// configured ones where `None`). Only pages in the document's
+    /// `page_ranges` are chunked. The old chunks are removed once the new
+    /// ones are stored, so the document stays searchable meanwhile.
+    pub async fn reprocess(
+        &self,
+        document: &mut Document,
+        chunk_size: Option<usize>,
+        overlap: Option<usize>,
+        on_progress: &(dyn Fn(&Document) + Send + Sync),
+    ) -> Result<usize, IngestError> {
+        let result = self.run_reprocess(document, chunk_size, overlap, on_progress).await;
+        self.finish(document, &result).await;
+        result
+    }
+
+    async fn run_reprocess(
+        &self,
+        document: &mut Document,
+        chunk_size: Option<usize>,
+        overlap: Option<usize>,
+        on_progress: &(dyn Fn(&Document) + Send + Sync),
+    ) -> Result<usize, IngestError> {
+        let stored = self
+            .pages
+            .load(&document.id)
+            .await
+            .map_err(IngestError::StoredPages)?
+            .ok_or(IngestError::NoStoredPages)?;
+        let chunker = self.resized_chunker(chunk_size, overlap);
+        let mut pages = stored.volumes;
+        if let Some(ranges) = &document.page_ranges {
+            for volume_pages in &mut pages {
+                volume_pages.retain(|p| ranges.contains(p.page_number));
+            }
+        }
+
+        // Progress of an earlier run describes other chunks; start afresh
+        self.progress
+            .remove(&document.id)
+            .await
+            .map_err(IngestError::Progress)?;
+        self.index(document, pages, &chunker, &[], on_progress).await
+    }
+
+    /// Set the document's status from the outcome of a run
+    async fn finish(&self, document: &mut Document, result: &Result<usize, IngestError>) {
+        match result {
+            Ok(_) => {
+                document.status = DocumentStatus::Completed;
+                if let Err(e) = self.progress.remove(&document.id).await {
+                    log::warn!("Failed to clear ingestion progress of {}: {:#}", document.id, e);
+                }
+            }
+            Err(e) => {
+                document.status = DocumentStatus::Failed;
+                document.error = Some(e.to_string());
+                document.error_code = match e {
+                    IngestError::Extraction(e) => Some(e.code().to_string()),
+                    _ => None,
+                };
+                self.record_failure(document).await;
+            }
+        }
+    }
+
+    /// Store the failed document with its progress, if the run got far enough to
+    /// keep any
+    async fn record_failure(&self, document: &Document) {
+        let saved = match self.progress.load(&document.id).await {
+            Ok(Some(mut progress)) => {
+                progress.document = document.clone();
+                self.progress.save(&progress).await
+            }
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = saved {
+            log::warn!("Failed to record ingestion failure of {}: {:#}", document.id, e);
+        }
+    }
+
+    async fn run(
+        &self,
+        document: &mut Document,
+        volumes: &[&[u8]],
+        on_progress: &(dyn Fn(&Document) + Send + Sync),
+    ) -> Result<usize, IngestError> {
+        let ranges = document.page_ranges.clone();
+        let extracted = volumes
+            .iter()
+            .map(|bytes| self.extract_selected(document.source_format, bytes, ranges.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let page_count: usize = extracted.iter().map(|e| e.page_count).sum();
+        document.skipped_pages = extracted.iter().flat_map(|e| e.skipped_pages.clone()).collect();
+        if !document.skipped_pages.is_empty() {
+            log::warn!(
+                "Skipped unparseable pages of document {}: {:?}",
+                document.id,
+                document.skipped_pages
+            );
+        }
+        document.page_count = page_count as u32;
+        document.pages_processed = extracted.iter().map(|e| e.pages.len()).sum::<usize>() as u32;
+
+        let stored = StoredPages {
+            document: document.clone(),
+            volumes: extracted.into_iter().map(|e| e.pages).collect(),
+        };
+        if let Err(e) = self.pages.save(&stored).await {
+            log::warn!("Failed to store page text of {}: {:#}", document.id, e);
+        }
+        let chunker =
+            self.resized_chunker(document.chunk_size_tokens, document.chunk_overlap_tokens);
+        self.index(document, stored.volumes, &chunker, volumes, on_progress).await
+    }
+
+    /// Extract and chunk files as an upload would be, without embedding or
+    /// storing anything. Returns a scratch document with the page, stripped
+    /// line and chunk counts, and the chunks.
+    pub fn preview(
+        &self,
+        format: SourceFormat,
+        volumes: &[&[u8]],
+        chunk_size: Option<usize>,
+        overlap: Option<usize>,
+    ) -> Result<(Document, Vec<DocumentChunk>), IngestError> {
+        let mut document = Document::new("preview", "Unknown");
+        document.source_format = format;
+        let pages = volumes
+            .iter()
+            .map(|bytes| self.extract(format, bytes, usize::MAX).map(|e| e.pages))
+            .collect::<Result<Vec<_>, _>>()?;
+        document.page_count = pages.iter().map(Vec::len).sum::<usize>() as u32;
+
+        let chunker = self.resized_chunker(chunk_size, overlap);
+        let chunks = self.chunk_pages(&mut document, pages, &chunker)?;
+        Ok((document, chunks))
+    }
+
+    /// Strip headers and footers from extracted pages, then chunk, embed and
+    /// store them under `document`. `volumes` are the uploaded files, kept so a
+    /// failed run can be resumed; reprocessing has none to keep.
+    async fn index(
+        &self,
+        document: &mut Document,
+        pages: Vec<Vec<PageText>>,
+        chunker: &Chunker,
+        volumes: &[&[u8]],
+        on_progress: &(dyn Fn(&Document) + Send + Sync),
+    ) -> Result<usize, IngestError> {
+        let mut chunks = self.chunk_pages(document, pages, chunker)?;
+
+        let mut progress = self.start_progress(document, volumes, &mut chunks).await?;
+        document.chunks_embedded = progress.upserted.len();
+        on_progress(document);
+
+        let pending: Vec<DocumentChunk> = chunks
+            .iter()
+            .filter(|c| !progress.upserted.contains(&c.metadata.chunk_index))
+            .cloned()
+            .collect();
+        let mut failed =
+            self.embed_and_store(pending, document, &mut progress, on_progress).await?;
+
+        // Give chunks from failed batches one more pass before giving up on them
+        if !failed.is_empty() {
+            log::warn!(
+                "Retrying {} chunks of document {} whose embedding failed",
+                failed.len(),
+                document.id
+            );
+            let retry = std::mem::take(&mut failed);
+            failed = self.embed_and_store(retry, document, &mut progress, on_progress).await?;
+        }
+        if !failed.is_empty() {
+            return Err(IngestError::PartialEmbedding {
+                failed: failed.len(),
+                total: document.chunk_count,
+            });
+        }
+
+        // A replaced document keeps its id; drop chunks left from the old version
+        let current = chunks.iter().map(|c| c.id.clone()).collect();
+        let removed = self
+            .vector_store
+            .delete_document_chunks(&document.id, &current)
+            .await
+            .map_err(IngestError::Indexing)?;
+        if removed > 0 {
+            log::info!("Removed {} stale chunks of document {}", removed, document.id);
+        }
+
+        log::info!(
+            "Ingested document {} ({}): {} pages, {} chunks ({} dropped)",
+            document.id,
+            document.filename,
+            document.page_count,
+            document.chunk_count,
+            document.dropped_chunks
+        );
+        Ok(document.chunk_count)
+    }
+
+    /// Strip headers and footers from the pages, chunk them and drop junk
+    /// chunks, recording what was stripped and the chunk counts on `document`
+    fn chunk_pages(
+        &self,
+        document: &mut Document,
+        mut pages: Vec<Vec<PageText>>,
+        chunker: &Chunker,
+    ) -> Result<Vec<DocumentChunk>, IngestError> {
+        for (volume, volume_pages) in pages.iter().enumerate() {
+            let empty_pages: Vec<u32> = volume_pages
+                .iter()
+                .filter(|p| p.is_empty())
+                .map(|p| p.page_number)
+                .collect();
+            if !empty_pages.is_empty() {
+                log::warn!(
+                    "Document {} volume {} has {} pages without text: {:?}",
+                    document.id,
+                    volume + 1,
+                    empty_pages.len(),
+                    empty_pages
+                );
+            }
+        }
+        let page_count = pages.iter().map(Vec::len).sum::<usize>();
+        let pages_without_text = pages.iter().flatten().filter(|p| p.has_little_text()).count();
+        if document.source_format == SourceFormat::Pdf
+            && page_count > 0
+            && pages_without_text as f32 >= self.image_only_page_ratio * page_count as f32
+        {
+            return Err(ExtractError::ImageOnly { pages_without_text, page_count }.into());
+        }
+        if pages.iter().flatten().all(PageText::is_empty) {
+            return Err(ExtractError::NoTextContent.into());
+        }
+
+        document.stripped_lines.clear();
+        if let Some(page_ratio) = self.boilerplate_page_ratio {
+            for volume_pages in &mut pages {
+                for line in strip_boilerplate(volume_pages, page_ratio) {
+                    if !document.stripped_lines.contains(&line) {
+                        document.stripped_lines.push(line);
+                    }
+                }
+            }
+        }
+        if !document.stripped_lines.is_empty() {
+            log::info!(
+                "Stripped headers/footers from document {}: {:?}",
+                document.id,
+                document.stripped_lines
+            );
+        }
+
+        let mut metadata = ChunkMetadata::new(normalize_model(&document.bike_model))
+            .with_tenant(document.tenant_id.clone());
+        metadata.manual_type = document.manual_type.clone();
+        metadata.year = document.year;
+        metadata.content_hash = document.content_hash.clone();
+
+        let chunks = match pages.as_slice() {
+            [single] => chunker.chunk(&document.id, single, &metadata),
+            _ => chunker.chunk_volumes(&document.id, &pages, &metadata),
+        };
+        let (kept, dropped) = self.quality.apply(chunks);
+        if !dropped.is_empty() {
+            log::info!(
+                "Dropped low-quality chunks of document {}: {}",
+                document.id,
+                dropped
+                    .iter()
+                    .map(|(reason, count)| format!("{} {}", count, reason.as_str()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        if kept.is_empty() {
+            return Err(ExtractError::NoTextContent.into());
+        }
+        let chunks = chunker.reindex(kept);
+        document.chunk_size_tokens = Some(chunker.chunk_size());
+        document.chunk_overlap_tokens = Some(chunker.overlap());
+        document.chunk_count = chunks.len();
+        document.dropped_chunks = dropped.values().sum();
+        Ok(chunks)
+    }
+
+    /// Keep the upload and record the chunk ids of this run. When an earlier run
+    /// over the same content stopped part way, its chunk ids are reused and the
+    /// chunks it stored that are still in the vector store count as done.
+    async fn start_progress(
+        &self,
+        document: &Document,
+        volumes: &[&[u8]],
+        chunks: &mut [DocumentChunk],
+    ) -> Result<IngestionProgress, IngestError> {
+        let previous = self
+            .progress
+            .load(&document.id)
+            .await
+            .map_err(IngestError::Progress)?
+            .filter(|p| {
+                p.document.content_hash == document.content_hash
+                    && p.chunk_ids.len() == chunks.len()
+            });
+
+        let mut progress = IngestionProgress::new(document.clone());
+        if let Some(previous) = previous {
+            for (chunk, id) in chunks.iter_mut().zip(&previous.chunk_ids) {
+                chunk.id = id.clone();
+            }
+            progress.upserted = previous.verified(&self.vector_store.ids().await);
+            log::info!(
+                "Resuming document {}: {} of {} chunks already indexed",
+                document.id,
+                progress.upserted.len(),
+                chunks.len()
+            );
+        }
+        progress.chunk_ids = chunks.iter().map(|c| c.id.clone()).collect();
+
+        self.progress
+            .save_upload(&document.id, volumes)
+            .await
+            .map_err(IngestError::Progress)?;
+        self.progress.save(&progress).await.map_err(IngestError::Progress)?;
+        Ok(progress)
+    }
+
+    /// Embed and store chunks. Batches are queued for a pool of embedding
+    /// workers, whose results a single writer upserts and records as progress;
+    /// full queues hold back the stage feeding them. The first error stops the
+    /// whole pipeline. Returns the chunks whose embedding failed.
+    async fn embed_and_store(
+        &self,
+        chunks: Vec<DocumentChunk>,
+        document: &mut Document,
+        progress: &mut IngestionProgress,
+        on_progress: &(dyn Fn(&Document) + Send + Sync),
+    ) -> Result<Vec<DocumentChunk>, IngestError> {
+        let (batch_tx, batch_rx) = mpsc::channel(self.embedding_queue_depth);
+        let (result_tx, mut result_rx) =
+            mpsc::channel::<(Vec<DocumentChunk>, BatchEmbeddings)>(self.embedding_queue_depth);
+        let batch_rx = Mutex::new(batch_rx);
+
+        let produce = async move {
+            for batch in chunks.chunks(self.embedding_batch_size) {
+                if batch_tx.send(batch.to_vec()).await.is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        };
+
+        let workers: Vec<_> = (0..self.embedding_workers)
+            .map(|_| {
+                let result_tx = result_tx.clone();
+                let batch_rx = &batch_rx;
+                async move {
+                    loop {
+                        let Some(batch) = batch_rx.lock().await.recv().await else {
+                            break;
+                        };
+                        let texts = batch.iter().map(|c| c.text.clone()).collect();
+                        let embeddings = self.embedder.embed_batch_partial(texts).await;
+                        if result_tx.send((batch, embeddings)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            })
+            .collect();
+        let embed = async {
+            futures::future::join_all(workers).await;
+            Ok(())
+        };
+        // Only the workers' senders keep the writer going
+        drop(result_tx);
+
+        let write = async {
+            let mut failed = Vec::new();
+            while let Some((batch, embeddings)) = result_rx.recv().await {
+                let stored = self.store_batch(batch, embeddings, document, progress, on_progress);
+                failed.extend(stored.await?);
+            }
+            Ok::<_, IngestError>(failed)
+        };
+
+        let ((), (), failed) = tokio::try_join!(produce, embed, write)?;
+        Ok(failed)
+    }
+
+    /// Upsert the embedded chunks of a batch and record them as done; returns
+    /// the chunks whose embedding failed
+    async fn store_batch(
+        &self,
+        batch: Vec<DocumentChunk>,
+        result: BatchEmbeddings,
+        document: &mut Document,
+        progress: &mut IngestionProgress,
+        on_progress: &(dyn Fn(&Document) + Send + Sync),
+    ) -> Result<Vec<DocumentChunk>, IngestError> {
+        let mut embedded = Vec::new();
+        let mut failed = Vec::new();
+        for (chunk, embedding) in batch.into_iter().zip(result.embeddings) {
+            match embedding {
+                Some(embedding) => embedded.push(chunk.with_embedding(embedding)),
+                None => failed.push(chunk),
+            }
+        }
+
+        if !embedded.is_empty() {
+            let indices: BTreeSet<usize> =
+                embedded.iter().map(|c| c.metadata.chunk_index).collect();
+            self.vector_store
+                .upsert(embedded)
+                .await
+                .map_err(IngestError::Indexing)?;
+            document.chunks_embedded += indices.len();
+            progress.upserted.extend(indices);
+            progress.document = document.clone();
+            self.progress.save(progress).await.map_err(IngestError::Progress)?;
+            on_progress(document);
+        }
+
+        Ok(failed)
+    }
+}
+
+/// Run ingestion of one or more volumes in the background, recording progress
+/// in the registry. A panic inside the job marks the document failed instead of
+/// taking the server down.
+pub fn spawn_ingestion(
+    ingestor: Arc<Ingestor>,
+    registry: Arc<DocumentRegistry>,
+    document: Document,
+    volumes: Vec<Vec<u8>>,
+) -> JoinHandle<()> {
+    let id = document.id.clone();
+    registry.update(&document);
+
+    let job_registry = registry.clone();
+    let job = tokio::spawn(async move {
+        let mut document = document;
+        let progress = |doc: &Document| job_registry.update(doc);
+        let volumes: Vec<&[u8]> = volumes.iter().map(Vec::as_slice).collect();
+        if let Err(e) = ingestor.ingest_volumes(&mut document, &volumes, &progress).await {
+            log::warn!("Ingestion of {} failed: {}", document.id, e);
+        }
+        job_registry.update(&document);
+    });
+    supervise(registry, id, job)
+}
+
+/// Reprocess a document's stored page text in the background, recording
+/// progress in the registry like [`spawn_ingestion`]
+pub fn spawn_reprocessing(
+    ingestor: Arc<Ingestor>,
+    registry: Arc<DocumentRegistry>,
+    document: Document,
+    chunk_size: Option<usize>,
+    overlap: Option<usize>,
+) -> JoinHandle<()> {
+    let id = document.id.clone();
+    registry.update(&document);
+
+    let job_registry = registry.clone();
+    let job = tokio::spawn(async move {
+        let mut document = document;
+        let progress = |doc: &Document| job_registry.update(doc);
+        let result = ingestor.reprocess(&mut document, chunk_size, overlap, &progress).await;
+        if let Err(e) = result {
+            log::warn!("Reprocessing of {} failed: {}", document.id, e);
+        }
+        job_registry.update(&document);
+    });
+    supervise(registry, id, job)
+}
+
+/// Mark the document failed if its job panics or is cancelled. Aborting the
+/// returned handle aborts the job; its progress is checkpointed, so the
+/// ingestion can be resumed later.
+fn supervise(registry: Arc<DocumentRegistry>, id: String, job: JoinHandle<()>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let _abort_job = AbortOnDrop(job.abort_handle());
+        if let Err(e) = job.await {
+            log::error!("Ingestion job for {} crashed: {}", id, e);
+            let reason = if e.is_panic() {
+                "ingestion job panicked"
+            } else {
+                "ingestion job was cancelled"
+            };
+            registry.fail(&id, reason);
+        }
+    })
+}
+
+/// Aborts a task when dropped
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::MockProvider;
+    use crate::ai::CompletionOptions;
+    use crate::models::Message;
+    use async_trait::async_trait;
+    use tempfile::TempDir;
+
+    /// Provider whose embedding calls panic
+    struct PanickingProvider;
+
+    #[async_trait]
+    impl AiProvider for PanickingProvider {
+        async fn complete(&self, _: Vec<Message>, _: CompletionOptions) -> anyhow::Result<String> {
+            Ok(String::new())
+        }
+
+        async fn generate_embedding(&self, _: &str) -> anyhow::Result<Vec<f32>> {
+            panic!("embedding backend exploded")
+        }
+
+        async fn generate_embeddings_batch(&self, _: Vec<String>) -> anyhow::Result<Vec<Vec<f32>>> {
+            panic!("embedding backend exploded")
+        }
+    }
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    /// Provider that records how many embedding calls overlap, each taking a
+    /// few milliseconds
+    #[derive(Default)]
+    struct SlowEmbeddingProvider {
+        in_flight: std::sync::atomic::AtomicUsize,
+        max_in_flight: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl AiProvider for SlowEmbeddingProvider {
+        async fn complete(&self, _: Vec<Message>, _: CompletionOptions) -> anyhow::Result<String> {
+            Ok(String::new())
+        }
+
+        async fn generate_embedding(&self, text: &str) -> anyhow::Result<Vec<f32>> {
+            Ok(MockProvider::embed(text))
+        }
+
+        async fn generate_embeddings_batch(
+            &self,
+            texts: Vec<String>,
+        ) -> anyhow::Result<Vec<Vec<f32>>> {
+            use std::sync::atomic::Ordering;
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(texts.iter().map(|t| MockProvider::embed(t)).collect())
+        }
+    }
+
+    async fn ingestor(provider: Arc<dyn AiProvider>) -> (Arc<Ingestor>, TempDir) {
+        ingestor_with(Config::default(), provider).await
+    }
+
+    async fn ingestor_with(
+        config: Config,
+        provider: Arc<dyn AiProvider>,
+    ) -> (Arc<Ingestor>, TempDir) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = VectorStore::new(path, &config.vector_store_collection, config.vector_distance)
+            .await
+            .unwrap();
+        (Arc::new(Ingestor::new(&config, provider, Arc::new(store))), dir)
+    }
+
+    #[tokio::test]
+    async fn test_background_job_records_progress() {
+        let (ingestor, _dir) = ingestor(Arc::new(MockProvider::new(""))).await;
+        let registry = Arc::new(DocumentRegistry::new());
+        let document = Document::new("r1.pdf", "Yamaha R1");
+        let id = document.id.clone();
+
+        spawn_ingestion(ingestor, registry.clone(), document, vec![fixture("manual.pdf")])
+            .await
+            .unwrap();
+
+        let document = registry.get(&id).unwrap();
+        assert_eq!(document.status, DocumentStatus::Completed);
+        assert_eq!(document.pages_processed, 3);
+        assert!(document.chunk_count > 0);
+        assert_eq!(document.chunks_embedded, document.chunk_count);
+    }
+
+    #[tokio::test]
+    async fn test_failed_extraction_records_error() {
+        let (ingestor, _dir) = ingestor(Arc::new(MockProvider::new(""))).await;
+        let registry = Arc::new(DocumentRegistry::new());
+        let document = Document::new("bad.pdf", "Yamaha R1");
+        let id = document.id.clone();
+
+        spawn_ingestion(ingestor, registry.clone(), document, vec![fixture("encrypted.pdf")])
+            .await
+            .unwrap();
+
+        let document = registry.get(&id).unwrap();
+        assert_eq!(document.status, DocumentStatus::Failed);
+        assert_eq!(document.error.as_deref(), Some("PDF is encrypted"));
+        assert_eq!(document.error_code.as_deref(), Some("PDF_ENCRYPTED"));
+    }
+
+    #[tokio::test]
+    async fn test_scanned_pdf_reported_as_image_only() {
+        let (ingestor, _dir) = ingestor(Arc::new(MockProvider::new(""))).await;
+        let registry = Arc::new(DocumentRegistry::new());
+        let mut pdf = lopdf::Document::load_mem(&fixture("manual.pdf")).unwrap();
+        for (_, page_id) in pdf.get_pages() {
+            for content_id in pdf.get_page_contents(page_id) {
+                let stream = pdf.get_object_mut(content_id).unwrap().as_stream_mut().unwrap();
+                stream.set_content(Vec::new());
+            }
+        }
+        let mut scanned = Vec::new();
+        pdf.save_to(&mut scanned).unwrap();
+        let document = Document::new("scanned.pdf", "Yamaha R1");
+        let id = document.id.clone();
+
+        spawn_ingestion(ingestor, registry.clone(), document, vec![scanned]).await.unwrap();
+
+        let document = registry.get(&id).unwrap();
+        assert_eq!(document.status, DocumentStatus::Failed);
+        assert_eq!(document.error_code.as_deref(), Some("PDF_IMAGE_ONLY"));
+        assert!(document.error.unwrap().contains("3 of 3 pages"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_embeddings_retried_then_reported() {
+        let provider = Arc::new(MockProvider::new("").with_failing_embeddings("Brake"));
+        let (ingestor, _dir) = ingestor(provider.clone()).await;
+        let mut document = Document::new("r1.pdf", "Yamaha R1");
+
+        let result = ingestor
+            .ingest(&mut document, &fixture("manual.pdf"), &|_| {})
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(IngestError::PartialEmbedding { failed: 1, total: 1 })
+        ));
+        assert_eq!(document.status, DocumentStatus::Failed);
+        assert_eq!(document.chunks_embedded, 0);
+        // Each pass makes the initial attempt plus the provider-level retries
+        let attempts = 1 + crate::ai::EMBEDDING_BATCH_RETRIES as usize;
+        assert_eq!(
+            provider.embedding_batch_calls.load(std::sync::atomic::Ordering::Relaxed),
+            2 * attempts
+        );
+    }
+
+    #[tokio::test]
+    async fn test_panicking_job_is_isolated() {
+        let (ingestor, _dir) = ingestor(Arc::new(PanickingProvider)).await;
+        let registry = Arc::new(DocumentRegistry::new());
+        let document = Document::new("r1.pdf", "Yamaha R1");
+        let id = document.id.clone();
+
+        spawn_ingestion(ingestor, registry.clone(), document, vec![fixture("manual.pdf")])
+            .await
+            .unwrap();
+
+        let document = registry.get(&id).unwrap();
+        assert_eq!(document.status, DocumentStatus::Failed);
+        assert_eq!(document.error.as_deref(), Some("ingestion job panicked"));
+    }
+
+    #[tokio::test]
+    async fn test_html_guide_ingested_through_same_pipeline() {
+        let (ingestor, _dir) = ingestor(Arc::new(MockProvider::new(""))).await;
+        let mut document = Document::new("chain.html", "Yamaha R1");
+        document.source_format = SourceFormat::Html;
+        let html = b"<html><head><title>Chain guide</title></head><body>\
+                     <nav>Home | Forum</nav><h1>Chain slack</h1>\
+                     <p>Keep 30 mm of slack.</p></body></html>";
+
+        ingestor.ingest(&mut document, html, &|_| {}).await.unwrap();
+
+        assert_eq!(document.status, DocumentStatus::Completed);
+        assert_eq!(document.page_count, 1);
+        let chunks = ingestor.vector_store.scroll().await;
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].text, "# Chain slack\n\nKeep 30 mm of slack.");
+        assert_eq!(chunks[0].metadata.section.as_deref(), Some("Chain slack"));
+    }
+
+    #[tokio::test]
+    async fn test_running_footer_stripped_and_reported() {
+        let (ingestor, _dir) = ingestor(Arc::new(MockProvider::new(""))).await;
+        let mut document = Document::new("r1.pdf", "Yamaha R1");
+
+        ingestor.ingest(&mut document, &fixture("footer.pdf"), &|_| {}).await.unwrap();
+
+        assert_eq!(document.stripped_lines.len(), 2, "{:?}", document.stripped_lines);
+        assert!(document.stripped_lines[0].contains("PROPRIETARY AND CONFIDENTIAL"));
+        let chunks = ingestor.vector_store.scroll().await;
+        assert!(!chunks.is_empty());
+        for chunk in &chunks {
+            assert!(!chunk.text.contains("CONFIDENTIAL") && !chunk.text.contains("Page "));
+        }
+        assert!(chunks.iter().any(|c| c.metadata.page_number == Some(1)));
+    }
+
+    #[tokio::test]
+    async fn test_footer_kept_when_stripping_is_off() {
+        let config = Config {
+            strip_boilerplate: false,
+            ..Config::default()
+        };
+        let (ingestor, _dir) = ingestor_with(config, Arc::new(MockProvider::new(""))).await;
+        let mut document = Document::new("r1.pdf", "Yamaha R1");
+
+        ingestor.ingest(&mut document, &fixture("footer.pdf"), &|_| {}).await.unwrap();
+
+        assert!(document.stripped_lines.is_empty());
+        let chunks = ingestor.vector_store.scroll().await;
+        assert!(chunks.iter().any(|c| c.text.contains("PROPRIETARY AND CONFIDENTIAL")));
+    }
+
+    #[tokio::test]
+    async fn test_volumes_ingested_as_one_document() {
+        let (ingestor, _dir) = ingestor(Arc::new(MockProvider::new(""))).await;
+        let mut document = Document::new("r1-set.pdf", "Yamaha R1");
+        let (engine, electrical) = (fixture("manual.pdf"), fixture("titled.pdf"));
+
+        ingestor
+            .ingest_volumes(&mut document, &[&engine, &electrical], &|_| {})
+            .await
+            .unwrap();
+
+        assert_eq!(document.page_count, 5);
+        let chunks = ingestor.vector_store.scroll().await;
+        let volumes: std::collections::BTreeSet<_> =
+            chunks.iter().map(|c| c.metadata.volume).collect();
+        assert_eq!(volumes, [Some(1), Some(2)].into());
+        assert!(chunks.iter().all(|c| c.document_id == document.id));
+        let mut indexes: Vec<_> = chunks.iter().map(|c| c.metadata.chunk_index).collect();
+        indexes.sort();
+        assert_eq!(indexes, (0..document.chunk_count).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_junk_chunks_dropped_and_counted() {
+        let (ingestor, _dir) = ingestor(Arc::new(MockProvider::new(""))).await;
+        let mut document = Document::new("r1-notes.txt", "Yamaha R1");
+        document.source_format = SourceFormat::Text;
+        let volumes: [&[u8]; 3] = [
+            b"Adjust the chain slack to 30 mm at the midpoint of the lower run.",
+            b"Fig. 3-12 Rear brake caliper",
+            b"Bleed the rear brake until no air bubbles leave the caliper.",
+        ];
+
+        ingestor.ingest_volumes(&mut document, &volumes, &|_| {}).await.unwrap();
+
+        assert_eq!(document.chunk_count, 2);
+        assert_eq!(document.dropped_chunks, 1);
+        let mut chunks = ingestor.vector_store.scroll().await;
+        chunks.sort_by_key(|c| c.metadata.chunk_index);
+        let indexes: Vec<_> = chunks.iter().map(|c| c.metadata.chunk_index).collect();
+        assert_eq!(indexes, [0, 1]);
+        assert_eq!(chunks[1].metadata.volume, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_embedding_indexes_every_chunk() {
+        let config = Config {
+            chunk_size_tokens: 40,
+            chunk_overlap_tokens: 0,
+            embedding_workers: 4,
+            embedding_queue_depth: 2,
+            embedding_batch_size: 2,
+            ..Config::default()
+        };
+        let provider = Arc::new(SlowEmbeddingProvider::default());
+        let (ingestor, _dir) = ingestor_with(config, provider.clone()).await;
+        let mut document = Document::new("r1-service.md", "Yamaha R1");
+        document.source_format = SourceFormat::Markdown;
+        let text = (1..=30)
+            .map(|i| format!("Step {}: torque bolt {} of the rear caliper to 30 Nm.", i, i))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let reported = std::sync::Mutex::new(Vec::new());
+
+        ingestor
+            .ingest(&mut document, text.as_bytes(), &|d| {
+                reported.lock().unwrap().push(d.chunks_embedded)
+            })
+            .await
+            .unwrap();
+
+        assert!(document.chunk_count > 8, "{}", document.chunk_count);
+        assert_eq!(document.chunks_embedded, document.chunk_count);
+        let mut indexes: Vec<_> = ingestor
+            .vector_store
+            .scroll()
+            .await
+            .iter()
+            .map(|c| c.metadata.chunk_index)
+            .collect();
+        indexes.sort();
+        assert_eq!(indexes, (0..document.chunk_count).collect::<Vec<_>>());
+        let reported = reported.into_inner().unwrap();
+        assert!(reported.windows(2).all(|w| w[0] <= w[1]), "{:?}", reported);
+        assert_eq!(reported.last(), Some(&document.chunk_count));
+        let max_in_flight = provider.max_in_flight.load(std::sync::atomic::Ordering::SeqCst);
+        assert!(max_in_flight > 1, "embedding calls never overlapped");
+    }
+
+    #[tokio::test]
+    async fn test_page_ranges_keep_original_page_numbers() {
+        let (ingestor, _dir) = ingestor(Arc::new(MockProvider::new(""))).await;
+        let mut document = Document::new("r1.pdf", "Yamaha R1");
+        document.page_ranges = Some("2-3".parse().unwrap());
+        document.chunk_size_tokens = Some(64);
+
+        ingestor.ingest(&mut document, &fixture("manual.pdf"), &|_| {}).await.unwrap();
+
+        assert_eq!(document.page_count, 3);
+        assert_eq!(document.pages_processed, 2);
+        assert_eq!(document.chunk_size_tokens, Some(64));
+        let chunks = ingestor.vector_store.scroll().await;
+        assert!(!chunks.is_empty());
+        assert!(chunks.iter().all(|c| matches!(c.metadata.page_number, Some(2 | 3))));
+    }
+}
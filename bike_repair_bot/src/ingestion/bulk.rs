@@ -0,0 +1,304 @@
+//! Bulk ingestion of a directory of manuals (the `ingest` CLI subcommand)
+
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use futures::StreamExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::ingestion::Ingestor;
+use crate::models::{Document, SourceFormat};
+
+/// File extensions picked up when walking a directory
+pub const INGESTIBLE_EXTENSIONS: &[&str] = &["pdf", "txt", "md", "markdown", "html", "htm"];
+
+/// Words in a filename that name the manual type rather than the model
+const MANUAL_TYPES: &[&str] = &[
+    "service", "repair", "maintenance", "parts", "owner", "owners", "workshop", "wiring",
+];
+
+/// Metadata read from a filename following `<make>_<model...>[_<year>][_<manual type>].<ext>`,
+/// e.g. `honda_cbr600rr_2007_service.pdf` → Honda CBR600RR, 2007, service
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilenameMetadata {
+    pub bike_model: Option<String>,
+    pub year: Option<u32>,
+    pub manual_type: Option<String>,
+}
+
+impl FilenameMetadata {
+    /// Parse a file name (the extension is ignored). Model words containing digits
+    /// are upper-cased and the others capitalized; a name with a make but no model
+    /// word yields no `bike_model`.
+    pub fn parse(path: &Path) -> Self {
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let words: Vec<&str> = stem
+            .split(|c: char| c == '_' || c.is_whitespace())
+            .filter(|w| !w.is_empty())
+            .collect();
+
+        let mut metadata = Self::default();
+        let mut model_words = Vec::new();
+        let mut type_words = Vec::new();
+        for word in words {
+            let year = (word.len() == 4).then(|| word.parse::<u32>().ok()).flatten();
+            if metadata.year.is_none() && year.is_some_and(|y| (1950..=2100).contains(&y)) {
+                metadata.year = year;
+            } else if metadata.year.is_some()
+                || !type_words.is_empty()
+                || MANUAL_TYPES.contains(&word.to_lowercase().as_str())
+            {
+                type_words.push(word.to_lowercase());
+            } else {
+                model_words.push(model_word(word));
+            }
+        }
+
+        if model_words.len() >= 2 {
+            metadata.bike_model = Some(model_words.join(" "));
+        }
+        if !type_words.is_empty() {
+            metadata.manual_type = Some(type_words.join(" "));
+        }
+        metadata
+    }
+}
+
+fn model_word(word: &str) -> String {
+    if word.chars().any(|c| c.is_ascii_digit()) {
+        return word.to_uppercase();
+    }
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect(),
+        None => String::new(),
+    }
+}
+
+/// Settings for one bulk run
+#[derive(Debug, Clone)]
+pub struct BulkOptions {
+    /// Namespace the documents are indexed under
+    pub tenant_id: String,
+
+    /// Files processed at the same time
+    pub concurrency: usize,
+
+    /// Model used when neither the filename nor the content names one
+    pub default_bike_model: Option<String>,
+}
+
+/// What happened to one file of a bulk run
+#[derive(Debug, Clone, PartialEq)]
+pub enum BulkStatus {
+    Ingested {
+        document_id: String,
+        chunks: usize,
+        /// Chunks dropped by the quality filter before embedding
+        dropped_chunks: usize,
+        /// Running headers/footers removed before chunking
+        stripped_lines: Vec<String>,
+    },
+    /// Same content as an indexed document (or another file of this run)
+    Skipped { duplicate_of: String },
+    Failed { error: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BulkOutcome {
+    pub path: PathBuf,
+    pub bike_model: Option<String>,
+    pub status: BulkStatus,
+}
+
+/// Ingestible files under `dir`, recursively, in path order
+pub fn find_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| INGESTIBLE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Ingest every file under `dir` with at most `options.concurrency` in flight,
+/// skipping content that is already indexed for the tenant. `on_done` is called
+/// as each file finishes; outcomes are returned in path order.
+pub async fn ingest_directory(
+    ingestor: Arc<Ingestor>,
+    dir: &Path,
+    options: &BulkOptions,
+    on_done: impl Fn(&BulkOutcome),
+) -> std::io::Result<Vec<BulkOutcome>> {
+    let files = find_files(dir)?;
+    // Hash → path of files claimed in this run, so copies in the folder are skipped
+    let claimed: DashMap<String, PathBuf> = DashMap::new();
+
+    let mut outcomes: Vec<BulkOutcome> = futures::stream::iter(files)
+        .map(|path| {
+            let (ingestor, claimed) = (ingestor.clone(), &claimed);
+            async move {
+                let (bike_model, status) = ingest_file(&ingestor, &path, options, claimed).await;
+                BulkOutcome {
+                    path,
+                    bike_model,
+                    status,
+                }
+            }
+        })
+        .buffer_unordered(options.concurrency.max(1))
+        .inspect(|outcome| on_done(outcome))
+        .collect()
+        .await;
+
+    outcomes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(outcomes)
+}
+
+async fn ingest_file(
+    ingestor: &Ingestor,
+    path: &Path,
+    options: &BulkOptions,
+    claimed: &DashMap<String, PathBuf>,
+) -> (Option<String>, BulkStatus) {
+    let failed = |error: String| (None, BulkStatus::Failed { error });
+
+    let bytes = match tokio::fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(e) => return failed(format!("failed to read file: {}", e)),
+    };
+    let filename = path.file_name().and_then(|n| n.to_str()).unwrap_or("manual").to_string();
+    let Some(format) = SourceFormat::detect(&filename, None, &bytes) else {
+        return failed("not a PDF, text, Markdown or HTML file".to_string());
+    };
+
+    let content_hash = Document::hash_content(std::slice::from_ref(&bytes));
+    if let Some(document_id) = ingestor.find_ingested(&options.tenant_id, &content_hash).await {
+        return (None, BulkStatus::Skipped { duplicate_of: document_id });
+    }
+    match claimed.entry(content_hash.clone()) {
+        Entry::Occupied(first) => {
+            let duplicate_of = first.get().display().to_string();
+            return (None, BulkStatus::Skipped { duplicate_of });
+        }
+        Entry::Vacant(slot) => {
+            slot.insert(path.to_path_buf());
+        }
+    }
+
+    let parsed = FilenameMetadata::parse(path);
+    let bike_model = parsed
+        .bike_model
+        .or_else(|| ingestor.detect(format, &bytes).bike_model)
+        .or_else(|| options.default_bike_model.clone())
+        .unwrap_or_else(|| "Unknown".to_string());
+
+    let mut document = Document::new(filename, bike_model.clone());
+    document.tenant_id = options.tenant_id.clone();
+    document.content_hash = Some(content_hash);
+    document.source_format = format;
+    document.year = parsed.year;
+    document.manual_type = parsed.manual_type;
+
+    let status = match ingestor.ingest(&mut document, &bytes, &|_| {}).await {
+        Ok(chunks) => BulkStatus::Ingested {
+            document_id: document.id,
+            chunks,
+            dropped_chunks: document.dropped_chunks,
+            stripped_lines: document.stripped_lines,
+        },
+        Err(e) => BulkStatus::Failed {
+            error: e.to_string(),
+        },
+    };
+    (Some(bike_model), status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::MockProvider;
+    use crate::config::Config;
+    use crate::models::DEFAULT_TENANT;
+    use crate::rag::VectorStore;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_filename_pattern() {
+        let parse = |name: &str| FilenameMetadata::parse(Path::new(name));
+
+        assert_eq!(
+            parse("honda_cbr600rr_2007_service.pdf"),
+            FilenameMetadata {
+                bike_model: Some("Honda CBR600RR".to_string()),
+                year: Some(2007),
+                manual_type: Some("service".to_string()),
+            }
+        );
+        assert_eq!(
+            parse("Triumph_street_triple_owners_manual.pdf").bike_model.as_deref(),
+            Some("Triumph Street Triple")
+        );
+        assert_eq!(parse("ktm_390_duke.md").bike_model.as_deref(), Some("Ktm 390 Duke"));
+        assert_eq!(parse("scan0001.pdf"), FilenameMetadata::default());
+    }
+
+    #[tokio::test]
+    async fn test_directory_ingested_once_with_duplicates_and_binaries_reported() {
+        let storage = TempDir::new().unwrap();
+        let config = Config::default();
+        let path = storage.path().to_str().unwrap();
+        let store = VectorStore::new(path, &config.vector_store_collection, config.vector_distance)
+            .await
+            .unwrap();
+        let provider = Arc::new(MockProvider::new(""));
+        let ingestor = Arc::new(Ingestor::new(&config, provider, Arc::new(store)));
+
+        let manuals = TempDir::new().unwrap();
+        let guide = "# Chain\n\nKeep 30 mm of chain slack.";
+        std::fs::write(manuals.path().join("yamaha_r1_2015_service.md"), guide).unwrap();
+        std::fs::create_dir(manuals.path().join("copies")).unwrap();
+        std::fs::write(manuals.path().join("copies/yamaha_r1.md"), guide).unwrap();
+        std::fs::write(manuals.path().join("photo.pdf"), b"\x89PNG\r\n").unwrap();
+        std::fs::write(manuals.path().join("notes.docx"), b"ignored").unwrap();
+        let options = BulkOptions {
+            tenant_id: DEFAULT_TENANT.to_string(),
+            concurrency: 2,
+            default_bike_model: None,
+        };
+
+        let outcomes = ingest_directory(ingestor.clone(), manuals.path(), &options, |_| {})
+            .await
+            .unwrap();
+
+        let names: Vec<_> = outcomes.iter().map(|o| o.path.file_name().unwrap()).collect();
+        assert_eq!(names, ["yamaha_r1.md", "photo.pdf", "yamaha_r1_2015_service.md"]);
+        let ingested = outcomes
+            .iter()
+            .filter(|o| matches!(o.status, BulkStatus::Ingested { .. }))
+            .count();
+        assert_eq!(ingested, 1);
+        assert!(outcomes.iter().any(|o| matches!(o.status, BulkStatus::Skipped { .. })));
+        assert!(matches!(outcomes[1].status, BulkStatus::Failed { .. }));
+
+        // A second run finds everything already indexed
+        let rerun = ingest_directory(ingestor, manuals.path(), &options, |_| {}).await.unwrap();
+        let skipped = rerun
+            .iter()
+            .filter(|o| matches!(o.status, BulkStatus::Skipped { .. }))
+            .count();
+        assert_eq!(skipped, 2);
+    }
+}
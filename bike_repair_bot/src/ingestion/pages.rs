@@ -0,0 +1,107 @@
+//! Extracted page text kept per document so it can be chunked again later
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use crate::models::Document;
+use crate::pdf::PageText;
+
+/// Page text of a document as extracted, before headers and footers are stripped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredPages {
+    /// Document record as of the last ingestion
+    pub document: Document,
+
+    /// Pages of each volume, in volume order
+    pub volumes: Vec<Vec<PageText>>,
+}
+
+/// Gzip-compressed JSON files of extracted pages, one per document
+pub struct PageStore {
+    dir: PathBuf,
+}
+
+impl PageStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub async fn save(&self, pages: &StoredPages) -> Result<()> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&serde_json::to_vec(pages)?)?;
+        let bytes = encoder.finish()?;
+
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let path = self.path(&pages.document.id);
+        tokio::fs::write(&path, bytes)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub async fn load(&self, document_id: &str) -> Result<Option<StoredPages>> {
+        let path = self.path(document_id);
+        let bytes = match tokio::fs::read(&path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let mut json = Vec::new();
+        GzDecoder::new(bytes.as_slice())
+            .read_to_end(&mut json)
+            .with_context(|| format!("Corrupt page text {}", path.display()))?;
+        serde_json::from_slice(&json)
+            .map(Some)
+            .with_context(|| format!("Corrupt page text {}", path.display()))
+    }
+
+    /// Document ids are generated UUIDs; anything else is kept out of the path
+    fn path(&self, document_id: &str) -> PathBuf {
+        let safe: String = document_id
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect();
+        self.dir.join(format!("{}.json.gz", safe))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_pages_round_trip_compressed() {
+        let dir = TempDir::new().unwrap();
+        let store = PageStore::new(dir.path());
+        let document = Document::new("r1.pdf", "Yamaha R1");
+        let text = "Check the chain slack. ".repeat(200);
+        let pages = StoredPages {
+            volumes: vec![vec![
+                PageText {
+                    page_number: 1,
+                    text: text.clone(),
+                },
+                PageText {
+                    page_number: 2,
+                    text: String::new(),
+                },
+            ]],
+            document,
+        };
+        store.save(&pages).await.unwrap();
+
+        let path = store.path(&pages.document.id);
+        assert!(std::fs::metadata(&path).unwrap().len() < text.len() as u64 / 10);
+        let loaded = store.load(&pages.document.id).await.unwrap().unwrap();
+        assert_eq!(loaded.volumes, pages.volumes);
+        assert_eq!(loaded.document.id, pages.document.id);
+        assert!(store.load("missing").await.unwrap().is_none());
+    }
+}
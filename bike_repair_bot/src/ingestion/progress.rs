@@ -0,0 +1,162 @@
+//! Per-document ingestion state kept on disk so a failed run can be resumed
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::models::Document;
+
+/// How far ingestion of a document got
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IngestionProgress {
+    /// Document record as of the last update
+    pub document: Document,
+
+    /// Id given to each chunk index when the document was chunked, reused on
+    /// resume so stored chunks are recognized
+    pub chunk_ids: Vec<String>,
+
+    /// Chunk indices upserted into the vector store
+    pub upserted: BTreeSet<usize>,
+}
+
+impl IngestionProgress {
+    pub fn new(document: Document) -> Self {
+        Self {
+            document,
+            chunk_ids: Vec::new(),
+            upserted: BTreeSet::new(),
+        }
+    }
+
+    /// Upserted chunk indices whose chunk is still in the store
+    pub fn verified(&self, stored_ids: &HashSet<String>) -> BTreeSet<usize> {
+        self.upserted
+            .iter()
+            .copied()
+            .filter(|&i| self.chunk_ids.get(i).is_some_and(|id| stored_ids.contains(id)))
+            .collect()
+    }
+
+    /// Chunks still to be indexed, given the ids in the store
+    pub fn remaining(&self, stored_ids: &HashSet<String>) -> usize {
+        self.chunk_ids.len() - self.verified(stored_ids).len()
+    }
+}
+
+/// Progress records and uploaded files of unfinished documents, one directory
+/// per document
+pub struct ProgressStore {
+    dir: PathBuf,
+}
+
+impl ProgressStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Keep the uploaded files until the document is fully indexed
+    pub async fn save_upload(&self, document_id: &str, volumes: &[&[u8]]) -> Result<()> {
+        let dir = self.document_dir(document_id);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        for (index, bytes) in volumes.iter().enumerate() {
+            let path = dir.join(format!("volume-{}", index + 1));
+            tokio::fs::write(&path, bytes)
+                .await
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Uploaded files of a document, in volume order (empty if none were kept)
+    pub async fn load_upload(&self, document_id: &str) -> Result<Vec<Vec<u8>>> {
+        let dir = self.document_dir(document_id);
+        let mut volumes = Vec::new();
+        loop {
+            let path = dir.join(format!("volume-{}", volumes.len() + 1));
+            match tokio::fs::read(&path).await {
+                Ok(bytes) => volumes.push(bytes),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(volumes),
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to read {}", path.display()))
+                }
+            }
+        }
+    }
+
+    pub async fn save(&self, progress: &IngestionProgress) -> Result<()> {
+        let dir = self.document_dir(&progress.document.id);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join("progress.json");
+        tokio::fs::write(&path, serde_json::to_vec(progress)?)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub async fn load(&self, document_id: &str) -> Result<Option<IngestionProgress>> {
+        let path = self.document_dir(document_id).join("progress.json");
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .with_context(|| format!("Corrupt ingestion progress {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Forget a document once it is fully indexed
+    pub async fn remove(&self, document_id: &str) -> Result<()> {
+        let dir = self.document_dir(document_id);
+        match tokio::fs::remove_dir_all(&dir).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", dir.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Document ids are generated UUIDs; anything else is kept out of the path
+    fn document_dir(&self, document_id: &str) -> PathBuf {
+        let safe: String = document_id
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect();
+        self.dir.join(Path::new(&safe))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_progress_and_upload_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let store = ProgressStore::new(dir.path());
+        let document = Document::new("r1.pdf", "Yamaha R1");
+        let id = document.id.clone();
+
+        let mut progress = IngestionProgress::new(document);
+        progress.chunk_ids = vec!["a".into(), "b".into(), "c".into()];
+        progress.upserted = [0, 1].into();
+        store.save(&progress).await.unwrap();
+        store.save_upload(&id, &[b"one", b"two"]).await.unwrap();
+
+        let loaded = store.load(&id).await.unwrap().unwrap();
+        assert_eq!(loaded.chunk_ids, progress.chunk_ids);
+        // Chunk "b" was lost from the store, so it counts as remaining again
+        let stored: HashSet<String> = ["a".to_string()].into();
+        assert_eq!(loaded.remaining(&stored), 2);
+        assert_eq!(store.load_upload(&id).await.unwrap(), vec![b"one".to_vec(), b"two".to_vec()]);
+
+        store.remove(&id).await.unwrap();
+        assert!(store.load(&id).await.unwrap().is_none());
+        assert!(store.load_upload(&id).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,351 @@
+//! Download of manuals from a URL (`POST /api/documents/from-url`), guarded
+//! against server-side request forgery
+
+use reqwest::{header, redirect, StatusCode, Url};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::config::Config;
+
+/// Redirects followed before giving up; every hop is validated like the first URL
+const MAX_REDIRECTS: usize = 5;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Upper bound on one download, generous enough for multi-hundred-MB manuals
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Content types a server may send a PDF with
+const PDF_CONTENT_TYPES: &[&str] = &[
+    "application/pdf",
+    "application/x-pdf",
+    "application/octet-stream",
+    "binary/octet-stream",
+];
+
+/// Why a manual could not be downloaded
+#[derive(Debug, Error)]
+pub enum FetchError {
+    #[error("invalid URL: {0}")]
+    InvalidUrl(String),
+    #[error("only https URLs are accepted")]
+    InsecureScheme,
+    #[error("host '{0}' is not in the allowed host list")]
+    HostNotAllowed(String),
+    #[error("host '{0}' resolves to a private or reserved address")]
+    ForbiddenAddress(String),
+    #[error("could not resolve host '{0}'")]
+    Resolve(String),
+    #[error("download failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("server responded with status {0}")]
+    Status(StatusCode),
+    #[error("more than {MAX_REDIRECTS} redirects")]
+    TooManyRedirects,
+    #[error("file exceeds the {0} byte limit")]
+    TooLarge(usize),
+    #[error("content is not a PDF ({0})")]
+    NotPdf(String),
+}
+
+/// A downloaded PDF
+#[derive(Debug, Clone)]
+pub struct FetchedFile {
+    /// Last path segment of the final URL, used as the document's filename
+    pub filename: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Downloads PDFs over https from public hosts, optionally restricted to an
+/// allow list, with the size capped while streaming
+#[derive(Debug, Clone)]
+pub struct UrlFetcher {
+    /// Hosts (and their subdomains) downloads may come from; empty allows any
+    /// public host
+    allowed_hosts: Vec<String>,
+    max_bytes: usize,
+}
+
+impl UrlFetcher {
+    pub fn new(allowed_hosts: Vec<String>, max_bytes: usize) -> Self {
+        Self {
+            allowed_hosts: allowed_hosts.into_iter().map(|h| h.to_lowercase()).collect(),
+            max_bytes,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(
+            config.url_ingest_allowed_hosts.clone(),
+            config.max_pdf_size_mb as usize * 1024 * 1024,
+        )
+    }
+
+    /// Download the PDF at `url`. Redirects are followed by hand so each hop's
+    /// host is checked, and connections go to the address that was validated,
+    /// so a second DNS answer cannot point the request elsewhere.
+    pub async fn fetch(&self, url: &str) -> Result<FetchedFile, FetchError> {
+        let mut url = Url::parse(url.trim()).map_err(|e| FetchError::InvalidUrl(e.to_string()))?;
+
+        for _ in 0..=MAX_REDIRECTS {
+            let addr = self.check_url(&url).await?;
+            let mut client = reqwest::Client::builder()
+                .redirect(redirect::Policy::none())
+                // A proxy would resolve the host itself, bypassing the address check
+                .no_proxy()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .timeout(DOWNLOAD_TIMEOUT);
+            if let Some(domain) = url.domain() {
+                client = client.resolve(domain, addr);
+            }
+            let response = client.build()?.get(url.clone()).send().await?;
+
+            if response.status().is_redirection() {
+                let location = response
+                    .headers()
+                    .get(header::LOCATION)
+                    .and_then(|l| l.to_str().ok())
+                    .ok_or(FetchError::Status(response.status()))?;
+                url = url.join(location).map_err(|e| FetchError::InvalidUrl(e.to_string()))?;
+                log::debug!("Following redirect to {}", url);
+                continue;
+            }
+            return self.read_pdf(&url, response).await;
+        }
+        Err(FetchError::TooManyRedirects)
+    }
+
+    /// Check scheme and host of a URL and resolve it to a public address
+    pub async fn check_url(&self, url: &Url) -> Result<SocketAddr, FetchError> {
+        if url.scheme() != "https" {
+            return Err(FetchError::InsecureScheme);
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| FetchError::InvalidUrl("URL has no host".to_string()))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']').to_lowercase();
+        if !self.host_allowed(&host) {
+            return Err(FetchError::HostNotAllowed(host));
+        }
+
+        let port = url.port_or_known_default().unwrap_or(443);
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port))
+            .await
+            .map_err(|_| FetchError::Resolve(host.clone()))?
+            .collect();
+        // Every answer must be public: which one a client picks is not ours to choose
+        match addrs.first() {
+            None => Err(FetchError::Resolve(host)),
+            Some(_) if addrs.iter().any(|a| !is_public(a.ip())) => {
+                Err(FetchError::ForbiddenAddress(host))
+            }
+            Some(addr) => Ok(*addr),
+        }
+    }
+
+    fn host_allowed(&self, host: &str) -> bool {
+        self.allowed_hosts.is_empty()
+            || self.allowed_hosts.iter().any(|allowed| {
+                host == allowed
+                    || host
+                        .strip_suffix(allowed.as_str())
+                        .is_some_and(|sub| sub.ends_with('.'))
+            })
+    }
+
+    async fn read_pdf(
+        &self,
+        url: &Url,
+        mut response: reqwest::Response,
+    ) -> Result<FetchedFile, FetchError> {
+        if !response.status().is_success() {
+            return Err(FetchError::Status(response.status()));
+        }
+        if response.content_length().is_some_and(|len| len > self.max_bytes as u64) {
+            return Err(FetchError::TooLarge(self.max_bytes));
+        }
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|t| t.to_str().ok())
+            .and_then(|t| t.split(';').next())
+            .map(|t| t.trim().to_lowercase());
+        if let Some(content_type) = content_type.filter(|t| !PDF_CONTENT_TYPES.contains(&t.as_str()))
+        {
+            return Err(FetchError::NotPdf(content_type));
+        }
+
+        // Content-Length may be missing or wrong; count what actually arrives
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if bytes.len() + chunk.len() > self.max_bytes {
+                return Err(FetchError::TooLarge(self.max_bytes));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        if !bytes.starts_with(b"%PDF-") {
+            return Err(FetchError::NotPdf("missing %PDF- signature".to_string()));
+        }
+
+        Ok(FetchedFile {
+            filename: filename_from_url(url),
+            bytes,
+        })
+    }
+}
+
+/// Last non-empty path segment of the URL, or `manual.pdf`
+fn filename_from_url(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|segments| segments.rev().find(|s| !s.is_empty()))
+        .map(String::from)
+        .unwrap_or_else(|| "manual.pdf".to_string())
+}
+
+/// Whether an address is reachable on the public internet; loopback, private,
+/// link-local (cloud metadata), shared, documentation and other reserved
+/// ranges are not
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_v4(mapped),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // Shared address space (carrier-grade NAT)
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking
+        || (a == 198 && (b == 18 || b == 19))
+        // Reserved for future use
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    let first = segments[0];
+    // NAT64 (64:ff9b::/96) and 6to4 (2002::/16) reach the IPv4 address they embed
+    let embedded = if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        Some((segments[6], segments[7]))
+    } else if first == 0x2002 {
+        Some((segments[1], segments[2]))
+    } else {
+        None
+    };
+    if let Some((high, low)) = embedded {
+        return is_public_v4(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)));
+    }
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local
+        || (first & 0xfe00) == 0xfc00
+        // Link-local
+        || (first & 0xffc0) == 0xfe80
+        // Documentation
+        || (first == 0x2001 && segments[1] == 0x0db8)
+        // IPv4-compatible and other addresses under ::/96
+        || segments[..6].iter().all(|&s| s == 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetcher(allowed_hosts: &[&str]) -> UrlFetcher {
+        UrlFetcher::new(allowed_hosts.iter().map(|h| h.to_string()).collect(), 1024)
+    }
+
+    #[test]
+    fn test_private_and_reserved_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::127.0.0.1",
+            "2002:a9fe:a9fe::1",
+            "2002:c0a8:101::",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "93.184.216.34",
+            "8.8.8.8",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+            "64:ff9b::8.8.8.8",
+            "2002:808:808::1",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_allowed_hosts_match_subdomains_only() {
+        let restricted = fetcher(&["Yamaha-Motor.com"]);
+
+        assert!(restricted.host_allowed("yamaha-motor.com"));
+        assert!(restricted.host_allowed("manuals.yamaha-motor.com"));
+        assert!(!restricted.host_allowed("evilyamaha-motor.com"));
+        assert!(!restricted.host_allowed("yamaha-motor.com.evil.net"));
+        assert!(fetcher(&[]).host_allowed("anything.example"));
+    }
+
+    #[tokio::test]
+    async fn test_urls_rejected_before_any_request() {
+        let open = fetcher(&[]);
+
+        for (url, expected) in [
+            ("http://example.com/a.pdf", "only https"),
+            ("ftp://example.com/a.pdf", "only https"),
+            ("not a url", "invalid URL"),
+            ("https://127.0.0.1/a.pdf", "private or reserved"),
+            ("https://[::1]/a.pdf", "private or reserved"),
+            ("https://169.254.169.254/latest/meta-data", "private or reserved"),
+            ("https://localhost:8443/a.pdf", "private or reserved"),
+        ] {
+            let error = open.fetch(url).await.unwrap_err().to_string();
+            assert!(error.contains(expected), "{}: {}", url, error);
+        }
+
+        let restricted = fetcher(&["example.com"]);
+        let error = restricted.fetch("https://other.org/a.pdf").await.unwrap_err();
+        assert!(matches!(error, FetchError::HostNotAllowed(host) if host == "other.org"));
+    }
+
+    #[test]
+    fn test_filename_from_url() {
+        let name = |url: &str| filename_from_url(&Url::parse(url).unwrap());
+
+        assert_eq!(name("https://example.com/manuals/r1_2019.pdf"), "r1_2019.pdf");
+        assert_eq!(name("https://example.com/manuals/r1.pdf/?dl=1"), "r1.pdf");
+        assert_eq!(name("https://example.com/"), "manual.pdf");
+    }
+}
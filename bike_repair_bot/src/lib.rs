@@ -0,0 +1,19 @@
+pub mod config;
+pub mod models;
+pub mod security;
+pub mod ai;
+pub mod rag;
+pub mod pdf;
+pub mod server;
+pub mod metrics;
+pub mod ingestion;
+pub mod sqlite;
+pub mod sessions;
+pub mod feedback;
+pub mod request_context;
+pub mod logging;
+pub mod tasks;
+pub mod self_check;
+pub mod readiness;
+pub mod maintenance;
+pub mod usage;
@@ -0,0 +1,76 @@
+//! Log output through `tracing`: plain text lines by default, one JSON object
+//! per line with `LOG_FORMAT=json`. Events carry the fields of the spans they
+//! happen in (a request's id and client address, a chat's session), and
+//! `log` records are forwarded as events.
+
+use anyhow::Result;
+use std::str::FromStr;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::EnvFilter;
+
+/// Shape of log lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines, the spans' fields before the message
+    #[default]
+    Text,
+    /// One JSON object per event, with the fields of its spans
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "text" | "" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => anyhow::bail!("Unknown LOG_FORMAT '{}' (expected text or json)", other),
+        }
+    }
+}
+
+impl LogFormat {
+    /// Format chosen by `LOG_FORMAT` (text when unset)
+    pub fn from_env() -> Result<Self> {
+        std::env::var("LOG_FORMAT").map_or(Ok(LogFormat::Text), |value| value.parse())
+    }
+}
+
+/// Subscriber writing events that pass `filter` to `writer`
+pub fn subscriber<W>(
+    format: LogFormat,
+    filter: EnvFilter,
+    writer: W,
+) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(writer);
+    match format {
+        LogFormat::Text => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(builder.json().flatten_event(true).finish()),
+    }
+}
+
+/// Log to stderr for the rest of the process, filtered by `RUST_LOG` (info
+/// when unset)
+pub fn init(format: LogFormat) -> Result<()> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_log::LogTracer::init()?;
+    tracing::subscriber::set_global_default(subscriber(format, filter, std::io::stderr))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_format() {
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("Text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
@@ -1,88 +1,542 @@
-mod config;
-mod models;
-mod security;
-mod ai;
-mod rag;
-mod pdf;
-mod server;
-
 use anyhow::Result;
-use std::sync::Arc;
+use clap::{Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use bike_repair_bot::config::Config;
+use bike_repair_bot::feedback::FeedbackStore;
+use bike_repair_bot::logging::{self, LogFormat};
+use bike_repair_bot::metrics::Metrics;
+use bike_repair_bot::ingestion::bulk::{
+    find_files, ingest_directory, BulkOptions, BulkOutcome, BulkStatus,
+};
+use bike_repair_bot::ingestion::{DocumentRegistry, Ingestor};
+use bike_repair_bot::maintenance::Maintenance;
+use bike_repair_bot::usage::{IpHasher, UsageLog, UsageStore};
+use bike_repair_bot::ai::{load_system_prompt, warm_up, AiProvider, OpenAIClient};
+use bike_repair_bot::rag::{
+    create_snapshot, reembed_collection, resolve_embedding_dimension, Retriever, SynonymMap,
+    VectorStore,
+};
+use bike_repair_bot::security::{
+    BlockList, CircuitBreaker, QueryValidator, RateLimiter, RepeatGuard, WebhookAlerter,
+};
+use bike_repair_bot::server::{AppState, start_server};
+use bike_repair_bot::readiness::ReadinessProbe;
+use bike_repair_bot::self_check;
+use bike_repair_bot::tasks::TaskRegistry;
+use bike_repair_bot::sessions::{
+    HistorySummarizer, MemorySessionStore, SessionBackend, SessionStore, SqliteSessionStore,
+};
+
+/// Bike Repair ChatBot
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Check the configuration, OpenAI and the vector store, print a report
+    /// and exit (non-zero on any failure) instead of running a command.
+    /// `CHECK_ONLY=true` does the same.
+    #[arg(long)]
+    check: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the HTTP server (default)
+    Serve,
+
+    /// Re-embed the active collection with the configured embedding model into a new
+    /// collection, then make it active. The old collection is kept for rollback.
+    Reembed {
+        /// Target collection name (default: derived from the embedding model)
+        #[arg(long)]
+        target: Option<String>,
+
+        /// Texts per embedding request
+        #[arg(long, default_value_t = 100)]
+        batch_size: usize,
+
+        /// Log progress every N chunks
+        #[arg(long, default_value_t = 500)]
+        log_every: usize,
+    },
+
+    /// Index every manual in a directory (recursively) with the server's pipeline.
+    /// The bike model, year and manual type are read from filenames like
+    /// `honda_cbr600rr_2007_service.pdf`; files already indexed are skipped.
+    Ingest {
+        /// Directory of PDF, text, Markdown or HTML files
+        dir: PathBuf,
+
+        /// Files processed at the same time
+        #[arg(long, default_value_t = 2)]
+        concurrency: usize,
 
-use config::Config;
-use ai::OpenAIClient;
-use rag::VectorStore;
-use security::{RateLimiter, QueryValidator, CircuitBreaker};
-use server::{AppState, start_server};
+        /// Tenant namespace to index into (default namespace if omitted)
+        #[arg(long)]
+        tenant: Option<String>,
+    },
+
+    /// Delete an inactive collection (e.g. the old one after a successful reembed)
+    DropCollection {
+        /// Collection to delete
+        name: String,
+
+        /// Required to actually delete
+        #[arg(long)]
+        confirm: bool,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logger
-    env_logger::Builder::from_default_env()
-        .filter_level(log::LevelFilter::Info)
-        .init();
+    let cli = Cli::parse();
 
-    log::info!("🏍️  Bike Repair ChatBot - Starting...");
+    // Load .env, then initialize logging (LOG_FORMAT may come from .env)
+    dotenvy::dotenv().ok();
+    logging::init(LogFormat::from_env()?)?;
 
-    // Load configuration
-    let config = Config::from_env()?;
-    config.validate()?;
+    log::info!("🏍️  Bike Repair ChatBot - Starting...");
 
-    log::info!("✅ Configuration loaded");
+    // Load configuration, listing every bad variable rather than a backtrace
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(EXIT_CONFIG_INVALID);
+        }
+    };
 
     // Initialize OpenAI client
-    let openai_client = Arc::new(OpenAIClient::new(
+    let ai_provider: Arc<dyn AiProvider> = Arc::new(OpenAIClient::new(
         config.openai_api_key.clone(),
         config.openai_chat_model.clone(),
         config.openai_embedding_model.clone(),
-    ));
+    )
+    .with_organization(config.openai_org_id.clone(), config.openai_project_id.clone()));
+
+    if cli.check || check_only_env() {
+        let report = self_check::run(&config, ai_provider.as_ref()).await;
+        println!("{}", report);
+        std::process::exit(if report.passed() { 0 } else { EXIT_CHECK_FAILED });
+    }
+
+    if let Err(e) = config.validate() {
+        eprintln!("{}", e);
+        std::process::exit(EXIT_CONFIG_INVALID);
+    }
+    log::info!("✅ Configuration loaded");
     log::info!("✅ OpenAI client initialized");
 
-    // Initialize vector store (embedded Qdrant)
-    let vector_store = Arc::new(
-        VectorStore::new(&config.qdrant_path)
-            .await
-            .expect("Failed to initialize vector store"),
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config, ai_provider).await,
+        Command::Reembed {
+            target,
+            batch_size,
+            log_every,
+        } => reembed(&config, ai_provider, target, batch_size, log_every).await,
+        Command::Ingest {
+            dir,
+            concurrency,
+            tenant,
+        } => ingest(config, ai_provider, dir, concurrency, tenant).await,
+        Command::DropCollection { name, confirm } => {
+            if !confirm {
+                anyhow::bail!("Pass --confirm to delete collection '{}'", name);
+            }
+            VectorStore::delete_collection(&config.vector_store_path, &config.vector_store_collection, &name)
+                .await?;
+            log::info!("🗑️  Collection '{}' deleted", name);
+            Ok(())
+        }
+    }
+}
+
+/// Re-embed the active collection into `target` and flip the active alias to it
+async fn reembed(
+    config: &Config,
+    ai_provider: Arc<dyn AiProvider>,
+    target: Option<String>,
+    batch_size: usize,
+    log_every: usize,
+) -> Result<()> {
+    let source =
+        VectorStore::new(&config.vector_store_path, &config.vector_store_collection, config.vector_distance)
+            .await?;
+    let target = target.unwrap_or_else(|| {
+        let slug: String = config
+            .openai_embedding_model
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("{}_{}", config.vector_store_collection, slug)
+    });
+    if target == source.collection() {
+        anyhow::bail!("Target collection '{}' is already active", target);
+    }
+
+    let target_store =
+        VectorStore::open(&config.vector_store_path, &target, config.vector_distance).await?;
+    let dimension =
+        resolve_embedding_dimension(&config.openai_embedding_model, ai_provider.as_ref()).await?;
+    target_store.ensure_dimension(dimension).await?;
+    reembed_collection(&source, &target_store, ai_provider.as_ref(), batch_size, log_every)
+        .await?;
+
+    VectorStore::set_active_collection(&config.vector_store_path, &config.vector_store_collection, &target)
+        .await?;
+    log::info!(
+        "✅ Active collection is now '{}'. Previous collection '{}' was kept; remove it with \
+         `drop-collection {} --confirm` once verified.",
+        target,
+        source.collection(),
+        source.collection()
+    );
+
+    Ok(())
+}
+
+/// Exit code when the vector store cannot be opened on startup
+const EXIT_VECTOR_STORE_UNAVAILABLE: i32 = 3;
+
+/// Exit code of `--check` when any check failed
+const EXIT_CHECK_FAILED: i32 = 1;
+
+/// Exit code when environment variables are missing or malformed
+const EXIT_CONFIG_INVALID: i32 = 1;
+
+/// `CHECK_ONLY=true` (read after `.env` is loaded) asks for `--check`
+fn check_only_env() -> bool {
+    std::env::var("CHECK_ONLY").is_ok_and(|value| value.eq_ignore_ascii_case("true"))
+}
+
+/// Open the active collection and check it against the embedding model's dimension.
+/// Opening is retried with backoff; if it keeps failing the process exits with
+/// `EXIT_VECTOR_STORE_UNAVAILABLE`.
+async fn open_vector_store(
+    config: &Config,
+    ai_provider: &dyn AiProvider,
+) -> Result<Arc<VectorStore>> {
+    // Initialize vector store (embedded, files under VECTOR_STORE_PATH)
+    let vector_store = match VectorStore::connect(
+        &config.vector_store_path,
+        &config.vector_store_collection,
+        config.vector_distance,
+        config.vector_store_init_attempts,
+        Duration::from_millis(config.vector_store_init_backoff_ms),
+    )
+    .await
+    {
+        Ok(store) => Arc::new(store),
+        Err(e) => {
+            log::error!("❌ Vector store unavailable at {}: {:#}", config.vector_store_path, e);
+            std::process::exit(EXIT_VECTOR_STORE_UNAVAILABLE);
+        }
+    };
+    let model = &config.openai_embedding_model;
+    let dimension = resolve_embedding_dimension(model, ai_provider).await?;
+    vector_store
+        .check_embedding_dimension(model, dimension, config.allow_dimension_mismatch)
+        .await?;
+    log::info!(
+        "✅ Vector store initialized ({}, {} distance, {} dimensions)",
+        config.vector_store_path,
+        config.vector_distance.as_str(),
+        dimension
+    );
+    Ok(vector_store)
+}
+
+/// Index a directory of manuals, showing progress and a per-file summary
+async fn ingest(
+    config: Config,
+    ai_provider: Arc<dyn AiProvider>,
+    dir: PathBuf,
+    concurrency: usize,
+    tenant: Option<String>,
+) -> Result<()> {
+    let vector_store = open_vector_store(&config, ai_provider.as_ref()).await?;
+    let ingestor = Arc::new(Ingestor::new(&config, ai_provider, vector_store));
+    let options = BulkOptions {
+        tenant_id: config.resolve_tenant(tenant.as_deref()),
+        concurrency,
+        default_bike_model: config.default_bike_model.clone(),
+    };
+
+    let total = find_files(&dir)?.len();
+    let progress = ProgressBar::new(total as u64).with_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} files ({elapsed}) {msg}")?,
+    );
+    let outcomes = ingest_directory(ingestor, &dir, &options, |outcome| {
+        progress.set_message(outcome.path.display().to_string());
+        progress.inc(1);
+    })
+    .await?;
+    progress.finish_and_clear();
+
+    print_summary(&outcomes);
+    let failed = outcomes
+        .iter()
+        .filter(|o| matches!(o.status, BulkStatus::Failed { .. }))
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} files failed to ingest", failed, outcomes.len());
+    }
+    Ok(())
+}
+
+fn print_summary(outcomes: &[BulkOutcome]) {
+    let rows: Vec<(String, String, String, String)> = outcomes
+        .iter()
+        .map(|o| {
+            let model = o.bike_model.clone().unwrap_or_default();
+            let (status, detail) = match &o.status {
+                BulkStatus::Ingested {
+                    document_id,
+                    chunks,
+                    dropped_chunks,
+                    stripped_lines,
+                } => {
+                    let mut detail = format!("{} ({} chunks)", document_id, chunks);
+                    if *dropped_chunks > 0 {
+                        detail.push_str(&format!(", dropped {} junk chunks", dropped_chunks));
+                    }
+                    if !stripped_lines.is_empty() {
+                        detail.push_str(&format!(", stripped {:?}", stripped_lines));
+                    }
+                    ("ingested", detail)
+                }
+                BulkStatus::Skipped { duplicate_of } => {
+                    ("skipped", format!("duplicate of {}", duplicate_of))
+                }
+                BulkStatus::Failed { error } => ("failed", error.clone()),
+            };
+            (o.path.display().to_string(), model, status.to_string(), detail)
+        })
+        .collect();
+
+    let header = ("FILE", "MODEL", "STATUS", "DETAIL");
+    let file_width = rows.iter().map(|r| r.0.len()).chain([header.0.len()]).max().unwrap_or(0);
+    let model_width = rows.iter().map(|r| r.1.len()).chain([header.1.len()]).max().unwrap_or(0);
+    println!(
+        "{:file_width$}  {:model_width$}  {:8}  {}",
+        header.0, header.1, header.2, header.3
+    );
+    for (file, model, status, detail) in &rows {
+        println!("{:file_width$}  {:model_width$}  {:8}  {}", file, model, status, detail);
+    }
+
+    let count = |status: &str| rows.iter().filter(|r| r.2 == status).count();
+    println!(
+        "\n{} ingested, {} skipped, {} failed",
+        count("ingested"),
+        count("skipped"),
+        count("failed")
+    );
+}
+
+/// Initialize all components and run the HTTP server
+async fn serve(config: Config, ai_provider: Arc<dyn AiProvider>) -> Result<()> {
+    if config.enable_warmup {
+        match warm_up(ai_provider.as_ref()).await {
+            Ok(took) => log::info!("✅ OpenAI connections warmed up in {}ms", took.as_millis()),
+            Err(e) => log::warn!("Warm-up failed, starting anyway: {:#}", e),
+        }
+    }
+
+    let vector_store = open_vector_store(&config, ai_provider.as_ref()).await?;
+
+    let ingestor = Arc::new(Ingestor::new(&config, ai_provider.clone(), vector_store.clone()));
+    log::info!("✅ Ingestion pipeline initialized");
+
+    let synonyms = match &config.synonyms_path {
+        Some(path) => SynonymMap::load(path)?,
+        None => SynonymMap::with_defaults(),
+    };
+    let retriever = Arc::new(
+        Retriever::new(
+            ai_provider.clone(),
+            vector_store,
+            config.retrieval_top_k,
+            config.min_confidence,
+        )
+        .with_synonyms(synonyms),
     );
-    log::info!("✅ Vector store initialized ({})", config.qdrant_path);
+    log::info!("✅ Retriever initialized");
 
     // Initialize security components
     let rate_limiter = Arc::new(RateLimiter::new(
         config.max_requests_per_minute,
         config.max_requests_per_hour,
     ));
-    log::info!("✅ Rate limiter initialized");
+    let upload_rate_limiter = Arc::new(RateLimiter::new(
+        config.max_uploads_per_minute,
+        config.max_uploads_per_hour,
+    ));
+    let feedback_rate_limiter = Arc::new(RateLimiter::new(
+        config.max_feedback_per_minute,
+        config.max_feedback_per_hour,
+    ));
+    let admin_lockout = Arc::new(RateLimiter::new(
+        config.admin_max_failures_per_minute,
+        config.admin_max_failures_per_hour,
+    ));
+    let repeat_guard = Arc::new(RepeatGuard::new(
+        config.repeat_query_limit,
+        config.repeat_query_window_seconds,
+    ));
+    log::info!("✅ Rate limiters initialized");
+
+    let sessions: Arc<dyn SessionStore> = match config.session_store {
+        SessionBackend::Memory => Arc::new(MemorySessionStore::new(
+            config.session_max_messages,
+            config.session_ttl_seconds,
+        )),
+        SessionBackend::Sqlite => Arc::new(
+            SqliteSessionStore::open(
+                &config.session_db_path,
+                config.session_max_messages,
+                config.session_ttl_seconds,
+            )
+            .await?,
+        ),
+    };
+    let history_summarizer = config.enable_history_summary.then(|| {
+        Arc::new(HistorySummarizer::new(
+            ai_provider.clone(),
+            config.history_summary_threshold,
+            config.history_summary_keep_recent,
+        ))
+    });
+    log::info!(
+        "✅ Session store initialized ({:?}, history summary {})",
+        config.session_store,
+        if history_summarizer.is_some() { "on" } else { "off" }
+    );
+
+    let feedback = Arc::new(FeedbackStore::open(&config.feedback_db_path).await?);
+    log::info!("✅ Feedback store initialized ({})", config.feedback_db_path);
+    let usage = Arc::new(UsageLog::start(
+        UsageStore::open(&config.usage_db_path).await?,
+        IpHasher::from_config(&config)?,
+    ));
+    log::info!("✅ Usage store initialized ({})", config.usage_db_path);
 
-    let query_validator = Arc::new(QueryValidator::new());
+    let query_validator = Arc::new(
+        QueryValidator::new()
+            .with_min_length(config.min_query_words, config.min_query_chars)
+            .with_max_special_char_ratio(config.max_special_char_ratio),
+    );
     log::info!("✅ Query validator initialized");
 
-    let circuit_breaker = Arc::new(CircuitBreaker::new(
+    let mut circuit_breaker = CircuitBreaker::new(
         config.circuit_breaker_threshold,
         config.circuit_breaker_timeout_seconds,
-    ));
-    log::info!("✅ Circuit breaker initialized");
+    );
+    if let Some(url) = &config.alert_webhook_url {
+        let alerter = Arc::new(WebhookAlerter::new(url.clone()));
+        circuit_breaker = circuit_breaker.with_transition_hook(alerter.transition_hook());
+    }
+    let circuit_breaker = Arc::new(circuit_breaker);
+    log::info!(
+        "✅ Circuit breaker initialized (alerts {})",
+        if config.alert_webhook_url.is_some() { "on" } else { "off" }
+    );
+
+    let block_list = Arc::new(match &config.blocklist_path {
+        Some(path) => BlockList::load(path)?,
+        None => BlockList::new(),
+    });
+    log::info!("✅ Block list initialized ({} entries)", block_list.entries().len());
+
+    let system_prompt = load_system_prompt(config.system_prompt_path.as_deref())?;
+    log::info!(
+        "✅ System prompt loaded ({})",
+        config.system_prompt_path.as_deref().unwrap_or("built-in")
+    );
+
+    let readiness = Arc::new(ReadinessProbe::new(config.readiness_embedding_interval_seconds));
+    let metrics = Arc::new(Metrics::with_latency_buckets(config.metrics_latency_buckets.clone()));
+    let maintenance = Arc::new(Maintenance::from_config(&config));
+    if maintenance.is_enabled() {
+        log::warn!("⚠️  Maintenance mode is on: chat requests get 503 MAINTENANCE");
+    }
 
     // Create application state
     let state = AppState {
         config: Arc::new(config),
-        openai_client,
+        ai_provider,
+        retriever,
         rate_limiter: rate_limiter.clone(),
+        upload_rate_limiter: upload_rate_limiter.clone(),
+        feedback_rate_limiter: feedback_rate_limiter.clone(),
+        admin_lockout: admin_lockout.clone(),
+        repeat_guard: repeat_guard.clone(),
         query_validator,
         circuit_breaker,
+        block_list,
+        metrics,
+        system_prompt: Arc::new(RwLock::new(system_prompt)),
+        ingestor,
+        documents: Arc::new(DocumentRegistry::new()),
+        sessions: sessions.clone(),
+        history_summarizer,
+        feedback,
+        usage: usage.clone(),
+        tasks: Arc::new(TaskRegistry::new()),
+        readiness,
+        maintenance,
     };
 
     log::info!("✅ Application state initialized");
 
-    // Start periodic cleanup task for rate limiter
+    // Start periodic cleanup task for rate limiters, idle sessions and expired usage records
     let rate_limiter_cleanup = rate_limiter.clone();
-    tokio::spawn(async move {
+    let usage_retention_days = state.config.usage_retention_days;
+    state.tasks.spawn("periodic cleanup", async move {
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(600)); // 10 minutes
         loop {
             interval.tick().await;
             rate_limiter_cleanup.cleanup_old_entries();
+            upload_rate_limiter.cleanup_old_entries();
+            feedback_rate_limiter.cleanup_old_entries();
+            admin_lockout.cleanup_old_entries();
+            repeat_guard.cleanup_old_entries();
+            if let Err(e) = sessions.cleanup_expired().await {
+                log::warn!("Session cleanup failed: {:#}", e);
+            }
+            match usage.prune(usage_retention_days).await {
+                Ok(0) => {}
+                Ok(pruned) => log::info!("Pruned {} expired usage records", pruned),
+                Err(e) => log::warn!("Usage record pruning failed: {:#}", e),
+            }
         }
     });
 
+    // Snapshot the collection for backup on a schedule
+    if state.config.snapshot_interval_hours > 0 {
+        let hours = state.config.snapshot_interval_hours;
+        let dir = PathBuf::from(&state.config.snapshot_dir);
+        let keep = state.config.snapshot_keep;
+        let vector_store = state.retriever.vector_store().clone();
+        state.tasks.spawn("scheduled snapshots", async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(hours * 3600));
+            interval.tick().await; // the first tick completes immediately
+            loop {
+                interval.tick().await;
+                if let Err(e) = create_snapshot(&vector_store, &dir, keep).await {
+                    log::error!("Scheduled snapshot failed: {:#}", e);
+                }
+            }
+        });
+        log::info!("✅ Snapshots scheduled every {}h into {}", hours, state.config.snapshot_dir);
+    }
+
     // Start HTTP server
     log::info!("🚀 Starting HTTP server...");
     start_server(state).await?;
@@ -0,0 +1,88 @@
+//! Maintenance mode: while it is on, chat requests are turned away with 503
+//! `MAINTENANCE` and a message for the caller. Starts from `MAINTENANCE_MODE`
+//! and is toggled by `POST /api/admin/maintenance`; the state lasts until the
+//! process exits or it is toggled off.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+/// Message used when `MAINTENANCE_MESSAGE` is not set
+pub const DEFAULT_MAINTENANCE_MESSAGE: &str =
+    "The repair assistant is down for maintenance. Please try again shortly.";
+
+/// Current maintenance state, as reported by `/api/status`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MaintenanceStatus {
+    pub enabled: bool,
+    pub message: String,
+}
+
+/// Process-wide maintenance switch
+#[derive(Debug)]
+pub struct Maintenance {
+    enabled: AtomicBool,
+    message: RwLock<String>,
+}
+
+impl Maintenance {
+    pub fn new(enabled: bool, message: impl Into<String>) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+            message: RwLock::new(message.into()),
+        }
+    }
+
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self::new(config.maintenance_mode, config.maintenance_message.clone())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// The message for callers while maintenance is on
+    pub fn message(&self) -> String {
+        self.message.read().unwrap().clone()
+    }
+
+    /// Turn maintenance on or off. A message replaces the current one; without
+    /// one the previous message is kept.
+    pub fn set(&self, enabled: bool, message: Option<String>) -> MaintenanceStatus {
+        if let Some(message) = message.filter(|message| !message.trim().is_empty()) {
+            *self.message.write().unwrap() = message;
+        }
+        self.enabled.store(enabled, Ordering::SeqCst);
+        self.status()
+    }
+
+    pub fn status(&self) -> MaintenanceStatus {
+        MaintenanceStatus {
+            enabled: self.is_enabled(),
+            message: self.message(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggle_keeps_message_unless_replaced() {
+        let maintenance = Maintenance::new(false, DEFAULT_MAINTENANCE_MESSAGE);
+        assert!(!maintenance.is_enabled());
+
+        let status = maintenance.set(true, Some("Back at 14:00".to_string()));
+        assert_eq!(
+            status,
+            MaintenanceStatus { enabled: true, message: "Back at 14:00".to_string() }
+        );
+
+        // A blank message does not wipe the current one
+        let status = maintenance.set(false, Some("  ".to_string()));
+        assert!(!status.enabled);
+        assert_eq!(status.message, "Back at 14:00");
+        assert_eq!(maintenance.set(true, None).message, "Back at 14:00");
+    }
+}
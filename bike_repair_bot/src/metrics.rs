@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::rag::RetrievalTrace;
+
+/// Number of recent samples kept per latency series
+const WINDOW_SIZE: usize = 1024;
+
+/// Upper bounds (seconds) of the latency histogram buckets unless
+/// `METRICS_LATENCY_BUCKETS` sets others
+pub const DEFAULT_LATENCY_BUCKETS: [f64; 12] =
+    [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Sliding window of latency samples for percentile estimates
+pub struct LatencyWindow {
+    samples: Mutex<VecDeque<f64>>,
+    capacity: usize,
+}
+
+impl LatencyWindow {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Record a sample in milliseconds, evicting the oldest when full
+    pub fn record(&self, ms: f64) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back(ms);
+    }
+
+    /// Nearest-rank percentile (0.0-1.0) of the current window
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        let mut sorted: Vec<f64> = self.samples.lock().unwrap().iter().copied().collect();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let rank = ((p * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+        Some(sorted[rank - 1])
+    }
+
+    /// Number of samples in the window
+    pub fn count(&self) -> usize {
+        self.samples.lock().unwrap().len()
+    }
+}
+
+/// Observations of one label set: cumulative bucket counts, sum and count
+struct HistogramSeries {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Histograms over the same bucket bounds, one per label set. Label values
+/// must come from a small fixed set (route templates, not raw paths).
+pub struct Histogram {
+    bounds: Vec<f64>,
+    series: Mutex<BTreeMap<String, HistogramSeries>>,
+}
+
+impl Histogram {
+    pub fn new(bounds: Vec<f64>) -> Self {
+        Self {
+            bounds,
+            series: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Record a duration for the label set
+    pub fn observe(&self, labels: &[(&str, &str)], duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let mut series = self.series.lock().unwrap();
+        let series = series.entry(label_set(labels)).or_insert_with(|| HistogramSeries {
+            buckets: vec![0; self.bounds.len()],
+            sum: 0.0,
+            count: 0,
+        });
+        for (bucket, bound) in series.buckets.iter_mut().zip(&self.bounds) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        series.sum += seconds;
+        series.count += 1;
+    }
+
+    /// Observations recorded for the label set
+    pub fn count(&self, labels: &[(&str, &str)]) -> u64 {
+        let series = self.series.lock().unwrap();
+        series.get(&label_set(labels)).map_or(0, |s| s.count)
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (labels, series) in self.series.lock().unwrap().iter() {
+            let prefix = if labels.is_empty() { String::new() } else { format!("{},", labels) };
+            for (bound, count) in self.bounds.iter().zip(&series.buckets) {
+                let _ = writeln!(out, "{}_bucket{{{}le=\"{}\"}} {}", name, prefix, bound, count);
+            }
+            let _ = writeln!(out, "{}_bucket{{{}le=\"+Inf\"}} {}", name, prefix, series.count);
+            let _ = writeln!(out, "{}_sum{} {}", name, braced(labels), series.sum);
+            let _ = writeln!(out, "{}_count{} {}", name, braced(labels), series.count);
+        }
+    }
+}
+
+/// Monotonic counters, one per label set
+#[derive(Default)]
+pub struct Counter {
+    series: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Counter {
+    pub fn add(&self, labels: &[(&str, &str)], value: u64) {
+        *self.series.lock().unwrap().entry(label_set(labels)).or_insert(0) += value;
+    }
+
+    pub fn get(&self, labels: &[(&str, &str)]) -> u64 {
+        self.series.lock().unwrap().get(&label_set(labels)).copied().unwrap_or(0)
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (labels, value) in self.series.lock().unwrap().iter() {
+            let _ = writeln!(out, "{}{} {}", name, braced(labels), value);
+        }
+    }
+}
+
+/// `name="value"` pairs in exposition format, values escaped
+fn label_set(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// A label set in braces, or nothing for an empty one
+fn braced(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    }
+}
+
+/// Application metrics exposed on `/metrics`
+pub struct Metrics {
+    pub retrieval_embed_ms: LatencyWindow,
+    pub retrieval_search_ms: LatencyWindow,
+    pub retrieval_rerank_ms: LatencyWindow,
+    /// Handling time per route template, method and status code
+    pub http_requests: Histogram,
+    /// OpenAI call time per operation (`completion`, `embedding`)
+    pub openai_requests: Histogram,
+    /// Whole retrieval time of a chat request
+    pub retrieval: Histogram,
+    /// Chat tokens per kind (`prompt`, `completion`), counted with the model's tokenizer
+    pub openai_tokens: Counter,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::with_latency_buckets(DEFAULT_LATENCY_BUCKETS.to_vec())
+    }
+
+    /// Metrics whose latency histograms use `buckets` (seconds, increasing)
+    pub fn with_latency_buckets(buckets: Vec<f64>) -> Self {
+        Self {
+            retrieval_embed_ms: LatencyWindow::new(WINDOW_SIZE),
+            retrieval_search_ms: LatencyWindow::new(WINDOW_SIZE),
+            retrieval_rerank_ms: LatencyWindow::new(WINDOW_SIZE),
+            http_requests: Histogram::new(buckets.clone()),
+            openai_requests: Histogram::new(buckets.clone()),
+            retrieval: Histogram::new(buckets),
+            openai_tokens: Counter::default(),
+        }
+    }
+
+    /// Record the stage timings of one retrieval; its query embedding is an
+    /// OpenAI call
+    pub fn record_retrieval(&self, trace: &RetrievalTrace) {
+        self.retrieval_embed_ms.record(trace.embed_ms);
+        self.retrieval_search_ms.record(trace.search_ms);
+        self.retrieval_rerank_ms.record(trace.rerank_ms);
+        let embed = Duration::from_secs_f64(trace.embed_ms.max(0.0) / 1000.0);
+        self.openai_requests.observe(&[("operation", "embedding")], embed);
+    }
+
+    /// Record one handled request under its route template
+    pub fn record_request(&self, route: &str, method: &str, status: u16, elapsed: Duration) {
+        let status = status.to_string();
+        let labels = [("route", route), ("method", method), ("status", status.as_str())];
+        self.http_requests.observe(&labels, elapsed);
+    }
+
+    /// Record the time of a chat completion call, failed ones included
+    pub fn record_completion(&self, elapsed: Duration) {
+        self.openai_requests.observe(&[("operation", "completion")], elapsed);
+    }
+
+    /// Record the tokens of a successful completion
+    pub fn record_tokens(&self, prompt_tokens: usize, completion_tokens: usize) {
+        self.openai_tokens.add(&[("kind", "prompt")], prompt_tokens as u64);
+        self.openai_tokens.add(&[("kind", "completion")], completion_tokens as u64);
+    }
+
+    /// Render in Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, help, window) in [
+            ("retrieval_embed_ms", "Query embedding latency", &self.retrieval_embed_ms),
+            ("retrieval_search_ms", "Vector search latency", &self.retrieval_search_ms),
+            ("retrieval_rerank_ms", "Result filtering/reranking latency", &self.retrieval_rerank_ms),
+        ] {
+            render_summary(&mut out, name, help, window);
+        }
+
+        let requests = self.http_requests.series.lock().unwrap();
+        let _ = writeln!(out, "# HELP http_requests_total Requests handled");
+        let _ = writeln!(out, "# TYPE http_requests_total counter");
+        for (labels, series) in requests.iter() {
+            let _ = writeln!(out, "http_requests_total{} {}", braced(labels), series.count);
+        }
+        drop(requests);
+
+        for (name, help, histogram) in [
+            ("http_request_duration_seconds", "Request handling time", &self.http_requests),
+            ("openai_request_duration_seconds", "OpenAI call time", &self.openai_requests),
+            ("retrieval_duration_seconds", "Chat retrieval time", &self.retrieval),
+        ] {
+            histogram.render(&mut out, name, help);
+        }
+        self.openai_tokens
+            .render(&mut out, "openai_tokens_total", "Chat tokens sent and generated");
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn render_summary(out: &mut String, name: &str, help: &str, window: &LatencyWindow) {
+    let _ = writeln!(out, "# HELP {} {} (milliseconds, recent window)", name, help);
+    let _ = writeln!(out, "# TYPE {} summary", name);
+    for (label, p) in [("0.5", 0.5), ("0.95", 0.95)] {
+        if let Some(value) = window.percentile(p) {
+            let _ = writeln!(out, "{}{{quantile=\"{}\"}} {:.3}", name, label, value);
+        }
+    }
+    let _ = writeln!(out, "{}_count {}", name, window.count());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let window = LatencyWindow::new(100);
+        assert_eq!(window.percentile(0.5), None);
+
+        for ms in 1..=100 {
+            window.record(ms as f64);
+        }
+
+        assert_eq!(window.percentile(0.5), Some(50.0));
+        assert_eq!(window.percentile(0.95), Some(95.0));
+    }
+
+    #[test]
+    fn test_window_evicts_oldest() {
+        let window = LatencyWindow::new(3);
+        for ms in [1000.0, 1.0, 2.0, 3.0] {
+            window.record(ms);
+        }
+
+        assert_eq!(window.count(), 3);
+        assert_eq!(window.percentile(1.0), Some(3.0));
+    }
+
+    #[test]
+    fn test_render_retrieval_percentiles() {
+        let metrics = Metrics::new();
+        metrics.record_retrieval(&RetrievalTrace {
+            embed_ms: 12.0,
+            search_ms: 3.0,
+            rerank_ms: 0.5,
+            candidates_considered: 5,
+            top_score: Some(0.8),
+            ..Default::default()
+        });
+
+        let text = metrics.render();
+        assert!(text.contains("retrieval_embed_ms{quantile=\"0.5\"} 12.000"));
+        assert!(text.contains("retrieval_search_ms{quantile=\"0.95\"} 3.000"));
+        assert!(text.contains("retrieval_rerank_ms_count 1"));
+    }
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let metrics = Metrics::with_latency_buckets(vec![0.1, 1.0]);
+        for ms in [50, 500, 5000] {
+            metrics.record_request("/api/chat", "POST", 200, Duration::from_millis(ms));
+        }
+
+        let text = metrics.render();
+        let labels = "route=\"/api/chat\",method=\"POST\",status=\"200\"";
+        assert!(text.contains(&format!("http_requests_total{{{}}} 3", labels)), "{}", text);
+        let bucket = |le: &str| {
+            format!("http_request_duration_seconds_bucket{{{},le=\"{}\"}}", labels, le)
+        };
+        assert!(text.contains(&format!("{} 1", bucket("0.1"))), "{}", text);
+        assert!(text.contains(&format!("{} 2", bucket("1"))), "{}", text);
+        assert!(text.contains(&format!("{} 3", bucket("+Inf"))), "{}", text);
+    }
+}
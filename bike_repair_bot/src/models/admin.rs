@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Request to block or unblock an IP address or CIDR range
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct BlockRequest {
+    /// Single IP (`203.0.113.7`) or CIDR range (`198.51.100.0/24`)
+    pub ip: String,
+}
+
+/// Request to restore the active collection from a snapshot
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct RestoreRequest {
+    /// File name of the snapshot in `SNAPSHOT_DIR`, as listed by `GET /api/admin/snapshots`
+    pub snapshot: String,
+}
+
+/// Request to switch maintenance mode on or off
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct MaintenanceRequest {
+    pub enabled: bool,
+    /// Message chat callers get while maintenance is on; omitted keeps the current one
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Indexed chunks of one bike model
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct BikeModelCount {
+    pub bike_model: String,
+    /// Distinct documents the chunks come from
+    pub documents: usize,
+    pub chunks: usize,
+    /// Documents per manual type (lowercased); untyped documents are left out
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub manual_types: Vec<ManualTypeCount>,
+}
+
+/// Indexed documents of one manual type, e.g. `service` or `owner`
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ManualTypeCount {
+    pub manual_type: String,
+    pub documents: usize,
+}
+
+/// Response of `GET /api/admin/index-stats`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct IndexStats {
+    pub collection: String,
+    /// Similarity metric: `cosine`, `dot` or `euclid`
+    pub distance: String,
+    /// Vector dimension; absent while the collection is empty
+    pub dimension: Option<usize>,
+    /// Chunks (vectors) stored
+    pub points: usize,
+    /// Size of the collection file on disk
+    pub disk_bytes: u64,
+    /// Every bike model in the index, by name
+    pub bike_models: Vec<BikeModelCount>,
+}
@@ -0,0 +1,133 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::models::BikeModelCount;
+
+/// Response of `GET /api/models`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BikeModels {
+    /// Normalized models the caller's manuals cover, sorted, with their
+    /// documents per manual type
+    pub models: Vec<BikeModelCount>,
+}
+
+/// Manufacturer names dropped from the front of a model ("Honda CBR600RR"
+/// and "CBR600RR" are one model); longer names first so "harleydavidson"
+/// is not left as "davidson"
+const MANUFACTURERS: &[&str] = &[
+    "harleydavidson",
+    "kawasaki",
+    "triumph",
+    "yamaha",
+    "ducati",
+    "harley",
+    "suzuki",
+    "honda",
+    "bmw",
+    "ktm",
+];
+
+/// Nicknames and alternative designations (normalized) and the model they
+/// stand for. An alias also matches when followed by a displacement, so
+/// "gixxer750" becomes "gsxr750".
+const ALIASES: &[(&str, &str)] = &[
+    ("gixxer", "gsxr"),
+    ("fireblade", "cbr1000rr"),
+    ("blade", "cbr1000rr"),
+    ("yzfr", "r"),
+    ("fz07", "mt07"),
+    ("fz09", "mt09"),
+    ("zx10r", "ninjazx10r"),
+    ("ex400", "ninja400"),
+    ("dl650", "vstrom650"),
+    ("dl1000", "vstrom1000"),
+    // Distinct keys: the two Sportsters' torque and service specs differ
+    ("xl883", "sportster883"),
+    ("xl1200", "sportster1200"),
+];
+
+/// Canonical form of a bike model for filtering and comparing: lowercase
+/// letters and digits only, without a leading manufacturer and with known
+/// aliases resolved. "CBR600RR", "cbr 600 rr" and "Honda CBR-600RR" all
+/// become "cbr600rr".
+pub fn normalize_model(model: &str) -> String {
+    let key: String = model
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect();
+    let key = MANUFACTURERS
+        .iter()
+        .find_map(|maker| key.strip_prefix(maker).filter(|rest| !rest.is_empty()))
+        .unwrap_or(&key);
+    resolve_alias(key)
+}
+
+/// Whether two spellings name the same model
+pub fn same_model(a: &str, b: &str) -> bool {
+    normalize_model(a) == normalize_model(b)
+}
+
+fn resolve_alias(key: &str) -> String {
+    for (alias, model) in ALIASES {
+        if let Some(rest) = key.strip_prefix(alias) {
+            if rest.chars().all(|c| c.is_ascii_digit()) {
+                return format!("{}{}", model, rest);
+            }
+        }
+    }
+    key.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pdf::KNOWN_MODELS;
+
+    #[test]
+    fn test_spellings_of_a_model_agree() {
+        for spelling in ["CBR600RR", "cbr 600 rr", "Honda CBR-600RR", "HONDA cbr600rr "] {
+            assert_eq!(normalize_model(spelling), "cbr600rr", "{}", spelling);
+        }
+        assert_eq!(normalize_model("Harley-Davidson Sportster"), "sportster");
+        assert_eq!(normalize_model("Harley Davidson Sportster"), "sportster");
+        // A manufacturer alone is kept rather than emptied
+        assert_eq!(normalize_model("Ducati"), "ducati");
+        assert_eq!(normalize_model(""), "");
+    }
+
+    #[test]
+    fn test_aliases_resolve() {
+        assert_eq!(normalize_model("gixxer"), "gsxr");
+        assert_eq!(normalize_model("Suzuki Gixxer 750"), "gsxr750");
+        assert_eq!(normalize_model("Fireblade"), "cbr1000rr");
+        assert_eq!(normalize_model("YZF-R1"), "r1");
+        assert_eq!(normalize_model("Yamaha YZF R6"), "r6");
+        assert_eq!(normalize_model("FZ-07"), "mt07");
+        assert_eq!(normalize_model("Kawasaki ZX-10R"), "ninjazx10r");
+        assert_eq!(normalize_model("XL1200"), "sportster1200");
+        assert_eq!(normalize_model("Harley-Davidson XL 883"), "sportster883");
+        // Only a displacement may follow an alias
+        assert_eq!(normalize_model("Bladerunner"), "bladerunner");
+    }
+
+    #[test]
+    fn test_sportster_displacements_stay_apart() {
+        assert!(!same_model("XL883", "XL1200"));
+        assert!(!same_model("XL883", "Sportster"));
+        assert!(same_model("xl-883", "Harley XL883"));
+    }
+
+    #[test]
+    fn test_known_model_designations_normalize_to_their_model() {
+        for (name, _, designations) in KNOWN_MODELS {
+            for designation in *designations {
+                // "Monster 821" and "XL883" are variants rather than other names
+                if designation.starts_with("Monster ") || designation.starts_with("XL") {
+                    continue;
+                }
+                assert!(same_model(name, designation), "{} / {}", name, designation);
+            }
+        }
+    }
+}
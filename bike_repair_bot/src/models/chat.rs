@@ -1,9 +1,17 @@
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::ai::{MAX_COMPLETION_CHOICES, TEMPERATURE_RANGE, TOP_P_RANGE};
+use crate::models::document::default_tenant;
+use crate::rag::RetrievalTrace;
 
 /// Chat request from client
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct ChatRequest {
-    /// User's query/message
+    /// User's query/message; may be omitted when regenerating
+    #[serde(default)]
     pub query: String,
     
     /// Optional session ID for conversation history
@@ -13,43 +21,320 @@ pub struct ChatRequest {
     /// Optional bike model filter for RAG retrieval
     #[serde(default)]
     pub bike_model: Option<String>,
+
+    /// Request a structured answer (steps, safety warnings, tools) alongside the text
+    #[serde(default)]
+    pub structured: bool,
+
+    /// Include retrieval diagnostics in the response (requires the admin key)
+    #[serde(default)]
+    pub debug: bool,
+
+    /// Answer length: concise, normal or detailed
+    #[serde(default)]
+    pub verbosity: Verbosity,
+
+    /// Sampling temperature (0.0–2.0) overriding `OPENAI_TEMPERATURE`
+    #[serde(default)]
+    pub temperature: Option<f32>,
+
+    /// Nucleus sampling (0.0–1.0) overriding `OPENAI_TOP_P`
+    #[serde(default)]
+    pub top_p: Option<f32>,
+
+    /// Generate this many alternative answers (1–`MAX_COMPLETION_CHOICES`),
+    /// returned in `variants`
+    #[serde(default)]
+    pub variants: Option<u8>,
+
+    /// Answer the session's last question again, replacing its answer; sampled
+    /// `REGENERATE_TEMPERATURE_BOOST` hotter unless `temperature` is given
+    #[serde(default)]
+    pub regenerate: bool,
+
+    /// Ask for follow-up questions in `suggestions` when `SUGGESTIONS_ENABLED`
+    /// is set; defaults to true there
+    #[serde(default)]
+    pub include_suggestions: Option<bool>,
+
+    /// Attach the full text of the retrieved chunks in `context`
+    #[serde(default)]
+    pub include_context: bool,
+
+    /// Tenant whose manuals are searched; never read from the body, set from
+    /// the caller's API key
+    #[serde(skip, default = "default_tenant")]
+    pub tenant_id: String,
+}
+
+/// Added to the default temperature when regenerating an answer, so the new
+/// one differs from the last
+pub const REGENERATE_TEMPERATURE_BOOST: f32 = 0.3;
+
+/// Body of `POST /api/validate`
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct ValidateRequest {
+    pub query: String,
+}
+
+/// Whether `/api/chat` would answer a query from the manuals
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ValidateResponse {
+    pub valid: bool,
+
+    /// Why the query would be refused, or the steer it would get instead of
+    /// an answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    /// The query as chat would see it
+    pub normalized_query: String,
+}
+
+/// Query of `GET /api/chat/ws`
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ChatSocketQuery {
+    /// Session the connection continues; a new one is started when unset
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+/// A chat request field that is missing, of the wrong type or out of range
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{field}: {reason}")]
+pub struct FieldError {
+    pub field: String,
+    pub reason: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl ChatRequest {
+    /// Parse a JSON request body, naming the field at fault instead of
+    /// reporting serde's position-based error
+    pub fn from_json(body: &[u8]) -> Result<Self, FieldError> {
+        let value: serde_json::Value = serde_json::from_slice(body)
+            .map_err(|e| FieldError::new("body", format!("not valid JSON: {}", e)))?;
+        let Some(fields) = value.as_object() else {
+            return Err(FieldError::new("body", "must be a JSON object"));
+        };
+        check_field::<bool>(fields, "regenerate")?;
+        let regenerate = fields.get("regenerate").and_then(|v| v.as_bool()).unwrap_or(false);
+        if !fields.contains_key("query") && !regenerate {
+            return Err(FieldError::new("query", "is required"));
+        }
+
+        check_field::<String>(fields, "query")?;
+        check_field::<Option<String>>(fields, "session_id")?;
+        check_field::<Option<String>>(fields, "bike_model")?;
+        check_field::<bool>(fields, "structured")?;
+        check_field::<bool>(fields, "debug")?;
+        check_field::<Verbosity>(fields, "verbosity")?;
+        check_field::<Option<f32>>(fields, "temperature")?;
+        check_field::<Option<f32>>(fields, "top_p")?;
+        check_field::<Option<u8>>(fields, "variants")?;
+        check_field::<bool>(fields, "include_context")?;
+        serde_json::from_value(value).map_err(|e| FieldError::new("body", e.to_string()))
+    }
+
+    /// Check what the types alone do not: the session id is UUID-shaped, the
+    /// bike model is one `is_known_model` accepts and sampling values and the
+    /// number of variants are in range
+    pub fn validate_fields(&self, is_known_model: impl Fn(&str) -> bool) -> Result<(), FieldError> {
+        if let Some(id) = &self.session_id {
+            if uuid::Uuid::parse_str(id).is_err() {
+                return Err(FieldError::new("session_id", format!("'{}' is not a UUID", id)));
+            }
+        }
+        if let Some(model) = &self.bike_model {
+            if !is_known_model(model) {
+                return Err(FieldError::new(
+                    "bike_model",
+                    format!("'{}' is not a known bike model", model),
+                ));
+            }
+        }
+        for (field, value, range) in [
+            ("temperature", self.temperature, TEMPERATURE_RANGE),
+            ("top_p", self.top_p, TOP_P_RANGE),
+        ] {
+            if let Some(value) = value.filter(|v| !range.contains(v)) {
+                return Err(FieldError::new(
+                    field,
+                    format!("{} is outside {}–{}", value, range.start(), range.end()),
+                ));
+            }
+        }
+        let variants_range = 1..=MAX_COMPLETION_CHOICES;
+        if let Some(variants) = self.variants.filter(|v| !variants_range.contains(v)) {
+            return Err(FieldError::new(
+                "variants",
+                format!("{} is outside 1–{}", variants, MAX_COMPLETION_CHOICES),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Fail if the field is present but does not deserialize as `T`
+fn check_field<T: DeserializeOwned>(
+    fields: &serde_json::Map<String, serde_json::Value>,
+    name: &str,
+) -> Result<(), FieldError> {
+    match fields.get(name) {
+        Some(value) => serde_json::from_value::<T>(value.clone())
+            .map(|_| ())
+            .map_err(|e| FieldError::new(name, e.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Requested answer length
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    /// Short bullet answers
+    Concise,
+    #[default]
+    Normal,
+    /// Full step-by-step explanations
+    Detailed,
+}
+
+impl Verbosity {
+    /// Completion token cap before applying the server ceiling
+    pub fn max_tokens(&self) -> u16 {
+        match self {
+            Verbosity::Concise => 200,
+            Verbosity::Normal => 500,
+            Verbosity::Detailed => 1000,
+        }
+    }
 }
 
 /// Chat response to client
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ChatResponse {
     /// AI-generated response
     pub response: String,
     
     /// Session ID for conversation tracking
     pub session_id: String,
+
+    /// Unique id of this answer, for rating it via `/api/feedback`
+    pub response_id: String,
     
     /// Sources/citations from manual
     #[serde(default)]
     pub sources: Vec<Source>,
     
+    /// Structured answer when requested and the model complied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub structured: Option<StructuredAnswer>,
+
+    /// Mean relevance (0.0 to 1.0) of the sources the structured answer cites;
+    /// 0 when it cites none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_confidence: Option<f32>,
+
+    /// Every alternative answer when `variants` was requested, the first being
+    /// `response`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<String>,
+
+    /// Follow-up questions the user might ask next; empty when not requested
+    /// or the model ignored the format
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<String>,
+
+    /// Manual text the answer was based on, when `include_context` was set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<Vec<ContextChunk>>,
+    
+    /// Retrieval diagnostics (only for authorized debug requests)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub debug: Option<RetrievalTrace>,
+    
     /// Rate limit information
     pub rate_limit_info: RateLimitInfo,
 }
 
+/// A retrieved chunk as given to the model, for `include_context`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ContextChunk {
+    /// Where the text comes from
+    #[serde(flatten)]
+    pub source: Source,
+
+    /// Full text of the chunk
+    pub text: String,
+}
+
+/// Answer split into UI-renderable parts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct StructuredAnswer {
+    /// Short prose answer
+    pub answer: String,
+    
+    /// Ordered repair steps
+    #[serde(default)]
+    pub steps: Vec<String>,
+
+    /// Sources each step relies on, one list per step, numbered from 1 in the
+    /// order of `sources`; empty when the answer cites nothing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub step_sources: Vec<Vec<usize>>,
+    
+    /// Safety warnings to display prominently
+    #[serde(default)]
+    pub safety_warnings: Vec<String>,
+    
+    /// Tools required for the job
+    #[serde(default)]
+    pub tools_needed: Vec<String>,
+}
+
 /// Source citation from manual
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Source {
+    /// Chunk the citation points at
+    pub chunk_id: String,
+
+    /// Document the chunk was extracted from
+    pub document_id: String,
+
     /// Bike model this source is from
     pub bike_model: String,
     
     /// Page number in PDF
     pub page_number: Option<u32>,
+
+    /// Volume of a multi-file manual set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u32>,
     
     /// Section/chapter title
     pub section: Option<String>,
     
     /// Similarity score (0.0 to 1.0)
     pub relevance_score: f32,
+
+    /// Start of the chunk text, cut at a word boundary
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub excerpt: Option<String>,
 }
 
 /// Rate limit information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct RateLimitInfo {
     /// Requests remaining this minute
     pub remaining_minute: u32,
@@ -61,11 +346,56 @@ pub struct RateLimitInfo {
     pub reset_in_seconds: u64,
 }
 
+/// Who wrote a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Instructions, manual context and summaries of earlier turns
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+
+    /// Role of a stored message; sessions saved before roles were checked may
+    /// hold anything, which is read as the user's with a warning
+    pub fn from_stored(role: &str) -> Self {
+        match role.trim().to_lowercase().as_str() {
+            "system" => Role::System,
+            "user" => Role::User,
+            "assistant" => Role::Assistant,
+            other => {
+                log::warn!("Unknown message role '{}', treating it as the user's", other);
+                Role::User
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Role {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|role| Role::from_stored(&role))
+    }
+}
+
 /// Single message in a conversation
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Message {
-    /// Message role (user, assistant, system)
-    pub role: String,
+    pub role: Role,
     
     /// Message content
     pub content: String,
@@ -73,40 +403,86 @@ pub struct Message {
     /// Timestamp
     #[serde(default)]
     pub timestamp: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Id of the chat response this assistant message was sent as, for feedback
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_id: Option<String>,
+
+    /// Ids of the manual chunks the answer was based on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_ids: Vec<String>,
 }
 
 impl Message {
     pub fn user(content: impl Into<String>) -> Self {
         Self {
-            role: "user".to_string(),
+            role: Role::User,
             content: content.into(),
             timestamp: Some(chrono::Utc::now()),
+            response_id: None,
+            source_ids: Vec::new(),
         }
     }
 
     pub fn assistant(content: impl Into<String>) -> Self {
         Self {
-            role: "assistant".to_string(),
+            role: Role::Assistant,
             content: content.into(),
             timestamp: Some(chrono::Utc::now()),
+            response_id: None,
+            source_ids: Vec::new(),
         }
     }
 
     pub fn system(content: impl Into<String>) -> Self {
         Self {
-            role: "system".to_string(),
+            role: Role::System,
             content: content.into(),
             timestamp: None,
+            response_id: None,
+            source_ids: Vec::new(),
         }
     }
+
+    /// Tag an assistant message with the response it was sent as and the
+    /// chunks it was based on
+    pub fn with_response(mut self, response_id: impl Into<String>, source_ids: Vec<String>) -> Self {
+        self.response_id = Some(response_id.into());
+        self.source_ids = source_ids;
+        self
+    }
+}
+
+/// Stored conversation of a chat session, for restoring it in the UI
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SessionHistory {
+    pub session_id: String,
+
+    /// Bike model stated or detected in the conversation, with its year if named
+    pub bike_model: Option<String>,
+    pub bike_year: Option<u32>,
+
+    /// Messages oldest first; earlier turns may be condensed into a system summary
+    pub messages: Vec<Message>,
+
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_active: chrono::DateTime<chrono::Utc>,
 }
 
 /// Error response
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ErrorResponse {
     pub error: String,
     pub code: String,
     pub details: Option<String>,
+
+    /// Set on `429` replies of the chat endpoints
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit_info: Option<RateLimitInfo>,
+
+    /// Id of the request, also returned in the `X-Request-Id` header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 impl ErrorResponse {
@@ -115,6 +491,8 @@ impl ErrorResponse {
             error: error.into(),
             code: code.into(),
             details: None,
+            rate_limit_info: None,
+            request_id: crate::request_context::request_id(),
         }
     }
 
@@ -122,4 +500,42 @@ impl ErrorResponse {
         self.details = Some(details.into());
         self
     }
+
+    pub fn with_rate_limit_info(mut self, info: RateLimitInfo) -> Self {
+        self.rate_limit_info = Some(info);
+        self
+    }
+
+    /// Set the request id where the reply is built outside the request's
+    /// context, as in a streamed response body
+    pub fn with_request_id(mut self, request_id: Option<String>) -> Self {
+        self.request_id = request_id;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_roles_round_trip() {
+        for message in [Message::system("s"), Message::user("u"), Message::assistant("a")] {
+            let json = serde_json::to_value(&message).unwrap();
+            assert_eq!(json["role"], message.role.as_str());
+            let parsed: Message = serde_json::from_value(json).unwrap();
+            assert_eq!(parsed.role, message.role);
+        }
+    }
+
+    #[test]
+    fn test_legacy_roles_read_leniently() {
+        let parse = |role: &str| {
+            let json = serde_json::json!({ "role": role, "content": "Check the chain" });
+            serde_json::from_value::<Message>(json).unwrap().role
+        };
+        assert_eq!(parse("Assistant"), Role::Assistant);
+        assert_eq!(parse("assistnat"), Role::User);
+        assert_eq!(parse(""), Role::User);
+    }
 }
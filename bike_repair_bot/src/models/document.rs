@@ -1,8 +1,21 @@
 use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::str::FromStr;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-/// PDF document metadata
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::pdf::{ModelDetection, PageRanges};
+
+/// Namespace used for documents and requests without a (valid) tenant
+pub const DEFAULT_TENANT: &str = "default";
+
+pub(crate) fn default_tenant() -> String {
+    DEFAULT_TENANT.to_string()
+}
+
+/// Uploaded manual or guide metadata
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Document {
     /// Unique document ID
     pub id: String,
@@ -10,6 +23,10 @@ pub struct Document {
     /// Original filename
     pub filename: String,
     
+    /// Tenant namespace that owns this document
+    #[serde(default = "default_tenant")]
+    pub tenant_id: String,
+    
     /// Detected bike model (Honda CBR600RR, Yamaha R1, etc.)
     pub bike_model: String,
     
@@ -25,11 +42,65 @@ pub struct Document {
     /// Number of pages
     pub page_count: u32,
     
-    /// Number of chunks created
+    /// Number of chunks kept for embedding
     pub chunk_count: usize,
+
+    /// Chunks dropped by the quality filter (page numbers, captions, TOC lines)
+    #[serde(default)]
+    pub dropped_chunks: usize,
     
     /// Processing status
     pub status: DocumentStatus,
+
+    /// Pages extracted so far
+    #[serde(default)]
+    pub pages_processed: u32,
+
+    /// Chunks embedded and stored so far (out of `chunk_count`)
+    #[serde(default)]
+    pub chunks_embedded: usize,
+
+    /// Failure reason when `status` is `failed`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// Error code of an extraction failure (`PDF_ENCRYPTED`, `PDF_MALFORMED`,
+    /// `PDF_NO_TEXT`, `PDF_IMAGE_ONLY`, ...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+
+    /// Pages left out because their content could not be parsed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_pages: Vec<u32>,
+
+    /// Hex SHA-256 of the uploaded file, used to reject duplicate uploads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+
+    /// Filenames of a multi-volume manual set, in volume order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<String>,
+
+    /// Format the document was uploaded in
+    #[serde(default)]
+    pub source_format: SourceFormat,
+
+    /// Running headers/footers removed from the pages before chunking
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stripped_lines: Vec<String>,
+
+    /// Pages selected for indexing (all when `None`); chunks keep the
+    /// original page numbers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "1-180,240-260")]
+    pub page_ranges: Option<PageRanges>,
+
+    /// Chunk size and overlap the document is chunked with (the configured
+    /// ones where `None`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size_tokens: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_overlap_tokens: Option<usize>,
 }
 
 impl Document {
@@ -37,19 +108,43 @@ impl Document {
         Self {
             id: Uuid::new_v4().to_string(),
             filename: filename.into(),
+            tenant_id: default_tenant(),
             bike_model: bike_model.into(),
             year: None,
             manual_type: None,
             uploaded_at: chrono::Utc::now(),
             page_count: 0,
             chunk_count: 0,
+            dropped_chunks: 0,
             status: DocumentStatus::Processing,
+            pages_processed: 0,
+            chunks_embedded: 0,
+            error: None,
+            error_code: None,
+            skipped_pages: Vec::new(),
+            content_hash: None,
+            volumes: Vec::new(),
+            source_format: SourceFormat::default(),
+            stripped_lines: Vec::new(),
+            page_ranges: None,
+            chunk_size_tokens: None,
+            chunk_overlap_tokens: None,
+        }
+    }
+
+    /// Hex SHA-256 of the uploaded files' bytes, in order (a single file hashes
+    /// to its plain SHA-256)
+    pub fn hash_content(files: &[Vec<u8>]) -> String {
+        let mut hasher = Sha256::new();
+        for file in files {
+            hasher.update(file);
         }
+        hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
     }
 }
 
 /// Document processing status
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum DocumentStatus {
     Processing,
@@ -57,6 +152,114 @@ pub enum DocumentStatus {
     Failed,
 }
 
+/// File formats accepted for ingestion
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceFormat {
+    #[default]
+    Pdf,
+    Text,
+    Markdown,
+    Html,
+}
+
+impl SourceFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SourceFormat::Pdf => "pdf",
+            SourceFormat::Text => "text",
+            SourceFormat::Markdown => "markdown",
+            SourceFormat::Html => "html",
+        }
+    }
+
+    /// Work out the format of an upload from its bytes, declared content type and
+    /// filename, in that order of trust. Other UTF-8 text is read as plain text;
+    /// binaries and files claiming to be PDFs without the `%PDF-` signature are
+    /// rejected with `None`.
+    pub fn detect(filename: &str, content_type: Option<&str>, bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"%PDF-") {
+            return Some(SourceFormat::Pdf);
+        }
+        if bytes.contains(&0) || std::str::from_utf8(bytes).is_err() {
+            return None;
+        }
+
+        let essence = content_type
+            .and_then(|t| t.split(';').next())
+            .map(|t| t.trim().to_lowercase());
+        let extension = filename.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+        if essence.as_deref() == Some("application/pdf") || extension.as_deref() == Some("pdf") {
+            return None;
+        }
+
+        let from_type = match essence.as_deref() {
+            Some("text/markdown" | "text/x-markdown") => Some(SourceFormat::Markdown),
+            Some("text/html" | "application/xhtml+xml") => Some(SourceFormat::Html),
+            _ => None,
+        };
+        let from_extension = match extension.as_deref() {
+            Some("md" | "markdown") => Some(SourceFormat::Markdown),
+            Some("html" | "htm" | "xhtml") => Some(SourceFormat::Html),
+            _ => None,
+        };
+        Some(from_type.or(from_extension).unwrap_or(SourceFormat::Text))
+    }
+}
+
+impl FromStr for SourceFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "pdf" => Ok(SourceFormat::Pdf),
+            "text" | "txt" | "plain" => Ok(SourceFormat::Text),
+            "markdown" | "md" => Ok(SourceFormat::Markdown),
+            "html" | "htm" => Ok(SourceFormat::Html),
+            other => anyhow::bail!(
+                "Unknown format '{}' (expected pdf, text, markdown or html)",
+                other
+            ),
+        }
+    }
+}
+
+/// How chunk IDs are assigned at ingestion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChunkIdMode {
+    /// Fresh random UUID per chunk
+    #[default]
+    Random,
+
+    /// UUID derived from document ID, chunk index and text, so re-ingesting
+    /// the same manual overwrites the existing points
+    Deterministic,
+}
+
+impl ChunkIdMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChunkIdMode::Random => "random",
+            ChunkIdMode::Deterministic => "deterministic",
+        }
+    }
+}
+
+impl FromStr for ChunkIdMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "random" => Ok(ChunkIdMode::Random),
+            "deterministic" => Ok(ChunkIdMode::Deterministic),
+            other => anyhow::bail!(
+                "Unknown chunk id mode '{}' (expected random or deterministic)",
+                other
+            ),
+        }
+    }
+}
+
 /// Text chunk from a document with metadata
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DocumentChunk {
@@ -92,6 +295,30 @@ impl DocumentChunk {
         }
     }
 
+    /// Stable ID for a chunk: a UUID built from a SHA-1 of its identity
+    pub fn deterministic_id(document_id: &str, chunk_index: usize, text: &str) -> String {
+        let mut hasher = Sha1::new();
+        hasher.update(document_id.as_bytes());
+        hasher.update([0]);
+        hasher.update(chunk_index.to_le_bytes());
+        hasher.update([0]);
+        hasher.update(text.as_bytes());
+        let digest = hasher.finalize();
+
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&digest[..16]);
+        uuid::Builder::from_sha1_bytes(bytes).into_uuid().to_string()
+    }
+
+    /// Reassign the ID according to `mode`
+    pub fn with_id_mode(mut self, mode: ChunkIdMode) -> Self {
+        if mode == ChunkIdMode::Deterministic {
+            self.id =
+                Self::deterministic_id(&self.document_id, self.metadata.chunk_index, &self.text);
+        }
+        self
+    }
+
     pub fn with_embedding(mut self, embedding: Vec<f32>) -> Self {
         self.embedding = Some(embedding);
         self
@@ -101,14 +328,30 @@ impl DocumentChunk {
 /// Metadata attached to each chunk
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChunkMetadata {
+    /// Tenant namespace the chunk belongs to
+    #[serde(default = "default_tenant")]
+    pub tenant_id: String,
+    
     /// Bike model
     pub bike_model: String,
     
     /// Page number in PDF
     pub page_number: Option<u32>,
+
+    /// 1-based volume of a multi-file manual set (`None` for single-file manuals)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u32>,
     
+    /// Kind of content when not plain prose (`table` for reconstructed tables)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+
     /// Section/chapter heading
     pub section: Option<String>,
+
+    /// Hex SHA-256 of the source file(s), to recognize already ingested files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
     
     /// Manual type
     pub manual_type: Option<String>,
@@ -123,21 +366,212 @@ pub struct ChunkMetadata {
 impl ChunkMetadata {
     pub fn new(bike_model: impl Into<String>) -> Self {
         Self {
+            tenant_id: default_tenant(),
             bike_model: bike_model.into(),
             page_number: None,
+            volume: None,
+            content_type: None,
             section: None,
+            content_hash: None,
             manual_type: None,
             year: None,
             chunk_index: 0,
         }
     }
+
+    pub fn with_tenant(mut self, tenant_id: impl Into<String>) -> Self {
+        self.tenant_id = tenant_id.into();
+        self
+    }
 }
 
 /// Upload response
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct UploadResponse {
     pub document_id: String,
     pub filename: String,
     pub status: String,
     pub message: String,
+
+    /// Model/year detection result when the upload omitted `bike_model`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detection: Option<ModelDetection>,
+}
+
+/// Response of `POST /api/documents/{id}/resume`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ResumeResponse {
+    pub document_id: String,
+    pub status: String,
+    pub message: String,
+
+    /// Chunks the document is split into
+    pub chunks_total: usize,
+
+    /// Chunks not yet in the vector store, to be embedded by the resumed run
+    pub chunks_remaining: usize,
+}
+
+/// Optional body of `POST /api/documents/{id}/reprocess`; omitted settings
+/// fall back to the configured chunk size and overlap, and to the document's
+/// page ranges
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReprocessRequest {
+    pub chunk_size_tokens: Option<usize>,
+    pub chunk_overlap_tokens: Option<usize>,
+    /// Pages to index, e.g. `1-180,240-260`
+    pub page_ranges: Option<String>,
+}
+
+/// Response of `POST /api/documents/{id}/reprocess`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReprocessResponse {
+    pub document_id: String,
+    pub status: String,
+    pub message: String,
+
+    /// Settings the document is chunked with
+    pub chunk_size_tokens: usize,
+    pub chunk_overlap_tokens: usize,
+
+    /// Pages indexed, when not all of them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "1-180,240-260")]
+    pub page_ranges: Option<PageRanges>,
+}
+
+/// Query parameters of `POST /api/documents`
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UploadOptions {
+    /// Replace an existing document with the same content instead of rejecting
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Query parameters of `POST /api/documents/preview`; omitted settings fall
+/// back to the configured chunk size and overlap
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PreviewOptions {
+    pub chunk_size_tokens: Option<usize>,
+    pub chunk_overlap_tokens: Option<usize>,
+    /// Number of leading chunks whose text is returned
+    pub previews: Option<usize>,
+}
+
+/// Response of `POST /api/documents/preview`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ChunkPreviewResponse {
+    pub page_count: u32,
+
+    /// Settings the upload was chunked with
+    pub chunk_size_tokens: usize,
+    pub chunk_overlap_tokens: usize,
+
+    /// Chunks that would be embedded
+    pub chunk_count: usize,
+
+    /// Chunks the quality filter would drop
+    pub dropped_chunks: usize,
+
+    /// Token count of every kept chunk, in order
+    pub token_counts: Vec<usize>,
+
+    /// Distinct sections, in order of first appearance
+    pub sections: Vec<String>,
+
+    /// Running headers/footers that would be removed
+    pub stripped_lines: Vec<String>,
+
+    /// The first chunks, with their text cut to a preview
+    pub chunks: Vec<ChunkPreview>,
+}
+
+/// One chunk of a preview
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ChunkPreview {
+    pub chunk_index: usize,
+    pub page_number: Option<u32>,
+    pub section: Option<String>,
+    pub tokens: usize,
+    pub text: String,
+}
+
+/// Body of `POST /api/documents/from-url`
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct UrlIngestRequest {
+    /// https URL of the PDF manual
+    pub url: String,
+    pub bike_model: Option<String>,
+    pub manual_type: Option<String>,
+}
+
+/// Body of `PATCH /api/documents/{id}`; omitted fields are left unchanged
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct DocumentMetadataUpdate {
+    pub bike_model: Option<String>,
+    pub year: Option<u32>,
+    pub manual_type: Option<String>,
+}
+
+impl DocumentMetadataUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.bike_model.is_none() && self.year.is_none() && self.manual_type.is_none()
+    }
+
+    /// Apply the provided fields to `document`
+    pub fn apply(&self, document: &mut Document) {
+        if let Some(bike_model) = &self.bike_model {
+            document.bike_model = bike_model.trim().to_string();
+        }
+        if let Some(year) = self.year {
+            document.year = Some(year);
+        }
+        if let Some(manual_type) = &self.manual_type {
+            document.manual_type = Some(manual_type.trim().to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_format_detection() {
+        let detect = SourceFormat::detect;
+
+        assert_eq!(detect("a.bin", None, b"%PDF-1.4"), Some(SourceFormat::Pdf));
+        let html = Some("text/html; charset=utf-8");
+        assert_eq!(detect("guide", html, b"<p>"), Some(SourceFormat::Html));
+        let markdown = Some(SourceFormat::Markdown);
+        assert_eq!(detect("notes.md", Some("text/plain"), b"# Chain"), markdown);
+        assert_eq!(detect("notes.txt", None, b"Chain"), Some(SourceFormat::Text));
+        assert_eq!(detect("notes", None, b"Chain"), Some(SourceFormat::Text));
+
+        assert_eq!(detect("a.pdf", None, b"GIF89a not a manual"), None);
+        assert_eq!(detect("a.txt", Some("application/pdf"), b"text"), None);
+        assert_eq!(detect("photo.md", None, b"\x89PNG\r\n\x1a\n\0\0"), None);
+        assert_eq!(detect("latin1.txt", None, b"caf\xe9"), None);
+    }
+
+    #[test]
+    fn test_deterministic_id_is_stable_and_distinct() {
+        let a = DocumentChunk::deterministic_id("doc-1", 0, "Check the chain.");
+
+        assert_eq!(a, DocumentChunk::deterministic_id("doc-1", 0, "Check the chain."));
+        assert_ne!(a, DocumentChunk::deterministic_id("doc-1", 1, "Check the chain."));
+        assert_ne!(a, DocumentChunk::deterministic_id("doc-2", 0, "Check the chain."));
+        assert!(Uuid::parse_str(&a).is_ok());
+    }
+
+    #[test]
+    fn test_hash_content_is_hex_sha256() {
+        assert_eq!(
+            Document::hash_content(&[b"abc".to_vec()]),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
 }
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::models::{Message, Role};
+
+/// Longest comment accepted with a rating, in characters
+pub const MAX_FEEDBACK_COMMENT_CHARS: usize = 2000;
+
+/// Entries returned by the admin listing when no limit is given
+pub const DEFAULT_FEEDBACK_LIMIT: usize = 100;
+
+/// Thumbs up or down on an answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    Up,
+    Down,
+}
+
+impl Rating {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rating::Up => "up",
+            Rating::Down => "down",
+        }
+    }
+}
+
+impl FromStr for Rating {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "up" => Ok(Rating::Up),
+            "down" => Ok(Rating::Down),
+            other => anyhow::bail!("Unknown rating '{}'", other),
+        }
+    }
+}
+
+/// Rating of an answer, which is named by its `response_id` or by its index
+/// in the session's messages (as returned by `GET /api/sessions/{id}`)
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct FeedbackRequest {
+    pub session_id: String,
+
+    #[serde(default)]
+    pub message_index: Option<usize>,
+
+    #[serde(default)]
+    pub response_id: Option<String>,
+
+    pub rating: Rating,
+
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+impl FeedbackRequest {
+    /// Check the request names exactly one answer and the comment is not too long
+    pub fn validate(&self) -> Result<(), String> {
+        if self.message_index.is_some() == self.response_id.is_some() {
+            return Err("Provide either message_index or response_id".to_string());
+        }
+        let comment_chars = self.comment.as_deref().map_or(0, |c| c.chars().count());
+        if comment_chars > MAX_FEEDBACK_COMMENT_CHARS {
+            return Err(format!(
+                "comment is longer than {} characters",
+                MAX_FEEDBACK_COMMENT_CHARS
+            ));
+        }
+        Ok(())
+    }
+
+    /// The rated answer among the session's messages, with the query it
+    /// replied to; `None` if there is no such assistant message
+    pub fn locate<'a>(&self, messages: &'a [Message]) -> Option<(Option<&'a Message>, &'a Message)> {
+        let index = match (&self.response_id, self.message_index) {
+            (Some(id), _) => messages
+                .iter()
+                .position(|m| m.response_id.as_deref() == Some(id.as_str()))?,
+            (None, Some(index)) => index,
+            (None, None) => return None,
+        };
+        let answer = messages.get(index).filter(|m| m.role == Role::Assistant)?;
+        let query = messages[..index].iter().rev().find(|m| m.role == Role::User);
+        Some((query, answer))
+    }
+}
+
+/// A stored rating together with the exchange it is about
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Feedback {
+    pub tenant_id: String,
+    pub session_id: String,
+    pub response_id: Option<String>,
+    pub rating: Rating,
+    pub comment: Option<String>,
+    pub query: Option<String>,
+    pub response: String,
+
+    /// Ids of the manual chunks the answer was based on
+    pub source_ids: Vec<String>,
+
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Query parameters of `GET /api/admin/feedback`
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FeedbackFilter {
+    pub rating: Option<Rating>,
+
+    /// Only feedback given at or after this time (RFC 3339)
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Only feedback given before this time (RFC 3339)
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+
+    pub limit: Option<usize>,
+}
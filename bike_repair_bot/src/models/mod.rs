@@ -1,5 +1,15 @@
 pub mod chat;
 pub mod document;
+pub mod admin;
+pub mod bike;
+pub mod feedback;
+pub mod search;
+pub mod usage;
 
 pub use chat::*;
 pub use document::*;
+pub use admin::*;
+pub use bike::*;
+pub use feedback::*;
+pub use search::*;
+pub use usage::*;
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Most results `POST /api/search` returns
+pub const MAX_SEARCH_TOP_K: usize = 50;
+
+/// Characters of chunk text returned per search result
+pub const SEARCH_PREVIEW_CHARS: usize = 300;
+
+/// Body of `POST /api/search`
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct SearchRequest {
+    pub query: String,
+
+    #[serde(default)]
+    pub bike_model: Option<String>,
+
+    /// Results to return; defaults to the retrieval `top_k`
+    #[serde(default)]
+    pub top_k: Option<usize>,
+}
+
+/// Chunks matching a search, best first
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SearchResponse {
+    pub results: Vec<SearchHit>,
+}
+
+/// One matched chunk
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SearchHit {
+    pub chunk_id: String,
+    pub document_id: String,
+    pub bike_model: String,
+    pub page_number: Option<u32>,
+    pub section: Option<String>,
+
+    /// Relevance normalized to 0.0-1.0
+    pub score: f32,
+
+    /// Score as reported by the collection's distance metric
+    pub raw_score: f32,
+
+    /// Start of the chunk's text
+    pub preview: String,
+}
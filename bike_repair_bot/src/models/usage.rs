@@ -0,0 +1,83 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+/// Days covered by the usage report when no `from` is given
+pub const DEFAULT_USAGE_DAYS: i64 = 7;
+
+/// Most hours or days one usage report may span
+pub const MAX_USAGE_BUCKETS: i64 = 1000;
+
+/// Size of the periods the usage report is split into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageGrouping {
+    Hour,
+    #[default]
+    Day,
+}
+
+impl UsageGrouping {
+    pub fn seconds(&self) -> i64 {
+        match self {
+            UsageGrouping::Hour => 3600,
+            UsageGrouping::Day => 86400,
+        }
+    }
+}
+
+/// Query parameters of `GET /api/admin/usage`
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UsageQuery {
+    /// Start of the report (RFC 3339); defaults to seven days before `to`
+    pub from: Option<DateTime<Utc>>,
+
+    /// End of the report, exclusive (RFC 3339); defaults to now
+    pub to: Option<DateTime<Utc>>,
+
+    #[serde(default)]
+    pub group_by: UsageGrouping,
+}
+
+/// Aggregates over the chat requests of a period
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub unique_sessions: u64,
+    pub tokens_in: u64,
+    pub tokens_out: u64,
+
+    /// Tokens priced at `USAGE_INPUT_PRICE_PER_MILLION` and
+    /// `USAGE_OUTPUT_PRICE_PER_MILLION`
+    pub estimated_cost_usd: f64,
+
+    /// Share of requests refused or failed, by any error code
+    pub error_rate: f64,
+
+    /// Share of the requests reaching retrieval that found manual chunks
+    pub retrieval_hit_rate: f64,
+
+    pub p95_latency_ms: u64,
+}
+
+/// Aggregates of one hour or day
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct UsageBucket {
+    pub start: DateTime<Utc>,
+
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+/// Response of `GET /api/admin/usage`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UsageReport {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub group_by: UsageGrouping,
+    pub totals: UsageTotals,
+
+    /// Every hour or day of the range in order, including empty ones
+    pub buckets: Vec<UsageBucket>,
+}
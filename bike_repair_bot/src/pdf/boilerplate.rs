@@ -0,0 +1,209 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::pdf::PageText;
+
+/// Share of pages a line must repeat on (strictly more than) to count as a header or footer
+pub const DEFAULT_BOILERPLATE_PAGE_RATIO: f32 = 0.5;
+
+/// Lines at the top and at the bottom of a page that may be a header or footer
+const EDGE_LINES: usize = 3;
+
+/// With fewer pages a repeated line is as likely to be content as boilerplate
+const MIN_PAGES: usize = 3;
+
+/// Pages a line must appear on however low the ratio, so tuning it down
+/// cannot strip lines that merely open or close a couple of pages
+const MIN_REPEATS: usize = 3;
+
+/// Remove running headers and footers: lines near the top or bottom of a page
+/// that repeat on more than `page_ratio` of the pages with text, and on at
+/// least `MIN_REPEATS` pages. Lines are
+/// compared ignoring case, punctuation and numbers, so `Page 3` and `Page 4`
+/// match. Page numbers stay in `PageText::page_number`. Returns the first
+/// occurrence of each removed line, in the order they were found.
+pub fn strip_boilerplate(pages: &mut [PageText], page_ratio: f32) -> Vec<String> {
+    let page_total = pages.iter().filter(|p| !p.is_empty()).count();
+    if page_total < MIN_PAGES || page_ratio >= 1.0 {
+        return Vec::new();
+    }
+
+    // Key → number of pages it appears on, plus its first occurrence
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut first_seen: Vec<(String, String)> = Vec::new();
+    for page in pages.iter() {
+        let lines: Vec<&str> = page.text.lines().collect();
+        let mut keys_on_page = HashSet::new();
+        for i in edge_indices(&lines) {
+            let Some(key) = line_key(lines[i]) else { continue };
+            if keys_on_page.insert(key.clone()) {
+                let count = counts.entry(key.clone()).or_default();
+                if *count == 0 {
+                    first_seen.push((key, lines[i].trim().to_string()));
+                }
+                *count += 1;
+            }
+        }
+    }
+
+    let is_boilerplate = |key: &str| {
+        counts
+            .get(key)
+            .is_some_and(|&n| n >= MIN_REPEATS && n as f32 / page_total as f32 > page_ratio)
+    };
+    let stripped: Vec<String> = first_seen
+        .iter()
+        .filter(|(key, _)| is_boilerplate(key))
+        .map(|(_, line)| line.clone())
+        .collect();
+    if stripped.is_empty() {
+        return stripped;
+    }
+
+    for page in pages.iter_mut() {
+        let lines: Vec<&str> = page.text.lines().collect();
+        let edges = edge_indices(&lines);
+        let kept: Vec<&str> = lines
+            .iter()
+            .enumerate()
+            .filter(|(i, line)| {
+                !edges.contains(i) || !line_key(line).is_some_and(|k| is_boilerplate(&k))
+            })
+            .map(|(_, line)| *line)
+            .collect();
+        page.text = join_lines(&kept);
+    }
+    stripped
+}
+
+/// Indices of the first and last `EDGE_LINES` non-blank lines of a page, in order
+fn edge_indices(lines: &[&str]) -> BTreeSet<usize> {
+    let non_blank: Vec<usize> =
+        (0..lines.len()).filter(|&i| !lines[i].trim().is_empty()).collect();
+    let top = non_blank.iter().take(EDGE_LINES);
+    let bottom = non_blank.iter().rev().take(EDGE_LINES);
+    top.chain(bottom).copied().collect()
+}
+
+/// Lowercase words with every number replaced by `#`; `None` for table rows
+/// and lines without letters or digits
+fn line_key(line: &str) -> Option<String> {
+    let line = line.trim();
+    if line.starts_with('|') {
+        return None;
+    }
+
+    let mut key = String::new();
+    for c in line.chars() {
+        if c.is_ascii_digit() {
+            if !key.ends_with('#') {
+                key.push('#');
+            }
+        } else if c.is_alphanumeric() {
+            key.extend(c.to_lowercase());
+        } else if !key.is_empty() && !key.ends_with(' ') {
+            key.push(' ');
+        }
+    }
+    let key = key.trim_end().to_string();
+    (!key.is_empty()).then_some(key)
+}
+
+/// Join lines, keeping one blank line between paragraphs and none at the edges
+fn join_lines(lines: &[&str]) -> String {
+    let mut text = String::new();
+    let mut paragraph_break = false;
+    for line in lines {
+        if line.trim().is_empty() {
+            paragraph_break = true;
+            continue;
+        }
+        if !text.is_empty() {
+            text.push_str(if paragraph_break { "\n\n" } else { "\n" });
+        }
+        text.push_str(line);
+        paragraph_break = false;
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pdf::PdfExtractor;
+    use std::path::PathBuf;
+
+    fn page(page_number: u32, text: &str) -> PageText {
+        PageText {
+            page_number,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_footer_on_every_page_is_stripped() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/footer.pdf");
+        let mut doc = PdfExtractor::new().extract(path).unwrap();
+        assert!(doc.pages.iter().all(|p| p.text.contains("PROPRIETARY AND CONFIDENTIAL")));
+
+        let stripped = strip_boilerplate(&mut doc.pages, DEFAULT_BOILERPLATE_PAGE_RATIO);
+
+        assert_eq!(stripped.len(), 2, "{:?}", stripped);
+        assert!(stripped[0].starts_with("YZF-R1 SERVICE MANUAL"));
+        assert_eq!(stripped[1], "Page 1");
+        let numbers: Vec<u32> = doc.pages.iter().map(|p| p.page_number).collect();
+        assert_eq!(numbers, [1, 2, 3, 4]);
+        assert!(doc.pages[0].text.starts_with("Chain Maintenance\nClean the chain"));
+        assert!(doc
+            .pages
+            .iter()
+            .all(|p| !p.text.contains("CONFIDENTIAL") && !p.text.contains("Page")));
+    }
+
+    #[test]
+    fn test_lines_below_threshold_and_body_text_kept() {
+        let mut pages = vec![
+            page(1, "Honda CB500 Manual\nWarning\nCheck the oil level.\nTorque bolts.\nMore.\n42"),
+            page(2, "Warning\nDrain the oil.\n\nSteps follow.\nEven more.\nLast line.\n43"),
+            page(3, "Fit the filter.\nLine two.\nLine three.\nWarning\nLine five.\nLine six.\n44"),
+            page(4, "Refill the oil.\n\n\nDone.\n45"),
+        ];
+
+        let stripped = strip_boilerplate(&mut pages, 0.75);
+
+        // "Warning" sits in the middle of page 3, so it only counts on 2 of 4 pages
+        assert_eq!(stripped, ["42"]);
+        assert_eq!(
+            pages[1].text,
+            "Warning\nDrain the oil.\n\nSteps follow.\nEven more.\nLast line."
+        );
+        assert_eq!(pages[3].text, "Refill the oil.\n\nDone.");
+        assert!(pages[2].text.contains("Warning"));
+    }
+
+    #[test]
+    fn test_short_documents_and_full_ratio_untouched() {
+        let mut pages = vec![page(1, "Header\nA"), page(2, "Header\nB")];
+        assert!(strip_boilerplate(&mut pages, 0.5).is_empty());
+
+        let mut pages: Vec<_> = (1..=4).map(|n| page(n, "Header\nBody")).collect();
+        assert!(strip_boilerplate(&mut pages, 1.0).is_empty());
+        assert_eq!(pages[0].text, "Header\nBody");
+    }
+
+    #[test]
+    fn test_low_ratio_still_needs_repeated_lines() {
+        let mut pages = vec![
+            page(1, "Chain slack\nKeep 30 mm.\nService guide"),
+            page(2, "Chain slack\nCheck at the midpoint.\nService guide"),
+            page(3, "Brake pads\nReplace below 1 mm.\nService guide"),
+            page(4, "Oil change\nUse 10W-40.\nService guide"),
+            page(5, "Coolant\nFlush every two years.\nService guide"),
+        ];
+
+        let stripped = strip_boilerplate(&mut pages, 0.0);
+
+        // "Chain slack" opens two pages, which is not enough to be a header
+        assert_eq!(stripped, ["Service guide"]);
+        assert_eq!(pages[1].text, "Chain slack\nCheck at the midpoint.");
+    }
+}
@@ -1,9 +1,691 @@
-// Placeholder - will implement text chunking later
+use crate::config::Config;
+use crate::models::{ChunkIdMode, ChunkMetadata, DocumentChunk};
+use crate::pdf::{
+    contains_table, heading_text, is_list_block, is_table_block, PageText, TABLE_CONTENT_TYPE,
+};
+use crate::rag::{count_tokens, tokenizer};
 
-pub struct Chunker;
+/// Splits extracted pages into token-bounded, overlapping chunks
+pub struct Chunker {
+    /// Model whose tokenizer is used for counting
+    model: String,
+
+    /// Maximum tokens per chunk
+    chunk_size: usize,
+
+    /// Tokens repeated from the end of one chunk at the start of the next
+    overlap: usize,
+
+    /// Chunks adding fewer new tokens are merged into the previous chunk
+    merge_below_tokens: usize,
+
+    /// How chunk IDs are assigned
+    id_mode: ChunkIdMode,
+}
+
+/// A sentence (or piece of an oversized sentence) and where it came from
+struct Segment {
+    page_number: u32,
+    /// Markdown heading the segment falls under
+    section: Option<String>,
+    text: String,
+    starts_paragraph: bool,
+    /// Table row or list item, kept on its own line
+    own_line: bool,
+}
+
+/// Chunk being assembled, with the page and section each part starts in
+#[derive(Default, Clone)]
+struct Pending {
+    text: String,
+    /// (byte offset into `text`, page number, section)
+    parts: Vec<(usize, u32, Option<String>)>,
+    has_new_content: bool,
+    /// Bytes at the start of `text` repeated from the previous chunk
+    carried: usize,
+}
+
+impl Pending {
+    fn push(&mut self, separator: &str, segment: &Segment) {
+        if !self.text.is_empty() {
+            self.text.push_str(separator);
+        }
+        self.parts.push((self.text.len(), segment.page_number, segment.section.clone()));
+        self.text.push_str(&segment.text);
+        self.has_new_content = true;
+    }
+
+    fn location_at(&self, offset: usize) -> (u32, Option<String>) {
+        self.parts
+            .iter()
+            .take_while(|(start, _, _)| *start <= offset)
+            .last()
+            .map(|(_, page, section)| (*page, section.clone()))
+            .unwrap_or((1, None))
+    }
+
+    fn start(&self) -> (u32, Option<String>) {
+        self.location_at(0)
+    }
+
+    /// Text this chunk adds after the overlap carried from the previous one
+    fn new_text(&self) -> &str {
+        &self.text[self.carried..]
+    }
+
+    /// Append what `next` adds to this chunk
+    fn absorb(&mut self, next: &Pending) {
+        let offset = self.text.len();
+        if next.carried == 0 {
+            // The separator went with the dropped overlap
+            self.text.push(' ');
+        }
+        let shift = self.text.len() - offset;
+        for (start, page, section) in &next.parts {
+            if *start >= next.carried {
+                self.parts.push((offset + shift + start - next.carried, *page, section.clone()));
+            }
+        }
+        self.text.push_str(next.new_text());
+    }
+
+    /// Split off the text from byte `cut` on as a chunk of its own, starting
+    /// without overlap
+    fn split_off(&mut self, cut: usize) -> Pending {
+        let rest = self.text[cut..].trim_start();
+        let rest_start = self.text.len() - rest.len();
+        let (page, section) = self.location_at(rest_start);
+        let mut parts = vec![(0, page, section)];
+        parts.extend(
+            self.parts
+                .iter()
+                .filter(|(start, _, _)| *start > rest_start)
+                .map(|(start, page, section)| (start - rest_start, *page, section.clone())),
+        );
+        let next = Pending {
+            text: rest.to_string(),
+            parts,
+            has_new_content: true,
+            carried: 0,
+        };
+
+        self.text.truncate(self.text[..cut].trim_end().len());
+        self.parts.retain(|(start, _, _)| *start < cut);
+        next
+    }
+}
 
 impl Chunker {
-    pub fn new() -> Self {
-        Self
+    /// Overlap is capped at half the chunk size so every chunk adds new text
+    pub fn new(model: impl Into<String>, chunk_size: usize, overlap: usize) -> Self {
+        let chunk_size = chunk_size.max(1);
+        Self {
+            model: model.into(),
+            chunk_size,
+            overlap: overlap.min(chunk_size / 2),
+            merge_below_tokens: 0,
+            id_mode: ChunkIdMode::default(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(
+            config.openai_embedding_model.clone(),
+            config.chunk_size_tokens,
+            config.chunk_overlap_tokens,
+        )
+        .with_id_mode(config.chunk_id_mode)
+        .with_merge_below_tokens(config.chunk_merge_below_tokens)
+    }
+
+    pub fn with_id_mode(mut self, id_mode: ChunkIdMode) -> Self {
+        self.id_mode = id_mode;
+        self
+    }
+
+    /// Merge chunks adding fewer than `merge_below_tokens` new tokens into the
+    /// previous chunk; capped at half the chunk size when chunking
+    pub fn with_merge_below_tokens(mut self, merge_below_tokens: usize) -> Self {
+        self.merge_below_tokens = merge_below_tokens;
+        self
+    }
+
+    /// Same model, ID mode and merge threshold with another chunk size and overlap
+    pub fn resized(&self, chunk_size: usize, overlap: usize) -> Self {
+        Self::new(self.model.clone(), chunk_size, overlap)
+            .with_id_mode(self.id_mode)
+            .with_merge_below_tokens(self.merge_below_tokens)
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    pub fn overlap(&self) -> usize {
+        self.overlap
+    }
+
+    /// Split pages into chunks, preferring paragraph and sentence boundaries.
+    /// Each chunk records the page it starts on and its index in the document.
+    pub fn chunk(
+        &self,
+        document_id: &str,
+        pages: &[PageText],
+        metadata: &ChunkMetadata,
+    ) -> Vec<DocumentChunk> {
+        let texts = self
+            .chunk_texts(pages)
+            .into_iter()
+            .map(|(page, section, text)| (None, page, section, text));
+        self.build_chunks(document_id, texts, metadata)
+    }
+
+    /// Chunk a multi-volume manual set as one document. Chunks never span
+    /// volumes; each records its 1-based volume and its index across the set.
+    pub fn chunk_volumes(
+        &self,
+        document_id: &str,
+        volumes: &[Vec<PageText>],
+        metadata: &ChunkMetadata,
+    ) -> Vec<DocumentChunk> {
+        let texts = volumes.iter().zip(1..).flat_map(|(pages, volume)| {
+            self.chunk_texts(pages)
+                .into_iter()
+                .map(move |(page, section, text)| (Some(volume), page, section, text))
+        });
+        self.build_chunks(document_id, texts, metadata)
+    }
+
+    /// Number chunks 0.. again after some were dropped, so indexes stay
+    /// contiguous; deterministic ids are recomputed to match
+    pub fn reindex(&self, chunks: Vec<DocumentChunk>) -> Vec<DocumentChunk> {
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(chunk_index, mut chunk)| {
+                chunk.metadata.chunk_index = chunk_index;
+                chunk.with_id_mode(self.id_mode)
+            })
+            .collect()
+    }
+
+    fn build_chunks(
+        &self,
+        document_id: &str,
+        texts: impl Iterator<Item = (Option<u32>, u32, Option<String>, String)>,
+        metadata: &ChunkMetadata,
+    ) -> Vec<DocumentChunk> {
+        texts
+            .enumerate()
+            .map(|(chunk_index, (volume, page_number, section, text))| {
+                let mut chunk_metadata = metadata.clone();
+                chunk_metadata.page_number = Some(page_number);
+                chunk_metadata.volume = volume;
+                chunk_metadata.section = section;
+                chunk_metadata.chunk_index = chunk_index;
+                if contains_table(&text) {
+                    chunk_metadata.content_type = Some(TABLE_CONTENT_TYPE.to_string());
+                }
+                DocumentChunk::new(document_id, text, chunk_metadata).with_id_mode(self.id_mode)
+            })
+            .collect()
+    }
+
+    /// Chunk texts with the page and section each starts in
+    fn chunk_texts(&self, pages: &[PageText]) -> Vec<(u32, Option<String>, String)> {
+        let mut chunks: Vec<Pending> = Vec::new();
+        let mut pending = Pending::default();
+
+        for segment in self.segments(pages) {
+            let separator = if segment.starts_paragraph {
+                "\n\n"
+            } else if segment.own_line {
+                "\n"
+            } else {
+                " "
+            };
+
+            if pending.has_new_content {
+                let candidate = format!("{}{}{}", pending.text, separator, segment.text);
+                if self.tokens(&candidate) > self.chunk_size {
+                    let finished = std::mem::take(&mut pending);
+                    pending = self.carry_overlap(&finished);
+                    chunks.push(finished);
+                }
+            }
+
+            if !pending.text.is_empty() {
+                // The carried overlap and the next segment can tokenize a little
+                // differently when joined; drop the overlap rather than overflow
+                let candidate = format!("{}{}{}", pending.text, separator, segment.text);
+                if self.tokens(&candidate) > self.chunk_size {
+                    pending = Pending::default();
+                }
+            }
+
+            pending.push(separator, &segment);
+        }
+
+        if pending.has_new_content {
+            chunks.push(pending);
+        }
+        self.merge_small(chunks)
+            .into_iter()
+            .map(|chunk| {
+                let (page, section) = chunk.start();
+                (page, section, chunk.text)
+            })
+            .collect()
+    }
+
+    /// Merge chunks adding fewer than `merge_below_tokens` new tokens (a section's
+    /// last sentence, a chunk cut short by a long segment) into the previous
+    /// one. When the result is over the chunk size it is split again near its
+    /// middle, so both halves carry a useful amount of text.
+    fn merge_small(&self, chunks: Vec<Pending>) -> Vec<Pending> {
+        let min_tokens = self.merge_below_tokens.min(self.chunk_size / 2);
+        if min_tokens == 0 {
+            return chunks;
+        }
+
+        let mut merged: Vec<Pending> = Vec::with_capacity(chunks.len());
+        for chunk in chunks {
+            let Some(previous) = merged.last_mut() else {
+                merged.push(chunk);
+                continue;
+            };
+            if self.tokens(chunk.new_text()) >= min_tokens {
+                merged.push(chunk);
+                continue;
+            }
+
+            let mut combined = previous.clone();
+            combined.absorb(&chunk);
+            if self.tokens(&combined.text) <= self.chunk_size {
+                *previous = combined;
+                continue;
+            }
+            match self.rebalance(&mut combined) {
+                Some(rest) => {
+                    *previous = combined;
+                    merged.push(rest);
+                }
+                // No cut keeps both halves within the chunk size
+                None => merged.push(chunk),
+            }
+        }
+        merged
+    }
+
+    /// Split `chunk` at the word boundary nearest the middle of its tokens,
+    /// returning the second half; `None` when a half would exceed the size
+    fn rebalance(&self, chunk: &mut Pending) -> Option<Pending> {
+        let half = self.tokens(&chunk.text) / 2;
+        let tail = self.token_tail(&chunk.text, half);
+        let from = chunk.text.len() - tail.len();
+        let cut = from + chunk.text[from..].find(char::is_whitespace)?;
+
+        let head = chunk.text[..cut].trim_end();
+        let rest = chunk.text[cut..].trim_start();
+        if head.is_empty()
+            || rest.is_empty()
+            || self.tokens(head) > self.chunk_size
+            || self.tokens(rest) > self.chunk_size
+        {
+            return None;
+        }
+        Some(chunk.split_off(cut))
+    }
+
+    /// Token count of `text` under the chunker's model
+    pub fn tokens(&self, text: &str) -> usize {
+        count_tokens(&self.model, text)
+    }
+
+    /// Start the next chunk with the last `overlap` tokens of the finished one
+    fn carry_overlap(&self, finished: &Pending) -> Pending {
+        let tail = self.token_tail(&finished.text, self.overlap);
+        let tail = tail.trim_start();
+        if tail.is_empty() {
+            return Pending::default();
+        }
+
+        let offset = finished.text.len() - tail.len();
+        let (page, section) = finished.location_at(offset);
+        Pending {
+            text: tail.to_string(),
+            parts: vec![(0, page, section)],
+            has_new_content: false,
+            carried: tail.len(),
+        }
+    }
+
+    /// Suffix of `text` made of at most `max_tokens` tokens
+    fn token_tail(&self, text: &str, max_tokens: usize) -> String {
+        if max_tokens == 0 {
+            return String::new();
+        }
+
+        let bpe = tokenizer(&self.model);
+        let tokens = bpe.encode_with_special_tokens(text);
+        let mut start = tokens.len().saturating_sub(max_tokens);
+
+        // A cut can land inside a multi-byte character; move forward until it decodes
+        while start < tokens.len() {
+            if let Ok(tail) = bpe.decode(tokens[start..].to_vec()) {
+                if text.ends_with(&tail) {
+                    return tail;
+                }
+            }
+            start += 1;
+        }
+
+        String::new()
+    }
+
+    /// Break pages into sentence segments small enough to fit beside the overlap.
+    /// A paragraph that is a single Markdown heading opens a new section.
+    fn segments(&self, pages: &[PageText]) -> Vec<Segment> {
+        let budget = self.chunk_size - self.overlap;
+        let mut segments = Vec::new();
+        let mut section: Option<String> = None;
+
+        for page in pages.iter().filter(|p| !p.is_empty()) {
+            for paragraph in page.text.split("\n\n") {
+                if !paragraph.trim().contains('\n') {
+                    if let Some(heading) = heading_text(paragraph) {
+                        section = Some(heading.to_string());
+                    }
+                }
+
+                if is_table_block(paragraph) || is_list_block(paragraph) {
+                    // Rows stay whole and on their own lines so values keep their labels
+                    for (i, row) in paragraph.lines().filter(|l| !l.trim().is_empty()).enumerate() {
+                        for piece in self.split_oversized(row.trim(), budget) {
+                            segments.push(Segment {
+                                page_number: page.page_number,
+                                section: section.clone(),
+                                text: piece,
+                                starts_paragraph: i == 0,
+                                own_line: true,
+                            });
+                        }
+                    }
+                    continue;
+                }
+
+                let paragraph = paragraph.split_whitespace().collect::<Vec<_>>().join(" ");
+                let mut starts_paragraph = true;
+
+                for sentence in split_sentences(&paragraph) {
+                    for piece in self.split_oversized(sentence, budget) {
+                        segments.push(Segment {
+                            page_number: page.page_number,
+                            section: section.clone(),
+                            text: piece,
+                            starts_paragraph,
+                            own_line: false,
+                        });
+                        starts_paragraph = false;
+                    }
+                }
+            }
+        }
+
+        segments
+    }
+
+    /// Split a sentence longer than `budget` tokens at word boundaries
+    fn split_oversized(&self, sentence: &str, budget: usize) -> Vec<String> {
+        if self.tokens(sentence) <= budget {
+            return vec![sentence.to_string()];
+        }
+
+        let mut pieces = Vec::new();
+        let mut current = String::new();
+
+        for word in sentence.split(' ') {
+            let candidate = if current.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", current, word)
+            };
+
+            if self.tokens(&candidate) <= budget {
+                current = candidate;
+            } else if current.is_empty() {
+                // A single word longer than the budget: cut it by tokens
+                pieces.extend(self.split_by_tokens(word, budget));
+            } else {
+                pieces.push(std::mem::take(&mut current));
+                if self.tokens(word) <= budget {
+                    current = word.to_string();
+                } else {
+                    pieces.extend(self.split_by_tokens(word, budget));
+                }
+            }
+        }
+
+        if !current.is_empty() {
+            pieces.push(current);
+        }
+        pieces
+    }
+
+    fn split_by_tokens(&self, text: &str, budget: usize) -> Vec<String> {
+        let bpe = tokenizer(&self.model);
+        let tokens = bpe.encode_with_special_tokens(text);
+        tokens
+            .chunks(budget.max(1))
+            .filter_map(|window| bpe.decode(window.to_vec()).ok())
+            .collect()
+    }
+}
+
+/// Split text after sentence-ending punctuation followed by whitespace
+fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((_, c)) = chars.next() {
+        if matches!(c, '.' | '!' | '?') {
+            if let Some(&(next, ' ')) = chars.peek() {
+                sentences.push(&text[start..next]);
+                start = next + 1;
+            }
+        }
+    }
+
+    if start < text.len() {
+        sentences.push(&text[start..]);
+    }
+    sentences.into_iter().filter(|s| !s.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: &str = "text-embedding-3-small";
+
+    fn page(page_number: u32, text: &str) -> PageText {
+        PageText {
+            page_number,
+            text: text.to_string(),
+        }
+    }
+
+    fn sentences(count: usize) -> String {
+        (0..count)
+            .map(|i| format!("Step {} is to check the torque on bolt number {}.", i, i))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn test_small_page_is_single_chunk() {
+        let chunker = Chunker::new(MODEL, 512, 50);
+        let metadata = ChunkMetadata::new("Honda CBR600RR");
+
+        let chunks = chunker.chunk("doc-1", &[page(4, "Check the brake pads.")], &metadata);
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].text, "Check the brake pads.");
+        assert_eq!(chunks[0].metadata.page_number, Some(4));
+        assert_eq!(chunks[0].metadata.chunk_index, 0);
+        assert_eq!(chunks[0].metadata.bike_model, "Honda CBR600RR");
+    }
+
+    #[test]
+    fn test_chunks_respect_size_and_overlap() {
+        let chunker = Chunker::new(MODEL, 60, 15);
+        let metadata = ChunkMetadata::new("Yamaha R1");
+
+        let chunks = chunker.chunk("doc-1", &[page(1, &sentences(20))], &metadata);
+
+        assert!(chunks.len() > 2);
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert!(count_tokens(MODEL, &chunk.text) <= 60);
+            assert_eq!(chunk.metadata.chunk_index, i);
+        }
+        for pair in chunks.windows(2) {
+            // The next chunk opens with the last tokens of the previous one
+            let tail = chunker.token_tail(&pair[0].text, 15);
+            let overlap = tail.trim_start();
+            assert!(!overlap.is_empty());
+            assert!(count_tokens(MODEL, overlap) <= 15);
+            assert!(pair[1].text.starts_with(overlap));
+            assert!(pair[1].text.len() > overlap.len());
+        }
+        // Whole sentences are kept together where possible
+        assert!(chunks[0].text.ends_with('.'));
+    }
+
+    #[test]
+    fn test_small_tail_merged_into_previous_chunk() {
+        let metadata = ChunkMetadata::new("Yamaha R1");
+        let tail = "Finally torque the axle nut.";
+        let pages = [page(1, &sentences(4)), page(2, tail)];
+        let chunker = Chunker::new(MODEL, 60, 15);
+        let chunks = chunker.chunk("doc-1", &pages, &metadata);
+        assert_eq!(chunks.len(), 2);
+        // Only the overlap and the tail
+        let overlap = chunker.token_tail(&chunks[0].text, 15);
+        assert_eq!(chunks[1].text, format!("{}\n\n{}", overlap.trim_start(), tail));
+
+        // Merged, and too big for one chunk: split again near the middle
+        let chunker = chunker.with_merge_below_tokens(20);
+        let chunks = chunker.chunk("doc-1", &pages, &metadata);
+        assert_eq!(chunks.len(), 2);
+        for chunk in &chunks {
+            let tokens = count_tokens(MODEL, &chunk.text);
+            assert!((20..=60).contains(&tokens), "{} tokens: {}", tokens, chunk.text);
+        }
+        assert!(chunks[1].text.ends_with(tail));
+        assert_eq!(chunks[1].metadata.page_number, Some(1));
+        assert_eq!(chunks[1].metadata.chunk_index, 1);
+        // Nothing lost, nothing repeated
+        let words = |text: &str| text.split_whitespace().count();
+        let source = words(&sentences(4)) + words(tail);
+        assert_eq!(words(&chunks[0].text) + words(&chunks[1].text), source);
+    }
+
+    #[test]
+    fn test_oversized_paragraph_is_split() {
+        let chunker = Chunker::new(MODEL, 40, 0);
+        let metadata = ChunkMetadata::new("Yamaha R1");
+        let run_on = "tighten the bolt and ".repeat(60);
+
+        let chunks = chunker.chunk("doc-1", &[page(2, &run_on)], &metadata);
+
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(count_tokens(MODEL, &chunk.text) <= 40);
+            assert_eq!(chunk.metadata.page_number, Some(2));
+        }
+    }
+
+    #[test]
+    fn test_deterministic_ids_are_stable_across_runs() {
+        let metadata = ChunkMetadata::new("Yamaha R1");
+        let pages = [page(1, &sentences(20))];
+        let ids = |chunker: &Chunker| {
+            chunker
+                .chunk("doc-1", &pages, &metadata)
+                .into_iter()
+                .map(|c| c.id)
+                .collect::<Vec<_>>()
+        };
+
+        let deterministic = Chunker::new(MODEL, 60, 15).with_id_mode(ChunkIdMode::Deterministic);
+        assert_eq!(ids(&deterministic), ids(&deterministic));
+
+        let random = Chunker::new(MODEL, 60, 15);
+        assert_ne!(ids(&random), ids(&random));
+    }
+
+    #[test]
+    fn test_chunk_spanning_pages_records_starting_page() {
+        let chunker = Chunker::new(MODEL, 512, 0);
+        let metadata = ChunkMetadata::new("Yamaha R1");
+        let pages = [page(3, "Remove the wheel."), page(4, "Replace the tire.")];
+
+        let chunks = chunker.chunk("doc-1", &pages, &metadata);
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].text, "Remove the wheel.\n\nReplace the tire.");
+        assert_eq!(chunks[0].metadata.page_number, Some(3));
+    }
+
+    #[test]
+    fn test_volumes_are_chunked_separately_with_global_indexes() {
+        let chunker = Chunker::new(MODEL, 512, 0);
+        let metadata = ChunkMetadata::new("Yamaha R1");
+        let volumes = [
+            vec![page(1, "Engine: check the valve clearance.")],
+            vec![page(1, "Electrical: test the stator.")],
+        ];
+
+        let chunks = chunker.chunk_volumes("doc-1", &volumes, &metadata);
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].metadata.volume, Some(1));
+        assert_eq!(chunks[1].metadata.volume, Some(2));
+        assert_eq!(chunks[1].metadata.page_number, Some(1));
+        assert_eq!(chunks[1].metadata.chunk_index, 1);
+    }
+
+    #[test]
+    fn test_table_rows_kept_on_lines_and_tagged() {
+        let chunker = Chunker::new(MODEL, 512, 0);
+        let metadata = ChunkMetadata::new("Yamaha R1");
+        let text = "Torque Specifications\n\n| Fastener | Torque |\n| --- | --- |\n\
+                    | Rear axle nut | 90 Nm |\n\nUse a torque wrench.";
+
+        let chunks = chunker.chunk("doc-1", &[page(1, text)], &metadata);
+
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].text.contains("\n| Rear axle nut | 90 Nm |"));
+        assert_eq!(chunks[0].metadata.content_type.as_deref(), Some(TABLE_CONTENT_TYPE));
+
+        let prose = chunker.chunk("doc-1", &[page(1, "Use a torque wrench.")], &metadata);
+        assert_eq!(prose[0].metadata.content_type, None);
+    }
+
+    #[test]
+    fn test_markdown_headings_set_sections_and_lists_keep_lines() {
+        let chunker = Chunker::new(MODEL, 512, 0);
+        let metadata = ChunkMetadata::new("Yamaha R1");
+        let text = "# Chain Care\n\nKeep it clean.\n\n- Check slack\n- Tighten the nut";
+
+        let chunks = chunker.chunk("doc-1", &[page(1, text)], &metadata);
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].metadata.section.as_deref(), Some("Chain Care"));
+        assert!(chunks[0].text.ends_with("Keep it clean.\n\n- Check slack\n- Tighten the nut"));
+
+        let untitled = chunker.chunk("doc-1", &[page(1, "Keep it clean.")], &metadata);
+        assert_eq!(untitled[0].metadata.section, None);
     }
 }
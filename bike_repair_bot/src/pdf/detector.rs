@@ -0,0 +1,223 @@
+use chrono::Datelike;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::pdf::ExtractedDocument;
+
+/// Pages of front matter searched for the bike model and year
+pub const DETECTION_PAGES: usize = 3;
+
+/// Known models: (canonical name, manufacturer, designations printed in manuals)
+pub const KNOWN_MODELS: &[(&str, &str, &[&str])] = &[
+    ("Honda CBR600RR", "Honda", &["CBR600RR", "CBR 600RR"]),
+    ("Honda CBR1000RR", "Honda", &["CBR1000RR", "Fireblade"]),
+    ("Honda CRF450R", "Honda", &["CRF450R"]),
+    ("Yamaha R1", "Yamaha", &["YZF-R1", "YZF R1"]),
+    ("Yamaha R6", "Yamaha", &["YZF-R6", "YZF R6"]),
+    ("Yamaha MT-07", "Yamaha", &["MT-07", "FZ-07"]),
+    ("Kawasaki Ninja ZX-10R", "Kawasaki", &["ZX-10R", "ZX10R"]),
+    ("Kawasaki Ninja 400", "Kawasaki", &["Ninja 400", "EX400"]),
+    ("Suzuki GSX-R750", "Suzuki", &["GSX-R750", "GSXR750"]),
+    ("Suzuki V-Strom 650", "Suzuki", &["V-Strom 650", "DL650"]),
+    ("Ducati Panigale V4", "Ducati", &["Panigale V4"]),
+    ("Ducati Monster", "Ducati", &["Monster 821", "Monster 1200", "Monster"]),
+    ("BMW R1250GS", "BMW", &["R1250GS", "R 1250 GS"]),
+    ("KTM 390 Duke", "KTM", &["390 Duke"]),
+    ("Triumph Street Triple", "Triumph", &["Street Triple"]),
+    ("Harley-Davidson Sportster", "Harley-Davidson", &["Sportster", "XL883", "XL1200"]),
+];
+
+/// Confidence contributed by a designation found in the PDF title
+const TITLE_MATCH: f32 = 0.6;
+/// Confidence contributed by a designation found in the front-matter text
+const TEXT_MATCH: f32 = 0.5;
+/// Extra confidence when the manufacturer is named as well
+const BRAND_MATCH: f32 = 0.35;
+/// Penalty when another model scores just as well
+const AMBIGUITY_PENALTY: f32 = 0.25;
+
+/// Outcome of guessing a manual's bike model and year
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct ModelDetection {
+    /// Detected model, set only when `confidence` reached the threshold
+    pub bike_model: Option<String>,
+
+    /// Best guess regardless of confidence, for the admin to confirm
+    pub candidate: Option<String>,
+
+    /// Model year found in the title or front matter
+    pub year: Option<u32>,
+
+    /// 0.0 (no idea) to 1.0 (certain)
+    pub confidence: f32,
+
+    /// True when the model was left unknown and should be set via PATCH
+    pub needs_review: bool,
+}
+
+/// Guesses bike model and year from a manual's title and first pages
+pub struct ModelDetector {
+    min_confidence: f32,
+}
+
+impl ModelDetector {
+    pub fn new(min_confidence: f32) -> Self {
+        Self { min_confidence }
+    }
+
+    pub fn detect(&self, document: &ExtractedDocument) -> ModelDetection {
+        let title = document.title.as_deref().unwrap_or("");
+        let front_matter = document
+            .pages
+            .iter()
+            .take(DETECTION_PAGES)
+            .map(|p| p.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.detect_in(title, &front_matter)
+    }
+
+    /// Bike model and year a user names in a chat query. The query counts as
+    /// much as a manual title: "my 2014 Street Triple" is enough.
+    pub fn detect_in_query(&self, query: &str) -> ModelDetection {
+        self.detect_in(query, "")
+    }
+
+    fn detect_in(&self, title: &str, front_matter: &str) -> ModelDetection {
+        let title_words = words(title);
+        let text_words = words(front_matter);
+
+        let mut scores: Vec<(&str, f32)> = KNOWN_MODELS
+            .iter()
+            .filter_map(|(name, brand, designations)| {
+                let found_in = |haystack: &[String]| {
+                    designations.iter().any(|d| contains_phrase(haystack, &words(d)))
+                };
+                let mut score = if found_in(&title_words) {
+                    TITLE_MATCH
+                } else if found_in(&text_words) {
+                    TEXT_MATCH
+                } else {
+                    return None;
+                };
+                let brand = words(brand);
+                if contains_phrase(&title_words, &brand) || contains_phrase(&text_words, &brand) {
+                    score += BRAND_MATCH;
+                }
+                Some((*name, score))
+            })
+            .collect();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let (candidate, confidence) = match scores.as_slice() {
+            [] => (None, 0.0),
+            [(name, score)] => (Some(name.to_string()), *score),
+            [(name, score), (_, runner_up), ..] => {
+                let penalty = if runner_up >= score { AMBIGUITY_PENALTY } else { 0.0 };
+                (Some(name.to_string()), score - penalty)
+            }
+        };
+        let confidence = confidence.min(1.0);
+        let accepted = confidence >= self.min_confidence;
+
+        ModelDetection {
+            bike_model: candidate.clone().filter(|_| accepted),
+            candidate,
+            year: find_year(title).or_else(|| find_year(front_matter)),
+            confidence,
+            needs_review: !accepted,
+        }
+    }
+}
+
+/// Lowercased alphanumeric words, with hyphens dropped so "YZF-R1" matches "yzfr1"
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '-'))
+        .map(|w| w.replace('-', "").to_lowercase())
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+fn contains_phrase(haystack: &[String], phrase: &[String]) -> bool {
+    !phrase.is_empty() && haystack.windows(phrase.len()).any(|w| w == phrase)
+}
+
+/// First standalone four-digit number that is a plausible model year
+fn find_year(text: &str) -> Option<u32> {
+    let latest = chrono::Utc::now().year() as u32 + 1;
+    text.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| w.len() == 4 && w.bytes().all(|b| b.is_ascii_digit()))
+        .filter_map(|w| w.parse().ok())
+        .find(|year| (1950..=latest).contains(year))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pdf::PageText;
+
+    fn document(title: Option<&str>, pages: &[&str]) -> ExtractedDocument {
+        ExtractedDocument {
+            pages: pages
+                .iter()
+                .enumerate()
+                .map(|(i, text)| PageText {
+                    page_number: i as u32 + 1,
+                    text: text.to_string(),
+                })
+                .collect(),
+            page_count: pages.len(),
+            title: title.map(String::from),
+            skipped_pages: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_detects_model_and_year_from_front_matter() {
+        let detector = ModelDetector::new(0.6);
+        let doc = document(
+            Some("CBR600RR Service Manual"),
+            &["Honda Motor Co., Ltd.", "Model year 2007. Part no. 61MFJ00"],
+        );
+
+        let detection = detector.detect(&doc);
+
+        assert_eq!(detection.bike_model.as_deref(), Some("Honda CBR600RR"));
+        assert_eq!(detection.year, Some(2007));
+        assert!(!detection.needs_review);
+    }
+
+    #[test]
+    fn test_weak_match_needs_review() {
+        let detector = ModelDetector::new(0.6);
+
+        // Designation without manufacturer, only in body text
+        let weak = detector.detect(&document(None, &["Fits the Sportster frame."]));
+        assert_eq!(weak.bike_model, None);
+        assert_eq!(weak.candidate.as_deref(), Some("Harley-Davidson Sportster"));
+        assert!(weak.needs_review);
+
+        let none = detector.detect(&document(Some("Owner's Manual"), &["Honda"]));
+        assert_eq!(none.candidate, None);
+        assert_eq!(none.confidence, 0.0);
+        assert!(none.needs_review);
+    }
+
+    #[test]
+    fn test_detects_model_named_in_query() {
+        let detector = ModelDetector::new(0.6);
+
+        let named = detector.detect_in_query("Chain keeps slipping on my 2014 Street Triple");
+        assert_eq!(named.bike_model.as_deref(), Some("Triumph Street Triple"));
+        assert_eq!(named.year, Some(2014));
+
+        let none = detector.detect_in_query("How do I adjust the chain slack?");
+        assert_eq!(none.bike_model, None);
+    }
+
+    #[test]
+    fn test_find_year_skips_part_numbers_and_torque_values() {
+        assert_eq!(find_year("Torque 9999 Nm, part 1234-5678, edition 2019"), Some(2019));
+        assert_eq!(find_year("12345 Nm"), None);
+    }
+}
@@ -1,9 +1,469 @@
-// Placeholder - will implement PDF extraction later
+use lopdf::encryption::DecryptionError;
+use lopdf::Document as PdfDocument;
+use serde::{Deserialize, Serialize};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use thiserror::Error;
 
+use crate::pdf::{extract_page_with_tables, PageRanges};
+
+/// Newest PDF version (major) whose syntax the extractor understands
+const MAX_PDF_MAJOR_VERSION: u32 = 2;
+
+/// Share of a page's characters that must be readable for its text to be kept
+const MIN_READABLE_RATIO: f32 = 0.7;
+
+/// Letters and digits a page needs to count as having text
+pub const MIN_PAGE_TEXT_CHARS: usize = 16;
+
+/// Share of textless pages from which a PDF is treated as scanned
+pub const DEFAULT_IMAGE_ONLY_PAGE_RATIO: f32 = 0.8;
+
+/// Errors raised while pulling text out of a document
+#[derive(Debug, Error)]
+pub enum ExtractError {
+    #[error("failed to read PDF: {0}")]
+    Io(#[from] std::io::Error),
+    /// Encrypted with a scheme that cannot be opened
+    #[error("PDF is encrypted")]
+    Encrypted,
+    /// Encrypted with a user password, which uploads cannot supply
+    #[error("PDF is protected by a password")]
+    PasswordRequired,
+    /// The file, or a page when `page` is set, could not be parsed
+    #[error("malformed PDF{}: {reason}", on_page(page))]
+    Malformed { page: Option<u32>, reason: String },
+    #[error("unsupported PDF version {0}")]
+    UnsupportedVersion(String),
+    /// Every page is empty, e.g. a scanned manual without a text layer
+    #[error("document contains no extractable text")]
+    NoTextContent,
+    /// Most pages have no text layer, e.g. a scanned manual; it needs OCR first
+    #[error(
+        "{pages_without_text} of {page_count} pages have no extractable text; the PDF looks \
+         scanned (image-only). Run it through OCR (e.g. `ocrmypdf`) and upload it again"
+    )]
+    ImageOnly {
+        pages_without_text: usize,
+        page_count: usize,
+    },
+}
+
+/// ` (page N)` for errors tied to a page
+fn on_page(page: &Option<u32>) -> String {
+    page.map(|p| format!(" (page {})", p)).unwrap_or_default()
+}
+
+impl ExtractError {
+    fn malformed(reason: impl ToString) -> Self {
+        ExtractError::Malformed {
+            page: None,
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Error code reported to API clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            ExtractError::Io(_) => "FILE_UNREADABLE",
+            ExtractError::Encrypted | ExtractError::PasswordRequired => "PDF_ENCRYPTED",
+            ExtractError::Malformed { .. } | ExtractError::UnsupportedVersion(_) => {
+                "PDF_MALFORMED"
+            }
+            ExtractError::NoTextContent => "PDF_NO_TEXT",
+            ExtractError::ImageOnly { .. } => "PDF_IMAGE_ONLY",
+        }
+    }
+}
+
+/// Normalized text of a single page (1-based page number)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PageText {
+    pub page_number: u32,
+    pub text: String,
+}
+
+impl PageText {
+    /// True for pages with no extractable text (e.g. scanned images)
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// True for empty pages and pages with only a few stray characters,
+    /// such as a page number stamped over a scanned image
+    pub fn has_little_text(&self) -> bool {
+        let mut alphanumeric = self.text.chars().filter(|c| c.is_alphanumeric());
+        alphanumeric.nth(MIN_PAGE_TEXT_CHARS - 1).is_none()
+    }
+}
+
+/// Per-page text of a whole PDF
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractedDocument {
+    pub pages: Vec<PageText>,
+    pub page_count: usize,
+
+    /// Title from the PDF document information dictionary, if set
+    pub title: Option<String>,
+
+    /// Pages left out of `pages` because their content could not be parsed
+    pub skipped_pages: Vec<u32>,
+}
+
+impl ExtractedDocument {
+    /// Page numbers that produced no text
+    pub fn empty_pages(&self) -> Vec<u32> {
+        self.pages
+            .iter()
+            .filter(|p| p.is_empty())
+            .map(|p| p.page_number)
+            .collect()
+    }
+}
+
+#[derive(Default)]
 pub struct PdfExtractor;
 
 impl PdfExtractor {
     pub fn new() -> Self {
         Self
     }
+
+    /// Extract per-page text from a PDF on disk
+    pub fn extract(&self, path: impl AsRef<Path>) -> Result<ExtractedDocument, ExtractError> {
+        let bytes = std::fs::read(path)?;
+        self.extract_bytes(&bytes)
+    }
+
+    /// Extract per-page text from an in-memory PDF
+    pub fn extract_bytes(&self, bytes: &[u8]) -> Result<ExtractedDocument, ExtractError> {
+        self.extract_pages(bytes, usize::MAX)
+    }
+
+    /// Check that a PDF can be opened (parses, is not password protected, is a
+    /// supported version) without extracting any text; returns its page count
+    pub fn check(&self, bytes: &[u8]) -> Result<u32, ExtractError> {
+        self.open(bytes).map(|document| document.get_pages().len() as u32)
+    }
+
+    /// Extract only the first `max_pages` pages; `page_count` still reports the total.
+    /// Pages whose content cannot be parsed are skipped and listed in
+    /// `skipped_pages`; the document fails only when no page can be read.
+    pub fn extract_pages(
+        &self,
+        bytes: &[u8],
+        max_pages: usize,
+    ) -> Result<ExtractedDocument, ExtractError> {
+        self.extract_where(bytes, max_pages, |_| true)
+    }
+
+    /// Extract only the pages in `ranges`, keeping their original page numbers;
+    /// the others are never parsed
+    pub fn extract_ranges(
+        &self,
+        bytes: &[u8],
+        ranges: &PageRanges,
+    ) -> Result<ExtractedDocument, ExtractError> {
+        self.extract_where(bytes, usize::MAX, |page| ranges.contains(page))
+    }
+
+    fn extract_where(
+        &self,
+        bytes: &[u8],
+        max_pages: usize,
+        selected: impl Fn(u32) -> bool,
+    ) -> Result<ExtractedDocument, ExtractError> {
+        let document = self.open(bytes)?;
+
+        let page_numbers = document.get_pages();
+        let mut pages = Vec::new();
+        let mut skipped_pages = Vec::new();
+        let mut first_error = None;
+        let wanted = page_numbers.iter().take(max_pages).filter(|(&n, _)| selected(n));
+        for (&page_number, &page_id) in wanted {
+            match extract_page(&document, page_number, page_id) {
+                Ok(text) if is_garbled(&text) => {
+                    // Fonts without a usable encoding extract as control or private-use glyphs
+                    log::warn!("Page {} has no readable text, treating it as empty", page_number);
+                    pages.push(PageText { page_number, text: String::new() });
+                }
+                Ok(text) => pages.push(PageText { page_number, text }),
+                Err(reason) => {
+                    log::warn!("Skipping unparseable page {}: {}", page_number, reason);
+                    skipped_pages.push(page_number);
+                    first_error.get_or_insert(ExtractError::Malformed {
+                        page: Some(page_number),
+                        reason,
+                    });
+                }
+            }
+        }
+        if pages.is_empty() {
+            if let Some(error) = first_error {
+                return Err(error);
+            }
+        }
+
+        Ok(ExtractedDocument {
+            page_count: page_numbers.len(),
+            pages,
+            title: document_title(&document),
+            skipped_pages,
+        })
+    }
+
+    /// Parse a PDF, decrypting it when it only has an owner password
+    fn open(&self, bytes: &[u8]) -> Result<PdfDocument, ExtractError> {
+        let mut document = catch_unwind(|| PdfDocument::load_mem(bytes))
+            .map_err(|_| ExtractError::malformed("parser crashed"))?
+            .map_err(ExtractError::malformed)?;
+
+        if !is_supported_version(&document.version) {
+            return Err(ExtractError::UnsupportedVersion(document.version));
+        }
+
+        // An empty user password opens the file for reading; any other needs a password
+        // lopdf panics on some malformed encryption dictionaries
+        if document.is_encrypted() {
+            match catch_unwind(AssertUnwindSafe(|| document.decrypt(""))) {
+                Ok(Ok(())) => log::debug!("Opened encrypted PDF with an empty user password"),
+                Ok(Err(lopdf::Error::Decryption(DecryptionError::IncorrectPassword))) => {
+                    return Err(ExtractError::PasswordRequired)
+                }
+                Ok(Err(_)) | Err(_) => return Err(ExtractError::Encrypted),
+            }
+        }
+        Ok(document)
+    }
+}
+
+/// Text of one page, or why it could not be read. Parser panics on broken
+/// content streams are caught so one bad page does not sink the document.
+fn extract_page(
+    document: &PdfDocument,
+    page_number: u32,
+    page_id: lopdf::ObjectId,
+) -> Result<String, String> {
+    catch_unwind(AssertUnwindSafe(|| {
+        // Pages with column-aligned tables are laid out from text positions
+        if let Some(text) = extract_page_with_tables(document, page_id) {
+            return Ok(text);
+        }
+        document
+            .extract_text(&[page_number])
+            .map(|raw| normalize_text(&raw))
+            .map_err(|e| e.to_string())
+    }))
+    .unwrap_or_else(|_| Err("parser crashed".to_string()))
+}
+
+/// True when too few characters of `text` are readable: control characters,
+/// replacement characters and private-use glyphs are what fonts without a
+/// Unicode mapping extract as
+fn is_garbled(text: &str) -> bool {
+    let (mut total, mut unreadable) = (0usize, 0usize);
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        total += 1;
+        let private_use = ('\u{E000}'..='\u{F8FF}').contains(&c);
+        if c.is_control() || c == char::REPLACEMENT_CHARACTER || private_use {
+            unreadable += 1;
+        }
+    }
+    total > 0 && (total - unreadable) as f32 / (total as f32) < MIN_READABLE_RATIO
+}
+
+/// `1.x` and `2.x` versions; anything newer may use syntax we cannot parse
+fn is_supported_version(version: &str) -> bool {
+    version
+        .split('.')
+        .next()
+        .and_then(|major| major.trim().parse::<u32>().ok())
+        .is_some_and(|major| (1..=MAX_PDF_MAJOR_VERSION).contains(&major))
+}
+
+/// Read `/Info /Title`, decoding UTF-16BE strings marked with a byte order mark
+fn document_title(document: &PdfDocument) -> Option<String> {
+    let info = document.trailer.get(b"Info").ok()?;
+    let (_, info) = document.dereference(info).ok()?;
+    let (_, title) = document.dereference(info.as_dict().ok()?.get(b"Title").ok()?).ok()?;
+    let bytes = title.as_str().ok()?;
+
+    let title = match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => String::from_utf8_lossy(bytes).into_owned(),
+    };
+    let title = normalize_text(&title);
+    (!title.is_empty()).then_some(title)
+}
+
+/// Collapse runs of whitespace, drop blank lines and join words split by a
+/// hyphen at the end of a line
+pub fn normalize_text(raw: &str) -> String {
+    let mut out = String::new();
+
+    for line in raw.lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if line.is_empty() {
+            continue;
+        }
+
+        if out.is_empty() {
+            out.push_str(&line);
+        } else if is_hyphenated_break(&out, &line) {
+            out.pop();
+            out.push_str(&line);
+        } else {
+            out.push('\n');
+            out.push_str(&line);
+        }
+    }
+
+    out
+}
+
+fn is_hyphenated_break(previous: &str, next: &str) -> bool {
+    let mut tail = previous.chars().rev();
+    let ends_with_hyphen = tail.next() == Some('-');
+    let letter_before = tail.next().is_some_and(char::is_alphabetic);
+    let continues_lowercase = next.chars().next().is_some_and(char::is_lowercase);
+    ends_with_hyphen && letter_before && continues_lowercase
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    #[test]
+    fn test_extracts_text_per_page() {
+        let doc = PdfExtractor::new().extract(fixture("manual.pdf")).unwrap();
+
+        assert_eq!(doc.page_count, 3);
+        assert_eq!(doc.pages[0].page_number, 1);
+        assert_eq!(
+            doc.pages[0].text,
+            "Chain Maintenance\nClean the chain before applying fresh lubricant."
+        );
+        assert_eq!(
+            doc.pages[2].text,
+            "Brake Adjustment\nTighten the cable until the pads\ntouch the rim."
+        );
+    }
+
+    #[test]
+    fn test_reads_document_title() {
+        let extractor = PdfExtractor::new();
+
+        let titled = extractor.extract(fixture("titled.pdf")).unwrap();
+        assert_eq!(titled.title.as_deref(), Some("YZF-R1 Owner's Manual"));
+        assert_eq!(extractor.extract(fixture("manual.pdf")).unwrap().title, None);
+    }
+
+    #[test]
+    fn test_extract_pages_limits_pages_but_counts_all() {
+        let extractor = PdfExtractor::new();
+        let bytes = std::fs::read(fixture("manual.pdf")).unwrap();
+        let front = extractor.extract_pages(&bytes, 1).unwrap();
+        assert_eq!((front.pages.len(), front.page_count), (1, 3));
+    }
+
+    #[test]
+    fn test_torque_table_values_stay_paired() {
+        let doc = PdfExtractor::new().extract(fixture("torque.pdf")).unwrap();
+
+        assert_eq!(
+            doc.pages[0].text,
+            "Torque Specifications\n\n\
+             | Fastener | Torque |\n\
+             | --- | --- |\n\
+             | Rear axle nut | 90 Nm |\n\
+             | Brake caliper bolt | 35 Nm |\n\
+             | Oil drain plug | 30 Nm |\n\n\
+             Always use a calibrated torque wrench."
+        );
+    }
+
+    #[test]
+    fn test_flags_empty_pages() {
+        let doc = PdfExtractor::new().extract(fixture("manual.pdf")).unwrap();
+
+        assert!(doc.pages[1].is_empty());
+        assert_eq!(doc.empty_pages(), vec![2]);
+    }
+
+    #[test]
+    fn test_rejects_encrypted_and_malformed() {
+        let extractor = PdfExtractor::new();
+
+        let encrypted = extractor.extract(fixture("encrypted.pdf")).unwrap_err();
+        assert!(
+            matches!(encrypted, ExtractError::Encrypted | ExtractError::PasswordRequired),
+            "{:?}",
+            encrypted
+        );
+        assert_eq!(encrypted.code(), "PDF_ENCRYPTED");
+        let malformed = extractor.extract(fixture("malformed.pdf")).unwrap_err();
+        assert!(matches!(malformed, ExtractError::Malformed { page: None, .. }));
+        assert_eq!(malformed.code(), "PDF_MALFORMED");
+    }
+
+    #[test]
+    fn test_unparseable_page_skipped() {
+        let mut document = PdfDocument::load(fixture("manual.pdf")).unwrap();
+        let page_id = document.get_pages()[&3];
+        let content_id = document.get_page_contents(page_id)[0];
+        // A font operator without operands
+        let stream = document.get_object_mut(content_id).unwrap().as_stream_mut().unwrap();
+        stream.set_content(b"BT Tf 72 720 Td (Brake) Tj ET".to_vec());
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+
+        let doc = PdfExtractor::new().extract_bytes(&bytes).unwrap();
+
+        assert_eq!(doc.page_count, 3);
+        assert_eq!(doc.skipped_pages, vec![3]);
+        let numbers: Vec<u32> = doc.pages.iter().map(|p| p.page_number).collect();
+        assert_eq!(numbers, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_unsupported_version_rejected() {
+        let bytes = std::fs::read(fixture("manual.pdf")).unwrap();
+        let mut future = b"%PDF-3.0".to_vec();
+        future.extend_from_slice(&bytes[b"%PDF-1.4".len()..]);
+
+        let error = PdfExtractor::new().extract_bytes(&future).unwrap_err();
+        assert!(matches!(error, ExtractError::UnsupportedVersion(v) if v == "3.0"));
+        assert!(is_supported_version("1.7") && is_supported_version("2.0"));
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(normalize_text("  a   b \n\n 3-\nd"), "a b\n3-\nd");
+        assert_eq!(normalize_text("re-\nplace the well-\nKnown"), "replace the well-\nKnown");
+    }
+
+    #[test]
+    fn test_garbled_text_detected() {
+        assert!(is_garbled("\u{1}\u{2}\u{3}\u{FFFD}\u{E01A}\u{E01B} ab"));
+        assert!(!is_garbled("Torque the axle nut to 65 N\u{b7}m \u{FFFD}"));
+        assert!(!is_garbled(""));
+
+        let stamped = PageText { page_number: 1, text: "- 12 -".to_string() };
+        assert!(stamped.has_little_text());
+        let written = PageText { page_number: 2, text: "Clean the chain".repeat(2) };
+        assert!(!written.has_little_text());
+    }
 }
@@ -0,0 +1,197 @@
+use scraper::{ElementRef, Html, Node, Selector};
+
+use crate::pdf::{heading_text, markdown_table, text::single_page, ExtractedDocument};
+
+/// Elements whose content is page chrome or code rather than guide text
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "script", "style", "noscript", "template", "nav", "iframe", "svg", "head",
+];
+
+/// Elements that start and end a paragraph
+const BLOCK_ELEMENTS: &[&str] = &[
+    "p", "div", "section", "article", "main", "header", "footer", "aside", "blockquote",
+    "pre", "figure", "figcaption", "dl", "dt", "dd", "address", "details", "summary", "hr",
+    "br", "form", "fieldset",
+];
+
+/// HTML as a single page of Markdown-like paragraphs: headings become `#` lines,
+/// lists `-`/`1.` items and tables markdown tables; navigation and scripts are
+/// dropped. The title is `<title>`, falling back to the first heading.
+pub fn extract_html(html: &str) -> ExtractedDocument {
+    let document = Html::parse_document(html);
+
+    let mut renderer = Renderer::default();
+    renderer.children(document.root_element());
+    renderer.flush();
+
+    let title_selector = Selector::parse("title").expect("valid selector");
+    let title = document
+        .select(&title_selector)
+        .next()
+        .map(|t| collapse(&t.text().collect::<String>()))
+        .filter(|t| !t.is_empty())
+        .or_else(|| renderer.blocks.iter().find_map(|b| heading_text(b)).map(String::from));
+
+    single_page(renderer.blocks, title)
+}
+
+#[derive(Default)]
+struct Renderer {
+    blocks: Vec<String>,
+
+    /// Inline text of the paragraph being built
+    inline: String,
+
+    /// Open lists, innermost last: `None` for bullets, the last number for ordered lists
+    lists: Vec<Option<usize>>,
+
+    /// Items of the outermost open list, emitted together as one paragraph
+    items: Vec<String>,
+
+    /// Marker of the list item whose text is being built
+    marker: Option<String>,
+}
+
+impl Renderer {
+    fn children(&mut self, element: ElementRef) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => self.inline.push_str(text),
+                Node::Element(_) => {
+                    if let Some(child) = ElementRef::wrap(child) {
+                        self.element(child);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn element(&mut self, element: ElementRef) {
+        let name = element.value().name();
+        match name {
+            _ if SKIPPED_ELEMENTS.contains(&name) => {}
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.flush();
+                let level = name[1..].parse().unwrap_or(1);
+                let text = collapse(&element.text().collect::<String>());
+                if !text.is_empty() {
+                    self.push_block(format!("{} {}", "#".repeat(level), text));
+                }
+            }
+            "ul" | "ol" => {
+                self.flush();
+                self.lists.push((name == "ol").then_some(0));
+                self.children(element);
+                self.flush();
+                self.lists.pop();
+                if self.lists.is_empty() && !self.items.is_empty() {
+                    let items = std::mem::take(&mut self.items);
+                    self.blocks.push(items.join("\n"));
+                }
+            }
+            "li" => {
+                self.flush();
+                self.marker = Some(match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}.", number)
+                    }
+                    _ => "-".to_string(),
+                });
+                self.children(element);
+                self.flush();
+            }
+            "table" => {
+                self.flush();
+                let rows = table_rows(element);
+                if !rows.is_empty() {
+                    self.push_block(markdown_table(&rows));
+                }
+            }
+            _ if BLOCK_ELEMENTS.contains(&name) => {
+                self.flush();
+                self.children(element);
+                self.flush();
+            }
+            _ => self.children(element),
+        }
+    }
+
+    /// End the current paragraph or list item
+    fn flush(&mut self) {
+        let text = collapse(&self.inline);
+        self.inline.clear();
+        if text.is_empty() {
+            return;
+        }
+        match self.marker.take() {
+            Some(marker) => self.items.push(format!("{} {}", marker, text)),
+            None => self.push_block(text),
+        }
+    }
+
+    fn push_block(&mut self, block: String) {
+        // Text directly inside a list (not in an item) joins the list paragraph
+        if self.lists.is_empty() {
+            self.blocks.push(block);
+        } else {
+            self.items.push(block);
+        }
+    }
+}
+
+/// Cell text of each non-empty row
+fn table_rows(table: ElementRef) -> Vec<Vec<String>> {
+    let rows = Selector::parse("tr").expect("valid selector");
+    let cells = Selector::parse("th, td").expect("valid selector");
+    table
+        .select(&rows)
+        .map(|row| {
+            row.select(&cells)
+                .map(|cell| collapse(&cell.text().collect::<String>()))
+                .collect::<Vec<_>>()
+        })
+        .filter(|row| row.iter().any(|cell| !cell.is_empty()))
+        .collect()
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_keeps_headings_lists_and_tables() {
+        let doc = extract_html(
+            "<html><head><title>R1 Chain Guide</title><style>p { color: red }</style></head>\
+             <body><nav><a href='/'>Home</a> | <a href='/guides'>Guides</a></nav>\
+             <h1>Chain  care</h1><p>Keep the <b>chain</b> clean &amp; oiled.</p>\
+             <ol><li>Loosen the axle nut</li><li>Set slack to <em>30 mm</em></li></ol>\
+             <table><tr><th>Fastener</th><th>Torque</th></tr>\
+             <tr><td>Axle nut</td><td>90 Nm</td></tr></table>\
+             <script>track('view')</script></body></html>",
+        );
+
+        assert_eq!(doc.title.as_deref(), Some("R1 Chain Guide"));
+        assert_eq!(
+            doc.pages[0].text,
+            "# Chain care\n\nKeep the chain clean & oiled.\n\n\
+             1. Loosen the axle nut\n2. Set slack to 30 mm\n\n\
+             | Fastener | Torque |\n| --- | --- |\n| Axle nut | 90 Nm |"
+        );
+    }
+
+    #[test]
+    fn test_nested_lists_form_one_paragraph_and_title_falls_back_to_heading() {
+        let doc = extract_html(
+            "<h2>Brakes</h2><ul><li>Pads<ul><li>Check wear</li></ul></li><li>Fluid</li></ul>",
+        );
+
+        assert_eq!(doc.title.as_deref(), Some("Brakes"));
+        assert_eq!(doc.pages[0].text, "## Brakes\n\n- Pads\n- Check wear\n- Fluid");
+    }
+}
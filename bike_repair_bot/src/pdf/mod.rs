@@ -1,8 +1,22 @@
-// Placeholder for PDF processing module
-// Will be implemented in Phase 6
+// Document processing module: PDF, text and HTML extraction, header/footer
+// stripping, page selection, model detection, chunking and chunk quality filtering
 
 pub mod extractor;
+pub mod boilerplate;
 pub mod chunker;
+pub mod detector;
+pub mod html;
+pub mod quality;
+pub mod ranges;
+pub mod tables;
+pub mod text;
 
 pub use extractor::*;
+pub use boilerplate::*;
 pub use chunker::*;
+pub use detector::*;
+pub use html::*;
+pub use quality::*;
+pub use ranges::*;
+pub use tables::*;
+pub use text::*;
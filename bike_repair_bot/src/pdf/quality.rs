@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+
+use crate::config::Config;
+use crate::models::DocumentChunk;
+use crate::pdf::{is_table_row, TABLE_CONTENT_TYPE};
+use crate::rag::count_tokens;
+
+/// Chunks with fewer tokens carry no retrievable content (page numbers, stray labels)
+pub const DEFAULT_CHUNK_MIN_TOKENS: usize = 5;
+
+/// Share of letters among non-whitespace characters below which a chunk is noise
+pub const DEFAULT_CHUNK_MIN_ALPHA_RATIO: f32 = 0.4;
+
+/// Longest chunk, in words, that can be dropped as a bare figure caption
+const MAX_CAPTION_WORDS: usize = 8;
+
+/// Leader dots joining a table-of-contents entry to its page number
+const TOC_LEADER: &str = "....";
+
+/// Why a chunk was dropped before embedding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChunkRejection {
+    TooShort,
+    LowAlphaRatio,
+    TableOfContents,
+    FigureCaption,
+}
+
+impl ChunkRejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChunkRejection::TooShort => "too_short",
+            ChunkRejection::LowAlphaRatio => "low_alpha_ratio",
+            ChunkRejection::TableOfContents => "table_of_contents",
+            ChunkRejection::FigureCaption => "figure_caption",
+        }
+    }
+}
+
+/// Drops junk chunks between chunking and embedding: fragments under a token
+/// minimum, chunks that are mostly digits and punctuation, table-of-contents
+/// listings and bare figure captions
+#[derive(Debug, Clone)]
+pub struct ChunkQualityFilter {
+    /// Model whose tokenizer is used for counting
+    model: String,
+    min_tokens: usize,
+    /// Not applied to reconstructed tables, which are mostly numbers by design
+    min_alpha_ratio: f32,
+    /// Whether table-of-contents and figure-caption chunks are dropped
+    drop_patterns: bool,
+}
+
+impl ChunkQualityFilter {
+    pub fn new(
+        model: impl Into<String>,
+        min_tokens: usize,
+        min_alpha_ratio: f32,
+        drop_patterns: bool,
+    ) -> Self {
+        Self {
+            model: model.into(),
+            min_tokens,
+            min_alpha_ratio,
+            drop_patterns,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(
+            config.openai_embedding_model.clone(),
+            config.chunk_min_tokens,
+            config.chunk_min_alpha_ratio,
+            config.chunk_drop_toc_and_captions,
+        )
+    }
+
+    /// The first rule a chunk fails, if any
+    pub fn check(&self, chunk: &DocumentChunk) -> Option<ChunkRejection> {
+        let text = chunk.text.trim();
+        if count_tokens(&self.model, text) < self.min_tokens {
+            return Some(ChunkRejection::TooShort);
+        }
+        if self.drop_patterns {
+            if is_table_of_contents(text) {
+                return Some(ChunkRejection::TableOfContents);
+            }
+            if is_figure_caption(text) {
+                return Some(ChunkRejection::FigureCaption);
+            }
+        }
+        let is_table = chunk.metadata.content_type.as_deref() == Some(TABLE_CONTENT_TYPE);
+        if !is_table && alpha_ratio(text) < self.min_alpha_ratio {
+            return Some(ChunkRejection::LowAlphaRatio);
+        }
+        None
+    }
+
+    /// Split chunks into those worth embedding and the number dropped per reason
+    pub fn apply(
+        &self,
+        chunks: Vec<DocumentChunk>,
+    ) -> (Vec<DocumentChunk>, BTreeMap<ChunkRejection, usize>) {
+        let mut dropped = BTreeMap::new();
+        let kept = chunks
+            .into_iter()
+            .filter(|chunk| match self.check(chunk) {
+                Some(reason) => {
+                    log::debug!("Dropping chunk ({}): {:?}", reason.as_str(), chunk.text);
+                    *dropped.entry(reason).or_insert(0) += 1;
+                    false
+                }
+                None => true,
+            })
+            .collect();
+        (kept, dropped)
+    }
+}
+
+/// Letters as a share of non-whitespace characters (0.0 for empty text)
+fn alpha_ratio(text: &str) -> f32 {
+    let (letters, total) = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .fold((0, 0), |(letters, total), c| {
+            (letters + c.is_alphabetic() as usize, total + 1)
+        });
+    if total == 0 {
+        0.0
+    } else {
+        letters as f32 / total as f32
+    }
+}
+
+/// Most lines are entries joined to a page number by leader dots
+/// (`Brakes ........ 4-12`), with or without spaces between the dots
+fn is_table_of_contents(text: &str) -> bool {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    let entries = lines
+        .iter()
+        .filter(|line| !is_table_row(line))
+        .filter(|line| line.replace(". ", ".").contains(TOC_LEADER))
+        .count();
+    entries > 0 && entries * 2 >= lines.len()
+}
+
+/// A single short line like `Fig. 3-12` or `Figure 4.2 Brake caliper`
+fn is_figure_caption(text: &str) -> bool {
+    if text.lines().filter(|l| !l.trim().is_empty()).count() > 1 {
+        return false;
+    }
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.len() > MAX_CAPTION_WORDS {
+        return false;
+    }
+    let (label, number) = match words.as_slice() {
+        [label, number, ..] => (label.to_lowercase(), *number),
+        _ => return false,
+    };
+    let is_label = matches!(
+        label.trim_end_matches('.'),
+        "fig" | "figure" | "illus" | "illustration" | "diagram"
+    );
+    is_label && number.starts_with(|c: char| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ChunkMetadata;
+
+    const MODEL: &str = "text-embedding-3-small";
+
+    fn filter() -> ChunkQualityFilter {
+        ChunkQualityFilter::new(
+            MODEL,
+            DEFAULT_CHUNK_MIN_TOKENS,
+            DEFAULT_CHUNK_MIN_ALPHA_RATIO,
+            true,
+        )
+    }
+
+    fn chunk(text: &str) -> DocumentChunk {
+        DocumentChunk::new("doc-1", text, ChunkMetadata::new("Yamaha R1"))
+    }
+
+    #[test]
+    fn test_short_chunks_dropped() {
+        assert_eq!(filter().check(&chunk("12")), Some(ChunkRejection::TooShort));
+        assert_eq!(
+            filter().check(&chunk("Page 4")),
+            Some(ChunkRejection::TooShort)
+        );
+
+        let lenient = ChunkQualityFilter::new(MODEL, 1, DEFAULT_CHUNK_MIN_ALPHA_RATIO, true);
+        assert_eq!(lenient.check(&chunk("Brakes")), None);
+    }
+
+    #[test]
+    fn test_mostly_symbols_dropped_but_tables_kept() {
+        let noise = "4-12 4-13 4-14 4-15 4-16 ###### ====== 0012 0013 0014";
+        assert_eq!(
+            filter().check(&chunk(noise)),
+            Some(ChunkRejection::LowAlphaRatio)
+        );
+
+        let mut table =
+            chunk("| Nm | 90 | 45 | 12 |\n| --- | --- | --- | --- |\n| Nm | 30 | 8 | 10 |");
+        table.metadata.content_type = Some(TABLE_CONTENT_TYPE.to_string());
+        assert_eq!(filter().check(&table), None);
+
+        let strict = ChunkQualityFilter::new(MODEL, DEFAULT_CHUNK_MIN_TOKENS, 0.95, true);
+        assert_eq!(
+            strict.check(&chunk("Tighten the axle nut to 90 Nm.")),
+            Some(ChunkRejection::LowAlphaRatio)
+        );
+    }
+
+    #[test]
+    fn test_table_of_contents_dropped() {
+        let toc = "Contents\nBrakes ........................ 4-12\n\
+                   Chain and sprockets . . . . . . . . . 4-20\nSuspension ........ 5-1";
+        assert_eq!(
+            filter().check(&chunk(toc)),
+            Some(ChunkRejection::TableOfContents)
+        );
+
+        let prose = "Check the brake pads... then bleed the system. Refill the reservoir.";
+        assert_eq!(filter().check(&chunk(prose)), None);
+
+        let patterns_off = ChunkQualityFilter::new(
+            MODEL,
+            DEFAULT_CHUNK_MIN_TOKENS,
+            DEFAULT_CHUNK_MIN_ALPHA_RATIO,
+            false,
+        );
+        assert_eq!(patterns_off.check(&chunk(toc)), None);
+    }
+
+    #[test]
+    fn test_figure_captions_dropped() {
+        for caption in [
+            "Fig. 3-12 Rear brake caliper",
+            "Figure 4.2 Chain adjuster bolts",
+        ] {
+            assert_eq!(
+                filter().check(&chunk(caption)),
+                Some(ChunkRejection::FigureCaption)
+            );
+        }
+
+        let reference = "Figure 4.2 shows how to loosen the axle nut before adjusting the chain.";
+        assert_eq!(filter().check(&chunk(reference)), None);
+    }
+
+    #[test]
+    fn test_apply_counts_drops_per_reason() {
+        let chunks = vec![
+            chunk("Adjust the chain slack to 30 mm at the midpoint."),
+            chunk("7"),
+            chunk("Fig. 2-1 Front fork oil level"),
+        ];
+
+        let (kept, dropped) = filter().apply(chunks);
+
+        assert_eq!(kept.len(), 1);
+        assert_eq!(
+            dropped,
+            [
+                (ChunkRejection::TooShort, 1),
+                (ChunkRejection::FigureCaption, 1)
+            ]
+            .into()
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Why a page range selection was rejected
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PageRangeError {
+    #[error("no page ranges given")]
+    Empty,
+    #[error("'{0}' is not a page or page range (expected e.g. 12 or 1-180)")]
+    Invalid(String),
+    #[error("range {0} ends before it starts")]
+    Reversed(String),
+    #[error("ranges {0} and {1} overlap")]
+    Overlapping(String, String),
+    #[error("range {range} is outside the document's {page_count} pages")]
+    OutOfBounds { range: String, page_count: u32 },
+    #[error("range {0} covers pages that were not extracted; upload the document again")]
+    NotExtracted(String),
+}
+
+/// Inclusive 1-based page range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PageRange {
+    start: u32,
+    end: u32,
+}
+
+impl fmt::Display for PageRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// Pages of a document selected for indexing, written like `1-180,240-260`.
+/// Ranges are kept in ascending order and never overlap.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PageRanges {
+    ranges: Vec<PageRange>,
+}
+
+impl PageRanges {
+    /// Whether `page` (1-based) is selected
+    pub fn contains(&self, page: u32) -> bool {
+        self.ranges.iter().any(|r| (r.start..=r.end).contains(&page))
+    }
+
+    /// Check that every range lies within a document of `page_count` pages
+    pub fn validate(&self, page_count: u32) -> Result<(), PageRangeError> {
+        match self.ranges.iter().find(|r| r.end > page_count) {
+            Some(range) => Err(PageRangeError::OutOfBounds {
+                range: range.to_string(),
+                page_count,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that every page selected here is also selected by `outer`
+    pub fn within(&self, outer: &PageRanges) -> Result<(), PageRangeError> {
+        let covered = |range: &PageRange| {
+            outer.ranges.iter().any(|o| o.start <= range.start && range.end <= o.end)
+        };
+        match self.ranges.iter().find(|r| !covered(r)) {
+            Some(range) => Err(PageRangeError::NotExtracted(range.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for PageRanges {
+    type Err = PageRangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ranges = Vec::new();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let page = |n: &str| match n.trim().parse::<u32>() {
+                Ok(n) if n > 0 => Ok(n),
+                _ => Err(PageRangeError::Invalid(part.to_string())),
+            };
+            let range = match part.split_once('-') {
+                Some((start, end)) => PageRange {
+                    start: page(start)?,
+                    end: page(end)?,
+                },
+                None => {
+                    let n = page(part)?;
+                    PageRange { start: n, end: n }
+                }
+            };
+            if range.end < range.start {
+                return Err(PageRangeError::Reversed(part.to_string()));
+            }
+            ranges.push(range);
+        }
+        if ranges.is_empty() {
+            return Err(PageRangeError::Empty);
+        }
+
+        ranges.sort_by_key(|r| r.start);
+        if let Some(pair) = ranges.windows(2).find(|pair| pair[1].start <= pair[0].end) {
+            return Err(PageRangeError::Overlapping(
+                pair[0].to_string(),
+                pair[1].to_string(),
+            ));
+        }
+        Ok(Self { ranges })
+    }
+}
+
+impl fmt::Display for PageRanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.ranges.iter().map(PageRange::to_string).collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+impl TryFrom<String> for PageRanges {
+    type Error = PageRangeError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PageRanges> for String {
+    fn from(ranges: PageRanges) -> Self {
+        ranges.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_select_pages() {
+        let ranges: PageRanges = "240-260, 1-180,200".parse().unwrap();
+
+        assert_eq!(ranges.to_string(), "1-180,200,240-260");
+        assert!(ranges.contains(1) && ranges.contains(180) && ranges.contains(200));
+        assert!(!ranges.contains(181) && !ranges.contains(261));
+        assert!(ranges.validate(260).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_ranges_rejected() {
+        for (input, expected) in [
+            ("", PageRangeError::Empty),
+            ("1-x", PageRangeError::Invalid("1-x".to_string())),
+            ("0-4", PageRangeError::Invalid("0-4".to_string())),
+            ("9-3", PageRangeError::Reversed("9-3".to_string())),
+            (
+                "1-10,5-20",
+                PageRangeError::Overlapping("1-10".to_string(), "5-20".to_string()),
+            ),
+        ] {
+            assert_eq!(input.parse::<PageRanges>().unwrap_err(), expected, "{}", input);
+        }
+
+        let ranges: PageRanges = "1-180,240-260".parse().unwrap();
+        assert_eq!(
+            ranges.validate(250).unwrap_err(),
+            PageRangeError::OutOfBounds {
+                range: "240-260".to_string(),
+                page_count: 250
+            }
+        );
+    }
+
+    #[test]
+    fn test_within_outer_selection() {
+        let outer: PageRanges = "1-180,240-260".parse().unwrap();
+
+        assert!("2-50,250".parse::<PageRanges>().unwrap().within(&outer).is_ok());
+        assert_eq!(
+            "170-200".parse::<PageRanges>().unwrap().within(&outer).unwrap_err(),
+            PageRangeError::NotExtracted("170-200".to_string())
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use lopdf::content::Content;
+use lopdf::{Document as PdfDocument, Object, ObjectId};
+use std::collections::BTreeMap;
+
+use crate::pdf::normalize_text;
+
+/// `ChunkMetadata::content_type` of chunks containing a reconstructed table
+pub const TABLE_CONTENT_TYPE: &str = "table";
+
+/// Runs whose baselines differ by less than this (in points) share a line
+const LINE_TOLERANCE: f32 = 2.0;
+
+/// Cells whose left edges differ by less than this (in points) share a column
+const COLUMN_TOLERANCE: f32 = 8.0;
+
+/// Header plus at least two body rows
+const MIN_TABLE_ROWS: usize = 3;
+
+/// Text shown at one position on the page
+#[derive(Debug, Clone, PartialEq)]
+struct TextRun {
+    x: f32,
+    y: f32,
+    text: String,
+}
+
+/// True for a markdown table row such as `| Axle nut | 90 Nm |`
+pub fn is_table_row(line: &str) -> bool {
+    let line = line.trim();
+    line.len() > 1 && line.starts_with('|') && line.ends_with('|')
+}
+
+/// True when every line of a paragraph is a table row
+pub fn is_table_block(text: &str) -> bool {
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty()).peekable();
+    lines.peek().is_some() && lines.all(is_table_row)
+}
+
+/// True when any line of the text is a table row
+pub fn contains_table(text: &str) -> bool {
+    text.lines().any(is_table_row)
+}
+
+/// Rebuild a page from text positions when it contains column-aligned tables,
+/// rendering each table as a markdown block. Returns `None` for pages without
+/// tables (or with content that cannot be laid out), which keep plain extraction.
+pub fn extract_page_with_tables(document: &PdfDocument, page_id: ObjectId) -> Option<String> {
+    let runs = page_runs(document, page_id).ok()?;
+    let lines = group_lines(runs);
+    let tables = find_tables(&lines);
+    if tables.is_empty() {
+        return None;
+    }
+
+    let mut blocks = Vec::new();
+    let mut plain = Vec::new();
+    let mut line = 0;
+    for (start, end) in tables {
+        plain.extend(lines[line..start].iter().map(|l| join_runs(l)));
+        flush_plain(&mut plain, &mut blocks);
+        blocks.push(render_table(&lines[start..end]));
+        line = end;
+    }
+    plain.extend(lines[line..].iter().map(|l| join_runs(l)));
+    flush_plain(&mut plain, &mut blocks);
+
+    Some(blocks.join("\n\n"))
+}
+
+fn flush_plain(plain: &mut Vec<String>, blocks: &mut Vec<String>) {
+    let text = normalize_text(&plain.join("\n"));
+    if !text.is_empty() {
+        blocks.push(text);
+    }
+    plain.clear();
+}
+
+fn join_runs(line: &[TextRun]) -> String {
+    line.iter().map(|r| r.text.trim()).collect::<Vec<_>>().join(" ")
+}
+
+fn render_table(rows: &[Vec<TextRun>]) -> String {
+    let rows: Vec<Vec<String>> = rows
+        .iter()
+        .map(|cells| cells.iter().map(|c| c.text.clone()).collect())
+        .collect();
+    markdown_table(&rows)
+}
+
+/// Render rows of cell text as a markdown table whose first row is the header.
+/// Short rows are padded to the widest one; `rows` must not be empty.
+pub fn markdown_table(rows: &[Vec<String>]) -> String {
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let row = |cells: &[String]| {
+        let cells: Vec<String> = (0..width)
+            .map(|i| {
+                let cell = cells.get(i).map(String::as_str).unwrap_or("");
+                cell.split_whitespace().collect::<Vec<_>>().join(" ").replace('|', "\\|")
+            })
+            .collect();
+        format!("| {} |", cells.join(" | "))
+    };
+
+    let mut out = vec![row(&rows[0])];
+    out.push(format!("|{}", " --- |".repeat(width)));
+    out.extend(rows[1..].iter().map(|r| row(r)));
+    out.join("\n")
+}
+
+/// Ranges of consecutive lines with the same number (≥ 2) of column-aligned cells
+fn find_tables(lines: &[Vec<TextRun>]) -> Vec<(usize, usize)> {
+    let mut tables = Vec::new();
+    let mut start = 0;
+
+    while start < lines.len() {
+        let columns = &lines[start];
+        let mut end = start + 1;
+        if columns.len() >= 2 {
+            while end < lines.len() && aligned(columns, &lines[end]) {
+                end += 1;
+            }
+        }
+
+        if end - start >= MIN_TABLE_ROWS {
+            tables.push((start, end));
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+    tables
+}
+
+fn aligned(columns: &[TextRun], line: &[TextRun]) -> bool {
+    columns.len() == line.len()
+        && columns
+            .iter()
+            .zip(line)
+            .all(|(a, b)| (a.x - b.x).abs() < COLUMN_TOLERANCE)
+}
+
+/// Group runs into lines from the top of the page down, each ordered left to right
+fn group_lines(mut runs: Vec<TextRun>) -> Vec<Vec<TextRun>> {
+    runs.sort_by(|a, b| b.y.total_cmp(&a.y).then(a.x.total_cmp(&b.x)));
+
+    let mut lines: Vec<Vec<TextRun>> = Vec::new();
+    for run in runs {
+        match lines.last_mut() {
+            Some(line) if (line[0].y - run.y).abs() < LINE_TOLERANCE => line.push(run),
+            _ => lines.push(vec![run]),
+        }
+    }
+    for line in &mut lines {
+        line.sort_by(|a, b| a.x.total_cmp(&b.x));
+    }
+    lines
+}
+
+/// Text runs of a page with their text-space positions. Only text positioning
+/// operators are interpreted; the current transformation matrix is ignored.
+fn page_runs(document: &PdfDocument, page_id: ObjectId) -> lopdf::Result<Vec<TextRun>> {
+    let encodings: BTreeMap<Vec<u8>, &str> = document
+        .get_page_fonts(page_id)
+        .into_iter()
+        .map(|(name, font)| (name, font.get_font_encoding()))
+        .collect();
+    let content = Content::decode(&document.get_page_content(page_id)?)?;
+
+    let mut runs: Vec<TextRun> = Vec::new();
+    let mut encoding = None;
+    let (mut line_x, mut line_y, mut leading) = (0.0f32, 0.0f32, 0.0f32);
+    // Set when the position moved, so the next shown text starts a new run
+    let mut moved = true;
+
+    let operand = |operands: &[Object], i: usize| {
+        operands.get(i).and_then(|o| o.as_float().ok()).unwrap_or(0.0)
+    };
+
+    for operation in &content.operations {
+        let operands = &operation.operands;
+        match operation.operator.as_str() {
+            "BT" => {
+                (line_x, line_y) = (0.0, 0.0);
+                moved = true;
+            }
+            "Tf" => {
+                encoding = operands
+                    .first()
+                    .and_then(|o| o.as_name().ok())
+                    .and_then(|name| encodings.get(name).copied());
+            }
+            "TL" => leading = operand(operands, 0),
+            "Td" | "TD" => {
+                line_x += operand(operands, 0);
+                line_y += operand(operands, 1);
+                if operation.operator == "TD" {
+                    leading = -operand(operands, 1);
+                }
+                moved = true;
+            }
+            "Tm" => {
+                (line_x, line_y) = (operand(operands, 4), operand(operands, 5));
+                moved = true;
+            }
+            "T*" => {
+                line_y -= leading;
+                moved = true;
+            }
+            "Tj" | "TJ" | "'" | "\"" => {
+                if operation.operator != "Tj" && operation.operator != "TJ" {
+                    line_y -= leading;
+                    moved = true;
+                }
+                let mut text = String::new();
+                collect_text(&mut text, encoding, operands);
+                if text.trim().is_empty() {
+                    continue;
+                }
+                match runs.last_mut() {
+                    Some(run) if !moved => run.text.push_str(&text),
+                    _ => runs.push(TextRun {
+                        x: line_x,
+                        y: line_y,
+                        text,
+                    }),
+                }
+                moved = false;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(runs)
+}
+
+fn collect_text(text: &mut String, encoding: Option<&str>, operands: &[Object]) {
+    for operand in operands {
+        match operand {
+            Object::String(bytes, _) => text.push_str(&PdfDocument::decode_text(encoding, bytes)),
+            Object::Array(items) => collect_text(text, encoding, items),
+            // Large negative kerning in a TJ array stands for a word space
+            Object::Integer(i) if *i < -100 => text.push(' '),
+            Object::Real(r) if *r < -100.0 => text.push(' '),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(x: f32, y: f32, text: &str) -> TextRun {
+        TextRun {
+            x,
+            y,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_columns_emitted_separately_are_paired_by_row() {
+        // Column-by-column drawing order, as many manual generators emit it
+        let runs = vec![
+            run(72.0, 700.0, "Fastener"),
+            run(72.0, 684.0, "Axle nut"),
+            run(72.0, 668.0, "Caliper bolt"),
+            run(300.0, 700.0, "Torque"),
+            run(300.0, 684.0, "90 Nm"),
+            run(301.0, 668.0, "35 Nm"),
+        ];
+
+        let lines = group_lines(runs);
+
+        assert_eq!(find_tables(&lines), vec![(0, 3)]);
+        assert_eq!(
+            render_table(&lines),
+            "| Fastener | Torque |\n| --- | --- |\n| Axle nut | 90 Nm |\n| Caliper bolt | 35 Nm |"
+        );
+    }
+
+    #[test]
+    fn test_prose_is_not_a_table() {
+        let lines = group_lines(vec![
+            run(72.0, 700.0, "Check the chain."),
+            run(72.0, 684.0, "Step 1"),
+            run(140.0, 684.0, "Loosen the axle."),
+        ]);
+
+        assert!(find_tables(&lines).is_empty());
+    }
+
+    #[test]
+    fn test_table_block_detection() {
+        assert!(is_table_block("| a | b |\n| --- | --- |\n| 1 | 2 |"));
+        assert!(!is_table_block("| a | b |\nplain text"));
+        assert!(contains_table("Torque values:\n| a | b |"));
+        assert!(!contains_table("a | b"));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::pdf::{normalize_text, ExtractedDocument, PageText};
+
+/// Text of a Markdown ATX heading (`## Chain slack` → `Chain slack`)
+pub fn heading_text(line: &str) -> Option<&str> {
+    let line = line.trim();
+    let level = line.bytes().take_while(|&b| b == b'#').count();
+    if !(1..=6).contains(&level) || !line[level..].starts_with(' ') {
+        return None;
+    }
+    let text = line[level..].trim().trim_end_matches('#').trim_end();
+    (!text.is_empty()).then_some(text)
+}
+
+/// True for a Markdown list item such as `- Drain the oil` or `2. Refill`
+pub fn is_list_item(line: &str) -> bool {
+    let line = line.trim_start();
+    if ["- ", "* ", "+ "].iter().any(|marker| line.starts_with(marker)) {
+        return true;
+    }
+    let digits = line.bytes().take_while(u8::is_ascii_digit).count();
+    digits > 0 && (line[digits..].starts_with(". ") || line[digits..].starts_with(") "))
+}
+
+/// True when every line of a paragraph is a list item
+pub fn is_list_block(text: &str) -> bool {
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty()).peekable();
+    lines.peek().is_some() && lines.all(is_list_item)
+}
+
+/// Plain text as a single page of paragraphs separated by blank lines
+pub fn extract_plain_text(text: &str) -> ExtractedDocument {
+    single_page(paragraphs(text, false), None)
+}
+
+/// Markdown as a single page. Headings become paragraphs of their own and the
+/// first one is used as the title.
+pub fn extract_markdown(text: &str) -> ExtractedDocument {
+    let blocks = paragraphs(text, true);
+    let title = blocks.iter().find_map(|b| heading_text(b)).map(String::from);
+    single_page(blocks, title)
+}
+
+/// Wrap paragraphs as page 1 of a one-page document
+pub(crate) fn single_page(blocks: Vec<String>, title: Option<String>) -> ExtractedDocument {
+    ExtractedDocument {
+        pages: vec![PageText {
+            page_number: 1,
+            text: blocks.join("\n\n"),
+        }],
+        page_count: 1,
+        title,
+        skipped_pages: Vec::new(),
+    }
+}
+
+fn paragraphs(text: &str, markdown: bool) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let flush = |current: &mut Vec<&str>, blocks: &mut Vec<String>| {
+        let block = normalize_text(&current.join("\n"));
+        if !block.is_empty() {
+            blocks.push(block);
+        }
+        current.clear();
+    };
+
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        if line.trim().is_empty() {
+            flush(&mut current, &mut blocks);
+        } else if markdown && heading_text(line).is_some() {
+            flush(&mut current, &mut blocks);
+            blocks.push(line.trim().to_string());
+        } else {
+            current.push(line);
+        }
+    }
+    flush(&mut current, &mut blocks);
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_headings_split_paragraphs_and_set_title() {
+        let doc = extract_markdown(
+            "# Chain Care\nKeep the chain clean.\n## Adjusting slack\n\
+             Loosen the axle nut.\n\n- Check slack\n- Tighten the nut\n",
+        );
+
+        assert_eq!(doc.title.as_deref(), Some("Chain Care"));
+        assert_eq!(
+            doc.pages[0].text,
+            "# Chain Care\n\nKeep the chain clean.\n\n## Adjusting slack\n\n\
+             Loosen the axle nut.\n\n- Check slack\n- Tighten the nut"
+        );
+    }
+
+    #[test]
+    fn test_plain_text_keeps_hash_lines() {
+        let doc = extract_plain_text("\u{feff}# not a heading\nmore\n\n\nNext   paragraph");
+
+        assert_eq!(doc.title, None);
+        assert_eq!(doc.pages[0].text, "# not a heading\nmore\n\nNext paragraph");
+    }
+
+    #[test]
+    fn test_heading_and_list_detection() {
+        assert_eq!(heading_text("### Brakes ###"), Some("Brakes"));
+        assert_eq!(heading_text("#hashtag"), None);
+        assert_eq!(heading_text("####### too deep"), None);
+        assert!(is_list_block("1. Drain\n2) Refill\n* Check"));
+        assert!(!is_list_block("- item\nprose"));
+        assert!(!is_list_item("-5 degrees"));
+    }
+}
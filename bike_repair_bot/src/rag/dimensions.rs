@@ -0,0 +1,62 @@
+use anyhow::{Context, Result};
+
+use crate::ai::AiProvider;
+
+/// Output dimensions of known embedding models
+const EMBEDDING_DIMENSIONS: &[(&str, usize)] = &[
+    ("text-embedding-3-small", 1536),
+    ("text-embedding-3-large", 3072),
+    ("text-embedding-ada-002", 1536),
+];
+
+/// Registered output dimension for an embedding model
+pub fn embedding_dimension(model: &str) -> Option<usize> {
+    EMBEDDING_DIMENSIONS
+        .iter()
+        .find(|(name, _)| *name == model)
+        .map(|(_, dimension)| *dimension)
+}
+
+/// Dimension for `model`, probing the provider with one embedding when the
+/// model is not in the registry
+pub async fn resolve_embedding_dimension(model: &str, provider: &dyn AiProvider) -> Result<usize> {
+    if let Some(dimension) = embedding_dimension(model) {
+        return Ok(dimension);
+    }
+
+    log::info!("Embedding model '{}' not registered; probing its dimension", model);
+    let probe = provider
+        .generate_embedding("dimension probe")
+        .await
+        .with_context(|| format!("Failed to probe embedding dimension of '{}'", model))?;
+    if probe.is_empty() {
+        anyhow::bail!("Embedding model '{}' returned an empty vector", model);
+    }
+    Ok(probe.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::{MockProvider, MOCK_EMBEDDING_DIM};
+
+    #[test]
+    fn test_registered_dimensions() {
+        assert_eq!(embedding_dimension("text-embedding-3-small"), Some(1536));
+        assert_eq!(embedding_dimension("text-embedding-3-large"), Some(3072));
+        assert_eq!(embedding_dimension("text-embedding-ada-002"), Some(1536));
+        assert_eq!(embedding_dimension("my-custom-embedder"), None);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_model_is_probed() {
+        let provider = MockProvider::new("");
+
+        let known = resolve_embedding_dimension("text-embedding-3-large", &provider).await.unwrap();
+        let probed = resolve_embedding_dimension("my-custom-embedder", &provider).await.unwrap();
+
+        assert_eq!(known, 3072);
+        assert_eq!(probed, MOCK_EMBEDDING_DIM);
+        assert_eq!(provider.embedded_texts.lock().unwrap().len(), 1);
+    }
+}
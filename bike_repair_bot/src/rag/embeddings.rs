@@ -1,12 +1,120 @@
-// Placeholder - will implement full RAG later
-// For now, this module is a stub
-
 use anyhow::Result;
+use std::sync::Arc;
+
+use crate::ai::{AiProvider, BatchEmbeddings};
+use crate::rag::truncate_to_tokens;
+
+/// Inputs sent per embedding request
+pub const EMBEDDING_BATCH_SIZE: usize = 100;
+
+/// Maximum input tokens accepted by OpenAI embedding models
+pub const DEFAULT_EMBEDDING_MAX_INPUT_TOKENS: usize = 8191;
 
-pub struct EmbeddingGenerator;
+/// Embeds chunk text, truncating inputs that exceed the model's token limit
+pub struct EmbeddingGenerator {
+    ai_provider: Arc<dyn AiProvider>,
+
+    /// Model name used to pick the tokenizer
+    model: String,
+
+    /// Inputs longer than this are truncated before embedding
+    max_input_tokens: usize,
+}
 
 impl EmbeddingGenerator {
-    pub fn new() -> Self {
-        Self
+    pub fn new(
+        ai_provider: Arc<dyn AiProvider>,
+        model: impl Into<String>,
+        max_input_tokens: usize,
+    ) -> Self {
+        Self {
+            ai_provider,
+            model: model.into(),
+            max_input_tokens,
+        }
+    }
+
+    /// Truncate a text to the model's input limit, logging when it happens
+    pub fn prepare_input(&self, text: &str) -> String {
+        match truncate_to_tokens(&self.model, text, self.max_input_tokens) {
+            Some(truncated) => {
+                log::warn!(
+                    "Embedding input truncated to {} tokens ({} -> {} chars)",
+                    self.max_input_tokens,
+                    text.len(),
+                    truncated.len()
+                );
+                truncated
+            }
+            None => text.to_string(),
+        }
+    }
+
+    /// Embed a single text
+    pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let input = self.prepare_input(text);
+        self.ai_provider.generate_embedding(&input).await
+    }
+
+    /// Embed multiple texts in batch
+    pub async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let inputs = texts.iter().map(|t| self.prepare_input(t)).collect();
+        self.ai_provider.generate_embeddings_batch(inputs).await
+    }
+
+    /// Embed texts in batches, reporting failed batches instead of erroring
+    pub async fn embed_batch_partial(&self, texts: Vec<String>) -> BatchEmbeddings {
+        let inputs = texts.iter().map(|t| self.prepare_input(t)).collect();
+        self.ai_provider
+            .generate_embeddings_partial(inputs, EMBEDDING_BATCH_SIZE)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::MockProvider;
+    use crate::rag::count_tokens;
+
+    const MODEL: &str = "text-embedding-3-small";
+
+    #[tokio::test]
+    async fn test_oversized_input_is_embedded_without_error() {
+        let provider = Arc::new(MockProvider::new("").with_embedding_token_limit(8191));
+        let long_text = "Remove the rear wheel and inspect the sprocket teeth. ".repeat(1500);
+        assert!(count_tokens(MODEL, &long_text) > 8191);
+
+        // The raw provider rejects it like the API would
+        assert!(provider.generate_embedding(&long_text).await.is_err());
+
+        let generator = EmbeddingGenerator::new(provider.clone(), MODEL, 8191);
+        assert!(generator.embed(&long_text).await.is_ok());
+        let batch = generator
+            .embed_batch(vec![long_text, "short".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(batch.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_batch_reported_as_partial_success() {
+        let provider = Arc::new(MockProvider::new("").with_failing_embeddings("POISON"));
+        let generator = EmbeddingGenerator::new(provider.clone(), MODEL, 8191);
+        let mut texts: Vec<String> = (0..250).map(|i| format!("chunk {}", i)).collect();
+        texts[130] = "POISON chunk".to_string();
+
+        let result = generator.embed_batch_partial(texts).await;
+
+        // Batch 100..200 fails on every attempt; the other two succeed
+        assert!(!result.is_complete());
+        assert_eq!(result.failed(), (100..200).collect::<Vec<_>>());
+        assert_eq!(result.succeeded().len(), 150);
+        assert_eq!(result.errors.len(), 1);
+        assert!(result.errors[0].starts_with("inputs 100..200"));
+        assert_eq!(
+            provider.embedding_batch_calls.load(std::sync::atomic::Ordering::Relaxed),
+            3 + crate::ai::EMBEDDING_BATCH_RETRIES as usize
+        );
     }
 }
@@ -0,0 +1,131 @@
+use anyhow::Result;
+
+use crate::ai::AiProvider;
+use crate::rag::VectorStore;
+
+/// Outcome of a re-embedding migration
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MigrationReport {
+    /// Chunks in the source collection
+    pub total: usize,
+
+    /// Chunks already present in the target (from an interrupted run)
+    pub skipped: usize,
+
+    /// Chunks embedded and written during this run
+    pub embedded: usize,
+}
+
+/// Re-embed every chunk of `source` with `provider` and write it into `target`.
+///
+/// Chunks whose id already exists in the target are skipped, so an interrupted
+/// migration can simply be re-run. The source collection is never modified.
+pub async fn reembed_collection(
+    source: &VectorStore,
+    target: &VectorStore,
+    provider: &dyn AiProvider,
+    batch_size: usize,
+    log_every: usize,
+) -> Result<MigrationReport> {
+    let chunks = source.scroll().await;
+    let existing = target.ids().await;
+
+    let mut report = MigrationReport {
+        total: chunks.len(),
+        ..Default::default()
+    };
+
+    let pending: Vec<_> = chunks
+        .into_iter()
+        .filter(|c| !existing.contains(&c.id))
+        .collect();
+    report.skipped = report.total - pending.len();
+
+    log::info!(
+        "Re-embedding {} chunks from '{}' into '{}' ({} already migrated)",
+        pending.len(),
+        source.collection(),
+        target.collection(),
+        report.skipped
+    );
+
+    let mut last_logged = 0;
+    for batch in pending.chunks(batch_size.max(1)) {
+        let texts = batch.iter().map(|c| c.text.clone()).collect();
+        let embeddings = provider.generate_embeddings_batch(texts).await?;
+        if embeddings.len() != batch.len() {
+            anyhow::bail!(
+                "Provider returned {} embeddings for {} chunks",
+                embeddings.len(),
+                batch.len()
+            );
+        }
+
+        let migrated = batch
+            .iter()
+            .cloned()
+            .zip(embeddings)
+            .map(|(chunk, embedding)| chunk.with_embedding(embedding))
+            .collect();
+        target.upsert(migrated).await?;
+
+        report.embedded += batch.len();
+        if log_every > 0 && report.embedded - last_logged >= log_every {
+            last_logged = report.embedded;
+            log::info!("Re-embedded {}/{} chunks", report.embedded, pending.len());
+        }
+    }
+
+    log::info!(
+        "Re-embedding complete: {} embedded, {} skipped",
+        report.embedded,
+        report.skipped
+    );
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::{MockProvider, MOCK_EMBEDDING_DIM};
+    use crate::models::{ChunkMetadata, DocumentChunk};
+    use crate::rag::{Distance, DEFAULT_COLLECTION};
+    use tempfile::TempDir;
+
+    fn old_chunk(text: &str) -> DocumentChunk {
+        DocumentChunk::new("doc-1", text, ChunkMetadata::new("KTM Duke 390"))
+            .with_embedding(vec![0.5, 0.5, 0.5])
+    }
+
+    #[tokio::test]
+    async fn test_reembed_into_new_dimension_and_resume() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        let source = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        source
+            .upsert(vec![old_chunk("chain slack"), old_chunk("valve clearance"), old_chunk("coolant")])
+            .await
+            .unwrap();
+
+        // Simulate an interrupted run: one chunk already migrated
+        let target = VectorStore::open(path, "bike_manuals_v2", Distance::Cosine).await.unwrap();
+        let first = source.scroll().await.remove(0);
+        target
+            .upsert(vec![first.clone().with_embedding(MockProvider::embed(&first.text))])
+            .await
+            .unwrap();
+
+        let provider = MockProvider::new("");
+        let report = reembed_collection(&source, &target, &provider, 2, 1).await.unwrap();
+
+        assert_eq!(report, MigrationReport { total: 3, skipped: 1, embedded: 2 });
+        assert_eq!(target.count().await, 3);
+        assert_eq!(target.dimension().await, Some(MOCK_EMBEDDING_DIM));
+        assert_eq!(provider.embedded_texts.lock().unwrap().len(), 2);
+
+        // Source collection is untouched
+        assert_eq!(source.dimension().await, Some(3));
+    }
+}
@@ -1,10 +1,21 @@
-// Placeholder for RAG pipeline module
-// Will be implemented in Phase 5
+// Embedded RAG pipeline: vector storage and retrieval
 
+pub mod dimensions;
 pub mod embeddings;
+pub mod tokenizer;
 pub mod vector_store;
 pub mod retriever;
+pub mod migration;
+pub mod synonyms;
+pub mod similarity;
+pub mod snapshot;
 
+pub use dimensions::*;
 pub use embeddings::*;
+pub use tokenizer::*;
 pub use vector_store::*;
 pub use retriever::*;
+pub use migration::*;
+pub use synonyms::*;
+pub use similarity::*;
+pub use snapshot::*;
@@ -1,9 +1,583 @@
-// Placeholder - will implement retriever later
+use anyhow::Result;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-pub struct Retriever;
+use crate::ai::AiProvider;
+use crate::models::{ContextChunk, Source};
+use crate::pdf::TABLE_CONTENT_TYPE;
+use crate::rag::{SearchFilter, SearchResult, SynonymMap, VectorStore};
+
+/// Query words asking for values that manuals keep in tables
+pub const SPEC_QUERY_TERMS: &[&str] = &[
+    "torque",
+    "spec",
+    "specs",
+    "specification",
+    "specifications",
+    "interval",
+    "intervals",
+    "capacity",
+    "capacities",
+];
+
+/// Score added to table chunks for spec queries
+pub const TABLE_BOOST: f32 = 0.1;
+
+/// Least time left in a request for retrieval to run its optional stages:
+/// synonym expansion and the wider search for spec tables
+pub const OPTIONAL_STAGES_MIN_BUDGET: Duration = Duration::from_secs(10);
+
+/// True when the query asks for specs, torques, intervals or capacities
+pub fn is_spec_query(query: &str) -> bool {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| SPEC_QUERY_TERMS.contains(&word.to_lowercase().as_str()))
+}
+
+/// Stage timings and quality signals for one retrieval
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetrievalTrace {
+    /// Time spent embedding the query
+    pub embed_ms: f64,
+
+    /// Time spent in the vector search
+    pub search_ms: f64,
+
+    /// Time spent filtering and reordering search results
+    pub rerank_ms: f64,
+
+    /// Candidates returned by the vector search before filtering
+    pub candidates_considered: usize,
+
+    /// Best relevance score among candidates
+    pub top_score: Option<f32>,
+
+    /// Optional stages were skipped because the request was short of time
+    pub optional_stages_skipped: bool,
+}
+
+/// Retrieves manual chunks relevant to a user query
+pub struct Retriever {
+    ai_provider: Arc<dyn AiProvider>,
+    vector_store: Arc<VectorStore>,
+
+    /// Maximum chunks returned per query
+    top_k: AtomicUsize,
+
+    /// Minimum similarity score for a chunk to be used (f32 bits)
+    min_confidence: AtomicU32,
+
+    /// Regional term variants added to queries before embedding
+    synonyms: SynonymMap,
+}
 
 impl Retriever {
-    pub fn new() -> Self {
-        Self
+    pub fn new(
+        ai_provider: Arc<dyn AiProvider>,
+        vector_store: Arc<VectorStore>,
+        top_k: usize,
+        min_confidence: f32,
+    ) -> Self {
+        Self {
+            ai_provider,
+            vector_store,
+            top_k: AtomicUsize::new(top_k),
+            min_confidence: AtomicU32::new(min_confidence.to_bits()),
+            synonyms: SynonymMap::with_defaults(),
+        }
+    }
+
+    pub fn with_synonyms(mut self, synonyms: SynonymMap) -> Self {
+        self.synonyms = synonyms;
+        self
+    }
+
+    /// Current (top_k, min_confidence)
+    pub fn params(&self) -> (usize, f32) {
+        (
+            self.top_k.load(Ordering::Relaxed),
+            f32::from_bits(self.min_confidence.load(Ordering::Relaxed)),
+        )
+    }
+
+    /// Change retrieval parameters at runtime
+    pub fn update_params(&self, top_k: usize, min_confidence: f32) {
+        self.top_k.store(top_k, Ordering::Relaxed);
+        self.min_confidence.store(min_confidence.to_bits(), Ordering::Relaxed);
+        log::info!("Retrieval params updated: top_k {}, min_confidence {}", top_k, min_confidence);
+    }
+
+    /// Embed the query and search the caller's tenant namespace, optionally
+    /// filtered by bike model
+    pub async fn retrieve(
+        &self,
+        query: &str,
+        tenant_id: &str,
+        bike_model: Option<&str>,
+    ) -> Result<(Vec<SearchResult>, RetrievalTrace)> {
+        self.retrieve_within(query, tenant_id, bike_model, Duration::MAX).await
+    }
+
+    /// Like [`Retriever::retrieve`] for a request with `budget` left; short of
+    /// [`OPTIONAL_STAGES_MIN_BUDGET`] only the embedding and search run
+    #[tracing::instrument(skip_all, fields(tenant_id = %tenant_id, bike_model = bike_model))]
+    pub async fn retrieve_within(
+        &self,
+        query: &str,
+        tenant_id: &str,
+        bike_model: Option<&str>,
+        budget: Duration,
+    ) -> Result<(Vec<SearchResult>, RetrievalTrace)> {
+        let mut trace = RetrievalTrace {
+            optional_stages_skipped: budget < OPTIONAL_STAGES_MIN_BUDGET,
+            ..Default::default()
+        };
+        let (top_k, min_confidence) = self.params();
+        if trace.optional_stages_skipped {
+            log::debug!("Skipping optional retrieval stages with {:?} left", budget);
+        }
+
+        let expanded = if trace.optional_stages_skipped {
+            query.to_string()
+        } else {
+            self.synonyms.expand(query)
+        };
+        if expanded != query {
+            log::debug!("Query expanded with synonyms: {}", expanded);
+        }
+
+        let started = Instant::now();
+        let embedding = self.ai_provider.generate_embedding(&expanded).await?;
+        trace.embed_ms = elapsed_ms(started);
+
+        // Spec queries look further down the ranking for tables worth boosting
+        let spec_query = !trace.optional_stages_skipped && is_spec_query(query);
+        let limit = if spec_query { top_k * 2 } else { top_k };
+
+        let started = Instant::now();
+        let filter = SearchFilter::tenant(tenant_id).with_bike_model(bike_model);
+        let mut candidates = self
+            .vector_store
+            .search(&embedding, limit, &filter)
+            .await?;
+        trace.search_ms = elapsed_ms(started);
+        trace.candidates_considered = candidates.len();
+        trace.top_score = candidates.first().map(|r| r.score);
+
+        let started = Instant::now();
+        if spec_query {
+            boost_tables(&mut candidates);
+        }
+        let results: Vec<SearchResult> = candidates
+            .into_iter()
+            .filter(|r| r.score >= min_confidence)
+            .take(top_k)
+            .collect();
+        let results = order_for_context(results);
+        trace.rerank_ms = elapsed_ms(started);
+
+        log::info!(
+            "Retrieved {} chunks (tenant: {}, bike_model: {:?}) embed={:.1}ms search={:.1}ms rerank={:.1}ms \
+             candidates={} top_score={:?}",
+            results.len(),
+            tenant_id,
+            bike_model,
+            trace.embed_ms,
+            trace.search_ms,
+            trace.rerank_ms,
+            trace.candidates_considered,
+            trace.top_score
+        );
+
+        Ok((results, trace))
+    }
+
+    /// The `top_k` chunks nearest to the query in the tenant's namespace, by
+    /// descending score, without the confidence cut-off or spec reordering
+    /// applied to answers
+    pub async fn search(
+        &self,
+        query: &str,
+        tenant_id: &str,
+        bike_model: Option<&str>,
+        top_k: usize,
+    ) -> Result<Vec<SearchResult>> {
+        let embedding = self
+            .ai_provider
+            .generate_embedding(&self.synonyms.expand(query))
+            .await?;
+        let filter = SearchFilter::tenant(tenant_id).with_bike_model(bike_model);
+        self.vector_store.search(&embedding, top_k, &filter).await
+    }
+
+    pub fn vector_store(&self) -> &Arc<VectorStore> {
+        &self.vector_store
+    }
+}
+
+/// Raise table chunks by `TABLE_BOOST` and re-rank by score
+fn boost_tables(results: &mut [SearchResult]) {
+    for result in results.iter_mut() {
+        if result.chunk.metadata.content_type.as_deref() == Some(TABLE_CONTENT_TYPE) {
+            result.score = (result.score + TABLE_BOOST).min(1.0);
+        }
+    }
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+}
+
+/// Order results for the prompt: chunks of the same document section (or
+/// page, when the section is unknown) are kept together, groups by their best
+/// score, and chunks within a group by page and position so procedure steps
+/// read in sequence
+pub fn order_for_context(results: Vec<SearchResult>) -> Vec<SearchResult> {
+    let mut groups: Vec<(f32, Vec<SearchResult>)> = Vec::new();
+    for result in results {
+        let group = groups
+            .iter_mut()
+            .find(|(_, members)| same_passage(&members[0], &result));
+        match group {
+            Some((best, members)) => {
+                *best = best.max(result.score);
+                members.push(result);
+            }
+            None => groups.push((result.score, vec![result])),
+        }
+    }
+
+    // Stable sorts: ties keep the order the search returned them in
+    groups.sort_by(|a, b| b.0.total_cmp(&a.0));
+    groups
+        .into_iter()
+        .flat_map(|(_, mut members)| {
+            members.sort_by_key(|r| (r.chunk.metadata.page_number, r.chunk.metadata.chunk_index));
+            members
+        })
+        .collect()
+}
+
+/// Whether two chunks come from the same section of the same manual, or the
+/// same page when either has no section
+fn same_passage(a: &SearchResult, b: &SearchResult) -> bool {
+    let (a_meta, b_meta) = (&a.chunk.metadata, &b.chunk.metadata);
+    if a.chunk.document_id != b.chunk.document_id || a_meta.volume != b_meta.volume {
+        return false;
+    }
+    match (&a_meta.section, &b_meta.section) {
+        (Some(a_section), Some(b_section)) => a_section == b_section,
+        _ => a_meta.page_number.is_some() && a_meta.page_number == b_meta.page_number,
+    }
+}
+
+fn elapsed_ms(started: Instant) -> f64 {
+    started.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Format retrieved chunks as prompt context with source markers
+pub fn build_context(results: &[SearchResult]) -> Option<String> {
+    if results.is_empty() {
+        return None;
+    }
+
+    let context = results
+        .iter()
+        .enumerate()
+        .map(|(i, r)| {
+            let volume = r
+                .chunk
+                .metadata
+                .volume
+                .map(|v| format!(", volume {}", v))
+                .unwrap_or_default();
+            let page = r
+                .chunk
+                .metadata
+                .page_number
+                .map(|p| format!(", page {}", p))
+                .unwrap_or_default();
+            format!(
+                "[Source {}: {}{}{}]\n{}",
+                i + 1,
+                r.chunk.metadata.bike_model,
+                volume,
+                page,
+                r.chunk.text
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    Some(context)
+}
+
+/// Convert retrieved chunks into response citations, quoting up to
+/// `excerpt_chars` characters of each chunk (`None` leaves excerpts out)
+pub fn to_sources(results: &[SearchResult], excerpt_chars: Option<usize>) -> Vec<Source> {
+    results
+        .iter()
+        .map(|r| Source {
+            chunk_id: r.chunk.id.clone(),
+            document_id: r.chunk.document_id.clone(),
+            bike_model: r.chunk.metadata.bike_model.clone(),
+            page_number: r.chunk.metadata.page_number,
+            volume: r.chunk.metadata.volume,
+            section: r.chunk.metadata.section.clone(),
+            relevance_score: r.score,
+            excerpt: excerpt_chars
+                .map(|max_chars| excerpt(&r.chunk.text, max_chars))
+                .filter(|excerpt| !excerpt.is_empty()),
+        })
+        .collect()
+}
+
+/// Full text of retrieved chunks with their citations
+pub fn to_context(results: &[SearchResult]) -> Vec<ContextChunk> {
+    to_sources(results, None)
+        .into_iter()
+        .zip(results)
+        .map(|(source, r)| ContextChunk {
+            source,
+            text: r.chunk.text.clone(),
+        })
+        .collect()
+}
+
+/// The first `max_chars` characters of `text` on one line, without the
+/// `[Source N: ...]` labels context assembly adds; a longer text is cut back
+/// to the last whole word and ends in an ellipsis
+pub fn excerpt(text: &str, max_chars: usize) -> String {
+    let text = strip_source_markers(text)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let Some((cut, _)) = text.char_indices().nth(max_chars) else {
+        return text;
+    };
+    let head = &text[..cut];
+    // Unless the cut falls between two words, drop the word it splits; a
+    // single word longer than the limit is cut mid-word instead
+    let head = match head.rfind(' ') {
+        Some(space) if !text[cut..].starts_with(' ') => &head[..space],
+        _ => head,
+    };
+    format!("{}…", head.trim_end_matches([' ', ',', ';', ':', '.', '-']))
+}
+
+/// Remove `[Source N...]` labels, leaving other bracketed text alone
+fn strip_source_markers(text: &str) -> String {
+    const MARKER: &str = "[Source ";
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(MARKER) {
+        let label = &rest[start + MARKER.len()..];
+        let end = label
+            .find(']')
+            .filter(|_| label.starts_with(|c: char| c.is_ascii_digit()));
+        match end {
+            Some(end) => {
+                stripped.push_str(&rest[..start]);
+                stripped.push(' ');
+                rest = &label[end + 1..];
+            }
+            None => {
+                stripped.push_str(&rest[..start + MARKER.len()]);
+                rest = label;
+            }
+        }
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// Ids of the chunks an answer was based on
+pub fn source_ids(results: &[SearchResult]) -> Vec<String> {
+    results.iter().map(|r| r.chunk.id.clone()).collect()
+}
+
+/// Manual type (lowercased) whose chunks add up to the most relevance among
+/// the results; `None` when no result is tagged with one
+pub fn dominant_manual_type(results: &[SearchResult]) -> Option<String> {
+    let mut weights: Vec<(String, f32)> = Vec::new();
+    for result in results {
+        let Some(manual_type) = result.chunk.metadata.manual_type.as_deref() else {
+            continue;
+        };
+        let manual_type = manual_type.trim().to_lowercase();
+        if manual_type.is_empty() {
+            continue;
+        }
+        match weights.iter_mut().find(|(t, _)| *t == manual_type) {
+            Some((_, weight)) => *weight += result.score,
+            None => weights.push((manual_type, result.score)),
+        }
+    }
+    weights
+        .into_iter()
+        .reduce(|best, next| if next.1 > best.1 { next } else { best })
+        .map(|(manual_type, _)| manual_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::MockProvider;
+    use crate::models::{ChunkMetadata, DocumentChunk, DEFAULT_TENANT};
+    use crate::rag::{Distance, DEFAULT_COLLECTION};
+    use tempfile::TempDir;
+
+    async fn retriever_with(texts: &[&str], synonyms: SynonymMap) -> (Retriever, TempDir) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine)
+            .await
+            .unwrap();
+        let chunks = texts
+            .iter()
+            .map(|text| {
+                DocumentChunk::new("doc-1", *text, ChunkMetadata::new("Harley Sportster"))
+                    .with_embedding(MockProvider::embed(text))
+            })
+            .collect();
+        store.upsert(chunks).await.unwrap();
+
+        let provider = Arc::new(MockProvider::new(""));
+        let retriever = Retriever::new(provider, Arc::new(store), 5, 0.45).with_synonyms(synonyms);
+        (retriever, dir)
+    }
+
+    #[tokio::test]
+    async fn test_synonyms_bridge_regional_terms() {
+        let texts = ["Replace the gas filter"];
+
+        let (plain, _dir) = retriever_with(&texts, SynonymMap::default()).await;
+        let (results, _) = plain.retrieve("petrol filter", DEFAULT_TENANT, None).await.unwrap();
+        assert!(results.is_empty());
+
+        let (expanded, _dir) = retriever_with(&texts, SynonymMap::with_defaults()).await;
+        let (results, _) = expanded.retrieve("petrol filter", DEFAULT_TENANT, None).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].chunk.text, "Replace the gas filter");
+
+        let short = Duration::from_secs(1);
+        let (results, trace) = expanded
+            .retrieve_within("petrol filter", DEFAULT_TENANT, None, short)
+            .await
+            .unwrap();
+        assert!(results.is_empty());
+        assert!(trace.optional_stages_skipped);
+    }
+
+    #[test]
+    fn test_spec_queries_boost_tables() {
+        assert!(is_spec_query("What is the rear axle torque?"));
+        assert!(is_spec_query("Oil change interval"));
+        assert!(!is_spec_query("Special tools for chain removal"));
+
+        let result = |text: &str, score: f32, content_type: Option<&str>| {
+            let mut metadata = ChunkMetadata::new("Yamaha R1");
+            metadata.content_type = content_type.map(String::from);
+            SearchResult {
+                chunk: DocumentChunk::new("doc-1", text, metadata),
+                score,
+                raw_score: score,
+            }
+        };
+        let mut results = vec![
+            result("Torque the axle nut in steps.", 0.8, None),
+            result("| Rear axle nut | 90 Nm |", 0.75, Some(TABLE_CONTENT_TYPE)),
+        ];
+
+        boost_tables(&mut results);
+
+        assert_eq!(results[0].chunk.text, "| Rear axle nut | 90 Nm |");
+        assert!((results[0].score - 0.85).abs() < 1e-6);
+        assert_eq!(results[1].score, 0.8);
+    }
+
+    #[test]
+    fn test_dominant_manual_type_weighs_relevance() {
+        let result = |score: f32, manual_type: Option<&str>| {
+            let mut metadata = ChunkMetadata::new("Yamaha R1");
+            metadata.manual_type = manual_type.map(String::from);
+            SearchResult {
+                chunk: DocumentChunk::new("doc-1", "text", metadata),
+                score,
+                raw_score: score,
+            }
+        };
+
+        let results = vec![
+            result(0.9, Some("Service")),
+            result(0.6, Some("parts")),
+            result(0.5, Some("Parts ")),
+            result(0.95, None),
+        ];
+        assert_eq!(dominant_manual_type(&results).as_deref(), Some("parts"));
+        assert_eq!(dominant_manual_type(&results[3..]), None);
+    }
+
+    #[test]
+    fn test_context_keeps_procedure_steps_in_page_order() {
+        let result = |text: &str, score: f32, page: u32, index: usize, section: &str| {
+            let mut metadata = ChunkMetadata::new("Yamaha R1");
+            metadata.page_number = Some(page);
+            metadata.chunk_index = index;
+            metadata.section = Some(section.to_string());
+            SearchResult {
+                chunk: DocumentChunk::new("doc-1", text, metadata),
+                score,
+                raw_score: score,
+            }
+        };
+        // As ranked by the search: best match first, steps out of order
+        let retrieved = vec![
+            result("3. Tighten the axle nut.", 0.9, 12, 31, "Chain slack"),
+            result("Chain lubricant: SAE 30", 0.85, 40, 80, "Lubrication"),
+            result("1. Loosen the axle nut.", 0.8, 11, 28, "Chain slack"),
+            result("2. Turn the adjusters evenly.", 0.7, 11, 29, "Chain slack"),
+        ];
+
+        let ordered = order_for_context(retrieved);
+
+        let context = build_context(&ordered).unwrap();
+        let positions: Vec<usize> = [
+            "1. Loosen",
+            "2. Turn",
+            "3. Tighten",
+            "Chain lubricant",
+        ]
+        .iter()
+        .map(|text| context.find(text).unwrap())
+        .collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]), "{}", context);
+        assert!(context.starts_with("[Source 1: Yamaha R1, page 11]"));
+    }
+
+    #[test]
+    fn test_sources_quote_chunk_excerpts() {
+        let text = "[Source 2: Yamaha R1, page 11]\nLoosen the axle nut,\n then turn the \
+                    adjusters evenly until the slack is 25-35 mm.";
+        let chunk = DocumentChunk::new("doc-1", text, ChunkMetadata::new("Yamaha R1"));
+        let results = [SearchResult {
+            chunk: chunk.clone(),
+            score: 0.8,
+            raw_score: 0.8,
+        }];
+
+        let sources = to_sources(&results, Some(300));
+        assert_eq!(sources[0].chunk_id, chunk.id);
+        assert_eq!(sources[0].document_id, "doc-1");
+        assert_eq!(
+            sources[0].excerpt.as_deref(),
+            Some("Loosen the axle nut, then turn the adjusters evenly until the slack is 25-35 mm.")
+        );
+        assert!(to_sources(&results, None)[0].excerpt.is_none());
+
+        // Cut back to the last whole word, without trailing punctuation
+        assert_eq!(excerpt(text, 23), "Loosen the axle nut…");
+        assert_eq!(excerpt(text, 20), "Loosen the axle nut…");
+        assert_eq!(excerpt("Handlebar", 4), "Hand…");
+        assert_eq!(excerpt("See [Source tables] below", 300), "See [Source tables] below");
     }
 }
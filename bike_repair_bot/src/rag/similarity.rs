@@ -0,0 +1,178 @@
+//! Vector similarity used when scoring and re-ranking candidates
+
+/// Cosine similarity of two vectors in -1.0..=1.0; 0.0 when either is all zeros.
+/// Vectors of different lengths are compared over the shorter one.
+///
+/// With the `simd` feature on x86_64, AVX and FMA are used when the CPU has them.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let (dot, norm_a, norm_b) = dot_and_norms(a, b);
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    let similarity = dot / (norm_a.sqrt() * norm_b.sqrt());
+    if similarity.is_nan() {
+        0.0
+    } else {
+        similarity.clamp(-1.0, 1.0)
+    }
+}
+
+/// Dot product of two vectors, over the shorter one
+pub fn dot_product(a: &[f32], b: &[f32]) -> f32 {
+    dot_and_norms(a, b).0
+}
+
+/// `(a·b, a·a, b·b)` in one pass over both vectors
+fn dot_and_norms(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if is_x86_feature_detected!("avx") && is_x86_feature_detected!("fma") {
+        // SAFETY: the required CPU features were just checked
+        return unsafe { avx::dot_and_norms(a, b) };
+    }
+    scalar_dot_and_norms(a, b)
+}
+
+fn scalar_dot_and_norms(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+    a.iter().zip(b).fold((0.0, 0.0, 0.0), |(dot, norm_a, norm_b), (x, y)| {
+        (dot + x * y, norm_a + x * x, norm_b + y * y)
+    })
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod avx {
+    use std::arch::x86_64::*;
+
+    const LANES: usize = 8;
+
+    /// # Safety
+    /// The CPU must support AVX and FMA.
+    #[target_feature(enable = "avx,fma")]
+    pub unsafe fn dot_and_norms(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+        let len = a.len().min(b.len());
+        let mut dot = _mm256_setzero_ps();
+        let mut norm_a = _mm256_setzero_ps();
+        let mut norm_b = _mm256_setzero_ps();
+
+        let mut i = 0;
+        while i + LANES <= len {
+            let x = _mm256_loadu_ps(a.as_ptr().add(i));
+            let y = _mm256_loadu_ps(b.as_ptr().add(i));
+            dot = _mm256_fmadd_ps(x, y, dot);
+            norm_a = _mm256_fmadd_ps(x, x, norm_a);
+            norm_b = _mm256_fmadd_ps(y, y, norm_b);
+            i += LANES;
+        }
+
+        let (tail_dot, tail_a, tail_b) = super::scalar_dot_and_norms(&a[i..len], &b[i..len]);
+        (
+            sum_lanes(dot) + tail_dot,
+            sum_lanes(norm_a) + tail_a,
+            sum_lanes(norm_b) + tail_b,
+        )
+    }
+
+    #[target_feature(enable = "avx")]
+    unsafe fn sum_lanes(v: __m256) -> f32 {
+        let mut lanes = [0.0f32; LANES];
+        _mm256_storeu_ps(lanes.as_mut_ptr(), v);
+        lanes.iter().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// Straightforward three-pass version, the baseline for the benchmark
+    fn naive_cosine(a: &[f32], b: &[f32]) -> f32 {
+        let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+        let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm_a == 0.0 || norm_b == 0.0 {
+            0.0
+        } else {
+            dot / (norm_a * norm_b)
+        }
+    }
+
+    /// Deterministic pseudo-random vector in -1.0..1.0
+    fn vector(seed: u32, dims: usize) -> Vec<f32> {
+        let mut state = seed.wrapping_mul(2_654_435_761).max(1);
+        (0..dims)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as f32 / u32::MAX as f32 * 2.0 - 1.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_identical_and_opposite_vectors() {
+        let v = [1.0, 2.0, 3.0];
+        assert!((cosine_similarity(&v, &v) - 1.0).abs() < 1e-6);
+        // Scale does not matter
+        assert!((cosine_similarity(&v, &[2.0, 4.0, 6.0]) - 1.0).abs() < 1e-6);
+        assert!((cosine_similarity(&v, &[-1.0, -2.0, -3.0]) + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_orthogonal_vectors() {
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]), 0.0);
+        assert!(cosine_similarity(&[1.0, 1.0, 0.0, 0.0], &[0.0, 0.0, 2.0, -3.0]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_zero_and_empty_vectors() {
+        assert_eq!(cosine_similarity(&[0.0; 4], &[1.0, 2.0, 3.0, 4.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0; 4], &[0.0; 4]), 0.0);
+        assert_eq!(cosine_similarity(&[], &[]), 0.0);
+    }
+
+    #[test]
+    fn test_matches_naive_version_on_embedding_sized_vectors() {
+        // 1537 dimensions also exercises the tail after the last full SIMD block
+        for dims in [1536, 1537] {
+            for seed in 1..20 {
+                let (a, b) = (vector(seed, dims), vector(seed + 100, dims));
+                let expected = naive_cosine(&a, &b);
+                assert!((cosine_similarity(&a, &b) - expected).abs() < 1e-4, "{} {}", dims, seed);
+                let dot: f32 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+                assert!((dot_product(&a, &b) - dot).abs() < 1e-2);
+            }
+        }
+    }
+
+    /// Run with `cargo test --release --features simd -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_against_naive_version() {
+        let query = vector(7, 1536);
+        let candidates: Vec<Vec<f32>> = (0..2_000).map(|seed| vector(seed, 1536)).collect();
+        let rounds = 50;
+
+        let time = |f: fn(&[f32], &[f32]) -> f32| {
+            let start = Instant::now();
+            let mut total = 0.0;
+            for _ in 0..rounds {
+                for candidate in &candidates {
+                    total += f(std::hint::black_box(&query), candidate);
+                }
+            }
+            (start.elapsed() / rounds, total)
+        };
+        let (naive, naive_total) = time(naive_cosine);
+        let (fast, fast_total) = time(cosine_similarity);
+
+        println!(
+            "{} candidates x 1536 dims: naive {:?}, cosine_similarity {:?} ({:.1}x)",
+            candidates.len(),
+            naive,
+            fast,
+            naive.as_secs_f64() / fast.as_secs_f64()
+        );
+        assert!((naive_total - fast_total).abs() / naive_total.abs().max(1.0) < 1e-3);
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::rag::VectorStore;
+
+/// Extension of snapshot files
+const SNAPSHOT_SUFFIX: &str = ".snapshot.json";
+
+/// A snapshot file and, when it was just written or restored, how long that took
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotInfo {
+    /// File name inside the snapshot directory
+    pub name: String,
+
+    /// Chunks written or restored; omitted when listing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<usize>,
+
+    pub size_bytes: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+/// Snapshot the active collection into `dir` as `<collection>-<UTC time>.snapshot.json`,
+/// then delete the collection's oldest snapshots beyond `keep` (0 keeps all)
+pub async fn create_snapshot(store: &VectorStore, dir: &Path, keep: usize) -> Result<SnapshotInfo> {
+    let started = Instant::now();
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create snapshot directory {}", dir.display()))?;
+
+    let name = format!(
+        "{}-{}{}",
+        store.collection(),
+        chrono::Utc::now().format("%Y%m%dT%H%M%S%3fZ"),
+        SNAPSHOT_SUFFIX
+    );
+    let path = dir.join(&name);
+    let chunks = store.snapshot(&path).await?;
+    let size_bytes = tokio::fs::metadata(&path).await?.len();
+    let info = SnapshotInfo {
+        name,
+        chunks: Some(chunks),
+        size_bytes,
+        duration_ms: Some(started.elapsed().as_millis() as u64),
+    };
+    log::info!(
+        "Snapshot {} written: {} chunks, {} bytes in {}ms",
+        info.name,
+        chunks,
+        size_bytes,
+        started.elapsed().as_millis()
+    );
+
+    if keep > 0 {
+        for old in list_snapshots(dir, store.collection()).await?.into_iter().skip(keep) {
+            tokio::fs::remove_file(dir.join(&old.name))
+                .await
+                .with_context(|| format!("Failed to delete old snapshot {}", old.name))?;
+            log::info!("Deleted old snapshot {}", old.name);
+        }
+    }
+
+    Ok(info)
+}
+
+/// Replace the collection's chunks with those of the snapshot `name` in `dir`.
+/// Returns `None` when no such snapshot exists.
+pub async fn restore_snapshot(
+    store: &VectorStore,
+    dir: &Path,
+    name: &str,
+) -> Result<Option<SnapshotInfo>> {
+    if !is_snapshot_name(name) {
+        anyhow::bail!("'{}' is not a snapshot file name", name);
+    }
+    let path = dir.join(name);
+    let size_bytes = match tokio::fs::metadata(&path).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let started = Instant::now();
+    let chunks = store.restore(&path).await?;
+    log::info!(
+        "Restored {} chunks into '{}' from snapshot {} in {}ms",
+        chunks,
+        store.collection(),
+        name,
+        started.elapsed().as_millis()
+    );
+    Ok(Some(SnapshotInfo {
+        name: name.to_string(),
+        chunks: Some(chunks),
+        size_bytes,
+        duration_ms: Some(started.elapsed().as_millis() as u64),
+    }))
+}
+
+/// Snapshots of `collection` in `dir`, newest first; a missing directory has none
+pub async fn list_snapshots(dir: &Path, collection: &str) -> Result<Vec<SnapshotInfo>> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let prefix = format!("{}-", collection);
+    let mut snapshots = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        // The timestamp has no '-', so `manuals-…` never matches `manuals-v2-…`
+        let of_collection = name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(SNAPSHOT_SUFFIX))
+            .is_some_and(|timestamp| !timestamp.contains('-'));
+        if of_collection && is_snapshot_name(&name) {
+            snapshots.push(SnapshotInfo {
+                size_bytes: entry.metadata().await?.len(),
+                name,
+                chunks: None,
+                duration_ms: None,
+            });
+        }
+    }
+
+    snapshots.sort_by(|a, b| b.name.cmp(&a.name));
+    Ok(snapshots)
+}
+
+/// A bare snapshot file name, which cannot point outside the snapshot directory
+fn is_snapshot_name(name: &str) -> bool {
+    name.ends_with(SNAPSHOT_SUFFIX)
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\'])
+        && !name.contains("..")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ChunkMetadata, DocumentChunk, DEFAULT_TENANT};
+    use crate::rag::{Distance, SearchFilter};
+    use tempfile::TempDir;
+
+    fn point(text: &str, embedding: Vec<f32>) -> DocumentChunk {
+        DocumentChunk::new("doc-1", text, ChunkMetadata::new("Yamaha R1")).with_embedding(embedding)
+    }
+
+    async fn texts(store: &VectorStore, query: &[f32]) -> Vec<(String, f32)> {
+        let filter = SearchFilter::tenant(DEFAULT_TENANT);
+        let results = store.search(query, 10, &filter).await.unwrap();
+        results.into_iter().map(|r| (r.chunk.text, r.score)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_restored_into_fresh_store_keeps_search_results() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let snapshots = dir.path().join("snapshots");
+        let store = VectorStore::new(path, "manuals", Distance::Cosine).await.unwrap();
+        store
+            .upsert(vec![point("chain", vec![1.0, 0.0]), point("brakes", vec![0.6, 0.8])])
+            .await
+            .unwrap();
+
+        let info = create_snapshot(&store, &snapshots, 0).await.unwrap();
+        assert_eq!(info.chunks, Some(2));
+        assert!(info.size_bytes > 0);
+
+        let fresh_dir = TempDir::new().unwrap();
+        let fresh_path = fresh_dir.path().to_str().unwrap();
+        let fresh = VectorStore::new(fresh_path, "manuals", Distance::Cosine).await.unwrap();
+        let restored = restore_snapshot(&fresh, &snapshots, &info.name).await.unwrap().unwrap();
+        assert_eq!(restored.chunks, Some(2));
+        assert_eq!(texts(&fresh, &[1.0, 0.0]).await, texts(&store, &[1.0, 0.0]).await);
+
+        // The restore is persisted, not only held in memory
+        let reopened = VectorStore::new(fresh_path, "manuals", Distance::Cosine).await.unwrap();
+        assert_eq!(reopened.count().await, 2);
+        assert_eq!(reopened.dimension().await, Some(2));
+
+        let euclid = VectorStore::new(fresh_path, "other", Distance::Euclid).await.unwrap();
+        assert!(restore_snapshot(&euclid, &snapshots, &info.name).await.is_err());
+        let missing = "manuals-20000101T000000000Z.snapshot.json";
+        assert!(restore_snapshot(&fresh, &snapshots, missing).await.unwrap().is_none());
+        assert!(restore_snapshot(&fresh, &snapshots, "../manuals.json").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_old_snapshots_pruned_per_collection() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let snapshots = dir.path().join("snapshots");
+        let store = VectorStore::new(path, "manuals", Distance::Cosine).await.unwrap();
+        let other = VectorStore::new(path, "manuals-v2", Distance::Cosine).await.unwrap();
+        create_snapshot(&other, &snapshots, 1).await.unwrap();
+
+        let mut names = Vec::new();
+        for _ in 0..3 {
+            names.push(create_snapshot(&store, &snapshots, 2).await.unwrap().name);
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+
+        let kept = list_snapshots(&snapshots, "manuals").await.unwrap();
+        let kept: Vec<_> = kept.into_iter().map(|s| s.name).collect();
+        assert_eq!(kept, [names[2].clone(), names[1].clone()]);
+        assert_eq!(list_snapshots(&snapshots, "manuals-v2").await.unwrap().len(), 1);
+    }
+}
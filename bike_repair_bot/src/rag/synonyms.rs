@@ -0,0 +1,120 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+/// Regional spellings and terms that mean the same thing in a manual
+pub const DEFAULT_SYNONYMS: &[&[&str]] = &[
+    &["tire", "tyre"],
+    &["gas", "petrol", "gasoline"],
+    &["muffler", "silencer"],
+    &["carburetor", "carburettor"],
+    &["aluminum", "aluminium"],
+    &["wrench", "spanner"],
+    &["fender", "mudguard"],
+    &["kickstand", "sidestand"],
+    &["windshield", "windscreen"],
+];
+
+/// Maps query words to equivalent terms so regional wording doesn't miss chunks
+#[derive(Debug, Clone, Default)]
+pub struct SynonymMap {
+    /// Lowercase word -> the other words in its group
+    synonyms: HashMap<String, Vec<String>>,
+}
+
+impl SynonymMap {
+    /// Map containing the built-in groups
+    pub fn with_defaults() -> Self {
+        let mut map = Self::default();
+        for group in DEFAULT_SYNONYMS {
+            map.add_group(group.iter().copied());
+        }
+        map
+    }
+
+    /// Built-in groups extended by a file of comma-separated groups, one per line
+    /// (`#` starts a comment)
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read synonyms file {}", path))?;
+        let mut map = Self::with_defaults();
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if !line.is_empty() {
+                map.add_group(line.split(','));
+            }
+        }
+        Ok(map)
+    }
+
+    /// Treat every term in the group as equivalent to the others
+    pub fn add_group<'a>(&mut self, terms: impl IntoIterator<Item = &'a str>) {
+        let terms: Vec<String> = terms
+            .into_iter()
+            .map(|t| t.trim().to_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect();
+        for term in &terms {
+            let entry = self.synonyms.entry(term.clone()).or_default();
+            for other in &terms {
+                if other != term && !entry.contains(other) {
+                    entry.push(other.clone());
+                }
+            }
+        }
+    }
+
+    /// Every term known to the map
+    pub fn terms(&self) -> impl Iterator<Item = &str> {
+        self.synonyms.keys().map(String::as_str)
+    }
+
+    /// Append synonyms of the query's words that it doesn't already contain,
+    /// e.g. "petrol filter" -> "petrol filter (gas gasoline)"
+    pub fn expand(&self, query: &str) -> String {
+        let words: Vec<String> = query
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+
+        let mut extras: Vec<&str> = Vec::new();
+        for word in &words {
+            for synonym in self.synonyms.get(word).into_iter().flatten() {
+                if !words.contains(synonym) && !extras.contains(&synonym.as_str()) {
+                    extras.push(synonym);
+                }
+            }
+        }
+
+        if extras.is_empty() {
+            query.to_string()
+        } else {
+            format!("{} ({})", query, extras.join(" "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_adds_regional_variants() {
+        let map = SynonymMap::with_defaults();
+
+        assert_eq!(map.expand("Petrol filter?"), "Petrol filter? (gas gasoline)");
+        assert_eq!(map.expand("tire and tyre pressure"), "tire and tyre pressure");
+        assert_eq!(map.expand("chain slack"), "chain slack");
+    }
+
+    #[test]
+    fn test_load_extends_defaults() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "# custom\nfairing, cowl\n").unwrap();
+
+        let map = SynonymMap::load(file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(map.expand("cowl bolts"), "cowl bolts (fairing)");
+        assert_eq!(map.expand("tyre"), "tyre (tire)");
+    }
+}
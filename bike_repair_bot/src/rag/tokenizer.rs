@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use tiktoken_rs::CoreBPE;
+
+/// Tokenizers are expensive to build, so keep one per model for the process
+static TOKENIZERS: OnceLock<Mutex<HashMap<String, Arc<CoreBPE>>>> = OnceLock::new();
+
+/// Tokenizer for an OpenAI model, falling back to cl100k_base for unknown models
+pub fn tokenizer(model: &str) -> Arc<CoreBPE> {
+    let cache = TOKENIZERS.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut cache = cache.lock().unwrap();
+
+    cache
+        .entry(model.to_string())
+        .or_insert_with(|| {
+            let bpe = tiktoken_rs::get_bpe_from_model(model).unwrap_or_else(|_| {
+                tiktoken_rs::cl100k_base().expect("cl100k_base tokenizer is bundled")
+            });
+            Arc::new(bpe)
+        })
+        .clone()
+}
+
+/// Count tokens in `text` for `model`
+pub fn count_tokens(model: &str, text: &str) -> usize {
+    tokenizer(model).encode_with_special_tokens(text).len()
+}
+
+/// Truncate `text` to at most `max_tokens` tokens for `model`.
+/// Returns `None` when the text already fits.
+pub fn truncate_to_tokens(model: &str, text: &str, max_tokens: usize) -> Option<String> {
+    let bpe = tokenizer(model);
+    let tokens = bpe.encode_with_special_tokens(text);
+    if tokens.len() <= max_tokens {
+        return None;
+    }
+
+    // A cut can land inside a multi-byte character; back off until it decodes
+    let mut end = max_tokens;
+    while end > 0 {
+        if let Ok(truncated) = bpe.decode(tokens[..end].to_vec()) {
+            return Some(truncated);
+        }
+        end -= 1;
+    }
+
+    Some(String::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: &str = "text-embedding-3-small";
+
+    #[test]
+    fn test_short_text_not_truncated() {
+        assert!(truncate_to_tokens(MODEL, "Check the brake pads", 100).is_none());
+    }
+
+    #[test]
+    fn test_truncation_is_token_accurate() {
+        let text = "Torque the axle nut to spec. ".repeat(200);
+        let truncated = truncate_to_tokens(MODEL, &text, 50).unwrap();
+
+        assert!(count_tokens(MODEL, &truncated) <= 50);
+        assert!(count_tokens(MODEL, &truncated) >= 48);
+        assert!(text.starts_with(&truncated));
+    }
+
+    #[test]
+    fn test_truncation_keeps_valid_utf8() {
+        let text = "ブレーキ液を交換してください。".repeat(50);
+        let truncated = truncate_to_tokens(MODEL, &text, 7).unwrap();
+
+        assert!(count_tokens(MODEL, &truncated) <= 7);
+        assert!(text.starts_with(&truncated));
+    }
+}
@@ -1,11 +1,1323 @@
-// Placeholder - will implement vector database later
+//! Embedded vector store backing retrieval. It takes the place of the
+//! `qdrant-client` dependency the project started with, which was never used:
+//! that crate is a gRPC client and needs a separate Qdrant server, while the
+//! service is meant to run as one binary. A manual library is a few thousand
+//! chunks per tenant, so an exact scan over vectors held in memory is fast
+//! enough, and each collection lives in plain files under `VECTOR_STORE_PATH`
+//! that snapshots, `reembed` and backups can copy.
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::RwLock;
 
-pub struct VectorStore;
+use crate::models::{
+    normalize_model, BikeModelCount, ChunkMetadata, DocumentChunk, ManualTypeCount,
+};
+use crate::rag::{cosine_similarity, dot_product};
+
+/// Name of the collection holding manual chunks
+pub const DEFAULT_COLLECTION: &str = "bike_manuals";
+
+/// File recording which collection is active (an alias, flipped by migrations)
+const ALIAS_FILE: &str = "active_collection";
+
+/// The change log is folded into the collection file once it outgrows both the
+/// file and this size, so rewrites stay proportional to what was appended
+const MIN_COMPACT_LOG_BYTES: u64 = 1024 * 1024;
+
+/// Similarity metric used to compare vectors, fixed when the collection is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Distance {
+    Cosine,
+    Dot,
+    Euclid,
+}
+
+impl Distance {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Distance::Cosine => "cosine",
+            Distance::Dot => "dot",
+            Distance::Euclid => "euclid",
+        }
+    }
+
+    /// Raw score as the metric defines it (Euclid is a distance: lower is better)
+    pub fn raw_score(&self, a: &[f32], b: &[f32]) -> f32 {
+        if a.len() != b.len() {
+            return match self {
+                Distance::Euclid => f32::INFINITY,
+                _ => 0.0,
+            };
+        }
+
+        match self {
+            Distance::Cosine => cosine_similarity(a, b),
+            Distance::Dot => dot_product(a, b),
+            Distance::Euclid => a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| (x - y) * (x - y))
+                .sum::<f32>()
+                .sqrt(),
+        }
+    }
+
+    /// Map a raw score into 0.0-1.0 relevance.
+    ///
+    /// For unit-length embeddings (as produced by OpenAI) all three metrics yield the
+    /// same relevance for the same pair of vectors: dot product equals cosine, and
+    /// Euclidean distance relates to cosine via `cos = 1 - d²/2`. Negative similarity
+    /// is treated as irrelevant.
+    pub fn normalize(&self, raw: f32) -> f32 {
+        let similarity = match self {
+            Distance::Cosine | Distance::Dot => raw,
+            Distance::Euclid => 1.0 - raw * raw / 2.0,
+        };
+
+        if similarity.is_nan() {
+            0.0
+        } else {
+            similarity.clamp(0.0, 1.0)
+        }
+    }
+
+    /// Key that sorts better matches higher regardless of metric direction
+    fn rank_key(&self, raw: f32) -> f32 {
+        match self {
+            Distance::Euclid => -raw,
+            _ => raw,
+        }
+    }
+}
+
+impl FromStr for Distance {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "cosine" => Ok(Distance::Cosine),
+            "dot" => Ok(Distance::Dot),
+            "euclid" | "euclidean" => Ok(Distance::Euclid),
+            other => anyhow::bail!(
+                "Unknown vector distance '{}' (expected cosine, dot or euclid)",
+                other
+            ),
+        }
+    }
+}
+
+/// Collection metadata persisted next to the points
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CollectionMeta {
+    distance: Distance,
+
+    /// Vector dimension, recorded once the embedding model is known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dimension: Option<usize>,
+}
+
+/// Point-in-time copy of a collection, written by [`VectorStore::snapshot`]
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    collection: String,
+    distance: Distance,
+    dimension: Option<usize>,
+    points: Vec<DocumentChunk>,
+}
+
+/// A change appended to a collection's log and replayed over the collection
+/// file on open
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum LogEntry<'a> {
+    Upsert { chunks: Cow<'a, [DocumentChunk]> },
+    Delete { ids: Cow<'a, [String]> },
+}
+
+/// Stored chunks with the position of each by id, and the sizes of the files
+/// persisting them
+#[derive(Default)]
+struct Points {
+    chunks: Vec<DocumentChunk>,
+    by_id: HashMap<String, usize>,
+
+    /// Bytes in the collection file
+    file_bytes: u64,
+
+    /// Bytes appended to the log since the collection file was written
+    log_bytes: u64,
+}
+
+impl Points {
+    fn new(chunks: Vec<DocumentChunk>) -> Self {
+        let mut points = Self::default();
+        for chunk in chunks {
+            points.upsert(chunk);
+        }
+        points
+    }
+
+    /// Insert a chunk, or replace the one with its id
+    fn upsert(&mut self, chunk: DocumentChunk) {
+        match self.by_id.get(&chunk.id) {
+            Some(&index) => self.chunks[index] = chunk,
+            None => {
+                self.by_id.insert(chunk.id.clone(), self.chunks.len());
+                self.chunks.push(chunk);
+            }
+        }
+    }
+
+    /// Remove the chunks with these ids
+    fn remove(&mut self, ids: &[String]) {
+        let ids: HashSet<&str> = ids.iter().map(String::as_str).collect();
+        let before = self.chunks.len();
+        self.chunks.retain(|c| !ids.contains(c.id.as_str()));
+        if self.chunks.len() != before {
+            self.reindex();
+        }
+    }
+
+    fn apply(&mut self, entry: LogEntry) {
+        match entry {
+            LogEntry::Upsert { chunks } => {
+                for chunk in chunks.into_owned() {
+                    self.upsert(chunk);
+                }
+            }
+            LogEntry::Delete { ids } => self.remove(&ids),
+        }
+    }
+
+    fn reindex(&mut self) {
+        self.by_id = self.chunks.iter().enumerate().map(|(i, c)| (c.id.clone(), i)).collect();
+    }
+
+    fn compaction_due(&self) -> bool {
+        self.log_bytes > self.file_bytes.max(MIN_COMPACT_LOG_BYTES)
+    }
+}
+
+/// Embedded vector store. Each collection is persisted as a file under the
+/// storage path plus a log of the changes since, so a write costs the size of
+/// the change rather than of the collection.
+pub struct VectorStore {
+    /// Collection name
+    collection: String,
+
+    /// File backing the collection
+    collection_file: PathBuf,
+
+    /// Changes made since the collection file was written, one JSON entry per line
+    log_file: PathBuf,
+
+    /// Similarity metric of the collection
+    distance: Distance,
+
+    /// File holding the collection metadata
+    meta_file: PathBuf,
+
+    /// Vector dimension fixed for the collection, if recorded
+    dimension: RwLock<Option<usize>>,
+
+    /// Stored chunks (each with its embedding)
+    points: RwLock<Points>,
+}
+
+/// Payload conditions a search result must satisfy. Every search is scoped to
+/// one tenant; there is no filter that spans tenants.
+#[derive(Debug, Clone)]
+pub struct SearchFilter {
+    /// Only chunks from this tenant namespace
+    pub tenant_id: String,
+
+    /// Only chunks for this bike model (case-insensitive)
+    pub bike_model: Option<String>,
+}
+
+impl SearchFilter {
+    /// Filter restricted to one tenant
+    pub fn tenant(tenant_id: impl Into<String>) -> Self {
+        Self {
+            tenant_id: tenant_id.into(),
+            bike_model: None,
+        }
+    }
+
+    /// Restrict to chunks of `bike_model`, however either side spells it
+    pub fn with_bike_model(mut self, bike_model: Option<&str>) -> Self {
+        self.bike_model = bike_model.map(normalize_model);
+        self
+    }
+
+    fn matches(&self, chunk: &DocumentChunk) -> bool {
+        let tenant_ok = chunk.metadata.tenant_id == self.tenant_id;
+        let model_ok = self
+            .bike_model
+            .as_ref()
+            .map(|model| normalize_model(&chunk.metadata.bike_model) == *model)
+            .unwrap_or(true);
+        tenant_ok && model_ok
+    }
+}
+
+/// A chunk matched by a similarity search
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    /// Matched chunk (embedding omitted)
+    pub chunk: DocumentChunk,
+
+    /// Relevance normalized to 0.0-1.0
+    pub score: f32,
+
+    /// Score as reported by the collection's distance metric
+    pub raw_score: f32,
+}
 
 impl VectorStore {
-    pub async fn new(_storage_path: &str) -> Result<Self> {
-        Ok(Self)
+    /// Open the collection currently active under the name `collection` (following
+    /// its alias if a migration flipped it to another collection)
+    pub async fn new(storage_path: &str, collection: &str, distance: Distance) -> Result<Self> {
+        let active = Self::active_collection(storage_path, collection).await?;
+        Self::open(storage_path, &active, distance).await
+    }
+
+    /// Open the active collection like [`VectorStore::new`], retrying up to
+    /// `attempts` times in total so a transient startup failure is ridden out
+    pub async fn connect(
+        storage_path: &str,
+        collection: &str,
+        distance: Distance,
+        attempts: u32,
+        backoff: Duration,
+    ) -> Result<Self> {
+        retry_with_backoff("open vector store", attempts, backoff, || {
+            Self::new(storage_path, collection, distance)
+        })
+        .await
+    }
+
+    /// Open (creating if needed) a named collection
+    pub async fn open(storage_path: &str, collection: &str, distance: Distance) -> Result<Self> {
+        let dir = Path::new(storage_path);
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Failed to create storage directory {}", storage_path))?;
+
+        // The metric is fixed at creation time; refuse to reopen with a different one
+        let meta_file = dir.join(format!("{}.meta.json", collection));
+        let dimension = match tokio::fs::read(&meta_file).await {
+            Ok(bytes) => {
+                let meta: CollectionMeta = serde_json::from_slice(&bytes)
+                    .with_context(|| format!("Corrupt collection metadata {}", meta_file.display()))?;
+                if meta.distance != distance {
+                    anyhow::bail!(
+                        "Collection '{}' was created with {} distance but VECTOR_DISTANCE is {}",
+                        collection,
+                        meta.distance.as_str(),
+                        distance.as_str()
+                    );
+                }
+                meta.dimension
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let meta = CollectionMeta { distance, dimension: None };
+                tokio::fs::write(&meta_file, serde_json::to_vec(&meta)?)
+                    .await
+                    .with_context(|| format!("Failed to write {}", meta_file.display()))?;
+                None
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let collection_file = dir.join(format!("{}.json", collection));
+        let mut points = match tokio::fs::read(&collection_file).await {
+            Ok(bytes) => {
+                let chunks = serde_json::from_slice(&bytes).with_context(|| {
+                    format!("Corrupt collection file {}", collection_file.display())
+                })?;
+                Points {
+                    file_bytes: bytes.len() as u64,
+                    ..Points::new(chunks)
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Points::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let log_file = dir.join(format!("{}.log", collection));
+        let torn = replay_log(&log_file, &mut points).await?;
+
+        let store = Self {
+            collection: collection.to_string(),
+            collection_file,
+            log_file,
+            distance,
+            meta_file,
+            dimension: RwLock::new(dimension),
+            points: RwLock::new(Points::default()),
+        };
+        // A crash mid-append leaves a partial last line; rewrite the collection
+        // so later appends do not land behind it
+        if torn {
+            log::warn!("Discarded a partial last entry of {}", store.log_file.display());
+            store.compact(&mut points).await?;
+        }
+        *store.points.write().await = points;
+        Ok(store)
+    }
+
+    /// Fix the collection's vector dimension (e.g. from the embedding model
+    /// registry). Fails if the collection already holds vectors of another size.
+    pub async fn ensure_dimension(&self, dimension: usize) -> Result<()> {
+        if let Some(existing) = self.dimension().await {
+            if existing != dimension {
+                anyhow::bail!(
+                    "Collection '{}' holds {}-dimensional vectors but the embedding model \
+                     produces {}; run `reembed` to migrate",
+                    self.collection,
+                    existing,
+                    dimension
+                );
+            }
+        }
+
+        let mut recorded = self.dimension.write().await;
+        if *recorded != Some(dimension) {
+            let meta = CollectionMeta {
+                distance: self.distance,
+                dimension: Some(dimension),
+            };
+            tokio::fs::write(&self.meta_file, serde_json::to_vec(&meta)?)
+                .await
+                .with_context(|| format!("Failed to write {}", self.meta_file.display()))?;
+            *recorded = Some(dimension);
+        }
+
+        Ok(())
+    }
+
+    /// Check the collection against the `dimension` of the embedding `model` and
+    /// record it. A collection holding vectors of another size is refused unless
+    /// `allow_mismatch`, in which case it is left as is; searches skip its vectors.
+    pub async fn check_embedding_dimension(
+        &self,
+        model: &str,
+        dimension: usize,
+        allow_mismatch: bool,
+    ) -> Result<()> {
+        match self.dimension().await {
+            Some(existing) if existing != dimension && allow_mismatch => {
+                log::warn!(
+                    "Collection '{}' holds {}-dimensional vectors but '{}' produces {}; \
+                     searches find nothing in it until `reembed` migrates it",
+                    self.collection,
+                    existing,
+                    model,
+                    dimension
+                );
+                Ok(())
+            }
+            Some(existing) if existing != dimension => anyhow::bail!(
+                "Collection '{}' was built with {}-dimensional embeddings but \
+                 OPENAI_EMBEDDING_MODEL '{}' produces {}; run `reembed` to migrate it, or set \
+                 ALLOW_DIMENSION_MISMATCH=true to start anyway",
+                self.collection,
+                existing,
+                model,
+                dimension
+            ),
+            _ => self.ensure_dimension(dimension).await,
+        }
+    }
+
+    /// Name of the collection the alias `name` currently points at (`name` itself
+    /// until a migration flips it)
+    pub async fn active_collection(storage_path: &str, name: &str) -> Result<String> {
+        match tokio::fs::read_to_string(alias_file(storage_path, name)).await {
+            Ok(active) if !active.trim().is_empty() => Ok(active.trim().to_string()),
+            Ok(_) => Ok(name.to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(name.to_string()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Atomically point the alias `name` at another collection (write then rename)
+    pub async fn set_active_collection(
+        storage_path: &str,
+        name: &str,
+        collection: &str,
+    ) -> Result<()> {
+        let alias = alias_file(storage_path, name);
+        let tmp = alias.with_extension("tmp");
+        tokio::fs::write(&tmp, collection).await?;
+        tokio::fs::rename(&tmp, alias)
+            .await
+            .context("Failed to update active collection alias")
+    }
+
+    /// Delete a collection's files; the collection active under the alias `name`
+    /// cannot be deleted
+    pub async fn delete_collection(storage_path: &str, name: &str, collection: &str) -> Result<()> {
+        if Self::active_collection(storage_path, name).await? == collection {
+            anyhow::bail!("Refusing to delete active collection '{}'", collection);
+        }
+
+        let dir = Path::new(storage_path);
+        let files = [
+            format!("{}.json", collection),
+            format!("{}.log", collection),
+            format!("{}.meta.json", collection),
+        ];
+        for file in files {
+            match tokio::fs::remove_file(dir.join(&file)).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Insert or replace chunks (matched by id); every chunk must carry an embedding
+    /// of the collection's dimension
+    pub async fn upsert(&self, chunks: Vec<DocumentChunk>) -> Result<()> {
+        if let Some(chunk) = chunks.iter().find(|c| c.embedding.is_none()) {
+            anyhow::bail!("Chunk {} has no embedding", chunk.id);
+        }
+
+        let recorded = *self.dimension.read().await;
+        let mut points = self.points.write().await;
+
+        let dimension = recorded.or_else(|| {
+            points
+                .chunks
+                .first()
+                .or_else(|| chunks.first())
+                .and_then(|c| c.embedding.as_ref())
+                .map(|e| e.len())
+        });
+        if let Some(chunk) = chunks
+            .iter()
+            .find(|c| c.embedding.as_ref().map(|e| e.len()) != dimension)
+        {
+            anyhow::bail!(
+                "Chunk {} has dimension {} but collection '{}' expects {}",
+                chunk.id,
+                chunk.embedding.as_ref().map(|e| e.len()).unwrap_or(0),
+                self.collection,
+                dimension.unwrap_or(0)
+            );
+        }
+
+        self.append(&mut points, &LogEntry::Upsert { chunks: Cow::Borrowed(&chunks) }).await?;
+        for chunk in chunks {
+            points.upsert(chunk);
+        }
+        self.compact_if_due(&mut points).await
+    }
+
+    /// Apply `update` to the payload of every chunk of a document, returning how
+    /// many chunks changed
+    pub async fn update_metadata(
+        &self,
+        document_id: &str,
+        update: impl Fn(&mut ChunkMetadata),
+    ) -> Result<usize> {
+        let mut points = self.points.write().await;
+        let changed: Vec<DocumentChunk> = points
+            .chunks
+            .iter()
+            .filter(|p| p.document_id == document_id)
+            .map(|p| {
+                let mut chunk = p.clone();
+                update(&mut chunk.metadata);
+                chunk
+            })
+            .collect();
+        let updated = changed.len();
+
+        if updated > 0 {
+            self.append(&mut points, &LogEntry::Upsert { chunks: Cow::Borrowed(&changed) })
+                .await?;
+            for chunk in changed {
+                points.upsert(chunk);
+            }
+            self.compact_if_due(&mut points).await?;
+        }
+        Ok(updated)
+    }
+
+    /// Delete a document's chunks except those in `keep`, returning how many were removed
+    pub async fn delete_document_chunks(
+        &self,
+        document_id: &str,
+        keep: &HashSet<String>,
+    ) -> Result<usize> {
+        let mut points = self.points.write().await;
+        let ids: Vec<String> = points
+            .chunks
+            .iter()
+            .filter(|p| p.document_id == document_id && !keep.contains(&p.id))
+            .map(|p| p.id.clone())
+            .collect();
+        let removed = ids.len();
+
+        if removed > 0 {
+            self.append(&mut points, &LogEntry::Delete { ids: Cow::Borrowed(&ids) }).await?;
+            points.remove(&ids);
+            self.compact_if_due(&mut points).await?;
+        }
+        Ok(removed)
+    }
+
+    /// Id of a tenant's document whose chunks were ingested from files with this hash
+    pub async fn find_document_by_hash(
+        &self,
+        tenant_id: &str,
+        content_hash: &str,
+    ) -> Option<String> {
+        self.points
+            .read()
+            .await
+            .chunks
+            .iter()
+            .find(|p| {
+                p.metadata.tenant_id == tenant_id
+                    && p.metadata.content_hash.as_deref() == Some(content_hash)
+            })
+            .map(|p| p.document_id.clone())
+    }
+
+    /// Find the `top_k` most similar chunks matching the filter (always within one tenant)
+    pub async fn search(
+        &self,
+        query_embedding: &[f32],
+        top_k: usize,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchResult>> {
+        let points = self.points.read().await;
+
+        let mut results: Vec<SearchResult> = points
+            .chunks
+            .iter()
+            .filter(|p| filter.matches(p))
+            .filter_map(|p| {
+                // Vectors of another size (a collection left from another
+                // embedding model) cannot be compared
+                let embedding = p.embedding.as_ref().filter(|e| e.len() == query_embedding.len())?;
+                let mut chunk = p.clone();
+                chunk.embedding = None;
+                let raw_score = self.distance.raw_score(query_embedding, embedding);
+                Some(SearchResult {
+                    score: self.distance.normalize(raw_score),
+                    raw_score,
+                    chunk,
+                })
+            })
+            .collect();
+
+        results.sort_by(|a, b| {
+            self.distance
+                .rank_key(b.raw_score)
+                .total_cmp(&self.distance.rank_key(a.raw_score))
+        });
+        results.truncate(top_k);
+
+        Ok(results)
+    }
+
+    /// All stored chunks (embeddings omitted)
+    pub async fn scroll(&self) -> Vec<DocumentChunk> {
+        self.points
+            .read()
+            .await
+            .chunks
+            .iter()
+            .map(|p| DocumentChunk {
+                embedding: None,
+                ..p.clone()
+            })
+            .collect()
+    }
+
+    /// Ids of all stored chunks
+    pub async fn ids(&self) -> HashSet<String> {
+        self.points.read().await.by_id.keys().cloned().collect()
+    }
+
+    /// Directory holding the collection files
+    pub fn storage_dir(&self) -> &Path {
+        self.collection_file.parent().unwrap_or(Path::new("."))
+    }
+
+    /// Name of the opened collection
+    pub fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    /// Similarity metric of the collection
+    pub fn distance(&self) -> Distance {
+        self.distance
+    }
+
+    /// Vector dimension of the collection (None while empty and unrecorded)
+    pub async fn dimension(&self) -> Option<usize> {
+        if let Some(dimension) = *self.dimension.read().await {
+            return Some(dimension);
+        }
+        self.points
+            .read()
+            .await
+            .chunks
+            .first()
+            .and_then(|p| p.embedding.as_ref())
+            .map(|e| e.len())
+    }
+
+    /// Number of stored chunks
+    pub async fn count(&self) -> usize {
+        self.points.read().await.chunks.len()
+    }
+
+    /// Number of stored chunks per tenant
+    pub async fn count_by_tenant(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for point in self.points.read().await.chunks.iter() {
+            *counts.entry(point.metadata.tenant_id.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Number of stored chunks and distinct documents per normalized bike
+    /// model, of one tenant or of all
+    pub async fn count_by_bike_model(&self, tenant_id: Option<&str>) -> Vec<BikeModelCount> {
+        #[derive(Default)]
+        struct Tally<'a> {
+            documents: HashSet<&'a str>,
+            chunks: usize,
+            manual_types: BTreeMap<String, HashSet<&'a str>>,
+        }
+
+        let points = self.points.read().await;
+        let mut models: BTreeMap<String, Tally> = BTreeMap::new();
+        let points = points
+            .chunks
+            .iter()
+            .filter(|point| tenant_id.is_none_or(|tenant| point.metadata.tenant_id == tenant));
+        for point in points {
+            let tally = models.entry(normalize_model(&point.metadata.bike_model)).or_default();
+            tally.documents.insert(&point.document_id);
+            tally.chunks += 1;
+            let manual_type = point.metadata.manual_type.as_deref().map(str::trim);
+            if let Some(manual_type) = manual_type.filter(|t| !t.is_empty()) {
+                let documents = tally.manual_types.entry(manual_type.to_lowercase()).or_default();
+                documents.insert(&point.document_id);
+            }
+        }
+        models
+            .into_iter()
+            .map(|(bike_model, tally)| BikeModelCount {
+                bike_model,
+                documents: tally.documents.len(),
+                chunks: tally.chunks,
+                manual_types: tally
+                    .manual_types
+                    .into_iter()
+                    .map(|(manual_type, documents)| ManualTypeCount {
+                        manual_type,
+                        documents: documents.len(),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Size of the collection file and its log in bytes; 0 before anything was stored
+    pub async fn disk_size(&self) -> u64 {
+        let mut size = 0;
+        for file in [&self.collection_file, &self.log_file] {
+            size += tokio::fs::metadata(file).await.map_or(0, |meta| meta.len());
+        }
+        size
+    }
+
+    /// Write a consistent copy of the collection (points, metric and dimension)
+    /// to `path`, returning the number of chunks. Writes wait while the copy is
+    /// taken; the file appears atomically (write then rename).
+    pub async fn snapshot(&self, path: &Path) -> Result<usize> {
+        let (bytes, chunks) = {
+            let dimension = self.dimension.read().await;
+            let points = self.points.read().await;
+            let snapshot = Snapshot {
+                collection: self.collection.clone(),
+                distance: self.distance,
+                dimension: *dimension,
+                points: points.chunks.clone(),
+            };
+            (serde_json::to_vec(&snapshot)?, points.chunks.len())
+        };
+
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, bytes)
+            .await
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, path)
+            .await
+            .with_context(|| format!("Failed to move snapshot to {}", path.display()))?;
+        Ok(chunks)
+    }
+
+    /// Replace every chunk of the collection with those of a snapshot, returning
+    /// the number of chunks restored. The snapshot must use the collection's
+    /// metric and vector dimension.
+    pub async fn restore(&self, path: &Path) -> Result<usize> {
+        let bytes = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read snapshot {}", path.display()))?;
+        let snapshot: Snapshot = serde_json::from_slice(&bytes)
+            .with_context(|| format!("Corrupt snapshot {}", path.display()))?;
+
+        if snapshot.distance != self.distance {
+            anyhow::bail!(
+                "Snapshot of '{}' uses {} distance but collection '{}' uses {}",
+                snapshot.collection,
+                snapshot.distance.as_str(),
+                self.collection,
+                self.distance.as_str()
+            );
+        }
+        let dimension = snapshot.dimension.or_else(|| {
+            snapshot.points.first().and_then(|p| p.embedding.as_ref()).map(|e| e.len())
+        });
+        if let Some(chunk) = snapshot
+            .points
+            .iter()
+            .find(|c| c.embedding.as_ref().map(|e| e.len()) != dimension)
+        {
+            anyhow::bail!(
+                "Snapshot chunk {} has no embedding of dimension {:?}",
+                chunk.id,
+                dimension
+            );
+        }
+        if let (Some(recorded), Some(dimension)) = (*self.dimension.read().await, dimension) {
+            if recorded != dimension {
+                anyhow::bail!(
+                    "Snapshot holds {}-dimensional vectors but collection '{}' expects {}",
+                    dimension,
+                    self.collection,
+                    recorded
+                );
+            }
+        }
+
+        let restored = snapshot.points.len();
+        {
+            let mut points = self.points.write().await;
+            let mut restored_points = Points::new(snapshot.points);
+            self.compact(&mut restored_points).await?;
+            *points = restored_points;
+        }
+        if let Some(dimension) = dimension {
+            self.ensure_dimension(dimension).await?;
+        }
+        Ok(restored)
+    }
+
+    /// Append a change to the log. A failed append is cut off again, so the
+    /// log never holds a partial entry followed by later ones.
+    async fn append(&self, points: &mut Points, entry: &LogEntry<'_>) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let mut log = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_file)
+            .await
+            .with_context(|| format!("Failed to open {}", self.log_file.display()))?;
+        let written = async {
+            log.write_all(&line).await?;
+            log.flush().await
+        };
+        if let Err(e) = written.await {
+            if let Err(e) = log.set_len(points.log_bytes).await {
+                log::warn!("Failed to truncate {}: {}", self.log_file.display(), e);
+            }
+            return Err(e).with_context(|| format!("Failed to write {}", self.log_file.display()));
+        }
+        points.log_bytes += line.len() as u64;
+        Ok(())
+    }
+
+    async fn compact_if_due(&self, points: &mut Points) -> Result<()> {
+        if points.compaction_due() {
+            self.compact(points).await?;
+        }
+        Ok(())
+    }
+
+    /// Write every chunk to the collection file and drop the log. Replaying a
+    /// log left by a crash between the two steps changes nothing.
+    async fn compact(&self, points: &mut Points) -> Result<()> {
+        let bytes = serde_json::to_vec(&points.chunks)?;
+        let file_bytes = bytes.len() as u64;
+        // Write then rename, so a crash or full disk mid-write leaves the
+        // previous file intact rather than a truncated one
+        let tmp = self.collection_file.with_extension("tmp");
+        tokio::fs::write(&tmp, bytes)
+            .await
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, &self.collection_file)
+            .await
+            .with_context(|| format!("Failed to write {}", self.collection_file.display()))?;
+        match tokio::fs::remove_file(&self.log_file).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to remove {}", self.log_file.display()))
+            }
+        }
+        points.file_bytes = file_bytes;
+        points.log_bytes = 0;
+        Ok(())
+    }
+}
+
+/// Apply the entries of a collection's log, returning whether its last line was
+/// cut short by a crash. A bad entry anywhere else means the log is corrupt.
+async fn replay_log(log_file: &Path, points: &mut Points) -> Result<bool> {
+    let bytes = match tokio::fs::read(log_file).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut lines = bytes.split(|&b| b == b'\n').filter(|line| !line.is_empty()).peekable();
+    while let Some(line) = lines.next() {
+        match serde_json::from_slice::<LogEntry>(line) {
+            Ok(entry) => points.apply(entry),
+            Err(_) if lines.peek().is_none() && !bytes.ends_with(b"\n") => return Ok(true),
+            Err(e) => {
+                let context = format!("Corrupt collection log {}", log_file.display());
+                return Err(e).context(context);
+            }
+        }
+    }
+    points.log_bytes = bytes.len() as u64;
+    Ok(false)
+}
+
+/// File holding the alias of a collection name; the default collection keeps the
+/// original unprefixed file
+fn alias_file(storage_path: &str, name: &str) -> PathBuf {
+    let file = if name == DEFAULT_COLLECTION {
+        ALIAS_FILE.to_string()
+    } else {
+        format!("{}.{}", name, ALIAS_FILE)
+    };
+    Path::new(storage_path).join(file)
+}
+
+/// Run `operation` until it succeeds or `attempts` tries have failed, sleeping
+/// `backoff` before the first retry and doubling it for each further one.
+/// Returns the last error.
+pub async fn retry_with_backoff<T, F, Fut>(
+    what: &str,
+    attempts: u32,
+    backoff: Duration,
+    mut operation: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let attempts = attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt >= attempts => {
+                return Err(e.context(format!("Failed to {} after {} attempts", what, attempts)));
+            }
+            Err(e) => {
+                let delay = backoff * 2u32.pow(attempt - 1);
+                log::warn!(
+                    "Could not {} (attempt {}/{}): {:#}; retrying in {:?}",
+                    what,
+                    attempt,
+                    attempts,
+                    e,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ChunkMetadata, DocumentChunk, DEFAULT_TENANT};
+    use tempfile::TempDir;
+
+    const ALL: [Distance; 3] = [Distance::Cosine, Distance::Dot, Distance::Euclid];
+
+    fn unit(v: &[f32]) -> Vec<f32> {
+        let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+        v.iter().map(|x| x / norm).collect()
+    }
+
+    fn point(text: &str, embedding: Vec<f32>) -> DocumentChunk {
+        DocumentChunk::new("doc-1", text, ChunkMetadata::new("Yamaha R1")).with_embedding(embedding)
+    }
+
+    #[test]
+    fn test_normalize_identical_vectors() {
+        let v = unit(&[1.0, 2.0, 3.0]);
+        for distance in ALL {
+            let score = distance.normalize(distance.raw_score(&v, &v));
+            assert!((score - 1.0).abs() < 1e-5, "{:?}: {}", distance, score);
+        }
+    }
+
+    #[test]
+    fn test_normalize_orthogonal_vectors() {
+        let a = [1.0, 0.0];
+        let b = [0.0, 1.0];
+        for distance in ALL {
+            let score = distance.normalize(distance.raw_score(&a, &b));
+            assert!(score.abs() < 1e-5, "{:?}: {}", distance, score);
+        }
+    }
+
+    #[test]
+    fn test_normalize_consistent_across_metrics_for_unit_vectors() {
+        let a = unit(&[1.0, 1.0, 0.0]);
+        let b = unit(&[1.0, 0.0, 0.0]);
+        let expected = Distance::Cosine.normalize(Distance::Cosine.raw_score(&a, &b));
+
+        for distance in ALL {
+            let score = distance.normalize(distance.raw_score(&a, &b));
+            assert!((score - expected).abs() < 1e-5, "{:?}: {}", distance, score);
+            assert!((0.0..=1.0).contains(&score));
+        }
+    }
+
+    #[test]
+    fn test_normalize_clamps_out_of_range() {
+        assert_eq!(Distance::Cosine.normalize(-0.5), 0.0);
+        assert_eq!(Distance::Dot.normalize(7.5), 1.0);
+        assert_eq!(Distance::Euclid.normalize(10.0), 0.0);
+        assert_eq!(Distance::Dot.normalize(f32::NAN), 0.0);
+    }
+
+    #[test]
+    fn test_parse_distance() {
+        assert_eq!("cosine".parse::<Distance>().unwrap(), Distance::Cosine);
+        assert_eq!("DOT".parse::<Distance>().unwrap(), Distance::Dot);
+        assert_eq!("euclid".parse::<Distance>().unwrap(), Distance::Euclid);
+        assert!("manhattan".parse::<Distance>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_search_ranks_best_match_first_for_every_metric() {
+        for distance in ALL {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().to_str().unwrap();
+            let store = VectorStore::new(path, DEFAULT_COLLECTION, distance)
+                .await
+                .unwrap();
+            store
+                .upsert(vec![
+                    point("far", unit(&[0.0, 1.0])),
+                    point("near", unit(&[1.0, 0.1])),
+                ])
+                .await
+                .unwrap();
+
+            let results = store.search(&unit(&[1.0, 0.0]), 2, &SearchFilter::tenant(DEFAULT_TENANT)).await.unwrap();
+
+            assert_eq!(results[0].chunk.text, "near", "{:?}", distance);
+            assert!(results[0].score > results[1].score);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tenant_filter_isolates_namespaces() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine)
+            .await
+            .unwrap();
+        let mut a = point("dealer a torque spec", unit(&[1.0, 0.0]));
+        a.metadata.tenant_id = "dealer-a".to_string();
+        let mut b = point("dealer b torque spec", unit(&[1.0, 0.0]));
+        b.metadata.tenant_id = "dealer-b".to_string();
+        store.upsert(vec![a, b]).await.unwrap();
+
+        let results = store
+            .search(&unit(&[1.0, 0.0]), 10, &SearchFilter::tenant("dealer-a"))
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].chunk.metadata.tenant_id, "dealer-a");
+        let counts = store.count_by_tenant().await;
+        assert_eq!(counts.get("dealer-a"), Some(&1));
+        assert_eq!(counts.get("dealer-b"), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_upsert_rejects_dimension_mismatch() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine)
+            .await
+            .unwrap();
+        store.upsert(vec![point("a", vec![1.0, 0.0])]).await.unwrap();
+
+        assert!(store.upsert(vec![point("b", vec![1.0, 0.0, 0.0])]).await.is_err());
+        assert_eq!(store.dimension().await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_recorded_dimension_enforced_and_persisted() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+
+        store.ensure_dimension(3).await.unwrap();
+        assert!(store.upsert(vec![point("a", vec![1.0, 0.0])]).await.is_err());
+        store.upsert(vec![point("b", vec![1.0, 0.0, 0.0])]).await.unwrap();
+
+        let reopened = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        assert_eq!(reopened.dimension().await, Some(3));
+        assert!(reopened.ensure_dimension(1536).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_write_leaves_collection_readable() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        store.upsert(vec![point("a", vec![1.0, 0.0])]).await.unwrap();
+
+        // A write cut short by a crash only ever reaches the temporary file
+        let tmp = dir.path().join(format!("{}.tmp", DEFAULT_COLLECTION));
+        std::fs::write(&tmp, b"[{\"id\":").unwrap();
+
+        let reopened = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        assert_eq!(reopened.count().await, 1);
+        reopened.upsert(vec![point("b", vec![0.0, 1.0])]).await.unwrap();
+        let reopened = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        assert_eq!(reopened.count().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_changes_are_appended_and_replayed_on_open() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        let a = point("a", vec![1.0, 0.0]);
+        let b = DocumentChunk::new("doc-2", "b", ChunkMetadata::new("Yamaha R1"))
+            .with_embedding(vec![0.0, 1.0]);
+        store.upsert(vec![a.clone(), b.clone()]).await.unwrap();
+        store.upsert(vec![DocumentChunk { text: "a2".to_string(), ..a.clone() }]).await.unwrap();
+        let updated = store
+            .update_metadata("doc-1", |metadata| metadata.year = Some(2009))
+            .await
+            .unwrap();
+        assert_eq!(updated, 1);
+        store.delete_document_chunks("doc-2", &HashSet::new()).await.unwrap();
+
+        // Small writes only grow the log
+        assert!(!dir.path().join(format!("{}.json", DEFAULT_COLLECTION)).exists());
+        let reopened = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        let chunks = reopened.scroll().await;
+        assert_eq!(chunks.len(), 1);
+        assert_eq!((chunks[0].id.as_str(), chunks[0].text.as_str()), (a.id.as_str(), "a2"));
+        assert_eq!(chunks[0].metadata.year, Some(2009));
+        assert_eq!(reopened.ids().await, HashSet::from([a.id]));
+    }
+
+    #[tokio::test]
+    async fn test_partial_log_entry_discarded_on_open() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        store.upsert(vec![point("a", vec![1.0, 0.0])]).await.unwrap();
+
+        // An append cut short by a crash
+        let log = dir.path().join(format!("{}.log", DEFAULT_COLLECTION));
+        let mut torn = std::fs::read(&log).unwrap();
+        torn.extend_from_slice(b"{\"op\":\"upsert\",\"chunks\":[{\"id\":");
+        std::fs::write(&log, torn).unwrap();
+
+        let reopened = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        assert_eq!(reopened.count().await, 1);
+        reopened.upsert(vec![point("b", vec![0.0, 1.0])]).await.unwrap();
+        let reopened = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        assert_eq!(reopened.count().await, 2);
+
+        // A bad entry followed by others is corruption, not a crash
+        std::fs::write(&log, b"{\"op\":\"delete\"\n{\"op\":\"delete\",\"ids\":[]}\n").unwrap();
+        let error = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.err();
+        assert!(error.unwrap().to_string().contains("Corrupt collection log"));
+    }
+
+    #[tokio::test]
+    async fn test_log_folded_into_collection_file_once_it_outgrows_it() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        let text = "Adjust the chain slack. ".repeat(400);
+        for _ in 0..200 {
+            store.upsert(vec![point(&text, vec![1.0, 0.0])]).await.unwrap();
+        }
+
+        let size = |ext: &str| {
+            let file = dir.path().join(format!("{}.{}", DEFAULT_COLLECTION, ext));
+            std::fs::metadata(file).map_or(0, |meta| meta.len())
+        };
+        assert!(size("json") > 0);
+        assert!(size("log") <= size("json").max(MIN_COMPACT_LOG_BYTES));
+        assert_eq!(store.disk_size().await, size("json") + size("log"));
+        let reopened = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        assert_eq!(reopened.count().await, 200);
+    }
+
+    #[tokio::test]
+    async fn test_collection_of_other_embedding_model_refused_unless_allowed() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        store.check_embedding_dimension("small", 2, false).await.unwrap();
+        store.upsert(vec![point("a", vec![1.0, 0.0])]).await.unwrap();
+
+        // Restarted with a model of another dimension
+        let reopened = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        let error = reopened.check_embedding_dimension("large", 3, false).await.unwrap_err();
+        assert!(error.to_string().contains("ALLOW_DIMENSION_MISMATCH"), "{}", error);
+
+        reopened.check_embedding_dimension("large", 3, true).await.unwrap();
+        assert_eq!(reopened.dimension().await, Some(2));
+        let filter = SearchFilter::tenant(DEFAULT_TENANT);
+        assert!(reopened.search(&[1.0, 0.0, 0.0], 5, &filter).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_alias_flip_and_cleanup() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        let active_name = VectorStore::active_collection(path, DEFAULT_COLLECTION).await.unwrap();
+        assert_eq!(active_name, DEFAULT_COLLECTION);
+        let v2 = VectorStore::open(path, "bike_manuals_v2", Distance::Cosine).await.unwrap();
+        v2.upsert(vec![point("new", vec![1.0, 0.0])]).await.unwrap();
+
+        VectorStore::set_active_collection(path, DEFAULT_COLLECTION, "bike_manuals_v2")
+            .await
+            .unwrap();
+        let active = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+        assert_eq!(active.collection(), "bike_manuals_v2");
+        assert_eq!(active.count().await, 1);
+
+        let delete = |name| VectorStore::delete_collection(path, DEFAULT_COLLECTION, name);
+        assert!(delete("bike_manuals_v2").await.is_err());
+        delete(DEFAULT_COLLECTION).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_named_collections_are_isolated() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        let dev = VectorStore::new(path, "manuals_dev", Distance::Cosine).await.unwrap();
+        let prod = VectorStore::new(path, "manuals_prod", Distance::Cosine).await.unwrap();
+        dev.upsert(vec![point("dev", vec![1.0, 0.0])]).await.unwrap();
+        prod.upsert(vec![point("prod", vec![0.0, 1.0])]).await.unwrap();
+
+        for (name, text) in [("manuals_dev", "dev"), ("manuals_prod", "prod")] {
+            let reopened = VectorStore::new(path, name, Distance::Cosine).await.unwrap();
+            let texts: Vec<_> = reopened.scroll().await.into_iter().map(|c| c.text).collect();
+            assert_eq!(texts, [text]);
+        }
+
+        // Flipping one name's alias leaves the other untouched
+        VectorStore::set_active_collection(path, "manuals_dev", "manuals_dev_v2").await.unwrap();
+        let active = VectorStore::active_collection(path, "manuals_prod").await.unwrap();
+        assert_eq!(active, "manuals_prod");
+        let default = VectorStore::active_collection(path, DEFAULT_COLLECTION).await.unwrap();
+        assert_eq!(default, DEFAULT_COLLECTION);
+    }
+
+    #[tokio::test]
+    async fn test_distance_mismatch_detected_on_reopen() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.unwrap();
+
+        assert!(VectorStore::new(path, DEFAULT_COLLECTION, Distance::Cosine).await.is_ok());
+        let err = VectorStore::new(path, DEFAULT_COLLECTION, Distance::Dot).await.err().unwrap();
+        assert!(err.to_string().contains("cosine"));
+    }
+
+    #[tokio::test]
+    async fn test_retry_succeeds_after_transient_failures() {
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        let result = retry_with_backoff("open store", 3, Duration::from_millis(1), || {
+            let call = calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async move {
+                if call < 2 {
+                    anyhow::bail!("storage not ready");
+                }
+                Ok(call)
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(calls.into_inner(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up_with_last_error() {
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        let result: Result<()> = retry_with_backoff("open store", 2, Duration::ZERO, || {
+            calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async { anyhow::bail!("storage not ready") }
+        })
+        .await;
+
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("after 2 attempts"), "{}", error);
+        assert!(error.contains("storage not ready"), "{}", error);
+        assert_eq!(calls.into_inner(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_connect_opens_store() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = VectorStore::connect(path, "manuals", Distance::Cosine, 3, Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(store.collection(), "manuals");
     }
 }
@@ -0,0 +1,220 @@
+//! Readiness probe behind `/api/health/ready`: reaches the vector store and the
+//! embedding API and reads the circuit breaker, cheap enough to poll every few
+//! seconds
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::ai::AiProvider;
+use crate::rag::VectorStore;
+use crate::security::CircuitState;
+
+/// Longest a single dependency check may take before it counts as down
+pub const READINESS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// State of one dependency
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyState {
+    Up,
+    /// Reachable, but requests to it are currently failing fast
+    Degraded,
+    Down,
+    /// Not checked (e.g. the embedding probe is disabled)
+    Skipped,
+}
+
+/// Outcome of checking one dependency
+#[derive(Debug, Clone, Serialize)]
+pub struct DependencyStatus {
+    pub status: DependencyState,
+    pub detail: String,
+
+    /// Age of a cached result in seconds; omitted for results checked just now
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached_seconds: Option<u64>,
+}
+
+impl DependencyStatus {
+    fn new(status: DependencyState, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            detail: detail.into(),
+            cached_seconds: None,
+        }
+    }
+
+    fn from_result(result: Result<String>) -> Self {
+        match result {
+            Ok(detail) => Self::new(DependencyState::Up, detail),
+            Err(e) => Self::new(DependencyState::Down, format!("{:#}", e)),
+        }
+    }
+}
+
+/// Overall readiness with the status of each dependency
+#[derive(Debug, Clone, Serialize)]
+pub struct ReadinessReport {
+    /// `ready`, `degraded` (serving, but a dependency fails fast) or `not_ready`
+    pub status: &'static str,
+    pub dependencies: BTreeMap<&'static str, DependencyStatus>,
+}
+
+impl ReadinessReport {
+    fn new(dependencies: BTreeMap<&'static str, DependencyStatus>) -> Self {
+        let states = || dependencies.values().map(|d| d.status);
+        let status = if states().any(|s| s == DependencyState::Down) {
+            "not_ready"
+        } else if states().any(|s| s == DependencyState::Degraded) {
+            "degraded"
+        } else {
+            "ready"
+        };
+        Self { status, dependencies }
+    }
+
+    /// No dependency is down; a degraded one still takes traffic
+    pub fn is_ready(&self) -> bool {
+        self.status != "not_ready"
+    }
+}
+
+/// Runs the readiness checks. The embedding call costs tokens, so its result
+/// is reused for `embedding_interval` and at most one call is in flight.
+pub struct ReadinessProbe {
+    embedding_interval: Duration,
+    last_embedding: Mutex<Option<(Instant, DependencyStatus)>>,
+}
+
+impl ReadinessProbe {
+    /// Probe embeddings at most once per `embedding_interval_seconds`; 0 skips
+    /// the embedding check
+    pub fn new(embedding_interval_seconds: u64) -> Self {
+        Self {
+            embedding_interval: Duration::from_secs(embedding_interval_seconds),
+            last_embedding: Mutex::new(None),
+        }
+    }
+
+    /// Check the vector store and the embedding API concurrently and combine
+    /// them with the circuit breaker state (open = degraded)
+    pub async fn check(
+        &self,
+        ai_provider: &dyn AiProvider,
+        vector_store: &VectorStore,
+        circuit: CircuitState,
+    ) -> ReadinessReport {
+        let (vector_store, embeddings) =
+            tokio::join!(check_vector_store(vector_store), self.check_embeddings(ai_provider));
+
+        let circuit_breaker = match circuit {
+            CircuitState::Closed => DependencyStatus::new(DependencyState::Up, "closed"),
+            CircuitState::HalfOpen => DependencyStatus::new(DependencyState::Up, "half-open"),
+            CircuitState::Open => DependencyStatus::new(
+                DependencyState::Degraded,
+                "open, chat requests are rejected until it closes",
+            ),
+        };
+
+        ReadinessReport::new(BTreeMap::from([
+            ("vector_store", vector_store),
+            ("embeddings", embeddings),
+            ("circuit_breaker", circuit_breaker),
+        ]))
+    }
+
+    /// Embed a one-word text, or reuse the last result while it is fresh.
+    /// Failures are cached too, so a revoked key is not retried on every poll.
+    async fn check_embeddings(&self, ai_provider: &dyn AiProvider) -> DependencyStatus {
+        if self.embedding_interval.is_zero() {
+            return DependencyStatus::new(DependencyState::Skipped, "disabled");
+        }
+
+        let mut last = self.last_embedding.lock().await;
+        if let Some((checked_at, status)) = last.as_ref() {
+            let age = checked_at.elapsed();
+            if age < self.embedding_interval {
+                return DependencyStatus {
+                    cached_seconds: Some(age.as_secs()),
+                    ..status.clone()
+                };
+            }
+        }
+
+        let status = DependencyStatus::from_result(
+            with_timeout(async {
+                let embedding = ai_provider.generate_embedding("ping").await?;
+                Ok(format!("{} dimensions", embedding.len()))
+            })
+            .await,
+        );
+        *last = Some((Instant::now(), status.clone()));
+        status
+    }
+}
+
+/// Read the collection's stats and make sure its storage directory is still there
+async fn check_vector_store(store: &VectorStore) -> DependencyStatus {
+    DependencyStatus::from_result(
+        with_timeout(async {
+            let dir = store.storage_dir();
+            let metadata = tokio::fs::metadata(dir).await.map_err(|e| {
+                anyhow::anyhow!("storage directory {} unreachable: {}", dir.display(), e)
+            })?;
+            if !metadata.is_dir() {
+                anyhow::bail!("{} is not a directory", dir.display());
+            }
+            Ok(format!(
+                "collection '{}', {} chunks",
+                store.collection(),
+                store.count().await
+            ))
+        })
+        .await,
+    )
+}
+
+async fn with_timeout<T>(check: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::time::timeout(READINESS_TIMEOUT, check).await.unwrap_or_else(|_| {
+        Err(anyhow::anyhow!("no answer within {}s", READINESS_TIMEOUT.as_secs()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::MockProvider;
+    use crate::rag::Distance;
+
+    #[tokio::test]
+    async fn test_embedding_probe_cached_and_failures_not_ready() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let store = VectorStore::new(path, "manuals", Distance::Cosine).await.unwrap();
+        let provider = MockProvider::new("ok");
+        let probe = ReadinessProbe::new(60);
+
+        let report = probe.check(&provider, &store, CircuitState::Closed).await;
+        assert_eq!(report.status, "ready");
+        let report = probe.check(&provider, &store, CircuitState::Closed).await;
+        assert!(report.dependencies["embeddings"].cached_seconds.is_some());
+        assert_eq!(provider.embedded_texts.lock().unwrap().len(), 1);
+
+        let report = probe.check(&provider, &store, CircuitState::Open).await;
+        assert_eq!(report.status, "degraded");
+        assert!(report.is_ready());
+
+        let failing = MockProvider::new("ok").with_embedding_token_limit(0);
+        let report = ReadinessProbe::new(60).check(&failing, &store, CircuitState::Closed).await;
+        assert_eq!(report.status, "not_ready");
+        assert_eq!(report.dependencies["embeddings"].status, DependencyState::Down);
+
+        let report = ReadinessProbe::new(0).check(&failing, &store, CircuitState::Closed).await;
+        assert_eq!(report.dependencies["embeddings"].status, DependencyState::Skipped);
+        assert!(report.is_ready());
+    }
+}
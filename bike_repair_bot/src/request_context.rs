@@ -0,0 +1,47 @@
+//! Context of the HTTP request a task is handling: the request id, which is
+//! returned to the client and added to every log line, and the client address
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::net::SocketAddr;
+
+tokio::task_local! {
+    static REQUEST: RefCell<RequestContext>;
+}
+
+#[derive(Debug, Default)]
+struct RequestContext {
+    id: Option<String>,
+    remote_addr: Option<SocketAddr>,
+}
+
+/// Handle a request from `remote_addr` in a context of its own. Tasks it
+/// spawns and response bodies streamed after it returns are outside of it.
+pub async fn scope<F: Future>(remote_addr: Option<SocketAddr>, f: F) -> F::Output {
+    let context = RequestContext {
+        id: None,
+        remote_addr,
+    };
+    REQUEST.scope(RefCell::new(context), f).await
+}
+
+/// Record the id assigned to the current request (ignored outside a request)
+pub fn set_request_id(id: &str) {
+    let _ = REQUEST.try_with(|context| context.borrow_mut().id = Some(id.to_string()));
+}
+
+/// Id of the request the current task is handling, once one was assigned
+pub fn request_id() -> Option<String> {
+    REQUEST
+        .try_with(|context| context.borrow().id.clone())
+        .ok()
+        .flatten()
+}
+
+/// Address of the client whose request the current task is handling
+pub fn remote_addr() -> Option<SocketAddr> {
+    REQUEST
+        .try_with(|context| context.borrow().remote_addr)
+        .ok()
+        .flatten()
+}
@@ -0,0 +1,80 @@
+//! Operator alerts for circuit breaker state changes
+
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::circuit_breaker::{CircuitTransition, TransitionHook};
+
+/// How long a webhook delivery may take before it is abandoned
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Body POSTed to the webhook
+#[derive(Debug, Serialize)]
+struct AlertPayload<'a> {
+    event: &'static str,
+    #[serde(flatten)]
+    transition: &'a CircuitTransition,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+/// POSTs every circuit breaker transition as JSON to `ALERT_WEBHOOK_URL`.
+/// Deliveries run in the background and are not retried; failures are logged.
+pub struct WebhookAlerter {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookAlerter {
+    pub fn new(url: impl Into<String>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self {
+            client,
+            url: url.into(),
+        }
+    }
+
+    /// Hook for `CircuitBreaker::with_transition_hook`; must be called within
+    /// a Tokio runtime
+    pub fn transition_hook(self: Arc<Self>) -> TransitionHook {
+        Arc::new(move |transition: &CircuitTransition| {
+            let alerter = self.clone();
+            let transition = *transition;
+            tokio::spawn(async move { alerter.send(&transition).await });
+        })
+    }
+
+    async fn send(&self, transition: &CircuitTransition) {
+        let payload = AlertPayload {
+            event: "circuit_breaker_transition",
+            transition,
+            timestamp: chrono::Utc::now(),
+        };
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                log::error!("Failed to encode circuit breaker alert: {}", e);
+                return;
+            }
+        };
+        let response = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match response {
+            Ok(_) => log::debug!(
+                "Sent circuit breaker alert ({:?} -> {:?})",
+                transition.from,
+                transition.to
+            ),
+            Err(e) => log::warn!("Failed to send circuit breaker alert: {}", e),
+        }
+    }
+}
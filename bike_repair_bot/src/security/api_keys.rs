@@ -0,0 +1,183 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Prefix of a key given by its hex SHA-256 digest instead of in plain text
+const SHA256_PREFIX: &str = "sha256:";
+
+/// Keys accepted by the chat endpoints. Only SHA-256 digests are kept, and a
+/// presented key is checked against every one of them in constant time.
+#[derive(Clone, Default)]
+pub struct ChatApiKeys {
+    digests: Vec<[u8; 32]>,
+}
+
+impl ChatApiKeys {
+    /// Parse comma-separated keys; an entry `sha256:<hex>` is the digest of a
+    /// key rather than the key itself
+    pub fn parse(list: &str) -> Result<Self> {
+        let digests = list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.strip_prefix(SHA256_PREFIX) {
+                Some(hex) => parse_digest(hex),
+                None => Ok(digest(entry)),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { digests })
+    }
+
+    /// No keys configured: the chat endpoints are open
+    pub fn is_empty(&self) -> bool {
+        self.digests.is_empty()
+    }
+
+    /// Whether `key` is one of the accepted keys. Every digest is compared in
+    /// full, so the time taken does not depend on which key (if any) matched.
+    pub fn verify(&self, key: &str) -> bool {
+        let presented = digest(key);
+        self.digests
+            .iter()
+            .fold(false, |matched, accepted| matched | constant_time_eq(accepted, &presented))
+    }
+}
+
+impl fmt::Debug for ChatApiKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ChatApiKeys({} keys)", self.digests.len())
+    }
+}
+
+/// Tenant API keys (`TENANT_API_KEYS`), kept as SHA-256 digests. Looking up a
+/// key compares it with every configured one in constant time.
+#[derive(Clone, Default)]
+pub struct TenantApiKeys {
+    keys: Vec<([u8; 32], String)>,
+}
+
+impl TenantApiKeys {
+    /// Parse comma-separated `tenant:key` pairs; the key of a pair may be given
+    /// as `sha256:<hex>`, like in `CHAT_API_KEYS`
+    pub fn parse(list: &str) -> Result<Self> {
+        let keys = list
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (tenant, key) = pair
+                    .split_once(':')
+                    .map(|(tenant, key)| (tenant.trim(), key.trim()))
+                    .filter(|(tenant, key)| !tenant.is_empty() && !key.is_empty())
+                    .ok_or_else(|| anyhow::anyhow!("'{}' is not a tenant:key pair", pair))?;
+                let digest = match key.strip_prefix(SHA256_PREFIX) {
+                    Some(hex) => parse_digest(hex)?,
+                    None => digest(key),
+                };
+                Ok((digest, tenant.to_string()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { keys })
+    }
+
+    /// Tenant of `key`, if it is configured. Every digest is compared in full,
+    /// so the time taken does not depend on which key (if any) matched.
+    pub fn tenant_for(&self, key: &str) -> Option<&str> {
+        let presented = digest(key);
+        self.keys.iter().fold(None, |found, (accepted, tenant)| {
+            let matched = constant_time_eq(accepted, &presented);
+            if matched && found.is_none() {
+                Some(tenant.as_str())
+            } else {
+                found
+            }
+        })
+    }
+
+    /// Whether some key belongs to `tenant`
+    pub fn has_tenant(&self, tenant: &str) -> bool {
+        self.keys.iter().any(|(_, t)| t == tenant)
+    }
+}
+
+impl fmt::Debug for TenantApiKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TenantApiKeys({} keys)", self.keys.len())
+    }
+}
+
+/// Compare a presented key with the expected one in constant time
+pub fn keys_match(expected: &str, presented: &str) -> bool {
+    constant_time_eq(&digest(expected), &digest(presented))
+}
+
+fn digest(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+fn parse_digest(hex: &str) -> Result<[u8; 32]> {
+    if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        anyhow::bail!("'{}{}' is not a hex SHA-256 digest", SHA256_PREFIX, hex);
+    }
+    let mut digest = [0u8; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(digest)
+}
+
+fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_and_hashed_keys_accepted() {
+        // echo -n "mobile-app" | sha256sum
+        let keys = ChatApiKeys::parse(
+            "web-key, sha256:73ff9edb0217159ba61364d0ccc10dfaa6f7dabbfaf626ce69de266127985364,",
+        )
+        .unwrap();
+        assert!(!keys.is_empty());
+        assert!(keys.verify("web-key"));
+        assert!(keys.verify("mobile-app"));
+        assert!(!keys.verify("web-key "));
+        assert!(!keys.verify(""));
+    }
+
+    #[test]
+    fn test_keys_match() {
+        assert!(keys_match("secret", "secret"));
+        assert!(!keys_match("secret", "secret2"));
+        assert!(!keys_match("secret", ""));
+    }
+
+    #[test]
+    fn test_tenant_keys_resolve_plain_and_hashed_keys() {
+        // echo -n "mobile-app" | sha256sum
+        let keys = TenantApiKeys::parse(concat!(
+            "acme:acme-key, dealer-b : ",
+            "sha256:73ff9edb0217159ba61364d0ccc10dfaa6f7dabbfaf626ce69de266127985364",
+        ))
+        .unwrap();
+        assert_eq!(keys.tenant_for("acme-key"), Some("acme"));
+        assert_eq!(keys.tenant_for("mobile-app"), Some("dealer-b"));
+        assert_eq!(keys.tenant_for("acme"), None);
+        assert_eq!(keys.tenant_for(""), None);
+        assert!(keys.has_tenant("dealer-b"));
+        assert!(!keys.has_tenant("acme-key"));
+
+        assert!(TenantApiKeys::parse("acme").is_err());
+        assert!(TenantApiKeys::parse("acme:").is_err());
+        assert!(TenantApiKeys::parse("acme:sha256:abc").is_err());
+    }
+
+    #[test]
+    fn test_malformed_digest_rejected() {
+        assert!(ChatApiKeys::parse("sha256:abc").is_err());
+        assert!(ChatApiKeys::parse("").unwrap().is_empty());
+    }
+}
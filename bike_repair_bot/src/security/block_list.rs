@@ -0,0 +1,162 @@
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+/// Manually maintained list of blocked IPs and CIDR ranges
+pub struct BlockList {
+    /// Blocked networks (single IPs are stored as /32 or /128)
+    entries: RwLock<Vec<IpNet>>,
+
+    /// File the list is loaded from and saved to on change
+    path: Option<PathBuf>,
+}
+
+impl BlockList {
+    /// Empty, in-memory only block list
+    pub fn new() -> Self {
+        Self {
+            entries: RwLock::new(Vec::new()),
+            path: None,
+        }
+    }
+
+    /// Load from a file with one IP or CIDR per line (`#` starts a comment).
+    /// A missing file yields an empty list that will be created on first change.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or("").trim())
+                .filter(|line| !line.is_empty())
+                .map(parse_entry)
+                .collect::<Result<Vec<_>>>()
+                .with_context(|| format!("Invalid entry in block list {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        log::info!("Loaded {} block list entries from {}", entries.len(), path.display());
+
+        Ok(Self {
+            entries: RwLock::new(entries),
+            path: Some(path),
+        })
+    }
+
+    /// Check whether an IP falls within any blocked entry
+    pub fn is_blocked(&self, ip: IpAddr) -> bool {
+        self.entries.read().unwrap().iter().any(|net| net.contains(&ip))
+    }
+
+    /// Block an IP or CIDR range, returning the normalized entry
+    pub fn block(&self, entry: &str) -> Result<IpNet> {
+        let net = parse_entry(entry)?;
+        let mut entries = self.entries.write().unwrap();
+        if !entries.contains(&net) {
+            entries.push(net);
+            self.save(&entries)?;
+        }
+        log::warn!("Blocked {}", net);
+        Ok(net)
+    }
+
+    /// Remove an entry; returns false if it was not blocked
+    pub fn unblock(&self, entry: &str) -> Result<bool> {
+        let net = parse_entry(entry)?;
+        let mut entries = self.entries.write().unwrap();
+        let before = entries.len();
+        entries.retain(|e| *e != net);
+        let removed = entries.len() != before;
+        if removed {
+            self.save(&entries)?;
+            log::info!("Unblocked {}", net);
+        }
+        Ok(removed)
+    }
+
+    /// Current entries as strings
+    pub fn entries(&self) -> Vec<String> {
+        self.entries.read().unwrap().iter().map(|e| e.to_string()).collect()
+    }
+
+    fn save(&self, entries: &[IpNet]) -> Result<()> {
+        if let Some(path) = &self.path {
+            let contents: String = entries.iter().map(|e| format!("{}\n", e)).collect();
+            std::fs::write(path, contents)
+                .with_context(|| format!("Failed to save block list {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for BlockList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parse a single IP or a CIDR range
+fn parse_entry(entry: &str) -> Result<IpNet> {
+    let entry = entry.trim();
+    if let Ok(net) = entry.parse::<IpNet>() {
+        return Ok(net.trunc());
+    }
+    entry
+        .parse::<IpAddr>()
+        .map(IpNet::from)
+        .map_err(|_| anyhow::anyhow!("'{}' is not a valid IP address or CIDR range", entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_single_ip() {
+        let list = BlockList::new();
+        list.block("203.0.113.7").unwrap();
+
+        assert!(list.is_blocked("203.0.113.7".parse().unwrap()));
+        assert!(!list.is_blocked("203.0.113.8".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_block_cidr_range() {
+        let list = BlockList::new();
+        list.block("198.51.100.0/24").unwrap();
+        list.block("2001:db8::/32").unwrap();
+
+        assert!(list.is_blocked("198.51.100.1".parse().unwrap()));
+        assert!(list.is_blocked("198.51.100.254".parse().unwrap()));
+        assert!(!list.is_blocked("198.51.101.1".parse().unwrap()));
+        assert!(list.is_blocked("2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_unblock_and_invalid_entries() {
+        let list = BlockList::new();
+        list.block("10.0.0.0/8").unwrap();
+
+        assert!(list.unblock("10.0.0.0/8").unwrap());
+        assert!(!list.unblock("10.0.0.0/8").unwrap());
+        assert!(!list.is_blocked("10.1.2.3".parse().unwrap()));
+        assert!(list.block("not-an-ip").is_err());
+    }
+
+    #[test]
+    fn test_load_and_persist() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("blocklist.txt");
+        std::fs::write(&path, "# abusive\n192.0.2.1\n192.0.2.128/25 # scraper\n").unwrap();
+
+        let list = BlockList::load(&path).unwrap();
+        assert!(list.is_blocked("192.0.2.200".parse().unwrap()));
+        list.block("192.0.2.5").unwrap();
+
+        let reloaded = BlockList::load(&path).unwrap();
+        assert_eq!(reloaded.entries(), vec!["192.0.2.1/32", "192.0.2.128/25", "192.0.2.5/32"]);
+    }
+}
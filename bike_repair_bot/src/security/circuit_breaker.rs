@@ -1,11 +1,14 @@
 use anyhow::Result;
+use serde::Serialize;
 use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
+use super::clock::{Clock, SystemClock};
+
 /// Circuit breaker states
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum CircuitState {
     /// Normal operation - requests pass through
     Closed,
@@ -17,8 +20,21 @@ pub enum CircuitState {
     HalfOpen,
 }
 
+/// A change of circuit breaker state
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CircuitTransition {
+    pub from: CircuitState,
+    pub to: CircuitState,
+
+    /// Consecutive failures at the time of the change
+    pub failure_count: u32,
+}
+
+/// Called once for every state change, e.g. to alert operators
+pub type TransitionHook = Arc<dyn Fn(&CircuitTransition) + Send + Sync>;
+
 /// Circuit breaker to protect against cascading failures
-pub struct CircuitBreaker {
+pub struct CircuitBreaker<C: Clock = SystemClock> {
     /// Current state
     state: Arc<RwLock<CircuitState>>,
     
@@ -26,32 +42,80 @@ pub struct CircuitBreaker {
     failure_count: Arc<AtomicU32>,
     
     /// Failure threshold before opening circuit
-    threshold: u32,
+    threshold: AtomicU32,
     
     /// Time when circuit was opened
     opened_at: Arc<RwLock<Option<Instant>>>,
     
-    /// Timeout before attempting recovery
-    timeout: Duration,
+    /// Timeout before attempting recovery, in seconds
+    timeout_seconds: AtomicU64,
     
     /// Total requests
     total_requests: Arc<AtomicU64>,
     
     /// Total failures
     total_failures: Arc<AtomicU64>,
+
+    /// Time source used for the recovery timeout
+    clock: C,
+
+    /// Notified of every state change
+    on_transition: Option<TransitionHook>,
 }
 
 impl CircuitBreaker {
     pub fn new(threshold: u32, timeout_seconds: u64) -> Self {
+        Self::with_clock(threshold, timeout_seconds, SystemClock)
+    }
+}
+
+impl<C: Clock> CircuitBreaker<C> {
+    /// Create a circuit breaker that reads time from the given clock
+    pub fn with_clock(threshold: u32, timeout_seconds: u64, clock: C) -> Self {
         Self {
             state: Arc::new(RwLock::new(CircuitState::Closed)),
             failure_count: Arc::new(AtomicU32::new(0)),
-            threshold,
+            threshold: AtomicU32::new(threshold),
             opened_at: Arc::new(RwLock::new(None)),
-            timeout: Duration::from_secs(timeout_seconds),
+            timeout_seconds: AtomicU64::new(timeout_seconds),
             total_requests: Arc::new(AtomicU64::new(0)),
             total_failures: Arc::new(AtomicU64::new(0)),
+            clock,
+            on_transition: None,
+        }
+    }
+
+    /// Call `hook` on every state change
+    pub fn with_transition_hook(mut self, hook: TransitionHook) -> Self {
+        self.on_transition = Some(hook);
+        self
+    }
+
+    /// Move to `to` if the breaker is in one of the `from` states. Returns
+    /// whether this call made the change, so concurrent callers racing for the
+    /// same transition report it only once.
+    async fn transition(&self, from: &[CircuitState], to: CircuitState) -> bool {
+        let mut state = self.state.write().await;
+        let previous = *state;
+        if !from.contains(&previous) {
+            return false;
+        }
+        *state = to;
+        match to {
+            CircuitState::Open => *self.opened_at.write().await = Some(self.clock.now()),
+            CircuitState::Closed => *self.opened_at.write().await = None,
+            CircuitState::HalfOpen => {}
         }
+        drop(state);
+
+        if let Some(hook) = &self.on_transition {
+            hook(&CircuitTransition {
+                from: previous,
+                to,
+                failure_count: self.failure_count.load(Ordering::Relaxed),
+            });
+        }
+        true
     }
 
     /// Check if request should be allowed
@@ -69,11 +133,12 @@ impl CircuitBreaker {
                 // Check if timeout has elapsed
                 let opened_at = self.opened_at.read().await;
                 if let Some(opened_time) = *opened_at {
-                    if Instant::now().duration_since(opened_time) >= self.timeout {
+                    if self.clock.now().duration_since(opened_time) >= self.timeout() {
                         // Transition to half-open
                         drop(opened_at);
-                        *self.state.write().await = CircuitState::HalfOpen;
-                        log::info!("Circuit breaker transitioning to half-open state");
+                        if self.transition(&[CircuitState::Open], CircuitState::HalfOpen).await {
+                            log::info!("Circuit breaker transitioning to half-open state");
+                        }
                         Ok(())
                     } else {
                         anyhow::bail!(
@@ -103,10 +168,10 @@ impl CircuitBreaker {
             }
             CircuitState::HalfOpen => {
                 // Success in half-open state - close the circuit
-                log::info!("Circuit breaker closing after successful request");
-                *self.state.write().await = CircuitState::Closed;
                 self.failure_count.store(0, Ordering::Relaxed);
-                *self.opened_at.write().await = None;
+                if self.transition(&[CircuitState::HalfOpen], CircuitState::Closed).await {
+                    log::info!("Circuit breaker closing after successful request");
+                }
             }
             CircuitState::Open => {
                 // Shouldn't happen, but reset anyway
@@ -124,23 +189,21 @@ impl CircuitBreaker {
 
         match state {
             CircuitState::Closed => {
-                if failures >= self.threshold {
-                    // Too many failures - open the circuit
+                // Too many failures - open the circuit
+                if failures >= self.threshold.load(Ordering::Relaxed)
+                    && self.transition(&[CircuitState::Closed], CircuitState::Open).await
+                {
                     log::warn!(
                         "Circuit breaker opening after {} consecutive failures",
                         failures
                     );
-                    drop(state);
-                    *self.state.write().await = CircuitState::Open;
-                    *self.opened_at.write().await = Some(Instant::now());
                 }
             }
             CircuitState::HalfOpen => {
                 // Failure in half-open - back to open
-                log::warn!("Circuit breaker reopening after failure in half-open state");
-                drop(state);
-                *self.state.write().await = CircuitState::Open;
-                *self.opened_at.write().await = Some(Instant::now());
+                if self.transition(&[CircuitState::HalfOpen], CircuitState::Open).await {
+                    log::warn!("Circuit breaker reopening after failure in half-open state");
+                }
             }
             CircuitState::Open => {
                 // Already open, do nothing
@@ -148,6 +211,20 @@ impl CircuitBreaker {
         }
     }
 
+    /// Change the failure threshold and recovery timeout at runtime
+    pub fn update_settings(&self, threshold: u32, timeout_seconds: u64) {
+        self.threshold.store(threshold, Ordering::Relaxed);
+        self.timeout_seconds.store(timeout_seconds, Ordering::Relaxed);
+        log::info!(
+            "Circuit breaker updated: threshold {}, timeout {}s",
+            threshold, timeout_seconds
+        );
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_seconds.load(Ordering::Relaxed))
+    }
+
     /// Get current state
     pub async fn get_state(&self) -> CircuitState {
         *self.state.read().await
@@ -158,7 +235,7 @@ impl CircuitBreaker {
         CircuitStats {
             state: *self.state.read().await,
             failure_count: self.failure_count.load(Ordering::Relaxed),
-            threshold: self.threshold,
+            threshold: self.threshold.load(Ordering::Relaxed),
             total_requests: self.total_requests.load(Ordering::Relaxed),
             total_failures: self.total_failures.load(Ordering::Relaxed),
         }
@@ -167,9 +244,9 @@ impl CircuitBreaker {
     /// Manually reset the circuit breaker
     pub async fn reset(&self) {
         log::info!("Manually resetting circuit breaker");
-        *self.state.write().await = CircuitState::Closed;
         self.failure_count.store(0, Ordering::Relaxed);
-        *self.opened_at.write().await = None;
+        self.transition(&[CircuitState::Open, CircuitState::HalfOpen], CircuitState::Closed)
+            .await;
     }
 }
 
@@ -186,6 +263,7 @@ pub struct CircuitStats {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::security::clock::MockClock;
 
     #[tokio::test]
     async fn test_circuit_breaker_opens_on_failures() {
@@ -203,15 +281,21 @@ mod tests {
 
     #[tokio::test]
     async fn test_circuit_breaker_closes_on_success() {
-        let breaker = CircuitBreaker::new(2, 1);
+        let clock = MockClock::new();
+        let breaker = CircuitBreaker::with_clock(2, 30, clock.clone());
 
         // Open the circuit
         breaker.record_failure().await;
         breaker.record_failure().await;
         assert_eq!(breaker.get_state().await, CircuitState::Open);
 
-        // Wait for timeout
-        tokio::time::sleep(Duration::from_secs(2)).await;
+        // Still open just before the timeout
+        clock.advance(Duration::from_secs(29));
+        assert!(breaker.check_request().await.is_err());
+        assert_eq!(breaker.get_state().await, CircuitState::Open);
+
+        // Advance past the timeout
+        clock.advance(Duration::from_secs(1));
 
         // Try request (should transition to half-open)
         assert!(breaker.check_request().await.is_ok());
@@ -221,4 +305,51 @@ mod tests {
         breaker.record_success().await;
         assert_eq!(breaker.get_state().await, CircuitState::Closed);
     }
+
+    #[tokio::test]
+    async fn test_transition_hook_fires_once_per_change() {
+        let clock = MockClock::new();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let hook: TransitionHook = {
+            let seen = seen.clone();
+            Arc::new(move |t: &CircuitTransition| seen.lock().unwrap().push(*t))
+        };
+        let breaker =
+            Arc::new(CircuitBreaker::with_clock(3, 30, clock.clone()).with_transition_hook(hook));
+
+        // Concurrent failures past the threshold open the circuit once
+        let failures: Vec<_> = (0..10)
+            .map(|_| {
+                let breaker = breaker.clone();
+                tokio::spawn(async move { breaker.record_failure().await })
+            })
+            .collect();
+        for failure in failures {
+            failure.await.unwrap();
+        }
+        clock.advance(Duration::from_secs(30));
+        assert!(breaker.check_request().await.is_ok());
+        assert!(breaker.check_request().await.is_ok());
+        breaker.record_failure().await;
+        clock.advance(Duration::from_secs(30));
+        assert!(breaker.check_request().await.is_ok());
+        breaker.record_success().await;
+        breaker.record_success().await;
+
+        let seen = seen.lock().unwrap();
+        let states: Vec<_> = seen.iter().map(|t| (t.from, t.to)).collect();
+        use CircuitState::*;
+        assert_eq!(
+            states,
+            [
+                (Closed, Open),
+                (Open, HalfOpen),
+                (HalfOpen, Open),
+                (Open, HalfOpen),
+                (HalfOpen, Closed)
+            ]
+        );
+        assert!(seen[0].failure_count >= 3);
+        assert_eq!(seen[4].failure_count, 0);
+    }
 }
@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Source of monotonic time, so time-based logic can be tested without sleeping
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Wall-clock time, for timestamps that outlive the process
+    fn now_utc(&self) -> DateTime<Utc>;
+}
+
+/// Clock backed by `Instant::now()`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_utc(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Manually advanced clock; clones share the same time
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    start: Instant,
+    start_utc: DateTime<Utc>,
+    offset_ms: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            start_utc: Utc::now(),
+            offset_ms: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Move the clock forward
+    pub fn advance(&self, by: Duration) {
+        self.offset_ms
+            .fetch_add(by.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + Duration::from_millis(self.offset_ms.load(Ordering::Relaxed))
+    }
+
+    fn now_utc(&self) -> DateTime<Utc> {
+        self.start_utc + Duration::from_millis(self.offset_ms.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_advances_shared_time() {
+        let clock = MockClock::new();
+        let handle = clock.clone();
+        let before = clock.now();
+
+        handle.advance(Duration::from_secs(30));
+
+        assert_eq!(clock.now().duration_since(before), Duration::from_secs(30));
+    }
+}
@@ -1,7 +1,17 @@
 pub mod rate_limiter;
 pub mod validator;
 pub mod circuit_breaker;
+pub mod block_list;
+pub mod clock;
+pub mod repeat_guard;
+pub mod alerts;
+pub mod api_keys;
 
 pub use rate_limiter::*;
 pub use validator::*;
 pub use circuit_breaker::*;
+pub use block_list::*;
+pub use clock::*;
+pub use repeat_guard::*;
+pub use alerts::*;
+pub use api_keys::*;
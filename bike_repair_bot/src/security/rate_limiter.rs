@@ -1,19 +1,40 @@
 use anyhow::Result;
+use async_trait::async_trait;
 use dashmap::DashMap;
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use crate::models::RateLimitInfo;
 
+/// Where request counts are kept. `RateLimiter` keeps them in memory, which
+/// limits each instance on its own; a shared store (such as Redis) lets several
+/// instances enforce one limit.
+#[async_trait]
+pub trait RateLimiterBackend: Send + Sync {
+    /// Record a request from `ip` and return what is left of its limits; fails
+    /// without recording when the request would exceed them
+    async fn check_and_record(&self, ip: IpAddr) -> Result<RateLimitInfo>;
+
+    /// What is left of the limits of `ip`, without recording a request
+    async fn get_status(&self, ip: IpAddr) -> RateLimitInfo;
+
+    /// Current (per-minute, per-hour) limits
+    fn limits(&self) -> (u32, u32);
+
+    /// Change the limits; existing request history is kept
+    fn update_limits(&self, max_per_minute: u32, max_per_hour: u32);
+}
+
 /// Rate limiter for controlling request frequency
 pub struct RateLimiter {
     /// Per-IP request tracking
     ip_requests: Arc<DashMap<IpAddr, RequestTracker>>,
     
-    /// Configuration
-    max_per_minute: u32,
-    max_per_hour: u32,
+    /// Configuration (adjustable at runtime)
+    max_per_minute: AtomicU32,
+    max_per_hour: AtomicU32,
 }
 
 /// Track requests for a single IP/user
@@ -112,21 +133,37 @@ impl RateLimiter {
     pub fn new(max_per_minute: u32, max_per_hour: u32) -> Self {
         Self {
             ip_requests: Arc::new(DashMap::new()),
-            max_per_minute,
-            max_per_hour,
+            max_per_minute: AtomicU32::new(max_per_minute),
+            max_per_hour: AtomicU32::new(max_per_hour),
         }
     }
 
+    /// Current (per-minute, per-hour) limits
+    pub fn limits(&self) -> (u32, u32) {
+        (
+            self.max_per_minute.load(Ordering::Relaxed),
+            self.max_per_hour.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Change the limits; existing request history is kept
+    pub fn update_limits(&self, max_per_minute: u32, max_per_hour: u32) {
+        self.max_per_minute.store(max_per_minute, Ordering::Relaxed);
+        self.max_per_hour.store(max_per_hour, Ordering::Relaxed);
+        log::info!("Rate limits updated: {}/min, {}/hour", max_per_minute, max_per_hour);
+    }
+
     /// Check if request is allowed and record it
     pub fn check_and_record(&self, ip: IpAddr) -> Result<RateLimitInfo> {
+        let (max_per_minute, max_per_hour) = self.limits();
         let mut tracker = self.ip_requests
             .entry(ip)
             .or_insert_with(RequestTracker::new)
             .clone();
 
         // Check limits before adding
-        if !tracker.check_limits(self.max_per_minute, self.max_per_hour) {
-            let info = tracker.get_info(self.max_per_minute, self.max_per_hour);
+        if !tracker.check_limits(max_per_minute, max_per_hour) {
+            let info = tracker.get_info(max_per_minute, max_per_hour);
             anyhow::bail!(
                 "Rate limit exceeded. Try again in {} seconds",
                 info.reset_in_seconds
@@ -140,20 +177,21 @@ impl RateLimiter {
         self.ip_requests.insert(ip, tracker.clone());
 
         // Return current limit info
-        Ok(tracker.get_info(self.max_per_minute, self.max_per_hour))
+        Ok(tracker.get_info(max_per_minute, max_per_hour))
     }
 
     /// Get current rate limit status without recording
     pub fn get_status(&self, ip: IpAddr) -> RateLimitInfo {
+        let (max_per_minute, max_per_hour) = self.limits();
         self.ip_requests
             .get(&ip)
             .map(|e| {
                 let mut tracker = e.clone();
-                tracker.get_info(self.max_per_minute, self.max_per_hour)
+                tracker.get_info(max_per_minute, max_per_hour)
             })
             .unwrap_or(RateLimitInfo {
-                remaining_minute: self.max_per_minute,
-                remaining_hour: self.max_per_hour,
+                remaining_minute: max_per_minute,
+                remaining_hour: max_per_hour,
                 reset_in_seconds: 0,
             })
     }
@@ -173,6 +211,25 @@ impl RateLimiter {
     }
 }
 
+#[async_trait]
+impl RateLimiterBackend for RateLimiter {
+    async fn check_and_record(&self, ip: IpAddr) -> Result<RateLimitInfo> {
+        RateLimiter::check_and_record(self, ip)
+    }
+
+    async fn get_status(&self, ip: IpAddr) -> RateLimitInfo {
+        RateLimiter::get_status(self, ip)
+    }
+
+    fn limits(&self) -> (u32, u32) {
+        RateLimiter::limits(self)
+    }
+
+    fn update_limits(&self, max_per_minute: u32, max_per_hour: u32) {
+        RateLimiter::update_limits(self, max_per_minute, max_per_hour)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -191,4 +248,17 @@ mod tests {
         // 6th request should fail (exceeds per-minute limit)
         assert!(limiter.check_and_record(ip).is_err());
     }
+
+    #[test]
+    fn test_update_limits_takes_effect() {
+        let limiter = RateLimiter::new(1, 10);
+        let ip = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+
+        assert!(limiter.check_and_record(ip).is_ok());
+        assert!(limiter.check_and_record(ip).is_err());
+
+        limiter.update_limits(3, 10);
+        assert!(limiter.check_and_record(ip).is_ok());
+        assert_eq!(limiter.get_status(ip).remaining_minute, 1);
+    }
 }
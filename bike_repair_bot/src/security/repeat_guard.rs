@@ -0,0 +1,122 @@
+use anyhow::Result;
+use dashmap::DashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::security::{Clock, SystemClock};
+
+/// Throttles clients that keep sending the same query, even within rate limits
+pub struct RepeatGuard<C: Clock = SystemClock> {
+    /// Identical queries allowed per IP within the window (0 disables the guard)
+    max_repeats: u32,
+
+    /// How long a query counts as a repeat
+    window: Duration,
+
+    /// Times each (IP, normalized query hash) was accepted within the window
+    seen: DashMap<(IpAddr, u64), Vec<Instant>>,
+
+    clock: C,
+}
+
+impl RepeatGuard {
+    pub fn new(max_repeats: u32, window_seconds: u64) -> Self {
+        Self::with_clock(max_repeats, window_seconds, SystemClock)
+    }
+}
+
+impl<C: Clock> RepeatGuard<C> {
+    pub fn with_clock(max_repeats: u32, window_seconds: u64, clock: C) -> Self {
+        Self {
+            max_repeats,
+            window: Duration::from_secs(window_seconds),
+            seen: DashMap::new(),
+            clock,
+        }
+    }
+
+    /// Record the query, failing if the IP already sent it `max_repeats` times
+    /// within the window
+    pub fn check(&self, ip: IpAddr, query: &str) -> Result<()> {
+        if self.max_repeats == 0 {
+            return Ok(());
+        }
+
+        let now = self.clock.now();
+        let mut times = self.seen.entry((ip, query_key(query))).or_default();
+        times.retain(|&t| now.duration_since(t) < self.window);
+
+        if times.len() as u32 >= self.max_repeats {
+            let retry_in = self.window.saturating_sub(now.duration_since(times[0]));
+            anyhow::bail!(
+                "The same question was asked {} times in the last {} seconds. \
+                 Try again in {} seconds or rephrase it",
+                times.len(),
+                self.window.as_secs(),
+                retry_in.as_secs().max(1)
+            );
+        }
+
+        times.push(now);
+        Ok(())
+    }
+
+    /// Drop entries whose repeats have all expired (should be called periodically)
+    pub fn cleanup_old_entries(&self) {
+        let now = self.clock.now();
+        self.seen
+            .retain(|_, times| times.iter().any(|&t| now.duration_since(t) < self.window));
+        log::debug!("Repeat guard cleanup: {} tracked queries", self.seen.len());
+    }
+}
+
+/// Hash of the query with case, punctuation and spacing ignored
+fn query_key(query: &str) -> u64 {
+    let normalized = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut hasher = DefaultHasher::new();
+    normalized.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::MockClock;
+    use std::net::Ipv4Addr;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    #[test]
+    fn test_identical_queries_throttled_until_window_passes() {
+        let clock = MockClock::new();
+        let guard = RepeatGuard::with_clock(2, 60, clock.clone());
+
+        assert!(guard.check(IP, "How do I adjust the chain?").is_ok());
+        assert!(guard.check(IP, "how do I adjust  the chain").is_ok());
+        assert!(guard.check(IP, "HOW DO I ADJUST THE CHAIN?!").is_err());
+
+        // Other queries and other clients are unaffected
+        assert!(guard.check(IP, "How do I bleed the brakes?").is_ok());
+        assert!(guard.check(IpAddr::V4(Ipv4Addr::LOCALHOST), "How do I adjust the chain?").is_ok());
+
+        clock.advance(Duration::from_secs(61));
+        assert!(guard.check(IP, "How do I adjust the chain?").is_ok());
+    }
+
+    #[test]
+    fn test_zero_limit_disables_guard() {
+        let guard = RepeatGuard::new(0, 60);
+
+        for _ in 0..10 {
+            assert!(guard.check(IP, "same").is_ok());
+        }
+    }
+}
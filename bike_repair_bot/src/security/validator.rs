@@ -1,8 +1,65 @@
 use anyhow::Result;
 
+use crate::rag::DEFAULT_SYNONYMS;
+
+/// Reply to queries that may be about another kind of vehicle
+const OTHER_VEHICLE_STEER: &str = "I can only help with motorcycles and scooters. If your \
+    question is about a motorcycle, mention the bike or the part you're working on.";
+
+/// Reply to queries that may be bike-related but don't say so
+const BORDERLINE_STEER: &str = "I'm not sure this is about motorcycle repair. Could you \
+    mention your bike model or the part involved (chain, brakes, engine, ...)?";
+
+/// Reason given for queries below the configured minimum length
+const TOO_SHORT_REASON: &str = "Query is too short. Add a bit more detail: your bike model \
+    and what you're seeing (symptoms, noises, warning lights).";
+
+/// Share of a query's characters that may be special characters (other than
+/// common punctuation) before it is refused as a possible injection
+pub const DEFAULT_MAX_SPECIAL_CHAR_RATIO: f32 = 0.3;
+
+/// Outcome of validating a query
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryValidation {
+    /// Bike-related; answer it
+    Ok,
+
+    /// Possibly bike-related (other vehicles, vague mechanical wording, greetings);
+    /// reply with the given steer instead of an error
+    SoftReject(String),
+
+    /// Malicious, malformed or clearly off-topic; refuse with the given reason
+    HardReject(String),
+
+    /// Shorter than the configured minimum; refuse with the given reason
+    TooShort(String),
+}
+
+/// The query as it is validated and answered: surrounding whitespace dropped
+/// and inner runs of whitespace (newlines, tabs) collapsed to single spaces
+pub fn normalize_query(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
 /// Validate that a query is bike-related
 pub struct QueryValidator {
     bike_keywords: Vec<String>,
+
+    /// Vehicles the bot doesn't cover, matched as whole words
+    other_vehicle_words: Vec<&'static str>,
+
+    /// Vague mechanical terms and greetings, matched as whole words
+    borderline_words: Vec<&'static str>,
+
+    /// Queries with fewer words are refused as too short (0 disables)
+    min_words: usize,
+
+    /// Queries with fewer characters, surrounding whitespace aside, are
+    /// refused as too short (0 disables)
+    min_chars: usize,
+
+    /// Queries with a higher share of special characters are refused
+    max_special_char_ratio: f32,
 }
 
 impl QueryValidator {
@@ -55,39 +112,92 @@ impl QueryValidator {
                 "cbr", "r1", "r6", "ninja", "gsxr", "zx",
             ]
             .iter()
+            // Regional variants (tyre, petrol, silencer, ...) count as bike terms too
+            .chain(DEFAULT_SYNONYMS.iter().flat_map(|group| group.iter()))
             .map(|s| s.to_lowercase())
             .collect(),
+            other_vehicle_words: vec![
+                "car", "cars", "truck", "van", "vehicle", "bicycle", "bicycles", "ebike", "atv",
+                "quad",
+            ],
+            borderline_words: vec![
+                "ride", "riding", "rider", "noise", "rattle", "squeak", "squeal", "leak",
+                "leaking", "vibration", "smoke", "stall", "stalls", "stalling", "bolt", "nut",
+                "screw", "torque", "wrench", "tool", "tools", "part", "parts", "helmet",
+                "mileage", "hello", "hi", "hey", "help",
+            ],
+            min_words: 0,
+            min_chars: 0,
+            max_special_char_ratio: DEFAULT_MAX_SPECIAL_CHAR_RATIO,
         }
     }
 
-    /// Validate a query for bike-related content
-    pub fn validate(&self, query: &str) -> Result<()> {
+    /// Refuse queries shorter than `min_words` words or `min_chars`
+    /// characters; 0 disables either check
+    pub fn with_min_length(mut self, min_words: usize, min_chars: usize) -> Self {
+        self.min_words = min_words;
+        self.min_chars = min_chars;
+        self
+    }
+
+    /// Refuse queries whose share of special characters exceeds `ratio`
+    pub fn with_max_special_char_ratio(mut self, ratio: f32) -> Self {
+        self.max_special_char_ratio = ratio;
+        self
+    }
+
+    /// Classify a query: bike-related queries pass, vague or other-vehicle ones
+    /// get a steer, and malformed, malicious or clearly off-topic ones are refused
+    pub fn validate(&self, query: &str) -> QueryValidation {
         // Basic validation
         if query.trim().is_empty() {
-            anyhow::bail!("Query cannot be empty");
+            return QueryValidation::HardReject("Query cannot be empty".to_string());
         }
 
         if query.len() > 1000 {
-            anyhow::bail!("Query is too long (max 1000 characters)");
+            return QueryValidation::HardReject(
+                "Query is too long (max 1000 characters)".to_string(),
+            );
         }
 
         // Check for malicious patterns
-        self.check_malicious_patterns(query)?;
+        if let Err(e) = self.check_malicious_patterns(query) {
+            return QueryValidation::HardReject(e.to_string());
+        }
+
+        // One-word queries retrieve poorly and get vague answers
+        let trimmed = query.trim();
+        if trimmed.split_whitespace().count() < self.min_words
+            || trimmed.chars().count() < self.min_chars
+        {
+            return QueryValidation::TooShort(TOO_SHORT_REASON.to_string());
+        }
 
         // Check for bike-related keywords
         let query_lower = query.to_lowercase();
         let has_bike_keyword = self.bike_keywords
             .iter()
             .any(|keyword| query_lower.contains(keyword));
+        if has_bike_keyword {
+            return QueryValidation::Ok;
+        }
 
-        if !has_bike_keyword {
-            anyhow::bail!(
+        let has_word = |words: &[&str]| {
+            query_lower
+                .split(|c: char| !c.is_alphanumeric())
+                .any(|word| words.contains(&word))
+        };
+        if has_word(&self.other_vehicle_words) {
+            QueryValidation::SoftReject(OTHER_VEHICLE_STEER.to_string())
+        } else if has_word(&self.borderline_words) {
+            QueryValidation::SoftReject(BORDERLINE_STEER.to_string())
+        } else {
+            QueryValidation::HardReject(
                 "This chatbot only answers motorcycle repair and maintenance questions. \
                 Your query doesn't appear to be bike-related."
-            );
+                    .to_string(),
+            )
         }
-
-        Ok(())
     }
 
     /// Check for SQL injection, XSS, and other malicious patterns
@@ -118,8 +228,13 @@ impl QueryValidator {
             .filter(|c| !c.is_alphanumeric() && !c.is_whitespace() && *c != '?' && *c != '.' && *c != ',' && *c != '\'' && *c != '-')
             .count();
 
-        if special_char_count as f32 / query.len() as f32 > 0.3 {
-            log::warn!("Blocked query with excessive special characters");
+        let ratio = special_char_count as f32 / query.len() as f32;
+        if ratio > self.max_special_char_ratio {
+            log::warn!(
+                "Blocked query with excessive special characters (ratio {:.3}, max {:.3})",
+                ratio,
+                self.max_special_char_ratio
+            );
             anyhow::bail!("Query contains too many special characters");
         }
 
@@ -142,37 +257,107 @@ impl Default for QueryValidator {
 mod tests {
     use super::*;
 
+    fn is_hard(validation: QueryValidation) -> bool {
+        matches!(validation, QueryValidation::HardReject(_))
+    }
+
     #[test]
     fn test_valid_bike_queries() {
         let validator = QueryValidator::new();
         
-        assert!(validator.validate("How do I change my motorcycle oil?").is_ok());
-        assert!(validator.validate("Honda CBR600RR brake maintenance").is_ok());
-        assert!(validator.validate("Why is my bike engine making noise?").is_ok());
+        assert_eq!(validator.validate("How do I change my motorcycle oil?"), QueryValidation::Ok);
+        assert_eq!(validator.validate("Honda CBR600RR brake maintenance"), QueryValidation::Ok);
+        assert_eq!(validator.validate("Why is my bike engine making noise?"), QueryValidation::Ok);
+    }
+
+    #[test]
+    fn test_normalize_query_collapses_whitespace() {
+        assert_eq!(normalize_query("  chain\n\tslack   on my  R1 "), "chain slack on my R1");
+        assert_eq!(normalize_query(" \n "), "");
+    }
+
+    #[test]
+    fn test_borderline_queries_soft_rejected() {
+        let validator = QueryValidator::new();
+
+        // A bike term wins over a borderline one
+        assert_eq!(validator.validate("Can I fit car tyres on my scooter?"), QueryValidation::Ok);
+        assert_eq!(
+            validator.validate("My car makes a grinding sound"),
+            QueryValidation::SoftReject(OTHER_VEHICLE_STEER.to_string())
+        );
+        assert_eq!(
+            validator.validate("What torque for the rear axle nut?"),
+            QueryValidation::SoftReject(BORDERLINE_STEER.to_string())
+        );
+        assert_eq!(
+            validator.validate("Hi, can you help me?"),
+            QueryValidation::SoftReject(BORDERLINE_STEER.to_string())
+        );
+        // Whole words only: "scar" is not "car"
+        assert!(is_hard(validator.validate("How do I treat a scar?")));
     }
 
     #[test]
     fn test_invalid_non_bike_queries() {
         let validator = QueryValidator::new();
         
-        assert!(validator.validate("What's the weather today?").is_err());
-        assert!(validator.validate("Tell me a joke").is_err());
-        assert!(validator.validate("Who won the game?").is_err());
+        assert!(is_hard(validator.validate("What's the weather today?")));
+        assert!(is_hard(validator.validate("Tell me a joke")));
+        assert!(is_hard(validator.validate("Who won the game?")));
     }
 
     #[test]
     fn test_malicious_queries() {
         let validator = QueryValidator::new();
         
-        assert!(validator.validate("DROP TABLE users").is_err());
-        assert!(validator.validate("<script>alert('xss')</script>").is_err());
-        assert!(validator.validate("../../../etc/passwd").is_err());
+        assert!(is_hard(validator.validate("DROP TABLE users")));
+        assert!(is_hard(validator.validate("<script>alert('xss')</script>")));
+        assert!(is_hard(validator.validate("../../../etc/passwd")));
+        // Borderline wording does not soften a malicious query
+        assert!(is_hard(validator.validate("help <script>alert('car')</script>")));
+    }
+
+    #[test]
+    fn test_minimum_length_refuses_short_queries() {
+        let too_short = QueryValidation::TooShort(TOO_SHORT_REASON.to_string());
+
+        // Off by default
+        assert_eq!(QueryValidator::new().validate("oil"), QueryValidation::Ok);
+
+        let by_words = QueryValidator::new().with_min_length(3, 0);
+        assert_eq!(by_words.validate("oil"), too_short);
+        assert_eq!(by_words.validate("  chain   slack  "), too_short);
+        assert_eq!(by_words.validate("R1 chain slack"), QueryValidation::Ok);
+
+        let by_chars = QueryValidator::new().with_min_length(0, 12);
+        assert_eq!(by_chars.validate("brake pads"), too_short);
+        assert_eq!(by_chars.validate(" brake pads?"), too_short);
+        assert_eq!(by_chars.validate("brake pads R1"), QueryValidation::Ok);
+        assert_eq!(by_chars.validate("brake pad R1"), QueryValidation::Ok);
+
+        // Malformed queries are still refused outright
+        assert!(is_hard(by_words.validate("<script>")));
+    }
+
+    #[test]
+    fn test_special_char_ratio_threshold() {
+        // 2 special characters in 9
+        let query = "engine ##";
+        assert_eq!(QueryValidator::new().validate(query), QueryValidation::Ok);
+
+        let at_limit = QueryValidator::new().with_max_special_char_ratio(2.0 / 9.0);
+        assert_eq!(at_limit.validate(query), QueryValidation::Ok);
+
+        let below = QueryValidator::new().with_max_special_char_ratio(0.2);
+        assert!(is_hard(below.validate(query)));
+        assert_eq!(below.validate("engine oil, 10W-40?"), QueryValidation::Ok);
     }
 
     #[test]
     fn test_empty_query() {
         let validator = QueryValidator::new();
-        assert!(validator.validate("").is_err());
-        assert!(validator.validate("   ").is_err());
+        assert!(is_hard(validator.validate("")));
+        assert!(is_hard(validator.validate("   ")));
     }
 }
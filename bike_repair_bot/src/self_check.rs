@@ -0,0 +1,143 @@
+//! Startup self-check (`--check`): validates the configuration and reaches
+//! OpenAI and the vector store, without starting the server
+
+use anyhow::Result;
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::ai::AiProvider;
+use crate::config::Config;
+use crate::rag::VectorStore;
+
+/// Longest a single check may take before it counts as failed
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Outcome of one check: what was found, or why it failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckOutcome {
+    pub name: &'static str,
+    pub result: Result<String, String>,
+}
+
+/// Outcomes of all checks, in the order they ran
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    pub checks: Vec<CheckOutcome>,
+}
+
+impl CheckReport {
+    pub fn record(&mut self, name: &'static str, result: Result<String>) {
+        self.checks.push(CheckOutcome {
+            name,
+            result: result.map_err(|e| format!("{:#}", e)),
+        });
+    }
+
+    /// Number of failed checks
+    pub fn failures(&self) -> usize {
+        self.checks.iter().filter(|c| c.result.is_err()).count()
+    }
+
+    /// Every check ran and passed
+    pub fn passed(&self) -> bool {
+        !self.checks.is_empty() && self.failures() == 0
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+        for check in &self.checks {
+            let (status, detail) = match &check.result {
+                Ok(detail) => ("PASS", detail),
+                Err(error) => ("FAIL", error),
+            };
+            writeln!(f, "{}  {:width$}  {}", status, check.name, detail)?;
+        }
+        write!(
+            f,
+            "\n{} passed, {} failed",
+            self.checks.len() - self.failures(),
+            self.failures()
+        )
+    }
+}
+
+/// Validate the configuration, probe OpenAI with the configured key and models,
+/// and open the active vector store collection
+pub async fn run(config: &Config, ai_provider: &dyn AiProvider) -> CheckReport {
+    let mut report = CheckReport::default();
+
+    let config_valid = config.validate().map(|()| "valid".to_string());
+    report.record("config", config_valid.map_err(Into::into));
+
+    let openai = with_timeout(ai_provider.check_access()).await.map(|()| {
+        format!(
+            "key accepted, {} and {} available",
+            config.openai_chat_model, config.openai_embedding_model
+        )
+    });
+    report.record("openai", openai);
+
+    let vector_store = with_timeout(async {
+        let store =
+            VectorStore::new(&config.vector_store_path, &config.vector_store_collection, config.vector_distance)
+                .await?;
+        Ok(format!(
+            "collection '{}' at {}, {} chunks",
+            store.collection(),
+            config.vector_store_path,
+            store.count().await
+        ))
+    })
+    .await;
+    report.record("vector store", vector_store);
+
+    report
+}
+
+async fn with_timeout<T>(check: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("no answer within {}s", CHECK_TIMEOUT.as_secs())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_fails_when_any_check_fails() {
+        let mut report = CheckReport::default();
+        assert!(!report.passed());
+
+        report.record("config", Ok("valid".to_string()));
+        report.record("openai", Ok("key accepted".to_string()));
+        assert!(report.passed());
+
+        report.record("vector store", Err(anyhow::anyhow!("disk full")));
+        assert!(!report.passed());
+        assert_eq!(report.failures(), 1);
+
+        let rendered = report.to_string();
+        assert!(rendered.contains("PASS  config        valid"), "{}", rendered);
+        assert!(rendered.contains("FAIL  vector store  disk full"), "{}", rendered);
+        assert!(rendered.ends_with("2 passed, 1 failed"));
+    }
+
+    #[tokio::test]
+    async fn test_run_reports_every_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            openai_api_key: "sk-test".to_string(),
+            vector_store_path: dir.path().to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+
+        let report = run(&config, &crate::ai::mock::MockProvider::new("ok")).await;
+        let names: Vec<&str> = report.checks.iter().map(|c| c.name).collect();
+        assert_eq!(names, ["config", "openai", "vector store"]);
+        assert!(report.passed(), "{}", report);
+    }
+}
@@ -0,0 +1,190 @@
+use flate2::write::{DeflateEncoder, GzEncoder};
+use std::io::Write;
+use warp::http::header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use warp::http::StatusCode;
+use warp::hyper::body::{self, Body};
+use warp::reply::Response;
+use warp::Reply;
+
+/// Brotli quality; the top levels cost far more CPU than they save bytes
+const BROTLI_QUALITY: u32 = 5;
+
+/// Brotli window size (log2 bytes)
+const BROTLI_WINDOW: u32 = 22;
+
+/// Content encodings this server can produce, most preferred first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut output = Vec::new();
+                let mut writer =
+                    brotli::CompressorWriter::new(&mut output, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                writer.write_all(data)?;
+                drop(writer);
+                Ok(output)
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Encoding to use for a client sending `accept_encoding`: the supported one
+/// with the highest q-value, ties going to the better compression. `None`
+/// when the client accepts none of them.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in Encoding::ALL {
+        let quality = accept_encoding
+            .split(',')
+            .filter_map(|entry| {
+                let mut parts = entry.split(';');
+                let name = parts.next()?.trim();
+                if !name.eq_ignore_ascii_case(encoding.as_str()) && name != "*" {
+                    return None;
+                }
+                let quality = parts
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                // An explicit entry overrides the wildcard
+                Some((name != "*", quality))
+            })
+            .max_by(|a, b| a.0.cmp(&b.0))
+            .map(|(_, quality)| quality);
+        match quality {
+            Some(q) if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) => {
+                best = Some((encoding, q));
+            }
+            _ => {}
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Compress `reply` for a client sending `accept_encoding` when its body is at
+/// least `min_bytes`. Server-Sent Events pass through untouched so events
+/// still reach the client as they are written, as do WebSocket upgrades.
+pub async fn compress(
+    accept_encoding: Option<String>,
+    min_bytes: u64,
+    reply: impl Reply,
+) -> Response {
+    let response = reply.into_response();
+    let headers = response.headers();
+    let is_event_stream = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    let upgrade = response.status() == StatusCode::SWITCHING_PROTOCOLS;
+    if is_event_stream || upgrade || headers.contains_key(CONTENT_ENCODING) {
+        return response;
+    }
+    let small = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok())
+        .is_some_and(|length| length < min_bytes);
+
+    let (mut parts, body) = response.into_parts();
+    parts.headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    let encoding = accept_encoding.as_deref().and_then(negotiate);
+    let Some(encoding) = encoding.filter(|_| !small) else {
+        return Response::from_parts(parts, body);
+    };
+
+    let data = match body::to_bytes(body).await {
+        Ok(data) => data,
+        Err(e) => {
+            log::warn!("Failed to read a response body for compression: {}", e);
+            parts.status = StatusCode::INTERNAL_SERVER_ERROR;
+            parts.headers.remove(CONTENT_LENGTH);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    if (data.len() as u64) < min_bytes {
+        return Response::from_parts(parts, Body::from(data));
+    }
+    match encoding.encode(&data) {
+        Ok(compressed) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            parts
+                .headers
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str()));
+            Response::from_parts(parts, Body::from(compressed))
+        }
+        Err(e) => {
+            log::warn!("Failed to {}-compress a response: {}", encoding.as_str(), e);
+            Response::from_parts(parts, Body::from(data))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_by_quality_then_preference() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip, deflate"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=0.5, gzip;q=0.8"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("DEFLATE"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("*, br;q=0"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip;q=0"), None);
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[test]
+    fn test_encodings_round_trip() {
+        let data = "Torque the axle nut to 100 Nm. ".repeat(100);
+        for encoding in Encoding::ALL {
+            let compressed = encoding.encode(data.as_bytes()).unwrap();
+            assert!(compressed.len() < data.len() / 4, "{:?}", encoding);
+
+            let mut decoded = Vec::new();
+            match encoding {
+                Encoding::Brotli => {
+                    brotli::BrotliDecompress(&mut compressed.as_slice(), &mut decoded).unwrap();
+                }
+                Encoding::Gzip => {
+                    let mut decoder = flate2::write::GzDecoder::new(&mut decoded);
+                    decoder.write_all(&compressed).unwrap();
+                    decoder.finish().unwrap();
+                }
+                Encoding::Deflate => {
+                    let mut decoder = flate2::write::DeflateDecoder::new(&mut decoded);
+                    decoder.write_all(&compressed).unwrap();
+                    decoder.finish().unwrap();
+                }
+            }
+            assert_eq!(decoded, data.as_bytes(), "{:?}", encoding);
+        }
+    }
+}
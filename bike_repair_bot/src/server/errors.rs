@@ -0,0 +1,405 @@
+//! Errors the handlers fail with. They are raised as warp rejections and
+//! answered with an `ErrorResponse` by `routes::handle_rejection`.
+
+use warp::http::StatusCode;
+use warp::reject::{Reject, Rejection};
+
+use crate::ingestion::remote::FetchError;
+use crate::models::{ErrorResponse, FieldError, RateLimitInfo};
+use crate::pdf::{ExtractError, PageRangeError};
+
+/// A refused or failed request, one variant per error code
+#[derive(Debug)]
+pub enum ApiError {
+    /// A chat request field is missing, malformed or out of range
+    InvalidField(FieldError),
+    InvalidRequest(String),
+    /// The query failed validation
+    InvalidQuery(String),
+    /// The query is shorter than `MIN_QUERY_WORDS` or `MIN_QUERY_CHARS`
+    QueryTooShort(String),
+    InvalidFeedback(String),
+    InvalidUpload(String),
+    InvalidUpdate(String),
+    InvalidPageRanges {
+        message: String,
+        details: Option<String>,
+    },
+    InvalidIp(String),
+    InvalidConfig(String),
+    /// A JSON body that does not decode, with the decoder's error
+    InvalidJson(String),
+    MissingFile,
+    /// An `Authorization` key that is not configured
+    InvalidApiKey,
+    /// A missing or wrong chat or admin key
+    Unauthorized(&'static str),
+    IpBlocked,
+    /// An `X-Tenant-Id` naming another tenant than the request's API key, or a
+    /// tenant with API keys on a request without one
+    TenantMismatch,
+    /// A path that does not exist, like the management endpoints without
+    /// `ADMIN_API_KEY`
+    NotFound,
+    SessionNotFound(String),
+    DocumentNotFound(String),
+    SnapshotNotFound(String),
+    ResponseNotFound,
+    MethodNotAllowed,
+    DocumentProcessing(String),
+    DocumentCompleted(String),
+    DuplicateDocument {
+        filename: String,
+        document_id: String,
+    },
+    NotResumable(String),
+    NotReprocessable(String),
+    LengthRequired,
+    PayloadTooLarge,
+    FileTooLarge(usize),
+    /// The files of one upload together exceed this many bytes
+    UploadTooLarge(usize),
+    /// An upload that is not a PDF, text, Markdown or HTML file
+    UnsupportedMediaType(String),
+    /// A file that cannot be read (`PDF_ENCRYPTED`, `PDF_MALFORMED`, ...)
+    Unreadable {
+        filename: String,
+        error: ExtractError,
+    },
+    ExtractionFailed(String),
+    /// `Retry-After` is sent when `retry_after` is set, the caller's limit in
+    /// the body when `rate_limit_info` is
+    RateLimitExceeded {
+        message: String,
+        retry_after: Option<u64>,
+        rate_limit_info: Option<RateLimitInfo>,
+    },
+    RepeatedQuery(String),
+    /// A session spent its `SESSION_TOKEN_BUDGET`
+    SessionBudgetExceeded(String),
+    /// Details are logged where the error happens, not sent to the client
+    Internal(&'static str),
+    AiError,
+    /// A manual could not be fetched from its URL
+    Download(FetchError),
+    ServiceUnavailable(String),
+    /// Chat is switched off for maintenance; carries the message for callers
+    Maintenance(String),
+    /// A chat did not finish within `REQUEST_TIMEOUT_SECONDS` (the limit)
+    RequestTimeout(u64),
+}
+
+impl ApiError {
+    /// 400 `INVALID_PAGE_RANGES` with the reason in `details`
+    pub fn page_ranges(ranges: &str, error: &PageRangeError) -> Self {
+        ApiError::InvalidPageRanges {
+            message: format!("Invalid page_ranges '{}'", ranges),
+            details: Some(error.to_string()),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidField(_)
+            | ApiError::InvalidRequest(_)
+            | ApiError::InvalidFeedback(_)
+            | ApiError::InvalidUpload(_)
+            | ApiError::InvalidUpdate(_)
+            | ApiError::InvalidPageRanges { .. }
+            | ApiError::InvalidIp(_)
+            | ApiError::InvalidConfig(_)
+            | ApiError::InvalidJson(_)
+            | ApiError::MissingFile => StatusCode::BAD_REQUEST,
+            ApiError::InvalidApiKey | ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::IpBlocked | ApiError::TenantMismatch => StatusCode::FORBIDDEN,
+            ApiError::NotFound
+            | ApiError::SessionNotFound(_)
+            | ApiError::DocumentNotFound(_)
+            | ApiError::SnapshotNotFound(_)
+            | ApiError::ResponseNotFound => StatusCode::NOT_FOUND,
+            ApiError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::DocumentProcessing(_)
+            | ApiError::DocumentCompleted(_)
+            | ApiError::DuplicateDocument { .. }
+            | ApiError::NotResumable(_)
+            | ApiError::NotReprocessable(_) => StatusCode::CONFLICT,
+            ApiError::LengthRequired => StatusCode::LENGTH_REQUIRED,
+            ApiError::PayloadTooLarge | ApiError::FileTooLarge(_) | ApiError::UploadTooLarge(_) => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            // Well-formed, but refused on its content
+            ApiError::InvalidQuery(_)
+            | ApiError::QueryTooShort(_)
+            | ApiError::Unreadable { .. }
+            | ApiError::ExtractionFailed(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::RateLimitExceeded { .. }
+            | ApiError::RepeatedQuery(_)
+            | ApiError::SessionBudgetExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Internal(_) | ApiError::AiError => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Download(e) => match e {
+                FetchError::InvalidUrl(_) | FetchError::InsecureScheme => StatusCode::BAD_REQUEST,
+                FetchError::HostNotAllowed(_) | FetchError::ForbiddenAddress(_) => {
+                    StatusCode::FORBIDDEN
+                }
+                FetchError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+                FetchError::NotPdf(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                FetchError::Resolve(_)
+                | FetchError::Request(_)
+                | FetchError::Status(_)
+                | FetchError::TooManyRedirects => StatusCode::BAD_GATEWAY,
+            },
+            ApiError::ServiceUnavailable(_) | ApiError::Maintenance(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ApiError::RequestTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidField(_) => "INVALID_FIELD",
+            ApiError::InvalidRequest(_) => "INVALID_REQUEST",
+            ApiError::InvalidQuery(_) => "INVALID_QUERY",
+            ApiError::QueryTooShort(_) => "QUERY_TOO_SHORT",
+            ApiError::InvalidFeedback(_) => "INVALID_FEEDBACK",
+            ApiError::InvalidUpload(_) => "INVALID_UPLOAD",
+            ApiError::InvalidUpdate(_) => "INVALID_UPDATE",
+            ApiError::InvalidPageRanges { .. } => "INVALID_PAGE_RANGES",
+            ApiError::InvalidIp(_) => "INVALID_IP",
+            ApiError::InvalidConfig(_) => "INVALID_CONFIG",
+            ApiError::InvalidJson(_) => "INVALID_JSON",
+            ApiError::MissingFile => "MISSING_FILE",
+            ApiError::InvalidApiKey => "INVALID_API_KEY",
+            ApiError::Unauthorized(_) => "UNAUTHORIZED",
+            ApiError::IpBlocked => "IP_BLOCKED",
+            ApiError::TenantMismatch => "TENANT_MISMATCH",
+            ApiError::NotFound => "NOT_FOUND",
+            ApiError::SessionNotFound(_) => "SESSION_NOT_FOUND",
+            ApiError::DocumentNotFound(_) => "DOCUMENT_NOT_FOUND",
+            ApiError::SnapshotNotFound(_) => "SNAPSHOT_NOT_FOUND",
+            ApiError::ResponseNotFound => "RESPONSE_NOT_FOUND",
+            ApiError::MethodNotAllowed => "METHOD_NOT_ALLOWED",
+            ApiError::DocumentProcessing(_) => "DOCUMENT_PROCESSING",
+            ApiError::DocumentCompleted(_) => "DOCUMENT_COMPLETED",
+            ApiError::DuplicateDocument { .. } => "DUPLICATE_DOCUMENT",
+            ApiError::NotResumable(_) => "NOT_RESUMABLE",
+            ApiError::NotReprocessable(_) => "NOT_REPROCESSABLE",
+            ApiError::LengthRequired => "LENGTH_REQUIRED",
+            ApiError::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            ApiError::FileTooLarge(_) => "FILE_TOO_LARGE",
+            ApiError::UploadTooLarge(_) => "UPLOAD_TOO_LARGE",
+            ApiError::UnsupportedMediaType(_) => "UNSUPPORTED_MEDIA_TYPE",
+            ApiError::Unreadable { error, .. } => error.code(),
+            ApiError::ExtractionFailed(_) => "EXTRACTION_FAILED",
+            ApiError::RateLimitExceeded { .. } => "RATE_LIMIT_EXCEEDED",
+            ApiError::RepeatedQuery(_) => "REPEATED_QUERY",
+            ApiError::SessionBudgetExceeded(_) => "SESSION_BUDGET_EXCEEDED",
+            ApiError::Internal(_) => "INTERNAL_ERROR",
+            ApiError::AiError => "AI_ERROR",
+            ApiError::Download(e) => match e {
+                FetchError::InvalidUrl(_) | FetchError::InsecureScheme => "INVALID_URL",
+                FetchError::HostNotAllowed(_) | FetchError::ForbiddenAddress(_) => {
+                    "URL_NOT_ALLOWED"
+                }
+                FetchError::TooLarge(_) => "FILE_TOO_LARGE",
+                FetchError::NotPdf(_) => "UNSUPPORTED_MEDIA_TYPE",
+                FetchError::Resolve(_)
+                | FetchError::Request(_)
+                | FetchError::Status(_)
+                | FetchError::TooManyRedirects => "DOWNLOAD_FAILED",
+            },
+            ApiError::ServiceUnavailable(_) => "SERVICE_UNAVAILABLE",
+            ApiError::Maintenance(_) => "MAINTENANCE",
+            ApiError::RequestTimeout(_) => "REQUEST_TIMEOUT",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ApiError::InvalidField(e) => format!("Invalid field '{}'", e.field),
+            ApiError::InvalidRequest(message)
+            | ApiError::InvalidQuery(message)
+            | ApiError::QueryTooShort(message)
+            | ApiError::InvalidFeedback(message)
+            | ApiError::InvalidUpload(message)
+            | ApiError::InvalidUpdate(message)
+            | ApiError::InvalidPageRanges { message, .. }
+            | ApiError::InvalidIp(message)
+            | ApiError::InvalidConfig(message)
+            | ApiError::ExtractionFailed(message)
+            | ApiError::RateLimitExceeded { message, .. }
+            | ApiError::RepeatedQuery(message)
+            | ApiError::SessionBudgetExceeded(message)
+            | ApiError::ServiceUnavailable(message)
+            | ApiError::Maintenance(message) => message.clone(),
+            ApiError::InvalidJson(_) => "Invalid JSON body".to_string(),
+            ApiError::MissingFile => "Missing 'file' field".to_string(),
+            ApiError::InvalidApiKey => "Invalid API key".to_string(),
+            ApiError::Unauthorized(message) | ApiError::Internal(message) => message.to_string(),
+            ApiError::IpBlocked => "Access denied".to_string(),
+            ApiError::TenantMismatch => {
+                "X-Tenant-Id names a tenant the request has no key for".to_string()
+            }
+            ApiError::NotFound => "Not found".to_string(),
+            ApiError::SessionNotFound(id) => format!("Session '{}' not found", id),
+            ApiError::DocumentNotFound(id) => format!("Document '{}' not found", id),
+            ApiError::SnapshotNotFound(name) => format!("Snapshot '{}' not found", name),
+            ApiError::ResponseNotFound => "No such answer in this session".to_string(),
+            ApiError::MethodNotAllowed => "Method not allowed".to_string(),
+            ApiError::DocumentProcessing(id) => format!("Document '{}' is still being ingested", id),
+            ApiError::DocumentCompleted(id) => {
+                format!("Document '{}' is already completed; nothing to resume", id)
+            }
+            ApiError::DuplicateDocument {
+                filename,
+                document_id,
+            } => format!(
+                "'{}' duplicates document {}; pass ?force=true to replace it",
+                filename, document_id
+            ),
+            ApiError::NotResumable(id) => {
+                format!("Document '{}' has no saved progress; upload it again", id)
+            }
+            ApiError::NotReprocessable(id) => {
+                format!("Document '{}' has no stored page text; upload it again", id)
+            }
+            ApiError::LengthRequired => "A Content-Length header is required".to_string(),
+            ApiError::PayloadTooLarge => "Request body is too large".to_string(),
+            ApiError::FileTooLarge(max_bytes) => {
+                format!("File exceeds the {} byte limit", max_bytes)
+            }
+            ApiError::UploadTooLarge(max_bytes) => {
+                format!("Files together exceed the {} byte limit", max_bytes)
+            }
+            ApiError::UnsupportedMediaType(filename) => {
+                format!("'{}' is not a PDF, text, Markdown or HTML file", filename)
+            }
+            ApiError::Unreadable { filename, error } => {
+                format!("Cannot read '{}': {}", filename, error)
+            }
+            ApiError::AiError => "Failed to generate response. Please try again.".to_string(),
+            ApiError::Download(e) => e.to_string(),
+            ApiError::RequestTimeout(seconds) => {
+                format!("Request did not complete within {} seconds", seconds)
+            }
+        }
+    }
+
+    fn details(&self) -> Option<String> {
+        match self {
+            ApiError::InvalidField(e) => Some(e.reason.clone()),
+            ApiError::InvalidPageRanges { details, .. } => details.clone(),
+            ApiError::InvalidJson(details) => Some(details.clone()),
+            ApiError::DuplicateDocument { document_id, .. } => Some(document_id.clone()),
+            _ => None,
+        }
+    }
+
+    /// Seconds to send in `Retry-After`
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            ApiError::RateLimitExceeded { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Body of the error reply
+    pub fn to_response(&self) -> ErrorResponse {
+        let mut response = ErrorResponse::new(self.message(), self.code());
+        response.details = self.details();
+        if let ApiError::RateLimitExceeded {
+            rate_limit_info: Some(info),
+            ..
+        } = self
+        {
+            response = response.with_rate_limit_info(info.clone());
+        }
+        response
+    }
+
+    /// Reject with this error, answered with the caller's remaining rate limit
+    /// in the `X-RateLimit-*` headers like a successful reply
+    pub fn with_rate_limit(self, info: &RateLimitInfo) -> Rejection {
+        warp::reject::custom(RateLimitedError {
+            error: self,
+            rate_limit_info: info.clone(),
+        })
+    }
+}
+
+/// Handlers return `Err(error.into())` or use `?` on an `ApiError`
+impl Reject for ApiError {}
+
+/// An `ApiError` raised after the caller's rate limit was recorded
+#[derive(Debug)]
+pub struct RateLimitedError {
+    pub error: ApiError,
+    pub rate_limit_info: RateLimitInfo,
+}
+
+impl Reject for RateLimitedError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_keep_their_status_and_code() {
+        let cases = [
+            (ApiError::InvalidApiKey, 401, "INVALID_API_KEY"),
+            (ApiError::IpBlocked, 403, "IP_BLOCKED"),
+            (ApiError::TenantMismatch, 403, "TENANT_MISMATCH"),
+            (ApiError::DocumentProcessing("d".into()), 409, "DOCUMENT_PROCESSING"),
+            (ApiError::FileTooLarge(10), 413, "FILE_TOO_LARGE"),
+            (ApiError::UploadTooLarge(10), 413, "UPLOAD_TOO_LARGE"),
+            (
+                ApiError::Unreadable {
+                    filename: "a.pdf".into(),
+                    error: ExtractError::Encrypted,
+                },
+                422,
+                "PDF_ENCRYPTED",
+            ),
+            (ApiError::InvalidJson("eof".into()), 400, "INVALID_JSON"),
+            (ApiError::InvalidQuery("off topic".into()), 422, "INVALID_QUERY"),
+            (ApiError::QueryTooShort("too short".into()), 422, "QUERY_TOO_SHORT"),
+            (ApiError::RepeatedQuery("again".into()), 429, "REPEATED_QUERY"),
+            (ApiError::SessionBudgetExceeded("spent".into()), 429, "SESSION_BUDGET_EXCEEDED"),
+            (ApiError::Download(FetchError::InsecureScheme), 400, "INVALID_URL"),
+            (ApiError::Download(FetchError::TooManyRedirects), 502, "DOWNLOAD_FAILED"),
+            (ApiError::ServiceUnavailable("open".into()), 503, "SERVICE_UNAVAILABLE"),
+            (ApiError::Maintenance("back soon".into()), 503, "MAINTENANCE"),
+            (ApiError::RequestTimeout(60), 504, "REQUEST_TIMEOUT"),
+        ];
+        for (error, status, code) in cases {
+            assert_eq!(error.status().as_u16(), status, "{:?}", error);
+            assert_eq!(error.code(), code);
+            assert_eq!(error.to_response().code, code);
+        }
+    }
+
+    #[test]
+    fn test_details_and_rate_limit_info_in_body() {
+        let duplicate = ApiError::DuplicateDocument {
+            filename: "manual.pdf".into(),
+            document_id: "doc-1".into(),
+        }
+        .to_response();
+        assert_eq!(duplicate.details.as_deref(), Some("doc-1"));
+        assert!(duplicate.error.contains("?force=true"));
+
+        let info = RateLimitInfo {
+            remaining_minute: 0,
+            remaining_hour: 5,
+            reset_in_seconds: 0,
+        };
+        let limited = ApiError::RateLimitExceeded {
+            message: "Too many requests".into(),
+            retry_after: Some(1),
+            rate_limit_info: Some(info),
+        };
+        assert_eq!(limited.retry_after(), Some(1));
+        assert_eq!(limited.to_response().rate_limit_info.unwrap().remaining_hour, 5);
+    }
+}
@@ -0,0 +1,822 @@
+//! Management endpoints behind `X-Admin-Key`: stats, reload, snapshots,
+//! IP blocking, usage and maintenance mode
+
+use warp::{reject::Rejection, reply::Reply};
+
+use crate::models::{
+    BlockRequest, ErrorResponse, IndexStats, MaintenanceRequest, RestoreRequest, UsageQuery,
+    UsageReport, DEFAULT_USAGE_DAYS, MAX_USAGE_BUCKETS,
+};
+use crate::server::errors::ApiError;
+use crate::server::routes::AppState;
+use crate::ai::load_system_prompt;
+use crate::config::{Config, ReloadableSettings};
+use crate::rag::{create_snapshot, list_snapshots, restore_snapshot};
+use crate::usage::TokenPrices;
+
+/// Admin stats - index size broken down per tenant
+#[utoipa::path(
+    get,
+    path = "/api/admin/stats",
+    tag = "admin",
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "Chunks in the index per tenant", body = Object),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_admin_stats(
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    let vector_store = state.retriever.vector_store();
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({
+            "collection": vector_store.collection(),
+            "total_chunks": vector_store.count().await,
+            "chunks_per_tenant": vector_store.count_by_tenant().await,
+        })),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+/// Index health: vectors stored, their size on disk and the bike models covered
+#[utoipa::path(
+    get,
+    path = "/api/admin/index-stats",
+    tag = "admin",
+    security(("admin_key" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Size of the index and chunks per bike model",
+            body = IndexStats
+        ),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_index_stats(state: AppState) -> Result<impl Reply, Rejection> {
+    let vector_store = state.retriever.vector_store();
+    Ok(warp::reply::json(&IndexStats {
+        collection: vector_store.collection().to_string(),
+        distance: vector_store.distance().as_str().to_string(),
+        dimension: vector_store.dimension().await,
+        points: vector_store.count().await,
+        disk_bytes: vector_store.disk_size().await,
+        bike_models: vector_store.count_by_bike_model(None).await,
+    }))
+}
+
+/// Reload rate limits, circuit breaker settings, retrieval params and the system prompt
+#[utoipa::path(
+    post,
+    path = "/api/admin/reload",
+    tag = "admin",
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "The settings now in effect", body = Object),
+        (
+            status = 400,
+            description = "The new configuration is invalid; nothing was changed",
+            body = ErrorResponse
+        ),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_reload(
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    let overrides = match Config::read_env_file(".env") {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            log::warn!("Config reload rejected: .env: {}", e);
+            return Err(ApiError::InvalidConfig(format!(".env: {}", e)).into());
+        }
+    };
+
+    let config = match Config::from_env_with(overrides)
+        .and_then(|config| config.validate().map(|()| config))
+    {
+        Ok(config) => config,
+        Err(e) => {
+            log::warn!("Config reload rejected: {}", e);
+            return Err(ApiError::InvalidConfig(e.to_string()).into());
+        }
+    };
+    let settings = ReloadableSettings::from_config(&config);
+
+    let system_prompt = match load_system_prompt(settings.system_prompt_path.as_deref()) {
+        Ok(prompt) => prompt,
+        Err(e) => {
+            log::warn!("Config reload rejected: {}", e);
+            return Err(ApiError::InvalidConfig(e.to_string()).into());
+        }
+    };
+
+    state
+        .rate_limiter
+        .update_limits(settings.max_requests_per_minute, settings.max_requests_per_hour);
+    state.circuit_breaker.update_settings(
+        settings.circuit_breaker_threshold,
+        settings.circuit_breaker_timeout_seconds,
+    );
+    state
+        .retriever
+        .update_params(settings.retrieval_top_k, settings.min_confidence);
+    *state.system_prompt.write().unwrap() = system_prompt;
+
+    let requires_restart = state.config.changed_restart_settings(&config);
+    if !requires_restart.is_empty() {
+        log::warn!("Config reloaded; changes to {:?} require a restart", requires_restart);
+    } else {
+        log::info!("Config reloaded");
+    }
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({
+            "reloaded": settings,
+            "requires_restart": requires_restart,
+        })),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+/// Snapshot the active collection into `SNAPSHOT_DIR`
+#[utoipa::path(
+    post,
+    path = "/api/admin/snapshots",
+    tag = "admin",
+    security(("admin_key" = [])),
+    responses(
+        (status = 201, description = "The snapshot written", body = Object),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_create_snapshot(state: AppState) -> Result<impl Reply, Rejection> {
+    let dir = std::path::Path::new(&state.config.snapshot_dir);
+    let vector_store = state.retriever.vector_store();
+    match create_snapshot(vector_store, dir, state.config.snapshot_keep).await {
+        Ok(info) => Ok(warp::reply::with_status(
+            warp::reply::json(&info),
+            warp::http::StatusCode::CREATED,
+        )),
+        Err(e) => {
+            log::error!("Snapshot of '{}' failed: {:#}", vector_store.collection(), e);
+            Err(ApiError::Internal("Snapshot failed").into())
+        }
+    }
+}
+
+/// Snapshots of the active collection, newest first
+#[utoipa::path(
+    get,
+    path = "/api/admin/snapshots",
+    tag = "admin",
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "Snapshots of the active collection", body = Object),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_list_snapshots(state: AppState) -> Result<impl Reply, Rejection> {
+    let dir = std::path::Path::new(&state.config.snapshot_dir);
+    let vector_store = state.retriever.vector_store();
+    match list_snapshots(dir, vector_store.collection()).await {
+        Ok(snapshots) => Ok(warp::reply::json(&serde_json::json!({
+            "collection": vector_store.collection(),
+            "snapshots": snapshots,
+        }))),
+        Err(e) => {
+            log::error!("Listing snapshots in {} failed: {:#}", dir.display(), e);
+            Err(ApiError::Internal("Listing snapshots failed").into())
+        }
+    }
+}
+
+/// Replace the active collection's chunks with those of a snapshot
+#[utoipa::path(
+    post,
+    path = "/api/admin/snapshots/restore",
+    tag = "admin",
+    request_body = RestoreRequest,
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "Chunks restored", body = Object),
+        (status = 400, description = "Not a snapshot file name", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "No such snapshot, or management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_restore_snapshot(
+    req: RestoreRequest,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    let dir = std::path::Path::new(&state.config.snapshot_dir);
+    match restore_snapshot(state.retriever.vector_store(), dir, &req.snapshot).await {
+        Ok(Some(info)) => Ok(warp::reply::json(&info)),
+        Ok(None) => Err(ApiError::SnapshotNotFound(req.snapshot).into()),
+        Err(e) => {
+            log::warn!("Restore from snapshot {} rejected: {:#}", req.snapshot, e);
+            Err(ApiError::InvalidRequest(format!("{:#}", e)).into())
+        }
+    }
+}
+
+/// Block an IP or CIDR range
+#[utoipa::path(
+    post,
+    path = "/api/admin/block",
+    tag = "admin",
+    request_body = BlockRequest,
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "The address is blocked", body = Object),
+        (status = 400, description = "Not an IP or CIDR range", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_block_ip(
+    req: BlockRequest,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    match state.block_list.block(&req.ip) {
+        Ok(net) => Ok(warp::reply::json(&serde_json::json!({
+            "blocked": net.to_string(),
+            "entries": state.block_list.entries(),
+        }))),
+        Err(e) => Err(ApiError::InvalidIp(e.to_string()).into()),
+    }
+}
+
+/// Remove an IP or CIDR range from the block list
+#[utoipa::path(
+    delete,
+    path = "/api/admin/block",
+    tag = "admin",
+    request_body = BlockRequest,
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "The address is no longer blocked", body = Object),
+        (status = 400, description = "Not an IP or CIDR range", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_unblock_ip(
+    req: BlockRequest,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    match state.block_list.unblock(&req.ip) {
+        Ok(removed) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "removed": removed,
+                "entries": state.block_list.entries(),
+            })),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => Err(ApiError::InvalidIp(e.to_string()).into()),
+    }
+}
+
+/// Request counts, unique sessions, tokens, estimated cost, error rate and p95
+/// latency of the chat endpoints, in total and per hour or day
+#[utoipa::path(
+    get,
+    path = "/api/admin/usage",
+    tag = "admin",
+    params(UsageQuery),
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "Usage aggregates of the range", body = UsageReport),
+        (
+            status = 400,
+            description = "`from` is not before `to`, or the range has too many buckets",
+            body = ErrorResponse
+        ),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_usage(query: UsageQuery, state: AppState) -> Result<impl Reply, Rejection> {
+    let to = query.to.unwrap_or_else(chrono::Utc::now);
+    let from = query.from.unwrap_or(to - chrono::Duration::days(DEFAULT_USAGE_DAYS));
+    if from >= to {
+        return Err(ApiError::InvalidRequest("from must be before to".to_string()).into());
+    }
+    if (to - from).num_seconds() / query.group_by.seconds() >= MAX_USAGE_BUCKETS {
+        return Err(ApiError::InvalidRequest(format!(
+            "The range spans more than {} buckets; narrow it or group by day",
+            MAX_USAGE_BUCKETS
+        ))
+        .into());
+    }
+
+    let prices = TokenPrices::from_config(&state.config);
+    match state.usage.report(from, to, query.group_by, prices).await {
+        Ok(report) => Ok(warp::reply::json(&report)),
+        Err(e) => {
+            log::error!("Failed to build the usage report: {:#}", e);
+            Err(ApiError::Internal("Failed to build the usage report").into())
+        }
+    }
+}
+
+/// Switch maintenance mode on or off. While it is on, chat requests get 503
+/// `MAINTENANCE` with its message; admin and document endpoints keep working.
+#[utoipa::path(
+    post,
+    path = "/api/admin/maintenance",
+    tag = "admin",
+    request_body = MaintenanceRequest,
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "The maintenance state now in effect", body = Object),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_maintenance(
+    req: MaintenanceRequest,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    let status = state.maintenance.set(req.enabled, req.message);
+    if status.enabled {
+        log::warn!("Maintenance mode switched on: {}", status.message);
+    } else {
+        log::info!("Maintenance mode switched off");
+    }
+    Ok(warp::reply::json(&status))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ai::mock::MockProvider;
+    use crate::config::Config;
+    use crate::server::create_routes;
+    use crate::server::test_support::{
+        chat_from, chunk, post_chat, seed, tenant_state, test_state, QUERY,
+    };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_usage_report_aggregates_chat_requests() {
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state.clone());
+
+        post_chat(state.clone(), serde_json::json!({ "query": QUERY })).await;
+        let refused = warp::test::request()
+            .method("POST")
+            .path("/api/chat")
+            .json(&serde_json::json!({ "query": "What is the capital of France?" }))
+            .reply(&routes)
+            .await;
+        assert_eq!(refused.status(), 422);
+        state.usage.flush().await.unwrap();
+
+        let usage = |query: &str| {
+            warp::test::request()
+                .path(&format!("/api/admin/usage{}", query))
+                .header("x-admin-key", "secret")
+                .reply(&routes)
+        };
+        let resp = usage("?group_by=day").await;
+        assert_eq!(resp.status(), 200);
+        let report: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        let totals = &report["totals"];
+        assert_eq!(totals["requests"], 2);
+        // The refused request never got a session
+        assert_eq!(totals["unique_sessions"], 1);
+        assert_eq!(totals["error_rate"], 0.5);
+        assert!(totals["tokens_in"].as_u64().unwrap() > 0);
+        assert!(totals["estimated_cost_usd"].as_f64().unwrap() > 0.0);
+        // Seven days back from now, in whole UTC days
+        assert_eq!(report["buckets"].as_array().unwrap().len(), 8);
+        let last = &report["buckets"][7];
+        assert_eq!(last["requests"], 2);
+
+        let inverted = "?from=2026-10-10T00:00:00Z&to=2026-10-01T00:00:00Z";
+        assert_eq!(usage(inverted).await.status(), 400);
+        let too_many_hours = "?from=2026-01-01T00:00:00Z&to=2026-10-01T00:00:00Z&group_by=hour";
+        assert_eq!(usage(too_many_hours).await.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_mode_turns_chat_away_until_switched_off() {
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let provider = Arc::new(MockProvider::new("ok"));
+        let (state, _dir) = test_state(config, provider.clone()).await;
+        let routes = create_routes(state.clone());
+        let send = |method: &str, path: &str, body: serde_json::Value| {
+            warp::test::request()
+                .method(method)
+                .path(path)
+                .header("x-admin-key", "secret")
+                .json(&body)
+                .reply(&routes)
+        };
+        let json = |resp: &warp::http::Response<warp::hyper::body::Bytes>| {
+            serde_json::from_slice::<serde_json::Value>(resp.body()).unwrap()
+        };
+
+        let on = serde_json::json!({ "enabled": true, "message": "Back at 14:00" });
+        let resp = send("POST", "/api/admin/maintenance", on).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(json(&resp), serde_json::json!({ "enabled": true, "message": "Back at 14:00" }));
+
+        let chat = serde_json::json!({ "query": QUERY });
+        for path in ["/api/chat", "/api/chat/stream"] {
+            let resp = send("POST", path, chat.clone()).await;
+            assert_eq!(resp.status(), 503, "{}", path);
+            let body = json(&resp);
+            assert_eq!(body["code"], "MAINTENANCE");
+            assert_eq!(body["error"], "Back at 14:00");
+        }
+        // Nothing was counted or sent to OpenAI
+        assert_eq!(provider.chat_call_count(), 0);
+        let ip = std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+        let limits = state.rate_limiter.get_status(ip).await;
+        assert_eq!(limits.remaining_minute, state.config.max_requests_per_minute);
+
+        let health = json(&send("GET", "/api/health", serde_json::Value::Null).await);
+        assert_eq!(health["status"], "maintenance");
+        let status = json(&send("GET", "/api/status", serde_json::Value::Null).await);
+        assert_eq!(status["maintenance"]["enabled"], true);
+        // Management endpoints keep working
+        assert_eq!(send("GET", "/api/admin/stats", serde_json::Value::Null).await.status(), 200);
+
+        let off = serde_json::json!({ "enabled": false });
+        assert_eq!(send("POST", "/api/admin/maintenance", off).await.status(), 200);
+        assert_eq!(send("POST", "/api/chat", chat).await.status(), 200);
+        let health = json(&send("GET", "/api/health", serde_json::Value::Null).await);
+        assert_eq!(health["status"], "healthy");
+    }
+
+    #[tokio::test]
+    async fn test_admin_block_endpoint_requires_key() {
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state.clone());
+
+        let denied = warp::test::request()
+            .method("POST")
+            .path("/api/admin/block")
+            .header("x-admin-key", "wrong")
+            .json(&serde_json::json!({ "ip": "192.0.2.0/24" }))
+            .reply(&routes)
+            .await;
+        assert_eq!(denied.status(), 401);
+
+        let blocked = warp::test::request()
+            .method("POST")
+            .path("/api/admin/block")
+            .header("x-admin-key", "secret")
+            .json(&serde_json::json!({ "ip": "192.0.2.0/24" }))
+            .reply(&routes)
+            .await;
+        assert_eq!(blocked.status(), 200);
+        assert_eq!(chat_from(state.clone(), "192.0.2.9").await, 403);
+
+        let unblocked = warp::test::request()
+            .method("DELETE")
+            .path("/api/admin/block")
+            .header("x-admin-key", "secret")
+            .json(&serde_json::json!({ "ip": "192.0.2.0/24" }))
+            .reply(&routes)
+            .await;
+        assert_eq!(unblocked.status(), 200);
+        assert_eq!(chat_from(state, "192.0.2.9").await, 200);
+    }
+
+    #[tokio::test]
+    async fn test_admin_snapshot_and_restore() {
+        let snapshots = tempfile::TempDir::new().unwrap();
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            snapshot_dir: snapshots.path().to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        seed(&state, vec![chunk("Honda CBR600RR", 12, "Adjust the drive chain tension")]).await;
+        let routes = create_routes(state.clone());
+        let send = |method: &str, path: &str, body: serde_json::Value| {
+            warp::test::request()
+                .method(method)
+                .path(path)
+                .header("x-admin-key", "secret")
+                .json(&body)
+                .reply(&routes)
+        };
+
+        let resp = send("POST", "/api/admin/snapshots", serde_json::Value::Null).await;
+        assert_eq!(resp.status(), 201);
+        let created: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(created["chunks"], 1);
+        assert!(created["size_bytes"].as_u64().unwrap() > 0);
+        assert!(created["duration_ms"].is_u64());
+
+        let resp = send("GET", "/api/admin/snapshots", serde_json::Value::Null).await;
+        let listed: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(listed["snapshots"][0]["name"], created["name"]);
+
+        seed(&state, vec![chunk("Honda CBR600RR", 13, "Bleed the front brakes")]).await;
+        assert_eq!(state.retriever.vector_store().count().await, 2);
+        let restore = serde_json::json!({ "snapshot": created["name"] });
+        let resp = send("POST", "/api/admin/snapshots/restore", restore).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(state.retriever.vector_store().count().await, 1);
+
+        let missing = serde_json::json!({ "snapshot": "bike_manuals-1.snapshot.json" });
+        let resp = send("POST", "/api/admin/snapshots/restore", missing).await;
+        assert_eq!(resp.status(), 404);
+        let outside = serde_json::json!({ "snapshot": "../bike_manuals.json" });
+        let resp = send("POST", "/api/admin/snapshots/restore", outside).await;
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_admin_stats_per_tenant() {
+        let (state, _dir) = tenant_state().await;
+        let routes = create_routes(state);
+
+        let resp = warp::test::request()
+            .path("/api/admin/stats")
+            .header("x-admin-key", "secret")
+            .reply(&routes)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+
+        assert_eq!(body["total_chunks"], 2);
+        assert_eq!(body["chunks_per_tenant"]["dealer-a"], 1);
+        assert_eq!(body["chunks_per_tenant"]["dealer-b"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_index_stats_follow_upserts() {
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state.clone());
+        let stats = || async {
+            let resp = warp::test::request()
+                .path("/api/admin/index-stats")
+                .header("x-admin-key", "secret")
+                .reply(&routes)
+                .await;
+            assert_eq!(resp.status(), 200);
+            serde_json::from_slice::<serde_json::Value>(resp.body()).unwrap()
+        };
+
+        let empty = stats().await;
+        assert_eq!((empty["points"].as_u64(), empty["disk_bytes"].as_u64()), (Some(0), Some(0)));
+        assert_eq!(empty["bike_models"], serde_json::json!([]));
+
+        seed(&state, vec![chunk("KTM 390", 3, "Adjust the drive chain slack")]).await;
+        let mut other = chunk("Yamaha R1", 5, "Bleed the front brake");
+        other.document_id = "doc-2".to_string();
+        // Spellings of one model are counted together
+        seed(&state, vec![chunk("ktm-390", 4, "Lubricate the chain"), other]).await;
+
+        let body = stats().await;
+        assert_eq!(body["points"], 3);
+        assert!(body["disk_bytes"].as_u64().unwrap() > 0);
+        assert_eq!(
+            body["bike_models"],
+            serde_json::json!([
+                { "bike_model": "390", "documents": 1, "chunks": 2 },
+                { "bike_model": "r1", "documents": 1, "chunks": 1 },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_ip_locked_out_after_invalid_keys() {
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            admin_max_failures_per_minute: 2,
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state);
+        let request = |ip: &str, key: &str, path: &str| {
+            warp::test::request()
+                .path(path)
+                .remote_addr(format!("{}:4000", ip).parse().unwrap())
+                .header("x-admin-key", key)
+                .reply(&routes)
+        };
+
+        for path in ["/api/admin/stats", "/api/documents/doc-1"] {
+            let resp = request("192.0.2.1", "guess", path).await;
+            assert_eq!(resp.status(), 401);
+            let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+            assert_eq!(body["code"], "UNAUTHORIZED");
+        }
+
+        // Locked out even with the right key, while other IPs are unaffected
+        let resp = request("192.0.2.1", "secret", "/api/admin/stats").await;
+        assert_eq!(resp.status(), 429);
+        assert!(resp.headers().contains_key("Retry-After"));
+        let resp = request("192.0.2.2", "secret", "/api/admin/stats").await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes_hidden_without_admin_key() {
+        let (state, _dir) = test_state(Config::default(), Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state);
+
+        for path in ["/api/admin/stats", "/api/admin/sessions", "/api/documents/doc-1"] {
+            let resp = warp::test::request()
+                .path(path)
+                .header("x-admin-key", "anything")
+                .reply(&routes)
+                .await;
+            assert_eq!(resp.status(), 404, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reload_applies_new_rate_limits() {
+        let _env = crate::config::ENV_LOCK.lock().await;
+        let config = Config {
+            openai_api_key: "sk-test".to_string(),
+            admin_api_key: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state.clone());
+
+        std::env::set_var("OPENAI_API_KEY", "sk-test");
+        std::env::set_var("MAX_REQUESTS_PER_MINUTE", "1");
+        std::env::set_var("SERVER_PORT", "9999");
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/admin/reload")
+            .header("x-admin-key", "secret")
+            .reply(&routes)
+            .await;
+        std::env::remove_var("OPENAI_API_KEY");
+        std::env::remove_var("MAX_REQUESTS_PER_MINUTE");
+        std::env::remove_var("SERVER_PORT");
+
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["reloaded"]["max_requests_per_minute"], 1);
+        assert_eq!(body["requires_restart"], serde_json::json!(["SERVER_PORT"]));
+
+        assert_eq!(chat_from(state.clone(), "192.0.2.1").await, 200);
+        assert_eq!(chat_from(state, "192.0.2.1").await, 429);
+    }
+
+    #[tokio::test]
+    async fn test_reload_rejects_invalid_values() {
+        let _env = crate::config::ENV_LOCK.lock().await;
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state.clone());
+        let reload = |vars: &[(&str, &str)]| {
+            std::env::set_var("OPENAI_API_KEY", "sk-test");
+            for (key, value) in vars {
+                std::env::set_var(key, value);
+            }
+            let request = warp::test::request()
+                .method("POST")
+                .path("/api/admin/reload")
+                .header("x-admin-key", "secret");
+            let routes = routes.clone();
+            let keys: Vec<String> = vars.iter().map(|(key, _)| key.to_string()).collect();
+            async move {
+                let resp = request.reply(&routes).await;
+                std::env::remove_var("OPENAI_API_KEY");
+                for key in keys {
+                    std::env::remove_var(key);
+                }
+                resp
+            }
+        };
+
+        // Anything startup refuses is refused here too
+        for vars in [
+            &[("MAX_REQUESTS_PER_HOUR", "lots")][..],
+            &[("MAX_REQUESTS_PER_MINUTE", "200"), ("MAX_REQUESTS_PER_HOUR", "100")],
+            &[("RETRIEVAL_TOP_K", "0")],
+            &[("MIN_CONFIDENCE", "1.5")],
+        ] {
+            let resp = reload(vars).await;
+            assert_eq!(resp.status(), 400, "{:?}", vars);
+            let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+            assert!(body["error"].as_str().unwrap().contains(vars[0].0), "{}", body);
+        }
+        assert_eq!(state.rate_limiter.limits(), (20, 100));
+    }
+}
@@ -0,0 +1,2407 @@
+//! The chat endpoints (JSON, SSE stream and WebSocket) and the pipeline
+//! they share: validation, retrieval, the model call and session bookkeeping
+
+use futures::{SinkExt, StreamExt};
+use warp::{reject::Rejection, reply::Reply};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+use crate::models::{
+    same_model, ChatRequest, ChatResponse, ChatSocketQuery, ErrorResponse, FieldError, Message,
+    RateLimitInfo, Role, REGENERATE_TEMPERATURE_BOOST,
+};
+use crate::server::errors::{ApiError, RateLimitedError};
+use crate::server::routes::AppState;
+use crate::ai::{
+    add_bike_note, add_manual_type_instructions, add_persona_instructions, answer_confidence,
+    add_structured_output_instructions, add_suggestion_instructions,
+    add_verbosity_instructions,
+    build_chat_prompt_with_system, parse_structured_answer, split_suggestions, CompletionOptions,
+    CompletionStream, TEMPERATURE_RANGE, VERIFY_WITH_MECHANIC_WARNING,
+};
+use crate::config::Config;
+use crate::pdf::ModelDetector;
+use crate::rag::{
+    build_context, count_tokens, dominant_manual_type, source_ids, to_context, to_sources,
+    RetrievalTrace, SearchResult,
+};
+use crate::security::{normalize_query, CircuitBreaker, QueryValidation};
+use crate::sessions::{BikeContext, Session};
+use crate::usage::{UsageRecord, CANCELLED};
+
+use super::{
+    authenticate_tenant, client_ip, elapsed_ms, is_admin, with_rate_limit_headers,
+    TenantCredentials,
+};
+
+/// Parse and check a chat request body, failing with 400 `INVALID_FIELD`
+/// naming the offending field
+fn parse_chat_request(body: &[u8], state: &AppState) -> Result<ChatRequest, ApiError> {
+    ChatRequest::from_json(body)
+        .and_then(|req| {
+            req.validate_fields(|model| state.config.is_known_bike_model(model))?;
+            // Servers that may not quote manuals do not hand out their text either
+            if req.include_context && !state.config.source_excerpts_enabled {
+                let reason = "quoting manual text is disabled on this server";
+                return Err(FieldError::new("include_context", reason));
+            }
+            Ok(req)
+        })
+        .map_err(ApiError::InvalidField)
+}
+
+/// Chat handler; the route adds the total handling time in `X-Response-Time-Ms`
+#[utoipa::path(
+    post,
+    path = "/api/chat",
+    tag = "chat",
+    request_body = ChatRequest,
+    security((), ("chat_api_key" = []), ("tenant_key" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Answer with its sources",
+            body = ChatResponse,
+            headers(
+                ("X-RateLimit-Remaining-Minute" = u32, description = "Requests left this minute"),
+                ("X-RateLimit-Remaining-Hour" = u32, description = "Requests left this hour"),
+                ("X-RateLimit-Reset" = u64, description = "Seconds until the limit resets"),
+                ("X-Response-Time-Ms" = u64, description = "Time spent handling the request"),
+            )
+        ),
+        (
+            status = 400,
+            description = "A field is missing, malformed or out of range (`INVALID_FIELD`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 401,
+            description = "Missing or wrong `X-Api-Key`, or an unknown tenant key",
+            body = ErrorResponse
+        ),
+        (status = 403, description = "The caller's IP is blocked", body = ErrorResponse),
+        (
+            status = 413,
+            description = "Body larger than `MAX_CHAT_BODY_BYTES`",
+            body = ErrorResponse
+        ),
+        (
+            status = 422,
+            description = "The query is not about bike repair (`INVALID_QUERY`) or too \
+                short (`QUERY_TOO_SHORT`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Rate limit exceeded, or the same query repeated too often",
+            body = ErrorResponse,
+            headers(
+                ("X-RateLimit-Remaining-Minute" = u32, description = "Requests left this minute"),
+                ("X-RateLimit-Remaining-Hour" = u32, description = "Requests left this hour"),
+                ("X-RateLimit-Reset" = u64, description = "Seconds until the limit resets"),
+                ("Retry-After" = u64, description = "Seconds to wait before retrying"),
+            )
+        ),
+        (
+            status = 503,
+            description = "Maintenance mode is on, or OpenAI is unavailable and the circuit \
+                breaker is open",
+            body = ErrorResponse
+        ),
+        (
+            status = 504,
+            description = "The answer took longer than `REQUEST_TIMEOUT_SECONDS`",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_chat(
+    body: warp::hyper::body::Bytes,
+    state: AppState,
+    remote_addr: Option<SocketAddr>,
+    admin_key: Option<String>,
+    credentials: TenantCredentials,
+) -> Result<impl Reply, Rejection> {
+    let started = Instant::now();
+    let req = match parse_chat_request(&body, &state) {
+        Ok(req) => req,
+        Err(e) => {
+            let result = Err(e.into());
+            let error_code = result.as_ref().err().map(rejection_code);
+            record_usage(&state, &ChatBudget::unlimited(), remote_addr, started, error_code);
+            return result;
+        }
+    };
+
+    let limit = state.config.request_timeout_seconds;
+    let budget = ChatBudget::until(started + Duration::from_secs(limit));
+    budget.usage.lock().unwrap().session_id = req.session_id.clone();
+    let span = chat_span(&req, remote_addr);
+    let pipeline = chat_pipeline(req, state.clone(), remote_addr, admin_key, credentials, &budget);
+    let result = async {
+        match tokio::time::timeout(Duration::from_secs(limit), pipeline).await {
+            Ok(result) => result,
+            Err(_) => {
+                log::warn!("Chat request timed out after {}ms", elapsed_ms(started));
+                // The request went past the breaker, so its outcome still counts;
+                // the rate limit slot it took stays taken
+                if budget.breaker_pending.swap(false, Ordering::SeqCst) {
+                    state.circuit_breaker.record_failure().await;
+                }
+                let error = ApiError::RequestTimeout(limit);
+                Err(match budget.rate_limit_info.lock().unwrap().take() {
+                    Some(info) => error.with_rate_limit(&info),
+                    None => error.into(),
+                })
+            }
+        }
+    }
+    .instrument(span)
+    .await;
+
+    record_usage(&state, &budget, remote_addr, started, result.as_ref().err().map(rejection_code));
+    result
+}
+
+/// Queue the usage record of a finished chat request; `error_code` is the
+/// code of its error response
+fn record_usage(
+    state: &AppState,
+    budget: &ChatBudget,
+    remote_addr: Option<SocketAddr>,
+    started: Instant,
+    error_code: Option<&str>,
+) {
+    let ip = client_ip(remote_addr);
+    let usage = std::mem::take(&mut *budget.usage.lock().unwrap());
+    state.usage.record(UsageRecord {
+        created_at: chrono::Utc::now(),
+        tenant_id: usage.tenant_id.unwrap_or_else(|| crate::models::DEFAULT_TENANT.to_string()),
+        ip_hash: state.usage.hash_ip(ip),
+        session_id: usage.session_id,
+        model: state.config.openai_chat_model.clone(),
+        tokens_in: usage.tokens_in as u64,
+        tokens_out: usage.tokens_out as u64,
+        latency_ms: elapsed_ms(started) as u64,
+        retrieval_hit: usage.retrieval_hit,
+        error_code: error_code.map(str::to_string),
+    });
+}
+
+/// Code of the error response a rejection becomes
+fn rejection_code(rejection: &Rejection) -> &'static str {
+    rejection
+        .find::<ApiError>()
+        .or_else(|| rejection.find::<RateLimitedError>().map(|e| &e.error))
+        .map_or("INTERNAL_ERROR", ApiError::code)
+}
+
+/// Usage record of a streamed chat answer, queued when this is dropped: once
+/// the answer finished or failed, or as `CANCELLED` when the client went away
+/// first
+struct PendingUsage {
+    state: AppState,
+    remote_addr: Option<SocketAddr>,
+    started: Instant,
+    budget: ChatBudget,
+    /// The answer so far, counted as completion tokens
+    answer: String,
+    /// Code of the error response (`None` for an answer); unset while the
+    /// answer is streaming
+    outcome: Option<Option<&'static str>>,
+}
+
+impl PendingUsage {
+    fn new(state: &AppState, remote_addr: Option<SocketAddr>) -> Self {
+        Self {
+            state: state.clone(),
+            remote_addr,
+            started: Instant::now(),
+            budget: ChatBudget::unlimited(),
+            answer: String::new(),
+            outcome: None,
+        }
+    }
+
+    /// Record the request as refused with `rejection`, handing it back
+    fn fail(mut self, rejection: Rejection) -> Rejection {
+        self.outcome = Some(Some(rejection_code(&rejection)));
+        rejection
+    }
+
+    /// Record the request as answered
+    fn answered(mut self) {
+        self.outcome = Some(None);
+    }
+}
+
+impl Drop for PendingUsage {
+    fn drop(&mut self) {
+        {
+            let mut usage = self.budget.usage.lock().unwrap();
+            // A steer has no prompt tokens and is not a completion either
+            if usage.tokens_in > 0 {
+                usage.tokens_out = count_tokens(&self.state.config.openai_chat_model, &self.answer);
+            }
+        }
+        let error_code = self.outcome.unwrap_or(Some(CANCELLED));
+        record_usage(&self.state, &self.budget, self.remote_addr, self.started, error_code);
+    }
+}
+
+/// Span around a chat request's pipeline. A new session's id is recorded once
+/// the answer is ready.
+fn chat_span(req: &ChatRequest, remote_addr: Option<SocketAddr>) -> tracing::Span {
+    tracing::info_span!(
+        "chat",
+        request_id = crate::request_context::request_id().as_deref(),
+        ip = remote_addr.map(|addr| addr.ip().to_string()),
+        session_id = req.session_id.as_deref(),
+    )
+}
+
+/// Time left to a chat request, and what it had done when its deadline passes
+struct ChatBudget {
+    deadline: Option<Instant>,
+    /// Limit left after the request took its rate limit slot
+    rate_limit_info: Mutex<Option<RateLimitInfo>>,
+    /// Passed the circuit breaker without reporting the completion's outcome
+    breaker_pending: AtomicBool,
+    /// What the request used so far, for its usage record
+    usage: Mutex<ChatUsage>,
+}
+
+/// Tenant, session, tokens and retrieval outcome of a chat request, noted as
+/// the pipeline learns them
+#[derive(Default)]
+struct ChatUsage {
+    tenant_id: Option<String>,
+    session_id: Option<String>,
+    tokens_in: usize,
+    tokens_out: usize,
+    retrieval_hit: Option<bool>,
+}
+
+impl ChatBudget {
+    fn until(deadline: Instant) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..Self::unlimited()
+        }
+    }
+
+    fn unlimited() -> Self {
+        Self {
+            deadline: None,
+            rate_limit_info: Mutex::new(None),
+            breaker_pending: AtomicBool::new(false),
+            usage: Mutex::new(ChatUsage::default()),
+        }
+    }
+
+    fn remaining(&self) -> Duration {
+        self.deadline
+            .map_or(Duration::MAX, |deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// A chat request that passed every check, with its prompt ready for the model
+struct PreparedChat {
+    ip: std::net::IpAddr,
+    /// Reply for a borderline query, sent instead of asking the model
+    steer: Option<String>,
+    messages: Vec<Message>,
+    /// Tokens of `messages`, as the model counts them
+    prompt_tokens: usize,
+    options: CompletionOptions,
+    retrieved: Vec<SearchResult>,
+    trace: Option<RetrievalTrace>,
+    rate_limit_info: RateLimitInfo,
+    /// Motorcycle named by this request, remembered for the rest of the session
+    bike: Option<BikeContext>,
+    /// Answer a `regenerate` request replaces in the session
+    replaces: Option<Message>,
+}
+
+async fn chat_pipeline(
+    mut req: ChatRequest,
+    state: AppState,
+    remote_addr: Option<SocketAddr>,
+    admin_key: Option<String>,
+    credentials: TenantCredentials,
+    budget: &ChatBudget,
+) -> Result<warp::reply::Response, Rejection> {
+    let started = Instant::now();
+    let chat = prepare_chat(&mut req, &state, remote_addr, &credentials, budget).await?;
+    let ip = chat.ip;
+
+    // 6. Call OpenAI API (borderline queries get their steer instead); the
+    // first choice is the answer, any others are variants
+    let mut choices = match chat.steer {
+        Some(steer) => vec![steer],
+        None => {
+            let model = state.config.openai_chat_model.as_str();
+            let prompt_tokens = chat.prompt_tokens;
+            let completion_started = Instant::now();
+            let completion = if chat.options.choices.is_some() {
+                state.ai_provider.complete_choices(chat.messages, chat.options).await
+            } else {
+                let completion = state.ai_provider.complete(chat.messages, chat.options).await;
+                completion.map(|text| vec![text])
+            };
+            tracing::info!(
+                stage = "completion",
+                duration_ms = elapsed_ms(completion_started) as u64,
+                ok = completion.is_ok(),
+                "Completion finished"
+            );
+            state.metrics.record_completion(completion_started.elapsed());
+            budget.breaker_pending.store(false, Ordering::SeqCst);
+            match completion {
+                Ok(choices) => {
+                    let completion_tokens = choices.iter().map(|c| count_tokens(model, c)).sum();
+                    state.metrics.record_tokens(prompt_tokens, completion_tokens);
+                    {
+                        let mut usage = budget.usage.lock().unwrap();
+                        (usage.tokens_in, usage.tokens_out) = (prompt_tokens, completion_tokens);
+                    }
+                    state.circuit_breaker.record_success().await;
+                    choices
+                }
+                Err(e) => {
+                    log::error!("OpenAI API error: {}", e);
+                    state.circuit_breaker.record_failure().await;
+                    return Err(ApiError::AiError.with_rate_limit(&chat.rate_limit_info));
+                }
+            }
+        }
+    };
+
+    // 7. Build response: follow-up questions are taken out of every choice and
+    // the first one's kept; structured answers fall back to plain text if unparseable
+    let mut suggestions = Vec::new();
+    if wants_suggestions(&req, &state) {
+        for (i, choice) in choices.iter_mut().enumerate() {
+            let (text, found) = split_suggestions(choice);
+            *choice = text;
+            if i == 0 {
+                suggestions = found;
+            }
+        }
+    }
+    let footer = state.config.response_footer.as_deref();
+    let mut answers: Vec<_> = choices
+        .into_iter()
+        .map(|text| match req.structured.then(|| parse_structured_answer(&text)).flatten() {
+            Some(answer) => (answer.answer.clone(), Some(answer)),
+            None => (text, None),
+        })
+        .collect();
+    let variants = if answers.len() > 1 {
+        answers.iter().map(|(text, _)| with_footer(text.clone(), footer)).collect()
+    } else {
+        Vec::new()
+    };
+    let (response_text, mut structured) = answers.swap_remove(0);
+
+    // A structured answer is as trustworthy as the sources it cites
+    let sources = to_sources(&chat.retrieved, source_excerpt_chars(&state.config));
+    let answer_confidence = structured.as_mut().map(|answer| {
+        let confidence = answer_confidence(answer, &sources);
+        if confidence < state.config.low_confidence_threshold {
+            answer.safety_warnings.push(VERIFY_WITH_MECHANIC_WARNING.to_string());
+        }
+        confidence
+    });
+
+    let session_id = req.session_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    tracing::Span::current().record("session_id", session_id.as_str());
+    budget.usage.lock().unwrap().session_id = Some(session_id.clone());
+    let response_id = uuid::Uuid::new_v4().to_string();
+    let reply = Message::assistant(response_text.clone())
+        .with_response(response_id.clone(), source_ids(&chat.retrieved));
+    let (query, bike) = (req.query.as_str(), chat.bike);
+    remember_exchange(&state, &req.tenant_id, &session_id, query, reply, bike, chat.replaces).await;
+    let tokens = {
+        let usage = budget.usage.lock().unwrap();
+        usage.tokens_in + usage.tokens_out
+    };
+    charge_session(&state, &req.tenant_id, &session_id, tokens).await;
+
+    let response = ChatResponse {
+        response: with_footer(response_text, footer),
+        session_id,
+        response_id,
+        sources,
+        structured,
+        answer_confidence,
+        variants,
+        suggestions,
+        context: req.include_context.then(|| to_context(&chat.retrieved)),
+        debug: if req.debug && is_admin(&state, admin_key.as_deref()) {
+            chat.trace
+        } else {
+            None
+        },
+        rate_limit_info: chat.rate_limit_info,
+    };
+
+    log::info!("Chat response sent to {} in {}ms", ip, elapsed_ms(started));
+
+    Ok(with_rate_limit_headers(
+        warp::reply::json(&response),
+        &response.rate_limit_info,
+    ))
+}
+
+/// Steps shared by the chat endpoints up to the model call: access checks,
+/// validation, retrieval and prompt building. Sets the request's tenant from
+/// its API key. Refusals after the rate limit check carry the rate limit
+/// headers. Progress is noted in `budget`, whose remaining time bounds the
+/// retrieval stages.
+async fn prepare_chat(
+    req: &mut ChatRequest,
+    state: &AppState,
+    remote_addr: Option<SocketAddr>,
+    credentials: &TenantCredentials,
+    budget: &ChatBudget,
+) -> Result<PreparedChat, Rejection> {
+    let ip = client_ip(remote_addr);
+
+    // 0. Turn everyone away during maintenance, before anything is counted
+    if state.maintenance.is_enabled() {
+        return Err(ApiError::Maintenance(state.maintenance.message()).into());
+    }
+
+    // 0a. New requests from blocked IPs are stopped by the routes' `not_blocked`
+    // filter; this catches an IP blocked while its WebSocket is open
+    if state.block_list.is_blocked(ip) {
+        log::warn!("Rejected chat request from blocked IP {}", ip);
+        return Err(ApiError::IpBlocked.into());
+    }
+
+    // 0b. Scope the request to the tenant of its API key
+    req.tenant_id = authenticate_tenant(state, credentials)?;
+    budget.usage.lock().unwrap().tenant_id = Some(req.tenant_id.clone());
+
+    // 0c. A regeneration asks the session's last question again
+    let (regenerated_session, replaces) = match req.regenerate {
+        true => Some(load_regeneration(state, req).await?).unzip(),
+        false => (None, None),
+    };
+
+    req.query = normalize_query(&req.query);
+    log::info!("Chat request from {} ({}): {}", ip, req.tenant_id, req.query);
+
+    // 1. Check rate limit
+    let rate_limit_info = match state.rate_limiter.check_and_record(ip).await {
+        Ok(info) => info,
+        Err(e) => {
+            log::warn!("Rate limit exceeded for {}: {}", ip, e);
+            let info = state.rate_limiter.get_status(ip).await;
+            let error = ApiError::RateLimitExceeded {
+                message: e.to_string(),
+                retry_after: Some(info.reset_in_seconds.max(1)),
+                rate_limit_info: Some(info.clone()),
+            };
+            return Err(error.with_rate_limit(&info));
+        }
+    };
+    *budget.rate_limit_info.lock().unwrap() = Some(rate_limit_info.clone());
+
+    // 1b. Throttle clients repeating the same query; a regeneration repeats
+    // its query on purpose
+    let repeated = match req.regenerate {
+        true => Ok(()),
+        false => state.repeat_guard.check(ip, &req.query),
+    };
+    if let Err(e) = repeated {
+        log::warn!("Repeated query from {}: {}", ip, e);
+        return Err(ApiError::RepeatedQuery(e.to_string()).with_rate_limit(&rate_limit_info));
+    }
+
+    // 1c. The motorcycle this request names, if any: the bike_model field, or
+    // a model confidently recognized in the query
+    let detection = ModelDetector::new(state.config.model_detection_min_confidence)
+        .detect_in_query(&req.query);
+    let stated_bike = match &req.bike_model {
+        Some(model) => Some(BikeContext {
+            model: model.clone(),
+            year: detection
+                .year
+                .filter(|_| {
+                    detection
+                        .bike_model
+                        .as_deref()
+                        .is_some_and(|detected| same_model(detected, model))
+                }),
+        }),
+        None => detection.bike_model.map(|model| BikeContext {
+            model,
+            year: detection.year,
+        }),
+    };
+
+    // 2. Validate query (bike-related and safe); borderline queries are steered
+    let validation_started = Instant::now();
+    let validation = state.query_validator.validate(&req.query);
+    tracing::info!(
+        stage = "validation",
+        duration_ms = elapsed_ms(validation_started) as u64,
+        outcome = match &validation {
+            QueryValidation::Ok => "ok",
+            QueryValidation::SoftReject(_) => "steered",
+            QueryValidation::HardReject(_) | QueryValidation::TooShort(_) => "rejected",
+        },
+        "Query validated"
+    );
+    match validation {
+        QueryValidation::Ok => {}
+        QueryValidation::SoftReject(steer) => {
+            log::info!("Steering borderline query from {}", ip);
+            return Ok(PreparedChat {
+                ip,
+                steer: Some(steer),
+                messages: Vec::new(),
+                prompt_tokens: 0,
+                options: CompletionOptions::default(),
+                retrieved: Vec::new(),
+                trace: None,
+                rate_limit_info,
+                bike: stated_bike,
+                replaces,
+            });
+        }
+        QueryValidation::HardReject(reason) => {
+            log::warn!("Invalid query from {}: {}", ip, reason);
+            return Err(ApiError::InvalidQuery(reason).with_rate_limit(&rate_limit_info));
+        }
+        QueryValidation::TooShort(reason) => {
+            log::info!("Query too short from {}", ip);
+            return Err(ApiError::QueryTooShort(reason).with_rate_limit(&rate_limit_info));
+        }
+    }
+
+    // 2b. Load the session's history, refusing sessions that spent their budget
+    let session = match (regenerated_session, &req.session_id) {
+        (Some(session), _) => Some(session),
+        (None, Some(id)) => state.sessions.get(&req.tenant_id, id).await.unwrap_or_else(|e| {
+            log::warn!("Failed to load session {}, answering without history: {:#}", id, e);
+            None
+        }),
+        (None, None) => None,
+    };
+    if let Some(session) = &session {
+        check_session_budget(state, session).map_err(|e| e.with_rate_limit(&rate_limit_info))?;
+    }
+
+    // 3. Check circuit breaker
+    if let Err(e) = state.circuit_breaker.check_request().await {
+        log::error!("Circuit breaker open: {}", e);
+        let error = ApiError::ServiceUnavailable(e.to_string());
+        return Err(error.with_rate_limit(&rate_limit_info));
+    }
+    budget.breaker_pending.store(true, Ordering::SeqCst);
+
+    // A differently spelled name for the session's bike keeps the session's
+    // spelling, and its year unless the request names another
+    let remembered_bike = session.as_ref().and_then(|s| s.bike.clone());
+    let stated_bike = stated_bike.map(|stated| match &remembered_bike {
+        Some(remembered) if same_model(&remembered.model, &stated.model) => BikeContext {
+            model: remembered.model.clone(),
+            year: stated.year.or(remembered.year),
+        },
+        _ => stated,
+    });
+
+    // 4. Retrieve manual context (falls back to the session's bike, then the
+    // configured default model)
+    let bike = stated_bike.clone().or(remembered_bike);
+    let bike_model = bike
+        .as_ref()
+        .map(|bike| bike.model.clone())
+        .or_else(|| state.config.default_bike_model.clone());
+
+    let retrieval_started = Instant::now();
+    let (retrieved, trace) = match state
+        .retriever
+        .retrieve_within(&req.query, &req.tenant_id, bike_model.as_deref(), budget.remaining())
+        .await
+    {
+        Ok((results, trace)) => {
+            state.metrics.record_retrieval(&trace);
+            (results, Some(trace))
+        }
+        Err(e) => {
+            log::warn!("Retrieval failed, answering without manual context: {}", e);
+            (Vec::new(), None)
+        }
+    };
+    state.metrics.retrieval.observe(&[], retrieval_started.elapsed());
+    budget.usage.lock().unwrap().retrieval_hit = Some(!retrieved.is_empty());
+    tracing::info!(
+        stage = "retrieval",
+        duration_ms = elapsed_ms(retrieval_started) as u64,
+        chunks = retrieved.len(),
+        "Retrieval finished"
+    );
+
+    // 5. Build prompt with the session's history, styled for the kind of manual
+    // the context mostly came from (answer length capped by the server ceiling)
+    let context = build_context(&retrieved);
+    let system_prompt = state.system_prompt.read().unwrap().clone();
+    let history = session.map(|s| s.messages).unwrap_or_default();
+    let mut messages =
+        build_chat_prompt_with_system(&system_prompt, &req.query, context.as_deref(), &history);
+    if let Some(manual_type) = dominant_manual_type(&retrieved) {
+        add_manual_type_instructions(&mut messages, &manual_type);
+    }
+    if let Some(bike) = &bike {
+        add_bike_note(&mut messages, &bike.to_string());
+    }
+    add_persona_instructions(
+        &mut messages,
+        state.config.assistant_name.as_deref(),
+        state.config.shop_name.as_deref(),
+    );
+    add_verbosity_instructions(&mut messages, req.verbosity);
+    if req.structured {
+        add_structured_output_instructions(&mut messages);
+    }
+    if wants_suggestions(req, state) {
+        add_suggestion_instructions(&mut messages, req.structured);
+    }
+
+    let max_tokens = req.verbosity.max_tokens().min(state.config.max_response_tokens);
+    let temperature = req.temperature.unwrap_or(match req.regenerate {
+        true => (state.config.openai_temperature + REGENERATE_TEMPERATURE_BOOST)
+            .min(*TEMPERATURE_RANGE.end()),
+        false => state.config.openai_temperature,
+    });
+    let model = state.config.openai_chat_model.as_str();
+    let prompt_tokens = messages.iter().map(|m| count_tokens(model, &m.content)).sum();
+    Ok(PreparedChat {
+        ip,
+        steer: None,
+        messages,
+        prompt_tokens,
+        options: CompletionOptions {
+            max_tokens: Some(max_tokens),
+            json_mode: req.structured,
+            temperature: Some(temperature),
+            top_p: Some(req.top_p.unwrap_or(state.config.openai_top_p)),
+            choices: req.variants.filter(|&n| n > 1),
+        },
+        retrieved,
+        trace,
+        rate_limit_info,
+        bike: stated_bike,
+        replaces,
+    })
+}
+
+/// Refuse a session whose tokens have reached `SESSION_TOKEN_BUDGET`
+fn check_session_budget(state: &AppState, session: &Session) -> Result<(), ApiError> {
+    let budget = state.config.session_token_budget;
+    let refill = state.config.session_token_refill_per_hour;
+    let used = session.tokens_used_at(chrono::Utc::now(), refill);
+    if budget == 0 || used < budget {
+        return Ok(());
+    }
+    let message = match refill {
+        0 => format!(
+            "This conversation has used its budget of {} tokens; start a new session",
+            budget
+        ),
+        _ => format!(
+            "This conversation has used its budget of {} tokens; it refills in about {} \
+             minutes, or start a new session",
+            budget,
+            ((used - budget + 1) * 60).div_ceil(refill).max(1)
+        ),
+    };
+    Err(ApiError::SessionBudgetExceeded(message))
+}
+
+/// Count an exchange's prompt and answer tokens against its session
+async fn charge_session(state: &AppState, tenant_id: &str, session_id: &str, tokens: usize) {
+    if tokens == 0 {
+        return;
+    }
+    let refill = state.config.session_token_refill_per_hour;
+    if let Err(e) = state.sessions.add_tokens(tenant_id, session_id, tokens as u64, refill).await
+    {
+        log::warn!("Failed to count the tokens of session {}: {:#}", session_id, e);
+    }
+}
+
+/// Whether the model is asked for follow-up questions after the answer
+fn wants_suggestions(req: &ChatRequest, state: &AppState) -> bool {
+    state.config.suggestions_enabled && req.include_suggestions.unwrap_or(true)
+}
+
+/// Length of the chunk excerpts quoted in `sources`, if they are enabled
+fn source_excerpt_chars(config: &Config) -> Option<usize> {
+    config
+        .source_excerpts_enabled
+        .then_some(config.source_excerpt_chars)
+}
+
+/// The session of a `regenerate` request without its last exchange, and the
+/// answer being replaced. The request's query becomes the question that
+/// answer replied to.
+async fn load_regeneration(
+    state: &AppState,
+    req: &mut ChatRequest,
+) -> Result<(Session, Message), Rejection> {
+    let Some(id) = req.session_id.clone() else {
+        let error = FieldError::new("session_id", "is required to regenerate");
+        return Err(ApiError::InvalidField(error).into());
+    };
+    let mut session = match state.sessions.get(&req.tenant_id, &id).await {
+        Ok(Some(session)) => session,
+        Ok(None) => return Err(ApiError::SessionNotFound(id).into()),
+        Err(e) => {
+            log::error!("Failed to load session {}: {:#}", id, e);
+            return Err(ApiError::Internal("Failed to load session").into());
+        }
+    };
+
+    let len = session.messages.len();
+    match &session.messages[len.saturating_sub(2)..] {
+        [question, answer] if question.role == Role::User && answer.role == Role::Assistant => {
+            req.query = question.content.clone();
+            let answer = answer.clone();
+            session.messages.truncate(len - 2);
+            Ok((session, answer))
+        }
+        _ => {
+            let message = "The session has no answer to regenerate".to_string();
+            Err(ApiError::InvalidRequest(message).into())
+        }
+    }
+}
+
+/// Add a query and its answer to the session's history, or put a regenerated
+/// answer in place of the one it `replaces`; then summarize the older turns in
+/// the background if the history grew too long
+async fn remember_exchange(
+    state: &AppState,
+    tenant_id: &str,
+    session_id: &str,
+    query: &str,
+    reply: Message,
+    bike: Option<BikeContext>,
+    replaces: Option<Message>,
+) {
+    let recorded = match replaces {
+        Some(previous) => state
+            .sessions
+            .replace_last(tenant_id, session_id, &previous, reply)
+            .await
+            .map(|replaced| {
+                if !replaced {
+                    log::warn!("Session {} changed, regenerated answer not stored", session_id);
+                }
+            }),
+        None => state.sessions.record(tenant_id, session_id, query, reply, bike).await,
+    };
+    if let Err(e) = recorded {
+        log::warn!("Failed to store exchange of session {}: {:#}", session_id, e);
+        return;
+    }
+
+    let Some(summarizer) = state.history_summarizer.clone() else {
+        return;
+    };
+    let sessions = state.sessions.clone();
+    let (tenant_id, session_id) = (tenant_id.to_string(), session_id.to_string());
+    tokio::spawn(async move {
+        match summarizer.compact(sessions.as_ref(), &tenant_id, &session_id).await {
+            Ok(true) => log::debug!("Summarized older turns of session {}", session_id),
+            Ok(false) => {}
+            Err(e) => log::warn!("Failed to summarize session {}: {}", session_id, e),
+        }
+    });
+}
+
+/// Streaming chat handler: a `start` event with the session and sources, `delta`
+/// events as the answer arrives, then `done` (or `error`). Idle connections get
+/// keep-alive comments, and a client that disconnects cancels the upstream stream.
+#[utoipa::path(
+    post,
+    path = "/api/chat/stream",
+    tag = "chat",
+    request_body = ChatRequest,
+    security((), ("chat_api_key" = []), ("tenant_key" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Server-Sent Events: `start`, a `delta` per piece of the answer, then \
+                `done` or `error`",
+            content_type = "text/event-stream",
+            body = String
+        ),
+        (
+            status = 400,
+            description = "A field is missing, malformed or out of range (`INVALID_FIELD`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 401,
+            description = "Missing or wrong `X-Api-Key`, or an unknown tenant key",
+            body = ErrorResponse
+        ),
+        (status = 403, description = "The caller's IP is blocked", body = ErrorResponse),
+        (
+            status = 413,
+            description = "Body larger than `MAX_CHAT_BODY_BYTES`",
+            body = ErrorResponse
+        ),
+        (
+            status = 422,
+            description = "The query is not about bike repair (`INVALID_QUERY`) or too \
+                short (`QUERY_TOO_SHORT`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Rate limit exceeded, or the same query repeated too often",
+            body = ErrorResponse,
+            headers(
+                ("X-RateLimit-Remaining-Minute" = u32, description = "Requests left this minute"),
+                ("X-RateLimit-Remaining-Hour" = u32, description = "Requests left this hour"),
+                ("X-RateLimit-Reset" = u64, description = "Seconds until the limit resets"),
+                ("Retry-After" = u64, description = "Seconds to wait before retrying"),
+            )
+        ),
+        (
+            status = 503,
+            description = "Maintenance mode is on, or OpenAI is unavailable and the circuit \
+                breaker is open",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_chat_stream(
+    body: warp::hyper::body::Bytes,
+    state: AppState,
+    remote_addr: Option<SocketAddr>,
+    admin_key: Option<String>,
+    credentials: TenantCredentials,
+) -> Result<warp::reply::Response, Rejection> {
+    // Recorded when the stream ends, or here if the request is refused
+    let usage = PendingUsage::new(&state, remote_addr);
+    let mut req = match parse_chat_request(&body, &state) {
+        Ok(req) => req,
+        Err(e) => return Err(usage.fail(e.into())),
+    };
+    let unavailable = if req.structured {
+        Some("Structured answers are not available when streaming")
+    } else if req.variants.is_some_and(|n| n > 1) {
+        Some("Variants are not available when streaming")
+    } else if req.include_suggestions == Some(true) {
+        Some("Suggestions are not available when streaming")
+    } else {
+        None
+    };
+    if let Some(message) = unavailable {
+        return Err(usage.fail(ApiError::InvalidRequest(message.to_string()).into()));
+    }
+    req.include_suggestions = Some(false);
+
+    let span = chat_span(&req, remote_addr);
+    let prepared = prepare_chat(&mut req, &state, remote_addr, &credentials, &usage.budget)
+        .instrument(span.clone())
+        .await;
+    let chat = match prepared {
+        Ok(chat) => chat,
+        Err(rejection) => return Err(usage.fail(rejection)),
+    };
+
+    // The exchange joins the session's history once the answer is complete
+    let session_id = req
+        .session_id
+        .clone()
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    {
+        let mut noted = usage.budget.usage.lock().unwrap();
+        (noted.session_id, noted.tokens_in) = (Some(session_id.clone()), chat.prompt_tokens);
+    }
+    let response_id = uuid::Uuid::new_v4().to_string();
+    let record_answer = {
+        let state = state.clone();
+        let (tenant_id, session_id) = (req.tenant_id.clone(), session_id.clone());
+        let (query, bike, replaces) = (req.query.clone(), chat.bike.clone(), chat.replaces);
+        let (response_id, sources) = (response_id.clone(), source_ids(&chat.retrieved));
+        let prompt_tokens = chat.prompt_tokens;
+        move |answer: &str| {
+            let reply = Message::assistant(answer).with_response(response_id, sources);
+            let tokens = match prompt_tokens {
+                0 => 0,
+                _ => prompt_tokens + count_tokens(&state.config.openai_chat_model, answer),
+            };
+            tokio::spawn(async move {
+                let (tenant_id, session_id) = (tenant_id.as_str(), session_id.as_str());
+                remember_exchange(&state, tenant_id, session_id, &query, reply, bike, replaces)
+                    .await;
+                charge_session(&state, tenant_id, session_id, tokens).await;
+            });
+        }
+    };
+
+    // Borderline queries get their steer as the whole answer, without the model
+    let answer = match chat.steer {
+        Some(steer) => {
+            record_answer(&steer);
+            usage.answered();
+            let mut events = vec![delta_event(&steer)];
+            events.extend(finish_events(state.config.response_footer.as_deref()));
+            futures::stream::iter(events).boxed()
+        }
+        None => match state
+            .ai_provider
+            .complete_stream(chat.messages, chat.options)
+            .instrument(span)
+            .await
+        {
+            Ok(inner) => {
+                let upstream = UpstreamStream {
+                    inner,
+                    ip: chat.ip,
+                    request_id: crate::request_context::request_id(),
+                    finished: false,
+                    usage,
+                };
+                let footer = state.config.response_footer.clone();
+                answer_events(upstream, state.circuit_breaker.clone(), footer, record_answer)
+                    .boxed()
+            }
+            Err(e) => {
+                log::error!("OpenAI API error: {}", e);
+                state.circuit_breaker.record_failure().await;
+                return Err(usage.fail(ApiError::AiError.with_rate_limit(&chat.rate_limit_info)));
+            }
+        },
+    };
+
+    let start = serde_json::json!({
+        "session_id": session_id,
+        "response_id": response_id,
+        "sources": to_sources(&chat.retrieved, source_excerpt_chars(&state.config)),
+        "context": req.include_context.then(|| to_context(&chat.retrieved)),
+        "debug": if req.debug && is_admin(&state, admin_key.as_deref()) {
+            chat.trace
+        } else {
+            None
+        },
+        "rate_limit_info": chat.rate_limit_info,
+    });
+    let events = futures::stream::once(async move { json_event("start", &start) })
+        .chain(answer)
+        .map(Ok::<_, std::convert::Infallible>);
+
+    let keep_alive = warp::sse::keep_alive()
+        .interval(std::time::Duration::from_secs(state.config.sse_keep_alive_seconds))
+        .text("keep-alive");
+    Ok(with_rate_limit_headers(
+        warp::sse::reply(keep_alive.stream(events)),
+        &chat.rate_limit_info,
+    ))
+}
+
+/// The model's completion stream for one client. Dropping it before the model
+/// finished (the client disconnected) cancels the upstream request. It is
+/// polled after the handler returned, outside the request's context, so it
+/// carries the request id and the request's usage record itself.
+struct UpstreamStream {
+    inner: CompletionStream,
+    ip: std::net::IpAddr,
+    request_id: Option<String>,
+    finished: bool,
+    usage: PendingUsage,
+}
+
+impl UpstreamStream {
+    fn request_id(&self) -> &str {
+        self.request_id.as_deref().unwrap_or("-")
+    }
+}
+
+impl Drop for UpstreamStream {
+    fn drop(&mut self) {
+        if !self.finished {
+            log::info!(
+                "Client {} disconnected, cancelling the completion stream (request_id={})",
+                self.ip,
+                self.request_id()
+            );
+        }
+    }
+}
+
+/// `delta` events for each piece of the answer, ending with `done` or `error`.
+/// Only a finished or failed stream is reported to the circuit breaker; a
+/// cancelled one says nothing about the upstream's health. `on_answer` gets the
+/// whole answer of a finished stream.
+fn answer_events(
+    upstream: UpstreamStream,
+    circuit_breaker: Arc<CircuitBreaker>,
+    footer: Option<String>,
+    on_answer: impl FnOnce(&str) + Send + 'static,
+) -> impl futures::Stream<Item = warp::sse::Event> {
+    let initial = Some((upstream, on_answer));
+    futures::stream::unfold(initial, move |state| {
+        let circuit_breaker = circuit_breaker.clone();
+        let footer = footer.clone();
+        async move {
+            let (mut upstream, on_answer) = state?;
+            match upstream.inner.next().await {
+                Some(Ok(text)) => {
+                    upstream.usage.answer.push_str(&text);
+                    Some((vec![delta_event(&text)], Some((upstream, on_answer))))
+                }
+                Some(Err(e)) => {
+                    upstream.finished = true;
+                    upstream.usage.outcome = Some(Some(ApiError::AiError.code()));
+                    log::error!(
+                        "OpenAI stream error for {} (request_id={}): {}",
+                        upstream.ip,
+                        upstream.request_id(),
+                        e
+                    );
+                    circuit_breaker.record_failure().await;
+                    let error = ApiError::AiError
+                        .to_response()
+                        .with_request_id(upstream.request_id.clone());
+                    Some((vec![json_event("error", &error)], None))
+                }
+                None => {
+                    upstream.finished = true;
+                    upstream.usage.outcome = Some(None);
+                    circuit_breaker.record_success().await;
+                    on_answer(&upstream.usage.answer);
+                    log::info!(
+                        "Chat stream to {} completed (request_id={})",
+                        upstream.ip,
+                        upstream.request_id()
+                    );
+                    Some((finish_events(footer.as_deref()), None))
+                }
+            }
+        }
+    })
+    .flat_map(futures::stream::iter)
+}
+
+fn delta_event(text: &str) -> warp::sse::Event {
+    json_event("delta", &serde_json::json!({ "text": text }))
+}
+
+/// The configured footer as a last `delta`, then `done`
+fn finish_events(footer: Option<&str>) -> Vec<warp::sse::Event> {
+    let footer = footer.map(|footer| delta_event(&format!("\n\n{}", footer)));
+    footer
+        .into_iter()
+        .chain([json_event("done", &serde_json::json!({}))])
+        .collect()
+}
+
+/// Append the configured footer to a successful answer. It is added after
+/// generation, so it never counts against the answer's token budget.
+fn with_footer(answer: String, footer: Option<&str>) -> String {
+    match footer {
+        Some(footer) => format!("{}\n\n{}", answer, footer),
+        None => answer,
+    }
+}
+
+fn json_event(name: &str, data: &impl serde::Serialize) -> warp::sse::Event {
+    warp::sse::Event::default()
+        .event(name)
+        .json_data(data)
+        .expect("event data serializes to JSON")
+}
+
+/// WebSocket close code of a connection idle for `WS_IDLE_TIMEOUT_SECONDS`
+pub const WS_CLOSE_IDLE: u16 = 4408;
+
+/// WebSocket close code once the caller's chat rate limit is exceeded
+pub const WS_CLOSE_RATE_LIMITED: u16 = 4429;
+
+/// WebSocket close code (policy violation) past `WS_MAX_MESSAGES_PER_CONNECTION`
+pub const WS_CLOSE_MESSAGE_LIMIT: u16 = 1008;
+
+/// Longest close reason a WebSocket close frame carries, in bytes
+const WS_MAX_CLOSE_REASON: usize = 123;
+
+/// WebSocket chat: upgrades callers that are not blocked and whose API key is
+/// accepted. The connection is bound to the `session_id` query parameter, or
+/// to a new session.
+#[utoipa::path(
+    get,
+    path = "/api/chat/ws",
+    tag = "chat",
+    params(ChatSocketQuery),
+    security((), ("chat_api_key" = []), ("tenant_key" = [])),
+    responses(
+        (
+            status = 101,
+            description = "WebSocket taking chat requests as text frames and answering with \
+                `delta`, `done` and `error` frames"
+        ),
+        (status = 400, description = "`session_id` is not a UUID", body = ErrorResponse),
+        (
+            status = 401,
+            description = "Missing or wrong `X-Api-Key`, or an unknown tenant key",
+            body = ErrorResponse
+        ),
+        (status = 403, description = "The caller's IP is blocked", body = ErrorResponse),
+    )
+)]
+pub async fn handle_chat_ws(
+    ws: warp::ws::Ws,
+    query: ChatSocketQuery,
+    state: AppState,
+    remote_addr: Option<SocketAddr>,
+    admin_key: Option<String>,
+    credentials: TenantCredentials,
+) -> Result<impl Reply, Rejection> {
+    authenticate_tenant(&state, &credentials)?;
+    let session_id = match query.session_id {
+        Some(id) if uuid::Uuid::parse_str(&id).is_err() => {
+            let error = FieldError::new("session_id", format!("'{}' is not a UUID", id));
+            return Err(ApiError::InvalidField(error).into());
+        }
+        Some(id) => id,
+        None => uuid::Uuid::new_v4().to_string(),
+    };
+
+    let connection = ChatSocket {
+        state,
+        session_id,
+        remote_addr,
+        admin_key,
+        credentials,
+        request_id: crate::request_context::request_id(),
+    };
+    Ok(ws.on_upgrade(move |socket| connection.run(socket)))
+}
+
+/// An upgraded chat connection and what it was opened with
+struct ChatSocket {
+    state: AppState,
+    session_id: String,
+    remote_addr: Option<SocketAddr>,
+    admin_key: Option<String>,
+    credentials: TenantCredentials,
+    /// Id of the upgrade request, repeated in error frames
+    request_id: Option<String>,
+}
+
+/// Why a chat connection ends
+enum SocketEnd {
+    /// The client went away or the socket failed
+    Disconnected,
+    /// The server closes it with this code and reason
+    Close(u16, String),
+}
+
+type SocketSink = futures::stream::SplitSink<warp::ws::WebSocket, warp::ws::Message>;
+
+impl ChatSocket {
+    /// Answer each text frame as a chat request until the client leaves, the
+    /// connection idles out, or it runs into the message or rate limit
+    async fn run(self, socket: warp::ws::WebSocket) {
+        let (mut tx, mut rx) = socket.split();
+        let idle = Duration::from_secs(self.state.config.ws_idle_timeout_seconds);
+        let max_messages = self.state.config.ws_max_messages_per_connection;
+        log::info!("Chat WebSocket opened for session {}", self.session_id);
+
+        let mut received = 0;
+        let end = loop {
+            let frame = match tokio::time::timeout(idle, rx.next()).await {
+                Err(_) => {
+                    let reason = format!("Idle for {}s", idle.as_secs());
+                    break SocketEnd::Close(WS_CLOSE_IDLE, reason);
+                }
+                Ok(Some(Ok(frame))) => frame,
+                Ok(_) => break SocketEnd::Disconnected,
+            };
+            if frame.is_close() {
+                break SocketEnd::Disconnected;
+            }
+            // Pings are answered by the socket itself
+            if frame.is_ping() || frame.is_pong() {
+                continue;
+            }
+
+            received += 1;
+            if received > max_messages {
+                let reason = format!("Limit of {} messages per connection reached", max_messages);
+                break SocketEnd::Close(WS_CLOSE_MESSAGE_LIMIT, reason);
+            }
+            let answered = if frame.is_text() {
+                self.answer(frame.as_bytes(), &mut tx).await
+            } else {
+                let error = ApiError::InvalidRequest("Send chat requests as text frames".into());
+                self.send_error(&mut tx, &error).await
+            };
+            if let Err(end) = answered {
+                break end;
+            }
+        };
+
+        if let SocketEnd::Close(code, mut reason) = end {
+            log::info!("Closing chat WebSocket of session {}: {}", self.session_id, reason);
+            while reason.len() > WS_MAX_CLOSE_REASON {
+                reason.pop();
+            }
+            let _ = tx.send(warp::ws::Message::close_with(code, reason)).await;
+        }
+        let _ = tx.close().await;
+    }
+
+    /// Answer one chat request: `delta` frames as the answer arrives, then
+    /// `done` with the sources and rate limit; or an `error` frame. The same
+    /// checks as `/api/chat` apply, in the connection's session.
+    async fn answer(&self, body: &[u8], tx: &mut SocketSink) -> Result<(), SocketEnd> {
+        let state = &self.state;
+        // Recorded on every way out, as `CANCELLED` if the client goes away
+        let mut usage = PendingUsage::new(state, self.remote_addr);
+        let mut req = match parse_chat_request(body, state) {
+            Ok(req) => req,
+            Err(error) => return self.refuse(tx, &mut usage, &error).await,
+        };
+        let unavailable = if req.structured || req.variants.is_some_and(|n| n > 1) {
+            Some("Structured answers and variants are not available over WebSocket")
+        } else if req.include_suggestions == Some(true) {
+            Some("Suggestions are not available over WebSocket")
+        } else if req.session_id.as_ref().is_some_and(|id| *id != self.session_id) {
+            Some("session_id differs from the session of this connection")
+        } else {
+            None
+        };
+        if let Some(message) = unavailable {
+            let error = ApiError::InvalidRequest(message.to_string());
+            return self.refuse(tx, &mut usage, &error).await;
+        }
+        req.include_suggestions = Some(false);
+        req.session_id = Some(self.session_id.clone());
+        usage.budget.usage.lock().unwrap().session_id = req.session_id.clone();
+
+        let span = chat_span(&req, self.remote_addr);
+        let credentials = &self.credentials;
+        let prepared = prepare_chat(&mut req, state, self.remote_addr, credentials, &usage.budget)
+            .instrument(span.clone())
+            .await;
+        let chat = match prepared {
+            Ok(chat) => chat,
+            Err(rejection) => {
+                let error = rejection
+                    .find::<ApiError>()
+                    .or_else(|| rejection.find::<RateLimitedError>().map(|limited| &limited.error));
+                return match error {
+                    Some(ApiError::RateLimitExceeded { message, .. }) => {
+                        usage.outcome = Some(error.map(ApiError::code));
+                        Err(SocketEnd::Close(WS_CLOSE_RATE_LIMITED, message.clone()))
+                    }
+                    Some(error) => self.refuse(tx, &mut usage, error).await,
+                    None => {
+                        let error = ApiError::Internal("Chat request failed");
+                        self.refuse(tx, &mut usage, &error).await
+                    }
+                };
+            }
+        };
+        usage.budget.usage.lock().unwrap().tokens_in = chat.prompt_tokens;
+
+        // Borderline queries get their steer as the whole answer, without the model
+        match chat.steer {
+            Some(steer) => {
+                send_frame(tx, "delta", serde_json::json!({ "text": steer })).await?;
+                usage.answer = steer;
+            }
+            None => {
+                let stream = state.ai_provider.complete_stream(chat.messages, chat.options);
+                let mut upstream = match stream.instrument(span).await {
+                    Ok(upstream) => upstream,
+                    Err(e) => {
+                        log::error!("OpenAI API error: {}", e);
+                        state.circuit_breaker.record_failure().await;
+                        return self.refuse(tx, &mut usage, &ApiError::AiError).await;
+                    }
+                };
+                // Leaving early (the client went away) drops and so cancels the stream
+                while let Some(piece) = upstream.next().await {
+                    match piece {
+                        Ok(text) => {
+                            send_frame(tx, "delta", serde_json::json!({ "text": text })).await?;
+                            usage.answer.push_str(&text);
+                        }
+                        Err(e) => {
+                            log::error!("OpenAI stream error for {}: {}", chat.ip, e);
+                            state.circuit_breaker.record_failure().await;
+                            return self.refuse(tx, &mut usage, &ApiError::AiError).await;
+                        }
+                    }
+                }
+                state.circuit_breaker.record_success().await;
+            }
+        }
+        usage.outcome = Some(None);
+        let answer = usage.answer.clone();
+        if let Some(footer) = &state.config.response_footer {
+            let text = format!("\n\n{}", footer);
+            send_frame(tx, "delta", serde_json::json!({ "text": text })).await?;
+        }
+
+        let response_id = uuid::Uuid::new_v4().to_string();
+        let tokens = match chat.prompt_tokens {
+            0 => 0,
+            prompt_tokens => prompt_tokens + count_tokens(&state.config.openai_chat_model, &answer),
+        };
+        let reply = Message::assistant(answer)
+            .with_response(response_id.clone(), source_ids(&chat.retrieved));
+        let (tenant_id, query) = (req.tenant_id.as_str(), req.query.as_str());
+        let (bike, replaces) = (chat.bike, chat.replaces);
+        remember_exchange(state, tenant_id, &self.session_id, query, reply, bike, replaces).await;
+        charge_session(state, tenant_id, &self.session_id, tokens).await;
+
+        let done = serde_json::json!({
+            "session_id": self.session_id,
+            "response_id": response_id,
+            "sources": to_sources(&chat.retrieved, source_excerpt_chars(&state.config)),
+            "context": req.include_context.then(|| to_context(&chat.retrieved)),
+            "debug": if req.debug && is_admin(state, self.admin_key.as_deref()) {
+                chat.trace
+            } else {
+                None
+            },
+            "rate_limit_info": chat.rate_limit_info,
+        });
+        send_frame(tx, "done", done).await
+    }
+
+    /// Answer a chat request with `error`, noting it in the request's usage record
+    async fn refuse(
+        &self,
+        tx: &mut SocketSink,
+        usage: &mut PendingUsage,
+        error: &ApiError,
+    ) -> Result<(), SocketEnd> {
+        usage.outcome = Some(Some(error.code()));
+        self.send_error(tx, error).await
+    }
+
+    async fn send_error(&self, tx: &mut SocketSink, error: &ApiError) -> Result<(), SocketEnd> {
+        let response = error.to_response().with_request_id(self.request_id.clone());
+        let data = serde_json::to_value(response).expect("error response serializes to JSON");
+        send_frame(tx, "error", data).await
+    }
+}
+
+/// Send `data` as a JSON text frame with a `type` field of `kind`
+async fn send_frame(
+    tx: &mut SocketSink,
+    kind: &str,
+    mut data: serde_json::Value,
+) -> Result<(), SocketEnd> {
+    if let Some(fields) = data.as_object_mut() {
+        fields.insert("type".to_string(), kind.into());
+    }
+    tx.send(warp::ws::Message::text(data.to_string()))
+        .await
+        .map_err(|_| SocketEnd::Disconnected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::MockProvider;
+    use crate::ai::SUGGESTIONS_PROMPT;
+    use crate::server::create_routes;
+    use crate::server::test_support::{chunk, post_chat, seed, test_state, QUERY};
+
+    async fn seeded_state(default_bike_model: Option<&str>) -> (AppState, tempfile::TempDir) {
+        let config = Config {
+            default_bike_model: default_bike_model.map(String::from),
+            min_confidence: 0.0,
+            ..Config::default()
+        };
+        let (state, dir) = test_state(config, Arc::new(MockProvider::new("Adjust it."))).await;
+        seed(
+            &state,
+            vec![
+                chunk("Harley Sportster", 40, "Adjust the drive chain slack"),
+                chunk("Honda CBR600RR", 12, "Adjust the drive chain tension"),
+            ],
+        )
+        .await;
+        (state, dir)
+    }
+
+    fn source_models(body: &serde_json::Value) -> Vec<String> {
+        body["sources"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["bike_model"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_refused_queries_unprocessable_and_malformed_json_bad_request() {
+        let (state, _dir) = test_state(Config::default(), Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state);
+        let send = |body: String| {
+            warp::test::request()
+                .method("POST")
+                .path("/api/chat")
+                .header("content-type", "application/json")
+                .body(body)
+                .reply(&routes)
+        };
+        let query = |query: &str| serde_json::json!({ "query": query }).to_string();
+
+        let cases = [
+            (send("{\"query\": ".to_string()).await, 400, "INVALID_FIELD"),
+            (send(query("What is the capital of France?")).await, 422, "INVALID_QUERY"),
+            (send(query(&"chain slack ".repeat(100))).await, 422, "INVALID_QUERY"),
+            (send(query("   ")).await, 422, "INVALID_QUERY"),
+        ];
+        for (resp, status, code) in cases {
+            let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+            assert_eq!((resp.status().as_u16(), body["code"].as_str()), (status, Some(code)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_too_short_query_refused_before_the_model() {
+        let config = Config {
+            min_query_words: 3,
+            ..Config::default()
+        };
+        let provider = Arc::new(MockProvider::new("ok"));
+        let (state, _dir) = test_state(config, provider.clone()).await;
+        let routes = create_routes(state);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/chat")
+            .json(&serde_json::json!({ "query": "chain slack" }))
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), 422);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["code"], "QUERY_TOO_SHORT");
+        assert_eq!(provider.chat_call_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_query_throttled() {
+        let config = Config {
+            repeat_query_limit: 2,
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state);
+
+        let mut statuses = Vec::new();
+        for _ in 0..3 {
+            let resp = warp::test::request()
+                .method("POST")
+                .path("/api/chat")
+                .json(&serde_json::json!({ "query": QUERY }))
+                .reply(&routes)
+                .await;
+            statuses.push(resp.status().as_u16());
+            if resp.status() == 429 {
+                let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+                assert_eq!(body["code"], "REPEATED_QUERY");
+            }
+        }
+
+        assert_eq!(statuses, vec![200, 200, 429]);
+    }
+
+    #[tokio::test]
+    async fn test_session_refused_once_its_token_budget_is_spent() {
+        let config = Config {
+            session_token_budget: 100,
+            ..Config::default()
+        };
+        let provider = Arc::new(MockProvider::new("Loosen the axle nut first."));
+        let (state, _dir) = test_state(config, provider.clone()).await;
+        let routes = create_routes(state.clone());
+        let session_id = uuid::Uuid::new_v4().to_string();
+
+        let request = serde_json::json!({ "query": QUERY, "session_id": session_id });
+        post_chat(state.clone(), request).await;
+        let session = state.sessions.get("default", &session_id).await.unwrap().unwrap();
+        assert!(session.tokens_used > 100);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/chat")
+            .json(&serde_json::json!({
+                "query": "How do I adjust the chain tension on my motorcycle?",
+                "session_id": session_id,
+            }))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 429);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["code"], "SESSION_BUDGET_EXCEEDED");
+        assert!(body["error"].as_str().unwrap().contains("start a new session"));
+        assert_eq!(provider.chat_call_count(), 1);
+
+        // A new session starts with a fresh budget
+        let query = "How do I bleed the front brake on my motorcycle?";
+        post_chat(state, serde_json::json!({ "query": query })).await;
+        assert_eq!(provider.chat_call_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_chat_stream_sends_answer_then_done() {
+        let provider = Arc::new(MockProvider::new("Loosen the axle nut"));
+        let (state, _dir) = test_state(Config::default(), provider.clone()).await;
+        let breaker = state.circuit_breaker.clone();
+        breaker.record_failure().await;
+        let routes = create_routes(state.clone());
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/chat/stream")
+            .json(&serde_json::json!({ "query": QUERY }))
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "text/event-stream");
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let events: Vec<_> = body.lines().filter_map(|l| l.strip_prefix("event:")).collect();
+        assert_eq!(events, ["start", "delta", "delta", "delta", "delta", "done"]);
+        assert!(body.contains(r#"data:{"text":"axle "}"#));
+        // A completed stream counts as a success
+        assert_eq!(breaker.get_stats().await.failure_count, 0);
+        assert_eq!(provider.open_streams.load(Ordering::SeqCst), 0);
+
+        let totals = usage_totals(&state, 1).await;
+        assert_eq!((totals.requests, totals.unique_sessions, totals.error_rate), (1, 1, 0.0));
+        assert!(totals.tokens_in > 0);
+        assert_eq!(totals.tokens_out, 5);
+    }
+
+    async fn recv_frame(client: &mut warp::test::WsClient) -> serde_json::Value {
+        let frame = client.recv().await.unwrap();
+        serde_json::from_str(frame.to_str().unwrap()).unwrap()
+    }
+
+    /// Frames of one answer, up to its `done` or `error` frame
+    async fn recv_answer(client: &mut warp::test::WsClient) -> Vec<serde_json::Value> {
+        let mut frames = Vec::new();
+        loop {
+            let frame = recv_frame(client).await;
+            let last = frame["type"] != "delta";
+            frames.push(frame);
+            if last {
+                return frames;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_websocket_chat_answers_in_one_session() {
+        let provider = Arc::new(MockProvider::new("Loosen the axle nut"));
+        let (state, _dir) = test_state(Config::default(), provider).await;
+        let sessions = state.sessions.clone();
+        let routes = create_routes(state.clone());
+        let mut client = warp::test::ws().path("/api/chat/ws").handshake(routes).await.unwrap();
+        let query = serde_json::json!({ "query": QUERY }).to_string();
+
+        client.send_text(query.clone()).await;
+        let frames = recv_answer(&mut client).await;
+        let text: String = frames[..frames.len() - 1]
+            .iter()
+            .map(|frame| frame["text"].as_str().unwrap())
+            .collect();
+        assert_eq!(text, "Loosen the axle nut");
+        let done = frames.last().unwrap();
+        assert_eq!(done["type"], "done");
+        assert!(done["sources"].is_array());
+        assert!(done["rate_limit_info"]["remaining_minute"].is_u64());
+        let session_id = done["session_id"].as_str().unwrap().to_string();
+
+        client.send_text(query).await;
+        let done = recv_answer(&mut client).await.pop().unwrap();
+        assert_eq!(done["session_id"], session_id.as_str());
+
+        // Refused requests get an error frame and the connection stays open
+        let other_session = uuid::Uuid::new_v4().to_string();
+        let foreign = serde_json::json!({ "query": QUERY, "session_id": other_session });
+        client.send_text(foreign.to_string()).await;
+        let error = recv_frame(&mut client).await;
+        assert_eq!((&error["type"], &error["code"]), (&"error".into(), &"INVALID_REQUEST".into()));
+        client.send_text("not json").await;
+        assert_eq!(recv_frame(&mut client).await["code"], "INVALID_FIELD");
+
+        let session = sessions.get(crate::models::DEFAULT_TENANT, &session_id).await.unwrap();
+        assert_eq!(session.unwrap().messages.len(), 4);
+
+        // Answers and refusals alike are recorded
+        let totals = usage_totals(&state, 4).await;
+        assert_eq!((totals.requests, totals.error_rate), (4, 0.5));
+        assert!(totals.tokens_out > 0);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_closed_past_rate_limit_message_cap_and_idle_timeout() {
+        let cases = [
+            (
+                Config {
+                    max_requests_per_minute: 1,
+                    ..Config::default()
+                },
+                2,
+                WS_CLOSE_RATE_LIMITED,
+            ),
+            (
+                Config {
+                    ws_max_messages_per_connection: 2,
+                    ..Config::default()
+                },
+                3,
+                WS_CLOSE_MESSAGE_LIMIT,
+            ),
+            (
+                Config {
+                    ws_idle_timeout_seconds: 1,
+                    ..Config::default()
+                },
+                0,
+                WS_CLOSE_IDLE,
+            ),
+        ];
+        // warp's test client hides close frames, so these go over a real socket
+        for (config, messages, expected) in cases {
+            use tokio_tungstenite::tungstenite::Message;
+            let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+            let (addr, server) =
+                warp::serve(create_routes(state)).bind_ephemeral(([127, 0, 0, 1], 0));
+            tokio::spawn(server);
+            let url = format!("ws://{}/api/chat/ws", addr);
+            let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+            let query = serde_json::json!({ "query": QUERY }).to_string();
+            for _ in 0..messages {
+                client.send(Message::Text(query.clone())).await.unwrap();
+            }
+
+            let close = loop {
+                match client.next().await.unwrap().unwrap() {
+                    Message::Close(frame) => break frame.expect("close frame without a code"),
+                    _ => continue,
+                }
+            };
+            assert_eq!(u16::from(close.code), expected, "{}", close.reason);
+            assert!(!close.reason.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_cancels_upstream_stream() {
+        use warp::hyper::body::HttpBody;
+
+        let provider = Arc::new(MockProvider::stalling());
+        let (state, _dir) = test_state(Config::default(), provider.clone()).await;
+        let breaker = state.circuit_breaker.clone();
+        breaker.record_failure().await;
+        let body = serde_json::to_vec(&serde_json::json!({ "query": QUERY })).unwrap();
+
+        let resp = handle_chat_stream(body.into(), state.clone(), None, None, Default::default())
+            .await
+            .unwrap();
+        let mut body = resp.into_body();
+        let mut received = String::new();
+        // The start event's `remaining_minute` contains "nut" too
+        while !received.contains(r#"{"text":"nut"}"#) {
+            let frame = body.data().await.unwrap().unwrap();
+            received.push_str(std::str::from_utf8(&frame).unwrap());
+        }
+        assert_eq!(provider.open_streams.load(Ordering::SeqCst), 1);
+
+        // The client goes away while the model is still generating
+        drop(body);
+
+        assert_eq!(provider.open_streams.load(Ordering::SeqCst), 0);
+        let stats = breaker.get_stats().await;
+        assert_eq!((stats.failure_count, stats.total_failures), (1, 1));
+
+        // Recorded as cancelled, with the tokens generated so far
+        let totals = usage_totals(&state, 1).await;
+        assert_eq!((totals.requests, totals.error_rate), (1, 1.0));
+        assert!(totals.tokens_out > 0);
+    }
+
+    #[tokio::test]
+    async fn test_borderline_query_steered_without_model_call() {
+        let provider = Arc::new(MockProvider::new("ok"));
+        let (state, _dir) = test_state(Config::default(), provider.clone()).await;
+        let routes = create_routes(state);
+
+        let ask = |query: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/api/chat")
+                .json(&serde_json::json!({ "query": query }))
+                .reply(&routes)
+        };
+
+        let resp = ask("My car makes a grinding sound").await;
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert!(body["response"].as_str().unwrap().contains("motorcycles and scooters"));
+        assert_eq!(provider.chat_call_count(), 0);
+
+        let resp = ask("Tell me a joke").await;
+        assert_eq!(resp.status(), 422);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["code"], "INVALID_QUERY");
+    }
+
+    #[tokio::test]
+    async fn test_footer_appended_to_answers_only() {
+        const FOOTER: &str = "Consult a certified mechanic for safety-critical repairs.";
+        let config = Config {
+            response_footer: Some(FOOTER.to_string()),
+            ..Config::default()
+        };
+        let provider = Arc::new(MockProvider::new("Adjust the chain to 30 mm of slack."));
+        let (state, _dir) = test_state(config, provider.clone()).await;
+
+        let body = post_chat(state.clone(), serde_json::json!({ "query": QUERY })).await;
+        assert_eq!(
+            body["response"],
+            format!("Adjust the chain to 30 mm of slack.\n\n{}", FOOTER)
+        );
+
+        provider.fail_chat.store(true, Ordering::Relaxed);
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/chat")
+            .json(&serde_json::json!({ "query": "How do I bleed the brakes?" }))
+            .reply(&create_routes(state))
+            .await;
+        assert_eq!(resp.status(), 500);
+        assert!(!String::from_utf8_lossy(resp.body()).contains(FOOTER));
+    }
+
+    #[tokio::test]
+    async fn test_default_bike_model_applied_when_omitted() {
+        let (state, _dir) = seeded_state(Some("Harley Sportster")).await;
+
+        let body = post_chat(state, serde_json::json!({ "query": QUERY })).await;
+
+        assert_eq!(source_models(&body), vec!["Harley Sportster"]);
+    }
+
+    #[tokio::test]
+    async fn test_request_bike_model_overrides_default() {
+        let (state, _dir) = seeded_state(Some("Harley Sportster")).await;
+
+        let body = post_chat(
+            state,
+            serde_json::json!({ "query": QUERY, "bike_model": "Honda CBR600RR" }),
+        )
+        .await;
+
+        assert_eq!(source_models(&body), vec!["Honda CBR600RR"]);
+    }
+
+    #[tokio::test]
+    async fn test_bike_model_spellings_filter_alike() {
+        let (state, _dir) = seeded_state(None).await;
+
+        for model in ["cbr 600 rr", "Honda CBR-600RR", "CBR600RR"] {
+            let body =
+                post_chat(state.clone(), serde_json::json!({ "query": QUERY, "bike_model": model }))
+                    .await;
+            assert_eq!(source_models(&body), vec!["Honda CBR600RR"], "{}", model);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_context_attached_only_when_requested() {
+        let config = Config {
+            min_confidence: 0.0,
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("Adjust it."))).await;
+        let mut other_tenant = chunk("Honda CBR600RR", 3, "Adjust the drive chain slack");
+        other_tenant.metadata.tenant_id = "acme".to_string();
+        seed(
+            &state,
+            vec![chunk("Honda CBR600RR", 12, "Adjust the drive chain tension"), other_tenant],
+        )
+        .await;
+
+        let body = post_chat(state.clone(), serde_json::json!({ "query": QUERY })).await;
+        assert!(body.get("context").is_none());
+
+        let request = serde_json::json!({ "query": QUERY, "include_context": true });
+        let body = post_chat(state.clone(), request.clone()).await;
+        // Only the caller's tenant is searched, so only its text is shared
+        assert_eq!(
+            body["context"],
+            serde_json::json!([{
+                "chunk_id": body["sources"][0]["chunk_id"],
+                "document_id": "doc-1",
+                "bike_model": "Honda CBR600RR",
+                "page_number": 12,
+                "section": null,
+                "relevance_score": body["sources"][0]["relevance_score"],
+                "text": "Adjust the drive chain tension",
+            }])
+        );
+
+        let config = Config {
+            source_excerpts_enabled: false,
+            ..(*state.config).clone()
+        };
+        let state = AppState {
+            config: Arc::new(config),
+            ..state
+        };
+        let body = post_invalid_chat(state, request).await;
+        assert_eq!(body["error"], "Invalid field 'include_context'");
+    }
+
+    #[tokio::test]
+    async fn test_second_turn_prompt_contains_first_exchange() {
+        let (state, _dir) = seeded_state(None).await;
+        let provider = Arc::new(MockProvider::new("Loosen the axle nut first."));
+        let state = AppState {
+            ai_provider: provider.clone(),
+            ..state
+        };
+
+        let first = post_chat(
+            state.clone(),
+            serde_json::json!({ "query": QUERY, "bike_model": "Honda CBR600RR" }),
+        )
+        .await;
+        let session_id = first["session_id"].as_str().unwrap();
+        let second = post_chat(
+            state,
+            serde_json::json!({
+                "query": "How do I torque the rear axle nut on my motorcycle?",
+                "session_id": session_id,
+            }),
+        )
+        .await;
+
+        let prompt = provider.last_prompt().unwrap();
+        let turns: Vec<_> = prompt[1..]
+            .iter()
+            .map(|m| (m.role.as_str(), m.content.as_str()))
+            .collect();
+        assert_eq!(
+            turns,
+            [
+                ("system", "The user's motorcycle is a Honda CBR600RR."),
+                ("user", QUERY),
+                ("assistant", "Loosen the axle nut first."),
+                ("user", "How do I torque the rear axle nut on my motorcycle?"),
+            ]
+        );
+        assert_eq!(second["session_id"], session_id);
+        // The session remembers the bike model the conversation is about
+        assert_eq!(source_models(&second), vec!["Honda CBR600RR"]);
+    }
+
+    #[tokio::test]
+    async fn test_bike_named_in_query_sticks_to_session() {
+        let (state, _dir) = seeded_state(None).await;
+        let provider = Arc::new(MockProvider::new("Loosen the axle nut first."));
+        let state = AppState {
+            ai_provider: provider.clone(),
+            ..state
+        };
+        let routes = create_routes(state.clone());
+
+        let first = post_chat(
+            state.clone(),
+            serde_json::json!({
+                "query": "How do I adjust the drive chain on my 2014 Street Triple?",
+            }),
+        )
+        .await;
+        let session_id = first["session_id"].as_str().unwrap();
+        let second = post_chat(
+            state.clone(),
+            serde_json::json!({
+                "query": "How do I torque the rear axle nut on my motorcycle?",
+                "session_id": session_id,
+            }),
+        )
+        .await;
+
+        let prompt = provider.last_prompt().unwrap();
+        assert_eq!(prompt[1].role, Role::System);
+        assert_eq!(prompt[1].content, "The user's motorcycle is a 2014 Triumph Street Triple.");
+        // Retrieval is limited to the remembered model, which has no manual here
+        assert!(source_models(&second).is_empty());
+        let path = format!("/api/sessions/{}", session_id);
+        let resp = warp::test::request().path(&path).reply(&routes).await;
+        let session: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(session["bike_model"], "Triumph Street Triple");
+        assert_eq!(session["bike_year"], 2014);
+
+        // A bike_model in a later request replaces the remembered one
+        let third = post_chat(
+            state.clone(),
+            serde_json::json!({
+                "query": QUERY,
+                "session_id": session_id,
+                "bike_model": "Honda CBR600RR",
+            }),
+        )
+        .await;
+        assert_eq!(source_models(&third), vec!["Honda CBR600RR"]);
+        let resp = warp::test::request().path(&path).reply(&routes).await;
+        let session: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(session["bike_model"], "Honda CBR600RR");
+        assert!(session["bike_year"].is_null());
+
+        // Another spelling of the same model keeps the session's
+        let fourth = post_chat(
+            state,
+            serde_json::json!({
+                "query": QUERY,
+                "session_id": session_id,
+                "bike_model": "cbr 600 rr",
+            }),
+        )
+        .await;
+        assert_eq!(source_models(&fourth), vec!["Honda CBR600RR"]);
+        let resp = warp::test::request().path(&path).reply(&routes).await;
+        let session: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(session["bike_model"], "Honda CBR600RR");
+    }
+
+    #[tokio::test]
+    async fn test_sampling_overrides_bounded() {
+        let config = Config {
+            openai_temperature: 0.4,
+            ..Config::default()
+        };
+        let provider = Arc::new(MockProvider::new("Adjust it."));
+        let (state, _dir) = test_state(config, provider.clone()).await;
+
+        post_chat(state.clone(), serde_json::json!({ "query": QUERY, "top_p": 0.5 })).await;
+        let options = provider.last_options().unwrap();
+        assert_eq!((options.temperature, options.top_p), (Some(0.4), Some(0.5)));
+        post_chat(state.clone(), serde_json::json!({ "query": QUERY, "temperature": 0.0 })).await;
+        assert_eq!(provider.last_options().unwrap().temperature, Some(0.0));
+
+        let body =
+            post_invalid_chat(state, serde_json::json!({ "query": QUERY, "temperature": 2.5 }))
+                .await;
+        assert_eq!(body["error"], "Invalid field 'temperature'");
+    }
+
+    #[tokio::test]
+    async fn test_variants_return_every_choice_when_requested() {
+        let provider = Arc::new(MockProvider::new("Adjust it."));
+        let (state, _dir) = test_state(Config::default(), provider.clone()).await;
+
+        let body = post_chat(state.clone(), serde_json::json!({ "query": QUERY })).await;
+        assert_eq!(provider.last_options().unwrap().choices, None);
+        assert!(body.get("variants").is_none());
+
+        let request = serde_json::json!({ "query": QUERY, "variants": 3 });
+        let body = post_chat(state.clone(), request).await;
+        assert_eq!(provider.last_options().unwrap().choices, Some(3));
+        let expected = ["Adjust it.", "Adjust it. (2)", "Adjust it. (3)"];
+        assert_eq!(body["variants"], serde_json::json!(expected));
+        assert_eq!(body["response"], body["variants"][0]);
+
+        let body =
+            post_invalid_chat(state.clone(), serde_json::json!({ "query": QUERY, "variants": 9 }))
+                .await;
+        assert_eq!(body["error"], "Invalid field 'variants'");
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/chat/stream")
+            .json(&serde_json::json!({ "query": QUERY, "variants": 2 }))
+            .reply(&create_routes(state))
+            .await;
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_chat_past_deadline_times_out_and_counts_as_failure() {
+        let config = Config {
+            request_timeout_seconds: 1,
+            ..Config::default()
+        };
+        let provider = MockProvider::new("ok").with_completion_delay(Duration::from_secs(30));
+        let (state, _dir) = test_state(config, Arc::new(provider)).await;
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/chat")
+            .json(&serde_json::json!({ "query": QUERY }))
+            .reply(&create_routes(state.clone()))
+            .await;
+        assert_eq!(resp.status(), 504);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["code"], "REQUEST_TIMEOUT");
+        assert!(resp.headers().contains_key("X-RateLimit-Remaining-Minute"));
+
+        assert_eq!(state.circuit_breaker.get_stats().await.total_failures, 1);
+        let localhost = std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+        let remaining = state.rate_limiter.get_status(localhost).await.remaining_minute;
+        assert_eq!(remaining, state.config.max_requests_per_minute - 1);
+    }
+
+    async fn post_invalid_chat(state: AppState, body: serde_json::Value) -> serde_json::Value {
+        let routes = create_routes(state);
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/chat")
+            .json(&body)
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["code"], "INVALID_FIELD");
+        body
+    }
+
+    #[tokio::test]
+    async fn test_regenerate_replaces_last_answer_without_repeating_the_question() {
+        let provider = Arc::new(MockProvider::new("Adjust the chain to 30 mm of slack."));
+        let (state, _dir) = test_state(Config::default(), provider.clone()).await;
+        let first = post_chat(state.clone(), serde_json::json!({ "query": QUERY })).await;
+        let session_id = first["session_id"].as_str().unwrap().to_string();
+
+        *provider.response.lock().unwrap() = "Loosen the axle nut first.".to_string();
+        let body = serde_json::json!({ "session_id": session_id, "regenerate": true });
+        let second = post_chat(state.clone(), body).await;
+
+        assert_eq!(second["response"], "Loosen the axle nut first.");
+        assert_ne!(second["response_id"], first["response_id"]);
+        assert_eq!(provider.chat_call_count(), 2);
+        // The question is asked again without its old answer in the history
+        let prompt = provider.last_prompt().unwrap();
+        assert_eq!(prompt.iter().filter(|m| m.role == Role::User && m.content == QUERY).count(), 1);
+        assert!(prompt.iter().all(|m| !m.content.contains("30 mm")));
+        let boosted = state.config.openai_temperature + REGENERATE_TEMPERATURE_BOOST;
+        assert_eq!(provider.last_options().unwrap().temperature, Some(boosted));
+
+        let session = state
+            .sessions
+            .get(crate::models::DEFAULT_TENANT, &session_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let history: Vec<_> =
+            session.messages.iter().map(|m| (m.role.as_str(), m.content.as_str())).collect();
+        assert_eq!(history, [("user", QUERY), ("assistant", "Loosen the axle nut first.")]);
+
+        let body = post_invalid_chat(state.clone(), serde_json::json!({ "regenerate": true })).await;
+        assert_eq!(body["error"], "Invalid field 'session_id'");
+        let routes = create_routes(state);
+        let unknown = serde_json::json!({ "session_id": uuid::Uuid::new_v4(), "regenerate": true });
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/chat")
+            .json(&unknown)
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_bad_session_id_names_field() {
+        let provider = Arc::new(MockProvider::new("Adjust it."));
+        let (state, _dir) = test_state(Config::default(), provider.clone()).await;
+
+        for session_id in [serde_json::json!(42), serde_json::json!("my-session")] {
+            let body = post_invalid_chat(
+                state.clone(),
+                serde_json::json!({ "query": QUERY, "session_id": session_id }),
+            )
+            .await;
+            assert_eq!(body["error"], "Invalid field 'session_id'");
+        }
+        let body = post_invalid_chat(state, serde_json::json!({ "query": QUERY, "debug": "yes" }))
+            .await;
+        assert_eq!(body["error"], "Invalid field 'debug'");
+        assert_eq!(provider.chat_call_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_bike_model_rejected() {
+        let config = Config {
+            known_bike_models: vec!["Honda CB750 Custom".to_string()],
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("Adjust it."))).await;
+
+        let body = post_invalid_chat(
+            state.clone(),
+            serde_json::json!({ "query": QUERY, "bike_model": "Bicycle 3000" }),
+        )
+        .await;
+        assert_eq!(body["error"], "Invalid field 'bike_model'");
+        assert!(body["details"].as_str().unwrap().contains("Bicycle 3000"));
+        let body = post_invalid_chat(
+            state.clone(),
+            serde_json::json!({ "query": QUERY, "bike_model": ["Yamaha R1"] }),
+        )
+        .await;
+        assert_eq!(body["error"], "Invalid field 'bike_model'");
+
+        // Built-in and configured models are accepted in any case, as is null
+        for model in [
+            serde_json::json!("yamaha r1"),
+            serde_json::json!("Honda CB750 Custom"),
+            serde_json::Value::Null,
+        ] {
+            post_chat(state.clone(), serde_json::json!({ "query": QUERY, "bike_model": model }))
+                .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_structured_response_parsed() {
+        let provider = Arc::new(MockProvider::new(
+            r#"{"answer": "Tighten the chain.", "steps": ["Loosen axle nut", "Turn adjusters"],
+                "safety_warnings": ["Engine off"], "tools_needed": ["27mm socket"]}"#,
+        ));
+        let (state, _dir) = test_state(Config::default(), provider.clone()).await;
+
+        let body = post_chat(state, serde_json::json!({ "query": QUERY, "structured": true })).await;
+
+        assert!(provider.last_options().unwrap().json_mode);
+        assert_eq!(body["response"], "Tighten the chain.");
+        assert_eq!(body["structured"]["steps"][1], "Turn adjusters");
+        assert_eq!(body["structured"]["safety_warnings"][0], "Engine off");
+        assert_eq!(body["structured"]["tools_needed"][0], "27mm socket");
+    }
+
+    #[tokio::test]
+    async fn test_structured_answer_confidence_from_cited_sources() {
+        let config = Config {
+            min_confidence: 0.0,
+            ..Config::default()
+        };
+        let answer = |sources: &str| {
+            format!(
+                r#"{{"answer": "Adjust the chain.", "steps": [
+                    {{"text": "Loosen axle nut", "sources": {}}}]}}"#,
+                sources
+            )
+        };
+        for (sources, nudged) in [("[1]", false), ("[]", true)] {
+            let provider = Arc::new(MockProvider::new(answer(sources)));
+            let (state, _dir) = test_state(config.clone(), provider).await;
+            seed(&state, vec![chunk("Yamaha R1", 12, QUERY)]).await;
+
+            let body =
+                post_chat(state, serde_json::json!({ "query": QUERY, "structured": true })).await;
+            let relevance = body["sources"][0]["relevance_score"].as_f64().unwrap();
+            let confidence = body["answer_confidence"].as_f64().unwrap();
+            assert_eq!(confidence, if nudged { 0.0 } else { relevance });
+            let warnings = body["structured"]["safety_warnings"].as_array().unwrap();
+            let warned = warnings.iter().any(|w| w == VERIFY_WITH_MECHANIC_WARNING);
+            assert_eq!(warned, nudged, "{}", sources);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verbosity_sets_prompt_and_token_cap() {
+        let provider = Arc::new(MockProvider::new("Adjust it."));
+        let config = Config {
+            max_response_tokens: 800,
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, provider.clone()).await;
+
+        let mut caps = Vec::new();
+        for verbosity in ["concise", "normal", "detailed"] {
+            post_chat(
+                state.clone(),
+                serde_json::json!({ "query": QUERY, "verbosity": verbosity }),
+            )
+            .await;
+            let system = provider.last_prompt().unwrap()[0].content.clone();
+            assert_eq!(system.contains("at most 5 short bullet points"), verbosity == "concise");
+            assert_eq!(system.contains("step-by-step procedure"), verbosity == "detailed");
+            caps.push(provider.last_options().unwrap().max_tokens.unwrap());
+        }
+
+        // Detailed is clamped to the server ceiling
+        assert_eq!(caps, vec![200, 500, 800]);
+    }
+
+    #[tokio::test]
+    async fn test_structured_response_falls_back_to_text() {
+        let provider = Arc::new(MockProvider::new("Loosen the axle nut and adjust."));
+        let (state, _dir) = test_state(Config::default(), provider).await;
+
+        let body = post_chat(state, serde_json::json!({ "query": QUERY, "structured": true })).await;
+
+        assert_eq!(body["response"], "Loosen the axle nut and adjust.");
+        assert!(body.get("structured").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_suggestions_split_from_answer_when_enabled() {
+        let config = Config {
+            suggestions_enabled: true,
+            ..Config::default()
+        };
+        let answer = "Adjust the chain slack.\n<suggestions>\nHow do I check chain tension?\n\
+            What chain lube do you recommend?\n</suggestions>";
+        let provider = Arc::new(MockProvider::new(answer));
+        let (state, _dir) = test_state(config, provider.clone()).await;
+
+        let body = post_chat(state.clone(), serde_json::json!({ "query": QUERY })).await;
+        assert_eq!(body["response"], "Adjust the chain slack.");
+        let expected = ["How do I check chain tension?", "What chain lube do you recommend?"];
+        assert_eq!(body["suggestions"], serde_json::json!(expected));
+        assert!(provider.last_prompt().unwrap()[0].content.contains(SUGGESTIONS_PROMPT));
+        // The whole completion counts toward usage, suggestions included
+        state.usage.flush().await.unwrap();
+        let now = chrono::Utc::now();
+        let (from, to) = (now - chrono::Duration::hours(1), now + chrono::Duration::hours(1));
+        let prices = crate::usage::TokenPrices::from_config(&state.config);
+        let report = state.usage.report(from, to, Default::default(), prices).await.unwrap();
+        let tokens_out = count_tokens(&state.config.openai_chat_model, answer);
+        assert_eq!(report.totals.tokens_out, tokens_out as u64);
+
+        let request = serde_json::json!({ "query": QUERY, "include_suggestions": false });
+        let body = post_chat(state.clone(), request).await;
+        assert!(body.get("suggestions").is_none());
+        assert!(!provider.last_prompt().unwrap()[0].content.contains(SUGGESTIONS_PROMPT));
+
+        // The model ignored the format
+        *provider.response.lock().unwrap() = "Adjust the chain slack.".to_string();
+        let body = post_chat(state, serde_json::json!({ "query": QUERY })).await;
+        assert_eq!(body["response"], "Adjust the chain slack.");
+        assert!(body.get("suggestions").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_chat_requests_count_in_usage() {
+        let (state, _dir) = test_state(Config::default(), Arc::new(MockProvider::new("ok"))).await;
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/chat")
+            .header("content-type", "application/json")
+            .body("{\"query\": ")
+            .reply(&create_routes(state.clone()))
+            .await;
+        assert_eq!(resp.status(), 400);
+
+        let totals = usage_totals(&state, 1).await;
+        assert_eq!(totals.requests, 1);
+        assert_eq!(totals.error_rate, 1.0);
+        assert_eq!(totals.tokens_in, 0);
+    }
+
+    /// Usage totals of the past hour, once at least `requests` records are
+    /// written (a WebSocket answer is recorded just after its last frame)
+    async fn usage_totals(state: &AppState, requests: u64) -> crate::models::UsageTotals {
+        let prices = crate::usage::TokenPrices::from_config(&state.config);
+        for _ in 0..100 {
+            state.usage.flush().await.unwrap();
+            let now = chrono::Utc::now();
+            let (from, to) = (now - chrono::Duration::hours(1), now + chrono::Duration::hours(1));
+            let report = state.usage.report(from, to, Default::default(), prices).await.unwrap();
+            if report.totals.requests >= requests {
+                return report.totals;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("fewer than {} usage records written", requests);
+    }
+
+    #[tokio::test]
+    async fn test_debug_trace_requires_admin_key() {
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            min_confidence: 0.0,
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        seed(&state, vec![chunk("Honda CBR600RR", 12, "Adjust the drive chain tension")]).await;
+        let routes = create_routes(state.clone());
+
+        let without_key: serde_json::Value = serde_json::from_slice(
+            warp::test::request()
+                .method("POST")
+                .path("/api/chat")
+                .json(&serde_json::json!({ "query": QUERY, "debug": true }))
+                .reply(&routes)
+                .await
+                .body(),
+        )
+        .unwrap();
+        assert!(without_key.get("debug").is_none());
+
+        let with_key: serde_json::Value = serde_json::from_slice(
+            warp::test::request()
+                .method("POST")
+                .path("/api/chat")
+                .header("x-admin-key", "secret")
+                .json(&serde_json::json!({ "query": QUERY, "debug": true }))
+                .reply(&routes)
+                .await
+                .body(),
+        )
+        .unwrap();
+        assert_eq!(with_key["debug"]["candidates_considered"], 1);
+        assert!(with_key["debug"]["top_score"].as_f64().unwrap() > 0.0);
+
+        let metrics = warp::test::request().path("/metrics").reply(&routes).await;
+        let text = String::from_utf8(metrics.body().to_vec()).unwrap();
+        assert!(text.contains("retrieval_embed_ms_count 2"));
+    }
+
+    #[tokio::test]
+    async fn test_parts_manual_context_asks_for_part_numbers() {
+        let config = Config {
+            min_confidence: 0.0,
+            ..Config::default()
+        };
+        let provider = Arc::new(MockProvider::new("Part 40530-MFJ-D01"));
+        let (state, _dir) = test_state(config, provider.clone()).await;
+        let mut parts = chunk("Honda CBR600RR", 88, "Drive chain adjuster bolt 40530-MFJ-D01");
+        parts.metadata.manual_type = Some("parts".to_string());
+        seed(&state, vec![parts]).await;
+
+        post_chat(state, serde_json::json!({ "query": QUERY })).await;
+
+        let prompt = provider.last_prompt().unwrap();
+        assert!(prompt[0].content.contains(crate::ai::PARTS_MANUAL_PROMPT));
+        assert!(!prompt[0].content.contains(crate::ai::OWNER_MANUAL_PROMPT));
+    }
+
+    #[tokio::test]
+    async fn test_unfiltered_without_default() {
+        let (state, _dir) = seeded_state(None).await;
+
+        let body = post_chat(state, serde_json::json!({ "query": QUERY })).await;
+
+        assert_eq!(source_models(&body).len(), 2);
+    }
+}
@@ -0,0 +1,1580 @@
+//! Manual uploads and the document endpoints: preview, URL ingestion,
+//! resume, reprocessing and metadata corrections
+
+use futures::StreamExt;
+use warp::hyper::body::Buf;
+use warp::multipart::{FormData, Part};
+use warp::{reject::Rejection, reply::Reply};
+use std::net::SocketAddr;
+
+use crate::ingestion::remote::UrlFetcher;
+use crate::ingestion::{spawn_ingestion, spawn_reprocessing, IngestError};
+use crate::models::{
+    ChunkPreview, ChunkPreviewResponse, Document, DocumentMetadataUpdate, DocumentStatus,
+    ErrorResponse, PreviewOptions, ReprocessRequest, ReprocessResponse, ResumeResponse,
+    SourceFormat, UploadOptions, UploadResponse, UrlIngestRequest,
+};
+use crate::server::errors::ApiError;
+use crate::server::routes::AppState;
+use crate::config::Config;
+use crate::pdf::PageRanges;
+
+use super::{authenticate_tenant, client_ip, TenantCredentials};
+
+/// A `file` part of an upload form
+struct UploadFile {
+    filename: String,
+    content_type: Option<String>,
+    bytes: Vec<u8>,
+}
+
+/// Fields read from an upload form
+struct UploadForm {
+    /// Each `file` part; several parts form a multi-volume set
+    files: Vec<UploadFile>,
+    /// Explicit `format` field, overriding detection from the content
+    format: Option<SourceFormat>,
+    bike_model: Option<String>,
+    year: Option<u32>,
+    manual_type: Option<String>,
+    /// Pages to index, all when `None`
+    page_ranges: Option<PageRanges>,
+    /// Chunk size and overlap overrides for this document
+    chunk_size_tokens: Option<usize>,
+    chunk_overlap_tokens: Option<usize>,
+}
+
+/// Read a part into memory, failing with 413 once it exceeds `max_bytes`
+async fn read_part(part: Part, max_bytes: usize) -> Result<Vec<u8>, ApiError> {
+    let mut data = Vec::new();
+    let mut stream = Box::pin(part.stream());
+
+    while let Some(chunk) = stream.next().await {
+        let mut chunk = chunk
+            .map_err(|e| ApiError::InvalidUpload(format!("Failed to read upload: {}", e)))?;
+
+        if data.len() + chunk.remaining() > max_bytes {
+            return Err(ApiError::FileTooLarge(max_bytes));
+        }
+        while chunk.has_remaining() {
+            let bytes = chunk.chunk();
+            data.extend_from_slice(bytes);
+            let read = bytes.len();
+            chunk.advance(read);
+        }
+    }
+
+    Ok(data)
+}
+
+/// Parse a numeric form field, failing with 400 naming the field
+fn parse_number_field<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, ApiError> {
+    value
+        .parse()
+        .map_err(|_| ApiError::InvalidUpload(format!("Invalid {} '{}'", name, value)))
+}
+
+async fn read_text_field(part: Part) -> Result<String, ApiError> {
+    let name = part.name().to_string();
+    let bytes = read_part(part, 1024).await?;
+    String::from_utf8(bytes)
+        .map(|s| s.trim().to_string())
+        .map_err(|_| ApiError::InvalidUpload(format!("Field '{}' must be UTF-8 text", name)))
+}
+
+/// Collect the file and optional metadata fields from the multipart form,
+/// holding the files to `MAX_PDF_SIZE_MB` each, `MAX_UPLOAD_VOLUMES` in number
+/// and `MAX_UPLOAD_TOTAL_MB` together
+async fn read_upload_form(mut form: FormData, config: &Config) -> Result<UploadForm, ApiError> {
+    let max_bytes = config.max_pdf_size_mb as usize * 1024 * 1024;
+    let max_total_bytes = config.max_upload_total_mb as usize * 1024 * 1024;
+    let mut total_bytes = 0;
+    let mut files = Vec::new();
+    let mut format = None;
+    let mut bike_model = None;
+    let mut year = None;
+    let mut manual_type = None;
+    let mut page_ranges = None;
+    let mut chunk_size_tokens = None;
+    let mut chunk_overlap_tokens = None;
+
+    while let Some(part) = form.next().await {
+        let part =
+            part.map_err(|e| ApiError::InvalidUpload(format!("Invalid multipart body: {}", e)))?;
+
+        match part.name() {
+            "file" => {
+                if files.len() == config.max_upload_volumes {
+                    let message =
+                        format!("An upload may have at most {} files", config.max_upload_volumes);
+                    return Err(ApiError::InvalidUpload(message));
+                }
+                let filename = part.filename().unwrap_or("manual").to_string();
+                let content_type = part.content_type().map(String::from);
+                let remaining = max_total_bytes - total_bytes;
+                let bytes = match read_part(part, max_bytes.min(remaining)).await {
+                    Err(ApiError::FileTooLarge(_)) if remaining < max_bytes => {
+                        return Err(ApiError::UploadTooLarge(max_total_bytes))
+                    }
+                    result => result?,
+                };
+                total_bytes += bytes.len();
+                files.push(UploadFile {
+                    filename,
+                    content_type,
+                    bytes,
+                });
+            }
+            "format" => {
+                let value = read_text_field(part).await?;
+                format = Some(
+                    value
+                        .parse()
+                        .map_err(|e: anyhow::Error| ApiError::InvalidUpload(e.to_string()))?,
+                );
+            }
+            "bike_model" => bike_model = Some(read_text_field(part).await?),
+            "manual_type" => manual_type = Some(read_text_field(part).await?),
+            "year" => year = Some(parse_number_field("year", &read_text_field(part).await?)?),
+            "page_ranges" => {
+                let value = read_text_field(part).await?;
+                page_ranges = Some(value.parse().map_err(|e| ApiError::page_ranges(&value, &e))?);
+            }
+            "chunk_size_tokens" => {
+                let value = read_text_field(part).await?;
+                chunk_size_tokens = Some(parse_number_field("chunk_size_tokens", &value)?);
+                if chunk_size_tokens == Some(0) {
+                    let message = "chunk_size_tokens must be at least 1".to_string();
+                    return Err(ApiError::InvalidUpload(message));
+                }
+            }
+            "chunk_overlap_tokens" => {
+                let value = read_text_field(part).await?;
+                chunk_overlap_tokens = Some(parse_number_field("chunk_overlap_tokens", &value)?);
+            }
+            other => log::debug!("Ignoring unknown upload field '{}'", other),
+        }
+    }
+
+    if files.is_empty() {
+        return Err(ApiError::MissingFile);
+    }
+
+    Ok(UploadForm {
+        files,
+        format,
+        bike_model: bike_model.filter(|m| !m.is_empty()),
+        year,
+        manual_type: manual_type.filter(|t| !t.is_empty()),
+        page_ranges,
+        chunk_size_tokens,
+        chunk_overlap_tokens,
+    })
+}
+
+/// Format of an uploaded file: the explicit one if it fits the content, else the
+/// detected one. `None` for files that cannot be ingested.
+fn upload_format(file: &UploadFile, explicit: Option<SourceFormat>) -> Option<SourceFormat> {
+    let detected = SourceFormat::detect(&file.filename, file.content_type.as_deref(), &file.bytes)?;
+    match explicit {
+        // Text formats can be read as one another, but never as a PDF
+        Some(format) if (format == SourceFormat::Pdf) == (detected == SourceFormat::Pdf) => {
+            Some(format)
+        }
+        Some(_) => None,
+        None => Some(detected),
+    }
+}
+
+/// Accept a PDF manual or a text, Markdown or HTML guide and index it in the
+/// background (extract → chunk → embed → upsert)
+#[utoipa::path(
+    post,
+    path = "/api/documents",
+    tag = "documents",
+    request_body(
+        content = String,
+        content_type = "multipart/form-data",
+        description = "`file` parts (several for a multi-volume manual), with optional \
+            `bike_model`, `year`, `manual_type`, `page_ranges` and chunking fields"
+    ),
+    params(UploadOptions),
+    security(("admin_key" = [])),
+    responses(
+        (status = 202, description = "The manual is being indexed", body = UploadResponse),
+        (status = 400, description = "No file, or an invalid form field", body = ErrorResponse),
+        (
+            status = 409,
+            description = "The same file was uploaded before (retry with `?force=true`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 413,
+            description = "A file larger than `MAX_PDF_SIZE_MB`, or files together larger than \
+                `MAX_UPLOAD_TOTAL_MB`",
+            body = ErrorResponse
+        ),
+        (
+            status = 415,
+            description = "Not a PDF, text, Markdown or HTML file",
+            body = ErrorResponse
+        ),
+        (status = 422, description = "The file cannot be read", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (status = 429, description = "Upload rate limit exceeded", body = ErrorResponse),
+    )
+)]
+pub async fn handle_upload(
+    credentials: TenantCredentials,
+    form: FormData,
+    options: UploadOptions,
+    state: AppState,
+    remote_addr: Option<SocketAddr>,
+) -> Result<impl Reply, Rejection> {
+    // Documents belong to the tenant of the API key sent along with the admin key
+    let tenant_id = authenticate_tenant(&state, &credentials)?;
+
+    let ip = client_ip(remote_addr);
+    if let Err(e) = state.upload_rate_limiter.check_and_record(ip).await {
+        log::warn!("Upload rate limit exceeded for {}: {}", ip, e);
+        return Err(ApiError::RateLimitExceeded {
+            message: e.to_string(),
+            retry_after: None,
+            rate_limit_info: None,
+        }
+        .into());
+    }
+
+    let upload = read_upload_form(form, &state.config).await?;
+    let source_format = upload_source_format(&upload)?;
+
+    Ok(queue_upload(&state, upload, source_format, tenant_id, options.force).await?)
+}
+
+/// The one format shared by all files of an upload
+fn upload_source_format(upload: &UploadForm) -> Result<SourceFormat, ApiError> {
+    let mut formats = Vec::new();
+    for file in &upload.files {
+        match upload_format(file, upload.format) {
+            Some(format) => formats.push(format),
+            None => return Err(ApiError::UnsupportedMediaType(file.filename.clone())),
+        }
+    }
+    let source_format = formats[0];
+    if formats.iter().any(|&f| f != source_format) {
+        let message = "All volumes of a document must have the same format".to_string();
+        return Err(ApiError::InvalidUpload(message));
+    }
+    Ok(source_format)
+}
+
+/// Chunks whose text a preview returns unless `previews` says otherwise
+const PREVIEW_CHUNKS: usize = 5;
+
+/// Characters of chunk text shown per previewed chunk
+const PREVIEW_CHARS: usize = 500;
+
+/// Dry run of an upload: extract and chunk the files (optionally with another
+/// chunk size and overlap) and report how they split, without embedding or
+/// storing anything
+#[utoipa::path(
+    post,
+    path = "/api/documents/preview",
+    tag = "documents",
+    request_body(
+        content = String,
+        content_type = "multipart/form-data",
+        description = "`file` parts as for an upload"
+    ),
+    params(PreviewOptions),
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "How the files would be chunked", body = ChunkPreviewResponse),
+        (status = 400, description = "No file, or invalid chunking options", body = ErrorResponse),
+        (
+            status = 413,
+            description = "A file larger than `MAX_PDF_SIZE_MB`, or files together larger than \
+                `MAX_UPLOAD_TOTAL_MB`",
+            body = ErrorResponse
+        ),
+        (status = 422, description = "The file cannot be read", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_preview_document(
+    form: FormData,
+    options: PreviewOptions,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    if options.chunk_size_tokens == Some(0) {
+        let message = "chunk_size_tokens must be at least 1".to_string();
+        return Err(ApiError::InvalidRequest(message).into());
+    }
+
+    let upload = read_upload_form(form, &state.config).await?;
+    let source_format = upload_source_format(&upload)?;
+
+    // Extraction is CPU-bound and large manuals take a while
+    let ingestor = state.ingestor.clone();
+    let (chunk_size, overlap) =
+        ingestor.chunk_settings(options.chunk_size_tokens, options.chunk_overlap_tokens);
+    let filename = upload.files[0].filename.clone();
+    let volumes: Vec<Vec<u8>> = upload.files.into_iter().map(|f| f.bytes).collect();
+    let preview = tokio::task::spawn_blocking(move || {
+        let volumes: Vec<&[u8]> = volumes.iter().map(Vec::as_slice).collect();
+        ingestor.preview(source_format, &volumes, Some(chunk_size), Some(overlap))
+    })
+    .await;
+    let (document, chunks) = match preview {
+        Ok(Ok(preview)) => preview,
+        Ok(Err(IngestError::Extraction(error))) => {
+            return Err(ApiError::Unreadable { filename, error }.into())
+        }
+        Ok(Err(e)) => return Err(ApiError::ExtractionFailed(e.to_string()).into()),
+        Err(e) => {
+            log::error!("Chunking preview panicked: {}", e);
+            return Err(ApiError::Internal("Failed to chunk document").into());
+        }
+    };
+
+    let token_counts: Vec<usize> =
+        chunks.iter().map(|c| state.ingestor.chunk_tokens(&c.text)).collect();
+    let mut sections: Vec<String> = Vec::new();
+    for section in chunks.iter().filter_map(|c| c.metadata.section.as_ref()) {
+        if !sections.contains(section) {
+            sections.push(section.clone());
+        }
+    }
+    let previews = chunks
+        .iter()
+        .zip(&token_counts)
+        .take(options.previews.unwrap_or(PREVIEW_CHUNKS))
+        .map(|(chunk, &tokens)| ChunkPreview {
+            chunk_index: chunk.metadata.chunk_index,
+            page_number: chunk.metadata.page_number,
+            section: chunk.metadata.section.clone(),
+            tokens,
+            text: chunk.text.chars().take(PREVIEW_CHARS).collect(),
+        })
+        .collect();
+
+    let response = ChunkPreviewResponse {
+        page_count: document.page_count,
+        chunk_size_tokens: chunk_size,
+        chunk_overlap_tokens: overlap,
+        chunk_count: document.chunk_count,
+        dropped_chunks: document.dropped_chunks,
+        token_counts,
+        sections,
+        stripped_lines: document.stripped_lines,
+        chunks: previews,
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+/// Reject unreadable files and duplicates (unless `force`), fill in missing
+/// metadata and start ingesting an accepted upload in the background
+async fn queue_upload(
+    state: &AppState,
+    upload: UploadForm,
+    source_format: SourceFormat,
+    tenant_id: String,
+    force: bool,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, ApiError> {
+    // Opening a large PDF is CPU-bound, so the files are checked off the
+    // async executor
+    let ingestor = state.ingestor.clone();
+    let files = upload.files;
+    let (files, checked) = run_blocking("Upload check", move || {
+        let mut page_count = 0;
+        for file in &files {
+            match ingestor.check(source_format, &file.bytes) {
+                Ok(pages) => page_count = pages,
+                Err(error) => {
+                    log::warn!("Rejected unreadable upload '{}': {}", file.filename, error);
+                    let filename = file.filename.clone();
+                    return (files, Err(ApiError::Unreadable { filename, error }));
+                }
+            }
+        }
+        (files, Ok(page_count))
+    })
+    .await?;
+    let page_count = checked?;
+    if let Some(ranges) = &upload.page_ranges {
+        // Volumes number their pages from 1 each, so a range would be ambiguous
+        if files.len() > 1 {
+            return Err(ApiError::InvalidPageRanges {
+                message: "page_ranges cannot be used with a multi-volume upload".to_string(),
+                details: None,
+            });
+        }
+        if let Err(e) = ranges.validate(page_count) {
+            return Err(ApiError::page_ranges(&ranges.to_string(), &e));
+        }
+    }
+
+    let (filenames, volumes): (Vec<_>, Vec<_>) =
+        files.into_iter().map(|f| (f.filename, f.bytes)).unzip();
+    let content_hash = Document::hash_content(&volumes);
+    let existing = state.documents.find_by_hash(&tenant_id, &content_hash);
+    if let Some(existing) = &existing {
+        if existing.status == DocumentStatus::Processing {
+            return Err(ApiError::DocumentProcessing(existing.id.clone()));
+        }
+        if !force {
+            log::info!("Rejected duplicate upload of document {}", existing.id);
+            return Err(ApiError::DuplicateDocument {
+                filename: filenames[0].clone(),
+                document_id: existing.id.clone(),
+            });
+        }
+    }
+
+    // Uploaders often omit the model; guess it from the title and front matter
+    let (volumes, detection) = match upload.bike_model {
+        Some(_) => (volumes, None),
+        None => {
+            let ingestor = state.ingestor.clone();
+            run_blocking("Model detection", move || {
+                let detection = ingestor.detect(source_format, &volumes[0]);
+                (volumes, Some(detection))
+            })
+            .await?
+        }
+    };
+    let detected = detection.as_ref();
+
+    let bike_model = upload
+        .bike_model
+        .or_else(|| detected.and_then(|d| d.bike_model.clone()))
+        .or_else(|| state.config.default_bike_model.clone())
+        .unwrap_or_else(|| "Unknown".to_string());
+    let mut document = Document::new(filenames[0].clone(), bike_model);
+    if filenames.len() > 1 {
+        document.volumes = filenames;
+    }
+    document.tenant_id = tenant_id;
+    document.content_hash = Some(content_hash);
+    document.source_format = source_format;
+    if let Some(existing) = existing {
+        // Forced replacement: reindex under the same id, stale chunks are dropped
+        log::info!("Replacing document {} with a re-upload", existing.id);
+        document.id = existing.id;
+    }
+    document.year = upload.year.or_else(|| detected.and_then(|d| d.year));
+    document.manual_type = upload.manual_type;
+    document.page_ranges = upload.page_ranges;
+    let (chunk_size, overlap) = state
+        .ingestor
+        .chunk_settings(upload.chunk_size_tokens, upload.chunk_overlap_tokens);
+    document.chunk_size_tokens = Some(chunk_size);
+    document.chunk_overlap_tokens = Some(overlap);
+
+    if let Some(detection) = detected.filter(|d| d.needs_review) {
+        log::warn!(
+            "Could not confidently detect the bike model of {} (best guess {:?}, {:.2})",
+            document.id,
+            detection.candidate,
+            detection.confidence
+        );
+    }
+
+    let response = UploadResponse {
+        document_id: document.id.clone(),
+        filename: document.filename.clone(),
+        status: "processing".to_string(),
+        message: format!("Poll /api/documents/{} for progress", document.id),
+        detection,
+    };
+    let id = document.id.clone();
+    let job = spawn_ingestion(
+        state.ingestor.clone(),
+        state.documents.clone(),
+        document,
+        volumes,
+    );
+    state.tasks.track(format!("ingestion of {}", id), job);
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        warp::http::StatusCode::ACCEPTED,
+    ))
+}
+
+/// Run CPU-bound work on an upload (parsing, detection) on the blocking
+/// thread pool
+async fn run_blocking<T: Send + 'static>(
+    what: &str,
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, ApiError> {
+    tokio::task::spawn_blocking(work).await.map_err(|e| {
+        log::error!("{} panicked: {}", what, e);
+        ApiError::Internal("Failed to read upload")
+    })
+}
+
+/// Download a PDF manual from an https URL and index it in the background like
+/// an upload. The host must resolve to public addresses only (and be in
+/// `URL_INGEST_ALLOWED_HOSTS` when that is set); `MAX_PDF_SIZE_MB` is enforced
+/// on Content-Length and while streaming.
+#[utoipa::path(
+    post,
+    path = "/api/documents/from-url",
+    tag = "documents",
+    request_body = UrlIngestRequest,
+    params(UploadOptions),
+    security(("admin_key" = [])),
+    responses(
+        (status = 202, description = "The manual is being indexed", body = UploadResponse),
+        (status = 400, description = "Not an https URL", body = ErrorResponse),
+        (
+            status = 403,
+            description = "The host is not allowed or resolves to a private address",
+            body = ErrorResponse
+        ),
+        (status = 413, description = "File larger than `MAX_PDF_SIZE_MB`", body = ErrorResponse),
+        (status = 415, description = "The URL does not serve a PDF", body = ErrorResponse),
+        (status = 502, description = "The download failed", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (status = 429, description = "Upload rate limit exceeded", body = ErrorResponse),
+    )
+)]
+pub async fn handle_upload_from_url(
+    credentials: TenantCredentials,
+    req: UrlIngestRequest,
+    options: UploadOptions,
+    state: AppState,
+    remote_addr: Option<SocketAddr>,
+) -> Result<impl Reply, Rejection> {
+    // Documents belong to the tenant of the API key sent along with the admin key
+    let tenant_id = authenticate_tenant(&state, &credentials)?;
+
+    let ip = client_ip(remote_addr);
+    if let Err(e) = state.upload_rate_limiter.check_and_record(ip).await {
+        log::warn!("Upload rate limit exceeded for {}: {}", ip, e);
+        return Err(ApiError::RateLimitExceeded {
+            message: e.to_string(),
+            retry_after: None,
+            rate_limit_info: None,
+        }
+        .into());
+    }
+
+    log::info!("Downloading manual from {} for {}", req.url, ip);
+    let fetched = match UrlFetcher::from_config(&state.config).fetch(&req.url).await {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            log::warn!("Download of {} rejected: {}", req.url, e);
+            return Err(ApiError::Download(e).into());
+        }
+    };
+
+    let upload = UploadForm {
+        files: vec![UploadFile {
+            filename: fetched.filename,
+            content_type: Some("application/pdf".to_string()),
+            bytes: fetched.bytes,
+        }],
+        format: Some(SourceFormat::Pdf),
+        bike_model: req.bike_model.map(|m| m.trim().to_string()).filter(|m| !m.is_empty()),
+        year: None,
+        manual_type: req.manual_type.map(|t| t.trim().to_string()).filter(|t| !t.is_empty()),
+        page_ranges: None,
+        chunk_size_tokens: None,
+        chunk_overlap_tokens: None,
+    };
+    Ok(queue_upload(&state, upload, SourceFormat::Pdf, tenant_id, options.force).await?)
+}
+
+/// Restart a failed ingestion, embedding only the chunks missing from the index
+#[utoipa::path(
+    post,
+    path = "/api/documents/{id}/resume",
+    tag = "documents",
+    params(("id" = String, Path, description = "Document id")),
+    security(("admin_key" = [])),
+    responses(
+        (status = 202, description = "Indexing resumed", body = ResumeResponse),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "No such document, or management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 409,
+            description = "The document is processing, completed, or cannot be resumed",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_resume_document(
+    id: String,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    let resumable = match state.ingestor.resumable(&id).await {
+        Ok(resumable) => resumable,
+        Err(e) => {
+            log::error!("Failed to load ingestion progress of {}: {:#}", id, e);
+            return Err(ApiError::Internal("Failed to load ingestion progress").into());
+        }
+    };
+
+    // The registry only knows documents of this process; after a restart the
+    // kept progress is all there is, and nothing can still be running
+    let known = state.documents.get(&id);
+    let document = known.clone().or_else(|| resumable.as_ref().map(|r| r.document.clone()));
+    let Some(mut document) = document else {
+        return Err(ApiError::DocumentNotFound(id).into());
+    };
+    if document.status == DocumentStatus::Completed {
+        return Err(ApiError::DocumentCompleted(id).into());
+    }
+    if known.is_some_and(|d| d.status == DocumentStatus::Processing) {
+        return Err(ApiError::DocumentProcessing(id).into());
+    }
+    let Some(resumable) = resumable else {
+        return Err(ApiError::NotResumable(id).into());
+    };
+
+    log::info!(
+        "Resuming ingestion of {}: {} of {} chunks remaining",
+        id,
+        resumable.chunks_remaining,
+        resumable.chunks_total
+    );
+    document.status = DocumentStatus::Processing;
+    document.error = None;
+    let response = ResumeResponse {
+        document_id: document.id.clone(),
+        status: "processing".to_string(),
+        message: format!("Poll /api/documents/{} for progress", document.id),
+        chunks_total: resumable.chunks_total,
+        chunks_remaining: resumable.chunks_remaining,
+    };
+    let job = spawn_ingestion(
+        state.ingestor.clone(),
+        state.documents.clone(),
+        document,
+        resumable.volumes,
+    );
+    state.tasks.track(format!("ingestion of {}", id), job);
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        warp::http::StatusCode::ACCEPTED,
+    ))
+}
+
+/// Chunk a document's stored page text again with the current settings, or
+/// with the chunk size, overlap and page ranges given in the optional JSON body
+#[utoipa::path(
+    post,
+    path = "/api/documents/{id}/reprocess",
+    tag = "documents",
+    request_body(
+        content = Option<ReprocessRequest>,
+        description = "Chunking settings replacing the current ones"
+    ),
+    params(("id" = String, Path, description = "Document id")),
+    security(("admin_key" = [])),
+    responses(
+        (
+            status = 202,
+            description = "The document is being chunked again",
+            body = ReprocessResponse
+        ),
+        (
+            status = 400,
+            description = "Invalid chunking options or page ranges",
+            body = ErrorResponse
+        ),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "No such document, or management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 409,
+            description = "The document is still processing or has no stored page text",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_reprocess_document(
+    id: String,
+    body: warp::hyper::body::Bytes,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    let request: ReprocessRequest = if body.iter().all(u8::is_ascii_whitespace) {
+        ReprocessRequest::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(request) => request,
+            Err(e) => {
+                let message = format!("Invalid reprocess request: {}", e);
+                return Err(ApiError::InvalidRequest(message).into());
+            }
+        }
+    };
+    if request.chunk_size_tokens == Some(0) {
+        let message = "chunk_size_tokens must be at least 1".to_string();
+        return Err(ApiError::InvalidRequest(message).into());
+    }
+
+    let stored = match state.ingestor.stored_document(&id).await {
+        Ok(stored) => stored,
+        Err(e) => {
+            log::error!("Failed to load stored page text of {}: {:#}", id, e);
+            return Err(ApiError::Internal("Failed to load stored page text").into());
+        }
+    };
+
+    // After a restart the stored record is all there is
+    let known = state.documents.get(&id);
+    let Some(mut document) = known.clone().or_else(|| stored.clone()) else {
+        return Err(ApiError::DocumentNotFound(id).into());
+    };
+    if known.is_some_and(|d| d.status == DocumentStatus::Processing) {
+        return Err(ApiError::DocumentProcessing(id).into());
+    }
+    let Some(stored) = stored else {
+        return Err(ApiError::NotReprocessable(id).into());
+    };
+
+    if let Some(value) = &request.page_ranges {
+        let ranges: PageRanges = value.parse().map_err(|e| ApiError::page_ranges(value, &e))?;
+        if !document.volumes.is_empty() {
+            return Err(ApiError::InvalidPageRanges {
+                message: "page_ranges cannot be used with a multi-volume document".to_string(),
+                details: None,
+            }
+            .into());
+        }
+        // Only the pages extracted at upload are stored
+        let checked = ranges.validate(document.page_count).and_then(|()| {
+            match &stored.page_ranges {
+                Some(extracted) => ranges.within(extracted),
+                None => Ok(()),
+            }
+        });
+        if let Err(e) = checked {
+            return Err(ApiError::page_ranges(value, &e).into());
+        }
+        document.page_ranges = Some(ranges);
+    }
+
+    let (chunk_size, overlap) = state
+        .ingestor
+        .chunk_settings(request.chunk_size_tokens, request.chunk_overlap_tokens);
+    log::info!(
+        "Reprocessing document {} with chunks of {} tokens, {} overlap",
+        id,
+        chunk_size,
+        overlap
+    );
+    document.status = DocumentStatus::Processing;
+    document.error = None;
+    let response = ReprocessResponse {
+        document_id: document.id.clone(),
+        status: "processing".to_string(),
+        message: format!("Poll /api/documents/{} for progress", document.id),
+        chunk_size_tokens: chunk_size,
+        chunk_overlap_tokens: overlap,
+        page_ranges: document.page_ranges.clone(),
+    };
+    let job = spawn_reprocessing(
+        state.ingestor.clone(),
+        state.documents.clone(),
+        document,
+        Some(chunk_size),
+        Some(overlap),
+    );
+    state.tasks.track(format!("reprocessing of {}", id), job);
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        warp::http::StatusCode::ACCEPTED,
+    ))
+}
+
+/// Ingestion status and progress of an uploaded document
+#[utoipa::path(
+    get,
+    path = "/api/documents/{id}",
+    tag = "documents",
+    params(("id" = String, Path, description = "Document id")),
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "Ingestion status and progress", body = Document),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "No such document, or management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_get_document(
+    id: String,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    match state.documents.get(&id) {
+        Some(document) => Ok(warp::reply::with_status(
+            warp::reply::json(&document),
+            warp::http::StatusCode::OK,
+        )),
+        None => Err(ApiError::DocumentNotFound(id).into()),
+    }
+}
+
+/// Correct a document's bike model, year or manual type, on the record and
+/// on every stored chunk
+#[utoipa::path(
+    patch,
+    path = "/api/documents/{id}",
+    tag = "documents",
+    request_body = DocumentMetadataUpdate,
+    params(("id" = String, Path, description = "Document id")),
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "The corrected document", body = Document),
+        (
+            status = 400,
+            description = "Nothing to change, or an invalid value",
+            body = ErrorResponse
+        ),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "No such document, or management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (status = 409, description = "The document is still processing", body = ErrorResponse),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_update_document(
+    id: String,
+    update: DocumentMetadataUpdate,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    if update.is_empty() || update.bike_model.as_deref().is_some_and(|m| m.trim().is_empty()) {
+        let message = "Provide a non-empty bike_model, year or manual_type".to_string();
+        return Err(ApiError::InvalidUpdate(message).into());
+    }
+
+    let Some(mut document) = state.documents.get(&id) else {
+        return Err(ApiError::DocumentNotFound(id).into());
+    };
+
+    // The ingestion job would stamp the old metadata on chunks it stores later
+    if document.status == DocumentStatus::Processing {
+        return Err(ApiError::DocumentProcessing(id).into());
+    }
+
+    update.apply(&mut document);
+    if let Err(e) = state.ingestor.update_metadata(&document).await {
+        log::error!("Failed to update chunks of document {}: {:#}", id, e);
+        return Err(ApiError::Internal("Failed to update stored chunks").into());
+    }
+    state.documents.update(&document);
+    log::info!(
+        "Updated metadata of document {}: {} ({:?})",
+        id,
+        document.bike_model,
+        document.year
+    );
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&document),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::MockProvider;
+    use crate::rag::to_sources;
+    use crate::server::create_routes;
+    use crate::server::test_support::{tenant_state, test_state};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_upload_stamped_with_tenant_of_api_key() {
+        let (state, _dir) = tenant_state().await;
+        let pdf = fixture("manual.pdf");
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/documents")
+            .header("x-admin-key", "secret")
+            .header("authorization", "Bearer key-b")
+            .header(
+                "content-type",
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .body(multipart(&[("file", Some("r1.pdf"), &pdf)]))
+            .reply(&create_routes(state.clone()))
+            .await;
+        assert_eq!(resp.status(), 202);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        let document = wait_for_document(state.clone(), body["document_id"].as_str().unwrap()).await;
+
+        assert_eq!(document["tenant_id"], "dealer-b");
+        let counts = state.retriever.vector_store().count_by_tenant().await;
+        assert!(counts["dealer-b"] > 1);
+        assert_eq!(counts["dealer-a"], 1);
+    }
+
+    const BOUNDARY: &str = "manual-upload-boundary";
+
+    /// Encode (name, filename, content) parts as a multipart/form-data body
+    fn multipart(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, filename, content) in parts {
+            body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+            match filename {
+                Some(filename) => body.extend_from_slice(
+                    format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+                         Content-Type: {}\r\n\r\n",
+                        name,
+                        filename,
+                        if filename.ends_with(".pdf") {
+                            "application/pdf"
+                        } else {
+                            "application/octet-stream"
+                        }
+                    )
+                    .as_bytes(),
+                ),
+                None => body.extend_from_slice(
+                    format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name)
+                        .as_bytes(),
+                ),
+            }
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    async fn upload(
+        state: AppState,
+        key: Option<&str>,
+        parts: &[(&str, Option<&str>, &[u8])],
+    ) -> (u16, serde_json::Value) {
+        upload_to(state, "/api/documents", key, parts).await
+    }
+
+    async fn upload_to(
+        state: AppState,
+        path: &str,
+        key: Option<&str>,
+        parts: &[(&str, Option<&str>, &[u8])],
+    ) -> (u16, serde_json::Value) {
+        let mut request = warp::test::request()
+            .method("POST")
+            .path(path)
+            .header(
+                "content-type",
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .body(multipart(parts));
+        if let Some(key) = key {
+            request = request.header("x-admin-key", key);
+        }
+        let resp = request.reply(&create_routes(state)).await;
+        (resp.status().as_u16(), serde_json::from_slice(resp.body()).unwrap())
+    }
+
+    async fn upload_state(max_pdf_size_mb: u64) -> (AppState, tempfile::TempDir) {
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            max_pdf_size_mb,
+            max_uploads_per_minute: 10,
+            ..Config::default()
+        };
+        test_state(config, Arc::new(MockProvider::new("ok"))).await
+    }
+
+    /// Poll the status endpoint until ingestion finishes
+    async fn wait_for_document(state: AppState, id: &str) -> serde_json::Value {
+        let routes = create_routes(state);
+        for _ in 0..200 {
+            let resp = warp::test::request()
+                .method("GET")
+                .path(&format!("/api/documents/{}", id))
+                .header("x-admin-key", "secret")
+                .reply(&routes)
+                .await;
+            assert_eq!(resp.status(), 200);
+            let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+            if body["status"] != "processing" {
+                return body;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("document {} still processing", id);
+    }
+
+    async fn resume(state: AppState, id: &str) -> (u16, serde_json::Value) {
+        let resp = warp::test::request()
+            .method("POST")
+            .path(&format!("/api/documents/{}/resume", id))
+            .header("x-admin-key", "secret")
+            .reply(&create_routes(state))
+            .await;
+        (resp.status().as_u16(), serde_json::from_slice(resp.body()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_failed_ingestion_resumes_with_remaining_chunks_only() {
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            chunk_size_tokens: 30,
+            chunk_overlap_tokens: 0,
+            ..Config::default()
+        };
+        // Enough chunks for two embedding batches; the second one fails
+        let provider = Arc::new(MockProvider::new("ok").with_failing_embeddings("Coolant"));
+        let (state, _dir) = test_state(config, provider.clone()).await;
+        let mut guide = String::new();
+        for step in 1..=250 {
+            guide.push_str(&format!("Step {}: check fastener {} and torque it.\n\n", step, step));
+        }
+        guide.push_str("Coolant: refill with a 50/50 mix.\n");
+
+        let (status, body) = upload(
+            state.clone(),
+            Some("secret"),
+            &[("bike_model", None, b"Yamaha R1"), ("file", Some("r1.md"), guide.as_bytes())],
+        )
+        .await;
+        assert_eq!(status, 202);
+        let id = body["document_id"].as_str().unwrap().to_string();
+        let failed = wait_for_document(state.clone(), &id).await;
+        assert_eq!(failed["status"], "failed");
+        let total = failed["chunk_count"].as_u64().unwrap() as usize;
+        let embedded = failed["chunks_embedded"].as_u64().unwrap() as usize;
+        assert!(embedded > 0 && embedded < total, "{} of {}", embedded, total);
+
+        // The quota comes back; only the missing chunks are embedded again
+        *provider.fail_embeddings_containing.lock().unwrap() = None;
+        let before = provider.embedded_texts.lock().unwrap().len();
+        let (status, body) = resume(state.clone(), &id).await;
+        assert_eq!(status, 202, "{}", body);
+        assert_eq!(body["chunks_total"], total);
+        assert_eq!(body["chunks_remaining"], total - embedded);
+
+        let done = wait_for_document(state.clone(), &id).await;
+        assert_eq!(done["status"], "completed");
+        assert_eq!(done["chunks_embedded"], total);
+        assert_eq!(provider.embedded_texts.lock().unwrap().len() - before, total - embedded);
+        assert_eq!(state.retriever.vector_store().count().await, total);
+
+        let (status, body) = resume(state, &id).await;
+        assert_eq!(status, 409);
+        assert_eq!(body["code"], "DOCUMENT_COMPLETED");
+    }
+
+    async fn reprocess(state: AppState, id: &str, body: &str) -> (u16, serde_json::Value) {
+        let resp = warp::test::request()
+            .method("POST")
+            .path(&format!("/api/documents/{}/reprocess", id))
+            .header("x-admin-key", "secret")
+            .body(body)
+            .reply(&create_routes(state))
+            .await;
+        (resp.status().as_u16(), serde_json::from_slice(resp.body()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_reprocess_rechunks_stored_pages_with_overrides() {
+        let (state, _dir) = upload_state(50).await;
+        let mut guide = String::new();
+        for step in 1..=40 {
+            guide.push_str(&format!("Step {}: check fastener {} and torque it.\n\n", step, step));
+        }
+        let (status, body) = upload(
+            state.clone(),
+            Some("secret"),
+            &[("bike_model", None, b"Yamaha R1"), ("file", Some("r1.md"), guide.as_bytes())],
+        )
+        .await;
+        assert_eq!(status, 202);
+        let id = body["document_id"].as_str().unwrap().to_string();
+        let first = wait_for_document(state.clone(), &id).await;
+        assert_eq!(first["status"], "completed");
+        let old_ids: Vec<String> =
+            state.retriever.vector_store().scroll().await.into_iter().map(|c| c.id).collect();
+
+        let (status, body) = reprocess(state.clone(), &id, r#"{"chunk_size_tokens": 30}"#).await;
+        assert_eq!(status, 202, "{}", body);
+        assert_eq!(body["chunk_size_tokens"], 30);
+        assert_eq!(body["chunk_overlap_tokens"], 15);
+
+        let done = wait_for_document(state.clone(), &id).await;
+        assert_eq!(done["status"], "completed");
+        let total = done["chunk_count"].as_u64().unwrap() as usize;
+        assert!(total > first["chunk_count"].as_u64().unwrap() as usize);
+        assert_eq!(done["chunks_embedded"], total);
+        let chunks = state.retriever.vector_store().scroll().await;
+        assert_eq!(chunks.len(), total);
+        assert!(chunks.iter().all(|c| !old_ids.contains(&c.id) && c.document_id == id));
+
+        let (status, body) = reprocess(state.clone(), &id, r#"{"chunk_size": 30}"#).await;
+        assert_eq!(status, 400);
+        assert_eq!(body["code"], "INVALID_REQUEST");
+        let (status, body) = reprocess(state, "missing", "").await;
+        assert_eq!(status, 404);
+        assert_eq!(body["code"], "DOCUMENT_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_upload_indexes_manual_in_background() {
+        let (state, _dir) = upload_state(50).await;
+        let pdf = fixture("manual.pdf");
+
+        let (status, body) = upload(
+            state.clone(),
+            Some("secret"),
+            &[
+                ("bike_model", None, b"Yamaha R1"),
+                ("year", None, b"2019"),
+                ("file", Some("r1.pdf"), &pdf),
+            ],
+        )
+        .await;
+
+        assert_eq!(status, 202);
+        assert_eq!(body["filename"], "r1.pdf");
+        assert_eq!(body["status"], "processing");
+        let id = body["document_id"].as_str().unwrap();
+
+        let document = wait_for_document(state.clone(), id).await;
+        assert_eq!(document["status"], "completed");
+        assert_eq!(document["pages_processed"], 3);
+        assert_eq!(document["chunks_embedded"], document["chunk_count"]);
+
+        let chunks = state.retriever.vector_store().scroll().await;
+        assert!(!chunks.is_empty());
+        assert!(chunks.iter().all(|c| c.document_id == id));
+        assert!(chunks.iter().all(|c| c.metadata.bike_model == "r1"));
+        assert_eq!(chunks[0].metadata.year, Some(2019));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_document_is_404() {
+        let (state, _dir) = upload_state(50).await;
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/api/documents/nope")
+            .header("x-admin-key", "secret")
+            .reply(&create_routes(state))
+            .await;
+
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_preview_chunks_without_indexing() {
+        let (state, _dir) = upload_state(50).await;
+        let pdf = fixture("footer.pdf");
+
+        let (status, body) = upload_to(
+            state.clone(),
+            "/api/documents/preview?chunk_size_tokens=40&chunk_overlap_tokens=5&previews=2",
+            Some("secret"),
+            &[("file", Some("r1.pdf"), &pdf)],
+        )
+        .await;
+
+        assert_eq!(status, 200, "{}", body);
+        assert_eq!(body["chunk_size_tokens"], 40);
+        assert_eq!(body["chunk_overlap_tokens"], 5);
+        let chunk_count = body["chunk_count"].as_u64().unwrap() as usize;
+        assert!(chunk_count > 1);
+        let token_counts = body["token_counts"].as_array().unwrap();
+        assert_eq!(token_counts.len(), chunk_count);
+        assert!(token_counts.iter().all(|t| (1..=40).contains(&t.as_u64().unwrap())));
+        let previews = body["chunks"].as_array().unwrap();
+        assert_eq!(previews.len(), 2);
+        assert!(previews.iter().all(|c| !c["text"].as_str().unwrap().is_empty()));
+        assert_eq!(previews[0]["chunk_index"], 0);
+        assert!(!body["stripped_lines"].as_array().unwrap().is_empty());
+
+        assert!(state.retriever.vector_store().scroll().await.is_empty());
+
+        let (status, _) =
+            upload_to(state, "/api/documents/preview", None, &[("file", Some("r1.pdf"), &pdf)])
+                .await;
+        assert_eq!(status, 401);
+    }
+
+    #[tokio::test]
+    async fn test_upload_requires_admin_key() {
+        let (state, _dir) = upload_state(50).await;
+        let pdf = fixture("manual.pdf");
+
+        let (status, _) = upload(state, None, &[("file", Some("r1.pdf"), &pdf)]).await;
+
+        assert_eq!(status, 401);
+    }
+
+    async fn upload_from_url(
+        state: AppState,
+        key: Option<&str>,
+        url: &str,
+    ) -> (u16, serde_json::Value) {
+        let mut request = warp::test::request()
+            .method("POST")
+            .path("/api/documents/from-url")
+            .json(&serde_json::json!({ "url": url, "bike_model": "Yamaha R1" }));
+        if let Some(key) = key {
+            request = request.header("x-admin-key", key);
+        }
+        let resp = request.reply(&create_routes(state)).await;
+        (resp.status().as_u16(), serde_json::from_slice(resp.body()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_upload_from_url_rejects_unsafe_urls() {
+        let (state, _dir) = upload_state(50).await;
+
+        let (status, _) = upload_from_url(state.clone(), None, "https://example.com/r1.pdf").await;
+        assert_eq!(status, 401);
+
+        for (url, expected) in [
+            ("http://example.com/r1.pdf", (400, "INVALID_URL")),
+            ("https://127.0.0.1/r1.pdf", (403, "URL_NOT_ALLOWED")),
+            ("https://169.254.169.254/latest/meta-data", (403, "URL_NOT_ALLOWED")),
+            ("https://localhost/r1.pdf", (403, "URL_NOT_ALLOWED")),
+        ] {
+            let (status, body) = upload_from_url(state.clone(), Some("secret"), url).await;
+            assert_eq!((status, body["code"].as_str().unwrap()), expected, "{}", url);
+        }
+
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            url_ingest_allowed_hosts: vec!["manuals.example.com".to_string()],
+            ..Config::default()
+        };
+        let (restricted, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let (status, body) =
+            upload_from_url(restricted, Some("secret"), "https://example.org/r1.pdf").await;
+        assert_eq!((status, body["code"].as_str()), (403, Some("URL_NOT_ALLOWED")));
+    }
+
+    #[tokio::test]
+    async fn test_upload_error_codes() {
+        let (state, _dir) = upload_state(50).await;
+        let not_pdf: &[u8] = b"GIF89a not a manual";
+        let malformed = fixture("malformed.pdf");
+        let encrypted = fixture("encrypted.pdf");
+
+        let (status, body) =
+            upload(state.clone(), Some("secret"), &[("file", Some("a.pdf"), not_pdf)]).await;
+        assert_eq!((status, body["code"].as_str()), (415, Some("UNSUPPORTED_MEDIA_TYPE")));
+
+        let (status, body) =
+            upload(state.clone(), Some("secret"), &[("file", Some("b.pdf"), &malformed)]).await;
+        assert_eq!((status, body["code"].as_str()), (422, Some("PDF_MALFORMED")));
+        assert!(body["error"].as_str().unwrap().contains("malformed PDF"), "{}", body);
+
+        let (status, body) =
+            upload(state.clone(), Some("secret"), &[("file", Some("e.pdf"), &encrypted)]).await;
+        assert_eq!((status, body["code"].as_str()), (422, Some("PDF_ENCRYPTED")));
+
+        let (small, _dir) = upload_state(0).await;
+        let pdf = fixture("manual.pdf");
+        let (status, body) = upload(small, Some("secret"), &[("file", Some("c.pdf"), &pdf)]).await;
+        assert_eq!((status, body["code"].as_str()), (413, Some("FILE_TOO_LARGE")));
+    }
+
+    #[tokio::test]
+    async fn test_upload_accepts_text_formats_and_rejects_binaries() {
+        let (state, _dir) = upload_state(50).await;
+        let markdown: &[u8] = b"# Chain Care\n\nKeep the chain clean and lubricated.";
+        let html: &[u8] = b"<h1>Brakes</h1><p>Bleed the brakes yearly.</p>";
+
+        let (status, body) =
+            upload(state.clone(), Some("secret"), &[("file", Some("chain.md"), markdown)]).await;
+        assert_eq!(status, 202);
+        let id = body["document_id"].as_str().unwrap();
+        let document = wait_for_document(state.clone(), id).await;
+        assert_eq!(document["status"], "completed");
+        assert_eq!(document["source_format"], "markdown");
+
+        // An explicit format wins over the extension
+        let parts = [("format", None, b"html".as_slice()), ("file", Some("guide.txt"), html)];
+        let (status, body) = upload(state.clone(), Some("secret"), &parts).await;
+        assert_eq!(status, 202);
+        let id = body["document_id"].as_str().unwrap();
+        let document = wait_for_document(state.clone(), id).await;
+        assert_eq!(document["source_format"], "html");
+
+        let png: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        let (status, body) =
+            upload(state.clone(), Some("secret"), &[("file", Some("photo.md"), png)]).await;
+        assert_eq!((status, body["code"].as_str()), (415, Some("UNSUPPORTED_MEDIA_TYPE")));
+
+        let parts = [("format", None, b"pdf".as_slice()), ("file", Some("a.txt"), markdown)];
+        let (status, _) = upload(state, Some("secret"), &parts).await;
+        assert_eq!(status, 415);
+    }
+
+    async fn patch_document(
+        state: AppState,
+        id: &str,
+        body: serde_json::Value,
+    ) -> (u16, serde_json::Value) {
+        let resp = warp::test::request()
+            .method("PATCH")
+            .path(&format!("/api/documents/{}", id))
+            .header("x-admin-key", "secret")
+            .json(&body)
+            .reply(&create_routes(state))
+            .await;
+        (resp.status().as_u16(), serde_json::from_slice(resp.body()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_upload_detects_model_and_year_when_omitted() {
+        let (state, _dir) = upload_state(50).await;
+        let pdf = fixture("titled.pdf");
+
+        let (status, body) =
+            upload(state.clone(), Some("secret"), &[("file", Some("r1.pdf"), &pdf)]).await;
+
+        assert_eq!(status, 202);
+        assert_eq!(body["detection"]["bike_model"], "Yamaha R1");
+        assert_eq!(body["detection"]["year"], 2015);
+        assert_eq!(body["detection"]["needs_review"], false);
+        let document = wait_for_document(state.clone(), body["document_id"].as_str().unwrap()).await;
+        assert_eq!(document["bike_model"], "Yamaha R1");
+
+        let chunks = state.retriever.vector_store().scroll().await;
+        assert!(!chunks.is_empty());
+        assert!(chunks
+            .iter()
+            .all(|c| c.metadata.bike_model == "r1" && c.metadata.year == Some(2015)));
+    }
+
+    #[tokio::test]
+    async fn test_undetected_model_can_be_corrected() {
+        let (state, _dir) = upload_state(50).await;
+        let pdf = fixture("manual.pdf");
+
+        let (_, body) =
+            upload(state.clone(), Some("secret"), &[("file", Some("m.pdf"), &pdf)]).await;
+        assert_eq!(body["detection"]["needs_review"], true);
+        let id = body["document_id"].as_str().unwrap();
+        let document = wait_for_document(state.clone(), id).await;
+        assert_eq!(document["bike_model"], "Unknown");
+
+        let (status, document) = patch_document(
+            state.clone(),
+            id,
+            serde_json::json!({ "bike_model": "Honda CBR600RR", "year": 2008 }),
+        )
+        .await;
+
+        assert_eq!(status, 200);
+        assert_eq!(document["bike_model"], "Honda CBR600RR");
+        assert_eq!(wait_for_document(state.clone(), id).await["year"], 2008);
+        let chunks = state.retriever.vector_store().scroll().await;
+        assert!(chunks
+            .iter()
+            .all(|c| c.metadata.bike_model == "cbr600rr" && c.metadata.year == Some(2008)));
+    }
+
+    #[tokio::test]
+    async fn test_patch_document_errors() {
+        let (state, _dir) = upload_state(50).await;
+        let mut processing = Document::new("r1.pdf", "Unknown");
+        processing.status = DocumentStatus::Processing;
+        state.documents.update(&processing);
+
+        let model = serde_json::json!({ "bike_model": "Yamaha R1" });
+        let (status, _) = patch_document(state.clone(), "nope", model.clone()).await;
+        assert_eq!(status, 404);
+        let (status, body) = patch_document(state.clone(), &processing.id, model).await;
+        assert_eq!((status, body["code"].as_str()), (409, Some("DOCUMENT_PROCESSING")));
+        let (status, _) =
+            patch_document(state, &processing.id, serde_json::json!({ "bike_model": " " })).await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_upload_rejected_unless_forced() {
+        let (state, _dir) = upload_state(50).await;
+        let pdf = fixture("manual.pdf");
+        let parts: &[(&str, Option<&str>, &[u8])] =
+            &[("bike_model", None, b"Yamaha R1"), ("file", Some("r1.pdf"), &pdf)];
+
+        let (_, first) = upload(state.clone(), Some("secret"), parts).await;
+        let id = first["document_id"].as_str().unwrap();
+        wait_for_document(state.clone(), id).await;
+        let original = state.retriever.vector_store().ids().await;
+
+        let (status, body) = upload(state.clone(), Some("secret"), parts).await;
+        assert_eq!((status, body["code"].as_str()), (409, Some("DUPLICATE_DOCUMENT")));
+        assert_eq!(body["details"], id);
+
+        let (status, body) =
+            upload_to(state.clone(), "/api/documents?force=true", Some("secret"), parts).await;
+        assert_eq!(status, 202);
+        assert_eq!(body["document_id"], id);
+        let document = wait_for_document(state.clone(), id).await;
+        assert_eq!(document["status"], "completed");
+
+        // The old chunks were replaced, not duplicated
+        let replaced = state.retriever.vector_store().ids().await;
+        assert_eq!(replaced.len(), original.len());
+        assert!(replaced.is_disjoint(&original));
+    }
+
+    #[tokio::test]
+    async fn test_different_files_are_not_duplicates() {
+        let (state, _dir) = upload_state(50).await;
+
+        for name in ["manual.pdf", "titled.pdf"] {
+            let pdf = fixture(name);
+            let (status, body) =
+                upload(state.clone(), Some("secret"), &[("file", Some(name), &pdf)]).await;
+            assert_eq!(status, 202);
+            wait_for_document(state.clone(), body["document_id"].as_str().unwrap()).await;
+        }
+
+        let documents: std::collections::HashSet<_> = state
+            .retriever
+            .vector_store()
+            .scroll()
+            .await
+            .into_iter()
+            .map(|c| c.document_id)
+            .collect();
+        assert_eq!(documents.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_upload_multi_volume_manual_set() {
+        let (state, _dir) = upload_state(50).await;
+        let (engine, frame) = (fixture("manual.pdf"), fixture("titled.pdf"));
+
+        let (status, body) = upload(
+            state.clone(),
+            Some("secret"),
+            &[
+                ("bike_model", None, b"Yamaha R1"),
+                ("file", Some("engine.pdf"), &engine),
+                ("file", Some("frame.pdf"), &frame),
+            ],
+        )
+        .await;
+
+        assert_eq!(status, 202);
+        let document = wait_for_document(state.clone(), body["document_id"].as_str().unwrap()).await;
+        assert_eq!(document["status"], "completed");
+        assert_eq!(document["page_count"], 5);
+        assert_eq!(document["volumes"], serde_json::json!(["engine.pdf", "frame.pdf"]));
+
+        let (results, _) = state
+            .retriever
+            .retrieve("chain lubricant", "default", None)
+            .await
+            .unwrap();
+        let sources = to_sources(&results, None);
+        assert!(!sources.is_empty());
+        assert!(sources.iter().all(|s| s.volume.is_some()));
+    }
+
+    #[tokio::test]
+    async fn test_upload_limits_volume_count_and_total_size() {
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            max_pdf_size_mb: 1,
+            max_upload_volumes: 2,
+            max_upload_total_mb: 1,
+            max_uploads_per_minute: 10,
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let volume = "Keep the chain clean and lubricated.\n".repeat(16 * 1024);
+        let volume = volume.as_bytes();
+        assert!(volume.len() < 1024 * 1024 && volume.len() * 2 > 1024 * 1024);
+
+        let small: &[u8] = b"# Chain Care\n\nKeep the chain clean and lubricated.";
+        let three = [
+            ("file", Some("a.md"), small),
+            ("file", Some("b.md"), small),
+            ("file", Some("c.md"), small),
+        ];
+        let (status, body) = upload(state.clone(), Some("secret"), &three).await;
+        assert_eq!((status, body["code"].as_str()), (400, Some("INVALID_UPLOAD")));
+        assert!(body["error"].as_str().unwrap().contains("at most 2 files"), "{}", body);
+
+        // Each volume is within MAX_PDF_SIZE_MB, both together are not
+        let two = [("file", Some("a.md"), volume), ("file", Some("b.md"), volume)];
+        let (status, body) = upload(state.clone(), Some("secret"), &two).await;
+        assert_eq!((status, body["code"].as_str()), (413, Some("UPLOAD_TOO_LARGE")));
+        let preview = "/api/documents/preview";
+        let (status, body) = upload_to(state.clone(), preview, Some("secret"), &two).await;
+        assert_eq!((status, body["code"].as_str()), (413, Some("UPLOAD_TOO_LARGE")));
+
+        let (status, body) = upload(state, Some("secret"), &two[..1]).await;
+        assert_eq!(status, 202, "{}", body);
+    }
+}
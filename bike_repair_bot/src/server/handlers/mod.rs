@@ -0,0 +1,1792 @@
+use warp::{reject::Rejection, reply::Reply};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::models::{
+    BikeModels, ErrorResponse, Feedback, FeedbackFilter, FeedbackRequest, RateLimitInfo,
+    SearchHit, SearchRequest, SearchResponse, SessionHistory, ValidateRequest,
+    ValidateResponse, MAX_SEARCH_TOP_K, SEARCH_PREVIEW_CHARS,
+};
+use crate::server::errors::ApiError;
+use crate::server::routes::AppState;
+use crate::config::Config;
+use crate::security::{keys_match, normalize_query, QueryValidation};
+
+mod admin;
+mod chat;
+mod documents;
+
+pub use admin::*;
+pub use chat::*;
+pub use documents::*;
+
+/// Health check handler
+#[utoipa::path(
+    get,
+    path = "/api/health",
+    tag = "health",
+    responses(
+        (
+            status = 200,
+            description = "The service is up; `status` is `maintenance` while chat is switched off",
+            body = Object
+        ),
+    )
+)]
+pub async fn handle_health(state: AppState) -> Result<impl Reply, Rejection> {
+    let status = if state.maintenance.is_enabled() { "maintenance" } else { "healthy" };
+    Ok(warp::reply::json(&serde_json::json!({
+        "status": status,
+        "service": state.config.service_name(),
+        "version": env!("CARGO_PKG_VERSION"),
+    })))
+}
+
+/// Readiness handler: 200 while the vector store and OpenAI answer (an open
+/// circuit breaker only degrades it), 503 once either is down
+#[utoipa::path(
+    get,
+    path = "/api/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "The vector store and OpenAI answer", body = Object),
+        (status = 503, description = "A dependency is down", body = Object),
+    )
+)]
+pub async fn handle_readiness(state: AppState) -> Result<impl Reply, Rejection> {
+    let report = state
+        .readiness
+        .check(
+            state.ai_provider.as_ref(),
+            state.retriever.vector_store(),
+            state.circuit_breaker.get_state().await,
+        )
+        .await;
+    let status = if report.is_ready() {
+        warp::http::StatusCode::OK
+    } else {
+        log::warn!("Readiness check failed: {:?}", report.dependencies);
+        warp::http::StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&report), status))
+}
+
+/// Header reporting how long the server spent on a request
+pub const RESPONSE_TIME_HEADER: &str = "X-Response-Time-Ms";
+
+/// Response header with the request's id
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+fn elapsed_ms(started: Instant) -> u128 {
+    started.elapsed().as_millis()
+}
+
+/// Add the caller's remaining rate limit to a chat reply, so clients can
+/// throttle themselves
+pub fn with_rate_limit_headers(reply: impl Reply, info: &RateLimitInfo) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    let headers = response.headers_mut();
+    headers.insert("X-RateLimit-Remaining-Minute", info.remaining_minute.into());
+    headers.insert("X-RateLimit-Remaining-Hour", info.remaining_hour.into());
+    headers.insert("X-RateLimit-Reset", info.reset_in_seconds.into());
+    response
+}
+
+/// Message history of a session of the caller's tenant
+#[utoipa::path(
+    get,
+    path = "/api/sessions/{id}",
+    tag = "sessions",
+    params(("id" = String, Path, description = "Session id")),
+    security((), ("tenant_key" = [])),
+    responses(
+        (status = 200, description = "Messages of the session", body = SessionHistory),
+        (status = 401, description = "Unknown tenant key", body = ErrorResponse),
+        (
+            status = 404,
+            description = "No such session in the caller's tenant",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_get_session(
+    id: String,
+    credentials: TenantCredentials,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    let tenant_id = authenticate_tenant(&state, &credentials)?;
+
+    match state.sessions.get(&tenant_id, &id).await {
+        Ok(Some(session)) => Ok(warp::reply::with_status(
+            warp::reply::json(&SessionHistory {
+                session_id: id,
+                bike_model: session.bike.as_ref().map(|bike| bike.model.clone()),
+                bike_year: session.bike.and_then(|bike| bike.year),
+                messages: session.messages,
+                created_at: session.created_at,
+                last_active: session.last_active,
+            }),
+            warp::http::StatusCode::OK,
+        )),
+        Ok(None) => Err(ApiError::SessionNotFound(id).into()),
+        Err(e) => {
+            log::error!("Failed to load session {}: {:#}", id, e);
+            Err(ApiError::Internal("Failed to load session").into())
+        }
+    }
+}
+
+/// Forget a session of the caller's tenant; deleting an unknown session also
+/// succeeds
+#[utoipa::path(
+    delete,
+    path = "/api/sessions/{id}",
+    tag = "sessions",
+    params(("id" = String, Path, description = "Session id")),
+    security((), ("tenant_key" = [])),
+    responses(
+        (status = 204, description = "The session is forgotten"),
+        (status = 401, description = "Unknown tenant key", body = ErrorResponse),
+    )
+)]
+pub async fn handle_delete_session(
+    id: String,
+    credentials: TenantCredentials,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    let tenant_id = authenticate_tenant(&state, &credentials)?;
+
+    match state.sessions.delete(&tenant_id, &id).await {
+        Ok(existed) => {
+            if existed {
+                log::info!("Deleted session {} ({})", id, tenant_id);
+            }
+            Ok(warp::reply::with_status(warp::reply(), warp::http::StatusCode::NO_CONTENT))
+        }
+        Err(e) => {
+            log::error!("Failed to delete session {}: {:#}", id, e);
+            Err(ApiError::Internal("Failed to delete session").into())
+        }
+    }
+}
+
+/// Active sessions of all tenants with their message counts and last activity
+#[utoipa::path(
+    get,
+    path = "/api/admin/sessions",
+    tag = "admin",
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "Active sessions of all tenants", body = Object),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_list_sessions(
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    match state.sessions.list().await {
+        Ok(sessions) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "total": sessions.len(),
+                "sessions": sessions,
+            })),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => {
+            log::error!("Failed to list sessions: {:#}", e);
+            Err(ApiError::Internal("Failed to list sessions").into())
+        }
+    }
+}
+
+/// Rate an answer of one of the caller's sessions. The rating is stored with
+/// the query, the answer and the chunks it was based on.
+#[utoipa::path(
+    post,
+    path = "/api/feedback",
+    tag = "sessions",
+    request_body = FeedbackRequest,
+    security((), ("tenant_key" = [])),
+    responses(
+        (status = 201, description = "The rating was stored", body = Feedback),
+        (
+            status = 400,
+            description = "The request names no answer, or the comment is too long",
+            body = ErrorResponse
+        ),
+        (status = 401, description = "Unknown tenant key", body = ErrorResponse),
+        (status = 404, description = "No such session or answer", body = ErrorResponse),
+        (
+            status = 413,
+            description = "Body larger than `MAX_CHAT_BODY_BYTES`",
+            body = ErrorResponse
+        ),
+        (status = 429, description = "Feedback rate limit exceeded", body = ErrorResponse),
+    )
+)]
+pub async fn handle_feedback(
+    req: FeedbackRequest,
+    state: AppState,
+    remote_addr: Option<SocketAddr>,
+    credentials: TenantCredentials,
+) -> Result<impl Reply, Rejection> {
+    let tenant_id = authenticate_tenant(&state, &credentials)?;
+
+    let ip = client_ip(remote_addr);
+    if let Err(e) = state.feedback_rate_limiter.check_and_record(ip).await {
+        log::warn!("Feedback rate limit exceeded for {}: {}", ip, e);
+        return Err(ApiError::RateLimitExceeded {
+            message: e.to_string(),
+            retry_after: None,
+            rate_limit_info: None,
+        }
+        .into());
+    }
+
+    if let Err(msg) = req.validate() {
+        return Err(ApiError::InvalidFeedback(msg).into());
+    }
+
+    let session = match state.sessions.get(&tenant_id, &req.session_id).await {
+        Ok(Some(session)) => session,
+        Ok(None) => return Err(ApiError::SessionNotFound(req.session_id.clone()).into()),
+        Err(e) => {
+            log::error!("Failed to load session {}: {:#}", req.session_id, e);
+            return Err(ApiError::Internal("Failed to load session").into());
+        }
+    };
+
+    let Some((query, answer)) = req.locate(&session.messages) else {
+        return Err(ApiError::ResponseNotFound.into());
+    };
+
+    let feedback = Feedback {
+        tenant_id,
+        session_id: req.session_id.clone(),
+        response_id: answer.response_id.clone(),
+        rating: req.rating,
+        comment: req.comment.clone(),
+        query: query.map(|m| m.content.clone()),
+        response: answer.content.clone(),
+        source_ids: answer.source_ids.clone(),
+        created_at: chrono::Utc::now(),
+    };
+
+    match state.feedback.record(feedback.clone()).await {
+        Ok(()) => {
+            log::info!(
+                "Feedback '{}' on session {} ({})",
+                feedback.rating.as_str(),
+                feedback.session_id,
+                feedback.tenant_id
+            );
+            Ok(warp::reply::with_status(
+                warp::reply::json(&feedback),
+                warp::http::StatusCode::CREATED,
+            ))
+        }
+        Err(e) => {
+            log::error!("Failed to store feedback: {:#}", e);
+            Err(ApiError::Internal("Failed to store feedback").into())
+        }
+    }
+}
+
+/// Ratings given on answers, newest first, optionally filtered by rating and
+/// time range
+#[utoipa::path(
+    get,
+    path = "/api/admin/feedback",
+    tag = "admin",
+    params(FeedbackFilter),
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "Ratings, newest first", body = Object),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_list_feedback(
+    filter: FeedbackFilter,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    match state.feedback.list(filter).await {
+        Ok(feedback) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "total": feedback.len(),
+                "feedback": feedback,
+            })),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => {
+            log::error!("Failed to list feedback: {:#}", e);
+            Err(ApiError::Internal("Failed to list feedback").into())
+        }
+    }
+}
+
+/// Raw retrieval for debugging: the chunks nearest to a query with their
+/// scores, without calling the model. The circuit breaker is not consulted as
+/// no completion is made.
+#[utoipa::path(
+    post,
+    path = "/api/search",
+    tag = "admin",
+    request_body = SearchRequest,
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "Nearest chunks, best first", body = SearchResponse),
+        (status = 400, description = "Empty query or `top_k` out of range", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong `X-Admin-Key`", body = ErrorResponse),
+        (
+            status = 404,
+            description = "Management endpoints are disabled (no `ADMIN_API_KEY`)",
+            body = ErrorResponse
+        ),
+        (
+            status = 429,
+            description = "Too many invalid admin keys from this IP",
+            body = ErrorResponse
+        ),
+    )
+)]
+pub async fn handle_search(
+    credentials: TenantCredentials,
+    req: SearchRequest,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    let tenant_id = authenticate_tenant(&state, &credentials)?;
+    if req.query.trim().is_empty() {
+        return Err(ApiError::InvalidRequest("query cannot be empty".to_string()).into());
+    }
+    let top_k = req.top_k.unwrap_or_else(|| state.retriever.params().0);
+    if !(1..=MAX_SEARCH_TOP_K).contains(&top_k) {
+        let message = format!("top_k must be between 1 and {}", MAX_SEARCH_TOP_K);
+        return Err(ApiError::InvalidRequest(message).into());
+    }
+
+    let results = match state
+        .retriever
+        .search(&req.query, &tenant_id, req.bike_model.as_deref(), top_k)
+        .await
+    {
+        Ok(results) => results,
+        Err(e) => {
+            log::error!("Search failed: {:#}", e);
+            return Err(ApiError::Internal("Search failed").into());
+        }
+    };
+
+    let results = results
+        .into_iter()
+        .map(|r| SearchHit {
+            chunk_id: r.chunk.id,
+            document_id: r.chunk.document_id,
+            bike_model: r.chunk.metadata.bike_model,
+            page_number: r.chunk.metadata.page_number,
+            section: r.chunk.metadata.section,
+            score: r.score,
+            raw_score: r.raw_score,
+            preview: r.chunk.text.chars().take(SEARCH_PREVIEW_CHARS).collect(),
+        })
+        .collect();
+    Ok(warp::reply::with_status(
+        warp::reply::json(&SearchResponse { results }),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+/// The OpenAPI specification of this API
+pub async fn handle_openapi() -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(crate::server::openapi::spec()))
+}
+
+/// Swagger UI for the specification, when `ENABLE_API_DOCS` is set
+pub async fn handle_api_docs(state: AppState) -> Result<impl Reply, Rejection> {
+    if !state.config.enable_api_docs {
+        return Err(ApiError::NotFound.into());
+    }
+    Ok(warp::reply::html(crate::server::openapi::DOCS_PAGE))
+}
+
+/// Metrics handler - Prometheus text format
+pub async fn handle_metrics(state: AppState) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::with_header(
+        state.metrics.render(),
+        "content-type",
+        "text/plain; version=0.0.4",
+    ))
+}
+
+/// Check a query the way chat would, without calling OpenAI or counting
+/// against the chat rate limit
+#[utoipa::path(
+    post,
+    path = "/api/validate",
+    tag = "chat",
+    request_body = ValidateRequest,
+    security((), ("chat_api_key" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Whether chat would answer the query",
+            body = ValidateResponse
+        ),
+        (status = 401, description = "Missing or wrong `X-Api-Key`", body = ErrorResponse),
+        (status = 403, description = "The caller's IP is blocked", body = ErrorResponse),
+    )
+)]
+pub async fn handle_validate(
+    req: ValidateRequest,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    let normalized_query = normalize_query(&req.query);
+    let (valid, reason) = match state.query_validator.validate(&normalized_query) {
+        QueryValidation::Ok => (true, None),
+        QueryValidation::SoftReject(steer) => (false, Some(steer)),
+        QueryValidation::HardReject(reason) | QueryValidation::TooShort(reason) => {
+            (false, Some(reason))
+        }
+    };
+    Ok(warp::reply::json(&ValidateResponse {
+        valid,
+        reason,
+        normalized_query,
+    }))
+}
+
+/// Status handler - get rate limit info
+#[utoipa::path(
+    get,
+    path = "/api/status",
+    tag = "chat",
+    responses(
+        (
+            status = 200,
+            description = "The caller's rate limit, the circuit breaker and maintenance state",
+            body = Object
+        ),
+    )
+)]
+pub async fn handle_status(
+    state: AppState,
+    remote_addr: Option<SocketAddr>,
+) -> Result<impl Reply, Rejection> {
+    let ip = client_ip(remote_addr);
+
+    let rate_limit_info = state.rate_limiter.get_status(ip).await;
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "rate_limit": rate_limit_info,
+        "circuit_breaker": {
+            "state": format!("{:?}", state.circuit_breaker.get_state().await),
+        },
+        "maintenance": state.maintenance.status(),
+    })))
+}
+
+/// Bike models the caller's tenant has manuals for, normalized, with their
+/// documents per manual type, for a model picker. Counted from the stored
+/// chunks, so the list follows uploads, re-indexing and metadata corrections.
+/// Any spelling of a model is accepted as a chat's `bike_model`.
+#[utoipa::path(
+    get,
+    path = "/api/models",
+    tag = "chat",
+    security((), ("tenant_key" = [])),
+    responses(
+        (status = 200, description = "Normalized bike models in the index", body = BikeModels),
+        (status = 401, description = "Unknown tenant key", body = ErrorResponse),
+    )
+)]
+pub async fn handle_list_models(
+    credentials: TenantCredentials,
+    state: AppState,
+) -> Result<impl Reply, Rejection> {
+    let tenant_id = authenticate_tenant(&state, &credentials)?;
+    let models = state
+        .retriever
+        .vector_store()
+        .count_by_bike_model(Some(&tenant_id))
+        .await;
+    Ok(warp::reply::json(&BikeModels { models }))
+}
+
+/// Address of the client, or localhost when warp does not know it
+fn client_ip(addr: Option<SocketAddr>) -> IpAddr {
+    addr.map(|addr| addr.ip()).unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+}
+
+/// Whether the provided key matches the configured admin key
+fn is_admin(state: &AppState, provided: Option<&str>) -> bool {
+    matches!(
+        (&state.config.admin_api_key, provided),
+        (Some(expected), Some(key)) if keys_match(expected, key)
+    )
+}
+
+/// Let a request through to the management endpoints if it carries the admin
+/// key. Invalid keys count against the IP's lockout limits; a locked out IP is
+/// refused before its key is checked.
+pub async fn authorize_admin(
+    state: AppState,
+    key: Option<String>,
+    remote_addr: Option<SocketAddr>,
+) -> Result<(), Rejection> {
+    // Without `ADMIN_API_KEY` the endpoints do not exist
+    let Some(expected) = &state.config.admin_api_key else {
+        return Err(ApiError::NotFound.into());
+    };
+
+    let ip = client_ip(remote_addr);
+    let lockout = state.admin_lockout.get_status(ip);
+    if lockout.remaining_minute == 0 || lockout.remaining_hour == 0 {
+        log::warn!("Refused admin request from locked out IP {}", ip);
+        let retry_after = lockout.reset_in_seconds.max(1);
+        return Err(ApiError::RateLimitExceeded {
+            message: format!("Too many invalid admin keys. Try again in {} seconds", retry_after),
+            retry_after: Some(retry_after),
+            rate_limit_info: None,
+        }
+        .into());
+    }
+
+    if key.as_deref().is_some_and(|key| keys_match(expected, key)) {
+        return Ok(());
+    }
+    // Only the failure is recorded here; the lockout is checked above
+    let _ = state.admin_lockout.check_and_record(ip);
+    log::warn!(
+        "Rejected admin request from {} with {} key",
+        ip,
+        if key.is_some() { "an invalid" } else { "no" }
+    );
+    Err(ApiError::Unauthorized("Invalid admin key").into())
+}
+
+/// Access log line for every request
+pub fn log_request(info: warp::log::Info) {
+    let ip = logged_client_ip(&info);
+    log::info!(
+        target: "api",
+        "{} \"{} {} {:?}\" {} \"{}\" \"{}\" {:?}",
+        ip,
+        info.method(),
+        info.path(),
+        info.version(),
+        info.status().as_u16(),
+        info.referer().unwrap_or("-"),
+        info.user_agent().unwrap_or("-"),
+        info.elapsed()
+    );
+}
+
+/// Client address of a logged request, from the request context when warp
+/// does not know it
+fn logged_client_ip(info: &warp::log::Info) -> String {
+    info.remote_addr()
+        .or_else(crate::request_context::remote_addr)
+        .map_or_else(|| "unknown".to_string(), |addr| addr.ip().to_string())
+}
+
+/// Audit log line for every call to a management endpoint, with its outcome
+pub fn audit_admin_call(info: warp::log::Info) {
+    let ip = logged_client_ip(&info);
+    log::info!(
+        target: "audit",
+        "Admin {} {} from {}: {}",
+        info.method(),
+        info.path(),
+        ip,
+        info.status().as_u16()
+    );
+}
+
+/// Reject requests from a blocked IP or CIDR range with 403 `IP_BLOCKED`
+pub async fn check_not_blocked(
+    state: AppState,
+    remote_addr: Option<SocketAddr>,
+) -> Result<(), Rejection> {
+    let ip = client_ip(remote_addr);
+    if state.block_list.is_blocked(ip) {
+        log::warn!("Rejected chat request from blocked IP {}", ip);
+        return Err(ApiError::IpBlocked.into());
+    }
+    Ok(())
+}
+
+/// Reject chat requests whose `X-Api-Key` is missing or not one of
+/// `CHAT_API_KEYS`; every request passes when no keys are configured
+pub async fn check_chat_api_key(
+    config: Arc<Config>,
+    key: Option<String>,
+    remote_addr: Option<SocketAddr>,
+) -> Result<(), Rejection> {
+    let keys = &config.chat_api_keys;
+    if keys.is_empty() || key.as_deref().is_some_and(|key| keys.verify(key)) {
+        return Ok(());
+    }
+
+    let ip = client_ip(remote_addr);
+    log::warn!(
+        "Rejected chat request from {} with {} API key",
+        ip,
+        if key.is_some() { "an invalid" } else { "no" }
+    );
+    Err(ApiError::Unauthorized("Missing or invalid API key").into())
+}
+
+/// What a request presented to be scoped to a tenant: its API key
+/// (`Authorization: Bearer <key>`) and the tenant named in `X-Tenant-Id`
+#[derive(Debug, Clone, Default)]
+pub struct TenantCredentials {
+    pub api_key: Option<String>,
+    pub tenant_id: Option<String>,
+}
+
+/// Refuse a request whose `X-Tenant-Id` names another tenant than its API
+/// key, or names a tenant with API keys without presenting one. Which tenant
+/// the request gets is left to `authenticate_tenant`, as is an unknown key.
+pub async fn check_tenant_header(
+    config: Arc<Config>,
+    api_key: Option<String>,
+    tenant_id: Option<String>,
+) -> Result<TenantCredentials, Rejection> {
+    let tenant_id = tenant_id
+        .map(|tenant| tenant.trim().to_string())
+        .filter(|tenant| !tenant.is_empty());
+    if let Some(requested) = tenant_id.as_deref() {
+        let mismatch = match api_key.as_deref() {
+            Some(key) => config
+                .tenant_api_keys
+                .tenant_for(key)
+                .is_some_and(|tenant| tenant != requested),
+            None => config.tenant_api_keys.has_tenant(requested),
+        };
+        if mismatch {
+            log::warn!("Rejected request for tenant '{}' without that tenant's key", requested);
+            return Err(ApiError::TenantMismatch.into());
+        }
+    }
+    Ok(TenantCredentials { api_key, tenant_id })
+}
+
+/// Tenant of the request: its API key's, failing with 401 `INVALID_API_KEY`
+/// for a key that is not configured; without a key, the tenant named in
+/// `X-Tenant-Id` when `TENANT_IDS` lists it, else the default namespace.
+fn authenticate_tenant(
+    state: &AppState,
+    credentials: &TenantCredentials,
+) -> Result<String, ApiError> {
+    let tenant = state
+        .config
+        .tenant_for(credentials.api_key.as_deref(), credentials.tenant_id.as_deref());
+    tenant.ok_or_else(|| {
+        log::warn!("Rejected request with an unknown API key");
+        ApiError::InvalidApiKey
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::MockProvider;
+    use crate::server::create_routes;
+    use crate::server::test_support::{
+        chat_from, chunk, post_chat, seed, tenant_state, test_state, QUERY,
+    };
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_models_lists_normalized_models_of_the_tenant() {
+        let config = Config {
+            tenant_api_keys: crate::security::TenantApiKeys::parse("acme:acme-key").unwrap(),
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let mut other_tenant = chunk("Ducati Monster", 2, "Check the belts");
+        other_tenant.metadata.tenant_id = "acme".to_string();
+        seed(
+            &state,
+            vec![
+                chunk("Honda CBR600RR", 12, "Adjust the drive chain tension"),
+                chunk("cbr 600 rr", 13, "Tighten the axle nut"),
+                chunk("Gixxer 750", 7, "Bleed the front brake"),
+                other_tenant,
+            ],
+        )
+        .await;
+        let routes = create_routes(state);
+
+        let models = |key: Option<&'static str>| {
+            let mut request = warp::test::request().path("/api/models");
+            if let Some(key) = key {
+                request = request.header("authorization", format!("Bearer {}", key));
+            }
+            let routes = routes.clone();
+            async move {
+                let resp = request.reply(&routes).await;
+                assert_eq!(resp.status(), 200);
+                serde_json::from_slice::<serde_json::Value>(resp.body()).unwrap()
+            }
+        };
+        let names = |body: serde_json::Value| -> Vec<String> {
+            body["models"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|model| model["bike_model"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(names(models(None).await), ["cbr600rr", "gsxr750"]);
+        assert_eq!(names(models(Some("acme-key")).await), ["monster"]);
+    }
+
+    #[tokio::test]
+    async fn test_models_follow_the_indexed_documents() {
+        let (state, _dir) = test_state(Config::default(), Arc::new(MockProvider::new("ok"))).await;
+        let manual = |document_id: &str, model: &str, manual_type: &str, page: u32| {
+            let mut chunk = chunk(model, page, &format!("{} page {}", manual_type, page));
+            chunk.document_id = document_id.to_string();
+            chunk.metadata.manual_type = Some(manual_type.to_string());
+            chunk
+        };
+        seed(
+            &state,
+            vec![
+                manual("doc-1", "Honda CBR600RR", "Service", 1),
+                manual("doc-1", "Honda CBR600RR", "Service", 2),
+                manual("doc-2", "cbr600rr", "owner ", 1),
+                manual("doc-3", "Yamaha YZF-R1", "service", 1),
+            ],
+        )
+        .await;
+        let routes = create_routes(state.clone());
+        let models = || async {
+            let resp = warp::test::request().path("/api/models").reply(&routes).await;
+            assert_eq!(resp.status(), 200);
+            serde_json::from_slice::<serde_json::Value>(resp.body()).unwrap()["models"].clone()
+        };
+
+        assert_eq!(
+            models().await,
+            serde_json::json!([
+                {
+                    "bike_model": "cbr600rr",
+                    "documents": 2,
+                    "chunks": 3,
+                    "manual_types": [
+                        {"manual_type": "owner", "documents": 1},
+                        {"manual_type": "service", "documents": 1},
+                    ],
+                },
+                {
+                    "bike_model": "r1",
+                    "documents": 1,
+                    "chunks": 1,
+                    "manual_types": [{"manual_type": "service", "documents": 1}],
+                },
+            ])
+        );
+
+        // Removing a document's chunks drops its model once nothing else covers it
+        let store = state.retriever.vector_store();
+        store.delete_document_chunks("doc-3", &Default::default()).await.unwrap();
+        store.delete_document_chunks("doc-2", &Default::default()).await.unwrap();
+        assert_eq!(
+            models().await,
+            serde_json::json!([{
+                "bike_model": "cbr600rr",
+                "documents": 1,
+                "chunks": 2,
+                "manual_types": [{"manual_type": "service", "documents": 1}],
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chat_reports_response_time() {
+        let (state, _dir) = test_state(Config::default(), Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state);
+
+        for query in [QUERY, "What is the capital of France?"] {
+            let resp = warp::test::request()
+                .method("POST")
+                .path("/api/chat")
+                .json(&serde_json::json!({ "query": query }))
+                .reply(&routes)
+                .await;
+
+            let header = resp.headers()[RESPONSE_TIME_HEADER].to_str().unwrap();
+            assert!(header.parse::<u64>().is_ok(), "{}: {}", resp.status(), header);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_id_returned_and_in_errors() {
+        let (state, _dir) = test_state(Config::default(), Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state);
+
+        let resp = warp::test::request().path("/api/health").reply(&routes).await;
+        let generated = resp.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert!(uuid::Uuid::parse_str(generated).is_ok(), "{}", generated);
+
+        // Requests run in a request context when served by `start_server`
+        let resp = crate::request_context::scope(None, async {
+            warp::test::request()
+                .method("POST")
+                .path("/api/chat")
+                .header(REQUEST_ID_HEADER, "client-42")
+                .json(&serde_json::json!({ "query": "" }))
+                .reply(&routes)
+                .await
+        })
+        .await;
+        assert_eq!(resp.status(), 422);
+        assert_eq!(resp.headers()[REQUEST_ID_HEADER], "client-42");
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["request_id"], "client-42");
+
+        let resp = warp::test::request()
+            .path("/api/health")
+            .header(REQUEST_ID_HEADER, "not a valid id")
+            .reply(&routes)
+            .await;
+        assert_ne!(resp.headers()[REQUEST_ID_HEADER], "not a valid id");
+    }
+
+    /// Log output collected in memory
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_logs_carry_request_fields_and_stage_durations() {
+        use crate::logging::{self, LogFormat};
+
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = logging::subscriber(
+            LogFormat::Json,
+            tracing_subscriber::EnvFilter::new("info"),
+            move || writer.clone(),
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let (state, _dir) = test_state(Config::default(), Arc::new(MockProvider::new("ok"))).await;
+        seed(&state, vec![chunk("Yamaha R1", 12, "Chain slack is 25-35 mm")]).await;
+        let routes = create_routes(state);
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let resp = crate::request_context::scope(None, async {
+            warp::test::request()
+                .method("POST")
+                .path("/api/chat")
+                .remote_addr("203.0.113.9:4000".parse().unwrap())
+                .header(REQUEST_ID_HEADER, "client-7")
+                .json(&serde_json::json!({ "query": QUERY, "session_id": session_id }))
+                .reply(&routes)
+                .await
+        })
+        .await;
+        assert_eq!(resp.status(), 200);
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<serde_json::Value> =
+            output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        for stage in ["validation", "retrieval", "completion"] {
+            let event = events
+                .iter()
+                .find(|e| e["stage"] == stage)
+                .unwrap_or_else(|| panic!("no {} event in {}", stage, output));
+            assert!(event["duration_ms"].is_u64(), "{}", event);
+            assert_eq!(event["span"]["name"], "chat");
+            assert_eq!(event["span"]["request_id"], "client-7");
+            assert_eq!(event["span"]["ip"], "203.0.113.9");
+            assert_eq!(event["span"]["session_id"], session_id.as_str());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_persona_named_in_prompt_and_health() {
+        let config = Config {
+            assistant_name: Some("Mike".to_string()),
+            shop_name: Some("Joe's Garage".to_string()),
+            ..Config::default()
+        };
+        let provider = Arc::new(MockProvider::new("ok"));
+        let (state, _dir) = test_state(config, provider.clone()).await;
+
+        post_chat(state.clone(), serde_json::json!({ "query": QUERY })).await;
+        let prompt = provider.last_prompt().unwrap();
+        assert!(prompt[0].content.contains("Your name is Mike"), "{}", prompt[0].content);
+
+        let resp = warp::test::request().path("/api/health").reply(&create_routes(state)).await;
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["service"], "Mike (Joe's Garage)");
+    }
+
+    #[tokio::test]
+    async fn test_readiness_unavailable_while_embeddings_fail() {
+        let (state, _dir) = test_state(Config::default(), Arc::new(MockProvider::new("ok"))).await;
+        let resp = warp::test::request().path("/api/health/ready").reply(&create_routes(state)).await;
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["status"], "ready");
+        assert_eq!(body["dependencies"]["vector_store"]["status"], "up");
+
+        let provider = Arc::new(MockProvider::new("ok").with_embedding_token_limit(0));
+        let (state, _dir) = test_state(Config::default(), provider).await;
+        let routes = create_routes(state);
+        let resp = warp::test::request().path("/api/health/ready").reply(&routes).await;
+        assert_eq!(resp.status(), 503);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["dependencies"]["embeddings"]["status"], "down");
+
+        // The liveness probe does not reach any dependency
+        let resp = warp::test::request().path("/api/health").reply(&routes).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_requests_get_json_errors() {
+        let config = Config {
+            max_chat_body_bytes: 256,
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state);
+        let send = |method: &str, path: &str, body: String| {
+            warp::test::request()
+                .method(method)
+                .path(path)
+                .header("content-type", "application/json")
+                .body(body)
+                .reply(&routes)
+        };
+
+        let oversized = serde_json::json!({ "query": "chain ".repeat(100) }).to_string();
+        let cases = [
+            (send("POST", "/api/chat", oversized.clone()).await, 413, "PAYLOAD_TOO_LARGE"),
+            (send("POST", "/api/chat/stream", oversized.clone()).await, 413, "PAYLOAD_TOO_LARGE"),
+            (send("POST", "/api/feedback", oversized).await, 413, "PAYLOAD_TOO_LARGE"),
+            (send("POST", "/api/chat", "{\"query\": ".to_string()).await, 400, "INVALID_FIELD"),
+            (send("POST", "/api/feedback", "{\"rating\": ".to_string()).await, 400, "INVALID_JSON"),
+            (send("GET", "/api/chat", String::new()).await, 405, "METHOD_NOT_ALLOWED"),
+        ];
+        for (resp, status, code) in cases {
+            assert_eq!(resp.status(), status, "{}", code);
+            assert_eq!(resp.headers()["content-type"], "application/json");
+            let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+            assert_eq!(body["code"], code);
+            assert!(body["error"].is_string());
+        }
+
+        let resp = send("GET", "/api/nowhere", String::new()).await;
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_static_frontend_served_beside_api() {
+        let site = tempfile::tempdir().unwrap();
+        std::fs::write(site.path().join("index.html"), "<html>chat</html>").unwrap();
+        let config = Config {
+            static_dir: Some(site.path().to_str().unwrap().to_string()),
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state);
+        let get = |path: &str| warp::test::request().path(path).reply(&routes);
+
+        assert_eq!(get("/").await.body().as_ref(), b"<html>chat</html>");
+        assert_eq!(get("/garage/settings").await.body().as_ref(), b"<html>chat</html>");
+        let health = get("/api/health").await;
+        assert_eq!(health.headers()["content-type"], "application/json");
+        assert_eq!(get("/api/nowhere").await.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_openapi_spec_describes_chat_and_its_errors() {
+        let (state, _dir) = test_state(Config::default(), Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state);
+        let resp = warp::test::request().path("/api/openapi.json").reply(&routes).await;
+        assert_eq!(resp.status(), 200);
+
+        let spec: utoipa::openapi::OpenApi = serde_json::from_slice(resp.body()).unwrap();
+        let chat = spec.paths.paths["/api/chat"].post.as_ref().expect("POST /api/chat");
+        let utoipa::openapi::RefOr::T(limited) = &chat.responses.responses["429"] else {
+            panic!("429 response is a reference");
+        };
+        let headers = limited.headers.keys().collect::<Vec<_>>();
+        assert!(headers.contains(&&"Retry-After".to_string()), "{:?}", headers);
+        assert!(headers.contains(&&"X-RateLimit-Remaining-Minute".to_string()));
+        assert!(chat.responses.responses.contains_key("200"));
+        let schemas = &spec.components.as_ref().unwrap().schemas;
+        for name in ["ChatRequest", "ChatResponse", "ErrorResponse", "Document", "UploadResponse"] {
+            assert!(schemas.contains_key(name), "{}", name);
+        }
+        assert!(spec.paths.paths.contains_key("/api/documents/{id}"));
+
+        // Swagger UI only when enabled
+        assert_eq!(warp::test::request().path("/api/docs").reply(&routes).await.status(), 404);
+        let config = Config {
+            enable_api_docs: true,
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let resp = warp::test::request().path("/api/docs").reply(&create_routes(state)).await;
+        assert_eq!(resp.status(), 200);
+        assert!(String::from_utf8_lossy(resp.body()).contains("/api/openapi.json"));
+    }
+
+    #[tokio::test]
+    async fn test_openapi_spec_documents_admin_lockout() {
+        let (state, _dir) = test_state(Config::default(), Arc::new(MockProvider::new("ok"))).await;
+        let resp = warp::test::request()
+            .path("/api/openapi.json")
+            .reply(&create_routes(state))
+            .await;
+        let spec: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+
+        let mut admin_operations = 0;
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                let admin_key = operation["security"]
+                    .as_array()
+                    .is_some_and(|security| security.iter().any(|s| s.get("admin_key").is_some()));
+                if !admin_key {
+                    continue;
+                }
+                admin_operations += 1;
+                let responses = operation["responses"].as_object().unwrap();
+                for status in ["401", "404", "429"] {
+                    assert!(responses.contains_key(status), "{} {} lacks {}", method, path, status);
+                }
+            }
+        }
+        assert!(admin_operations > 10, "{}", admin_operations);
+        assert!(spec["paths"]["/api/admin/block"]["post"]["responses"]["200"].is_object());
+    }
+
+    #[tokio::test]
+    async fn test_validate_checks_query_without_answering() {
+        let provider = Arc::new(MockProvider::new("ok"));
+        let (state, _dir) = test_state(Config::default(), provider.clone()).await;
+        let rate_limiter = state.rate_limiter.clone();
+        let routes = create_routes(state);
+        let validate = |query: &str| {
+            let request = warp::test::request()
+                .method("POST")
+                .path("/api/validate")
+                .json(&serde_json::json!({ "query": query }))
+                .reply(&routes);
+            async move {
+                let resp = request.await;
+                assert_eq!(resp.status(), 200);
+                serde_json::from_slice::<serde_json::Value>(resp.body()).unwrap()
+            }
+        };
+        let ip = std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+        let before = rate_limiter.get_status(ip).await.remaining_minute;
+
+        let body = validate("  How do I adjust the\n chain   on my motorcycle? ").await;
+        assert_eq!(body["valid"], true);
+        assert!(body.get("reason").is_none());
+        assert_eq!(body["normalized_query"], "How do I adjust the chain on my motorcycle?");
+
+        let body = validate("What is the best recipe for banana bread?").await;
+        assert_eq!(body["valid"], false);
+        assert!(body["reason"].as_str().unwrap().contains("bike-related"));
+
+        let body = validate("motorcycle chain <script>alert(1)</script>").await;
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["reason"], "Query contains invalid characters or patterns");
+
+        assert_eq!(provider.chat_call_count(), 0);
+        assert_eq!(rate_limiter.get_status(ip).await.remaining_minute, before);
+    }
+
+    #[tokio::test]
+    async fn test_large_responses_compressed_when_accepted() {
+        use std::io::Read;
+
+        let answer = "Loosen the axle nut, then turn the adjuster a quarter turn. ".repeat(100);
+        let provider = Arc::new(MockProvider::new(&answer));
+        let (state, _dir) = test_state(Config::default(), provider).await;
+        let routes = create_routes(state);
+        let send = |method: &str, path: &str, encoding: Option<&str>| {
+            let mut request = warp::test::request().method(method).path(path);
+            if method == "POST" {
+                request = request.json(&serde_json::json!({ "query": QUERY }));
+            }
+            if let Some(encoding) = encoding {
+                request = request.header("accept-encoding", encoding);
+            }
+            request.reply(&routes)
+        };
+
+        let resp = send("POST", "/api/chat", Some("gzip")).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-encoding"], "gzip");
+        let mut json = String::new();
+        flate2::read::GzDecoder::new(resp.body().as_ref()).read_to_string(&mut json).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(body["response"].as_str().unwrap().contains("quarter turn"));
+        assert!(resp.body().len() < json.len() / 4);
+
+        let resp = send("POST", "/api/chat", None).await;
+        assert!(!resp.headers().contains_key("content-encoding"));
+        assert!(serde_json::from_slice::<serde_json::Value>(resp.body()).is_ok());
+
+        // Below COMPRESSION_MIN_BYTES, and streamed events, go out as they are
+        let resp = send("GET", "/api/health", Some("gzip")).await;
+        assert!(!resp.headers().contains_key("content-encoding"));
+        let resp = send("POST", "/api/chat/stream", Some("gzip, br")).await;
+        assert_eq!(resp.headers()["content-type"], "text/event-stream");
+        assert!(!resp.headers().contains_key("content-encoding"));
+    }
+
+    #[tokio::test]
+    async fn test_handler_errors_keep_status_code_and_headers() {
+        let (state, _dir) = tenant_state().await;
+        let routes = create_routes(state);
+        let send = |method: &str, path: &str, body: serde_json::Value| {
+            warp::test::request()
+                .method(method)
+                .path(path)
+                .header("authorization", "Bearer key-a")
+                .header("x-admin-key", "secret")
+                .json(&body)
+                .reply(&routes)
+        };
+        let no_body = serde_json::Value::Null;
+
+        let resp = warp::test::request()
+            .path("/api/sessions/abc")
+            .header("authorization", "Bearer unknown")
+            .reply(&routes)
+            .await;
+        let stream_structured = serde_json::json!({ "query": QUERY, "structured": true });
+        let cases = [
+            (resp, 401, "INVALID_API_KEY"),
+            (send("GET", "/api/sessions/abc", no_body.clone()).await, 404, "SESSION_NOT_FOUND"),
+            (send("GET", "/api/documents/abc", no_body.clone()).await, 404, "DOCUMENT_NOT_FOUND"),
+            (send("POST", "/api/documents/abc/resume", no_body).await, 404, "DOCUMENT_NOT_FOUND"),
+            (send("PATCH", "/api/documents/abc", serde_json::json!({})).await, 400, "INVALID_UPDATE"),
+            (send("POST", "/api/admin/block", serde_json::json!({ "ip": "x" })).await, 400, "INVALID_IP"),
+            (send("POST", "/api/chat/stream", stream_structured).await, 400, "INVALID_REQUEST"),
+        ];
+        for (resp, status, code) in cases {
+            assert_eq!(resp.status(), status, "{}", code);
+            let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+            assert_eq!(body["code"], code);
+        }
+
+        // Refused after the rate limit check: rate limit and timing headers kept
+        let off_topic = serde_json::json!({ "query": "What is the capital of France?" });
+        let resp = send("POST", "/api/chat", off_topic).await;
+        assert_eq!(resp.status(), 422);
+        assert!(resp.headers().contains_key("X-RateLimit-Remaining-Minute"));
+        assert!(resp.headers().contains_key(RESPONSE_TIME_HEADER));
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["code"], "INVALID_QUERY");
+    }
+
+    #[tokio::test]
+    async fn test_chat_requires_configured_api_key() {
+        let config = Config {
+            chat_api_keys: crate::security::ChatApiKeys::parse("app-key").unwrap(),
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state);
+
+        for (path, key) in [
+            ("/api/chat", None),
+            ("/api/chat", Some("wrong-key")),
+            ("/api/chat/stream", None),
+        ] {
+            let mut request = warp::test::request()
+                .method("POST")
+                .path(path)
+                .json(&serde_json::json!({ "query": QUERY }));
+            if let Some(key) = key {
+                request = request.header("x-api-key", key);
+            }
+            let resp = request.reply(&routes).await;
+            assert_eq!(resp.status(), 401, "{} with {:?}", path, key);
+            let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+            assert_eq!(body["code"], "UNAUTHORIZED");
+        }
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/chat")
+            .header("x-api-key", "app-key")
+            .json(&serde_json::json!({ "query": QUERY }))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+
+        let resp = warp::test::request().path("/api/health").reply(&routes).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_headers_match_body() {
+        let config = Config {
+            max_requests_per_minute: 1,
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let routes = create_routes(state);
+
+        for expected_status in [200, 429] {
+            let resp = warp::test::request()
+                .method("POST")
+                .path("/api/chat")
+                .json(&serde_json::json!({ "query": QUERY }))
+                .reply(&routes)
+                .await;
+            assert_eq!(resp.status(), expected_status);
+
+            let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+            let info = &body["rate_limit_info"];
+            for (header, field) in [
+                ("X-RateLimit-Remaining-Minute", "remaining_minute"),
+                ("X-RateLimit-Remaining-Hour", "remaining_hour"),
+                ("X-RateLimit-Reset", "reset_in_seconds"),
+            ] {
+                let value = resp.headers()[header].to_str().unwrap();
+                assert_eq!(value, info[field].to_string(), "{} on {}", header, expected_status);
+            }
+            assert_eq!(info["remaining_minute"], 0);
+            assert_eq!(resp.headers().contains_key("Retry-After"), expected_status == 429);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_fetched_listed_and_deleted() {
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let provider = Arc::new(MockProvider::new("Loosen the axle nut first."));
+        let (state, _dir) = test_state(config, provider).await;
+        let routes = create_routes(state.clone());
+        let chat = post_chat(
+            state,
+            serde_json::json!({ "query": QUERY, "bike_model": "Honda CBR600RR" }),
+        )
+        .await;
+        let path = format!("/api/sessions/{}", chat["session_id"].as_str().unwrap());
+
+        let resp = warp::test::request().path(&path).reply(&routes).await;
+        assert_eq!(resp.status(), 200);
+        let session: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(session["bike_model"], "Honda CBR600RR");
+        assert_eq!(session["messages"][0]["role"], "user");
+        assert_eq!(session["messages"][1]["content"], "Loosen the axle nut first.");
+        assert!(session["messages"][1]["timestamp"].is_string());
+
+        let resp = warp::test::request()
+            .path("/api/admin/sessions")
+            .header("x-admin-key", "secret")
+            .reply(&routes)
+            .await;
+        let listed: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(listed["total"], 1);
+        assert_eq!(listed["sessions"][0]["message_count"], 2);
+
+        // Deleting is idempotent
+        for _ in 0..2 {
+            let resp = warp::test::request()
+                .method("DELETE")
+                .path(&path)
+                .reply(&routes)
+                .await;
+            assert_eq!(resp.status(), 204);
+        }
+        let resp = warp::test::request().path(&path).reply(&routes).await;
+        assert_eq!(resp.status(), 404);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["code"], "SESSION_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_feedback_rates_answers_of_the_session() {
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            max_feedback_per_minute: 3,
+            ..Config::default()
+        };
+        let provider = Arc::new(MockProvider::new("Loosen the axle nut first."));
+        let (state, _dir) = test_state(config, provider).await;
+        let routes = create_routes(state.clone());
+        let chat = post_chat(state, serde_json::json!({ "query": QUERY })).await;
+        let session_id = chat["session_id"].as_str().unwrap();
+        let post_feedback = |body: serde_json::Value| {
+            warp::test::request()
+                .method("POST")
+                .path("/api/feedback")
+                .json(&body)
+                .reply(&routes)
+        };
+
+        let resp = post_feedback(serde_json::json!({
+            "session_id": session_id,
+            "response_id": chat["response_id"],
+            "rating": "down",
+            "comment": "Wrong torque",
+        }))
+        .await;
+        assert_eq!(resp.status(), 201);
+        let stored: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(stored["query"], QUERY);
+        assert_eq!(stored["response"], "Loosen the axle nut first.");
+
+        // Rating the same answer by position replaces the earlier rating
+        let resp = post_feedback(serde_json::json!({
+            "session_id": session_id,
+            "message_index": 1,
+            "rating": "up",
+        }))
+        .await;
+        assert_eq!(resp.status(), 201);
+
+        let resp = post_feedback(serde_json::json!({
+            "session_id": session_id,
+            "message_index": 0,
+            "rating": "up",
+        }))
+        .await;
+        assert_eq!(resp.status(), 404);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["code"], "RESPONSE_NOT_FOUND");
+
+        let resp = post_feedback(serde_json::json!({
+            "session_id": session_id,
+            "message_index": 1,
+            "rating": "up",
+        }))
+        .await;
+        assert_eq!(resp.status(), 429);
+
+        for (rating, total) in [("up", 1), ("down", 0)] {
+            let resp = warp::test::request()
+                .path(&format!("/api/admin/feedback?rating={}", rating))
+                .header("x-admin-key", "secret")
+                .reply(&routes)
+                .await;
+            let listed: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+            assert_eq!(listed["total"], total);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blocked_single_ip_gets_403() {
+        let provider = Arc::new(MockProvider::new("ok"));
+        let (state, _dir) = test_state(Config::default(), provider.clone()).await;
+        state.block_list.block("203.0.113.7").unwrap();
+
+        assert_eq!(chat_from(state.clone(), "203.0.113.7").await, 403);
+        assert_eq!(provider.chat_call_count(), 0);
+        assert_eq!(chat_from(state, "203.0.113.8").await, 200);
+    }
+
+    #[tokio::test]
+    async fn test_blocked_cidr_range_gets_403() {
+        let (state, _dir) = test_state(Config::default(), Arc::new(MockProvider::new("ok"))).await;
+        state.block_list.block("198.51.100.0/24").unwrap();
+
+        assert_eq!(chat_from(state.clone(), "198.51.100.42").await, 403);
+        assert_eq!(chat_from(state, "198.51.101.42").await, 200);
+    }
+
+    #[tokio::test]
+    async fn test_blocked_ip_refused_before_key_and_body_checks() {
+        let config = Config {
+            chat_api_keys: crate::security::ChatApiKeys::parse("app-key").unwrap(),
+            max_chat_body_bytes: 64,
+            ..Config::default()
+        };
+        let provider = Arc::new(MockProvider::new("ok"));
+        let (state, _dir) = test_state(config, provider.clone()).await;
+        state.block_list.block("203.0.113.7").unwrap();
+        let routes = create_routes(state);
+        let send = |method: &str, path: &str, key: Option<&str>, body: &str| {
+            let mut request = warp::test::request()
+                .method(method)
+                .path(path)
+                .remote_addr(std::net::SocketAddr::new([203, 0, 113, 7].into(), 40000))
+                .body(body);
+            if let Some(key) = key {
+                request = request.header("x-api-key", key);
+            }
+            let routes = routes.clone();
+            async move { request.reply(&routes).await }
+        };
+
+        let query = serde_json::json!({ "query": QUERY }).to_string();
+        let oversized = serde_json::json!({ "query": QUERY.repeat(10) }).to_string();
+        let requests = [
+            ("POST", "/api/chat", None, query.as_str()),
+            ("POST", "/api/chat", Some("wrong-key"), query.as_str()),
+            ("POST", "/api/chat", Some("app-key"), "{not json"),
+            ("POST", "/api/chat", Some("app-key"), oversized.as_str()),
+            ("POST", "/api/chat/stream", Some("wrong-key"), "{not json"),
+            ("POST", "/api/validate", None, "{not json"),
+            ("GET", "/api/chat/ws", Some("wrong-key"), ""),
+        ];
+        for (method, path, key, body) in requests {
+            let resp = send(method, path, key, body).await;
+            assert_eq!(resp.status(), 403, "{} {} {:?} {}", method, path, key, body);
+            let error: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+            assert_eq!(error["code"], "IP_BLOCKED");
+        }
+        assert_eq!(provider.chat_call_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_requests_per_route_and_chat_timings() {
+        let (state, _dir) = test_state(Config::default(), Arc::new(MockProvider::new("ok"))).await;
+        seed(&state, vec![chunk("Yamaha R1", 12, "Chain slack is 25-35 mm")]).await;
+        post_chat(state.clone(), serde_json::json!({ "query": QUERY })).await;
+
+        let routes = create_routes(state);
+        for id in ["a1", "b2"] {
+            let path = format!("/api/documents/{}", id);
+            warp::test::request().path(&path).reply(&routes).await;
+        }
+        warp::test::request().path("/api/health").reply(&routes).await;
+
+        let resp = warp::test::request().path("/metrics").reply(&routes).await;
+        let text = String::from_utf8(resp.body().to_vec()).unwrap();
+        let series = [
+            r#"http_requests_total{route="/api/chat",method="POST",status="200"} 1"#,
+            r#"http_requests_total{route="/api/health",method="GET",status="200"} 1"#,
+            // Both document ids fall under one route template
+            r#"http_requests_total{route="/api/documents/{id}",method="GET",status="404"} 2"#,
+            r#"openai_request_duration_seconds_count{operation="completion"} 1"#,
+            r#"openai_request_duration_seconds_count{operation="embedding"} 1"#,
+            "retrieval_duration_seconds_count 1",
+        ];
+        for line in series {
+            assert!(text.contains(line), "missing {} in\n{}", line, text);
+        }
+        let tokens = text
+            .lines()
+            .find_map(|l| l.strip_prefix(r#"openai_tokens_total{kind="prompt"} "#))
+            .unwrap();
+        assert!(tokens.parse::<u64>().unwrap() > 0);
+    }
+
+    async fn chat_with_key(state: AppState, key: Option<&str>) -> (u16, serde_json::Value) {
+        let routes = create_routes(state);
+        let mut request = warp::test::request()
+            .method("POST")
+            .path("/api/chat")
+            .json(&serde_json::json!({ "query": QUERY }));
+        if let Some(key) = key {
+            request = request.header("authorization", format!("Bearer {}", key));
+        }
+        let resp = request.reply(&routes).await;
+        (resp.status().as_u16(), serde_json::from_slice(resp.body()).unwrap())
+    }
+
+    fn source_pages(body: &serde_json::Value) -> Vec<u64> {
+        body["sources"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["page_number"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_tenant_cannot_retrieve_other_tenant_chunks() {
+        let (state, _dir) = tenant_state().await;
+
+        let (_, a) = chat_with_key(state.clone(), Some("key-a")).await;
+        let (_, b) = chat_with_key(state.clone(), Some("key-b")).await;
+
+        assert_eq!(source_pages(&a), vec![10]);
+        assert_eq!(source_pages(&b), vec![20]);
+
+        // The tenant comes from the key alone; a tenant named in the body is ignored
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/chat")
+            .header("authorization", "Bearer key-a")
+            .json(&serde_json::json!({ "query": QUERY, "tenant_id": "dealer-b" }))
+            .reply(&create_routes(state))
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(source_pages(&body), vec![10]);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_header_must_match_the_key() {
+        let (state, _dir) = tenant_state().await;
+        let routes = create_routes(state);
+        let chat = |key: Option<&str>, tenant: &str| {
+            let mut request = warp::test::request()
+                .method("POST")
+                .path("/api/chat")
+                .header("x-tenant-id", tenant)
+                .json(&serde_json::json!({ "query": QUERY }));
+            if let Some(key) = key {
+                request = request.header("authorization", format!("Bearer {}", key));
+            }
+            let routes = routes.clone();
+            async move { request.reply(&routes).await }
+        };
+
+        let resp = chat(Some("key-a"), "dealer-a").await;
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(source_pages(&body), vec![10]);
+        assert_eq!(chat(None, "default").await.status(), 200);
+
+        for (key, tenant) in [(Some("key-a"), "dealer-b"), (None, "dealer-a")] {
+            let resp = chat(key, tenant).await;
+            assert_eq!(resp.status(), 403, "{:?} {}", key, tenant);
+            let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+            assert_eq!(body["code"], "TENANT_MISMATCH");
+        }
+        let resp = chat(Some("unknown"), "dealer-a").await;
+        assert_eq!(resp.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_header_selects_listed_tenants_without_a_key() {
+        let config = Config {
+            tenant_api_keys: crate::security::TenantApiKeys::parse("dealer-a:key-a").unwrap(),
+            tenant_ids: vec!["dealer-c".to_string()],
+            min_confidence: 0.0,
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let mut c = chunk("Honda CBR600RR", 30, "Dealer C chain adjustment procedure");
+        c.metadata.tenant_id = "dealer-c".to_string();
+        let shared = chunk("Honda CBR600RR", 40, "Default chain adjustment procedure");
+        seed(&state, vec![c, shared]).await;
+        let routes = create_routes(state);
+        let chat = |tenant: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/api/chat")
+                .header("x-tenant-id", tenant)
+                .json(&serde_json::json!({ "query": QUERY }))
+                .reply(&routes)
+        };
+
+        let body: serde_json::Value = serde_json::from_slice(chat("dealer-c").await.body()).unwrap();
+        assert_eq!(source_pages(&body), vec![30]);
+        // An unlisted tenant falls back to the default namespace
+        let body: serde_json::Value = serde_json::from_slice(chat("dealer-x").await.body()).unwrap();
+        assert_eq!(source_pages(&body), vec![40]);
+        // A tenant with API keys cannot be selected by the header alone
+        assert_eq!(chat("dealer-a").await.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight_allows_the_tenant_header() {
+        let (state, _dir) = tenant_state().await;
+        let resp = warp::test::request()
+            .method("OPTIONS")
+            .path("/api/chat")
+            .header("origin", "https://shop.example")
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "authorization, x-tenant-id")
+            .reply(&create_routes(state))
+            .await;
+        assert_eq!(resp.status(), 200);
+        let allowed = resp.headers()["access-control-allow-headers"].to_str().unwrap();
+        assert!(allowed.contains("x-tenant-id"), "{}", allowed);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_key_rejected_and_missing_key_uses_default_namespace() {
+        let (state, _dir) = tenant_state().await;
+
+        let (status, body) = chat_with_key(state.clone(), Some("dealer-a")).await;
+        assert_eq!((status, body["code"].as_str()), (401, Some("INVALID_API_KEY")));
+
+        let (status, missing) = chat_with_key(state, None).await;
+        assert_eq!(status, 200);
+        assert!(source_pages(&missing).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_returns_chunks_by_descending_score() {
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let provider = Arc::new(MockProvider::new("ok"));
+        let (state, _dir) = test_state(config, provider.clone()).await;
+        seed(
+            &state,
+            vec![
+                chunk("KTM 390", 3, "Check the tyre pressure before riding"),
+                chunk("KTM 390", 7, "Adjust the drive chain slack at the rear axle"),
+                chunk("KTM 390", 9, "Lubricate the drive chain after riding in rain"),
+            ],
+        )
+        .await;
+        let routes = create_routes(state);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/search")
+            .header("x-admin-key", "secret")
+            .json(&serde_json::json!({ "query": QUERY, "top_k": 3 }))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        let scores: Vec<f64> = results.iter().map(|r| r["score"].as_f64().unwrap()).collect();
+        assert!(scores.windows(2).all(|w| w[0] >= w[1]), "{:?}", scores);
+        assert_eq!(results[0]["page_number"], 7);
+        assert!(results[0]["preview"].as_str().unwrap().starts_with("Adjust the drive chain"));
+        assert_eq!(provider.chat_call_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_search_results_compressed_above_threshold() {
+        use std::io::Read;
+
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            compression_min_bytes: 256,
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let text = "Adjust the drive chain slack at the rear axle. ".repeat(20);
+        seed(&state, vec![chunk("KTM 390", 7, &text), chunk("KTM 390", 8, &text)]).await;
+        let routes = create_routes(state);
+        let search = |top_k: usize| {
+            warp::test::request()
+                .method("POST")
+                .path("/api/search")
+                .header("x-admin-key", "secret")
+                .header("accept-encoding", "deflate, gzip;q=0.5")
+                .json(&serde_json::json!({ "query": QUERY, "top_k": top_k }))
+                .reply(&routes)
+        };
+
+        let resp = search(2).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-encoding"], "deflate");
+        assert_eq!(resp.headers()["vary"], "accept-encoding");
+        let mut json = String::new();
+        let mut decoder = flate2::read::DeflateDecoder::new(resp.body().as_ref());
+        decoder.read_to_string(&mut json).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(body["results"].as_array().unwrap().len(), 2);
+
+        // A short error body stays under the threshold
+        let resp = search(0).await;
+        assert_eq!(resp.status(), 400);
+        assert!(!resp.headers().contains_key("content-encoding"));
+    }
+
+    /// Backend refusing every request, as a shared store would once the other
+    /// instances have used up the limit
+    struct DenyAll;
+
+    #[async_trait::async_trait]
+    impl crate::security::RateLimiterBackend for DenyAll {
+        async fn check_and_record(&self, _ip: std::net::IpAddr) -> anyhow::Result<RateLimitInfo> {
+            anyhow::bail!("Rate limit exceeded. Try again in 30 seconds")
+        }
+
+        async fn get_status(&self, _ip: std::net::IpAddr) -> RateLimitInfo {
+            RateLimitInfo {
+                remaining_minute: 0,
+                remaining_hour: 0,
+                reset_in_seconds: 30,
+            }
+        }
+
+        fn limits(&self) -> (u32, u32) {
+            (0, 0)
+        }
+
+        fn update_limits(&self, _max_per_minute: u32, _max_per_hour: u32) {}
+    }
+
+    #[tokio::test]
+    async fn test_chat_limited_by_pluggable_backend() {
+        let provider = Arc::new(MockProvider::new("ok"));
+        let (mut state, _dir) = test_state(Config::default(), provider.clone()).await;
+        state.rate_limiter = Arc::new(DenyAll);
+        let routes = create_routes(state);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/chat")
+            .json(&serde_json::json!({ "query": QUERY }))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 429);
+        assert_eq!(resp.headers()["retry-after"], "30");
+        assert_eq!(provider.chat_call_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_server_stops_after_shutdown_signal() {
+        let (state, _dir) = test_state(Config::default(), Arc::new(MockProvider::new("ok"))).await;
+        state.tasks.spawn("forever", std::future::pending());
+        let (signal, shutdown) = tokio::sync::oneshot::channel::<()>();
+
+        let server = tokio::spawn(crate::server::run_server(
+            state.clone(),
+            "127.0.0.1:0".parse().unwrap(),
+            async {
+                let _ = shutdown.await;
+            },
+        ));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!server.is_finished());
+
+        signal.send(()).unwrap();
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), server)
+            .await
+            .expect("server did not stop after the shutdown signal");
+        assert!(result.unwrap().is_ok());
+        assert!(state.tasks.running().is_empty());
+    }
+}
@@ -1,5 +1,14 @@
 pub mod routes;
 pub mod handlers;
+pub mod errors;
+pub mod compression;
+pub mod static_files;
+pub mod tls;
+pub mod openapi;
+
+#[cfg(test)]
+pub mod test_support;
 
 pub use routes::*;
 pub use handlers::*;
+pub use errors::*;
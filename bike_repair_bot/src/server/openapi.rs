@@ -0,0 +1,129 @@
+//! OpenAPI description of the HTTP API, built from the handlers'
+//! `#[utoipa::path]` annotations and the request and response models.
+//! Served at `/api/openapi.json`, and as Swagger UI at `/api/docs` when
+//! `ENABLE_API_DOCS` is set.
+
+use std::sync::OnceLock;
+
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::models::{
+    BikeModelCount, BikeModels, BlockRequest, ChatRequest, ChatResponse, ChunkPreview,
+    ChunkPreviewResponse, ContextChunk, Document, DocumentMetadataUpdate, DocumentStatus,
+    ErrorResponse, Feedback, FeedbackRequest, IndexStats, MaintenanceRequest, ManualTypeCount,
+    Message, RateLimitInfo, Rating, ReprocessRequest, ReprocessResponse, RestoreRequest,
+    ResumeResponse, Role, SearchHit, SearchRequest, SearchResponse, SessionHistory, Source,
+    SourceFormat, StructuredAnswer, UploadResponse, UrlIngestRequest, ValidateRequest, UsageBucket,
+    UsageGrouping, UsageReport, UsageTotals, ValidateResponse, Verbosity,
+};
+use crate::pdf::ModelDetection;
+use crate::server::handlers;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Bike Repair ChatBot API",
+        description = "Answers motorcycle repair questions from the indexed service manuals.",
+        license(name = "Proprietary")
+    ),
+    paths(
+        handlers::handle_health,
+        handlers::handle_readiness,
+        handlers::handle_chat,
+        handlers::handle_chat_stream,
+        handlers::handle_chat_ws,
+        handlers::handle_validate,
+        handlers::handle_status,
+        handlers::handle_list_models,
+        handlers::handle_get_session,
+        handlers::handle_delete_session,
+        handlers::handle_feedback,
+        handlers::handle_search,
+        handlers::handle_admin_stats,
+        handlers::handle_index_stats,
+        handlers::handle_reload,
+        handlers::handle_block_ip,
+        handlers::handle_unblock_ip,
+        handlers::handle_maintenance,
+        handlers::handle_usage,
+        handlers::handle_list_sessions,
+        handlers::handle_list_feedback,
+        handlers::handle_create_snapshot,
+        handlers::handle_list_snapshots,
+        handlers::handle_restore_snapshot,
+        handlers::handle_upload,
+        handlers::handle_preview_document,
+        handlers::handle_upload_from_url,
+        handlers::handle_get_document,
+        handlers::handle_resume_document,
+        handlers::handle_reprocess_document,
+        handlers::handle_update_document,
+    ),
+    components(schemas(
+        ChatRequest, ChatResponse, ErrorResponse, Source, RateLimitInfo, StructuredAnswer,
+        Verbosity, ValidateRequest, ValidateResponse, SessionHistory, Message, Role,
+        FeedbackRequest, Feedback, Rating, SearchRequest, SearchResponse, SearchHit, BlockRequest,
+        RestoreRequest, Document, DocumentStatus, SourceFormat, UploadResponse, ModelDetection,
+        ResumeResponse, ReprocessRequest, ReprocessResponse, ChunkPreviewResponse, ChunkPreview,
+        UrlIngestRequest, DocumentMetadataUpdate, MaintenanceRequest, UsageReport, UsageTotals,
+        UsageBucket, UsageGrouping, IndexStats, BikeModelCount, BikeModels, ContextChunk,
+        ManualTypeCount,
+    )),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "health", description = "Liveness and readiness"),
+        (name = "chat", description = "Questions answered from the manuals"),
+        (name = "sessions", description = "Conversation history and ratings"),
+        (name = "admin", description = "Management endpoints, behind `X-Admin-Key`"),
+        (name = "documents", description = "Manual ingestion, behind `X-Admin-Key`"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Adds the key schemes the paths refer to: `chat_api_key` (required by the
+/// chat endpoints when `CHAT_API_KEYS` is set), `admin_key` and `tenant_key`
+/// (the bearer key selecting the caller's tenant)
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "chat_api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Api-Key"))),
+        );
+        components.add_security_scheme(
+            "admin_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Admin-Key"))),
+        );
+        components.add_security_scheme(
+            "tenant_key",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// The specification, generated on first use
+pub fn spec() -> &'static utoipa::openapi::OpenApi {
+    static SPEC: OnceLock<utoipa::openapi::OpenApi> = OnceLock::new();
+    SPEC.get_or_init(ApiDoc::openapi)
+}
+
+/// Swagger UI page for `/api/docs`, loading its assets from a CDN
+pub const DOCS_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Bike Repair ChatBot API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/api/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
@@ -1,73 +1,791 @@
+use futures::future::Either;
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use warp::hyper::server::conn::AddrStream;
+use warp::hyper::service::{make_service_fn, service_fn, Service};
 use warp::{reject::Rejection, Filter, Reply};
+use std::convert::Infallible;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tracing::Instrument;
 
+use crate::server::compression::compress;
+use crate::server::errors::{ApiError, RateLimitedError};
 use crate::server::handlers::*;
+use crate::server::static_files::static_site;
+use crate::server::tls::CertificateStore;
 
 /// Shared application state
 #[derive(Clone)]
 pub struct AppState {
     pub config: Arc<crate::config::Config>,
-    pub openai_client: Arc<crate::ai::OpenAIClient>,
-    pub rate_limiter: Arc<crate::security::RateLimiter>,
+    pub ai_provider: Arc<dyn crate::ai::AiProvider>,
+    pub retriever: Arc<crate::rag::Retriever>,
+    pub rate_limiter: Arc<dyn crate::security::RateLimiterBackend>,
+    pub upload_rate_limiter: Arc<dyn crate::security::RateLimiterBackend>,
+    pub feedback_rate_limiter: Arc<dyn crate::security::RateLimiterBackend>,
+    /// Counts invalid admin keys per IP, locking the IP out of the management
+    /// endpoints past its limits
+    pub admin_lockout: Arc<crate::security::RateLimiter>,
+    pub repeat_guard: Arc<crate::security::RepeatGuard>,
     pub query_validator: Arc<crate::security::QueryValidator>,
     pub circuit_breaker: Arc<crate::security::CircuitBreaker>,
+    pub block_list: Arc<crate::security::BlockList>,
+    pub metrics: Arc<crate::metrics::Metrics>,
+    pub system_prompt: Arc<std::sync::RwLock<String>>,
+    pub ingestor: Arc<crate::ingestion::Ingestor>,
+    pub documents: Arc<crate::ingestion::DocumentRegistry>,
+    pub sessions: Arc<dyn crate::sessions::SessionStore>,
+    /// Set when `ENABLE_HISTORY_SUMMARY` is on
+    pub history_summarizer: Option<Arc<crate::sessions::HistorySummarizer>>,
+    pub feedback: Arc<crate::feedback::FeedbackStore>,
+    /// Background tasks stopped on shutdown
+    pub tasks: Arc<crate::tasks::TaskRegistry>,
+    pub readiness: Arc<crate::readiness::ReadinessProbe>,
+    /// While on, chat requests get 503 `MAINTENANCE`
+    pub maintenance: Arc<crate::maintenance::Maintenance>,
+    pub usage: Arc<crate::usage::UsageLog>,
+}
+
+/// Longest incoming `X-Request-Id` that is kept as the request's id
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Id of the request: the client's `X-Request-Id` when it is a short token,
+/// otherwise a new UUID. Recorded in the request context for error responses
+/// and in the request's span for log lines.
+fn request_id() -> impl Filter<Extract = (String,), Error = std::convert::Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: warp::http::HeaderMap| {
+        let id = headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| is_valid_request_id(id))
+            .map(str::to_string)
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        crate::request_context::set_request_id(&id);
+        tracing::Span::current().record("request_id", id.as_str());
+        id
+    })
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Address of the client; `start_server` passes it through the request
+/// context, as warp does not see the connection
+fn client_addr() -> impl Filter<Extract = (Option<SocketAddr>,), Error = std::convert::Infallible> + Clone {
+    warp::addr::remote().map(|addr: Option<SocketAddr>| addr.or_else(crate::request_context::remote_addr))
+}
+
+/// Tenant API key from an `Authorization: Bearer <key>` header and the tenant
+/// named in `X-Tenant-Id`, refusing a header that contradicts the key; see
+/// `check_tenant_header`
+fn api_key(
+    config: Arc<crate::config::Config>,
+) -> impl Filter<Extract = (TenantCredentials,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .map(|header: Option<String>| {
+            header.and_then(|value| value.strip_prefix("Bearer ").map(|key| key.trim().to_string()))
+        })
+        .and(warp::header::optional::<String>("x-tenant-id"))
+        .and_then(move |key, tenant_id| check_tenant_header(config.clone(), key, tenant_id))
+}
+
+/// Passes requests whose IP is not on the block list. Leads the chat
+/// endpoints' filters, so a blocked client learns nothing about its key or body.
+fn not_blocked(
+    state: impl Filter<Extract = (AppState,), Error = std::convert::Infallible> + Clone,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    state.and(client_addr()).and_then(check_not_blocked).untuple_one()
+}
+
+/// Passes chat requests carrying an accepted `X-Api-Key` header (or any
+/// request when `CHAT_API_KEYS` is unset)
+fn chat_api_key(
+    config: Arc<crate::config::Config>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-api-key")
+        .and(client_addr())
+        .and_then(move |key, remote_addr| check_chat_api_key(config.clone(), key, remote_addr))
+        .untuple_one()
+}
+
+/// Passes requests carrying the admin key in `X-Admin-Key`; see
+/// `authorize_admin`
+fn admin_auth(
+    state: impl Filter<Extract = (AppState,), Error = std::convert::Infallible> + Clone,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    state
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(client_addr())
+        .and_then(authorize_admin)
+        .untuple_one()
 }
 
 /// Create all routes
 pub fn create_routes(
     state: AppState,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let chat_key = chat_api_key(state.config.clone());
+    let api_key = api_key(state.config.clone());
+    let chat_body_limit = warp::body::content_length_limit(state.config.max_chat_body_bytes);
+    let request_metrics = state.metrics.clone();
+    let static_dir = state.config.static_dir.clone();
+    let compression_min_bytes = state.config.compression_min_bytes;
     let state_filter = warp::any().map(move || state.clone());
+    let not_blocked = not_blocked(state_filter.clone());
 
     // Health check endpoint
-    let health = warp::path("health")
+    let health = warp::path!("health")
         .and(warp::get())
+        .and(state_filter.clone())
         .and_then(handle_health);
 
-    // Chat endpoint
-    let chat = warp::path("chat")
+    // Readiness: reaches the vector store and OpenAI, 503 when one is down
+    let readiness = warp::path!("health" / "ready")
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(handle_readiness);
+
+    // Streaming chat endpoint (Server-Sent Events)
+    let chat_stream = warp::path!("chat" / "stream")
         .and(warp::post())
+        .and(not_blocked.clone())
+        .and(chat_key.clone())
+        .and(chat_body_limit)
+        .and(warp::body::bytes())
+        .and(state_filter.clone())
+        .and(client_addr())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(api_key.clone())
+        .and_then(handle_chat_stream);
+
+    // Chat over a WebSocket, one session per connection
+    let chat_ws = warp::path!("chat" / "ws")
+        .and(warp::get())
+        .and(not_blocked.clone())
+        .and(chat_key.clone())
+        .and(warp::ws())
+        .and(warp::query::<crate::models::ChatSocketQuery>())
+        .and(state_filter.clone())
+        .and(client_addr())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(api_key.clone())
+        .and_then(handle_chat_ws);
+
+    // Dry run of chat's query checks, without a completion or the rate limit
+    let validate = warp::path!("validate")
+        .and(warp::post())
+        .and(not_blocked.clone())
+        .and(chat_key.clone())
+        .and(chat_body_limit)
         .and(warp::body::json())
         .and(state_filter.clone())
-        .and(warp::addr::remote())
-        .and_then(handle_chat);
+        .and_then(handle_validate);
+
+    // Chat endpoint; every reply, errors included, carries the total handling
+    // time in `X-Response-Time-Ms`
+    let chat = warp::path!("chat")
+        .and(warp::post())
+        .and(not_blocked)
+        .and(chat_key)
+        .and(chat_body_limit)
+        .and(warp::body::bytes())
+        .and(state_filter.clone())
+        .and(client_addr())
+        .and(warp::header::optional::<String>("x-admin-key"))
+        .and(api_key.clone())
+        .and_then(handle_chat)
+        .recover(handle_rejection);
+    let chat = warp::any().map(Instant::now).and(chat).map(|started: Instant, reply| {
+        let elapsed_ms = started.elapsed().as_millis().to_string();
+        warp::reply::with_header(reply, RESPONSE_TIME_HEADER, elapsed_ms)
+    });
+
+    // Route groups are boxed so the combined filter type stays within the
+    // compiler's recursion limit
+    let chat = chat_stream.or(chat_ws).or(chat).or(validate).boxed();
+
+    // API contract, and Swagger UI over it when enabled
+    let openapi = warp::path!("openapi.json").and(warp::get()).and_then(handle_openapi);
+    let api_docs = warp::path!("docs")
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(handle_api_docs);
 
     // Status endpoint (rate limit info)
     let status = warp::path("status")
         .and(warp::get())
         .and(state_filter.clone())
-        .and(warp::addr::remote())
+        .and(client_addr())
         .and_then(handle_status);
 
+    // Bike models the caller's manuals cover
+    let models = warp::path!("models")
+        .and(warp::get())
+        .and(api_key.clone())
+        .and(state_filter.clone())
+        .and_then(handle_list_models);
+
+    let session_get = warp::path!("sessions" / String)
+        .and(warp::get())
+        .and(api_key.clone())
+        .and(state_filter.clone())
+        .and_then(handle_get_session);
+
+    let session_delete = warp::path!("sessions" / String)
+        .and(warp::delete())
+        .and(api_key.clone())
+        .and(state_filter.clone())
+        .and_then(handle_delete_session);
+    let sessions = session_get.or(session_delete).boxed();
+
+    // Rate an answer
+    let feedback = warp::path("feedback")
+        .and(warp::post())
+        .and(chat_body_limit)
+        .and(warp::body::json())
+        .and(state_filter.clone())
+        .and(client_addr())
+        .and(api_key.clone())
+        .and_then(handle_feedback);
+
+    // Management endpoints below sit behind `admin_auth`, so their paths are
+    // relative to /api/admin and /api/documents
+
+    // Admin: index statistics
+    let admin_stats = warp::path!("stats")
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(handle_admin_stats);
+
+    // Admin: vector count, size and bike models of the index
+    let index_stats = warp::path!("index-stats")
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(handle_index_stats);
+
+    // Admin: reload runtime-adjustable configuration
+    let reload = warp::path!("reload")
+        .and(warp::post())
+        .and(state_filter.clone())
+        .and_then(handle_reload);
+
+    // Admin: block an IP or CIDR range
+    let block = warp::path!("block")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(state_filter.clone())
+        .and_then(handle_block_ip);
+
+    // Admin: unblock an IP or CIDR range
+    let unblock = warp::path!("block")
+        .and(warp::delete())
+        .and(warp::body::json())
+        .and(state_filter.clone())
+        .and_then(handle_unblock_ip);
+
+    // Admin: usage aggregates of the chat endpoint
+    let usage = warp::path!("usage")
+        .and(warp::get())
+        .and(warp::query::<crate::models::UsageQuery>())
+        .and(state_filter.clone())
+        .and_then(handle_usage);
+
+    // Admin: switch maintenance mode on or off
+    let maintenance = warp::path!("maintenance")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(state_filter.clone())
+        .and_then(handle_maintenance);
+
+    // Admin: snapshot the active collection for backup
+    let snapshot_create = warp::path!("snapshots")
+        .and(warp::post())
+        .and(state_filter.clone())
+        .and_then(handle_create_snapshot);
+
+    // Admin: snapshots of the active collection
+    let snapshot_list = warp::path!("snapshots")
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(handle_list_snapshots);
+
+    // Admin: replace the active collection with a snapshot
+    let snapshot_restore = warp::path!("snapshots" / "restore")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(state_filter.clone())
+        .and_then(handle_restore_snapshot);
+
+    // Admin: active sessions of all tenants
+    let admin_sessions = warp::path!("sessions")
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(handle_list_sessions);
+
+    // Admin: ratings given on answers
+    let admin_feedback = warp::path!("feedback")
+        .and(warp::get())
+        .and(warp::query::<crate::models::FeedbackFilter>())
+        .and(state_filter.clone())
+        .and_then(handle_list_feedback);
+
+    // Admin: chunks nearest to a query, without an answer
+    let search = warp::path::end()
+        .and(warp::post())
+        .and(api_key.clone())
+        .and(warp::body::json())
+        .and(state_filter.clone())
+        .and_then(handle_search);
+
+    // Admin: upload a manual (multipart), size enforced while streaming
+    let upload = warp::path::end()
+        .and(warp::post())
+        .and(api_key.clone())
+        .and(warp::multipart::form().max_length(None))
+        .and(warp::query::<crate::models::UploadOptions>())
+        .and(state_filter.clone())
+        .and(client_addr())
+        .and_then(handle_upload);
+
+    // Admin: dry run of an upload, reporting how it would be chunked
+    let document_preview = warp::path!("preview")
+        .and(warp::post())
+        .and(warp::multipart::form().max_length(None))
+        .and(warp::query::<crate::models::PreviewOptions>())
+        .and(state_filter.clone())
+        .and_then(handle_preview_document);
+
+    // Admin: download a PDF manual from an https URL and ingest it
+    let upload_from_url = warp::path!("from-url")
+        .and(warp::post())
+        .and(api_key.clone())
+        .and(warp::body::json())
+        .and(warp::query::<crate::models::UploadOptions>())
+        .and(state_filter.clone())
+        .and(client_addr())
+        .and_then(handle_upload_from_url);
+
+    // Admin: ingestion status of an uploaded document
+    let document_status = warp::path!(String)
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(handle_get_document);
+
+    // Admin: restart a failed ingestion where it stopped
+    let document_resume = warp::path!(String / "resume")
+        .and(warp::post())
+        .and(state_filter.clone())
+        .and_then(handle_resume_document);
+
+    // Admin: re-chunk a document's stored page text, optionally with other settings
+    let document_reprocess = warp::path!(String / "reprocess")
+        .and(warp::post())
+        .and(warp::body::bytes())
+        .and(state_filter.clone())
+        .and_then(handle_reprocess_document);
+
+    // Admin: correct a document's bike model, year or manual type
+    let document_update = warp::path!(String)
+        .and(warp::patch())
+        .and(warp::body::json())
+        .and(state_filter.clone())
+        .and_then(handle_update_document);
+
+    let admin_auth = admin_auth(state_filter.clone());
+    let admin = warp::path("admin").and(admin_auth.clone()).and(
+        admin_stats
+            .or(index_stats)
+            .or(reload)
+            .or(block)
+            .or(unblock)
+            .or(maintenance)
+            .or(usage)
+            .or(admin_sessions)
+            .or(admin_feedback)
+            .or(snapshot_create)
+            .or(snapshot_list)
+            .or(snapshot_restore)
+            .boxed(),
+    );
+    let search = warp::path("search").and(admin_auth.clone()).and(search);
+    let documents = warp::path("documents").and(admin_auth).and(
+        upload
+            .or(document_preview)
+            .or(upload_from_url)
+            .or(document_status)
+            .or(document_resume)
+            .or(document_reprocess)
+            .or(document_update)
+            .boxed(),
+    );
+    let management = admin
+        .or(search)
+        .or(documents)
+        .recover(handle_rejection)
+        .with(warp::log::custom(audit_admin_call))
+        .boxed();
+
     // Combine routes under /api prefix
-    let api = warp::path("api").and(health.or(chat).or(status));
+    let api = warp::path("api")
+        .and(
+            health
+                .or(readiness)
+                .or(chat)
+                .or(openapi.or(api_docs))
+                .or(status)
+                .or(models)
+                .or(sessions)
+                .or(feedback)
+                .or(management),
+        )
+        .recover(handle_rejection);
+    let api = warp::header::optional::<String>("accept-encoding")
+        .and(api)
+        .then(move |encoding, reply| compress(encoding, compression_min_bytes, reply));
+
+    // Prometheus metrics (outside /api for scrapers)
+    let metrics = warp::path("metrics")
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(handle_metrics);
+
+    // Web frontend from STATIC_DIR, for paths outside /api and /metrics
+    let site = match &static_dir {
+        Some(dir) => static_site(PathBuf::from(dir)),
+        None => warp::any()
+            .and_then(|| async { Err::<warp::reply::Response, _>(warp::reject::not_found()) })
+            .boxed(),
+    };
+
+    // Tag every response with the request's id
+    let routes = request_id()
+        .and(api.or(metrics).or(site))
+        .map(|id: String, reply| warp::reply::with_header(reply, REQUEST_ID_HEADER, id));
 
     // Add CORS
-    api.with(
+    routes.with(
         warp::cors()
             .allow_any_origin()
-            .allow_methods(vec!["GET", "POST", "OPTIONS"])
-            .allow_headers(vec!["Content-Type", "Authorization"])
+            .allow_methods(vec!["GET", "POST", "PATCH", "DELETE", "OPTIONS"])
+            .allow_headers(vec![
+                "Content-Type",
+                "Authorization",
+                "X-Admin-Key",
+                "X-Api-Key",
+                "X-Tenant-Id",
+                REQUEST_ID_HEADER,
+            ])
+            .expose_headers(vec![
+                REQUEST_ID_HEADER,
+                RESPONSE_TIME_HEADER,
+                "X-RateLimit-Remaining-Minute",
+                "X-RateLimit-Remaining-Hour",
+                "X-RateLimit-Reset",
+                "Retry-After",
+            ])
     )
-    .with(warp::log("api"))
+    .with(warp::log::custom(log_request))
+    .with(warp::log::custom(move |info| record_request_metrics(&request_metrics, info)))
+}
+
+/// Count and time a handled request under its route template
+fn record_request_metrics(metrics: &crate::metrics::Metrics, info: warp::log::Info) {
+    use warp::http::Method;
+
+    let method = match *info.method() {
+        Method::GET | Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+        | Method::HEAD | Method::OPTIONS => info.method().as_str(),
+        _ => "OTHER",
+    };
+    let route = route_template(info.path());
+    metrics.record_request(route, method, info.status().as_u16(), info.elapsed());
+}
+
+/// Route a request path was served by, with ids replaced by placeholders, so
+/// metrics get one series per route rather than per path. Paths that match no
+/// route are `other`.
+pub fn route_template(path: &str) -> &'static str {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["metrics"] => "/metrics",
+        ["api", "health"] => "/api/health",
+        ["api", "health", "ready"] => "/api/health/ready",
+        ["api", "chat"] => "/api/chat",
+        ["api", "chat", "stream"] => "/api/chat/stream",
+        ["api", "chat", "ws"] => "/api/chat/ws",
+        ["api", "validate"] => "/api/validate",
+        ["api", "openapi.json"] => "/api/openapi.json",
+        ["api", "docs"] => "/api/docs",
+        ["api", "status", ..] => "/api/status",
+        ["api", "models"] => "/api/models",
+        ["api", "sessions", _] => "/api/sessions/{id}",
+        ["api", "feedback", ..] => "/api/feedback",
+        ["api", "search"] => "/api/search",
+        ["api", "admin", "stats"] => "/api/admin/stats",
+        ["api", "admin", "index-stats"] => "/api/admin/index-stats",
+        ["api", "admin", "reload"] => "/api/admin/reload",
+        ["api", "admin", "block"] => "/api/admin/block",
+        ["api", "admin", "maintenance"] => "/api/admin/maintenance",
+        ["api", "admin", "usage"] => "/api/admin/usage",
+        ["api", "admin", "sessions"] => "/api/admin/sessions",
+        ["api", "admin", "feedback"] => "/api/admin/feedback",
+        ["api", "admin", "snapshots"] => "/api/admin/snapshots",
+        ["api", "admin", "snapshots", "restore"] => "/api/admin/snapshots/restore",
+        ["api", "documents"] => "/api/documents",
+        ["api", "documents", "preview"] => "/api/documents/preview",
+        ["api", "documents", "from-url"] => "/api/documents/from-url",
+        ["api", "documents", _] => "/api/documents/{id}",
+        ["api", "documents", _, "resume"] => "/api/documents/{id}/resume",
+        ["api", "documents", _, "reprocess"] => "/api/documents/{id}/reprocess",
+        _ => "other",
+    }
+}
+
+/// Answer a failed request with an `ErrorResponse` of the right status: the
+/// handlers' `ApiError`s and the rejections warp raises for malformed requests
+/// (`413` for a body over the limit, `411` without a `Content-Length`, `400`
+/// for a JSON body that does not decode, `405` for a known path with the wrong
+/// method). Other rejections, like unknown paths, are passed on.
+pub async fn handle_rejection(rejection: Rejection) -> Result<warp::reply::Response, Rejection> {
+    use warp::reject::{LengthRequired, MethodNotAllowed, PayloadTooLarge};
+
+    let response = if let Some(error) = rejection.find::<ApiError>() {
+        error_response(error, None)
+    } else if let Some(limited) = rejection.find::<RateLimitedError>() {
+        error_response(&limited.error, Some(&limited.rate_limit_info))
+    } else if rejection.find::<PayloadTooLarge>().is_some() {
+        error_response(&ApiError::PayloadTooLarge, None)
+    } else if rejection.find::<LengthRequired>().is_some() {
+        error_response(&ApiError::LengthRequired, None)
+    } else if let Some(e) = rejection.find::<warp::filters::body::BodyDeserializeError>() {
+        error_response(&ApiError::InvalidJson(e.to_string()), None)
+    } else if rejection.find::<MethodNotAllowed>().is_some() {
+        error_response(&ApiError::MethodNotAllowed, None)
+    } else {
+        return Err(rejection);
+    };
+    Ok(response)
+}
+
+/// JSON reply for `error`, with `Retry-After` when the error names a delay and
+/// the caller's rate limit headers once it was checked
+fn error_response(
+    error: &ApiError,
+    rate_limit_info: Option<&crate::models::RateLimitInfo>,
+) -> warp::reply::Response {
+    let status = error.status();
+    let body = error.to_response();
+    if status.is_server_error() {
+        log::warn!("Request failed with {} {}: {}", status.as_u16(), body.code, body.error);
+    } else {
+        log::debug!("Request refused with {} {}: {}", status.as_u16(), body.code, body.error);
+    }
+
+    let reply = warp::reply::with_status(warp::reply::json(&body), status);
+    let mut response = match rate_limit_info {
+        Some(info) => with_rate_limit_headers(reply, info),
+        None => reply.into_response(),
+    };
+    if let Some(seconds) = error.retry_after() {
+        response
+            .headers_mut()
+            .insert(warp::http::header::RETRY_AFTER, seconds.into());
+    }
+    response
 }
 
-/// Start the HTTP server
+/// Start the HTTP server and run it until SIGTERM or Ctrl-C
 pub async fn start_server(state: AppState) -> anyhow::Result<()> {
     let host = state.config.server_host.parse::<std::net::IpAddr>()?;
     let port = state.config.server_port;
     let addr = SocketAddr::new(host, port);
 
-    let routes = create_routes(state);
-
-    log::info!("🚀 Server starting on http://{}", addr);
+    let scheme = if state.config.tls_cert_path.is_some() { "https" } else { "http" };
+    log::info!("🚀 Server starting on {}://{}", scheme, addr);
     log::info!("📍 Endpoints:");
     log::info!("   GET  /api/health  - Health check");
+    log::info!("   GET  /api/health/ready - Readiness of OpenAI and the vector store");
     log::info!("   POST /api/chat    - Chat with AI");
+    log::info!("   POST /api/chat/stream - Chat with AI, streamed as Server-Sent Events");
+    log::info!("   GET  /api/chat/ws - Chat with AI over a WebSocket");
+    log::info!("   POST /api/validate - Check a query without answering it");
     log::info!("   GET  /api/status  - Rate limit status");
+    log::info!("   GET  /api/models  - Bike models with indexed manuals");
+    log::info!("   GET  /api/openapi.json - OpenAPI specification");
+    if state.config.enable_api_docs {
+        log::info!("   GET  /api/docs    - Swagger UI");
+    }
+    log::info!("   GET  /api/admin/stats - Index statistics per tenant (admin)");
+    log::info!("   GET  /api/admin/index-stats - Index size and bike models (admin)");
+    log::info!("   POST /api/admin/reload - Reload runtime config (admin)");
+    log::info!("   POST/DELETE /api/admin/block - Manage blocked IPs (admin)");
+    log::info!("   POST /api/admin/maintenance - Switch maintenance mode (admin)");
+    log::info!("   GET  /api/admin/usage - Usage aggregates per day (admin)");
+    log::info!("   GET/POST /api/admin/snapshots - List or take collection snapshots (admin)");
+    log::info!("   POST /api/admin/snapshots/restore - Restore a collection snapshot (admin)");
+    log::info!("   POST /api/search - Chunks matching a query, without an answer (admin)");
+    log::info!("   POST /api/documents - Upload a PDF manual (admin)");
+    log::info!("   POST /api/documents/preview - Preview how an upload chunks (admin)");
+    log::info!("   POST /api/documents/from-url - Ingest a PDF manual from an https URL (admin)");
+    log::info!("   GET  /api/documents/{{id}} - Ingestion status (admin)");
+    log::info!("   PATCH /api/documents/{{id}} - Correct model/year metadata (admin)");
+    log::info!("   POST /api/documents/{{id}}/resume - Resume a failed ingestion (admin)");
+    log::info!("   POST /api/documents/{{id}}/reprocess - Re-chunk a stored document (admin)");
+    log::info!("   GET  /metrics     - Prometheus metrics");
+    match &state.config.static_dir {
+        Some(dir) => log::info!("   GET  /            - Web frontend served from {}", dir),
+        None => log::info!("   Static file serving disabled (set STATIC_DIR to enable)"),
+    }
+
+    run_server(state, addr, shutdown_signal()).await
+}
 
-    warp::serve(routes).run(addr).await;
+/// Serve on `addr` (over HTTPS when `TLS_CERT_PATH` and `TLS_KEY_PATH` are
+/// set) until `shutdown` resolves. Then stop accepting connections,
+/// give in-flight requests `SHUTDOWN_GRACE_SECONDS` to finish, abort the
+/// background tasks (ingestion jobs can be resumed from their checkpoint) and
+/// flush the persistent stores.
+pub async fn run_server(
+    state: AppState,
+    addr: SocketAddr,
+    shutdown: impl Future<Output = ()> + Send,
+) -> anyhow::Result<()> {
+    let grace = Duration::from_secs(state.config.shutdown_grace_seconds);
+
+    // Unreadable or mismatched certificate files stop the server before it binds
+    let certificates = match (&state.config.tls_cert_path, &state.config.tls_key_path) {
+        (Some(cert), Some(key)) => Some(Arc::new(CertificateStore::load(cert, key)?)),
+        _ => None,
+    };
+
+    // Each connection's requests run in a request context holding the
+    // client's address, and in a `request` span
+    let svc = warp::service(create_routes(state.clone()));
+    let connection_service = move |remote_addr: Option<SocketAddr>| {
+        let svc = svc.clone();
+        service_fn(move |req: warp::http::Request<_>| {
+            let mut svc = svc.clone();
+            let span = tracing::info_span!(
+                "request",
+                request_id = tracing::field::Empty,
+                ip = tracing::field::Empty,
+                method = %req.method(),
+                path = %req.uri().path(),
+            );
+            if let Some(addr) = remote_addr {
+                span.record("ip", tracing::field::display(addr.ip()));
+            }
+            let call = async move { svc.call(req).await }.instrument(span);
+            crate::request_context::scope(remote_addr, call)
+        })
+    };
+
+    let (shutdown_started, draining) = oneshot::channel();
+    let stop_accepting = async move {
+        shutdown.await;
+        log::info!("🛑 Shutdown requested, no longer accepting connections");
+        let _ = shutdown_started.send(());
+    };
+    let server = match certificates {
+        None => Either::Left(
+            warp::hyper::Server::try_bind(&addr)?
+                .serve(make_service_fn(move |conn: &AddrStream| {
+                    let service = connection_service(Some(conn.remote_addr()));
+                    async move { Ok::<_, Infallible>(service) }
+                }))
+                .with_graceful_shutdown(stop_accepting),
+        ),
+        Some(certificates) => {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            if let Some(port) = state.config.redirect_http_port {
+                let redirect_addr = SocketAddr::new(addr.ip(), port);
+                let (_, redirect) = warp::serve(crate::server::tls::https_redirect(addr.port()))
+                    .try_bind_ephemeral(redirect_addr)?;
+                state.tasks.spawn("https-redirect", redirect);
+                log::info!("↪️  Redirecting http://{} to HTTPS", redirect_addr);
+            }
+            #[cfg(unix)]
+            state.tasks.spawn(
+                "tls-reload",
+                crate::server::tls::reload_on_sighup(certificates.clone()),
+            );
+
+            let incoming = crate::server::tls::incoming(listener, certificates.acceptor());
+            Either::Right(
+                warp::hyper::Server::builder(incoming)
+                    .serve(make_service_fn(move |conn: &TlsStream<TcpStream>| {
+                        let service = connection_service(conn.get_ref().0.peer_addr().ok());
+                        async move { Ok::<_, Infallible>(service) }
+                    }))
+                    .with_graceful_shutdown(stop_accepting),
+            )
+        }
+    };
+    tokio::pin!(server);
+    tokio::select! {
+        result = &mut server => result?,
+        Ok(()) = draining => {
+            log::info!("Waiting up to {}s for in-flight requests", grace.as_secs());
+            match tokio::time::timeout(grace, &mut server).await {
+                Ok(result) => result?,
+                Err(_) => log::warn!("Grace period over, dropping the remaining connections"),
+            }
+        }
+    }
+
+    let aborted = state.tasks.shutdown().await;
+    if !aborted.is_empty() {
+        log::info!("Stopped background tasks: {}", aborted.join(", "));
+    }
+    if let Err(e) = state.sessions.flush().await {
+        log::error!("Failed to flush the session store: {:#}", e);
+    }
+    if let Err(e) = state.feedback.flush().await {
+        log::error!("Failed to flush the feedback store: {:#}", e);
+    }
+    if let Err(e) = state.usage.flush().await {
+        log::error!("Failed to flush the usage store: {:#}", e);
+    }
+    log::info!("👋 Shutdown complete");
 
     Ok(())
 }
+
+/// Resolves on Ctrl-C or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                log::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
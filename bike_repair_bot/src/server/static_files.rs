@@ -0,0 +1,160 @@
+use warp::filters::fs::File;
+use warp::filters::BoxedFilter;
+use warp::http::header::CACHE_CONTROL;
+use warp::{reject::Rejection, Filter, Reply};
+use std::path::{Path, PathBuf};
+
+/// `Cache-Control` of files whose name carries a content hash
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// `Cache-Control` of `index.html` and other files that keep their name when
+/// their content changes
+const NO_CACHE: &str = "no-cache";
+
+/// Shortest run of letters and digits taken for a content hash in a file name
+const MIN_HASH_LEN: usize = 8;
+
+/// Web frontend served from `dir`: its files by path, and `index.html` for any
+/// other extension-less path so client-side routes load the app. `/api` and
+/// `/metrics` are never answered here.
+pub fn static_site(dir: PathBuf) -> BoxedFilter<(warp::reply::Response,)> {
+    let base = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+    let index = dir.join("index.html");
+
+    let files = not_reserved()
+        .and(warp::fs::dir(dir))
+        .and_then(move |file: File| {
+            let base = base.clone();
+            async move {
+                // warp refuses `..` segments; a symlink must not lead out
+                // either. Checked on the resolved path, after warp has decoded
+                // the request path, so `/%2eenv` is caught as well.
+                match file.path().canonicalize() {
+                    Ok(path) if path.starts_with(&base) && !is_hidden(&path, &base) => Ok(file),
+                    _ => Err(warp::reject::not_found()),
+                }
+            }
+        });
+
+    let fallback = not_reserved()
+        .and(warp::path::peek())
+        .and_then(|path: warp::path::Peek| async move {
+            let last = path.as_str().rsplit('/').next().unwrap_or_default();
+            // The path is still percent-encoded, so `%2e` counts as a dot
+            if last.contains('.') || last.to_ascii_lowercase().contains("%2e") {
+                // A missing asset, not a client-side route
+                return Err(warp::reject::not_found());
+            }
+            Ok(())
+        })
+        .untuple_one()
+        .and(warp::fs::file(index));
+
+    files
+        .or(fallback)
+        .unify()
+        .map(|file: File| {
+            let cache = cache_control(file.path());
+            warp::reply::with_header(file, CACHE_CONTROL, cache).into_response()
+        })
+        .boxed()
+}
+
+/// Whether `path` is or lies under a hidden file (.env, .git) of `base`;
+/// those are never served
+fn is_hidden(path: &Path, base: &Path) -> bool {
+    path.strip_prefix(base).map_or(true, |relative| {
+        relative
+            .components()
+            .any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+    })
+}
+
+/// Passes requests outside `/api` and `/metrics`
+fn not_reserved() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path::peek()
+        .and_then(|path: warp::path::Peek| async move {
+            let first = path.as_str().split('/').next().unwrap_or_default();
+            if first == "api" || first == "metrics" {
+                return Err(warp::reject::not_found());
+            }
+            Ok(())
+        })
+        .untuple_one()
+}
+
+/// Hashed assets (`app.3f9a1c2e.js`) never change under their name; anything
+/// else is revalidated on every use
+fn cache_control(path: &Path) -> &'static str {
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let mut parts: Vec<&str> = name.split(['.', '-']).collect();
+    // The last part is the extension
+    parts.pop();
+    let hashed = parts.iter().skip(1).any(|part| {
+        part.len() >= MIN_HASH_LEN
+            && part.chars().all(|c| c.is_ascii_alphanumeric())
+            && part.chars().any(|c| c.is_ascii_digit())
+    });
+    if hashed {
+        IMMUTABLE
+    } else {
+        NO_CACHE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_hashed_names_cached_for_good() {
+        assert_eq!(cache_control(Path::new("assets/app.3f9a1c2e.js")), IMMUTABLE);
+        assert_eq!(cache_control(Path::new("main-BkR7x2Qa9.css")), IMMUTABLE);
+        assert_eq!(cache_control(Path::new("index.html")), NO_CACHE);
+        assert_eq!(cache_control(Path::new("favicon.ico")), NO_CACHE);
+        assert_eq!(cache_control(Path::new("background.js")), NO_CACHE);
+    }
+
+    #[tokio::test]
+    async fn test_files_served_with_index_fallback_and_no_escape() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("secret.txt"), "secret").unwrap();
+        let site = root.path().join("site");
+        std::fs::create_dir_all(site.join("assets")).unwrap();
+        std::fs::write(site.join("index.html"), "<html>chat</html>").unwrap();
+        std::fs::write(site.join("assets/app.3f9a1c2e.js"), "app()").unwrap();
+        std::fs::write(site.join(".env"), "OPENAI_API_KEY=sk").unwrap();
+        std::fs::create_dir_all(site.join(".git")).unwrap();
+        std::fs::write(site.join(".git/config"), "[core]").unwrap();
+        let routes = static_site(site);
+        let get = |path: &str| warp::test::request().path(path).reply(&routes);
+
+        let resp = get("/assets/app.3f9a1c2e.js").await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()[CACHE_CONTROL], IMMUTABLE);
+
+        for path in ["/", "/chat/history"] {
+            let resp = get(path).await;
+            assert_eq!(resp.status(), 200, "{}", path);
+            assert_eq!(resp.body().as_ref(), b"<html>chat</html>");
+            assert_eq!(resp.headers()[CACHE_CONTROL], NO_CACHE);
+        }
+
+        let refused = [
+            "/../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/.env",
+            "/%2eenv",
+            "/%2Eenv",
+            "/missing.js",
+            "/api/x",
+        ];
+        for path in refused {
+            assert_eq!(get(path).await.status(), 404, "{}", path);
+        }
+
+        // Extension-less, so it gets the app rather than the hidden file
+        let resp = get("/%2egit/config").await;
+        assert_eq!(resp.body().as_ref(), b"<html>chat</html>");
+    }
+}
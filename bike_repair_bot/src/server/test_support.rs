@@ -0,0 +1,163 @@
+//! Shared fixtures for handler and route tests
+
+use std::sync::{Arc, RwLock};
+use tempfile::TempDir;
+
+use crate::ai::mock::MockProvider;
+use crate::ai::SYSTEM_PROMPT;
+use crate::config::Config;
+use crate::feedback::FeedbackStore;
+use crate::ingestion::{DocumentRegistry, Ingestor};
+use crate::metrics::Metrics;
+use crate::models::{ChunkMetadata, DocumentChunk};
+use crate::rag::{Retriever, VectorStore};
+use crate::readiness::ReadinessProbe;
+use crate::security::{BlockList, CircuitBreaker, QueryValidator, RateLimiter, RepeatGuard};
+use crate::server::{create_routes, AppState};
+use crate::sessions::{HistorySummarizer, MemorySessionStore};
+use crate::usage::{IpHasher, UsageLog, UsageStore};
+
+/// Build an `AppState` backed by the mock provider and a temporary vector store.
+/// The returned `TempDir` must be kept alive for the duration of the test.
+pub async fn test_state(config: Config, provider: Arc<MockProvider>) -> (AppState, TempDir) {
+    let dir = TempDir::new().unwrap();
+    let vector_store = Arc::new(VectorStore::new(
+        dir.path().to_str().unwrap(),
+        &config.vector_store_collection,
+        config.vector_distance,
+    )
+    .await
+    .unwrap());
+
+    let ingestor = Arc::new(Ingestor::new(&config, provider.clone(), vector_store.clone()));
+    let retriever = Arc::new(Retriever::new(
+        provider.clone(),
+        vector_store,
+        config.retrieval_top_k,
+        config.min_confidence,
+    ));
+
+    let state = AppState {
+        rate_limiter: Arc::new(RateLimiter::new(
+            config.max_requests_per_minute,
+            config.max_requests_per_hour,
+        )),
+        upload_rate_limiter: Arc::new(RateLimiter::new(
+            config.max_uploads_per_minute,
+            config.max_uploads_per_hour,
+        )),
+        feedback_rate_limiter: Arc::new(RateLimiter::new(
+            config.max_feedback_per_minute,
+            config.max_feedback_per_hour,
+        )),
+        admin_lockout: Arc::new(RateLimiter::new(
+            config.admin_max_failures_per_minute,
+            config.admin_max_failures_per_hour,
+        )),
+        repeat_guard: Arc::new(RepeatGuard::new(
+            config.repeat_query_limit,
+            config.repeat_query_window_seconds,
+        )),
+        query_validator: Arc::new(
+            QueryValidator::new()
+                .with_min_length(config.min_query_words, config.min_query_chars)
+                .with_max_special_char_ratio(config.max_special_char_ratio),
+        ),
+        circuit_breaker: Arc::new(CircuitBreaker::new(
+            config.circuit_breaker_threshold,
+            config.circuit_breaker_timeout_seconds,
+        )),
+        block_list: Arc::new(BlockList::new()),
+        metrics: Arc::new(Metrics::with_latency_buckets(config.metrics_latency_buckets.clone())),
+        system_prompt: Arc::new(RwLock::new(SYSTEM_PROMPT.to_string())),
+        ingestor,
+        documents: Arc::new(DocumentRegistry::new()),
+        sessions: Arc::new(MemorySessionStore::new(
+            config.session_max_messages,
+            config.session_ttl_seconds,
+        )),
+        history_summarizer: config.enable_history_summary.then(|| {
+            Arc::new(HistorySummarizer::new(
+                provider.clone(),
+                config.history_summary_threshold,
+                config.history_summary_keep_recent,
+            ))
+        }),
+        feedback: Arc::new(FeedbackStore::open(dir.path().join("feedback.db")).await.unwrap()),
+        usage: Arc::new(UsageLog::start(
+            UsageStore::open(dir.path().join("usage.db")).await.unwrap(),
+            IpHasher::new("test"),
+        )),
+        tasks: Arc::new(crate::tasks::TaskRegistry::new()),
+        readiness: Arc::new(ReadinessProbe::new(config.readiness_embedding_interval_seconds)),
+        maintenance: Arc::new(crate::maintenance::Maintenance::from_config(&config)),
+        config: Arc::new(config),
+        ai_provider: provider,
+        retriever,
+    };
+
+    (state, dir)
+}
+
+/// Build an embedded chunk for the given bike model and page
+pub fn chunk(bike_model: &str, page: u32, text: &str) -> DocumentChunk {
+    let mut metadata = ChunkMetadata::new(bike_model);
+    metadata.page_number = Some(page);
+    DocumentChunk::new("doc-1", text, metadata).with_embedding(MockProvider::embed(text))
+}
+
+/// Insert chunks into the state's vector store
+pub async fn seed(state: &AppState, chunks: Vec<DocumentChunk>) {
+    state.retriever.vector_store().upsert(chunks).await.unwrap();
+}
+
+/// A bike-related query the validator lets through
+pub const QUERY: &str = "How do I adjust the drive chain?";
+
+/// Send a chat request and return its body, asserting a 200
+pub async fn post_chat(state: AppState, body: serde_json::Value) -> serde_json::Value {
+    let routes = create_routes(state);
+    let resp = warp::test::request()
+        .method("POST")
+        .path("/api/chat")
+        .json(&body)
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 200);
+    serde_json::from_slice(resp.body()).unwrap()
+}
+
+/// Send a chat request from the given IP and return its status
+pub async fn chat_from(state: AppState, ip: &str) -> u16 {
+    let routes = create_routes(state);
+    warp::test::request()
+        .method("POST")
+        .path("/api/chat")
+        .remote_addr(std::net::SocketAddr::new(ip.parse().unwrap(), 40000))
+        .json(&serde_json::json!({ "query": QUERY }))
+        .reply(&routes)
+        .await
+        .status()
+        .as_u16()
+}
+
+/// A state with tenants `dealer-a` (`key-a`) and `dealer-b` (`key-b`), one
+/// chunk each, and admin key `secret`
+pub async fn tenant_state() -> (AppState, TempDir) {
+    let config = Config {
+        tenant_api_keys: crate::security::TenantApiKeys::parse(
+            "dealer-a:key-a, dealer-b:key-b",
+        )
+        .unwrap(),
+        admin_api_key: Some("secret".to_string()),
+        min_confidence: 0.0,
+        ..Config::default()
+    };
+    let (state, dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+    let mut a = chunk("Honda CBR600RR", 10, "Dealer A chain adjustment procedure");
+    a.metadata.tenant_id = "dealer-a".to_string();
+    let mut b = chunk("Honda CBR600RR", 20, "Dealer B chain adjustment procedure");
+    b.metadata.tenant_id = "dealer-b".to_string();
+    seed(&state, vec![a, b]).await;
+    (state, dir)
+}
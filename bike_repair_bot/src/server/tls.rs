@@ -0,0 +1,401 @@
+//! HTTPS with rustls when `TLS_CERT_PATH` and `TLS_KEY_PATH` are set
+
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::WebPkiSupportedAlgorithms;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::{
+    self, ClientConfig, ClientConnection, Connection, DigitallySignedStruct, ServerConfig,
+    ServerConnection, SignatureScheme,
+};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use warp::hyper::server::accept::Accept;
+use warp::{reject::Rejection, Filter, Reply};
+
+/// TLS handshakes in progress at once; further connections wait to be accepted
+const MAX_CONCURRENT_HANDSHAKES: usize = 64;
+
+/// Time a client gets to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause after a failed `accept`, so running out of file descriptors does not
+/// spin the loop
+const ACCEPT_ERROR_PAUSE: Duration = Duration::from_millis(100);
+
+/// Certificate chain and key presented to clients. `reload` reads the files
+/// again, so a renewed certificate is served without a restart.
+#[derive(Debug)]
+pub struct CertificateStore {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertificateStore {
+    /// Load the PEM certificate chain and private key, failing when either is
+    /// unreadable or the key does not belong to the certificate
+    pub fn load(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Result<Self> {
+        let cert_path = cert_path.into();
+        let key_path = key_path.into();
+        let current = RwLock::new(load_certified_key(&cert_path, &key_path)?);
+        Ok(Self { cert_path, key_path, current })
+    }
+
+    /// Load the files again. When they are not valid the previous certificate
+    /// stays in use.
+    pub fn reload(&self) -> Result<()> {
+        let key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = key;
+        log::info!("🔐 Reloaded TLS certificate from {}", self.cert_path.display());
+        Ok(())
+    }
+
+    /// Acceptor of TLS connections presenting the store's current certificate
+    pub fn acceptor(self: Arc<Self>) -> TlsAcceptor {
+        let mut config = ServerConfig::builder().with_no_client_auth().with_cert_resolver(self);
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        TlsAcceptor::from(Arc::new(config))
+    }
+}
+
+impl ResolvesServerCert for CertificateStore {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Reload `store` on every SIGHUP until the task is aborted
+#[cfg(unix)]
+pub async fn reload_on_sighup(store: Arc<CertificateStore>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            log::error!("Failed to listen for SIGHUP, TLS certificate reload disabled: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        if let Err(e) = store.reload() {
+            log::error!("TLS certificate reload failed, keeping the current one: {:#}", e);
+        }
+    }
+}
+
+/// TLS connections accepted on `listener`. Handshakes run concurrently; one
+/// that fails or times out drops its connection without affecting the others.
+pub fn incoming(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+) -> impl Accept<Conn = TlsStream<TcpStream>, Error = std::io::Error> {
+    let connections = futures::stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await;
+        Some((accepted, listener))
+    })
+    .filter_map(|accepted| async move {
+        match accepted {
+            Ok((tcp, _)) => Some(tcp),
+            Err(e) => {
+                log::warn!("Failed to accept a connection: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_PAUSE).await;
+                None
+            }
+        }
+    })
+    .map(move |tcp| tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)))
+    .buffer_unordered(MAX_CONCURRENT_HANDSHAKES)
+    .filter_map(|handshake| async move {
+        match handshake {
+            Ok(Ok(stream)) => Some(Ok(stream)),
+            Ok(Err(e)) => {
+                log::debug!("TLS handshake failed: {}", e);
+                None
+            }
+            Err(_) => {
+                log::debug!("TLS handshake timed out");
+                None
+            }
+        }
+    });
+    warp::hyper::server::accept::from_stream(connections)
+}
+
+/// Plain-HTTP listener answering every request with a permanent redirect to
+/// the same host and path on the HTTPS port
+pub fn https_redirect(
+    https_port: u16,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("host")
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(move |host: Option<String>, path: warp::path::FullPath, query: String| {
+            let location = host
+                .map(|host| https_location(&host, https_port, path.as_str(), &query))
+                .and_then(|location| location.parse::<warp::http::Uri>().ok());
+            match location {
+                Some(uri) => warp::redirect::permanent(uri).into_response(),
+                None => warp::reply::with_status(
+                    "A valid Host header is required",
+                    warp::http::StatusCode::BAD_REQUEST,
+                )
+                .into_response(),
+            }
+        })
+}
+
+/// `https://` URL of `path` on the host named in a `Host` header
+fn https_location(host: &str, https_port: u16, path: &str, query: &str) -> String {
+    // Drop the plain-HTTP port, keeping IPv6 literals (`[::1]:80`) intact
+    let name = match host.find(']') {
+        Some(end) => &host[..=end],
+        None => host.split(':').next().unwrap_or(host),
+    };
+    let port = if https_port == 443 { String::new() } else { format!(":{}", https_port) };
+    let query = if query.is_empty() { String::new() } else { format!("?{}", query) };
+    format!("https://{}{}{}{}", name, port, path, query)
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<Arc<CertifiedKey>> {
+    let certs = rustls_pemfile::certs(&mut open(cert_path, "TLS_CERT_PATH")?)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("TLS_CERT_PATH {} is not a PEM file", cert_path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("TLS_CERT_PATH {} holds no PEM certificate", cert_path.display());
+    }
+
+    let key = rustls_pemfile::private_key(&mut open(key_path, "TLS_KEY_PATH")?)
+        .with_context(|| format!("TLS_KEY_PATH {} is not a PEM file", key_path.display()))?
+        .with_context(|| format!("TLS_KEY_PATH {} holds no private key", key_path.display()))?;
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .with_context(|| format!("TLS_KEY_PATH {} holds an unsupported key", key_path.display()))?;
+
+    let certified = Arc::new(CertifiedKey::new(certs, signing_key));
+    check_key_matches(&certified).with_context(|| {
+        format!(
+            "TLS_KEY_PATH {} does not match the certificate in TLS_CERT_PATH {}",
+            key_path.display(),
+            cert_path.display()
+        )
+    })?;
+    Ok(certified)
+}
+
+fn open(path: &Path, setting: &str) -> Result<BufReader<std::fs::File>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Cannot read {} {}", setting, path.display()))?;
+    Ok(BufReader::new(file))
+}
+
+/// Complete a handshake in memory with `key`. The client checks the server's
+/// signature against the certificate's public key, which fails when the key
+/// belongs to another certificate.
+fn check_key_matches(key: &Arc<CertifiedKey>) -> Result<()> {
+    let server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(FixedKey(key.clone())));
+    let client_config = ClientConfig::builder_with_protocol_versions(&[&rustls::version::TLS13])
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SignatureOnly::new()))
+        .with_no_client_auth();
+
+    let server_name = ServerName::try_from("localhost")?;
+    let mut client = Connection::from(ClientConnection::new(Arc::new(client_config), server_name)?);
+    let mut server = Connection::from(ServerConnection::new(Arc::new(server_config))?);
+    // A TLS 1.3 handshake takes two round trips
+    for _ in 0..4 {
+        if !client.is_handshaking() && !server.is_handshaking() {
+            return Ok(());
+        }
+        transfer(&mut client, &mut server)?;
+        transfer(&mut server, &mut client)?;
+    }
+    anyhow::bail!("TLS handshake did not complete")
+}
+
+/// Deliver the TLS records `from` has queued to `to`
+fn transfer(from: &mut Connection, to: &mut Connection) -> Result<()> {
+    let mut records = Vec::new();
+    while from.wants_write() {
+        from.write_tls(&mut records)?;
+    }
+    let mut records = records.as_slice();
+    while !records.is_empty() {
+        to.read_tls(&mut records)?;
+        to.process_new_packets()?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct FixedKey(Arc<CertifiedKey>);
+
+impl ResolvesServerCert for FixedKey {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
+
+/// Trusts any certificate, but checks that handshake signatures were made with
+/// its key
+#[derive(Debug)]
+struct SignatureOnly(WebPkiSupportedAlgorithms);
+
+impl SignatureOnly {
+    fn new() -> Self {
+        Self(rustls::crypto::ring::default_provider().signature_verification_algorithms)
+    }
+}
+
+impl ServerCertVerifier for SignatureOnly {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::MockProvider;
+    use crate::config::Config;
+    use crate::server::test_support::test_state;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls");
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(FIXTURES).join(name)
+    }
+
+    fn served_cert(store: &CertificateStore) -> CertificateDer<'static> {
+        store.current.read().unwrap().cert[0].clone()
+    }
+
+    #[test]
+    fn test_certificate_loaded_and_bad_files_refused() {
+        assert!(CertificateStore::load(fixture("cert.pem"), fixture("key.pem")).is_ok());
+
+        let mismatched = CertificateStore::load(fixture("cert.pem"), fixture("other_key.pem"));
+        let message = format!("{:#}", mismatched.unwrap_err());
+        assert!(message.contains("does not match the certificate"), "{}", message);
+
+        let missing = CertificateStore::load(fixture("missing.pem"), fixture("key.pem"));
+        assert!(format!("{:#}", missing.unwrap_err()).contains("Cannot read TLS_CERT_PATH"));
+
+        let swapped = CertificateStore::load(fixture("key.pem"), fixture("cert.pem"));
+        assert!(format!("{:#}", swapped.unwrap_err()).contains("holds no PEM certificate"));
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_current_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+        std::fs::copy(fixture("cert.pem"), &cert).unwrap();
+        std::fs::copy(fixture("key.pem"), &key).unwrap();
+        let store = CertificateStore::load(&cert, &key).unwrap();
+        let before = served_cert(&store);
+
+        std::fs::copy(fixture("other_key.pem"), &key).unwrap();
+        assert!(store.reload().is_err());
+        assert_eq!(served_cert(&store), before);
+
+        std::fs::copy(fixture("key.pem"), &key).unwrap();
+        store.reload().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_plain_http_redirected_to_https_port() {
+        let redirect = |port, host: Option<&str>, path: &str| {
+            let mut request = warp::test::request().path(path);
+            if let Some(host) = host {
+                request = request.header("host", host);
+            }
+            let filter = https_redirect(port);
+            async move { request.reply(&filter).await }
+        };
+
+        let resp = redirect(8443, Some("shop.example:8080"), "/api/status?verbose=1").await;
+        assert_eq!(resp.status(), 308);
+        assert_eq!(resp.headers()["location"], "https://shop.example:8443/api/status?verbose=1");
+
+        let resp = redirect(443, Some("[::1]:80"), "/").await;
+        assert_eq!(resp.headers()["location"], "https://[::1]/");
+
+        assert_eq!(redirect(443, None, "/").await.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_server_answers_over_https() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let config = Config {
+            tls_cert_path: Some(fixture("cert.pem").display().to_string()),
+            tls_key_path: Some(fixture("key.pem").display().to_string()),
+            ..Config::default()
+        };
+        let (state, _dir) = test_state(config, Arc::new(MockProvider::new("ok"))).await;
+        let (signal, shutdown) = tokio::sync::oneshot::channel::<()>();
+        let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+        let server = tokio::spawn(crate::server::run_server(state, addr, async {
+            let _ = shutdown.await;
+        }));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let client_config = ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(SignatureOnly::new()))
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut tls = connector.connect(server_name, tcp).await.unwrap();
+        tls.write_all(b"GET /api/health HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        tls.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        signal.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,640 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::ai::{
+    build_history_summary_prompt, AiProvider, CompletionOptions, HISTORY_SUMMARY_PREFIX,
+};
+use crate::models::{Message, Role};
+use crate::security::{Clock, SystemClock};
+
+pub mod sqlite;
+
+pub use sqlite::SqliteSessionStore;
+
+/// Conversation of one chat session
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// Past user queries and assistant replies, oldest first
+    pub messages: Vec<Message>,
+    pub created_at: DateTime<Utc>,
+    pub last_active: DateTime<Utc>,
+    /// Motorcycle the conversation is about, used when a request names none
+    pub bike: Option<BikeContext>,
+    /// Tokens the conversation has cost, as of `tokens_updated_at`
+    pub tokens_used: u64,
+    pub tokens_updated_at: DateTime<Utc>,
+}
+
+impl Session {
+    /// Tokens counted against the session at `now`, `refill_per_hour` having
+    /// been paid back every hour since the last charge
+    pub fn tokens_used_at(&self, now: DateTime<Utc>, refill_per_hour: u64) -> u64 {
+        refilled(self.tokens_used, self.tokens_updated_at, now, refill_per_hour)
+    }
+}
+
+/// Motorcycle a user has named in a conversation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BikeContext {
+    pub model: String,
+    pub year: Option<u32>,
+}
+
+impl fmt::Display for BikeContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.year {
+            Some(year) => write!(f, "{} {}", year, self.model),
+            None => f.write_str(&self.model),
+        }
+    }
+}
+
+/// Overview of an active session, for the admin listing
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub tenant_id: String,
+    pub session_id: String,
+    pub message_count: usize,
+    pub created_at: DateTime<Utc>,
+    pub last_active: DateTime<Utc>,
+    pub bike_model: Option<String>,
+    pub bike_year: Option<u32>,
+    pub tokens_used: u64,
+}
+
+/// Chat sessions by tenant and session id, forgotten once idle for the TTL
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// The session, unless it is unknown or has been idle beyond the TTL
+    async fn get(&self, tenant_id: &str, session_id: &str) -> Result<Option<Session>>;
+
+    /// Append a query and its reply (an assistant message) to the session,
+    /// creating it if needed. A `bike` replaces the one remembered for the session.
+    async fn record(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+        query: &str,
+        reply: Message,
+        bike: Option<BikeContext>,
+    ) -> Result<()>;
+
+    /// Replace the oldest messages of the session with `replacement`, provided
+    /// they are still `prefix`; returns whether they were
+    async fn replace_prefix(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+        prefix: &[Message],
+        replacement: Message,
+    ) -> Result<bool>;
+
+    /// Replace the session's last message with `replacement`, provided it is
+    /// still `previous`; returns whether it was
+    async fn replace_last(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+        previous: &Message,
+        replacement: Message,
+    ) -> Result<bool>;
+
+    /// Count `tokens` against the session, after paying back `refill_per_hour`
+    /// for every hour since its last charge; returns the new count, or 0 when
+    /// the session is not stored
+    async fn add_tokens(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+        tokens: u64,
+        refill_per_hour: u64,
+    ) -> Result<u64>;
+
+    /// Forget the session; returns whether it existed
+    async fn delete(&self, tenant_id: &str, session_id: &str) -> Result<bool>;
+
+    /// Sessions not yet expired, most recently active first
+    async fn list(&self) -> Result<Vec<SessionSummary>>;
+
+    /// Drop sessions idle beyond the TTL (should be called periodically);
+    /// returns how many were dropped
+    async fn cleanup_expired(&self) -> Result<usize>;
+
+    /// Write everything to durable storage before the process exits
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Where chat sessions are kept (`SESSION_STORE`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SessionBackend {
+    /// Lost on restart
+    #[default]
+    Memory,
+    /// SQLite database at `SESSION_DB_PATH`
+    Sqlite,
+}
+
+impl FromStr for SessionBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "memory" => Ok(SessionBackend::Memory),
+            "sqlite" => Ok(SessionBackend::Sqlite),
+            other => anyhow::bail!(
+                "Unknown session store '{}' (expected memory or sqlite)",
+                other
+            ),
+        }
+    }
+}
+
+/// Whether a session last active at `last_active` has expired by `now`
+fn is_expired(last_active: DateTime<Utc>, now: DateTime<Utc>, ttl: Duration) -> bool {
+    (now - last_active).to_std().unwrap_or_default() >= ttl
+}
+
+/// `used` tokens less what `per_hour` pays back between `since` and `now`
+fn refilled(used: u64, since: DateTime<Utc>, now: DateTime<Utc>, per_hour: u64) -> u64 {
+    let elapsed_ms = (now - since).num_milliseconds().max(0) as u64;
+    used.saturating_sub(per_hour.saturating_mul(elapsed_ms) / 3_600_000)
+}
+
+/// Number of oldest messages to drop so that at most `max_messages` remain,
+/// rounded up to whole exchanges so the history never starts with a reply
+fn excess_messages(len: usize, max_messages: usize) -> usize {
+    let excess = len.saturating_sub(max_messages);
+    excess + excess % 2
+}
+
+/// Whether two messages are the same stored message
+fn same_message(a: &Message, b: &Message) -> bool {
+    a.role == b.role && a.content == b.content && a.timestamp == b.timestamp
+}
+
+/// Sessions kept in memory, lost on restart
+pub struct MemorySessionStore<C: Clock = SystemClock> {
+    /// Messages kept per session; the oldest are dropped first (0 disables history)
+    max_messages: usize,
+
+    /// How long an idle session is kept
+    ttl: Duration,
+
+    sessions: DashMap<(String, String), Session>,
+
+    clock: C,
+}
+
+impl MemorySessionStore {
+    pub fn new(max_messages: usize, ttl_seconds: u64) -> Self {
+        Self::with_clock(max_messages, ttl_seconds, SystemClock)
+    }
+}
+
+impl<C: Clock> MemorySessionStore<C> {
+    pub fn with_clock(max_messages: usize, ttl_seconds: u64, clock: C) -> Self {
+        Self {
+            max_messages,
+            ttl: Duration::from_secs(ttl_seconds),
+            sessions: DashMap::new(),
+            clock,
+        }
+    }
+
+    /// Number of sessions held, including expired ones not yet evicted
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+}
+
+#[async_trait]
+impl<C: Clock> SessionStore for MemorySessionStore<C> {
+    async fn get(&self, tenant_id: &str, session_id: &str) -> Result<Option<Session>> {
+        let now = self.clock.now_utc();
+        Ok(self
+            .sessions
+            .get(&(tenant_id.to_string(), session_id.to_string()))
+            .filter(|session| !is_expired(session.last_active, now, self.ttl))
+            .map(|session| session.clone()))
+    }
+
+    async fn record(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+        query: &str,
+        reply: Message,
+        bike: Option<BikeContext>,
+    ) -> Result<()> {
+        if self.max_messages == 0 {
+            return Ok(());
+        }
+
+        let now = self.clock.now_utc();
+        let key = (tenant_id.to_string(), session_id.to_string());
+        let mut session = self.sessions.entry(key).or_insert_with(|| Session {
+            messages: Vec::new(),
+            created_at: now,
+            last_active: now,
+            bike: None,
+            tokens_used: 0,
+            tokens_updated_at: now,
+        });
+        // An expired session that was not evicted yet starts over
+        if is_expired(session.last_active, now, self.ttl) {
+            session.messages.clear();
+            session.created_at = now;
+            session.bike = None;
+            (session.tokens_used, session.tokens_updated_at) = (0, now);
+        }
+
+        session.messages.push(Message::user(query));
+        session.messages.push(reply);
+        let excess = excess_messages(session.messages.len(), self.max_messages);
+        session.messages.drain(..excess);
+        session.last_active = now;
+        if bike.is_some() {
+            session.bike = bike;
+        }
+        Ok(())
+    }
+
+    async fn replace_prefix(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+        prefix: &[Message],
+        replacement: Message,
+    ) -> Result<bool> {
+        let key = (tenant_id.to_string(), session_id.to_string());
+        let Some(mut session) = self.sessions.get_mut(&key) else {
+            return Ok(false);
+        };
+        let unchanged = session.messages.len() >= prefix.len()
+            && session
+                .messages
+                .iter()
+                .zip(prefix)
+                .all(|(a, b)| same_message(a, b));
+        if unchanged {
+            session.messages.splice(..prefix.len(), [replacement]);
+        }
+        Ok(unchanged)
+    }
+
+    async fn replace_last(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+        previous: &Message,
+        replacement: Message,
+    ) -> Result<bool> {
+        let key = (tenant_id.to_string(), session_id.to_string());
+        let Some(mut session) = self.sessions.get_mut(&key) else {
+            return Ok(false);
+        };
+        match session.messages.last_mut() {
+            Some(last) if same_message(last, previous) => *last = replacement,
+            _ => return Ok(false),
+        }
+        session.last_active = self.clock.now_utc();
+        Ok(true)
+    }
+
+    async fn add_tokens(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+        tokens: u64,
+        refill_per_hour: u64,
+    ) -> Result<u64> {
+        let key = (tenant_id.to_string(), session_id.to_string());
+        let Some(mut session) = self.sessions.get_mut(&key) else {
+            return Ok(0);
+        };
+        let now = self.clock.now_utc();
+        session.tokens_used = session.tokens_used_at(now, refill_per_hour) + tokens;
+        session.tokens_updated_at = now;
+        Ok(session.tokens_used)
+    }
+
+    async fn delete(&self, tenant_id: &str, session_id: &str) -> Result<bool> {
+        let key = (tenant_id.to_string(), session_id.to_string());
+        Ok(self.sessions.remove(&key).is_some())
+    }
+
+    async fn list(&self) -> Result<Vec<SessionSummary>> {
+        let now = self.clock.now_utc();
+        let mut sessions: Vec<SessionSummary> = self
+            .sessions
+            .iter()
+            .filter(|entry| !is_expired(entry.last_active, now, self.ttl))
+            .map(|entry| {
+                let (tenant_id, session_id) = entry.key().clone();
+                SessionSummary {
+                    tenant_id,
+                    session_id,
+                    message_count: entry.messages.len(),
+                    created_at: entry.created_at,
+                    last_active: entry.last_active,
+                    bike_model: entry.bike.as_ref().map(|bike| bike.model.clone()),
+                    bike_year: entry.bike.as_ref().and_then(|bike| bike.year),
+                    tokens_used: entry.tokens_used,
+                }
+            })
+            .collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_active));
+        Ok(sessions)
+    }
+
+    async fn cleanup_expired(&self) -> Result<usize> {
+        let now = self.clock.now_utc();
+        let before = self.sessions.len();
+        self.sessions
+            .retain(|_, session| !is_expired(session.last_active, now, self.ttl));
+        log::debug!("Session cleanup: {} active sessions", self.sessions.len());
+        Ok(before.saturating_sub(self.sessions.len()))
+    }
+}
+
+/// Compresses the older turns of long session histories into a summary
+/// message, keeping the most recent turns verbatim
+pub struct HistorySummarizer {
+    provider: Arc<dyn AiProvider>,
+
+    /// Histories with more messages than this are summarized
+    threshold: usize,
+
+    /// Newest messages left out of the summary
+    keep_recent: usize,
+}
+
+impl HistorySummarizer {
+    pub fn new(provider: Arc<dyn AiProvider>, threshold: usize, keep_recent: usize) -> Self {
+        Self {
+            provider,
+            threshold,
+            keep_recent,
+        }
+    }
+
+    /// Summarize the older turns of the session once its history exceeds the
+    /// threshold. Returns whether the history was compressed; it is left alone
+    /// if it changed while the model was summarizing.
+    pub async fn compact(
+        &self,
+        store: &dyn SessionStore,
+        tenant_id: &str,
+        session_id: &str,
+    ) -> Result<bool> {
+        let Some(session) = store.get(tenant_id, session_id).await? else {
+            return Ok(false);
+        };
+        let messages = session.messages;
+        if messages.len() <= self.threshold {
+            return Ok(false);
+        }
+
+        // Keep whole exchanges: the recent part starts with a user message
+        let mut split = messages.len().saturating_sub(self.keep_recent);
+        while split > 0 && messages[split].role != Role::User {
+            split -= 1;
+        }
+        if split < 2 {
+            return Ok(false);
+        }
+
+        let older = &messages[..split];
+        let options = CompletionOptions {
+            max_tokens: Some(300),
+            ..Default::default()
+        };
+        let summary = self
+            .provider
+            .complete(build_history_summary_prompt(older), options)
+            .await?;
+        let summary = Message::system(format!("{} {}", HISTORY_SUMMARY_PREFIX, summary.trim()));
+        store
+            .replace_prefix(tenant_id, session_id, older, summary)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::MockProvider;
+    use crate::security::MockClock;
+
+    fn contents(session: &Session) -> Vec<&str> {
+        session
+            .messages
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_history_capped_to_newest_messages() {
+        let store = MemorySessionStore::new(4, 60);
+        let bike = BikeContext {
+            model: "Yamaha R1".to_string(),
+            year: Some(2019),
+        };
+
+        store
+            .record("default", "s1", "q1", Message::assistant("a1"), Some(bike.clone()))
+            .await
+            .unwrap();
+        store
+            .record("default", "s1", "q2", Message::assistant("a2"), None)
+            .await
+            .unwrap();
+        store
+            .record("default", "s1", "q3", Message::assistant("a3"), None)
+            .await
+            .unwrap();
+
+        let session = store.get("default", "s1").await.unwrap().unwrap();
+        assert_eq!(contents(&session), ["q2", "a2", "q3", "a3"]);
+        assert_eq!(session.bike, Some(bike));
+        // Sessions are per tenant
+        assert!(store.get("acme", "s1").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_idle_sessions_expire_and_are_evicted() {
+        let clock = MockClock::new();
+        let store = MemorySessionStore::with_clock(10, 60, clock.clone());
+        store
+            .record("default", "idle", "q1", Message::assistant("a1"), None)
+            .await
+            .unwrap();
+        clock.advance(Duration::from_secs(30));
+        store
+            .record("default", "active", "q1", Message::assistant("a1"), None)
+            .await
+            .unwrap();
+
+        clock.advance(Duration::from_secs(31));
+        assert!(store.get("default", "idle").await.unwrap().is_none());
+        assert!(store.get("default", "active").await.unwrap().is_some());
+
+        assert_eq!(store.cleanup_expired().await.unwrap(), 1);
+        assert_eq!(store.len(), 1);
+
+        // Writing to an expired session starts a fresh conversation
+        clock.advance(Duration::from_secs(60));
+        store
+            .record("default", "active", "q2", Message::assistant("a2"), None)
+            .await
+            .unwrap();
+        let session = store.get("default", "active").await.unwrap().unwrap();
+        assert_eq!(contents(&session), ["q2", "a2"]);
+    }
+
+    #[tokio::test]
+    async fn test_session_tokens_refill_over_time() {
+        let clock = MockClock::new();
+        let store = MemorySessionStore::with_clock(10, 864000, clock.clone());
+        assert_eq!(store.add_tokens("default", "s1", 500, 100).await.unwrap(), 0);
+        store
+            .record("default", "s1", "q1", Message::assistant("a1"), None)
+            .await
+            .unwrap();
+
+        assert_eq!(store.add_tokens("default", "s1", 500, 100).await.unwrap(), 500);
+        clock.advance(Duration::from_secs(1800));
+        let session = store.get("default", "s1").await.unwrap().unwrap();
+        assert_eq!(session.tokens_used_at(clock.now_utc(), 100), 450);
+        assert_eq!(store.add_tokens("default", "s1", 200, 100).await.unwrap(), 650);
+        // Without a refill the count only grows
+        clock.advance(Duration::from_secs(7200));
+        assert_eq!(store.add_tokens("default", "s1", 10, 0).await.unwrap(), 660);
+        clock.advance(Duration::from_secs(86400));
+        assert_eq!(store.add_tokens("default", "s1", 10, 100).await.unwrap(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_list_and_delete_sessions() {
+        let clock = MockClock::new();
+        let store = MemorySessionStore::with_clock(10, 60, clock.clone());
+        store
+            .record("default", "old", "q1", Message::assistant("a1"), None)
+            .await
+            .unwrap();
+        clock.advance(Duration::from_secs(10));
+        store
+            .record(
+                "acme",
+                "new",
+                "q1",
+                Message::assistant("a1"),
+                Some(BikeContext {
+                    model: "Honda CBR600RR".to_string(),
+                    year: None,
+                }),
+            )
+            .await
+            .unwrap();
+        store
+            .record("acme", "new", "q2", Message::assistant("a2"), None)
+            .await
+            .unwrap();
+
+        let listed = store.list().await.unwrap();
+        let ids: Vec<&str> = listed.iter().map(|s| s.session_id.as_str()).collect();
+        assert_eq!(ids, ["new", "old"]);
+        assert_eq!(listed[0].tenant_id, "acme");
+        assert_eq!(listed[0].message_count, 4);
+
+        assert!(store.delete("default", "old").await.unwrap());
+        assert!(!store.delete("default", "old").await.unwrap());
+        assert!(store.get("default", "old").await.unwrap().is_none());
+
+        // Expired sessions are not listed
+        clock.advance(Duration::from_secs(60));
+        assert!(store.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_old_turns_summarized_and_recent_kept() {
+        let provider = Arc::new(MockProvider::new("R6 idles rough; plugs already replaced."));
+        let summarizer = HistorySummarizer::new(provider.clone(), 6, 4);
+        let store = MemorySessionStore::new(20, 60);
+        for i in 1..=3 {
+            let (query, reply) = (format!("q{}", i), format!("a{}", i));
+            store
+                .record("default", "s1", &query, Message::assistant(reply), None)
+                .await
+                .unwrap();
+        }
+        assert!(!summarizer.compact(&store, "default", "s1").await.unwrap());
+
+        store
+            .record("default", "s1", "q4", Message::assistant("a4"), None)
+            .await
+            .unwrap();
+        assert!(summarizer.compact(&store, "default", "s1").await.unwrap());
+
+        let session = store.get("default", "s1").await.unwrap().unwrap();
+        assert_eq!(
+            contents(&session),
+            [
+                "Summary of the earlier conversation: R6 idles rough; plugs already replaced.",
+                "q3",
+                "a3",
+                "q4",
+                "a4"
+            ]
+        );
+        assert_eq!(session.messages[0].role, Role::System);
+        let transcript = &provider.last_prompt().unwrap()[1].content;
+        assert!(transcript.contains("q1") && transcript.contains("a2"));
+        assert!(!transcript.contains("q3"));
+    }
+
+    #[tokio::test]
+    async fn test_changed_history_not_replaced() {
+        let store = MemorySessionStore::new(20, 60);
+        store
+            .record("default", "s1", "q1", Message::assistant("a1"), None)
+            .await
+            .unwrap();
+        let stale = vec![Message::user("q0"), Message::assistant("a0")];
+
+        let replaced = store
+            .replace_prefix("default", "s1", &stale, Message::system("summary"))
+            .await
+            .unwrap();
+
+        assert!(!replaced);
+        let session = store.get("default", "s1").await.unwrap().unwrap();
+        assert_eq!(contents(&session), ["q1", "a1"]);
+    }
+
+    #[tokio::test]
+    async fn test_zero_limit_disables_history() {
+        let store = MemorySessionStore::new(0, 60);
+
+        store
+            .record("default", "s1", "q1", Message::assistant("a1"), None)
+            .await
+            .unwrap();
+
+        assert!(store.is_empty());
+    }
+}
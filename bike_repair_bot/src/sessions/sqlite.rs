@@ -0,0 +1,568 @@
+//! Chat sessions in a SQLite database, so conversations survive restarts
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use rusqlite::{params, OptionalExtension, Transaction};
+use std::path::Path;
+use std::time::Duration;
+
+use super::{
+    excess_messages, refilled, same_message, BikeContext, Session, SessionStore, SessionSummary,
+};
+use crate::models::{Message, Role};
+use crate::security::{Clock, SystemClock};
+use crate::sqlite::Database;
+
+/// Schema changes, applied in order at startup; `PRAGMA user_version` counts
+/// the ones already applied
+const MIGRATIONS: &[&str] = &[
+    r#"
+    CREATE TABLE sessions (
+        tenant_id   TEXT NOT NULL,
+        session_id  TEXT NOT NULL,
+        created_at  INTEGER NOT NULL,
+        last_active INTEGER NOT NULL,
+        bike_model  TEXT,
+        PRIMARY KEY (tenant_id, session_id)
+    );
+    CREATE INDEX sessions_last_active ON sessions (last_active);
+
+    CREATE TABLE messages (
+        id         INTEGER PRIMARY KEY,
+        tenant_id  TEXT NOT NULL,
+        session_id TEXT NOT NULL,
+        role       TEXT NOT NULL,
+        content    TEXT NOT NULL,
+        timestamp  TEXT,
+        FOREIGN KEY (tenant_id, session_id)
+            REFERENCES sessions (tenant_id, session_id) ON DELETE CASCADE
+    );
+    CREATE INDEX messages_session ON messages (tenant_id, session_id, id);
+"#,
+    "ALTER TABLE sessions ADD COLUMN bike_year INTEGER;",
+    r#"
+    ALTER TABLE messages ADD COLUMN response_id TEXT;
+    ALTER TABLE messages ADD COLUMN source_ids TEXT;
+"#,
+    r#"
+    ALTER TABLE sessions ADD COLUMN tokens_used INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE sessions ADD COLUMN tokens_updated_at INTEGER;
+"#,
+];
+
+/// Sessions in a SQLite database file
+pub struct SqliteSessionStore<C: Clock = SystemClock> {
+    db: Database,
+
+    /// Messages kept per session; the oldest are dropped first (0 disables history)
+    max_messages: usize,
+
+    /// How long an idle session is kept
+    ttl: Duration,
+
+    clock: C,
+}
+
+impl SqliteSessionStore {
+    /// Open (or create) the database at `path` and bring its schema up to date
+    pub async fn open(
+        path: impl AsRef<Path>,
+        max_messages: usize,
+        ttl_seconds: u64,
+    ) -> Result<Self> {
+        Self::open_with_clock(path, max_messages, ttl_seconds, SystemClock).await
+    }
+}
+
+impl<C: Clock> SqliteSessionStore<C> {
+    pub async fn open_with_clock(
+        path: impl AsRef<Path>,
+        max_messages: usize,
+        ttl_seconds: u64,
+        clock: C,
+    ) -> Result<Self> {
+        Ok(Self {
+            db: Database::open(path, "session", MIGRATIONS).await?,
+            max_messages,
+            ttl: Duration::from_secs(ttl_seconds),
+            clock,
+        })
+    }
+
+    /// Sessions last active at or before this time (in ms) have expired
+    fn cutoff_ms(&self) -> i64 {
+        (self.clock.now_utc() - self.ttl).timestamp_millis()
+    }
+}
+
+fn timestamp_to_sql(timestamp: Option<DateTime<Utc>>) -> Option<String> {
+    timestamp.map(|t| t.to_rfc3339_opts(SecondsFormat::Nanos, true))
+}
+
+fn timestamp_from_sql(timestamp: Option<String>) -> Option<DateTime<Utc>> {
+    timestamp
+        .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+        .map(|t| t.with_timezone(&Utc))
+}
+
+fn datetime_from_millis(millis: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(millis).single().unwrap_or_default()
+}
+
+fn insert_message(
+    tx: &Transaction,
+    id: Option<i64>,
+    key: &(String, String),
+    message: &Message,
+) -> Result<()> {
+    tx.execute(
+        "INSERT INTO messages
+             (id, tenant_id, session_id, role, content, timestamp, response_id, source_ids)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            id,
+            key.0,
+            key.1,
+            message.role.as_str(),
+            message.content,
+            timestamp_to_sql(message.timestamp),
+            message.response_id,
+            serde_json::to_string(&message.source_ids)?
+        ],
+    )?;
+    Ok(())
+}
+
+/// Messages of a session with their row ids, oldest first
+fn load_messages(tx: &Transaction, key: &(String, String)) -> Result<Vec<(i64, Message)>> {
+    let mut statement = tx.prepare(
+        "SELECT id, role, content, timestamp, response_id, source_ids FROM messages
+         WHERE tenant_id = ?1 AND session_id = ?2 ORDER BY id",
+    )?;
+    let rows = statement.query_map(params![key.0, key.1], |row| {
+        let source_ids: Option<String> = row.get(5)?;
+        Ok((
+            row.get(0)?,
+            Message {
+                role: Role::from_stored(&row.get::<_, String>(1)?),
+                content: row.get(2)?,
+                timestamp: timestamp_from_sql(row.get(3)?),
+                response_id: row.get(4)?,
+                source_ids: source_ids
+                    .and_then(|ids| serde_json::from_str(&ids).ok())
+                    .unwrap_or_default(),
+            },
+        ))
+    })?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+#[async_trait]
+impl<C: Clock> SessionStore for SqliteSessionStore<C> {
+    async fn get(&self, tenant_id: &str, session_id: &str) -> Result<Option<Session>> {
+        let key = (tenant_id.to_string(), session_id.to_string());
+        let cutoff = self.cutoff_ms();
+        self.db.transaction(move |tx| {
+            let row = tx
+                .query_row(
+                    "SELECT created_at, last_active, bike_model, bike_year, tokens_used,
+                            tokens_updated_at
+                     FROM sessions WHERE tenant_id = ?1 AND session_id = ?2",
+                    params![key.0, key.1],
+                    |row| {
+                        let year = row.get(3)?;
+                        let bike = row
+                            .get::<_, Option<String>>(2)?
+                            .map(|model| BikeContext { model, year });
+                        let tokens = (row.get::<_, u64>(4)?, row.get::<_, Option<i64>>(5)?);
+                        Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, bike, tokens))
+                    },
+                )
+                .optional()?;
+            let Some((created_at, last_active, bike, (tokens_used, tokens_updated_at))) = row
+            else {
+                return Ok(None);
+            };
+            if last_active <= cutoff {
+                return Ok(None);
+            }
+
+            let messages = load_messages(tx, &key)?
+                .into_iter()
+                .map(|(_, m)| m)
+                .collect();
+            Ok(Some(Session {
+                messages,
+                created_at: datetime_from_millis(created_at),
+                last_active: datetime_from_millis(last_active),
+                bike,
+                tokens_used,
+                tokens_updated_at: datetime_from_millis(tokens_updated_at.unwrap_or(created_at)),
+            }))
+        })
+        .await
+    }
+
+    async fn record(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+        query: &str,
+        reply: Message,
+        bike: Option<BikeContext>,
+    ) -> Result<()> {
+        if self.max_messages == 0 {
+            return Ok(());
+        }
+
+        let key = (tenant_id.to_string(), session_id.to_string());
+        let exchange = [Message::user(query), reply];
+        let now = self.clock.now_utc().timestamp_millis();
+        let cutoff = self.cutoff_ms();
+        let max_messages = self.max_messages;
+        let (bike_model, bike_year) = match bike {
+            Some(bike) => (Some(bike.model), bike.year),
+            None => (None, None),
+        };
+        self.db.transaction(move |tx| {
+            // An expired session that was not evicted yet starts over
+            tx.execute(
+                "DELETE FROM sessions WHERE tenant_id = ?1 AND session_id = ?2 AND last_active <= ?3",
+                params![key.0, key.1, cutoff],
+            )?;
+            tx.execute(
+                "INSERT INTO sessions (tenant_id, session_id, created_at, last_active, bike_model)
+                 VALUES (?1, ?2, ?3, ?3, NULL) ON CONFLICT DO NOTHING",
+                params![key.0, key.1, now],
+            )?;
+            for message in &exchange {
+                insert_message(tx, None, &key, message)?;
+            }
+
+            let count: usize = tx.query_row(
+                "SELECT COUNT(*) FROM messages WHERE tenant_id = ?1 AND session_id = ?2",
+                params![key.0, key.1],
+                |row| row.get(0),
+            )?;
+            let excess = excess_messages(count, max_messages);
+            if excess > 0 {
+                tx.execute(
+                    "DELETE FROM messages WHERE id IN (
+                         SELECT id FROM messages WHERE tenant_id = ?1 AND session_id = ?2
+                         ORDER BY id LIMIT ?3)",
+                    params![key.0, key.1, excess],
+                )?;
+            }
+            tx.execute(
+                "UPDATE sessions SET last_active = ?3,
+                     bike_year = CASE WHEN ?4 IS NULL THEN bike_year ELSE ?5 END,
+                     bike_model = COALESCE(?4, bike_model)
+                 WHERE tenant_id = ?1 AND session_id = ?2",
+                params![key.0, key.1, now, bike_model, bike_year],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn replace_prefix(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+        prefix: &[Message],
+        replacement: Message,
+    ) -> Result<bool> {
+        let key = (tenant_id.to_string(), session_id.to_string());
+        let prefix = prefix.to_vec();
+        self.db.transaction(move |tx| {
+            let stored = load_messages(tx, &key)?;
+            let unchanged = stored.len() >= prefix.len()
+                && stored
+                    .iter()
+                    .zip(&prefix)
+                    .all(|((_, a), b)| same_message(a, b));
+            let last_id = match prefix.len().checked_sub(1).map(|i| stored[i].0) {
+                Some(id) if unchanged => id,
+                _ => return Ok(false),
+            };
+
+            // The replacement takes the place of the last message it stands for
+            tx.execute(
+                "DELETE FROM messages WHERE tenant_id = ?1 AND session_id = ?2 AND id <= ?3",
+                params![key.0, key.1, last_id],
+            )?;
+            insert_message(tx, Some(last_id), &key, &replacement)?;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn replace_last(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+        previous: &Message,
+        replacement: Message,
+    ) -> Result<bool> {
+        let key = (tenant_id.to_string(), session_id.to_string());
+        let previous = previous.clone();
+        let now = self.clock.now_utc().timestamp_millis();
+        self.db.transaction(move |tx| {
+            let last_id = match load_messages(tx, &key)?.pop() {
+                Some((id, last)) if same_message(&last, &previous) => id,
+                _ => return Ok(false),
+            };
+
+            tx.execute("DELETE FROM messages WHERE id = ?1", params![last_id])?;
+            insert_message(tx, Some(last_id), &key, &replacement)?;
+            tx.execute(
+                "UPDATE sessions SET last_active = ?3 WHERE tenant_id = ?1 AND session_id = ?2",
+                params![key.0, key.1, now],
+            )?;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn add_tokens(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+        tokens: u64,
+        refill_per_hour: u64,
+    ) -> Result<u64> {
+        let key = (tenant_id.to_string(), session_id.to_string());
+        let now = self.clock.now_utc();
+        self.db.transaction(move |tx| {
+            let row = tx
+                .query_row(
+                    "SELECT tokens_used, COALESCE(tokens_updated_at, created_at) FROM sessions
+                     WHERE tenant_id = ?1 AND session_id = ?2",
+                    params![key.0, key.1],
+                    |row| Ok((row.get::<_, u64>(0)?, row.get::<_, i64>(1)?)),
+                )
+                .optional()?;
+            let Some((used, updated_at)) = row else {
+                return Ok(0);
+            };
+
+            let used = refilled(used, datetime_from_millis(updated_at), now, refill_per_hour)
+                + tokens;
+            tx.execute(
+                "UPDATE sessions SET tokens_used = ?3, tokens_updated_at = ?4
+                 WHERE tenant_id = ?1 AND session_id = ?2",
+                params![key.0, key.1, used, now.timestamp_millis()],
+            )?;
+            Ok(used)
+        })
+        .await
+    }
+
+    async fn delete(&self, tenant_id: &str, session_id: &str) -> Result<bool> {
+        let key = (tenant_id.to_string(), session_id.to_string());
+        self.db.transaction(move |tx| {
+            let removed = tx.execute(
+                "DELETE FROM sessions WHERE tenant_id = ?1 AND session_id = ?2",
+                params![key.0, key.1],
+            )?;
+            Ok(removed > 0)
+        })
+        .await
+    }
+
+    async fn list(&self) -> Result<Vec<SessionSummary>> {
+        let cutoff = self.cutoff_ms();
+        self.db.transaction(move |tx| {
+            let mut statement = tx.prepare(
+                "SELECT s.tenant_id, s.session_id, COUNT(m.id), s.created_at, s.last_active,
+                        s.bike_model, s.bike_year, s.tokens_used
+                 FROM sessions s
+                 LEFT JOIN messages m
+                     ON m.tenant_id = s.tenant_id AND m.session_id = s.session_id
+                 WHERE s.last_active > ?1
+                 GROUP BY s.tenant_id, s.session_id
+                 ORDER BY s.last_active DESC",
+            )?;
+            let rows = statement.query_map(params![cutoff], |row| {
+                Ok(SessionSummary {
+                    tenant_id: row.get(0)?,
+                    session_id: row.get(1)?,
+                    message_count: row.get(2)?,
+                    created_at: datetime_from_millis(row.get(3)?),
+                    last_active: datetime_from_millis(row.get(4)?),
+                    bike_model: row.get(5)?,
+                    bike_year: row.get(6)?,
+                    tokens_used: row.get(7)?,
+                })
+            })?;
+            Ok(rows.collect::<rusqlite::Result<_>>()?)
+        })
+        .await
+    }
+
+    async fn cleanup_expired(&self) -> Result<usize> {
+        let cutoff = self.cutoff_ms();
+        let removed = self
+            .db
+            .transaction(move |tx| {
+                Ok(tx.execute(
+                    "DELETE FROM sessions WHERE last_active <= ?1",
+                    params![cutoff],
+                )?)
+            })
+            .await?;
+        log::debug!("Session cleanup: {} expired sessions removed", removed);
+        Ok(removed)
+    }
+
+    /// Fold the write-ahead log into the database file
+    async fn flush(&self) -> Result<()> {
+        self.db.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::MockClock;
+    use rusqlite::Connection;
+    use tempfile::TempDir;
+
+    fn contents(session: &Session) -> Vec<&str> {
+        session
+            .messages
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_history_survives_reopening_the_store() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("sessions.db");
+
+        let store = SqliteSessionStore::open(&path, 4, 3600).await.unwrap();
+        assert_eq!(store.add_tokens("default", "s1", 120, 0).await.unwrap(), 0);
+        let bike = BikeContext {
+            model: "Yamaha R6".to_string(),
+            year: Some(2008),
+        };
+        store
+            .record(
+                "default",
+                "s1",
+                "Why does it idle rough?",
+                Message::assistant("Check the plugs."),
+                Some(bike.clone()),
+            )
+            .await
+            .unwrap();
+        store
+            .record(
+                "default",
+                "s1",
+                "Plugs are new",
+                Message::assistant("Sync the throttle bodies.")
+                    .with_response("r2", vec!["chunk-7".to_string()]),
+                None,
+            )
+            .await
+            .unwrap();
+        store
+            .record("default", "s1", "How?", Message::assistant("Use a vacuum gauge."), None)
+            .await
+            .unwrap();
+        assert_eq!(store.add_tokens("default", "s1", 120, 0).await.unwrap(), 120);
+        drop(store);
+        // Rows written before roles were checked are read leniently
+        let conn = Connection::open(&path).unwrap();
+        let legacy = "UPDATE messages SET role = 'Assistant' WHERE content = 'Use a vacuum gauge.'";
+        conn.execute(legacy, []).unwrap();
+        drop(conn);
+
+        let store = SqliteSessionStore::open(&path, 4, 3600).await.unwrap();
+        let session = store.get("default", "s1").await.unwrap().unwrap();
+        assert_eq!(
+            contents(&session),
+            [
+                "Plugs are new",
+                "Sync the throttle bodies.",
+                "How?",
+                "Use a vacuum gauge."
+            ]
+        );
+        assert_eq!(session.messages[0].role, Role::User);
+        assert_eq!(session.messages[3].role, Role::Assistant);
+        assert_eq!(session.messages[1].response_id.as_deref(), Some("r2"));
+        assert_eq!(session.messages[1].source_ids, ["chunk-7"]);
+        assert_eq!(session.bike.as_ref(), Some(&bike));
+        assert_eq!(session.tokens_used, 120);
+        assert!(store.get("acme", "s1").await.unwrap().is_none());
+
+        // Stored messages compare equal to what was read, so they can be summarized
+        let summary = Message::system("Summary");
+        let replaced = store
+            .replace_prefix("default", "s1", &session.messages[..2], summary)
+            .await
+            .unwrap();
+        assert!(replaced);
+        let session = store.get("default", "s1").await.unwrap().unwrap();
+        assert_eq!(
+            contents(&session),
+            ["Summary", "How?", "Use a vacuum gauge."]
+        );
+
+        // A regenerated answer replaces the last one, unless that changed
+        let stale = Message::assistant("Use a vacuum gauge.");
+        let regenerated = Message::assistant("Balance them with a manometer.");
+        assert!(!store.replace_last("default", "s1", &stale, regenerated.clone()).await.unwrap());
+        let last = session.messages.last().unwrap();
+        assert!(store.replace_last("default", "s1", last, regenerated).await.unwrap());
+        let session = store.get("default", "s1").await.unwrap().unwrap();
+        assert_eq!(
+            contents(&session),
+            ["Summary", "How?", "Balance them with a manometer."]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expired_sessions_removed() {
+        let dir = TempDir::new().unwrap();
+        let clock = MockClock::new();
+        let path = dir.path().join("sessions.db");
+        let store = SqliteSessionStore::open_with_clock(&path, 10, 60, clock.clone())
+            .await
+            .unwrap();
+        store
+            .record("default", "idle", "q1", Message::assistant("a1"), None)
+            .await
+            .unwrap();
+        clock.advance(Duration::from_secs(30));
+        store
+            .record("default", "active", "q1", Message::assistant("a1"), None)
+            .await
+            .unwrap();
+
+        clock.advance(Duration::from_secs(31));
+        assert!(store.get("default", "idle").await.unwrap().is_none());
+        assert_eq!(store.cleanup_expired().await.unwrap(), 1);
+        assert!(store.get("default", "active").await.unwrap().is_some());
+        let listed = store.list().await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].message_count, 2);
+
+        // Writing to an expired session starts a fresh conversation
+        clock.advance(Duration::from_secs(60));
+        store
+            .record("default", "active", "q2", Message::assistant("a2"), None)
+            .await
+            .unwrap();
+        let session = store.get("default", "active").await.unwrap().unwrap();
+        assert_eq!(contents(&session), ["q2", "a2"]);
+
+        assert!(store.delete("default", "active").await.unwrap());
+        assert!(!store.delete("default", "active").await.unwrap());
+        assert!(store.list().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+//! SQLite plumbing shared by the session, feedback and usage stores: opening
+//! a database file, bringing its schema up to date and running queries off
+//! the async runtime
+
+use anyhow::{Context, Result};
+use rusqlite::{Connection, Transaction};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Connection to a SQLite database file. Queries run on the blocking thread pool.
+pub struct Database {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Database {
+    /// Open (or create) the database at `path` and apply the `migrations` it
+    /// has not seen yet; `PRAGMA user_version` counts the ones already
+    /// applied. `name` labels the database in errors and logs.
+    pub async fn open(
+        path: impl AsRef<Path>,
+        name: &'static str,
+        migrations: &'static [&'static str],
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let conn = tokio::task::spawn_blocking(move || -> Result<Connection> {
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+            }
+            let mut conn = Connection::open(&path)
+                .with_context(|| format!("Failed to open {} database {}", name, path.display()))?;
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            conn.pragma_update(None, "foreign_keys", true)?;
+            migrate(&mut conn, name, migrations)?;
+            Ok(conn)
+        })
+        .await??;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Run `f` in a transaction on the blocking thread pool
+    pub async fn transaction<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Transaction) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap();
+            let tx = conn.transaction()?;
+            let result = f(&tx)?;
+            tx.commit()?;
+            Ok(result)
+        })
+        .await?
+    }
+
+    /// Fold the write-ahead log into the database file
+    pub async fn flush(&self) -> Result<()> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().unwrap();
+            conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+            Ok(())
+        })
+        .await?
+    }
+}
+
+/// Apply the migrations the database has not seen yet
+fn migrate(conn: &mut Connection, name: &str, migrations: &[&str]) -> Result<()> {
+    let applied: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (version, sql) in migrations.iter().enumerate().skip(applied) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)
+            .with_context(|| format!("Migration {} of the {} database failed", version + 1, name))?;
+        tx.pragma_update(None, "user_version", version + 1)?;
+        tx.commit()?;
+        log::info!("Applied migration {} of the {} database", version + 1, name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const MIGRATIONS: &[&str] = &[
+        "CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT NOT NULL);",
+        "ALTER TABLE notes ADD COLUMN author TEXT;",
+    ];
+
+    #[tokio::test]
+    async fn test_migrations_apply_once_across_reopens() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("nested").join("notes.db");
+
+        let db = Database::open(&path, "notes", &MIGRATIONS[..1]).await.unwrap();
+        db.transaction(|tx| Ok(tx.execute("INSERT INTO notes (body) VALUES ('a')", [])?))
+            .await
+            .unwrap();
+        db.flush().await.unwrap();
+        drop(db);
+
+        // Only the new migration runs; the first would fail on the existing table
+        let db = Database::open(&path, "notes", MIGRATIONS).await.unwrap();
+        let (count, version) = db
+            .transaction(|tx| {
+                tx.execute("UPDATE notes SET author = 'b'", [])?;
+                let count: i64 = tx.query_row("SELECT COUNT(*) FROM notes", [], |row| row.get(0))?;
+                let version: i64 = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+                Ok((count, version))
+            })
+            .await
+            .unwrap();
+        assert_eq!((count, version), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn test_failed_transaction_rolls_back() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path().join("notes.db"), "notes", MIGRATIONS)
+            .await
+            .unwrap();
+        let result: Result<()> = db
+            .transaction(|tx| {
+                tx.execute("INSERT INTO notes (body) VALUES ('a')", [])?;
+                anyhow::bail!("nope")
+            })
+            .await;
+        assert!(result.is_err());
+        let count: i64 = db
+            .transaction(|tx| Ok(tx.query_row("SELECT COUNT(*) FROM notes", [], |row| row.get(0))?))
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,77 @@
+//! Background tasks of the server, kept so shutdown can stop them
+
+use std::future::Future;
+use std::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// Named handles of running background tasks: periodic cleanup and ingestion
+/// jobs. Finished tasks are dropped as new ones are added.
+#[derive(Default)]
+pub struct TaskRegistry {
+    tasks: Mutex<Vec<(String, JoinHandle<()>)>>,
+}
+
+impl TaskRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawn `task` and keep its handle
+    pub fn spawn<F>(&self, name: impl Into<String>, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.track(name, tokio::spawn(task));
+    }
+
+    /// Keep the handle of an already spawned task
+    pub fn track(&self, name: impl Into<String>, handle: JoinHandle<()>) {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|(_, handle)| !handle.is_finished());
+        tasks.push((name.into(), handle));
+    }
+
+    /// Names of the tasks still running
+    pub fn running(&self) -> Vec<String> {
+        self.tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, handle)| !handle.is_finished())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Abort every running task and wait for them to stop; returns the names
+    /// of the tasks that were aborted
+    pub async fn shutdown(&self) -> Vec<String> {
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        let mut aborted = Vec::new();
+        for (name, handle) in tasks {
+            if handle.is_finished() {
+                continue;
+            }
+            handle.abort();
+            let _ = handle.await;
+            aborted.push(name);
+        }
+        aborted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown_aborts_running_tasks() {
+        let tasks = TaskRegistry::new();
+        tasks.spawn("done", async {});
+        tasks.spawn("forever", std::future::pending());
+        tokio::task::yield_now().await;
+
+        assert_eq!(tasks.running(), vec!["forever".to_string()]);
+        assert_eq!(tasks.shutdown().await, vec!["forever".to_string()]);
+        assert!(tasks.running().is_empty());
+    }
+}
@@ -0,0 +1,545 @@
+//! Usage records of the chat endpoints in a SQLite database, aggregated into
+//! the report behind `GET /api/admin/usage`. The handlers queue records on a
+//! channel and a background task writes them, so a chat never waits on the
+//! database.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use rusqlite::params;
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+use crate::models::{UsageBucket, UsageGrouping, UsageReport, UsageTotals};
+use crate::sqlite::Database;
+
+/// Records waiting to be written; past this, new ones are dropped
+const USAGE_QUEUE_CAPACITY: usize = 1024;
+
+/// Most records written in one transaction
+const USAGE_WRITE_BATCH: usize = 100;
+
+/// Longest shutdown waits for queued records to be written
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Error code of a streamed answer whose client went away before it finished
+pub const CANCELLED: &str = "CANCELLED";
+
+/// Schema of the usage database, see [`Database::open`]
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE usage_records (
+        id            INTEGER PRIMARY KEY,
+        created_at    INTEGER NOT NULL,
+        tenant_id     TEXT NOT NULL,
+        ip_hash       TEXT NOT NULL,
+        session_id    TEXT,
+        model         TEXT NOT NULL,
+        tokens_in     INTEGER NOT NULL,
+        tokens_out    INTEGER NOT NULL,
+        latency_ms    INTEGER NOT NULL,
+        retrieval_hit INTEGER,
+        error_code    TEXT
+    );
+    CREATE INDEX usage_records_created_at ON usage_records (created_at);
+"#];
+
+/// One chat request
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageRecord {
+    pub created_at: DateTime<Utc>,
+    pub tenant_id: String,
+    /// Client address, see [`IpHasher`]
+    pub ip_hash: String,
+    pub session_id: Option<String>,
+    pub model: String,
+    pub tokens_in: u64,
+    pub tokens_out: u64,
+    pub latency_ms: u64,
+    /// Whether retrieval found manual chunks; `None` for a request refused
+    /// before retrieval
+    pub retrieval_hit: Option<bool>,
+    /// Code of the error response; `None` for an answer
+    pub error_code: Option<String>,
+}
+
+/// Hashes client addresses with HMAC-SHA256 under a per-deployment secret,
+/// telling clients apart without storing their IPs. Without the secret the
+/// hashes cannot be reversed by hashing every IPv4 address.
+#[derive(Clone)]
+pub struct IpHasher {
+    secret: Vec<u8>,
+}
+
+impl IpHasher {
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self {
+            secret: secret.as_ref().to_vec(),
+        }
+    }
+
+    /// `USAGE_IP_HASH_SECRET`, or else the secret stored next to the usage
+    /// database, generated on first use
+    pub fn from_config(config: &crate::config::Config) -> Result<Self> {
+        match &config.usage_ip_hash_secret {
+            Some(secret) => Ok(Self::new(secret)),
+            None => Self::load_or_create(secret_path(&config.usage_db_path)),
+        }
+    }
+
+    /// Read the secret from `path`, creating the file with a random one
+    /// (readable only by its owner) when there is none
+    pub fn load_or_create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(secret) if !secret.trim().is_empty() => return Ok(Self::new(secret.trim())),
+            Ok(_) => anyhow::bail!("IP hash secret {} is empty", path.display()),
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+            Err(_) => {}
+        }
+
+        let secret = format!(
+            "{}{}",
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        );
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(path)
+            .and_then(|mut file| file.write_all(secret.as_bytes()))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        log::info!("Generated the IP hash secret {}", path.display());
+        Ok(Self::new(secret))
+    }
+
+    /// Short keyed digest of `ip`
+    pub fn hash(&self, ip: IpAddr) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+            .expect("HMAC accepts keys of any length");
+        mac.update(ip.to_string().as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .take(8)
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// File holding the generated IP hash secret of the usage database at `db_path`
+fn secret_path(db_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.key", db_path))
+}
+
+/// Token prices behind the report's cost estimate, in USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenPrices {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+impl TokenPrices {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            input_per_million: config.usage_input_price_per_million,
+            output_per_million: config.usage_output_price_per_million,
+        }
+    }
+
+    fn cost(&self, tokens_in: u64, tokens_out: u64) -> f64 {
+        (tokens_in as f64 * self.input_per_million + tokens_out as f64 * self.output_per_million)
+            / 1_000_000.0
+    }
+}
+
+/// Usage records in a SQLite database file
+pub struct UsageStore {
+    db: Database,
+}
+
+impl UsageStore {
+    /// Open (or create) the database at `path` and bring its schema up to date
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            db: Database::open(path, "usage", MIGRATIONS).await?,
+        })
+    }
+
+    pub async fn insert(&self, records: Vec<UsageRecord>) -> Result<()> {
+        self.db.transaction(move |tx| {
+            let mut statement = tx.prepare(
+                "INSERT INTO usage_records (created_at, tenant_id, ip_hash, session_id, model,
+                                            tokens_in, tokens_out, latency_ms, retrieval_hit,
+                                            error_code)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            for record in records {
+                statement.execute(params![
+                    record.created_at.timestamp_millis(),
+                    record.tenant_id,
+                    record.ip_hash,
+                    record.session_id,
+                    record.model,
+                    record.tokens_in as i64,
+                    record.tokens_out as i64,
+                    record.latency_ms as i64,
+                    record.retrieval_hit,
+                    record.error_code,
+                ])?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Records made at or after `from` and before `to`, oldest first
+    pub async fn between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<UsageRecord>> {
+        self.db.transaction(move |tx| {
+            let mut statement = tx.prepare(
+                "SELECT created_at, tenant_id, ip_hash, session_id, model, tokens_in, tokens_out,
+                        latency_ms, retrieval_hit, error_code
+                 FROM usage_records
+                 WHERE created_at >= ?1 AND created_at < ?2
+                 ORDER BY created_at, id",
+            )?;
+            let mut rows =
+                statement.query(params![from.timestamp_millis(), to.timestamp_millis()])?;
+
+            let mut records = Vec::new();
+            while let Some(row) = rows.next()? {
+                records.push(UsageRecord {
+                    created_at: Utc.timestamp_millis_opt(row.get(0)?).single().unwrap_or_default(),
+                    tenant_id: row.get(1)?,
+                    ip_hash: row.get(2)?,
+                    session_id: row.get(3)?,
+                    model: row.get(4)?,
+                    tokens_in: row.get::<_, i64>(5)? as u64,
+                    tokens_out: row.get::<_, i64>(6)? as u64,
+                    latency_ms: row.get::<_, i64>(7)? as u64,
+                    retrieval_hit: row.get(8)?,
+                    error_code: row.get(9)?,
+                });
+            }
+            Ok(records)
+        })
+        .await
+    }
+
+    /// Delete the records made before `cutoff`; returns how many were deleted
+    pub async fn prune(&self, cutoff: DateTime<Utc>) -> Result<usize> {
+        self.db.transaction(move |tx| {
+            Ok(tx.execute(
+                "DELETE FROM usage_records WHERE created_at < ?1",
+                params![cutoff.timestamp_millis()],
+            )?)
+        })
+        .await
+    }
+
+    /// Fold the write-ahead log into the database file before the process exits
+    pub async fn flush(&self) -> Result<()> {
+        self.db.flush().await
+    }
+}
+
+/// Queue in front of the usage store, emptied by a background writer task.
+/// The task is not in the task registry: shutdown flushes the queue instead
+/// of aborting the writer, and the task ends when the log is dropped.
+pub struct UsageLog {
+    store: Arc<UsageStore>,
+    hasher: IpHasher,
+    sender: mpsc::Sender<UsageRecord>,
+    /// Records queued or being written
+    pending: Arc<AtomicUsize>,
+}
+
+impl UsageLog {
+    /// Start the writer task for `store`; records hash client IPs with `hasher`
+    pub fn start(store: UsageStore, hasher: IpHasher) -> Self {
+        let store = Arc::new(store);
+        let (sender, receiver) = mpsc::channel(USAGE_QUEUE_CAPACITY);
+        let pending = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(write_records(store.clone(), receiver, pending.clone()));
+        Self {
+            store,
+            hasher,
+            sender,
+            pending,
+        }
+    }
+
+    /// Client address as stored in a record's `ip_hash`
+    pub fn hash_ip(&self, ip: IpAddr) -> String {
+        self.hasher.hash(ip)
+    }
+
+    /// Queue a record without waiting; it is dropped if the queue is full
+    pub fn record(&self, record: UsageRecord) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        if let Err(e) = self.sender.try_send(record) {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            log::warn!("Usage record dropped: {}", e);
+        }
+    }
+
+    /// Aggregates of the records made at or after `from` and before `to`
+    pub async fn report(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        group_by: UsageGrouping,
+        prices: TokenPrices,
+    ) -> Result<UsageReport> {
+        let records = self.store.between(from, to).await?;
+        Ok(summarize(&records, from, to, group_by, prices))
+    }
+
+    /// Delete the records older than `retention_days`; 0 keeps them all
+    pub async fn prune(&self, retention_days: u64) -> Result<usize> {
+        if retention_days == 0 {
+            return Ok(0);
+        }
+        let cutoff = Utc::now() - chrono::Duration::days(retention_days as i64);
+        self.store.prune(cutoff).await
+    }
+
+    /// Wait for the queued records to be written (up to a few seconds), then
+    /// fold the write-ahead log into the database file
+    pub async fn flush(&self) -> Result<()> {
+        let deadline = Instant::now() + FLUSH_TIMEOUT;
+        loop {
+            let pending = self.pending.load(Ordering::SeqCst);
+            if pending == 0 {
+                break;
+            }
+            if Instant::now() >= deadline {
+                log::warn!("{} usage records were not written", pending);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        self.store.flush().await
+    }
+}
+
+/// Write queued records in batches until every sender is gone
+async fn write_records(
+    store: Arc<UsageStore>,
+    mut receiver: mpsc::Receiver<UsageRecord>,
+    pending: Arc<AtomicUsize>,
+) {
+    while let Some(record) = receiver.recv().await {
+        let mut batch = vec![record];
+        while batch.len() < USAGE_WRITE_BATCH {
+            match receiver.try_recv() {
+                Ok(record) => batch.push(record),
+                Err(_) => break,
+            }
+        }
+        let count = batch.len();
+        if let Err(e) = store.insert(batch).await {
+            log::error!("Failed to write {} usage records: {:#}", count, e);
+        }
+        pending.fetch_sub(count, Ordering::SeqCst);
+    }
+}
+
+/// Aggregate the records of `[from, to)` in total and per hour or day.
+/// Buckets are aligned to UTC hours or midnights, starting with the one
+/// holding `from`.
+pub fn summarize(
+    records: &[UsageRecord],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    group_by: UsageGrouping,
+    prices: TokenPrices,
+) -> UsageReport {
+    let step = group_by.seconds();
+    let in_range: Vec<&UsageRecord> = records
+        .iter()
+        .filter(|record| record.created_at >= from && record.created_at < to)
+        .collect();
+
+    let mut grouped: BTreeMap<i64, Vec<&UsageRecord>> = BTreeMap::new();
+    for record in &in_range {
+        let start = record.created_at.timestamp().div_euclid(step) * step;
+        grouped.entry(start).or_default().push(record);
+    }
+
+    let mut buckets = Vec::new();
+    let mut start = from.timestamp().div_euclid(step) * step;
+    while start < to.timestamp() {
+        let records = grouped.get(&start).map(Vec::as_slice).unwrap_or_default();
+        buckets.push(UsageBucket {
+            start: Utc.timestamp_opt(start, 0).single().unwrap_or_default(),
+            totals: totals(records, prices),
+        });
+        start += step;
+    }
+
+    UsageReport {
+        from,
+        to,
+        group_by,
+        totals: totals(&in_range, prices),
+        buckets,
+    }
+}
+
+fn totals(records: &[&UsageRecord], prices: TokenPrices) -> UsageTotals {
+    if records.is_empty() {
+        return UsageTotals::default();
+    }
+    let requests = records.len() as u64;
+    let tokens_in = records.iter().map(|r| r.tokens_in).sum();
+    let tokens_out = records.iter().map(|r| r.tokens_out).sum();
+    let sessions: HashSet<&str> = records.iter().filter_map(|r| r.session_id.as_deref()).collect();
+    let errors = records.iter().filter(|r| r.error_code.is_some()).count();
+    let retrieved: Vec<bool> = records.iter().filter_map(|r| r.retrieval_hit).collect();
+    let hits = retrieved.iter().filter(|hit| **hit).count();
+
+    // Nearest-rank 95th percentile
+    let mut latencies: Vec<u64> = records.iter().map(|r| r.latency_ms).collect();
+    latencies.sort_unstable();
+    let rank = (latencies.len() * 95).div_ceil(100).max(1);
+
+    UsageTotals {
+        requests,
+        unique_sessions: sessions.len() as u64,
+        tokens_in,
+        tokens_out,
+        estimated_cost_usd: prices.cost(tokens_in, tokens_out),
+        error_rate: errors as f64 / requests as f64,
+        retrieval_hit_rate: if retrieved.is_empty() {
+            0.0
+        } else {
+            hits as f64 / retrieved.len() as f64
+        },
+        p95_latency_ms: latencies[rank - 1],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::SubsecRound;
+
+    fn record(at: &str, session: &str, latency_ms: u64, error: Option<&str>) -> UsageRecord {
+        UsageRecord {
+            created_at: at.parse().unwrap(),
+            tenant_id: "default".to_string(),
+            ip_hash: IpHasher::new("secret").hash("203.0.113.7".parse().unwrap()),
+            session_id: Some(session.to_string()),
+            model: "gpt-4o-mini".to_string(),
+            tokens_in: 1000,
+            tokens_out: 200,
+            latency_ms,
+            retrieval_hit: error.is_none().then_some(latency_ms > 100),
+            error_code: error.map(str::to_string),
+        }
+    }
+
+    const PRICES: TokenPrices = TokenPrices {
+        input_per_million: 0.15,
+        output_per_million: 0.60,
+    };
+
+    #[test]
+    fn test_summarize_groups_by_day_including_empty_days() {
+        let records = vec![
+            record("2026-10-10T08:00:00Z", "a", 120, None),
+            record("2026-10-10T09:00:00Z", "a", 80, None),
+            record("2026-10-10T23:59:59Z", "b", 900, Some("RATE_LIMIT_EXCEEDED")),
+            record("2026-10-12T12:00:00Z", "c", 300, None),
+            // Outside the range
+            record("2026-10-13T00:00:00Z", "d", 50, None),
+        ];
+        let from = "2026-10-10T06:00:00Z".parse().unwrap();
+        let to = "2026-10-13T00:00:00Z".parse().unwrap();
+
+        let report = summarize(&records, from, to, UsageGrouping::Day, PRICES);
+
+        let starts: Vec<String> = report.buckets.iter().map(|b| b.start.to_rfc3339()).collect();
+        assert_eq!(
+            starts,
+            ["2026-10-10T00:00:00+00:00", "2026-10-11T00:00:00+00:00", "2026-10-12T00:00:00+00:00"]
+        );
+        let first = &report.buckets[0].totals;
+        assert_eq!((first.requests, first.unique_sessions), (3, 2));
+        assert_eq!((first.tokens_in, first.tokens_out), (3000, 600));
+        assert!((first.estimated_cost_usd - 0.00081).abs() < 1e-12);
+        assert!((first.error_rate - 1.0 / 3.0).abs() < 1e-12);
+        assert_eq!(first.retrieval_hit_rate, 0.5);
+        assert_eq!(first.p95_latency_ms, 900);
+        assert_eq!(report.buckets[1].totals, UsageTotals::default());
+
+        assert_eq!(report.totals.requests, 4);
+        assert_eq!(report.totals.unique_sessions, 3);
+        assert_eq!(report.totals.p95_latency_ms, 900);
+    }
+
+    #[test]
+    fn test_ip_hash_depends_on_the_persisted_secret() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usage.db.key");
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+
+        let generated = IpHasher::load_or_create(&path).unwrap();
+        let reloaded = IpHasher::load_or_create(&path).unwrap();
+        assert_eq!(generated.hash(ip), reloaded.hash(ip));
+        assert_eq!(generated.hash(ip).len(), 16);
+        assert_ne!(generated.hash(ip), generated.hash("203.0.113.8".parse().unwrap()));
+        assert_ne!(generated.hash(ip), IpHasher::new("other").hash(ip));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queued_records_written_and_pruned() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = UsageLog::start(
+            UsageStore::open(dir.path().join("usage.db")).await.unwrap(),
+            IpHasher::new("secret"),
+        );
+        // Stored with millisecond precision
+        let now = Utc::now().trunc_subsecs(3);
+        let old = UsageRecord {
+            created_at: now - chrono::Duration::days(40),
+            ..record("2026-10-10T08:00:00Z", "a", 120, None)
+        };
+        let recent = UsageRecord {
+            created_at: now - chrono::Duration::hours(1),
+            ..record("2026-10-10T08:00:00Z", "b", 80, Some("AI_ERROR"))
+        };
+        log.record(old.clone());
+        log.record(recent.clone());
+        log.flush().await.unwrap();
+
+        let week_ago = now - chrono::Duration::days(7);
+        assert_eq!(log.store.between(week_ago, now).await.unwrap(), vec![recent.clone()]);
+
+        assert_eq!(log.prune(0).await.unwrap(), 0);
+        assert_eq!(log.prune(30).await.unwrap(), 1);
+        let all = log.store.between(now - chrono::Duration::days(365), now).await.unwrap();
+        assert_eq!(all, vec![recent]);
+    }
+}